@@ -1,6 +1,12 @@
 module walrus_remote::remote_state {
-    use std::string::String;
-    use sui::{clock::{Self, Clock}, table::{Self, Table}, vec_set::{Self, VecSet}};
+    use std::string::{Self, String};
+    use sui::{
+        clock::{Self, Clock},
+        dynamic_field as df,
+        table::{Self, Table},
+        vec_map::{Self, VecMap},
+        vec_set::{Self, VecSet},
+    };
 
     // Error codes
     const ERR_LOCK_HELD: u64 = 1;
@@ -9,6 +15,26 @@ module walrus_remote::remote_state {
     const ERR_LOCK_EXPIRED: u64 = 4;
     const ERR_NOT_AUTHORIZED: u64 = 5;
     const ERR_NOT_OWNER: u64 = 6;
+    const ERR_INLINE_REFS_FULL: u64 = 7;
+    const ERR_NOT_INLINE: u64 = 8;
+    const ERR_REF_LIMIT: u64 = 9;
+
+    /// Maximum number of refs kept inline before the remote must migrate to the table layout
+    const MAX_INLINE_REFS: u64 = 64;
+
+    /// Dynamic field of an inline-layout RemoteState holding its refs as a
+    /// `VecMap<String, String>` (when present, the `refs` table is unused)
+    const INLINE_REFS_FIELD: vector<u8> = b"inline_refs";
+
+    /// Dynamic field holding the RemoteState's `Policy` (absent: unlimited)
+    const POLICY_FIELD: vector<u8> = b"policy";
+
+    /// Dynamic field holding the RemoteState's `LastWriter`
+    const LAST_WRITER_FIELD: vector<u8> = b"last_writer";
+
+    /// Dynamic field holding the RemoteState's symrefs (such as `HEAD`) as a
+    /// `VecMap<String, String>` of name to target ref
+    const SYMREFS_FIELD: vector<u8> = b"symrefs";
 
     /// Main state object for a git remote repository
     public struct RemoteState has key {
@@ -31,6 +57,25 @@ module walrus_remote::remote_state {
         expires_ms: u64,
     }
 
+    /// Who last wrote a remote's objects map, and the format header they recorded for it, kept
+    /// in its `last_writer` dynamic field
+    public struct LastWriter has drop, store {
+        writer: address,
+        /// Objects map the header describes; a client that records no header leaves this
+        /// behind the current map
+        objects_blob_object_id: Option<String>,
+        format: String,
+    }
+
+    /// Limits the owner sets on a remote, kept in its `policy` dynamic field
+    public struct Policy has drop, store {
+        /// Most refs the remote may hold (none is unlimited)
+        max_refs: Option<u64>,
+        /// Most entries the objects map may hold (none is unlimited); the map lives on
+        /// Walrus, so clients enforce this one
+        max_objects: Option<u64>,
+    }
+
     /// Create a new RemoteState (owned by caller)
     public fun create_remote(ctx: &mut TxContext) {
         let owner = ctx.sender();
@@ -46,6 +91,22 @@ module walrus_remote::remote_state {
         transfer::transfer(remote, owner);
     }
 
+    /// Create a new RemoteState that keeps its refs inline (owned by caller)
+    public fun create_remote_inline(ctx: &mut TxContext) {
+        let owner = ctx.sender();
+        let mut remote = RemoteState {
+            id: object::new(ctx),
+            owner,
+            refs: table::new(ctx),
+            objects_blob_object_id: option::none(),
+            lock: option::none(),
+            allowlist: option::none(),
+        };
+        df::add(&mut remote.id, string::utf8(INLINE_REFS_FIELD), vec_map::empty<String, String>());
+
+        transfer::transfer(remote, owner);
+    }
+
     /// Convert owned RemoteState to shared with allowlist
     #[lint_allow(share_owned)]
     public fun share_with_allowlist(
@@ -116,10 +177,22 @@ module walrus_remote::remote_state {
     ) {
         check_authorized(state, ctx);
 
-        if (table::contains(&state.refs, ref_name)) {
+        let (max_refs, _) = get_policy(state);
+        if (has_inline_refs(state)) {
+            let refs = inline_refs_mut(state);
+            if (vec_map::contains(refs, &ref_name)) {
+                let value = vec_map::get_mut(refs, &ref_name);
+                *value = git_sha1;
+            } else {
+                assert!(vec_map::size(refs) < MAX_INLINE_REFS, ERR_INLINE_REFS_FULL);
+                assert!(below_limit(&max_refs, vec_map::size(refs)), ERR_REF_LIMIT);
+                vec_map::insert(refs, ref_name, git_sha1);
+            };
+        } else if (table::contains(&state.refs, ref_name)) {
             let value = table::borrow_mut(&mut state.refs, ref_name);
             *value = git_sha1;
         } else {
+            assert!(below_limit(&max_refs, table::length(&state.refs)), ERR_REF_LIMIT);
             table::add(&mut state.refs, ref_name, git_sha1);
         };
     }
@@ -128,11 +201,30 @@ module walrus_remote::remote_state {
     public fun delete_ref(state: &mut RemoteState, ref_name: String, ctx: &mut TxContext) {
         check_authorized(state, ctx);
 
-        if (table::contains(&state.refs, ref_name)) {
+        if (has_inline_refs(state)) {
+            let refs = inline_refs_mut(state);
+            if (vec_map::contains(refs, &ref_name)) {
+                vec_map::remove(refs, &ref_name);
+            };
+        } else if (table::contains(&state.refs, ref_name)) {
             table::remove(&mut state.refs, ref_name);
         };
     }
 
+    /// Move inline refs into the refs table (one-way)
+    public fun migrate_refs_to_table(state: &mut RemoteState, ctx: &mut TxContext) {
+        check_authorized(state, ctx);
+        assert!(has_inline_refs(state), ERR_NOT_INLINE);
+
+        let inline: VecMap<String, String> = df::remove(&mut state.id, string::utf8(INLINE_REFS_FIELD));
+        let (mut names, mut values) = vec_map::into_keys_values(inline);
+        while (!vector::is_empty(&names)) {
+            let name = vector::pop_back(&mut names);
+            let value = vector::pop_back(&mut values);
+            table::add(&mut state.refs, name, value);
+        };
+    }
+
     /// Update objects blob object ID (requires lock)
     public fun update_objects_blob(
         state: &mut RemoteState,
@@ -144,6 +236,60 @@ module walrus_remote::remote_state {
         option::swap_or_fill(&mut state.objects_blob_object_id, blob_object_id);
     }
 
+    /// Record the format header of the objects map this transaction points the remote at
+    /// (requires lock)
+    public fun set_last_writer(
+        state: &mut RemoteState,
+        format: String,
+        clock: &Clock,
+        ctx: &mut TxContext,
+    ) {
+        check_lock_held(state, clock, ctx);
+        let name = string::utf8(LAST_WRITER_FIELD);
+        if (df::exists_(&state.id, name)) {
+            let _: LastWriter = df::remove(&mut state.id, name);
+        };
+        let last_writer = LastWriter {
+            writer: ctx.sender(),
+            objects_blob_object_id: state.objects_blob_object_id,
+            format,
+        };
+        df::add(&mut state.id, name, last_writer);
+    }
+
+    /// Replace the symrefs with `names` pointing at `targets` (requires lock)
+    public fun set_symrefs(
+        state: &mut RemoteState,
+        names: vector<String>,
+        targets: vector<String>,
+        clock: &Clock,
+        ctx: &mut TxContext,
+    ) {
+        check_lock_held(state, clock, ctx);
+        let name = string::utf8(SYMREFS_FIELD);
+        let symrefs = vec_map::from_keys_values(names, targets);
+        if (df::exists_(&state.id, name)) {
+            *df::borrow_mut(&mut state.id, name) = symrefs;
+        } else {
+            df::add(&mut state.id, name, symrefs);
+        };
+    }
+
+    /// Set the ref and objects-map limits; none lifts a limit (owner only)
+    public fun set_policy(
+        state: &mut RemoteState,
+        max_refs: Option<u64>,
+        max_objects: Option<u64>,
+        ctx: &mut TxContext,
+    ) {
+        assert!(state.owner == ctx.sender(), ERR_NOT_OWNER);
+        let name = string::utf8(POLICY_FIELD);
+        if (df::exists_(&state.id, name)) {
+            let _: Policy = df::remove(&mut state.id, name);
+        };
+        df::add(&mut state.id, name, Policy { max_refs, max_objects });
+    }
+
     /// Add address to allowlist (owner only)
     public fun add_to_allowlist(state: &mut RemoteState, address_to_add: address, ctx: &mut TxContext) {
         assert!(state.owner == ctx.sender(), ERR_NOT_OWNER);
@@ -178,18 +324,62 @@ module walrus_remote::remote_state {
 
     /// Get ref value
     public fun get_ref(state: &RemoteState, ref_name: String): Option<String> {
-        if (table::contains(&state.refs, ref_name)) {
+        if (has_inline_refs(state)) {
+            let refs: &VecMap<String, String> = df::borrow(&state.id, string::utf8(INLINE_REFS_FIELD));
+            if (vec_map::contains(refs, &ref_name)) {
+                option::some(*vec_map::get(refs, &ref_name))
+            } else {
+                option::none()
+            }
+        } else if (table::contains(&state.refs, ref_name)) {
             option::some(*table::borrow(&state.refs, ref_name))
         } else {
             option::none()
         }
     }
 
+    /// Check whether refs are stored inline rather than in the table
+    public fun has_inline_refs(state: &RemoteState): bool {
+        df::exists_(&state.id, string::utf8(INLINE_REFS_FIELD))
+    }
+
     /// Get objects blob object ID
     public fun get_objects_blob_object_id(state: &RemoteState): Option<String> {
         state.objects_blob_object_id
     }
 
+    /// Get the ref and objects-map limits
+    public fun get_policy(state: &RemoteState): (Option<u64>, Option<u64>) {
+        let name = string::utf8(POLICY_FIELD);
+        if (df::exists_(&state.id, name)) {
+            let policy: &Policy = df::borrow(&state.id, name);
+            (policy.max_refs, policy.max_objects)
+        } else {
+            (option::none(), option::none())
+        }
+    }
+
+    /// Get the format header the last writer recorded, if any
+    public fun get_last_writer_format(state: &RemoteState): Option<String> {
+        let name = string::utf8(LAST_WRITER_FIELD);
+        if (df::exists_(&state.id, name)) {
+            let last_writer: &LastWriter = df::borrow(&state.id, name);
+            option::some(last_writer.format)
+        } else {
+            option::none()
+        }
+    }
+
+    /// Get the symrefs (empty if none were ever set)
+    public fun get_symrefs(state: &RemoteState): VecMap<String, String> {
+        let name = string::utf8(SYMREFS_FIELD);
+        if (df::exists_(&state.id, name)) {
+            *df::borrow(&state.id, name)
+        } else {
+            vec_map::empty()
+        }
+    }
+
     /// Check if address is authorized
     public fun is_authorized(state: &RemoteState, addr: address): bool {
         // Owner always authorized
@@ -236,6 +426,16 @@ module walrus_remote::remote_state {
         }
     }
 
+    /// The refs of an inline-layout remote
+    fun inline_refs_mut(state: &mut RemoteState): &mut VecMap<String, String> {
+        df::borrow_mut(&mut state.id, string::utf8(INLINE_REFS_FIELD))
+    }
+
+    /// Whether a remote holding `count` refs may add one more under `max_refs`
+    fun below_limit(max_refs: &Option<u64>, count: u64): bool {
+        option::is_none(max_refs) || count < *option::borrow(max_refs)
+    }
+
     /// Check that caller holds a valid (non-expired) lock
     fun check_lock_held(state: &RemoteState, clock: &Clock, ctx: &TxContext) {
         assert!(option::is_some(&state.lock), ERR_NO_LOCK);
@@ -262,8 +462,30 @@ module walrus_remote::remote_state {
     }
 
     #[test_only]
-    public fun destroy_for_testing(state: RemoteState) {
-        let RemoteState { id, owner: _, refs, objects_blob_object_id: _, lock: _, allowlist: _ } = state;
+    public fun destroy_for_testing(mut state: RemoteState) {
+        if (has_inline_refs(&state)) {
+            let _: VecMap<String, String> = df::remove(&mut state.id, string::utf8(INLINE_REFS_FIELD));
+        };
+        let name = string::utf8(POLICY_FIELD);
+        if (df::exists_(&state.id, name)) {
+            let _: Policy = df::remove(&mut state.id, name);
+        };
+        let name = string::utf8(LAST_WRITER_FIELD);
+        if (df::exists_(&state.id, name)) {
+            let _: LastWriter = df::remove(&mut state.id, name);
+        };
+        let name = string::utf8(SYMREFS_FIELD);
+        if (df::exists_(&state.id, name)) {
+            let _: VecMap<String, String> = df::remove(&mut state.id, name);
+        };
+        let RemoteState {
+            id,
+            owner: _,
+            refs,
+            objects_blob_object_id: _,
+            lock: _,
+            allowlist: _,
+        } = state;
         table::drop(refs);
         object::delete(id);
     }
@@ -1,6 +1,48 @@
 pub mod capabilities;
 pub mod export;
 pub mod fetch;
+pub mod hooks;
 pub mod import;
 pub mod list;
+pub mod namespace;
 pub mod push;
+pub mod push_cert;
+pub mod push_options;
+pub mod ref_summary;
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    /// Calls that would write to stdout behind the protocol writer's back
+    const FORBIDDEN: &[&str] = &["stdout()", "print!(", "println!(", "dbg!("];
+
+    #[test]
+    fn test_handlers_only_write_through_protocol_writer() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/commands");
+        let mut checked = 0;
+
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "rs") {
+                continue;
+            }
+
+            let source = std::fs::read_to_string(&path).unwrap();
+            for (number, line) in source.lines().enumerate() {
+                for pattern in FORBIDDEN {
+                    assert!(
+                        !line.contains(pattern),
+                        "{}:{} writes to stdout directly ({}); use ProtocolWriter",
+                        path.display(),
+                        number + 1,
+                        pattern
+                    );
+                }
+            }
+            checked += 1;
+        }
+
+        assert!(checked >= 6, "expected to scan every command handler");
+    }
+}
@@ -1,17 +1,159 @@
-use std::io::Write;
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    protocol::{ProtocolWriter, SessionOptions},
+    storage::StorageBackend,
+};
+
+/// What a session advertises in answer to `capabilities`, and which options it accepts
+///
+/// Computed once per session from the backend and configuration, so the `capabilities` answer
+/// and the `option` and push handling can't disagree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapabilitySet {
+    /// Pushes through `export`, with signed tags kept verbatim
+    pub export: bool,
+    /// `option pushcert`, for `git push --signed`
+    pub push_certs: bool,
+    /// `option atomic`, for `git push --atomic`: the backend applies a push's refs in one write
+    pub atomic: bool,
+    /// `option push-option`, for `git push --push-option`: recorded in repository metadata
+    pub push_options: bool,
+    /// Where git keeps fast-export marks between pushes
+    pub marks_file: Option<PathBuf>,
+    /// Where git records what pushes sent, `refs/walrus/<remote>` (see [`private_name`])
+    pub private_refs: String,
+}
+
+impl CapabilitySet {
+    /// Capabilities of a session on `storage` for the remote git calls `remote_name`, with
+    /// `options`
+    pub fn for_session(
+        storage: &impl StorageBackend,
+        remote_name: &str,
+        options: &SessionOptions,
+    ) -> Self {
+        let private_refs = format!("refs/walrus/{}", private_name(remote_name));
+        if options.read_only {
+            return Self {
+                private_refs,
+                ..Self::default()
+            };
+        }
+        Self {
+            export: true,
+            push_certs: true,
+            atomic: storage.atomic_ref_updates(),
+            push_options: true,
+            marks_file: options.marks_file.clone(),
+            private_refs,
+        }
+    }
+
+    /// Whether git may set any `option`
+    fn options(&self) -> bool {
+        self.push_certs || self.atomic || self.push_options
+    }
+}
 
 /// Handle the capabilities command
-/// Output the capabilities this remote helper supports
-pub fn handle<W: Write>(output: &mut W) -> Result<()> {
+/// Output the capabilities this remote helper supports (no `export` when read-only)
+///
+/// With a marks file, git keeps fast-export marks there between pushes; it is created empty if
+/// missing, since fast-export refuses to import marks from a file that doesn't exist.
+pub fn handle<W: Write>(
+    output: &mut ProtocolWriter<W>,
+    capabilities: &CapabilitySet,
+) -> Result<()> {
     // Use fetch capability for native pack format (no fast-export/import)
     // Export is still used for push operations
-    writeln!(output, "fetch")?;
-    writeln!(output, "export")?;
-    writeln!(output, "refspec refs/heads/*:refs/heads/*")?;
-    writeln!(output, "refspec refs/tags/*:refs/tags/*")?;
-    writeln!(output)?; // Empty line signals completion
+    output.line("fetch")?;
+    if capabilities.export {
+        output.line("export")?;
+        // Have git run fast-export with --signed-tags=verbatim instead of stripping signatures
+        output.line("signed-tags")?;
+        // Lets git pass `option pushcert` (`git push --signed`), `atomic` and `push-option`
+        if capabilities.options() {
+            output.line("option")?;
+        }
+        // Git excludes what the remote's refs reach from fast-export; with marks, an annotated
+        // tag of an excluded (already pushed) commit is exported instead of aborting the push
+        if let Some(marks_file) = &capabilities.marks_file {
+            ensure_marks_file(marks_file)?;
+            output.line(format_args!("export-marks {}", marks_file.display()))?;
+            output.line(format_args!("import-marks {}", marks_file.display()))?;
+        }
+    }
+    output.line(format_args!(
+        "refspec refs/heads/*:{}/heads/*",
+        capabilities.private_refs
+    ))?;
+    output.line(format_args!(
+        "refspec refs/tags/*:{}/tags/*",
+        capabilities.private_refs
+    ))?;
+    output.end()?; // Empty line signals completion
 
     Ok(())
 }
+
+/// Name of the directory under `$GIT_DIR/walrus` and `refs/walrus` holding what git keeps for
+/// the remote git calls `remote_name`, so two remotes of one repository never share marks or
+/// private refs
+///
+/// Plain remote names are used as they are. Any other name, such as the URL git passes as the
+/// name of a remote given by URL alone, becomes `url-<hash of the name>`.
+pub fn private_name(remote_name: &str) -> String {
+    let plain = !remote_name.is_empty()
+        && !remote_name.starts_with('.')
+        && !remote_name.ends_with(".lock")
+        && !remote_name.contains("..")
+        && remote_name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+    if plain {
+        return remote_name.to_string();
+    }
+    let digest = Sha256::digest(remote_name.as_bytes());
+    format!("url-{}", hex::encode(&digest[..8]))
+}
+
+/// Create an empty marks file (and its directory) unless one exists
+fn ensure_marks_file(marks_file: &Path) -> Result<()> {
+    if marks_file.exists() {
+        return Ok(());
+    }
+    if let Some(dir) = marks_file.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create marks directory {:?}", dir))?;
+    }
+    fs::write(marks_file, "")
+        .with_context(|| format!("Failed to create marks file {:?}", marks_file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_private_name_per_remote() {
+        assert_eq!(private_name("origin"), "origin");
+        assert_eq!(private_name("my-remote_2.x"), "my-remote_2.x");
+
+        // URLs (and anything else that isn't a plain name) are hashed, one name per URL
+        let url = private_name("walrus::0x1234");
+        assert!(url.starts_with("url-") && url.len() == 20, "{}", url);
+        assert_eq!(private_name("walrus::0x1234"), url);
+        assert_ne!(private_name("walrus::0x5678"), url);
+        for name in ["team/storage", ".hidden", "x.lock", "a..b", ""] {
+            assert!(private_name(name).starts_with("url-"), "{}", name);
+        }
+    }
+}
@@ -1,47 +1,81 @@
 use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
     io::{BufRead, Write},
-    process::Command,
+    path::Path,
+    time::Duration,
 };
 
 use anyhow::{Context, Result};
 
-use crate::{git::fast_export, pack::receive_pack, storage::StorageBackend};
+use super::{
+    fetch::{read_shallow, RepoPaths},
+    hooks::{PushedRef, ZERO_SHA1},
+    namespace::{ref_name_from_bytes, RefNamespace},
+    push_cert,
+    ref_summary::{self, RefChangeRecord},
+};
+use crate::{
+    git::fast_export,
+    pack::{objects::ObjectId, receive_pack_with_epochs},
+    protocol::{ProtocolReader, ProtocolWriter, PushRequest, SessionOptions},
+    session_env,
+    storage::{metadata, ContentId, State, StateChanged, StorageBackend},
+    subprocess::CommandRunner,
+    sui::{projected_ref_count, RefChange, RemotePolicy},
+};
 
 /// Handle the export command (push)
 /// Uses pack format internally to preserve GPG signatures
+///
+/// With `--atomic`, a refused ref fails the whole push: nothing is stored. Push options are
+/// recorded with the push in the repository metadata.
 pub fn handle<S: StorageBackend, W: Write, R: BufRead>(
     storage: &S,
-    output: &mut W,
-    input: &mut std::io::Lines<R>,
+    output: &mut ProtocolWriter<W>,
+    input: &mut ProtocolReader<R>,
+    options: &SessionOptions,
+    request: &PushRequest,
 ) -> Result<()> {
+    let namespace = &options.namespace;
+    let hooks = &options.hooks;
+    let signing = options.signing(request.cert_mode);
     // Read the export commands from Git
-    // Note: Git runs fast-export for us, but it may fail for annotated tags
-    // We handle this gracefully by using git commands to get ref information
+    // Note: Git runs fast-export for us; only the ref names are used, and objects (including
+    // signed tag objects, byte for byte) are stored from a pack instead
     let parse_result = fast_export::parse_stream(input);
 
-    let ref_updates = match parse_result {
-        Ok((_stream_data, updates)) if !updates.is_empty() => updates,
+    let (ref_updates, non_utf8_refs) = match parse_result {
+        Ok(parsed) if !parsed.ref_updates.is_empty() || !parsed.non_utf8_refs.is_empty() => {
+            (parsed.ref_updates, parsed.non_utf8_refs)
+        }
         _ => {
             // Fast-export failed or returned no updates
             // This can happen with annotated tags
             // Fall back to using git show-ref to get all refs that need pushing
             tracing::warn!("fast-export failed or empty, using fallback method");
-            get_refs_from_git()?
+            (get_refs_from_git()?, Default::default())
         }
     };
+    // Refs named by bytes that aren't UTF-8 can't be stored, and are refused one by one
+    let mut refused: Vec<(Vec<u8>, anyhow::Error)> = non_utf8_refs
+        .into_iter()
+        .filter_map(|refname| {
+            let e = ref_name_from_bytes(&refname).err()?;
+            Some((refname, e))
+        })
+        .collect();
 
     tracing::debug!("ref updates from git: {:?}", ref_updates);
 
-    // For each ref being pushed, get the commit SHA
+    // Resolve every pushed ref to the commit SHA it points to locally
+    let mut resolved: Vec<(String, String)> = Vec::new();
     for refname in ref_updates.keys() {
         tracing::debug!(refname, "processing ref");
 
-        // Get the commit SHA that this ref points to locally
-        let sha_output = Command::new("git")
+        let sha_output = CommandRunner::git()
             .arg("rev-parse")
             .arg(refname)
-            .output()
-            .context("Failed to run git rev-parse")?;
+            .output()?;
 
         if !sha_output.status.success() {
             tracing::warn!("Could not resolve ref {}", refname);
@@ -52,73 +86,521 @@ pub fn handle<S: StorageBackend, W: Write, R: BufRead>(
             .trim()
             .to_string();
         tracing::debug!("Ref {} points to {}", refname, git_sha1);
+        resolved.push((refname.clone(), git_sha1));
+    }
+
+    // Refuse refs that would land outside this session's namespace or conflict with other refs,
+    // including refs accepted earlier in this push
+    let state = storage.read_state()?;
+    let mut refs = state.refs.clone();
+    resolved.retain(
+        |(refname, git_sha1)| match namespace.check_push(refname, &refs) {
+            Ok(()) => {
+                refs.insert(namespace.to_remote(refname), git_sha1.clone());
+                true
+            }
+            Err(e) => {
+                refused.push((refname.clone().into_bytes(), e));
+                false
+            }
+        },
+    );
+
+    // The repository git is pushing from (a daemon's own directory is elsewhere)
+    let repo_dir = session_env::current_dir()?;
+
+    // As receive-pack refuses shallow updates: a ref whose new history stops at a commit this
+    // shallow repository lacks the parents of would leave the remote without that history
+    let cut_off = shallow_commits_reached(&state, &resolved, &repo_dir)?;
+    resolved.retain(|(refname, _)| match cut_off.get(refname) {
+        Some(commit) => {
+            refused.push((
+                refname.clone().into_bytes(),
+                anyhow::anyhow!(
+                    "shallow update not allowed: the remote lacks the history of shallow \
+                     commit {}; run `git fetch --unshallow` first",
+                    commit
+                ),
+            ));
+            false
+        }
+        None => true,
+    });
+
+    if request.atomic && !refused.is_empty() {
+        refused.extend(
+            resolved
+                .drain(..)
+                .map(|(refname, _)| (refname.into_bytes(), anyhow::anyhow!("atomic push failed"))),
+        );
+    }
+
+    if !resolved.is_empty() {
+        let pushed: Vec<PushedRef> = resolved
+            .iter()
+            .map(|(refname, git_sha1)| PushedRef {
+                local_ref: refname.clone(),
+                local_sha1: git_sha1.clone(),
+                remote_ref: refname.clone(),
+                remote_sha1: state
+                    .refs
+                    .get(&namespace.to_remote(refname))
+                    .map_or(ZERO_SHA1.to_string(), String::clone),
+            })
+            .collect();
+        hooks.pre_push(&pushed)?;
+
+        let policy = storage.policy()?;
+        if !policy.is_unlimited() {
+            check_policy(&policy, &state, &resolved, namespace, &repo_dir)?;
+        }
+        let certificate = signing.certify(&state, &pushed)?;
+
+        let object_mappings = store_refs(
+            storage,
+            &state,
+            &resolved,
+            &repo_dir,
+            options.decompress_threads,
+        )?;
+        let counts = PushCounts::new(&state, &object_mappings);
+
+        tracing::debug!("stored {} objects", object_mappings.len());
+
+        // Update state with new objects and all refs in one go, noting each ref's old tip and
+        // where the new tip is stored for the summary
+        let mut records: Vec<(RefChangeRecord, Option<ContentId>)> = Vec::new();
+        update_state_retrying(storage, &pushed, namespace, &repo_dir, |state| {
+            records.clear();
+            if let Some(certificate) = &certificate {
+                push_cert::check_unchanged(certificate, state, &pushed)?;
+            }
+            state.insert_objects(
+                &object_mappings,
+                options.on_mapping_conflict,
+                |git_sha1, id| storage.read_git_objects(&[(git_sha1, id)]).is_ok(),
+            )?;
+            for (refname, git_sha1) in &resolved {
+                let old = state
+                    .refs
+                    .insert(namespace.to_remote(refname), git_sha1.clone());
+                let record = RefChangeRecord {
+                    refname: refname.clone(),
+                    old,
+                    new: git_sha1.clone(),
+                    subject: None,
+                };
+                records.push((record, state.objects.get(git_sha1).cloned()));
+            }
+            // Refresh last-push bookkeeping
+            let set: BTreeMap<String, String> = resolved
+                .iter()
+                .map(|(refname, git_sha1)| (namespace.to_remote(refname), git_sha1.clone()))
+                .collect();
+            metadata::record_push(
+                storage,
+                state,
+                &set,
+                certificate.clone(),
+                request.push_options.as_slice(),
+            )
+        })?;
+
+        let records: Vec<RefChangeRecord> = records
+            .into_iter()
+            .map(|(mut record, content_id)| {
+                if request.verbosity > 0 || tracing::enabled!(tracing::Level::DEBUG) {
+                    record.read_subject(storage, content_id.as_deref());
+                }
+                record
+            })
+            .collect();
+        ref_summary::report(&mut std::io::stderr(), &records, request.verbosity)?;
+
+        tracing::info!("Push summary: {}", counts);
+        if counts.diverges() {
+            tracing::warn!(
+                "{} of {} packed object(s) were already on the remote; the push re-sent \
+                 objects it should have left out",
+                counts.resent(),
+                counts.packed
+            );
+        }
+    }
 
-        // Create a packfile containing all objects for this ref
-        // Use git pack-objects to create the packfile
-        let state = storage.read_state()?;
-        let old_sha = state.refs.get(refname);
+    // Report success
+    for (refname, _) in &resolved {
+        output.line(format_args!("ok {}", refname))?;
+    }
+    for (refname, e) in &refused {
+        output.ref_error(refname, e)?;
+    }
+
+    // Empty line signals completion
+    output.end()?;
+
+    Ok(())
+}
+
+/// Retries of the state write when another push changes the remote state first
+const MAX_STATE_RETRIES: u32 = 3;
+
+/// Wait before the first retry of the state write, doubled for each one after it
+const STATE_RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Apply a push's `update_fn` to the remote state, retrying while other pushes change it first
+///
+/// The objects are already stored, so only the state write is redone, over a fresh read. That
+/// is only safe while the `pushed` refs themselves haven't moved: a ref another push moved
+/// fails the push, unless it moved to a commit this push builds on.
+fn update_state_retrying<S: StorageBackend>(
+    storage: &S,
+    pushed: &[PushedRef],
+    namespace: &RefNamespace,
+    repo_dir: &Path,
+    mut update_fn: impl FnMut(&mut State) -> Result<()>,
+) -> Result<()> {
+    let mut retries = 0;
+    loop {
+        let result = storage.update_state(|state| {
+            check_tips_unmoved(state, pushed, namespace, repo_dir)?;
+            update_fn(state)
+        });
+        match result {
+            Err(e) if retries < MAX_STATE_RETRIES && e.is::<StateChanged>() => {
+                let delay = STATE_RETRY_BACKOFF * 2u32.pow(retries);
+                retries += 1;
+                tracing::warn!(
+                    "Another push changed the remote; writing the refs again in {:?} \
+                     (retry {} of {})",
+                    delay,
+                    retries,
+                    MAX_STATE_RETRIES
+                );
+                std::thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
 
-        // Build revision range for incremental push
-        let rev_range = if let Some(old) = old_sha {
-            format!("{}..{}", old, git_sha1)
-        } else {
-            git_sha1.clone()
+/// Fail if a `pushed` ref moved on the remote since the push read it, other than to a commit
+/// the push builds on
+fn check_tips_unmoved(
+    state: &State,
+    pushed: &[PushedRef],
+    namespace: &RefNamespace,
+    repo_dir: &Path,
+) -> Result<()> {
+    for update in pushed {
+        let Some(current) = state.refs.get(&namespace.to_remote(&update.remote_ref)) else {
+            continue;
         };
+        if *current == update.remote_sha1 || *current == update.local_sha1 {
+            continue;
+        }
+        let is_ancestor = CommandRunner::git()
+            .current_dir(repo_dir)
+            .args([
+                "merge-base",
+                "--is-ancestor",
+                current.as_str(),
+                update.local_sha1.as_str(),
+            ])
+            .output()?
+            .status
+            .success();
+        if !is_ancestor {
+            anyhow::bail!(
+                "{} moved on the remote to {} during the push; fetch and push again",
+                update.remote_ref,
+                current
+            );
+        }
+    }
+    Ok(())
+}
 
-        tracing::debug!("Creating packfile for {}", rev_range);
+/// Fail before anything is uploaded if pushing `resolved` refs (name -> tip) from the
+/// repository at `repo_dir` would leave `state` over the remote's limits
+///
+/// Objects are only counted when `max_objects` is set, by listing everything reachable from
+/// the pushed tips.
+fn check_policy(
+    policy: &RemotePolicy,
+    state: &State,
+    resolved: &[(String, String)],
+    namespace: &RefNamespace,
+    repo_dir: &Path,
+) -> Result<()> {
+    let names: Vec<String> = resolved
+        .iter()
+        .map(|(refname, _)| namespace.to_remote(refname))
+        .collect();
+    let changes: Vec<RefChange> = names.iter().map(|name| RefChange::Upsert(name)).collect();
+    let refs_after = projected_ref_count(state.refs.keys(), &changes);
+
+    let objects_after = match policy.max_objects {
+        Some(_) => {
+            let revs: String = resolved
+                .iter()
+                .map(|(_, tip)| format!("{}\n", tip))
+                .collect();
+            let listing = CommandRunner::git()
+                .current_dir(repo_dir)
+                .arg("rev-list")
+                .arg("--objects")
+                .arg("--stdin")
+                .stdin(revs)
+                .run()
+                .context("Failed to count the objects to push")?;
+            let new_objects = String::from_utf8_lossy(&listing.stdout)
+                .lines()
+                .filter_map(|line| line.split_whitespace().next())
+                .filter(|id| !state.objects.contains_key(*id))
+                .count();
+            Some(state.objects.len() + new_objects)
+        }
+        None => None,
+    };
+
+    policy
+        .check(refs_after, objects_after)
+        .context("Push refused by the remote's on-chain policy")
+}
+
+/// Re-sent objects a push may pack before [`PushCounts::diverges`] reports it, however small
+const RESENT_TOLERANCE: usize = 8;
+
+/// Objects git packed for a push versus entries the push added to the objects map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PushCounts {
+    packed: usize,
+    added: usize,
+}
+
+impl PushCounts {
+    /// Counts for storing `object_mappings` (one per packed object) on top of `state`
+    fn new(state: &State, object_mappings: &[(ObjectId, ContentId)]) -> Self {
+        let added: HashSet<&str> = object_mappings
+            .iter()
+            .map(|(obj_id, _)| obj_id.as_str())
+            .filter(|obj_id| !state.objects.contains_key(*obj_id))
+            .collect();
+        Self {
+            packed: object_mappings.len(),
+            added: added.len(),
+        }
+    }
+
+    /// Packed objects that were already on the remote or packed twice
+    fn resent(&self) -> usize {
+        self.packed.saturating_sub(self.added)
+    }
+
+    /// Whether more than a tenth of the pack (and more than [`RESENT_TOLERANCE`] objects) was
+    /// re-sent
+    fn diverges(&self) -> bool {
+        self.resent() > RESENT_TOLERANCE.max(self.packed / 10)
+    }
+}
+
+impl std::fmt::Display for PushCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} object(s) packed, {} added to the objects map",
+            self.packed, self.added
+        )
+    }
+}
+
+/// Pushed refs whose newly introduced objects are stored for the same number of epochs
+struct RetentionGroup<'a> {
+    /// None when the backend's objects don't expire
+    epochs: Option<u32>,
+    /// Ref name, tip and the `retention_rules` glob that chose `epochs`
+    refs: Vec<(&'a str, &'a str, Option<String>)>,
+}
+
+/// Group `resolved` refs (name -> tip) by retention, longest first
+fn retention_groups<'a>(
+    storage: &impl StorageBackend,
+    resolved: &'a [(String, String)],
+) -> Vec<RetentionGroup<'a>> {
+    let mut groups: Vec<RetentionGroup> = Vec::new();
+    for (refname, git_sha1) in resolved {
+        let retention = storage.retention_for(refname);
+        let epochs = retention.as_ref().map(|r| r.epochs);
+        let entry = (
+            refname.as_str(),
+            git_sha1.as_str(),
+            retention.and_then(|r| r.rule),
+        );
+        match groups.iter_mut().find(|group| group.epochs == epochs) {
+            Some(group) => group.refs.push(entry),
+            None => groups.push(RetentionGroup {
+                epochs,
+                refs: vec![entry],
+            }),
+        }
+    }
+    groups.sort_by_key(|group| std::cmp::Reverse(group.epochs));
+    groups
+}
+
+/// Pack the objects reachable from `resolved` refs in the repository at `repo_dir` and store them
+///
+/// Objects reachable from refs already in `state` are left out when the repository has their
+/// tips, since the remote holds everything its refs reach.
+///
+/// Branches pushed together usually share most of their history (often the same tip), so each
+/// retention group is packed once for the union of its tips. Groups are stored longest
+/// retention first and each pack leaves out what earlier groups reached, so an object shared
+/// between refs gets the most epochs any of them asks for.
+fn store_refs(
+    storage: &impl StorageBackend,
+    state: &State,
+    resolved: &[(String, String)],
+    repo_dir: &Path,
+    decompress_threads: usize,
+) -> Result<Vec<(ObjectId, ContentId)>> {
+    let groups = retention_groups(storage, resolved);
+    let remote_tips = local_objects(state.refs.values(), repo_dir)?;
+    let report = groups.len() > 1
+        || groups
+            .iter()
+            .any(|group| group.refs.iter().any(|(_, _, rule)| rule.is_some()));
+
+    let mut stored_tips: Vec<&str> = Vec::new();
+    let mut object_mappings = Vec::new();
+    for group in &groups {
+        let mut tips: Vec<&str> = group.refs.iter().map(|(_, tip, _)| *tip).collect();
+        tips.sort_unstable();
+        tips.dedup();
+        let revs: String = tips
+            .iter()
+            .map(|tip| format!("{}\n", tip))
+            .chain(remote_tips.iter().map(|tip| format!("^{}\n", tip)))
+            .chain(stored_tips.iter().map(|tip| format!("^{}\n", tip)))
+            .collect();
+
+        tracing::debug!(
+            "Creating packfile for {} ref(s) with {} distinct tip(s)",
+            group.refs.len(),
+            tips.len()
+        );
 
         // Use git pack-objects --include-tag to include annotated tag objects
-        let mut pack_output = Command::new("git")
+        // The revisions are passed on pack-objects stdin
+        let pack_result = CommandRunner::git()
+            .current_dir(repo_dir)
             .arg("pack-objects")
             .arg("--revs")
             .arg("--include-tag") // Include annotated tag objects
             .arg("--stdout")
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .spawn()
-            .context("Failed to spawn git pack-objects")?;
-
-        // Write the revision to pack-objects stdin
-        {
-            use std::io::Write as _;
-            if let Some(ref mut stdin) = pack_output.stdin {
-                writeln!(stdin, "{}", git_sha1)?;
-            }
-        }
-
-        let pack_result = pack_output.wait_with_output()?;
-        if !pack_result.status.success() {
-            anyhow::bail!("git pack-objects failed");
-        }
+            .stdin(revs)
+            .run()?;
 
         tracing::debug!("created packfile of {} bytes", pack_result.stdout.len());
 
         // Receive and store the packfile
         let mut pack_data = &pack_result.stdout[..];
-        let object_mappings =
-            receive_pack(&mut pack_data, storage).context("Failed to receive pack")?;
+        let mappings = receive_pack_with_epochs(
+            &mut pack_data,
+            storage,
+            &state.objects,
+            group.epochs,
+            decompress_threads,
+        )
+        .context("Failed to receive pack")?;
 
-        tracing::debug!("stored {} objects", object_mappings.len());
+        if let (true, Some(epochs)) = (report, group.epochs) {
+            let refs: Vec<String> = group
+                .refs
+                .iter()
+                .map(|(refname, _, rule)| match rule {
+                    Some(rule) => format!("{} (rule {})", refname, rule),
+                    None => format!("{} (default_epochs)", refname),
+                })
+                .collect();
+            tracing::info!(
+                "Retention: {} epochs for {} ({} object(s))",
+                epochs,
+                refs.join(", "),
+                mappings.len()
+            );
+        }
 
-        // Update state with new objects and ref
-        storage.update_state(|state| {
-            // Add all object mappings
-            for (obj_id, content_id) in &object_mappings {
-                state.objects.insert(obj_id.clone(), content_id.clone());
-            }
-            // Update the ref to point to the new commit
-            state.refs.insert(refname.clone(), git_sha1.clone());
-            Ok(())
-        })?;
+        object_mappings.extend(mappings);
+        stored_tips.extend(tips);
+    }
 
-        // Report success
-        writeln!(output, "ok {}", refname)?;
+    Ok(object_mappings)
+}
+
+/// For each of `resolved` refs (name -> tip), a commit its new history reaches that the
+/// repository at `repo_dir` is shallow at and the remote doesn't store (none if the
+/// repository isn't shallow)
+fn shallow_commits_reached(
+    state: &State,
+    resolved: &[(String, String)],
+    repo_dir: &Path,
+) -> Result<BTreeMap<String, ObjectId>> {
+    let shallow: HashSet<ObjectId> = read_shallow(&RepoPaths::resolve()?.shallow_file)?
+        .into_iter()
+        .filter(|commit| !state.objects.contains_key(commit))
+        .collect();
+    if shallow.is_empty() {
+        return Ok(BTreeMap::new());
     }
 
-    // Empty line signals completion
-    writeln!(output)?;
+    let remote_tips = local_objects(state.refs.values(), repo_dir)?;
+    let mut reached = BTreeMap::new();
+    for (refname, tip) in resolved {
+        let revs: String = std::iter::once(format!("{}\n", tip))
+            .chain(remote_tips.iter().map(|tip| format!("^{}\n", tip)))
+            .collect();
+        let listing = CommandRunner::git()
+            .current_dir(repo_dir)
+            .args(["rev-list", "--stdin"])
+            .stdin(revs)
+            .run()
+            .with_context(|| format!("Failed to list the commits {} adds", refname))?;
+        if let Some(commit) = String::from_utf8_lossy(&listing.stdout)
+            .lines()
+            .find(|commit| shallow.contains(*commit))
+        {
+            reached.insert(refname.clone(), commit.to_string());
+        }
+    }
+    Ok(reached)
+}
 
-    Ok(())
+/// The distinct `ids` the repository at `repo_dir` has
+fn local_objects<'a>(
+    ids: impl IntoIterator<Item = &'a String>,
+    repo_dir: &Path,
+) -> Result<BTreeSet<&'a str>> {
+    let ids: BTreeSet<&str> = ids.into_iter().map(String::as_str).collect();
+    if ids.is_empty() {
+        return Ok(ids);
+    }
+    let query: String = ids.iter().map(|id| format!("{}\n", id)).collect();
+    let output = CommandRunner::git()
+        .current_dir(repo_dir)
+        .arg("cat-file")
+        .arg("--batch-check")
+        .stdin(query)
+        .run()
+        .context("Failed to check which remote tips exist locally")?;
+    // Missing objects are reported as `<id> missing`
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let missing: BTreeSet<&str> = stdout
+        .lines()
+        .filter_map(|line| line.strip_suffix(" missing"))
+        .collect();
+    Ok(ids.into_iter().filter(|id| !missing.contains(id)).collect())
 }
 
 /// Fallback method to get refs when fast-export fails
@@ -128,3 +610,333 @@ fn get_refs_from_git() -> Result<std::collections::HashMap<String, String>> {
     // The export handler will get the SHA using git rev-parse for each ref anyway
     Ok(std::collections::HashMap::new())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::{
+        config::{Retention, WalrusRemoteConfig},
+        storage::{ImmutableStore, MemoryStorage, MutableState, State},
+        walrus::{mock_client, WalrusClient},
+    };
+
+    /// Memory-backed storage that also uploads each write as one blob through the mock CLI
+    struct RetentionStorage {
+        memory: MemoryStorage,
+        client: WalrusClient,
+        config: WalrusRemoteConfig,
+        /// (epochs, object count) of each write
+        writes: RefCell<Vec<(u32, usize)>>,
+    }
+
+    impl ImmutableStore for RetentionStorage {
+        fn write_object(&self, content: &[u8]) -> Result<ContentId> {
+            self.memory.write_object(content)
+        }
+
+        fn write_objects(&self, contents: &[&[u8]]) -> Result<Vec<ContentId>> {
+            self.write_objects_with_epochs(contents, None)
+        }
+
+        fn write_objects_with_epochs(
+            &self,
+            contents: &[&[u8]],
+            epochs: Option<u32>,
+        ) -> Result<Vec<ContentId>> {
+            let epochs = epochs.unwrap_or(self.config.default_epochs);
+            if !contents.is_empty() {
+                self.client.store_with_epochs(&contents.concat(), epochs)?;
+            }
+            self.writes.borrow_mut().push((epochs, contents.len()));
+            self.memory.write_objects(contents)
+        }
+
+        fn read_object(&self, id: &str) -> Result<Vec<u8>> {
+            self.memory.read_object(id)
+        }
+
+        fn read_objects(&self, ids: &[&str]) -> Result<Vec<Vec<u8>>> {
+            self.memory.read_objects(ids)
+        }
+
+        fn delete_object(&self, id: &str) -> Result<()> {
+            self.memory.delete_object(id)
+        }
+
+        fn object_exists(&self, id: &str) -> Result<bool> {
+            self.memory.object_exists(id)
+        }
+    }
+
+    impl MutableState for RetentionStorage {
+        fn read_state(&self) -> Result<State> {
+            self.memory.read_state()
+        }
+
+        fn write_state(&self, state: &State) -> Result<()> {
+            self.memory.write_state(state)
+        }
+
+        fn update_state<F>(&self, update_fn: F) -> Result<()>
+        where
+            F: FnOnce(&mut State) -> Result<()>,
+        {
+            self.memory.update_state(update_fn)
+        }
+    }
+
+    impl StorageBackend for RetentionStorage {
+        fn initialize(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn retention_for(&self, refname: &str) -> Option<Retention> {
+            Some(self.config.retention_for(refname))
+        }
+    }
+
+    fn git(repo: &Path, args: &[&str]) -> String {
+        let output = CommandRunner::git()
+            .current_dir(repo)
+            .args(["-c", "user.name=T", "-c", "user.email=t@example.com"])
+            .args(args)
+            .run()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    fn commit_file(repo: &Path, name: &str) -> String {
+        std::fs::write(repo.join(name), name).unwrap();
+        git(repo, &["add", name]);
+        git(repo, &["commit", "-q", "-m", name]);
+        git(repo, &["rev-parse", "HEAD"])
+    }
+
+    #[test]
+    fn test_retention_rules_choose_epochs_per_batch() {
+        let repo = tempfile::tempdir().unwrap();
+        git(repo.path(), &["init", "-q"]);
+        let release = commit_file(repo.path(), "release.txt");
+        let spike = commit_file(repo.path(), "spike.txt");
+
+        let mock_dir = tempfile::tempdir().unwrap();
+        let config: WalrusRemoteConfig = serde_yaml::from_str(
+            r#"
+sui_wallet_path: /wallet
+cache_dir: /cache
+default_epochs: 5
+retention_rules:
+  - { refs: "refs/tags/v*", epochs: 53 }
+  - { refs: "refs/heads/tmp/*", epochs: 1 }
+"#,
+        )
+        .unwrap();
+        let storage = RetentionStorage {
+            memory: MemoryStorage::new(),
+            client: mock_client(mock_dir.path()),
+            config,
+            writes: RefCell::default(),
+        };
+
+        // The branch is listed first, but the tag's history is stored (for longer) first
+        let resolved = vec![
+            ("refs/heads/tmp/spike".to_string(), spike.clone()),
+            ("refs/tags/v1.0".to_string(), release.clone()),
+        ];
+        let mappings = store_refs(&storage, &State::default(), &resolved, repo.path(), 0).unwrap();
+
+        // Each commit brings its own commit, tree and blob; the tag's are not stored again
+        assert_eq!(*storage.writes.borrow(), vec![(53, 3), (1, 3)]);
+        assert_eq!(mappings.len(), 6);
+        assert!(mappings.iter().any(|(id, _)| *id == release));
+        assert!(mappings.iter().any(|(id, _)| *id == spike));
+
+        let calls = std::fs::read_to_string(mock_dir.path().join("calls.log")).unwrap();
+        let epochs: Vec<&str> = calls
+            .lines()
+            .filter(|line| line.starts_with("store"))
+            .map(|line| {
+                let args: Vec<&str> = line.split(' ').collect();
+                let at = args.iter().position(|arg| *arg == "--epochs").unwrap();
+                args[at + 1]
+            })
+            .collect();
+        assert_eq!(epochs, vec!["53", "1"]);
+
+        // A ref sharing the tag's tip adds nothing to the short-lived batch
+        storage.writes.borrow_mut().clear();
+        let resolved = vec![
+            ("refs/heads/tmp/copy".to_string(), release.clone()),
+            ("refs/tags/v1.0".to_string(), release),
+            ("refs/heads/main".to_string(), spike),
+        ];
+        store_refs(&storage, &State::default(), &resolved, repo.path(), 0).unwrap();
+        assert_eq!(*storage.writes.borrow(), vec![(53, 3), (5, 3), (1, 0)]);
+    }
+
+    #[test]
+    fn test_push_counts_match_pack_for_normal_pushes() {
+        let repo = tempfile::tempdir().unwrap();
+        git(repo.path(), &["init", "-q"]);
+        let first = commit_file(repo.path(), "first.txt");
+        let storage = MemoryStorage::new();
+
+        let push = |tip: &str| {
+            let state = storage.read_state().unwrap();
+            let resolved = vec![("refs/heads/main".to_string(), tip.to_string())];
+            let mappings = store_refs(&storage, &state, &resolved, repo.path(), 0).unwrap();
+            let counts = PushCounts::new(&state, &mappings);
+            storage
+                .update_state(|state| {
+                    state.objects.extend(mappings);
+                    state.refs.extend(resolved);
+                    Ok(())
+                })
+                .unwrap();
+            counts
+        };
+
+        // Commit, tree and blob each time: the second push leaves out what main already has
+        assert_eq!(
+            push(&first),
+            PushCounts {
+                packed: 3,
+                added: 3
+            }
+        );
+        let second = commit_file(repo.path(), "second.txt");
+        let counts = push(&second);
+        assert_eq!(
+            counts,
+            PushCounts {
+                packed: 3,
+                added: 3
+            }
+        );
+        assert!(!counts.diverges());
+        assert_eq!(
+            counts.to_string(),
+            "3 object(s) packed, 3 added to the objects map"
+        );
+    }
+
+    #[test]
+    fn test_state_write_retries_over_other_pushes() {
+        let repo = tempfile::tempdir().unwrap();
+        git(repo.path(), &["init", "-q"]);
+        let base = commit_file(repo.path(), "base.txt");
+        let theirs = commit_file(repo.path(), "theirs.txt");
+        let mine = commit_file(repo.path(), "mine.txt");
+        git(repo.path(), &["checkout", "-q", "-b", "other", &base]);
+        let other = commit_file(repo.path(), "other.txt");
+
+        let storage = MemoryStorage::new();
+        let set_main = |tip: &str| {
+            storage
+                .update_state(|state| {
+                    state
+                        .refs
+                        .insert("refs/heads/main".to_string(), tip.to_string());
+                    Ok(())
+                })
+                .unwrap();
+        };
+        set_main(&base);
+
+        // Another push lands each of `races` (ref, tip) from its own thread while this one is
+        // between reading and writing the state, so the write is refused
+        let races: RefCell<Vec<(String, String)>> = RefCell::default();
+        let push = |refname: &str, tip: &str| {
+            let state = storage.read_state().unwrap();
+            let pushed = vec![PushedRef {
+                local_ref: refname.to_string(),
+                local_sha1: tip.to_string(),
+                remote_ref: refname.to_string(),
+                remote_sha1: state
+                    .refs
+                    .get(refname)
+                    .map_or(ZERO_SHA1.to_string(), String::clone),
+            }];
+            let mut attempts = 0;
+            let result = update_state_retrying(
+                &storage,
+                &pushed,
+                &RefNamespace::default(),
+                repo.path(),
+                |state| {
+                    attempts += 1;
+                    if let Some((race_ref, race_tip)) = races.borrow_mut().pop() {
+                        std::thread::scope(|scope| {
+                            scope
+                                .spawn(|| {
+                                    storage.update_state(|state| {
+                                        state.refs.insert(race_ref, race_tip);
+                                        Ok(())
+                                    })
+                                })
+                                .join()
+                                .unwrap()
+                        })
+                        .unwrap();
+                    }
+                    state.refs.insert(refname.to_string(), tip.to_string());
+                    Ok(())
+                },
+            );
+            (result, attempts)
+        };
+        let main = || storage.read_state().unwrap().refs["refs/heads/main"].clone();
+
+        // Another push moving a different ref is kept, and the push lands on top of it
+        races
+            .borrow_mut()
+            .push(("refs/heads/main".to_string(), theirs.clone()));
+        let (result, attempts) = push("refs/heads/topic", &other);
+        result.unwrap();
+        assert_eq!(attempts, 2);
+        let state = storage.read_state().unwrap();
+        assert_eq!(state.refs["refs/heads/main"], theirs);
+        assert_eq!(state.refs["refs/heads/topic"], other);
+
+        // A ref moved to a commit the push builds on is still a fast-forward
+        set_main(&base);
+        races
+            .borrow_mut()
+            .push(("refs/heads/main".to_string(), theirs.clone()));
+        let (result, attempts) = push("refs/heads/main", &mine);
+        result.unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(main(), mine);
+
+        // A ref moved anywhere else fails the push and keeps the other push's tip
+        races
+            .borrow_mut()
+            .push(("refs/heads/main".to_string(), other.clone()));
+        let (result, attempts) = push("refs/heads/main", &theirs);
+        let message = format!("{:#}", result.unwrap_err());
+        assert_eq!(
+            message,
+            format!(
+                "refs/heads/main moved on the remote to {} during the push; fetch and push again",
+                other
+            )
+        );
+        assert_eq!(attempts, 1);
+        assert_eq!(main(), other);
+    }
+
+    #[test]
+    fn test_push_counts_divergence_tolerance() {
+        let counts = |packed, added| PushCounts { packed, added };
+        assert!(!counts(0, 0).diverges());
+        // A few re-sent objects are tolerated, however small the push
+        assert!(!counts(8, 0).diverges());
+        assert!(counts(9, 0).diverges());
+        // Large pushes tolerate a tenth of the pack
+        assert!(!counts(1000, 900).diverges());
+        assert!(counts(1000, 899).diverges());
+        assert_eq!(counts(1000, 899).resent(), 101);
+    }
+}
@@ -1,69 +1,131 @@
-//! Handle fetch command - write objects to .git/objects (no fast-export)
+//! Handle fetch command - write objects to the repository's object directory (no fast-export)
 
 use std::{
+    collections::HashSet,
     io::Write,
-    process::{Command, Stdio},
+    path::{Path, PathBuf},
 };
 
 use anyhow::{Context, Result};
 
-use crate::{pack::send_pack, storage::StorageBackend};
+use super::{hooks::Hooks, namespace::RefNamespace};
+use crate::{
+    config,
+    pack::{
+        diagnose::diagnose,
+        objects::ObjectId,
+        send_pack_with_deepen,
+        shallow::{Deepen, ShallowCut},
+    },
+    protocol::{FetchRequest, ProtocolWriter},
+    session_env,
+    storage::{State, StorageBackend},
+    subprocess::CommandRunner,
+};
 
-/// Handle fetch command - write objects to .git/objects for requested refs
+/// Handle fetch command - write objects to the repository for requested refs
 /// This replaces the old import handler and eliminates fast-export
 ///
-/// The fetch capability requires us to write objects to .git/objects, not to stdout.
+/// The fetch capability requires us to write objects into the repository, not to stdout.
 /// We do this by creating a packfile and piping it to `git index-pack --stdin`.
+///
+/// A shallow fetch (`deepen`, or any fetch into a shallow repository) also updates the
+/// repository's `shallow` file, which the helper protocol has no `shallow` lines for.
 pub fn handle<S: StorageBackend, W: Write>(
     storage: &S,
-    output: &mut W,
+    output: &mut ProtocolWriter<W>,
     refs: &[String],
+    namespace: &RefNamespace,
+    hooks: &Hooks,
+    request: &FetchRequest,
 ) -> Result<()> {
     tracing::debug!("fetch requested for refs: {:?}", refs);
 
-    // Create packfile in memory
-    let mut packfile = Vec::new();
-    send_pack(refs, storage, &mut packfile)?;
+    // Create packfile in memory (object IDs requested for HEAD aren't namespaced)
+    let mut remote_refs: Vec<String> = refs
+        .iter()
+        .map(|r| {
+            if r.starts_with("refs/") {
+                namespace.to_remote(r)
+            } else {
+                r.clone()
+            }
+        })
+        .collect();
 
-    // Write packfile to .git/objects using git index-pack
-    let git_dir = std::env::var("GIT_DIR").unwrap_or_else(|_| ".git".to_string());
+    // `git fetch --tags` (or tagOpt=--tags) wants every tag, even those no fetched branch
+    // reaches; git only asks to follow tags itself when the tags it names are all it wants
+    if !request.follow_tags && refs.iter().any(|r| r.starts_with("refs/tags/")) {
+        let tag_prefix = namespace.to_remote("refs/tags/");
+        let state = storage.read_state()?;
+        remote_refs.extend(
+            state
+                .refs
+                .keys()
+                .filter(|name| name.starts_with(&tag_prefix))
+                .cloned(),
+        );
+    }
+    let repo = RepoPaths::resolve()?;
+    tracing::debug!(
+        "fetching into {} (objects: {})",
+        repo.git_dir.display(),
+        repo.objects_dir.display()
+    );
 
-    let mut index_pack = Command::new("git")
+    // Without limits of its own, a fetch into a shallow repository stays behind its boundary;
+    // `--deepen` counts from it
+    let shallow = read_shallow(&repo.shallow_file)?;
+    let deepen = if request.deepen.is_requested() {
+        let mut deepen = resolve_deepen_not(&request.deepen, namespace, &storage.read_state()?);
+        if deepen.relative {
+            deepen.shallow = shallow.clone();
+        }
+        Some(deepen)
+    } else if !shallow.is_empty() {
+        Some(Deepen {
+            shallow: shallow.clone(),
+            ..Default::default()
+        })
+    } else {
+        None
+    };
+
+    // Only what the wanted refs reach is read: a single-branch clone requests just its branch or
+    // tag, following tags itself
+    let mut packfile = Vec::new();
+    let layout = send_pack_with_deepen(&remote_refs, storage, deepen.as_ref(), &mut packfile)?;
+    if let Some(path) = config::metrics_file_from_env()? {
+        if let Err(e) = layout.metrics.append_to(&path) {
+            tracing::warn!("{:#}", e);
+        }
+    }
+
+    // Write packfile to the repository's object directory using git index-pack
+    let result = CommandRunner::git()
         .arg("--git-dir")
-        .arg(&git_dir)
+        .arg(&repo.git_dir)
         .arg("index-pack")
         .arg("--stdin")
         .arg("--fix-thin")
         .arg("-v")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .context("Failed to spawn git index-pack")?;
-
-    // Write packfile to stdin
-    index_pack
-        .stdin
-        .as_mut()
-        .unwrap()
-        .write_all(&packfile)
-        .context("Failed to write packfile to git index-pack")?;
-    drop(index_pack.stdin.take());
-
-    // Wait for git index-pack to complete
-    let result = index_pack
-        .wait_with_output()
-        .context("Failed to wait for git index-pack")?;
-
+        .env("GIT_OBJECT_DIRECTORY", &repo.objects_dir)
+        .stdin(packfile)
+        .output()
+        .context("Failed to index fetched pack")?;
     if !result.status.success() {
-        tracing::error!(
-            "git index-pack stderr: {}",
-            String::from_utf8_lossy(&result.stderr)
-        );
-        anyhow::bail!(
-            "git index-pack failed: {}",
-            String::from_utf8_lossy(&result.stderr)
+        // Name the stored object behind git's complaint, which only gives a pack offset or SHA-1
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        let mut message = format!(
+            "Failed to index fetched pack: `git index-pack` failed ({}): {}",
+            result.status,
+            stderr.trim()
         );
+        if let Some(diagnosis) = diagnose(&stderr, &layout, storage) {
+            message.push('\n');
+            message.push_str(&diagnosis);
+        }
+        anyhow::bail!(message);
     }
 
     tracing::debug!(
@@ -75,10 +137,213 @@ pub fn handle<S: StorageBackend, W: Write>(
         String::from_utf8_lossy(&result.stderr)
     );
 
+    if let Some(cut) = &layout.shallow {
+        write_shallow(&repo.shallow_file, &update_shallow(&shallow, cut))?;
+    }
+
+    // Failing here, before the blank line, keeps git from updating its refs
+    if hooks.has_post_fetch() {
+        let state = storage.read_state()?;
+        let mut fetched: Vec<(String, String)> = refs
+            .iter()
+            .zip(&remote_refs)
+            .filter_map(|(r, remote_ref)| {
+                if r.starts_with("refs/") {
+                    Some((state.refs.get(remote_ref)?.clone(), r.clone()))
+                } else {
+                    // A bare object ID is what git asks for when HEAD is detached
+                    Some((r.clone(), "HEAD".to_string()))
+                }
+            })
+            .collect();
+        // Clones ask for the ref HEAD points at twice
+        let mut seen = std::collections::HashSet::new();
+        fetched.retain(|entry| seen.insert(entry.clone()));
+        hooks.post_fetch(&fetched)?;
+    }
+
     // Output blank line to signal completion
-    writeln!(output)?;
+    output.end()?;
     output.flush()?;
 
     tracing::info!("fetch completed");
     Ok(())
 }
+
+/// Where the repository git invoked us for keeps its metadata and objects
+#[derive(Debug)]
+pub(super) struct RepoPaths {
+    /// Absolute git directory (`.git`, a bare repo, or `.git/worktrees/<name>`)
+    git_dir: PathBuf,
+    /// Absolute object directory (shared by all worktrees of a repository)
+    objects_dir: PathBuf,
+    /// The repository's list of commits whose parents it lacks
+    pub(super) shallow_file: PathBuf,
+}
+
+impl RepoPaths {
+    /// Ask git rather than assuming `./.git`: git sets `GIT_DIR` for helpers, but bare repos,
+    /// linked worktrees and `--separate-git-dir` all keep objects somewhere else
+    pub(super) fn resolve() -> Result<Self> {
+        let output = CommandRunner::git()
+            .args([
+                "rev-parse",
+                "--absolute-git-dir",
+                "--git-path",
+                "objects",
+                "--git-path",
+                "shallow",
+            ])
+            .run()
+            .context("Failed to locate the git repository to fetch into")?;
+        Self::parse(
+            &String::from_utf8_lossy(&output.stdout),
+            &session_env::current_dir()?,
+        )
+    }
+
+    /// Parse `git rev-parse --absolute-git-dir --git-path objects --git-path shallow` output
+    /// run from `cwd`
+    fn parse(output: &str, cwd: &Path) -> Result<Self> {
+        let mut lines = output.lines();
+        let (Some(git_dir), Some(objects_dir), Some(shallow_file)) =
+            (lines.next(), lines.next(), lines.next())
+        else {
+            anyhow::bail!("Unexpected `git rev-parse` output: {:?}", output);
+        };
+
+        // --git-path is relative to the current directory unless GIT_DIR is absolute
+        Ok(Self {
+            git_dir: PathBuf::from(git_dir),
+            objects_dir: cwd.join(objects_dir),
+            shallow_file: cwd.join(shallow_file),
+        })
+    }
+}
+
+/// Map `deepen-not` names to stored refs, trying a short name as a branch and then a tag
+///
+/// Names matching no ref are kept as given, for an object ID or the error naming them.
+fn resolve_deepen_not(deepen: &Deepen, namespace: &RefNamespace, state: &State) -> Deepen {
+    let not = deepen
+        .not
+        .iter()
+        .map(|name| {
+            let candidates = if name.starts_with("refs/") {
+                vec![name.clone()]
+            } else {
+                vec![
+                    format!("refs/heads/{}", name),
+                    format!("refs/tags/{}", name),
+                ]
+            };
+            candidates
+                .iter()
+                .map(|candidate| namespace.to_remote(candidate))
+                .find(|stored| state.refs.contains_key(stored))
+                .unwrap_or_else(|| name.clone())
+        })
+        .collect();
+    Deepen {
+        not,
+        ..deepen.clone()
+    }
+}
+
+/// Commits listed in a repository's `shallow` file (none if it has no such file)
+pub(super) fn read_shallow(path: &Path) -> Result<HashSet<ObjectId>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(text
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// The repository's shallow commits after receiving `cut`: those it now has the parents of
+/// drop out, and the commits sent without theirs join
+fn update_shallow(old: &HashSet<ObjectId>, cut: &ShallowCut) -> Vec<ObjectId> {
+    let mut shallow: Vec<ObjectId> = old
+        .iter()
+        .filter(|id| !cut.commits.contains(*id))
+        .chain(&cut.boundary)
+        .cloned()
+        .collect();
+    shallow.sort();
+    shallow.dedup();
+    shallow
+}
+
+/// Replace the `shallow` file through `shallow.lock`, as git does, removing it once empty
+fn write_shallow(path: &Path, shallow: &[ObjectId]) -> Result<()> {
+    if shallow.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+    let lock = path.with_extension("lock");
+    let contents: String = shallow.iter().map(|id| format!("{}\n", id)).collect();
+    std::fs::write(&lock, contents)
+        .with_context(|| format!("Failed to write {}", lock.display()))?;
+    std::fs::rename(&lock, path).with_context(|| format!("Failed to update {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_repo_paths() {
+        let cwd = Path::new("/work/repo");
+
+        let repo = RepoPaths::parse("/work/repo/.git\n.git/objects\n.git/shallow\n", cwd).unwrap();
+        assert_eq!(repo.git_dir, Path::new("/work/repo/.git"));
+        assert_eq!(repo.objects_dir, Path::new("/work/repo/.git/objects"));
+        assert_eq!(repo.shallow_file, Path::new("/work/repo/.git/shallow"));
+
+        // Linked worktrees share the main repository's objects
+        let repo = RepoPaths::parse(
+            "/work/repo/.git/worktrees/wt\n/work/repo/.git/objects\n/work/repo/.git/shallow\n",
+            Path::new("/work/wt"),
+        )
+        .unwrap();
+        assert_eq!(repo.git_dir, Path::new("/work/repo/.git/worktrees/wt"));
+        assert_eq!(repo.objects_dir, Path::new("/work/repo/.git/objects"));
+
+        assert!(RepoPaths::parse("/work/repo/.git\n", cwd).is_err());
+        assert!(RepoPaths::parse("/work/repo/.git\n.git/objects\n", cwd).is_err());
+    }
+
+    #[test]
+    fn test_update_shallow() {
+        let ids = |ids: &[&str]| -> Vec<ObjectId> { ids.iter().map(|id| id.to_string()).collect() };
+        let cut = ShallowCut {
+            commits: ids(&["a", "b", "c"]).into_iter().collect(),
+            boundary: ids(&["c"]),
+        };
+
+        // "a" was shallow and now has its parents; "z" wasn't touched
+        let old = ids(&["a", "z"]).into_iter().collect();
+        assert_eq!(update_shallow(&old, &cut), ids(&["c", "z"]));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shallow");
+        write_shallow(&path, &update_shallow(&old, &cut)).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "c\nz\n");
+        assert_eq!(
+            read_shallow(&path).unwrap(),
+            ids(&["c", "z"]).into_iter().collect()
+        );
+
+        // Unshallowing removes the file
+        write_shallow(&path, &[]).unwrap();
+        assert!(!path.exists());
+        assert!(read_shallow(&path).unwrap().is_empty());
+    }
+}
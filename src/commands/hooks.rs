@@ -0,0 +1,241 @@
+//! Client-side hooks around pushes and fetches, for policy checks a remote has no server for
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::{config::WalrusRemoteConfig, session_env, subprocess::CommandRunner};
+
+/// Set to skip every hook in an emergency
+pub const NO_HOOKS_ENV: &str = "WALRUS_REMOTE_NO_HOOKS";
+
+/// Object ID git's hooks use for a ref that doesn't exist
+pub const ZERO_SHA1: &str = "0000000000000000000000000000000000000000";
+
+/// Default limit on how long a hook may run
+pub const DEFAULT_HOOK_TIMEOUT_SECS: u64 = 60;
+
+/// Shell commands run before a push is uploaded and after a fetch
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    pre_push: Option<String>,
+    post_fetch: Option<String>,
+    timeout: Duration,
+    /// Remote name and URL, passed to hooks as `$1` and `$2` like git's pre-push hook
+    remote: String,
+    url: String,
+}
+
+/// A ref a push updates, as a line of git's pre-push hook input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushedRef {
+    pub local_ref: String,
+    pub local_sha1: String,
+    pub remote_ref: String,
+    /// Current tip on the remote ([`ZERO_SHA1`] for a new ref)
+    pub remote_sha1: String,
+}
+
+impl Hooks {
+    /// Hooks for `remote` at `url` from `config` (filesystem remotes have none), overridden by
+    /// `WALRUS_REMOTE_PRE_PUSH_HOOK`, `WALRUS_REMOTE_POST_FETCH_HOOK` and
+    /// `WALRUS_REMOTE_HOOK_TIMEOUT_SECS`; no hooks at all when `WALRUS_REMOTE_NO_HOOKS` is set
+    pub fn resolve(config: Option<&WalrusRemoteConfig>, remote: &str, url: &str) -> Result<Self> {
+        let mut hooks = Self {
+            pre_push: config.and_then(|c| c.pre_push_hook.clone()),
+            post_fetch: config.and_then(|c| c.post_fetch_hook.clone()),
+            timeout: Duration::from_secs(
+                config.map_or(DEFAULT_HOOK_TIMEOUT_SECS, |c| c.hook_timeout_secs),
+            ),
+            remote: remote.to_string(),
+            url: url.to_string(),
+        };
+        if let Some(command) = session_env::var("WALRUS_REMOTE_PRE_PUSH_HOOK") {
+            hooks.pre_push = Some(command).filter(|c| !c.trim().is_empty());
+        }
+        if let Some(command) = session_env::var("WALRUS_REMOTE_POST_FETCH_HOOK") {
+            hooks.post_fetch = Some(command).filter(|c| !c.trim().is_empty());
+        }
+        if let Some(secs) = session_env::var("WALRUS_REMOTE_HOOK_TIMEOUT_SECS") {
+            hooks.timeout = Duration::from_secs(
+                secs.trim()
+                    .parse()
+                    .context("Failed to parse WALRUS_REMOTE_HOOK_TIMEOUT_SECS as u64")?,
+            );
+        }
+        if let Some(value) = session_env::var(NO_HOOKS_ENV) {
+            if !matches!(value.trim(), "" | "0" | "false" | "no" | "off") {
+                if hooks.pre_push.is_some() || hooks.post_fetch.is_some() {
+                    tracing::warn!(
+                        "{} is set; skipping pre-push and post-fetch hooks",
+                        NO_HOOKS_ENV
+                    );
+                }
+                hooks.pre_push = None;
+                hooks.post_fetch = None;
+            }
+        }
+        Ok(hooks)
+    }
+
+    /// Whether a pre-push hook will run
+    pub fn has_pre_push(&self) -> bool {
+        self.pre_push.is_some()
+    }
+
+    /// Run the pre-push hook, if any, failing if it rejects the push
+    ///
+    /// The hook gets `<local ref> <local sha1> <remote ref> <remote sha1>` lines on stdin,
+    /// as git's pre-push hook does.
+    pub fn pre_push(&self, refs: &[PushedRef]) -> Result<()> {
+        let Some(command) = &self.pre_push else {
+            return Ok(());
+        };
+        let input: String = refs
+            .iter()
+            .map(|r| {
+                format!(
+                    "{} {} {} {}\n",
+                    r.local_ref, r.local_sha1, r.remote_ref, r.remote_sha1
+                )
+            })
+            .collect();
+        self.run("pre-push", command, "push", input)
+    }
+
+    /// Whether a post-fetch hook will run
+    pub fn has_post_fetch(&self) -> bool {
+        self.post_fetch.is_some()
+    }
+
+    /// Run the post-fetch hook, if any, with `<sha1> <ref>` lines for the fetched refs on
+    /// stdin; failing makes the fetch fail, so git leaves its refs alone
+    pub fn post_fetch(&self, refs: &[(String, String)]) -> Result<()> {
+        let Some(command) = &self.post_fetch else {
+            return Ok(());
+        };
+        let input: String = refs
+            .iter()
+            .map(|(git_sha1, refname)| format!("{} {}\n", git_sha1, refname))
+            .collect();
+        self.run("post-fetch", command, "fetch", input)
+    }
+
+    fn run(&self, hook: &str, command: &str, action: &str, input: String) -> Result<()> {
+        tracing::info!("running {} hook `{}`", hook, command);
+        let output = CommandRunner::new("sh")
+            .arg("-c")
+            .arg(command)
+            .arg(hook)
+            .arg(&self.remote)
+            .arg(&self.url)
+            .stdin(input)
+            .timeout(self.timeout)
+            .output()
+            .with_context(|| {
+                format!(
+                    "{} hook `{}` did not complete, so the {} was stopped \
+                     (set {}=1 to skip hooks in an emergency)",
+                    hook, command, action, NO_HOOKS_ENV
+                )
+            })?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !stdout.trim().is_empty() {
+            tracing::info!("{} hook: {}", hook, stdout.trim());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} hook `{}` rejected the {} ({}): {}",
+                hook,
+                command,
+                action,
+                output.status,
+                stderr.trim()
+            );
+        }
+        if !stderr.trim().is_empty() {
+            tracing::info!("{} hook: {}", hook, stderr.trim());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn hooks(pre_push: Option<&str>, post_fetch: Option<&str>) -> Hooks {
+        Hooks {
+            pre_push: pre_push.map(str::to_string),
+            post_fetch: post_fetch.map(str::to_string),
+            timeout: Duration::from_secs(DEFAULT_HOOK_TIMEOUT_SECS),
+            remote: "origin".to_string(),
+            url: "walrus::/tmp/remote".to_string(),
+        }
+    }
+
+    fn pushed(local_sha1: &str, remote_sha1: &str) -> PushedRef {
+        PushedRef {
+            local_ref: "refs/heads/main".to_string(),
+            local_sha1: local_sha1.to_string(),
+            remote_ref: "refs/heads/main".to_string(),
+            remote_sha1: remote_sha1.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_pre_push_hook_accepts_with_git_input() {
+        let dir = TempDir::new().unwrap();
+        let seen = dir.path().join("seen");
+        let hook = format!("echo \"$0 $1 $2\" > {0}; cat >> {0}", seen.display());
+
+        let refs = [pushed(&"a".repeat(40), ZERO_SHA1)];
+        hooks(Some(&hook), None).pre_push(&refs).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&seen).unwrap(),
+            format!(
+                "pre-push origin walrus::/tmp/remote\nrefs/heads/main {} refs/heads/main {}\n",
+                "a".repeat(40),
+                ZERO_SHA1
+            )
+        );
+
+        // No hook configured
+        hooks(None, Some("exit 1")).pre_push(&refs).unwrap();
+    }
+
+    #[test]
+    fn test_rejecting_hooks_report_their_stderr() {
+        let refs = [pushed(&"a".repeat(40), &"b".repeat(40))];
+        let err = hooks(Some("echo 'unsigned commit' >&2; exit 3"), None)
+            .pre_push(&refs)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("pre-push hook"), "{}", err);
+        assert!(err.contains("rejected the push"), "{}", err);
+        assert!(err.contains("unsigned commit"), "{}", err);
+
+        let fetched = [("a".repeat(40), "refs/heads/main".to_string())];
+        let err = hooks(None, Some("grep -q nothing-like-this"))
+            .post_fetch(&fetched)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("post-fetch hook"), "{}", err);
+        assert!(err.contains("rejected the fetch"), "{}", err);
+    }
+
+    #[test]
+    fn test_hook_timeout() {
+        let mut slow = hooks(Some("sleep 5"), None);
+        slow.timeout = Duration::from_millis(100);
+
+        let err = format!("{:#}", slow.pre_push(&[]).unwrap_err());
+        assert!(err.contains("did not complete"), "{}", err);
+        assert!(err.contains("timed out"), "{}", err);
+        assert!(err.contains(NO_HOOKS_ENV), "{}", err);
+    }
+}
@@ -1,22 +1,23 @@
-use std::{
-    io::Write,
-    process::{Command, Stdio},
-};
+use std::io::Write;
 
 use anyhow::{Context, Result};
 use tempfile::TempDir;
 
+use super::namespace::RefNamespace;
 use crate::{
     pack::objects::{write_loose_object, GitObject},
+    protocol::ProtocolWriter,
     storage::StorageBackend,
+    subprocess::CommandRunner,
 };
 
 /// Handle the import command (fetch)
 /// Reconstructs Git repo from pack objects and uses git fast-export
 pub fn handle<S: StorageBackend, W: Write>(
     storage: &S,
-    output: &mut W,
+    output: &mut ProtocolWriter<W>,
     refs: &[String],
+    namespace: &RefNamespace,
 ) -> Result<()> {
     tracing::info!("Import requested for refs: {:?}", refs);
 
@@ -30,11 +31,15 @@ pub fn handle<S: StorageBackend, W: Write>(
 
     // Write all objects as loose objects to temp repo
     let objects_dir = git_dir.join("objects");
-    for (obj_id, content_id) in &state.objects {
-        let content = storage
-            .read_object(content_id)
-            .with_context(|| format!("Failed to read object {} from storage", obj_id))?;
-
+    let objects: Vec<(&str, &str)> = state
+        .objects
+        .iter()
+        .map(|(obj_id, content_id)| (obj_id.as_str(), content_id.as_str()))
+        .collect();
+    let contents = storage
+        .read_git_objects(&objects)
+        .context("Failed to read objects from storage")?;
+    for ((obj_id, _), content) in objects.iter().zip(contents) {
         let obj = GitObject::from_loose_format(&content)
             .with_context(|| format!("Failed to parse object {}", obj_id))?;
 
@@ -42,45 +47,35 @@ pub fn handle<S: StorageBackend, W: Write>(
             .with_context(|| format!("Failed to write loose object {}", obj_id))?;
     }
 
-    // Update refs in temp repo
-    for (ref_name, commit_id) in &state.refs {
-        if refs.contains(ref_name) {
-            let ref_path = git_dir.join(ref_name);
-            std::fs::create_dir_all(ref_path.parent().unwrap())?;
-            std::fs::write(&ref_path, format!("{}\n", commit_id))?;
-            tracing::debug!("Created ref {} -> {}", ref_name, commit_id);
-        }
-    }
+    // Create the requested refs in the temp repo in one transaction; update-ref (unlike raw
+    // file writes) checks names and D/F conflicts and works with any ref storage
+    let updates: String = namespace
+        .local_refs(&state.refs)
+        .filter(|(ref_name, _)| refs.iter().any(|r| r == ref_name))
+        .map(|(ref_name, commit_id)| {
+            tracing::debug!("Creating ref {} -> {}", ref_name, commit_id);
+            format!("create {} {}\n", ref_name, commit_id)
+        })
+        .collect();
+    CommandRunner::git_scratch(&git_dir)
+        .arg("update-ref")
+        .arg("--stdin")
+        .stdin(updates)
+        .run()
+        .context("Failed to create refs in temporary repository")?;
 
     // Use git fast-export to generate stream
-    let fast_export = Command::new("git")
-        .arg("--git-dir")
-        .arg(&git_dir)
+    let export_output = CommandRunner::git_scratch(&git_dir)
         .arg("fast-export")
         .arg("--all")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .context("Failed to spawn git fast-export")?;
-
-    let export_output = fast_export
-        .wait_with_output()
-        .context("Failed to wait for git fast-export")?;
-
-    if !export_output.status.success() {
-        tracing::error!(
-            "git fast-export stderr: {}",
-            String::from_utf8_lossy(&export_output.stderr)
-        );
-        anyhow::bail!("git fast-export failed");
-    }
+        .run()?;
 
     // Write fast-export stream to output
-    output.write_all(&export_output.stdout)?;
+    output.stream(&export_output.stdout)?;
 
     // Signal completion
-    writeln!(output, "done")?;
-    writeln!(output)?;
+    output.line("done")?;
+    output.end()?;
 
     Ok(())
 }
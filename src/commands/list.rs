@@ -1,34 +1,352 @@
-use std::io::Write;
+use std::{fmt, io::Write, str::FromStr};
 
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
-use crate::storage::StorageBackend;
+use super::namespace::RefNamespace;
+use crate::{
+    config::glob_match,
+    pack::archive::peel_to_commit,
+    protocol::ProtocolWriter,
+    storage::{State, StorageBackend},
+};
+
+/// HEAD advertised by remotes with tags but no branches (and no stored HEAD)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagOnlyHead {
+    /// Detached at the tagged commit with the newest committer time, so clones check it out
+    #[default]
+    Latest,
+    /// A symref to the first tag by name
+    First,
+}
+
+impl FromStr for TagOnlyHead {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "latest" => Ok(TagOnlyHead::Latest),
+            "first" => Ok(TagOnlyHead::First),
+            other => anyhow::bail!(
+                "invalid tag-only HEAD {:?} (expected 'latest' or 'first')",
+                other
+            ),
+        }
+    }
+}
+
+impl fmt::Display for TagOnlyHead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagOnlyHead::Latest => write!(f, "latest"),
+            TagOnlyHead::First => write!(f, "first"),
+        }
+    }
+}
 
 /// Handle the list command
-/// Output all refs with their Git SHA-1 hashes
+/// Output the refs in the session's namespace with their Git SHA-1 hashes
+///
+/// With `patterns`, only matching refs are advertised. Git only fetches advertised refs, so
+/// hidden refs need a URL with `?all_refs=true`.
 pub fn handle<S: StorageBackend, W: Write>(
     storage: &S,
-    output: &mut W,
-    _for_push: bool,
+    output: &mut ProtocolWriter<W>,
+    namespace: &RefNamespace,
+    patterns: &[String],
+    tag_only_head: TagOnlyHead,
+    for_push: bool,
 ) -> Result<()> {
     let state = storage.read_state()?;
+    if let Some(banner) = storage.list_banner(&state) {
+        tracing::info!("{}", banner);
+    }
+    let (refs, hidden): (Vec<(&str, &String)>, Vec<_>) = namespace
+        .local_refs(&state.refs)
+        .partition(|(refname, _)| is_advertised(patterns, refname));
+    if !hidden.is_empty() {
+        tracing::info!(
+            "{} ref(s) not matching advertise_ref_patterns are hidden; \
+             add ?all_refs=true to the remote URL to fetch them",
+            hidden.len()
+        );
+    }
 
     // For the fetch capability, we MUST output actual SHA-1 hashes
     // Git can only fetch objects that were listed with a SHA-1 hash
-    for (refname, git_sha1) in &state.refs {
-        writeln!(output, "{} {}", git_sha1, refname)?;
+    for (refname, git_sha1) in &refs {
+        output.line(format_args!("{} {}", git_sha1, refname))?;
+    }
+
+    // Symrefs are listed as `@<target> <name>`, and only when their target is listed
+    let mut head = None;
+    for (name, target) in namespace.local_symrefs(&state.symrefs) {
+        if !refs.iter().any(|(refname, _)| *refname == target) {
+            continue;
+        }
+        if name == "HEAD" {
+            head = Some(target);
+        } else if is_advertised(patterns, name) {
+            output.line(format_args!("@{} {}", target, name))?;
+        }
     }
 
     // Output default branch pointer (HEAD)
-    // If we have a main branch, point to it, otherwise the first ref
-    if state.refs.contains_key("refs/heads/main") {
-        writeln!(output, "@refs/heads/main HEAD")?;
-    } else if let Some((first_ref, _)) = state.refs.iter().next() {
-        writeln!(output, "@{} HEAD", first_ref)?;
+    // Use the stored HEAD; otherwise point to main if we have it, else the first ref.
+    // Pushes don't check HEAD out, so they skip reading tags for a detached one.
+    if let Some(target) = head {
+        output.line(format_args!("@{} HEAD", target))?;
+    } else if refs
+        .iter()
+        .any(|(refname, _)| *refname == "refs/heads/main")
+    {
+        output.line("@refs/heads/main HEAD")?;
+    } else if let Some(commit) = (tag_only_head == TagOnlyHead::Latest && !for_push)
+        .then(|| latest_tagged_commit(storage, &state, &refs))
+        .flatten()
+    {
+        output.line(format_args!("{} HEAD", commit))?;
+    } else if let Some((first_ref, _)) = refs.first() {
+        output.line(format_args!("@{} HEAD", first_ref))?;
     }
 
     // Empty line signals completion
-    writeln!(output)?;
+    output.end()?;
 
     Ok(())
 }
+
+/// For refs that are all tags, the commit they peel to with the newest committer time
+/// (ties go to the first tag by name)
+///
+/// Unreadable tags are skipped with a warning, so a listing never fails over HEAD.
+fn latest_tagged_commit<S: StorageBackend>(
+    storage: &S,
+    state: &State,
+    refs: &[(&str, &String)],
+) -> Option<String> {
+    if !refs
+        .iter()
+        .all(|(refname, _)| refname.starts_with("refs/tags/"))
+    {
+        return None;
+    }
+    let mut latest: Option<(u64, String)> = None;
+    for (refname, git_sha1) in refs {
+        match peel_to_commit(storage, state, git_sha1) {
+            Ok((Some(commit), time)) => {
+                if latest.as_ref().is_none_or(|(newest, _)| time > *newest) {
+                    latest = Some((time, commit));
+                }
+            }
+            Ok((None, _)) => {}
+            Err(e) => tracing::warn!("Failed to read {} for HEAD: {:#}", refname, e),
+        }
+    }
+    latest.map(|(_, commit)| commit)
+}
+
+/// Whether `refname` matches one of `patterns` (every ref does when there are none)
+pub fn is_advertised(patterns: &[String], refname: &str) -> bool {
+    patterns.is_empty() || patterns.iter().any(|p| glob_match(p, refname))
+}
+
+#[cfg(test)]
+mod tests {
+    use gix_object::Kind;
+
+    use super::*;
+    use crate::storage::{store_object, MemoryStorage, MutableState};
+
+    fn list(storage: &MemoryStorage, namespace: &RefNamespace, patterns: &[String]) -> String {
+        list_with(storage, namespace, patterns, TagOnlyHead::Latest, false)
+    }
+
+    fn list_with(
+        storage: &MemoryStorage,
+        namespace: &RefNamespace,
+        patterns: &[String],
+        tag_only_head: TagOnlyHead,
+        for_push: bool,
+    ) -> String {
+        let mut output = ProtocolWriter::new(Vec::new());
+        handle(
+            storage,
+            &mut output,
+            namespace,
+            patterns,
+            tag_only_head,
+            for_push,
+        )
+        .unwrap();
+        String::from_utf8(output.into_inner()).unwrap()
+    }
+
+    /// An annotated tag of a commit made at `time`
+    fn tagged_commit(storage: &MemoryStorage, name: &str, time: u32) -> (String, String) {
+        let commit = store_object(
+            storage,
+            Kind::Commit,
+            format!(
+                "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+                 author A <a@a> {time} +0000
+committer A <a@a> {time} +0000
+
+{name}
+"
+            ),
+        );
+        let tag = store_object(
+            storage,
+            Kind::Tag,
+            format!(
+                "object {commit}
+type commit
+tag {name}
+                 tagger A <a@a> {time} +0000
+
+{name}
+"
+            ),
+        );
+        (commit, tag)
+    }
+
+    #[test]
+    fn test_lists_stored_symrefs() {
+        let (main, dev) = ("a".repeat(40), "b".repeat(40));
+        let storage = MemoryStorage::new();
+        storage
+            .update_state(|state| {
+                state.refs.insert("refs/heads/main".into(), main.clone());
+                state.refs.insert("refs/heads/dev".into(), dev.clone());
+                state.symrefs.insert("HEAD".into(), "refs/heads/dev".into());
+                state
+                    .symrefs
+                    .insert("refs/heads/staging".into(), "refs/heads/dev".into());
+                // Dangling symrefs are left out
+                state
+                    .symrefs
+                    .insert("refs/heads/old".into(), "refs/heads/gone".into());
+                Ok(())
+            })
+            .unwrap();
+
+        let root = RefNamespace::default();
+        assert_eq!(
+            list(&storage, &root, &[]),
+            format!(
+                "{dev} refs/heads/dev\n{main} refs/heads/main\n\
+                 @refs/heads/dev refs/heads/staging\n@refs/heads/dev HEAD\n\n"
+            )
+        );
+
+        // Without a usable stored HEAD, main is the default
+        storage
+            .update_state(|state| {
+                state
+                    .symrefs
+                    .insert("HEAD".into(), "refs/heads/gone".into());
+                Ok(())
+            })
+            .unwrap();
+        let patterns = vec!["refs/heads/main".to_string()];
+        assert_eq!(
+            list(&storage, &root, &patterns),
+            format!("{main} refs/heads/main\n@refs/heads/main HEAD\n\n")
+        );
+    }
+
+    #[test]
+    fn test_invalid_ref_values_are_not_listed() {
+        let main = "a".repeat(40);
+        let storage = MemoryStorage::new();
+        storage
+            .update_state(|state| {
+                state.refs.insert("refs/heads/main".into(), main.clone());
+                // As an older client or a manual Move call might have stored them
+                state.refs.insert("refs/heads/empty".into(), String::new());
+                state
+                    .refs
+                    .insert("refs/heads/short".into(), "abc123".into());
+                state.refs.insert("refs/heads/big".into(), "f".repeat(4096));
+                state.refs.insert("refs/heads/upper".into(), "A".repeat(40));
+                state
+                    .refs
+                    .insert("refs/heads/two\nlines".into(), "b".repeat(40));
+                Ok(())
+            })
+            .unwrap();
+
+        let root = RefNamespace::default();
+        assert_eq!(
+            list(&storage, &root, &[]),
+            format!("{main} refs/heads/main\n@refs/heads/main HEAD\n\n")
+        );
+        // Reads set them aside rather than dropping them
+        assert_eq!(storage.read_state().unwrap().invalid_refs.len(), 5);
+    }
+
+    #[test]
+    fn test_is_advertised() {
+        assert!(is_advertised(&[], "refs/anything"));
+        let patterns = vec!["refs/heads/*".to_string(), "refs/tags/v*".to_string()];
+        assert!(is_advertised(&patterns, "refs/tags/v1.2"));
+        assert!(!is_advertised(&patterns, "refs/tags/old"));
+    }
+
+    #[test]
+    fn test_tag_only_remote_detaches_head_at_latest_tag() {
+        let storage = MemoryStorage::new();
+        // Tag names and commit times disagree about which is newest
+        let (_, v2) = tagged_commit(&storage, "v2.0", 100);
+        let (newest, v10) = tagged_commit(&storage, "v10.0", 300);
+        let (_, old) = tagged_commit(&storage, "old", 200);
+        storage
+            .update_state(|state| {
+                state.refs.insert("refs/tags/v2.0".into(), v2.clone());
+                state.refs.insert("refs/tags/v10.0".into(), v10.clone());
+                state.refs.insert("refs/tags/old".into(), old.clone());
+                Ok(())
+            })
+            .unwrap();
+        let refs = format!("{old} refs/tags/old\n{v10} refs/tags/v10.0\n{v2} refs/tags/v2.0\n");
+
+        let root = RefNamespace::default();
+        assert_eq!(
+            list(&storage, &root, &[]),
+            format!("{refs}{newest} HEAD\n\n")
+        );
+        // Configured off, and for pushes, HEAD is the first tag as before
+        let first = format!("{refs}@refs/tags/old HEAD\n\n");
+        assert_eq!(
+            list_with(&storage, &root, &[], TagOnlyHead::First, false),
+            first
+        );
+        assert_eq!(
+            list_with(&storage, &root, &[], TagOnlyHead::Latest, true),
+            first
+        );
+
+        // Any branch means the remote isn't tag-only
+        storage
+            .update_state(|state| {
+                state.refs.insert("refs/heads/dev".into(), newest.clone());
+                Ok(())
+            })
+            .unwrap();
+        assert!(list(&storage, &root, &[]).ends_with("@refs/heads/dev HEAD\n\n"));
+    }
+
+    #[test]
+    fn test_tag_only_head_from_str() {
+        assert_eq!(
+            "latest".parse::<TagOnlyHead>().unwrap(),
+            TagOnlyHead::Latest
+        );
+        assert_eq!("first".parse::<TagOnlyHead>().unwrap(), TagOnlyHead::First);
+        assert!("newest".parse::<TagOnlyHead>().is_err());
+    }
+}
@@ -0,0 +1,469 @@
+//! Translation between a client's ref names and the keys stored on a shared RemoteState
+//!
+//! A remote URL with a repo suffix (`walrus::0xabc/myrepo`) stores its refs under
+//! `refs/namespaces/myrepo/`, so several repositories can share one RemoteState. The objects
+//! map is shared between them, which is safe since it is keyed by SHA.
+//!
+//! `GIT_NAMESPACE` nests further inside that, the way git nests namespaces, so a host serving
+//! several repositories through git's namespaces can keep them apart on one remote.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+
+use crate::session_env;
+
+/// Prefix of every namespaced ref key
+const NAMESPACES_PREFIX: &str = "refs/namespaces/";
+
+/// Environment variable naming the git namespace a command operates in
+pub const GIT_NAMESPACE_ENV: &str = "GIT_NAMESPACE";
+
+/// The namespace a helper session reads and writes refs in (the root namespace by default)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefNamespace {
+    /// `refs/namespaces/<name>/`, or None for the root namespace
+    prefix: Option<String>,
+}
+
+impl RefNamespace {
+    /// Namespace for a repo name from the remote URL (None for the root namespace)
+    pub fn new(name: Option<&str>) -> Self {
+        Self {
+            prefix: name.map(|name| format!("{}{}/", NAMESPACES_PREFIX, name)),
+        }
+    }
+
+    /// Namespace for a repo name from the remote URL, nested by `git_namespace` (the value of
+    /// `GIT_NAMESPACE`) as git nests namespaces: `a/b` is stored under
+    /// `refs/namespaces/a/refs/namespaces/b/`
+    pub fn with_git_namespace(name: Option<&str>, git_namespace: Option<&str>) -> Result<Self> {
+        let mut namespace = Self::new(name);
+        for component in git_namespace
+            .unwrap_or_default()
+            .split('/')
+            .filter(|component| !component.is_empty())
+        {
+            let prefix = format!(
+                "{}{}{}/",
+                namespace.prefix.as_deref().unwrap_or_default(),
+                NAMESPACES_PREFIX,
+                component
+            );
+            check_ref_name(&format!("{}HEAD", prefix)).with_context(|| {
+                format!("Invalid {} {:?}", GIT_NAMESPACE_ENV, git_namespace.unwrap())
+            })?;
+            namespace.prefix = Some(prefix);
+        }
+        Ok(namespace)
+    }
+
+    /// Namespace for a repo name from the remote URL, nested by `GIT_NAMESPACE` when set
+    pub fn resolve(name: Option<&str>) -> Result<Self> {
+        let git_namespace = session_env::var(GIT_NAMESPACE_ENV);
+        Self::with_git_namespace(name, git_namespace.as_deref())
+    }
+
+    /// Key under which the client's `refname` is stored on the remote
+    pub fn to_remote(&self, refname: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}{}", prefix, refname),
+            None => refname.to_string(),
+        }
+    }
+
+    /// Client ref name for a stored key, or None if the key belongs to another namespace
+    /// (including one nested in this one)
+    pub fn to_local<'a>(&self, key: &'a str) -> Option<&'a str> {
+        let local = match &self.prefix {
+            Some(prefix) => key.strip_prefix(prefix.as_str())?,
+            None => key,
+        };
+        (!local.starts_with(NAMESPACES_PREFIX)).then_some(local)
+    }
+
+    /// The refs visible in this namespace, by client ref name
+    pub fn local_refs<'a>(
+        &'a self,
+        refs: &'a BTreeMap<String, String>,
+    ) -> impl Iterator<Item = (&'a str, &'a String)> + 'a {
+        refs.iter()
+            .filter_map(|(key, sha)| self.to_local(key).map(|local| (local, sha)))
+    }
+
+    /// The symrefs visible in this namespace, with names and targets as client ref names
+    pub fn local_symrefs<'a>(
+        &'a self,
+        symrefs: &'a BTreeMap<String, String>,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        symrefs
+            .iter()
+            .filter_map(|(key, target)| Some((self.to_local(key)?, self.to_local(target)?)))
+    }
+
+    /// Refuse pushes that would write outside this namespace
+    ///
+    /// Refs can't name another namespace directly, and a push without a namespace is refused
+    /// once the remote hosts namespaced repositories, so a misconfigured URL can't clobber them.
+    ///
+    /// The ref name must also be valid per `git check-ref-format`, and must not differ only in
+    /// case from a stored ref or be a directory or file prefix of one (a D/F conflict): git
+    /// stores refs as files, so neither could be fetched alongside the existing ref.
+    pub fn check_push(&self, refname: &str, refs: &BTreeMap<String, String>) -> Result<()> {
+        check_ref_name(refname)?;
+
+        if refname.starts_with(NAMESPACES_PREFIX) {
+            anyhow::bail!(
+                "refusing to push {}: namespaced refs are managed by the remote URL's repo suffix",
+                refname
+            );
+        }
+
+        let own = self.prefix.as_deref().unwrap_or_default();
+        let mut names: Vec<&str> = refs
+            .keys()
+            .filter_map(|key| key.strip_prefix(own)?.strip_prefix(NAMESPACES_PREFIX))
+            .filter_map(|rest| rest.split_once('/').map(|(name, _)| name))
+            .collect();
+        names.dedup();
+        if let Some(first) = names.first() {
+            if self.prefix.is_none() {
+                anyhow::bail!(
+                    "refusing to push {} without a repo namespace: this remote hosts namespaced \
+                     repositories ({}); add the repo to the URL (e.g. walrus::0x.../{})",
+                    refname,
+                    names.join(", "),
+                    first
+                );
+            }
+            anyhow::bail!(
+                "refusing to push {} outside a {}: this namespace hosts nested namespaces \
+                 ({}); set {}={} to push into one",
+                refname,
+                GIT_NAMESPACE_ENV,
+                names.join(", "),
+                GIT_NAMESPACE_ENV,
+                first
+            );
+        }
+
+        let key = self.to_remote(refname);
+        for existing in refs.keys().filter(|existing| **existing != key) {
+            if existing.eq_ignore_ascii_case(&key) {
+                anyhow::bail!(
+                    "refusing to push {}: it differs only in case from existing ref {}",
+                    refname,
+                    self.to_local(existing).unwrap_or(existing)
+                );
+            }
+            if is_dir_prefix(existing, &key) || is_dir_prefix(&key, existing) {
+                anyhow::bail!(
+                    "refusing to push {}: it conflicts with existing ref {}",
+                    refname,
+                    self.to_local(existing).unwrap_or(existing)
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `refs/a` is a directory of `refs/a/b`
+fn is_dir_prefix(dir: &str, refname: &str) -> bool {
+    refname
+        .strip_prefix(dir)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Validate a full ref name against the rules of `git check-ref-format`
+pub fn check_ref_name(refname: &str) -> Result<()> {
+    let invalid = |reason: &str| anyhow::anyhow!("invalid ref name {:?}: {}", refname, reason);
+
+    if !refname.starts_with("refs/") {
+        return Err(invalid("must start with refs/"));
+    }
+    if refname == "@" || refname.contains("@{") {
+        return Err(invalid("must not contain @{"));
+    }
+    if refname.contains("..") {
+        return Err(invalid("must not contain .."));
+    }
+    if refname.ends_with('/') || refname.ends_with('.') {
+        return Err(invalid("must not end with / or ."));
+    }
+    if let Some(c) = refname.chars().find(|c| {
+        c.is_ascii_control() || matches!(c, ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\')
+    }) {
+        return Err(invalid(&format!("must not contain {:?}", c)));
+    }
+    for component in refname.split('/') {
+        if component.is_empty() {
+            return Err(invalid("must not contain empty components"));
+        }
+        if component.starts_with('.') || component.ends_with(".lock") {
+            return Err(invalid(
+                "components must not start with . or end with .lock",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Validate a ref name as git sent it, which may be any bytes `git check-ref-format` allows
+///
+/// The remote stores ref names as strings (on-chain strings must be UTF-8), so a name that
+/// isn't UTF-8 is refused rather than converted lossily into a different ref.
+pub fn ref_name_from_bytes(refname: &[u8]) -> Result<&str> {
+    let Ok(refname) = std::str::from_utf8(refname) else {
+        anyhow::bail!(
+            "invalid ref name \"{}\": this remote stores only UTF-8 ref names",
+            refname.escape_ascii()
+        );
+    };
+    check_ref_name(refname)?;
+    Ok(refname)
+}
+
+/// Validate a symref name: `HEAD` or a full ref name
+pub fn check_symref_name(name: &str) -> Result<()> {
+    if name == "HEAD" {
+        return Ok(());
+    }
+    check_ref_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refs(keys: &[&str]) -> BTreeMap<String, String> {
+        keys.iter()
+            .map(|key| (key.to_string(), "a".repeat(40)))
+            .collect()
+    }
+
+    #[test]
+    fn test_translation() {
+        let ns = RefNamespace::new(Some("myrepo"));
+        assert_eq!(
+            ns.to_remote("refs/heads/main"),
+            "refs/namespaces/myrepo/refs/heads/main"
+        );
+        assert_eq!(
+            ns.to_local("refs/namespaces/myrepo/refs/heads/main"),
+            Some("refs/heads/main")
+        );
+        assert_eq!(ns.to_local("refs/namespaces/other/refs/heads/main"), None);
+        assert_eq!(ns.to_local("refs/heads/main"), None);
+
+        let root = RefNamespace::default();
+        assert_eq!(root.to_remote("refs/heads/main"), "refs/heads/main");
+        assert_eq!(root.to_local("refs/heads/main"), Some("refs/heads/main"));
+        assert_eq!(
+            root.to_local("refs/namespaces/myrepo/refs/heads/main"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_git_namespace_nests() {
+        let ns = RefNamespace::with_git_namespace(None, Some("a/b")).unwrap();
+        assert_eq!(
+            ns.to_remote("refs/heads/main"),
+            "refs/namespaces/a/refs/namespaces/b/refs/heads/main"
+        );
+        let ns = RefNamespace::with_git_namespace(Some("repo"), Some("/team/")).unwrap();
+        assert_eq!(
+            ns.to_remote("HEAD"),
+            "refs/namespaces/repo/refs/namespaces/team/HEAD"
+        );
+        assert_eq!(
+            RefNamespace::with_git_namespace(Some("repo"), Some("")).unwrap(),
+            RefNamespace::new(Some("repo"))
+        );
+        assert!(RefNamespace::with_git_namespace(None, Some("bad name")).is_err());
+        assert!(RefNamespace::with_git_namespace(None, Some("x..y")).is_err());
+
+        // The outer namespace doesn't see the nested one's refs, and won't push beside them
+        let refs = refs(&["refs/namespaces/repo/refs/namespaces/team/refs/heads/main"]);
+        let outer = RefNamespace::new(Some("repo"));
+        assert_eq!(outer.local_refs(&refs).count(), 0);
+        let err = outer
+            .check_push("refs/heads/main", &refs)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("set GIT_NAMESPACE=team"), "{}", err);
+        RefNamespace::with_git_namespace(Some("repo"), Some("team"))
+            .unwrap()
+            .check_push("refs/heads/main", &refs)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_local_refs_do_not_leak() {
+        let refs = refs(&[
+            "refs/heads/root",
+            "refs/namespaces/a/refs/heads/main",
+            "refs/namespaces/b/refs/heads/main",
+            "refs/namespaces/b/refs/tags/v1",
+        ]);
+
+        let b = RefNamespace::new(Some("b"));
+        let names: Vec<&str> = b.local_refs(&refs).map(|(name, _)| name).collect();
+        assert_eq!(names, ["refs/heads/main", "refs/tags/v1"]);
+
+        let root = RefNamespace::default();
+        let names: Vec<&str> = root.local_refs(&refs).map(|(name, _)| name).collect();
+        assert_eq!(names, ["refs/heads/root"]);
+    }
+
+    #[test]
+    fn test_local_symrefs() {
+        let symrefs: BTreeMap<String, String> = [
+            ("HEAD", "refs/heads/main"),
+            ("refs/namespaces/a/HEAD", "refs/namespaces/a/refs/heads/dev"),
+            (
+                "refs/namespaces/a/refs/heads/prod",
+                "refs/namespaces/a/refs/heads/release",
+            ),
+            // A target in another namespace is never exposed
+            ("refs/namespaces/a/refs/heads/sneaky", "refs/heads/main"),
+        ]
+        .into_iter()
+        .map(|(name, target)| (name.to_string(), target.to_string()))
+        .collect();
+
+        let a = RefNamespace::new(Some("a"));
+        let a: Vec<_> = a.local_symrefs(&symrefs).collect();
+        assert_eq!(
+            a,
+            [
+                ("HEAD", "refs/heads/dev"),
+                ("refs/heads/prod", "refs/heads/release")
+            ]
+        );
+        let root = RefNamespace::default();
+        let root: Vec<_> = root.local_symrefs(&symrefs).collect();
+        assert_eq!(root, [("HEAD", "refs/heads/main")]);
+
+        check_symref_name("HEAD").unwrap();
+        check_symref_name("refs/heads/prod").unwrap();
+        assert!(check_symref_name("ORIG_HEAD").is_err());
+    }
+
+    #[test]
+    fn test_check_push() {
+        let shared = refs(&["refs/namespaces/a/refs/heads/main"]);
+
+        // Namespaced pushes are fine; root pushes to a namespaced remote are refused
+        RefNamespace::new(Some("b"))
+            .check_push("refs/heads/main", &shared)
+            .unwrap();
+        let err = RefNamespace::default()
+            .check_push("refs/heads/main", &shared)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("hosts namespaced repositories (a)"), "{}", err);
+
+        // Nobody may name a namespace directly
+        assert!(RefNamespace::new(Some("b"))
+            .check_push("refs/namespaces/a/refs/heads/main", &shared)
+            .is_err());
+
+        // Root pushes to an ordinary remote are unaffected
+        RefNamespace::default()
+            .check_push("refs/heads/main", &refs(&["refs/heads/main"]))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_check_ref_name() {
+        for name in [
+            "refs/heads/main",
+            "refs/heads/feature/x-1",
+            "refs/tags/v1.0",
+        ] {
+            check_ref_name(name).unwrap();
+        }
+        for name in [
+            "HEAD",
+            "refs/heads/",
+            "refs/heads//x",
+            "refs/heads/a..b",
+            "refs/heads/.hidden",
+            "refs/heads/x.lock",
+            "refs/heads/x.",
+            "refs/heads/a b",
+            "refs/heads/a~1",
+            "refs/heads/a^",
+            "refs/heads/a:b",
+            "refs/heads/a*",
+            "refs/heads/a?",
+            "refs/heads/a[",
+            "refs/heads/a\\b",
+            "refs/heads/a@{1}",
+            "refs/heads/a\x7f",
+        ] {
+            assert!(check_ref_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_ref_name_from_bytes() {
+        // Git allows any bytes past ASCII in ref names
+        assert_eq!(
+            ref_name_from_bytes("refs/heads/café".as_bytes()).unwrap(),
+            "refs/heads/café"
+        );
+        let err = ref_name_from_bytes(b"refs/heads/caf\xe9")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("refs/heads/caf\\xe9"), "{}", err);
+        assert!(ref_name_from_bytes(b"refs/heads/a\x01").is_err());
+    }
+
+    #[test]
+    fn test_check_push_rejects_case_variants() {
+        let existing = refs(&["refs/heads/main"]);
+        let err = RefNamespace::default()
+            .check_push("refs/heads/Main", &existing)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("differs only in case from existing ref refs/heads/main"),
+            "{}",
+            err
+        );
+
+        // Updating the same ref is fine, and so is the same name in another namespace
+        RefNamespace::default()
+            .check_push("refs/heads/main", &existing)
+            .unwrap();
+        let namespaced = refs(&["refs/namespaces/a/refs/heads/main"]);
+        RefNamespace::new(Some("b"))
+            .check_push("refs/heads/Main", &namespaced)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_check_push_rejects_directory_file_conflicts() {
+        let existing = refs(&["refs/heads/feature"]);
+        let root = RefNamespace::default();
+        assert!(root
+            .check_push("refs/heads/feature/x", &existing)
+            .unwrap_err()
+            .to_string()
+            .contains("conflicts with existing ref refs/heads/feature"));
+        // A shared prefix that isn't a whole component is no conflict
+        root.check_push("refs/heads/feature-x", &existing).unwrap();
+
+        let existing = refs(&["refs/namespaces/a/refs/heads/feature/x"]);
+        let ns = RefNamespace::new(Some("a"));
+        assert!(ns
+            .check_push("refs/heads/feature", &existing)
+            .unwrap_err()
+            .to_string()
+            .contains("conflicts with existing ref refs/heads/feature/x"));
+    }
+}
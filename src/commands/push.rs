@@ -1,78 +1,162 @@
 //! Handle push command (replaces export)
 
-use std::io::{BufRead, Write};
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+};
 
 use anyhow::{Context, Result};
 
-use crate::{pack::receive_pack, storage::StorageBackend};
+use super::{
+    hooks::{PushedRef, ZERO_SHA1},
+    namespace::{ref_name_from_bytes, RefNamespace},
+    push_cert,
+};
+use crate::{
+    pack::receive_pack_with_epochs,
+    protocol::{ProtocolReader, ProtocolWriter, PushRequest, SessionOptions},
+    storage::{metadata, State, StorageBackend},
+    subprocess::CommandRunner,
+    sui::{projected_ref_count, RefChange},
+};
 
 /// Handle push command - receive packfile and update refs
+///
+/// Handles one batch of push lines and its pack; git may send further batches in the same
+/// session, which the protocol loop dispatches here again.
 pub fn handle<S: StorageBackend, W: Write, R: BufRead>(
     storage: &S,
-    output: &mut W,
-    lines: &mut std::io::Lines<R>,
+    output: &mut ProtocolWriter<W>,
+    first_line: &[u8],
+    lines: &mut ProtocolReader<R>,
+    options: &SessionOptions,
+    request: &PushRequest,
 ) -> Result<()> {
-    // The push command line already contains the first push spec
-    // Format: "push <src>:<dst>" was already parsed in protocol.rs
-    // We need to read the actual push specs from the command itself
-
-    // Git sends push specs on separate lines after "push" command
-    // Read push commands until empty line
+    let namespace = &options.namespace;
+    let hooks = &options.hooks;
+    let signing = options.signing(request.cert_mode);
+    // The batch is the command line the protocol loop already read ("push <src>:<dst>"),
+    // followed by further push lines until an empty line. Lines are kept as bytes, since ref
+    // names need not be UTF-8; those that aren't are refused one by one.
     let mut ref_updates = Vec::new();
+    let mut refused: Vec<(Vec<u8>, anyhow::Error)> = Vec::new();
+    let mut line = first_line.to_vec();
 
-    #[allow(clippy::while_let_on_iterator)]
-    while let Some(line) = lines.next() {
-        let line = line?;
-        let line_trimmed = line.trim();
+    loop {
+        let line_trimmed = line.trim_ascii();
 
-        tracing::debug!("Push line: '{}'", line_trimmed);
+        tracing::debug!("Push line: '{}'", line_trimmed.escape_ascii());
 
         if line_trimmed.is_empty() {
             break;
         }
 
         // Parse push command: "push <src>:<dst>"
-        if let Some(push_spec) = line_trimmed.strip_prefix("push ") {
-            let parts: Vec<&str> = push_spec.split(':').collect();
-            if parts.len() == 2 {
-                let src = parts[0].to_string();
-                let dst = parts[1].to_string();
-                tracing::debug!("Parsed ref update: {} -> {}", src, dst);
-                ref_updates.push((src, dst));
-            }
-        } else {
-            // Line doesn't start with "push ", might be the refspec directly
-            let parts: Vec<&str> = line_trimmed.split(':').collect();
-            if parts.len() == 2 {
-                let src = parts[0].to_string();
-                let dst = parts[1].to_string();
-                tracing::debug!("Parsed ref update (no prefix): {} -> {}", src, dst);
-                ref_updates.push((src, dst));
+        // (a line without "push " might be the refspec directly)
+        let push_spec = line_trimmed.strip_prefix(b"push ").unwrap_or(line_trimmed);
+        let parts: Vec<&[u8]> = push_spec.split(|b| *b == b':').collect();
+        if let [src, dst] = parts[..] {
+            match (std::str::from_utf8(src), ref_name_from_bytes(dst)) {
+                (Ok(src), Ok(dst)) => {
+                    tracing::debug!("Parsed ref update: {} -> {}", src, dst);
+                    ref_updates.push((src.to_string(), dst.to_string()));
+                }
+                (_, Err(e)) => refused.push((dst.to_vec(), e)),
+                (Err(_), Ok(_)) => refused.push((
+                    dst.to_vec(),
+                    anyhow::anyhow!("source ref {} is not valid UTF-8", src.escape_ascii()),
+                )),
             }
         }
+
+        line = match lines.next_bytes() {
+            Some(next) => next?,
+            None => break,
+        };
+    }
+
+    if request.atomic && !refused.is_empty() {
+        refused.extend(
+            ref_updates
+                .drain(..)
+                .map(|(_, dst)| (dst.into_bytes(), anyhow::anyhow!("atomic push failed"))),
+        );
     }
 
     if ref_updates.is_empty() {
         tracing::info!("No refs to push");
-        writeln!(output)?;
+        if !refused.is_empty() {
+            // The pack sent for the refused refs is read and dropped
+            lines.pack().context("Failed to read pack")?;
+        }
+        for (refname, e) in &refused {
+            output.ref_error(refname, e)?;
+        }
+        output.end()?;
         return Ok(());
     }
 
-    // Receive packfile from stdin
+    let state = storage.read_state()?;
+    let mut refs = state.refs.clone();
+    for (_, dst) in &ref_updates {
+        namespace.check_push(dst, &refs)?;
+        // Later refs in this push must not conflict with earlier ones either
+        refs.insert(namespace.to_remote(dst), String::new());
+    }
+
+    let pushed = if hooks.has_pre_push() || signing.policy.wants_certificate(signing.mode)? {
+        pushed_refs(&ref_updates, &state, namespace)?
+    } else {
+        Vec::new()
+    };
+    hooks.pre_push(&pushed)?;
+
+    // The ref limit is checked before anything is received; objects once the pack is stored
+    let policy = storage.policy()?;
+    let names: Vec<String> = ref_updates
+        .iter()
+        .map(|(_, dst)| namespace.to_remote(dst))
+        .collect();
+    let changes: Vec<RefChange> = names.iter().map(|name| RefChange::Upsert(name)).collect();
+    let refs_after = projected_ref_count(state.refs.keys(), &changes);
+    policy.check(refs_after, None)?;
+    let certificate = signing.certify(&state, &pushed)?;
+
+    // Receive the packfile that follows the batch, leaving any later batch unread
     tracing::info!("Receiving packfile...");
-    let mut stdin = std::io::stdin();
-    let object_mappings = receive_pack(&mut stdin, storage).context("Failed to receive pack")?;
+    let pack = lines.pack().context("Failed to read pack")?;
+    let object_mappings = receive_pack_with_epochs(
+        &mut pack.as_slice(),
+        storage,
+        &state.objects,
+        None,
+        options.decompress_threads,
+    )
+    .context("Failed to receive pack")?;
 
     tracing::info!("Stored {} objects", object_mappings.len());
 
+    let new_objects = object_mappings
+        .iter()
+        .filter(|(obj_id, _)| !state.objects.contains_key(obj_id))
+        .count();
+    policy.check(refs_after, Some(state.objects.len() + new_objects))?;
+
     // Update state with new objects and refs
     storage.update_state(|state| {
-        // Add object mappings
-        for (obj_id, content_id) in &object_mappings {
-            state.objects.insert(obj_id.clone(), content_id.clone());
+        if let Some(certificate) = &certificate {
+            push_cert::check_unchanged(certificate, state, &pushed)?;
         }
 
+        // Add object mappings
+        state.insert_objects(
+            &object_mappings,
+            options.on_mapping_conflict,
+            |git_sha1, id| storage.read_git_objects(&[(git_sha1, id)]).is_ok(),
+        )?;
+
         // Update refs
+        let mut set = BTreeMap::new();
         for (_src, dst) in &ref_updates {
             // src is the local ref (e.g., "refs/heads/main")
             // dst is the remote ref (e.g., "refs/heads/main")
@@ -81,21 +165,65 @@ pub fn handle<S: StorageBackend, W: Write, R: BufRead>(
             // For now, find the commit SHA from the pushed objects
             // In a real implementation, Git sends the old/new SHAs
             if let Some((obj_id, _)) = object_mappings.first() {
-                state.refs.insert(dst.clone(), obj_id.clone());
+                state.refs.insert(namespace.to_remote(dst), obj_id.clone());
+                set.insert(namespace.to_remote(dst), obj_id.clone());
                 tracing::debug!("Updated ref {} to {}", dst, obj_id);
             }
         }
 
-        Ok(())
+        // Refresh last-push bookkeeping
+        metadata::record_push(
+            storage,
+            state,
+            &set,
+            certificate.clone(),
+            request.push_options.as_slice(),
+        )
     })?;
 
     // Report success for each ref
     for (_, dst) in &ref_updates {
-        writeln!(output, "ok {}", dst)?;
+        output.line(format_args!("ok {}", dst))?;
+    }
+    for (refname, e) in &refused {
+        output.ref_error(refname, e)?;
     }
 
-    writeln!(output)?; // Empty line signals completion
+    output.end()?; // Empty line signals completion
     tracing::info!("Push completed");
 
     Ok(())
 }
+
+/// Pushed refs as hooks and push certificates see them: `<src>` resolved in the local
+/// repository and `<dst>`'s current tip on the remote
+fn pushed_refs(
+    ref_updates: &[(String, String)],
+    state: &State,
+    namespace: &RefNamespace,
+) -> Result<Vec<PushedRef>> {
+    let mut pushed = Vec::new();
+    for (src, dst) in ref_updates {
+        let local_ref = src.trim_start_matches('+');
+        let sha_output = CommandRunner::git()
+            .arg("rev-parse")
+            .arg(local_ref)
+            .output()?;
+        pushed.push(PushedRef {
+            local_ref: local_ref.to_string(),
+            local_sha1: if sha_output.status.success() {
+                String::from_utf8_lossy(&sha_output.stdout)
+                    .trim()
+                    .to_string()
+            } else {
+                ZERO_SHA1.to_string()
+            },
+            remote_ref: dst.clone(),
+            remote_sha1: state
+                .refs
+                .get(&namespace.to_remote(dst))
+                .map_or(ZERO_SHA1.to_string(), String::clone),
+        });
+    }
+    Ok(pushed)
+}
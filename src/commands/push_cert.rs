@@ -0,0 +1,545 @@
+//! Signed pushes (`git push --signed`)
+//!
+//! Git only builds push certificates inside `git send-pack`; a remote helper just gets
+//! `option pushcert`. The helper therefore writes the certificate in git's format, signs it with
+//! the pusher's OpenPGP key the way send-pack would, and verifies it against the remote's trust
+//! policy before any ref moves.
+
+use std::{fmt, io::Write, path::PathBuf};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use super::hooks::PushedRef;
+use crate::{
+    config::WalrusRemoteConfig,
+    session_env,
+    storage::{PushCertificate, State},
+    subprocess::CommandRunner,
+};
+
+const SIGNATURE_START: &str = "-----BEGIN PGP SIGNATURE-----";
+
+/// Whether git asked for a signed push, from `option pushcert`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PushCertMode {
+    #[default]
+    Off,
+    /// `--signed=if-asked`: sign only if the remote requires it
+    IfAsked,
+    /// `--signed`
+    Always,
+}
+
+impl PushCertMode {
+    /// Parse the value of `option pushcert`
+    pub fn from_option(value: &str) -> Option<Self> {
+        match value {
+            "false" => Some(Self::Off),
+            "if-asked" => Some(Self::IfAsked),
+            "true" => Some(Self::Always),
+            _ => None,
+        }
+    }
+}
+
+/// Who may push to a remote, and whether pushes must be signed
+#[derive(Debug, Clone, Default)]
+pub struct PushCertPolicy {
+    /// Refuse pushes without a verified certificate
+    pub required: bool,
+    /// GnuPG home holding the keys certificates are verified against (the user's by default)
+    pub keyring: Option<PathBuf>,
+    /// Fingerprints (or long key IDs) allowed to sign pushes; empty allows any key in the keyring
+    pub signers: Vec<String>,
+}
+
+impl PushCertPolicy {
+    /// The policy from `config` (filesystem remotes have none), overridden by
+    /// `WALRUS_REMOTE_REQUIRE_SIGNED_PUSH`, `WALRUS_REMOTE_PUSH_CERT_KEYRING` and
+    /// `WALRUS_REMOTE_PUSH_CERT_SIGNERS`
+    pub fn resolve(config: Option<&WalrusRemoteConfig>) -> Result<Self> {
+        let mut policy = Self {
+            required: config.is_some_and(|c| c.require_signed_push),
+            keyring: config.and_then(|c| c.push_cert_keyring.clone()),
+            signers: config
+                .map(|c| c.push_cert_signers.clone())
+                .unwrap_or_default(),
+        };
+        if let Some(value) = session_env::var("WALRUS_REMOTE_REQUIRE_SIGNED_PUSH") {
+            policy.required = match value.trim() {
+                "1" | "true" | "yes" | "on" => true,
+                "0" | "false" | "no" | "off" | "" => false,
+                other => anyhow::bail!(
+                    "Invalid WALRUS_REMOTE_REQUIRE_SIGNED_PUSH {:?} (expected 1 or 0)",
+                    other
+                ),
+            };
+        }
+        if let Some(keyring) = session_env::var("WALRUS_REMOTE_PUSH_CERT_KEYRING") {
+            policy.keyring = Some(PathBuf::from(keyring)).filter(|k| !k.as_os_str().is_empty());
+        }
+        if let Some(signers) = session_env::var("WALRUS_REMOTE_PUSH_CERT_SIGNERS") {
+            policy.signers = signers
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        Ok(policy)
+    }
+
+    /// Whether a push in `mode` gets a certificate, failing if one is required but git
+    /// wasn't asked to sign
+    pub fn wants_certificate(&self, mode: PushCertMode) -> Result<bool> {
+        match mode {
+            PushCertMode::Always => Ok(true),
+            PushCertMode::IfAsked => Ok(self.required),
+            PushCertMode::Off if self.required => anyhow::bail!(
+                "this remote only accepts signed pushes; push again with `git push --signed`"
+            ),
+            PushCertMode::Off => Ok(false),
+        }
+    }
+
+    /// Whether `fingerprint` may sign pushes
+    fn allows(&self, fingerprint: &str) -> bool {
+        self.signers.is_empty()
+            || self.signers.iter().any(|signer| {
+                let signer = signer.replace(' ', "").to_uppercase();
+                !signer.is_empty() && fingerprint.to_uppercase().ends_with(&signer)
+            })
+    }
+}
+
+/// Nonce binding a certificate to the refs a push starts from, so it can't be replayed
+/// against a remote that has moved on
+pub fn state_nonce(state: &State) -> String {
+    let mut hasher = Sha256::new();
+    for (ref_name, git_sha1) in &state.refs {
+        hasher.update(format!("{} {}\n", ref_name, git_sha1));
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// A signed push certificate: git's certificate text followed by an armored signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushCert {
+    payload: String,
+    signature: String,
+}
+
+/// Key that made a good signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signer {
+    pub fingerprint: String,
+    pub uid: String,
+}
+
+impl fmt::Display for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.uid, self.fingerprint)
+    }
+}
+
+impl fmt::Display for PushCert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.payload, self.signature)
+    }
+}
+
+impl PushCert {
+    /// Certificate text in `git send-pack`'s format
+    pub fn payload(pusher: &str, pushee: &str, nonce: &str, updates: &[PushedRef]) -> String {
+        let mut payload = format!(
+            "certificate version 0.1\npusher {}\npushee {}\nnonce {}\n\n",
+            pusher, pushee, nonce
+        );
+        for update in updates {
+            payload.push_str(&format!(
+                "{} {} {}\n",
+                update.remote_sha1, update.local_sha1, update.remote_ref
+            ));
+        }
+        payload
+    }
+
+    /// Sign `payload` with the key git signs with (`user.signingkey`, else the committer)
+    pub fn sign(payload: String) -> Result<Self> {
+        let program = openpgp_program()?;
+        let key = match git_config("user.signingkey")? {
+            Some(key) => key,
+            None => committer_ident()?,
+        };
+
+        let output = CommandRunner::new(&program)
+            .args(["--status-fd=2", "-bsau", &key])
+            .stdin(payload.clone())
+            .run()
+            .with_context(|| format!("Failed to sign the push certificate with key {:?}", key))?;
+        let signature = String::from_utf8(output.stdout)
+            .context("Push certificate signature is not valid UTF-8")?;
+        if !signature.starts_with(SIGNATURE_START) {
+            anyhow::bail!("{} did not produce an armored signature", program);
+        }
+        Ok(Self { payload, signature })
+    }
+
+    /// Split a signed certificate into its text and signature
+    pub fn parse(text: &str) -> Result<Self> {
+        let start = text
+            .find(SIGNATURE_START)
+            .context("Push certificate has no signature")?;
+        Ok(Self {
+            payload: text[..start].to_string(),
+            signature: text[start..].to_string(),
+        })
+    }
+
+    /// Check the signature against `policy`'s keyring and allowed signers
+    pub fn verify(&self, policy: &PushCertPolicy) -> Result<Signer> {
+        let mut signature_file =
+            tempfile::NamedTempFile::new().context("Failed to create signature file")?;
+        signature_file
+            .write_all(self.signature.as_bytes())
+            .context("Failed to write signature file")?;
+
+        let program = openpgp_program()?;
+        let mut gpg = CommandRunner::new(&program)
+            .args(["--status-fd=1", "--verify"])
+            .arg(signature_file.path())
+            .arg("-")
+            .stdin(self.payload.clone());
+        if let Some(keyring) = &policy.keyring {
+            gpg = gpg.env("GNUPGHOME", keyring);
+        }
+        let output = gpg
+            .output()
+            .with_context(|| format!("Failed to run {} to verify push certificate", program))?;
+
+        let status = String::from_utf8_lossy(&output.stdout);
+        let mut good_uid = None;
+        let mut fingerprint = None;
+        for line in status.lines() {
+            let Some(line) = line.strip_prefix("[GNUPG:] ") else {
+                continue;
+            };
+            let mut fields = line.splitn(3, ' ');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some("GOODSIG"), Some(_), uid) => good_uid = Some(uid.unwrap_or("").to_string()),
+                (Some("VALIDSIG"), Some(fpr), _) => fingerprint = Some(fpr.to_string()),
+                (Some("BADSIG"), ..) => {
+                    anyhow::bail!("push certificate signature is bad; was the certificate altered?")
+                }
+                (Some("ERRSIG"), Some(key_id), _) => anyhow::bail!(
+                    "push certificate is signed by key {}, which is not in the keyring",
+                    key_id
+                ),
+                (Some("EXPKEYSIG" | "REVKEYSIG" | "EXPSIG"), Some(key_id), _) => anyhow::bail!(
+                    "push certificate is signed by expired or revoked key {}",
+                    key_id
+                ),
+                _ => {}
+            }
+        }
+        let (Some(uid), Some(fingerprint)) = (good_uid, fingerprint) else {
+            anyhow::bail!(
+                "could not verify push certificate signature: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        };
+        if !policy.allows(&fingerprint) {
+            anyhow::bail!(
+                "push certificate is signed by {} ({}), which may not push to this remote",
+                uid,
+                fingerprint
+            );
+        }
+        Ok(Signer { fingerprint, uid })
+    }
+
+    /// Check the certificate covers exactly `updates`, starting from the refs in `state`
+    pub fn check(&self, state: &State, updates: &[PushedRef]) -> Result<()> {
+        let (header, refs) = self
+            .payload
+            .split_once("\n\n")
+            .context("Malformed push certificate")?;
+        if header.lines().next() != Some("certificate version 0.1") {
+            anyhow::bail!("Unsupported push certificate version");
+        }
+        let nonce = header.lines().find_map(|line| line.strip_prefix("nonce "));
+        if nonce != Some(state_nonce(state).as_str()) {
+            anyhow::bail!(
+                "push certificate was signed for a different state of this remote; \
+                 fetch and push again"
+            );
+        }
+        let expected: Vec<String> = updates
+            .iter()
+            .map(|u| format!("{} {} {}", u.remote_sha1, u.local_sha1, u.remote_ref))
+            .collect();
+        if refs.lines().collect::<Vec<_>>() != expected {
+            anyhow::bail!("push certificate does not match the refs being pushed");
+        }
+        Ok(())
+    }
+}
+
+/// A session's signed push settings: what git asked for and what the remote requires
+#[derive(Debug, Clone, Copy)]
+pub struct PushSigning<'a> {
+    pub mode: PushCertMode,
+    pub policy: &'a PushCertPolicy,
+    /// Remote URL, recorded in the certificate
+    pub pushee: &'a str,
+}
+
+impl PushSigning<'_> {
+    /// Sign, verify and check a certificate for `updates` starting from `state`, if the
+    /// push asked for one or the remote requires it
+    pub fn certify(&self, state: &State, updates: &[PushedRef]) -> Result<Option<PushCertificate>> {
+        if !self.policy.wants_certificate(self.mode)? {
+            return Ok(None);
+        }
+        let payload = PushCert::payload(
+            &committer_ident_with_date()?,
+            self.pushee,
+            &state_nonce(state),
+            updates,
+        );
+        let cert = PushCert::sign(payload)?;
+        let signer = cert
+            .verify(self.policy)
+            .context("Push certificate was rejected")?;
+        cert.check(state, updates)?;
+        tracing::info!("push signed by {}", signer);
+        Ok(Some(PushCertificate {
+            signer: signer.to_string(),
+            certificate: cert.to_string(),
+        }))
+    }
+}
+
+/// Fail if the remote moved between signing `certificate` and applying the push to `state`
+pub fn check_unchanged(
+    certificate: &PushCertificate,
+    state: &State,
+    updates: &[PushedRef],
+) -> Result<()> {
+    PushCert::parse(&certificate.certificate)?.check(state, updates)
+}
+
+/// `git config <key>`, or None when unset
+/// The OpenPGP program git signs and verifies with (`gpg.openpgp.program`, else `gpg.program`)
+fn openpgp_program() -> Result<String> {
+    let format = git_config("gpg.format")?;
+    if format.as_deref().is_some_and(|f| f != "openpgp") {
+        anyhow::bail!(
+            "signed pushes need an OpenPGP key, but gpg.format is {:?}",
+            format.unwrap_or_default()
+        );
+    }
+    match git_config("gpg.openpgp.program")? {
+        Some(program) => Ok(program),
+        None => Ok(git_config("gpg.program")?.unwrap_or_else(|| "gpg".to_string())),
+    }
+}
+
+fn git_config(key: &str) -> Result<Option<String>> {
+    let output = CommandRunner::git().args(["config", key]).output()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok(Some(value).filter(|v| output.status.success() && !v.is_empty()))
+}
+
+/// `Name <email> <timestamp> <tz>` of the committer
+fn committer_ident_with_date() -> Result<String> {
+    let output = CommandRunner::git()
+        .args(["var", "GIT_COMMITTER_IDENT"])
+        .run()
+        .context("Failed to read committer identity for the push certificate")?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// `Name <email>` of the committer, which git signs with when `user.signingkey` is unset
+fn committer_ident() -> Result<String> {
+    let ident = committer_ident_with_date()?;
+    Ok(match ident.rfind('>') {
+        Some(end) => ident[..=end].to_string(),
+        None => ident,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, process::Command};
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::commands::hooks::ZERO_SHA1;
+
+    fn update(remote_sha1: &str, local_sha1: &str) -> PushedRef {
+        PushedRef {
+            local_ref: "refs/heads/main".to_string(),
+            local_sha1: local_sha1.to_string(),
+            remote_ref: "refs/heads/main".to_string(),
+            remote_sha1: remote_sha1.to_string(),
+        }
+    }
+
+    /// Sign `payload` with a throwaway key in `gnupg_home`, or None without gpg
+    fn sign_with_test_key(gnupg_home: &Path, payload: &str) -> Option<PushCert> {
+        let gpg = |args: &[&str], input: Option<&str>| {
+            CommandRunner::new("gpg")
+                .env("GNUPGHOME", gnupg_home)
+                .args(args)
+                .stdin(input.unwrap_or_default())
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+        };
+        let keygen = [
+            "--batch",
+            "--passphrase",
+            "",
+            "--quick-gen-key",
+            "Pusher <pusher@test.com>",
+            "ed25519",
+            "sign",
+            "never",
+        ];
+        gpg(&keygen, None)?;
+        let signature = gpg(&["-bsau", "pusher@test.com"], Some(payload))?;
+        Some(PushCert {
+            payload: payload.to_string(),
+            signature: String::from_utf8(signature.stdout).unwrap(),
+        })
+    }
+
+    #[test]
+    fn test_push_cert_mode_and_policy() {
+        assert_eq!(
+            PushCertMode::from_option("if-asked"),
+            Some(PushCertMode::IfAsked)
+        );
+        assert_eq!(PushCertMode::from_option("maybe"), None);
+
+        let optional = PushCertPolicy::default();
+        assert!(!optional.wants_certificate(PushCertMode::Off).unwrap());
+        assert!(!optional.wants_certificate(PushCertMode::IfAsked).unwrap());
+        assert!(optional.wants_certificate(PushCertMode::Always).unwrap());
+
+        let required = PushCertPolicy {
+            required: true,
+            signers: vec!["89ab cdef 0123 4567".to_string()],
+            ..Default::default()
+        };
+        let err = required
+            .wants_certificate(PushCertMode::Off)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("git push --signed"), "{}", err);
+        assert!(required.wants_certificate(PushCertMode::IfAsked).unwrap());
+
+        // Fingerprints or long key IDs, spaced or not
+        assert!(required.allows("0000000000000000000000000089ABCDEF01234567"));
+        assert!(!required.allows("0000000000000000000000000089ABCDEF01234568"));
+        assert!(optional.allows("anything"));
+    }
+
+    #[test]
+    fn test_check_against_state_and_updates() {
+        let mut state = State::default();
+        state
+            .refs
+            .insert("refs/heads/main".to_string(), "a".repeat(40));
+        let updates = [update(&"a".repeat(40), &"b".repeat(40))];
+        let payload = PushCert::payload(
+            "Pusher <pusher@test.com> 1700000000 +0000",
+            "walrus::/tmp/remote",
+            &state_nonce(&state),
+            &updates,
+        );
+        assert!(payload.starts_with("certificate version 0.1\npusher Pusher"));
+        assert!(payload.ends_with(&format!(
+            "\n\n{} {} refs/heads/main\n",
+            "a".repeat(40),
+            "b".repeat(40)
+        )));
+
+        let cert = PushCert::parse(&format!("{}{}\nxyz\n", payload, SIGNATURE_START)).unwrap();
+        cert.check(&state, &updates).unwrap();
+
+        // Other refs than the ones signed for
+        let err = cert
+            .check(&state, &[update(ZERO_SHA1, &"b".repeat(40))])
+            .unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{}", err);
+
+        // Replayed after the remote moved
+        state
+            .refs
+            .insert("refs/heads/main".to_string(), "c".repeat(40));
+        let err = cert.check(&state, &updates).unwrap_err();
+        assert!(err.to_string().contains("different state"), "{}", err);
+
+        assert!(PushCert::parse(&payload).is_err());
+    }
+
+    #[test]
+    fn test_verify_signed_and_tampered_certificates() {
+        let dir = TempDir::new().unwrap();
+        let gnupg_home = dir.path().join("gnupg");
+        std::fs::create_dir(&gnupg_home).unwrap();
+        let payload = PushCert::payload(
+            "Pusher <pusher@test.com> 1700000000 +0000",
+            "walrus::/tmp/remote",
+            &state_nonce(&State::default()),
+            &[update(ZERO_SHA1, &"b".repeat(40))],
+        );
+        // Skipped where gpg is unavailable
+        let Some(cert) = sign_with_test_key(&gnupg_home, &payload) else {
+            return;
+        };
+        let policy = PushCertPolicy {
+            keyring: Some(gnupg_home.clone()),
+            ..Default::default()
+        };
+
+        let signer = cert.verify(&policy).unwrap();
+        assert_eq!(signer.uid, "Pusher <pusher@test.com>");
+        assert_eq!(signer.fingerprint.len(), 40);
+        let round_trip = PushCert::parse(&cert.to_string()).unwrap();
+        assert_eq!(round_trip.verify(&policy).unwrap(), signer);
+
+        // Pointing the ref somewhere else breaks the signature
+        let tampered =
+            PushCert::parse(&cert.to_string().replace(&"b".repeat(40), &"c".repeat(40))).unwrap();
+        let err = tampered.verify(&policy).unwrap_err().to_string();
+        assert!(err.contains("signature is bad"), "{}", err);
+
+        // Keys outside the allowed signers are refused
+        let others_only = PushCertPolicy {
+            signers: vec!["0123456789ABCDEF".to_string()],
+            ..policy.clone()
+        };
+        let err = cert.verify(&others_only).unwrap_err().to_string();
+        assert!(err.contains("may not push"), "{}", err);
+
+        // ...as are keys missing from the keyring
+        let empty_home = dir.path().join("empty");
+        std::fs::create_dir(&empty_home).unwrap();
+        let strangers = PushCertPolicy {
+            keyring: Some(empty_home),
+            ..Default::default()
+        };
+        let err = cert.verify(&strangers).unwrap_err().to_string();
+        assert!(err.contains("not in the keyring"), "{}", err);
+
+        let _ = Command::new("gpgconf")
+            .env("GNUPGHOME", &gnupg_home)
+            .args(["--kill", "gpg-agent"])
+            .output();
+    }
+}
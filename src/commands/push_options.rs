@@ -0,0 +1,71 @@
+//! Push options (`git push --push-option`), recorded with the push for automation to act on
+
+use anyhow::Result;
+
+/// Most push options one push may carry
+pub const MAX_PUSH_OPTIONS: usize = 32;
+
+/// Longest push option accepted, in bytes
+pub const MAX_PUSH_OPTION_BYTES: usize = 1024;
+
+/// Push options git sent with `option push-option`, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PushOptions(Vec<String>);
+
+impl PushOptions {
+    /// Add `value`, refusing empty or over-long options, control characters and more than
+    /// `MAX_PUSH_OPTIONS` options
+    pub fn add(&mut self, value: &str) -> Result<()> {
+        if self.0.len() >= MAX_PUSH_OPTIONS {
+            anyhow::bail!("too many push options (at most {})", MAX_PUSH_OPTIONS);
+        }
+        if value.is_empty() {
+            anyhow::bail!("empty push option");
+        }
+        if value.len() > MAX_PUSH_OPTION_BYTES {
+            anyhow::bail!(
+                "push option is {} bytes, over the {} byte limit",
+                value.len(),
+                MAX_PUSH_OPTION_BYTES
+            );
+        }
+        if value.chars().any(char::is_control) {
+            anyhow::bail!("push option {:?} contains control characters", value);
+        }
+        self.0.push(value.to_string());
+        Ok(())
+    }
+
+    pub fn as_slice(&self) -> &[String] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_options_are_bounded() {
+        let mut options = PushOptions::default();
+        options.add("ci.skip").unwrap();
+        options.add("merge-request.create").unwrap();
+        options.add("notify=team a").unwrap();
+        assert_eq!(
+            options.as_slice(),
+            ["ci.skip", "merge-request.create", "notify=team a"]
+        );
+
+        assert!(options.add("").is_err());
+        assert!(options.add("a\tb").is_err());
+        assert!(options.add(&"x".repeat(MAX_PUSH_OPTION_BYTES + 1)).is_err());
+        options.add(&"x".repeat(MAX_PUSH_OPTION_BYTES)).unwrap();
+
+        while options.as_slice().len() < MAX_PUSH_OPTIONS {
+            options.add("more").unwrap();
+        }
+        let err = options.add("one too many").unwrap_err();
+        assert!(err.to_string().contains("too many"), "{}", err);
+        assert_eq!(options.as_slice().len(), MAX_PUSH_OPTIONS);
+    }
+}
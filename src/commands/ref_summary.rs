@@ -0,0 +1,189 @@
+//! What a push changed on the remote, summarized like git's `old..new` lines
+
+use std::{fmt, io::Write};
+
+use anyhow::Result;
+
+use crate::{pack::objects::GitObject, storage::ImmutableStore};
+
+/// Hex digits shown for a SHA-1 below `-v`
+const ABBREV: usize = 7;
+
+/// How a push changed a ref on the remote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefChangeKind {
+    Created,
+    Updated,
+    Unchanged,
+}
+
+impl fmt::Display for RefChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefChangeKind::Created => write!(f, "created"),
+            RefChangeKind::Updated => write!(f, "updated"),
+            RefChangeKind::Unchanged => write!(f, "unchanged"),
+        }
+    }
+}
+
+/// A pushed ref as the remote accepted it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefChangeRecord {
+    /// Ref name as git pushed it
+    pub refname: String,
+    /// Tip on the remote before the write (None for a new ref)
+    pub old: Option<String>,
+    /// Tip the write stored
+    pub new: String,
+    /// Subject of the new tip's commit or tag message
+    pub subject: Option<String>,
+}
+
+impl RefChangeRecord {
+    pub fn kind(&self) -> RefChangeKind {
+        match &self.old {
+            None => RefChangeKind::Created,
+            Some(old) if *old == self.new => RefChangeKind::Unchanged,
+            Some(_) => RefChangeKind::Updated,
+        }
+    }
+
+    /// Read the subject of the new tip from `store`, where the remote's objects map says it is
+    ///
+    /// Best effort: the summary goes without a subject rather than failing a stored push.
+    pub fn read_subject(&mut self, store: &impl ImmutableStore, content_id: Option<&str>) {
+        self.subject = content_id
+            .and_then(|id| store.read_git_objects(&[(self.new.as_str(), id)]).ok())
+            .and_then(|mut contents| contents.pop())
+            .and_then(|content| GitObject::from_loose_format(&content).ok())
+            .and_then(|object| object.subject());
+    }
+
+    /// One summary line: full SHA-1s from `verbosity` 2 (`-v`) up
+    fn line(&self, verbosity: u32) -> String {
+        let sha = |sha: &str| {
+            if verbosity >= 2 {
+                sha.to_string()
+            } else {
+                sha[..sha.len().min(ABBREV)].to_string()
+            }
+        };
+        let (flag, summary) = match &self.old {
+            None => ('*', format!("[new] {}", sha(&self.new))),
+            Some(old) if *old == self.new => ('=', format!("[up to date] {}", sha(&self.new))),
+            Some(old) => (' ', format!("{}..{}", sha(old), sha(&self.new))),
+        };
+        match &self.subject {
+            Some(subject) => format!(" {} {}  {}  {}", flag, summary, self.refname, subject),
+            None => format!(" {} {}  {}", flag, summary, self.refname),
+        }
+    }
+}
+
+/// Write the summary of `records` to `out`, unless `verbosity` is 0 (`--quiet`)
+///
+/// Each record is also logged at debug level with its fields, for structured log output.
+pub fn report(out: &mut impl Write, records: &[RefChangeRecord], verbosity: u32) -> Result<()> {
+    for record in records {
+        tracing::debug!(
+            refname = %record.refname,
+            kind = %record.kind(),
+            old = record.old.as_deref(),
+            new = %record.new,
+            subject = record.subject.as_deref(),
+            "ref change"
+        );
+    }
+    if verbosity == 0 || records.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "Remote refs after push:")?;
+    for record in records {
+        writeln!(out, "{}", record.line(verbosity))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use gix_object::Kind;
+
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn record(
+        refname: &str,
+        old: Option<&str>,
+        new: &str,
+        subject: Option<&str>,
+    ) -> RefChangeRecord {
+        RefChangeRecord {
+            refname: refname.to_string(),
+            old: old.map(str::to_string),
+            new: new.to_string(),
+            subject: subject.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_summary_by_verbosity() {
+        let (a, b) = ("a".repeat(40), "b".repeat(40));
+        let records = vec![
+            record("refs/heads/main", Some(&a), &b, Some("Fix the build")),
+            record("refs/heads/topic", None, &a, Some("Start topic")),
+            record("refs/tags/v1", Some(&a), &a, None),
+        ];
+        let summary = |verbosity| {
+            let mut out = Vec::new();
+            report(&mut out, &records, verbosity).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(summary(0), "");
+        assert_eq!(
+            summary(1),
+            "Remote refs after push:\n   \
+             aaaaaaa..bbbbbbb  refs/heads/main  Fix the build\n \
+             * [new] aaaaaaa  refs/heads/topic  Start topic\n \
+             = [up to date] aaaaaaa  refs/tags/v1\n"
+        );
+        assert!(summary(2).contains(&format!("   {}..{}  refs/heads/main", a, b)));
+        assert_eq!(
+            records
+                .iter()
+                .map(RefChangeRecord::kind)
+                .collect::<Vec<_>>(),
+            vec![
+                RefChangeKind::Updated,
+                RefChangeKind::Created,
+                RefChangeKind::Unchanged
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subject_is_read_from_the_store() {
+        let storage = MemoryStorage::new();
+        let commit = GitObject::from_raw(
+            Kind::Commit,
+            format!(
+                "tree {}\nauthor A <a@a> 0 +0000\ncommitter A <a@a> 0 +0000\n\nAdd feature\n\nBody\n",
+                "c".repeat(40)
+            )
+            .into_bytes(),
+        )
+        .unwrap();
+        let content_id = storage.write_object(&commit.to_loose_format()).unwrap();
+
+        let mut change = record("refs/heads/main", None, &commit.id, None);
+        change.read_subject(&storage, Some(&content_id));
+        assert_eq!(change.subject.as_deref(), Some("Add feature"));
+
+        // Missing objects leave the summary without a subject
+        change.read_subject(&storage, Some("missing"));
+        assert_eq!(change.subject, None);
+        change.read_subject(&storage, None);
+        assert_eq!(change.subject, None);
+    }
+}
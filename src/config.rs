@@ -1,11 +1,24 @@
+mod git_config;
+
 use std::{
-    env,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
+use self::git_config::GitConfig;
+use crate::{
+    commands::list::TagOnlyHead,
+    pack::PrefetchStrategy,
+    proxy::ProxySettings,
+    session_env,
+    storage::MappingConflict,
+    sui::SuiNetwork,
+    walrus::BlobPersistence,
+};
+
 /// Expand tilde (~) in path to user's home directory
 fn expand_tilde(path: &Path) -> PathBuf {
     if let Some(s) = path.to_str() {
@@ -22,6 +35,125 @@ fn expand_tilde(path: &Path) -> PathBuf {
     path.to_path_buf()
 }
 
+/// Parse a boolean environment flag (1/0, true/false, yes/no)
+fn parse_env_flag(value: &str) -> Result<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" | "" => Ok(false),
+        other => anyhow::bail!("invalid boolean flag: {:?}", other),
+    }
+}
+
+/// `WALRUS_REMOTE_METRICS_FILE`, where fetches append their timings; relative to the
+/// directory git runs the helper in
+pub fn metrics_file_from_env() -> Result<Option<PathBuf>> {
+    session_env::var("WALRUS_REMOTE_METRICS_FILE")
+        .filter(|value| !value.trim().is_empty())
+        .map(|value| Ok(session_env::current_dir()?.join(value.trim())))
+        .transpose()
+}
+
+/// `WALRUS_REMOTE_READ_ONLY`, which also applies to remotes that don't load the config file
+pub fn read_only_from_env() -> Result<Option<bool>> {
+    session_env::var("WALRUS_REMOTE_READ_ONLY")
+        .map(|value| {
+            parse_env_flag(&value).context("Failed to parse WALRUS_REMOTE_READ_ONLY as a boolean")
+        })
+        .transpose()
+}
+
+/// `WALRUS_REMOTE_ADVERTISE_REF_PATTERNS` (comma-separated), which also applies to remotes that
+/// don't load the config file
+pub fn advertise_ref_patterns_from_env() -> Option<Vec<String>> {
+    session_env::var("WALRUS_REMOTE_ADVERTISE_REF_PATTERNS").map(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(String::from)
+            .collect()
+    })
+}
+
+/// `WALRUS_REMOTE_TAG_ONLY_HEAD` (`latest` or `first`), which also applies to remotes that
+/// don't load the config file
+pub fn tag_only_head_from_env() -> Result<Option<TagOnlyHead>> {
+    session_env::var("WALRUS_REMOTE_TAG_ONLY_HEAD")
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .context("Failed to parse WALRUS_REMOTE_TAG_ONLY_HEAD")
+        })
+        .transpose()
+}
+
+/// `WALRUS_REMOTE_ON_MAPPING_CONFLICT`, which also applies to remotes that don't load the
+/// config file
+pub fn on_mapping_conflict_from_env() -> Result<Option<MappingConflict>> {
+    session_env::var("WALRUS_REMOTE_ON_MAPPING_CONFLICT")
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .context("Failed to parse WALRUS_REMOTE_ON_MAPPING_CONFLICT")
+        })
+        .transpose()
+}
+
+/// `WALRUS_REMOTE_DECOMPRESS_THREADS`, which also applies to remotes that don't load the
+/// config file
+pub fn decompress_threads_from_env() -> Result<Option<usize>> {
+    session_env::var("WALRUS_REMOTE_DECOMPRESS_THREADS")
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .context("Failed to parse WALRUS_REMOTE_DECOMPRESS_THREADS as a thread count")
+        })
+        .transpose()
+}
+
+/// Match `name` against `pattern`, where `*` stands for any run of characters (including `/`)
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*`: exact match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Storage lifetime for the objects a push introduces through matching refs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionRule {
+    /// Refs the rule applies to, as a `*` glob
+    pub refs: String,
+    /// Epochs to store newly introduced objects for
+    pub epochs: u32,
+}
+
+/// Epochs chosen for the objects a ref introduces, and the rule that chose them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Retention {
+    pub epochs: u32,
+    /// Glob of the deciding `retention_rules` entry (None when `default_epochs` applies)
+    pub rule: Option<String>,
+}
+
 /// Configuration for git-remote-walrus
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -30,6 +162,9 @@ pub struct WalrusRemoteConfig {
     pub sui_wallet_path: PathBuf,
     /// Path to Walrus CLI config
     pub walrus_config_path: Option<PathBuf>,
+    /// Walrus CLI to run (None uses `walrus` from PATH)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub walrus_binary: Option<PathBuf>,
     /// Cache directory for local storage
     pub cache_dir: PathBuf,
     /// Default number of epochs for blob storage
@@ -44,11 +179,157 @@ pub struct WalrusRemoteConfig {
     /// Maximum size for batched blobs (in bytes)
     #[serde(default = "defaults::default_max_batch_blob_size")]
     pub max_batch_blob_size: u64,
+    /// Simulated network blob size limit (in bytes), for forcing small batches and chunked
+    /// objects maps; never raises the network's real limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_blob_size: Option<u64>,
+    /// Allow state-mutating operations against Sui mainnet
+    #[serde(default)]
+    pub allow_mainnet: bool,
+    /// Whether new blobs are stored as permanent or deletable
+    #[serde(default)]
+    pub blob_persistence: BlobPersistence,
+    /// Walrus encoding type for new blobs (None uses the walrus CLI default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub walrus_encoding: Option<String>,
+    /// Skip the pre-push balance check (escape hatch for estimation errors)
+    #[serde(default)]
+    pub skip_preflight: bool,
+    /// WAL coin type, for networks where it isn't built in (devnet, localnet)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wal_coin_type: Option<String>,
+    /// SUI balance (in MIST) that pushes must never spend
+    #[serde(default)]
+    pub gas_reserve_mist: u64,
+    /// Refuse pushes; the helper only lists and fetches
+    #[serde(default)]
+    pub read_only: bool,
+    /// Largest objects map blob (in bytes) the helper will download and parse
+    #[serde(default = "defaults::default_max_objects_map_bytes")]
+    pub max_objects_map_bytes: u64,
+    /// Disk space (in bytes) for whole blobs cached after download, so objects sharing a blob
+    /// don't download it again (0 disables the blob cache)
+    #[serde(default = "defaults::default_max_blob_cache_bytes")]
+    pub max_blob_cache_bytes: u64,
+    /// Most objects the local cache keeps, evicting the least recently cached (unset: no limit)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_max_entries: Option<usize>,
+    /// Refs `list` advertises, as `*` globs (empty advertises every ref)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub advertise_ref_patterns: Vec<String>,
+    /// Proxy for Walrus and Sui connections when `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY` are unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Hosts reached without the proxy when `NO_PROXY` is unset
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub no_proxy: Vec<String>,
+    /// Per-ref storage epochs, overriding `default_epochs` for matching refs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retention_rules: Vec<RetentionRule>,
+    /// Top up from the faucet when a push's pre-flight check finds the wallet short
+    /// (testnet, devnet and localnet only)
+    #[serde(default)]
+    pub auto_faucet: bool,
+    /// Log a health banner (objects, earliest blob expiry) when listing refs, at most hourly
+    #[serde(default)]
+    pub list_banner: bool,
+    /// HEAD advertised by remotes with only tags: `latest` detaches at the newest tagged
+    /// commit, `first` points at the first tag by name
+    #[serde(default)]
+    pub tag_only_head: TagOnlyHead,
+    /// Shell command run before a push uploads anything, with git pre-push hook input on
+    /// stdin; a nonzero exit aborts the push
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_push_hook: Option<String>,
+    /// Shell command run after a fetch, with `<sha1> <ref>` lines for the fetched refs on stdin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_fetch_hook: Option<String>,
+    /// Seconds a hook may run before the push or fetch is stopped
+    #[serde(default = "defaults::default_hook_timeout_secs")]
+    pub hook_timeout_secs: u64,
+    /// Refuse pushes without a verified certificate (`git push --signed`)
+    #[serde(default)]
+    pub require_signed_push: bool,
+    /// GnuPG home holding the keys push certificates are verified against
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push_cert_keyring: Option<PathBuf>,
+    /// Key fingerprints allowed to sign pushes (empty allows any key in the keyring)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub push_cert_signers: Vec<String>,
+    /// What pushes do with an object already stored under a different content ID:
+    /// `keep` the first mapping, `replace` it, or fail with `error`
+    #[serde(default)]
+    pub on_mapping_conflict: MappingConflict,
+    /// Seconds to wait for a TCP connection to the Sui RPC endpoint
+    #[serde(default = "defaults::default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    /// Seconds a walrus CLI command may go without progress before it is killed (unset: no
+    /// limit); Sui RPC requests keep the Sui SDK's own request timeout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
+    /// Threads that read unpacked objects back during a push (unset or 0: every core)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decompress_threads: Option<usize>,
+    /// Default for `upload_concurrency` and `download_concurrency`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<usize>,
+    /// `walrus store` invocations a push runs at once (unset: `jobs`, or 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_concurrency: Option<usize>,
+    /// Blobs a fetch downloads at once (unset: `jobs`, or 4)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_concurrency: Option<usize>,
+    /// Order a fetch reads objects in: `topo` from the oldest commit forward, `size` largest
+    /// blobs first, or `none` from the wanted refs back
+    #[serde(default)]
+    pub prefetch_strategy: PrefetchStrategy,
+    /// Shared Clock object passed to lock and push transactions
+    #[serde(default = "defaults::default_clock_object_id")]
+    pub clock_object_id: String,
+    /// Transactions whose state URLs pinned to them (`walrus::0x...@<digest>`) must keep
+    /// reading: renewal keeps the blobs it reads alive, and repacking leaves them as they are
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keep_pinned: Vec<String>,
+    /// Transaction digest a remote URL pins reads to (`walrus::0x...@<digest>`; never saved)
+    #[serde(skip)]
+    pub pinned_transaction: Option<String>,
+}
+
+/// Limits on how long network operations may take to connect and to make progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkTimeouts {
+    pub connect: Duration,
+    pub idle: Option<Duration>,
+}
+
+impl Default for NetworkTimeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(defaults::default_connect_timeout_secs()),
+            idle: None,
+        }
+    }
+}
+
+/// How many Walrus uploads and downloads run at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Concurrency {
+    pub upload: usize,
+    pub download: usize,
 }
 
 impl WalrusRemoteConfig {
-    /// Load configuration from environment variables and config file
+    /// Load configuration from the config file, git config and environment variables
     pub fn load() -> Result<Self> {
+        Self::load_for_remote(None)
+    }
+
+    /// Load configuration for the remote git calls `remote_name`
+    ///
+    /// Later sources override earlier ones: the YAML config file, then `walrus.*` git config,
+    /// then `remote.<remote_name>.walrus*` git config, then environment variables. Options in
+    /// the remote URL are applied on top by the caller.
+    pub fn load_for_remote(remote_name: Option<&str>) -> Result<Self> {
         // Try to load from config file
         let config_path = Self::config_file_path()?;
         tracing::debug!("loading git-remote-walrus config from {:?}", config_path);
@@ -58,29 +339,165 @@ impl WalrusRemoteConfig {
             anyhow::bail!("config file not found at {:?}", config_path);
         };
 
-        if let Ok(path) = env::var("SUI_WALLET") {
+        GitConfig::read(None, remote_name)?.apply(&mut config)?;
+
+        if let Some(path) = session_env::var("SUI_WALLET") {
             config.sui_wallet_path = expand_tilde(&PathBuf::from(path));
         }
 
-        if let Ok(path) = env::var("WALRUS_CONFIG") {
+        if let Some(path) = session_env::var("WALRUS_CONFIG") {
             config.walrus_config_path = Some(expand_tilde(&PathBuf::from(path)));
         }
 
-        if let Ok(path) = env::var("WALRUS_REMOTE_CACHE_DIR") {
+        if let Some(path) = session_env::var("WALRUS_BINARY") {
+            config.walrus_binary = Some(expand_tilde(&PathBuf::from(path)));
+        }
+
+        if let Some(path) = session_env::var("WALRUS_REMOTE_CACHE_DIR") {
             config.cache_dir = expand_tilde(&PathBuf::from(path));
         }
 
-        if let Ok(epochs) = env::var("WALRUS_REMOTE_BLOB_EPOCHS") {
+        if let Some(epochs) = session_env::var("WALRUS_REMOTE_BLOB_EPOCHS") {
             config.default_epochs = epochs
                 .parse()
                 .context("Failed to parse WALRUS_BLOB_EPOCHS as u32")?;
         }
 
-        if let Ok(threshold) = env::var("WALRUS_EXPIRATION_WARNING_THRESHOLD") {
+        if let Some(threshold) = session_env::var("WALRUS_EXPIRATION_WARNING_THRESHOLD") {
             config.expiration_warning_threshold = threshold
                 .parse()
                 .context("Failed to parse WALRUS_EXPIRATION_WARNING_THRESHOLD as u64")?;
         }
+
+        if let Some(allow) = session_env::var("WALRUS_REMOTE_ALLOW_MAINNET") {
+            config.allow_mainnet = parse_env_flag(&allow)
+                .context("Failed to parse WALRUS_REMOTE_ALLOW_MAINNET as a boolean")?;
+        }
+
+        if let Some(skip) = session_env::var("WALRUS_REMOTE_SKIP_PREFLIGHT") {
+            config.skip_preflight = parse_env_flag(&skip)
+                .context("Failed to parse WALRUS_REMOTE_SKIP_PREFLIGHT as a boolean")?;
+        }
+
+        if let Some(reserve) = session_env::var("WALRUS_REMOTE_GAS_RESERVE_MIST") {
+            config.gas_reserve_mist = reserve
+                .parse()
+                .context("Failed to parse WALRUS_REMOTE_GAS_RESERVE_MIST as u64")?;
+        }
+
+        if let Some(max) = session_env::var("WALRUS_REMOTE_MAX_BLOB_SIZE") {
+            config.max_blob_size = match max.trim() {
+                "" => None,
+                max => Some(
+                    max.parse()
+                        .context("Failed to parse WALRUS_REMOTE_MAX_BLOB_SIZE as u64")?,
+                ),
+            };
+        }
+
+        if let Some(max) = session_env::var("WALRUS_REMOTE_MAX_OBJECTS_MAP_BYTES") {
+            config.max_objects_map_bytes = max
+                .parse()
+                .context("Failed to parse WALRUS_REMOTE_MAX_OBJECTS_MAP_BYTES as u64")?;
+        }
+
+        if let Some(max) = session_env::var("WALRUS_REMOTE_MAX_BLOB_CACHE_BYTES") {
+            config.max_blob_cache_bytes = max
+                .trim()
+                .parse()
+                .context("Failed to parse WALRUS_REMOTE_MAX_BLOB_CACHE_BYTES as u64")?;
+        }
+
+        if let Some(max) = session_env::var("WALRUS_REMOTE_CACHE_MAX_ENTRIES") {
+            config.cache_max_entries = match max.trim() {
+                "" => None,
+                max => Some(
+                    max.parse()
+                        .context("Failed to parse WALRUS_REMOTE_CACHE_MAX_ENTRIES as a count")?,
+                ),
+            };
+        }
+
+        if let Some(read_only) = read_only_from_env()? {
+            config.read_only = read_only;
+        }
+
+        if let Some(patterns) = advertise_ref_patterns_from_env() {
+            config.advertise_ref_patterns = patterns;
+        }
+
+        if let Some(tag_only_head) = tag_only_head_from_env()? {
+            config.tag_only_head = tag_only_head;
+        }
+
+        if let Some(on_mapping_conflict) = on_mapping_conflict_from_env()? {
+            config.on_mapping_conflict = on_mapping_conflict;
+        }
+
+        if let Some(threads) = decompress_threads_from_env()? {
+            config.decompress_threads = Some(threads);
+        }
+
+        for (var, setting) in [
+            ("WALRUS_REMOTE_JOBS", &mut config.jobs),
+            (
+                "WALRUS_REMOTE_UPLOAD_CONCURRENCY",
+                &mut config.upload_concurrency,
+            ),
+            (
+                "WALRUS_REMOTE_DOWNLOAD_CONCURRENCY",
+                &mut config.download_concurrency,
+            ),
+        ] {
+            if let Some(value) = session_env::var(var) {
+                *setting = match value.trim() {
+                    "" => None,
+                    value => Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("Failed to parse {} as a count", var))?,
+                    ),
+                };
+            }
+        }
+
+        if let Some(strategy) = session_env::var("WALRUS_REMOTE_PREFETCH_STRATEGY") {
+            config.prefetch_strategy = strategy
+                .trim()
+                .parse()
+                .context("Failed to parse WALRUS_REMOTE_PREFETCH_STRATEGY")?;
+        }
+
+        if let Some(faucet) = session_env::var("WALRUS_REMOTE_AUTO_FAUCET") {
+            config.auto_faucet = parse_env_flag(&faucet)
+                .context("Failed to parse WALRUS_REMOTE_AUTO_FAUCET as a boolean")?;
+        }
+
+        if let Some(banner) = session_env::var("WALRUS_REMOTE_LIST_BANNER") {
+            config.list_banner = parse_env_flag(&banner)
+                .context("Failed to parse WALRUS_REMOTE_LIST_BANNER as a boolean")?;
+        }
+
+        if let Some(encoding) = session_env::var("WALRUS_REMOTE_ENCODING") {
+            config.walrus_encoding = Some(encoding).filter(|e| !e.is_empty());
+        }
+
+        if let Some(secs) = session_env::var("WALRUS_REMOTE_CONNECT_TIMEOUT_SECS") {
+            config.connect_timeout_secs = secs
+                .trim()
+                .parse()
+                .context("Failed to parse WALRUS_REMOTE_CONNECT_TIMEOUT_SECS as u64")?;
+        }
+
+        if let Some(secs) = session_env::var("WALRUS_REMOTE_IDLE_TIMEOUT_SECS") {
+            config.idle_timeout_secs = match secs.trim() {
+                "" | "0" => None,
+                secs => Some(
+                    secs.parse()
+                        .context("Failed to parse WALRUS_REMOTE_IDLE_TIMEOUT_SECS as u64")?,
+                ),
+            };
+        }
         Ok(config)
     }
 
@@ -98,6 +515,12 @@ impl WalrusRemoteConfig {
         if let Some(ref walrus_path) = config.walrus_config_path {
             config.walrus_config_path = Some(expand_tilde(walrus_path));
         }
+        if let Some(ref walrus_binary) = config.walrus_binary {
+            config.walrus_binary = Some(expand_tilde(walrus_binary));
+        }
+        if let Some(ref keyring) = config.push_cert_keyring {
+            config.push_cert_keyring = Some(expand_tilde(keyring));
+        }
 
         Ok(config)
     }
@@ -126,6 +549,61 @@ impl WalrusRemoteConfig {
             .context("Could not determine home directory for config file")
     }
 
+    /// Proxy settings from the environment, falling back to `proxy` and `no_proxy`
+    pub fn proxy_settings(&self) -> ProxySettings {
+        ProxySettings::resolve(self.proxy.as_deref(), &self.no_proxy)
+    }
+
+    /// Retention for objects introduced by `refname`: the most epochs among matching
+    /// `retention_rules`, or `default_epochs` when none match
+    pub fn retention_for(&self, refname: &str) -> Retention {
+        self.retention_rules
+            .iter()
+            .filter(|rule| glob_match(&rule.refs, refname))
+            .max_by_key(|rule| rule.epochs)
+            .map(|rule| Retention {
+                epochs: rule.epochs,
+                rule: Some(rule.refs.clone()),
+            })
+            .unwrap_or(Retention {
+                epochs: self.default_epochs,
+                rule: None,
+            })
+    }
+
+    /// WAL coin type on `network`: `wal_coin_type`, else the network's well-known one
+    pub fn wal_coin_type_on(&self, network: SuiNetwork) -> Option<String> {
+        self.wal_coin_type
+            .clone()
+            .or_else(|| network.wal_coin_type().map(String::from))
+    }
+
+    /// Connect timeout for Sui RPC and idle timeout for the walrus CLI
+    pub fn network_timeouts(&self) -> NetworkTimeouts {
+        NetworkTimeouts {
+            connect: Duration::from_secs(self.connect_timeout_secs),
+            idle: self
+                .idle_timeout_secs
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+        }
+    }
+
+    /// Uploads and downloads to run at once: `upload_concurrency` and `download_concurrency`,
+    /// falling back to `jobs` and then to defaults (never below 1)
+    ///
+    /// The publisher is usually the bottleneck and rate-limits, so uploads default to one at a
+    /// time, while the aggregator serves wide fan-out well. Each upload in flight holds up to
+    /// `max_batch_blob_size` bytes in memory, and each download a whole blob.
+    pub fn concurrency(&self) -> Concurrency {
+        let resolve =
+            |setting: Option<usize>, default| setting.or(self.jobs).unwrap_or(default).max(1);
+        Concurrency {
+            upload: resolve(self.upload_concurrency, 1),
+            download: resolve(self.download_concurrency, 4),
+        }
+    }
+
     /// Get cache directory, creating it if necessary
     pub fn ensure_cache_dir(&self) -> Result<PathBuf> {
         std::fs::create_dir_all(&self.cache_dir)
@@ -150,10 +628,33 @@ mod defaults {
     pub(crate) fn default_max_batch_blob_size() -> u64 {
         100 * 1024 * 1024 // 100 MB
     }
+
+    pub(crate) fn default_max_objects_map_bytes() -> u64 {
+        256 * 1024 * 1024 // 256 MiB
+    }
+
+    pub(crate) fn default_max_blob_cache_bytes() -> u64 {
+        // Two full batch blobs, not a second copy of everything the object cache holds
+        2 * default_max_batch_blob_size()
+    }
+
+    pub(crate) fn default_hook_timeout_secs() -> u64 {
+        crate::commands::hooks::DEFAULT_HOOK_TIMEOUT_SECS
+    }
+
+    pub(crate) fn default_connect_timeout_secs() -> u64 {
+        10
+    }
+
+    pub(crate) fn default_clock_object_id() -> String {
+        "0x6".to_string()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::env;
+
     use tempfile::tempdir;
 
     use super::*;
@@ -171,11 +672,155 @@ mod tests {
             expiration_warning_threshold: 15,
             enable_batching: true,
             max_batch_blob_size: 100 * 1024 * 1024,
+            max_blob_size: Some(4096),
+            allow_mainnet: false,
+            blob_persistence: BlobPersistence::Deletable,
+            walrus_encoding: Some("RS2".to_string()),
+            skip_preflight: true,
+            wal_coin_type: None,
+            gas_reserve_mist: 0,
+            walrus_binary: None,
+            read_only: true,
+            max_objects_map_bytes: 1024,
+            max_blob_cache_bytes: 0,
+            cache_max_entries: Some(50_000),
+            advertise_ref_patterns: vec!["refs/heads/*".to_string()],
+            proxy: Some("http://proxy.example:3128".to_string()),
+            no_proxy: Vec::new(),
+            retention_rules: vec![RetentionRule {
+                refs: "refs/tags/v*".to_string(),
+                epochs: 50,
+            }],
+            auto_faucet: false,
+            list_banner: true,
+            tag_only_head: TagOnlyHead::First,
+            pre_push_hook: Some("./check-signatures.sh".to_string()),
+            post_fetch_hook: None,
+            hook_timeout_secs: 30,
+            require_signed_push: true,
+            push_cert_keyring: Some(PathBuf::from("/path/to/gnupg")),
+            push_cert_signers: vec!["0123456789ABCDEF".to_string()],
+            on_mapping_conflict: MappingConflict::Error,
+            connect_timeout_secs: 5,
+            idle_timeout_secs: Some(90),
+            decompress_threads: Some(2),
+            jobs: Some(3),
+            upload_concurrency: None,
+            download_concurrency: Some(16),
+            prefetch_strategy: PrefetchStrategy::Size,
+            clock_object_id: "0x1234".to_string(),
+            keep_pinned: Vec::new(),
+            pinned_transaction: None,
         };
         config.save(&config_path).unwrap();
 
         let loaded = WalrusRemoteConfig::load_from_file(&config_path).unwrap();
         assert_eq!(loaded.default_epochs, config.default_epochs);
+        assert_eq!(loaded.blob_persistence, BlobPersistence::Deletable);
+        assert!(loaded.read_only);
+        assert_eq!(loaded.max_objects_map_bytes, 1024);
+        assert_eq!(loaded.max_blob_cache_bytes, 0);
+        assert_eq!(loaded.cache_max_entries, Some(50_000));
+        assert_eq!(loaded.max_blob_size, Some(4096));
+        assert_eq!(loaded.advertise_ref_patterns, vec!["refs/heads/*"]);
+        assert_eq!(loaded.tag_only_head, TagOnlyHead::First);
+        assert_eq!(loaded.pre_push_hook, config.pre_push_hook);
+        assert_eq!(loaded.post_fetch_hook, None);
+        assert_eq!(loaded.hook_timeout_secs, 30);
+        assert!(loaded.require_signed_push);
+        assert_eq!(loaded.push_cert_keyring, config.push_cert_keyring);
+        assert_eq!(loaded.push_cert_signers, config.push_cert_signers);
+        assert_eq!(loaded.on_mapping_conflict, MappingConflict::Error);
+        assert_eq!(loaded.decompress_threads, Some(2));
+        assert_eq!(
+            loaded.concurrency(),
+            Concurrency {
+                upload: 3,
+                download: 16
+            }
+        );
+        assert_eq!(loaded.prefetch_strategy, PrefetchStrategy::Size);
+        assert_eq!(loaded.clock_object_id, "0x1234");
+        assert_eq!(
+            loaded.network_timeouts(),
+            NetworkTimeouts {
+                connect: Duration::from_secs(5),
+                idle: Some(Duration::from_secs(90)),
+            }
+        );
+        assert!(loaded.skip_preflight);
+        assert_eq!(loaded.retention_rules, config.retention_rules);
+        assert!(loaded.list_banner);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("refs/heads/*", "refs/heads/main"));
+        assert!(glob_match("refs/heads/*", "refs/heads/feature/x"));
+        assert!(glob_match("refs/tags/v*", "refs/tags/v1.2"));
+        assert!(!glob_match("refs/tags/v*", "refs/tags/old"));
+        assert!(glob_match("refs/heads/main", "refs/heads/main"));
+        assert!(!glob_match("refs/heads/main", "refs/heads/main2"));
+        assert!(glob_match("refs/*/release-*", "refs/heads/release-1"));
+        assert!(!glob_match("refs/*/release-*", "refs/heads/hotfix"));
+        // Prefix and suffix may not overlap
+        assert!(!glob_match("refs/a*a", "refs/a"));
+    }
+
+    #[test]
+    fn test_retention_for_takes_most_epochs() {
+        let config: WalrusRemoteConfig = serde_yaml::from_str(
+            r#"
+sui_wallet_path: /wallet
+cache_dir: /cache
+default_epochs: 5
+retention_rules:
+  - { refs: "refs/tags/*", epochs: 20 }
+  - { refs: "refs/tags/v*", epochs: 53 }
+  - { refs: "refs/heads/tmp/*", epochs: 1 }
+"#,
+        )
+        .unwrap();
+
+        let retention = config.retention_for("refs/tags/v1.0");
+        assert_eq!(retention.epochs, 53);
+        assert_eq!(retention.rule.as_deref(), Some("refs/tags/v*"));
+        assert_eq!(config.retention_for("refs/tags/nightly").epochs, 20);
+        assert_eq!(config.retention_for("refs/heads/tmp/spike").epochs, 1);
+        assert_eq!(
+            config.retention_for("refs/heads/main"),
+            Retention {
+                epochs: 5,
+                rule: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_concurrency_defaults_per_direction() {
+        let config: WalrusRemoteConfig =
+            serde_yaml::from_str("sui_wallet_path: /wallet\ncache_dir: /cache\n").unwrap();
+        assert_eq!(
+            config.concurrency(),
+            Concurrency {
+                upload: 1,
+                download: 4
+            }
+        );
+
+        // `jobs` sets both, and each direction can still be set on its own
+        let config = WalrusRemoteConfig {
+            jobs: Some(6),
+            upload_concurrency: Some(0),
+            ..config
+        };
+        assert_eq!(
+            config.concurrency(),
+            Concurrency {
+                upload: 1,
+                download: 6
+            }
+        );
     }
 
     #[test]
@@ -188,6 +833,15 @@ mod tests {
         env::remove_var("WALRUS_REMOTE_BLOB_EPOCHS");
     }
 
+    #[test]
+    fn test_parse_env_flag() {
+        assert!(parse_env_flag("1").unwrap());
+        assert!(parse_env_flag("true").unwrap());
+        assert!(!parse_env_flag("0").unwrap());
+        assert!(!parse_env_flag("no").unwrap());
+        assert!(parse_env_flag("maybe").is_err());
+    }
+
     #[test]
     fn test_tilde_expansion() {
         let dir = tempdir().unwrap();
@@ -211,6 +865,7 @@ expiration_warning_threshold: 10
             assert_eq!(loaded.cache_dir, home.join("cache"));
             assert_eq!(loaded.walrus_config_path, Some(home.join("walrus/config")));
         }
+        assert_eq!(loaded.clock_object_id, "0x6");
     }
 
     #[test]
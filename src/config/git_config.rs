@@ -0,0 +1,370 @@
+//! Settings from the git config hierarchy: `walrus.<key>`, and `remote.<name>.walrus<key>` for
+//! the remote in use (e.g. `walrus.cacheDir`, `remote.origin.walrusEpochs`)
+
+use std::{collections::BTreeMap, path::Path, str::FromStr};
+
+use anyhow::{Context, Result};
+
+use super::{expand_tilde, parse_env_flag, RetentionRule, WalrusRemoteConfig};
+use crate::subprocess::CommandRunner;
+
+/// Values read from git config, keyed by lowercased name without the `walrus` prefix
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GitConfig {
+    /// `walrus.<key>`
+    global: BTreeMap<String, String>,
+    /// `remote.<name>.walrus<key>`
+    remote: BTreeMap<String, String>,
+    remote_name: Option<String>,
+}
+
+impl GitConfig {
+    /// Read the walrus settings git sees from `repo` (the current repository if None), with
+    /// those of `remote_name`
+    pub fn read(repo: Option<&Path>, remote_name: Option<&str>) -> Result<Self> {
+        let mut runner = CommandRunner::git().args([
+            "config",
+            "-z",
+            "--get-regexp",
+            r"^walrus\.|^remote\..*\.walrus",
+        ]);
+        if let Some(repo) = repo {
+            runner = runner.current_dir(repo);
+        }
+        let output = match runner.output() {
+            Ok(output) => output,
+            Err(e) => {
+                tracing::debug!("Not reading git config: {:#}", e);
+                return Ok(Self::default());
+            }
+        };
+        match output.status.code() {
+            Some(0) => Ok(Self::parse(&output.stdout, remote_name)),
+            // Nothing matched
+            Some(1) => Ok(Self::default()),
+            _ => anyhow::bail!(
+                "Failed to read git config: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        }
+    }
+
+    /// Parse `git config -z --get-regexp` output: `<key>\n<value>\0`, or `<key>\0` for a key
+    /// without a value (which git treats as true)
+    fn parse(output: &[u8], remote_name: Option<&str>) -> Self {
+        let mut config = Self {
+            remote_name: remote_name.map(str::to_string),
+            ..Self::default()
+        };
+        for entry in String::from_utf8_lossy(output).split('\0') {
+            let (key, value) = entry.split_once('\n').unwrap_or((entry, "true"));
+            if let Some(name) = key.strip_prefix("walrus.") {
+                config.global.insert(name.to_string(), value.to_string());
+            } else if let Some((remote, name)) = key
+                .strip_prefix("remote.")
+                .and_then(|rest| rest.rsplit_once('.'))
+            {
+                // Only the subsection (the remote's name) keeps its case
+                if Some(remote) == remote_name {
+                    if let Some(name) = name.strip_prefix("walrus").filter(|n| !n.is_empty()) {
+                        config.remote.insert(name.to_string(), value.to_string());
+                    }
+                }
+            }
+        }
+        config
+    }
+
+    /// Override `config` with `walrus.*` settings, then with the remote's own
+    pub fn apply(&self, config: &mut WalrusRemoteConfig) -> Result<()> {
+        for (name, value) in &self.global {
+            apply_key(config, name, value)
+                .with_context(|| format!("Invalid git config walrus.{}", name))?;
+        }
+        for (name, value) in &self.remote {
+            apply_key(config, name, value).with_context(|| {
+                format!(
+                    "Invalid git config remote.{}.walrus{}",
+                    self.remote_name.as_deref().unwrap_or_default(),
+                    name
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Set the config field a (lowercased) git config name stands for
+///
+/// Every field of the config file has a key; names that match none are an error rather than
+/// silently ignored. An empty value clears optional settings.
+fn apply_key(config: &mut WalrusRemoteConfig, name: &str, value: &str) -> Result<()> {
+    match name {
+        "suiwallet" => config.sui_wallet_path = expand_tilde(Path::new(value)),
+        "walrusconfig" => {
+            config.walrus_config_path = optional(value)?.map(|path| expand_tilde(&path))
+        }
+        "binary" => config.walrus_binary = optional(value)?.map(|path| expand_tilde(&path)),
+        "cachedir" => config.cache_dir = expand_tilde(Path::new(value)),
+        "epochs" => config.default_epochs = value.trim().parse()?,
+        "expirationwarningthreshold" => {
+            config.expiration_warning_threshold = value.trim().parse()?
+        }
+        "enablebatching" => config.enable_batching = parse_env_flag(value)?,
+        "maxbatchblobsize" => config.max_batch_blob_size = value.trim().parse()?,
+        "maxblobsize" => config.max_blob_size = optional(value)?,
+        "allowmainnet" => config.allow_mainnet = parse_env_flag(value)?,
+        "blobpersistence" => config.blob_persistence = value.trim().parse()?,
+        "encoding" => config.walrus_encoding = optional(value)?,
+        "skippreflight" => config.skip_preflight = parse_env_flag(value)?,
+        "walcointype" => config.wal_coin_type = optional(value)?,
+        "gasreservemist" => config.gas_reserve_mist = value.trim().parse()?,
+        "readonly" => config.read_only = parse_env_flag(value)?,
+        "maxobjectsmapbytes" => config.max_objects_map_bytes = value.trim().parse()?,
+        "maxblobcachebytes" => config.max_blob_cache_bytes = value.trim().parse()?,
+        "cachemaxentries" => config.cache_max_entries = optional(value)?,
+        "advertiserefpatterns" => config.advertise_ref_patterns = list(value),
+        "proxy" => config.proxy = optional(value)?,
+        "noproxy" => config.no_proxy = list(value),
+        "retentionrules" => config.retention_rules = retention_rules(value)?,
+        "autofaucet" => config.auto_faucet = parse_env_flag(value)?,
+        "listbanner" => config.list_banner = parse_env_flag(value)?,
+        "tagonlyhead" => config.tag_only_head = value.trim().parse()?,
+        "prepushhook" => config.pre_push_hook = optional(value)?,
+        "postfetchhook" => config.post_fetch_hook = optional(value)?,
+        "hooktimeoutsecs" => config.hook_timeout_secs = value.trim().parse()?,
+        "requiresignedpush" => config.require_signed_push = parse_env_flag(value)?,
+        "pushcertkeyring" => {
+            config.push_cert_keyring = optional(value)?.map(|path| expand_tilde(&path))
+        }
+        "pushcertsigners" => config.push_cert_signers = list(value),
+        "onmappingconflict" => config.on_mapping_conflict = value.trim().parse()?,
+        "connecttimeoutsecs" => config.connect_timeout_secs = value.trim().parse()?,
+        "idletimeoutsecs" => config.idle_timeout_secs = optional(value)?,
+        "decompressthreads" => config.decompress_threads = optional(value)?,
+        "jobs" => config.jobs = optional(value)?,
+        "uploadconcurrency" => config.upload_concurrency = optional(value)?,
+        "downloadconcurrency" => config.download_concurrency = optional(value)?,
+        "prefetchstrategy" => config.prefetch_strategy = value.trim().parse()?,
+        "clockobjectid" => config.clock_object_id = value.trim().to_string(),
+        "keeppinned" => config.keep_pinned = list(value),
+        other => anyhow::bail!("Unknown walrus setting {:?}", other),
+    }
+    Ok(())
+}
+
+/// `value` parsed, or None if it is empty
+fn optional<T: FromStr>(value: &str) -> Result<Option<T>>
+where
+    anyhow::Error: From<T::Err>,
+{
+    match value.trim() {
+        "" => Ok(None),
+        value => Ok(Some(value.parse()?)),
+    }
+}
+
+/// Comma-separated values, without blanks
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Comma-separated `<glob>=<epochs>` rules, e.g. `refs/tags/v*=50, refs/heads/*=10`
+fn retention_rules(value: &str) -> Result<Vec<RetentionRule>> {
+    list(value)
+        .into_iter()
+        .map(|rule| {
+            let (refs, epochs) = rule
+                .rsplit_once('=')
+                .with_context(|| format!("Expected <glob>=<epochs>, got {:?}", rule))?;
+            Ok(RetentionRule {
+                refs: refs.trim().to_string(),
+                epochs: epochs
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid epochs in retention rule {:?}", rule))?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{commands::list::TagOnlyHead, storage::MappingConflict, walrus::BlobPersistence};
+
+    fn git(dir: &Path, args: &[&str]) {
+        CommandRunner::git_scratch(&dir.join(".git"))
+            .args(args)
+            .run()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_git_config_overrides_yaml() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.yaml");
+        std::fs::write(
+            &config_path,
+            "sui_wallet_path: /wallet\ncache_dir: /cache\nwalrus_config_path: null\n",
+        )
+        .unwrap();
+        let mut config = WalrusRemoteConfig::load_from_file(&config_path).unwrap();
+        assert_eq!(config.default_epochs, 5);
+
+        let repo = dir.path().join("repo");
+        std::fs::create_dir(&repo).unwrap();
+        CommandRunner::git()
+            .args(["init", "--quiet"])
+            .current_dir(&repo)
+            .run()
+            .unwrap();
+        git(&repo, &["config", "walrus.epochs", "9"]);
+        git(&repo, &["config", "walrus.cacheDir", "/repo-cache"]);
+        git(&repo, &["config", "walrus.readOnly", "yes"]);
+        git(
+            &repo,
+            &[
+                "config",
+                "walrus.advertiseRefPatterns",
+                "refs/heads/*, refs/tags/v*",
+            ],
+        );
+        // The remote's own settings win over walrus.*, and only for that remote
+        git(&repo, &["config", "remote.origin.walrusEpochs", "12"]);
+        git(
+            &repo,
+            &["config", "remote.origin.walrusBlobPersistence", "deletable"],
+        );
+        git(&repo, &["config", "remote.other.walrusEpochs", "99"]);
+
+        GitConfig::read(Some(&repo), Some("origin"))
+            .unwrap()
+            .apply(&mut config)
+            .unwrap();
+        assert_eq!(config.default_epochs, 12);
+        assert_eq!(config.cache_dir, Path::new("/repo-cache"));
+        assert!(config.read_only);
+        assert_eq!(
+            config.advertise_ref_patterns,
+            vec!["refs/heads/*", "refs/tags/v*"]
+        );
+        assert_eq!(config.blob_persistence, BlobPersistence::Deletable);
+        assert_eq!(config.sui_wallet_path, Path::new("/wallet"));
+
+        // Without a remote name only walrus.* applies
+        let mut config = WalrusRemoteConfig::load_from_file(&config_path).unwrap();
+        GitConfig::read(Some(&repo), None)
+            .unwrap()
+            .apply(&mut config)
+            .unwrap();
+        assert_eq!(config.default_epochs, 9);
+
+        git(&repo, &["config", "walrus.epochs", "many"]);
+        let err = GitConfig::read(Some(&repo), None)
+            .unwrap()
+            .apply(&mut config)
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("walrus.epochs"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_every_key_applies() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.yaml");
+        std::fs::write(
+            &config_path,
+            "sui_wallet_path: /wallet\ncache_dir: /cache\nwalrus_config_path: /walrus\n\
+             proxy: http://proxy:8080\n",
+        )
+        .unwrap();
+        let mut config = WalrusRemoteConfig::load_from_file(&config_path).unwrap();
+        for (name, value) in [
+            ("enablebatching", "false"),
+            ("maxbatchblobsize", "1024"),
+            ("maxblobsize", "2048"),
+            ("walcointype", "0x2::wal::WAL"),
+            ("maxobjectsmapbytes", "4096"),
+            ("noproxy", "localhost, 10.0.0.1"),
+            ("retentionrules", "refs/tags/v*=50, refs/heads/*=10"),
+            ("tagonlyhead", "first"),
+            ("prepushhook", "./check"),
+            ("hooktimeoutsecs", "7"),
+            ("requiresignedpush", "yes"),
+            ("pushcertsigners", "AAAA,BBBB"),
+            ("onmappingconflict", "replace"),
+            ("connecttimeoutsecs", "3"),
+            ("idletimeoutsecs", "30"),
+            ("decompressthreads", "2"),
+            ("clockobjectid", "0x6"),
+            ("keeppinned", "7xVHGwaG, 9aBcDeF"),
+            // Empty values clear optional settings
+            ("walrusconfig", ""),
+            ("proxy", ""),
+        ] {
+            apply_key(&mut config, name, value)
+                .unwrap_or_else(|e| panic!("{} = {:?}: {:#}", name, value, e));
+        }
+
+        assert!(!config.enable_batching);
+        assert_eq!(config.max_batch_blob_size, 1024);
+        assert_eq!(config.max_blob_size, Some(2048));
+        assert_eq!(config.wal_coin_type.as_deref(), Some("0x2::wal::WAL"));
+        assert_eq!(config.max_objects_map_bytes, 4096);
+        assert_eq!(config.no_proxy, vec!["localhost", "10.0.0.1"]);
+        assert_eq!(
+            config.retention_rules,
+            vec![
+                RetentionRule {
+                    refs: "refs/tags/v*".to_string(),
+                    epochs: 50,
+                },
+                RetentionRule {
+                    refs: "refs/heads/*".to_string(),
+                    epochs: 10,
+                },
+            ]
+        );
+        assert_eq!(config.tag_only_head, TagOnlyHead::First);
+        assert_eq!(config.pre_push_hook.as_deref(), Some("./check"));
+        assert_eq!(config.hook_timeout_secs, 7);
+        assert!(config.require_signed_push);
+        assert_eq!(config.push_cert_signers, vec!["AAAA", "BBBB"]);
+        assert_eq!(config.on_mapping_conflict, MappingConflict::Replace);
+        assert_eq!(config.connect_timeout_secs, 3);
+        assert_eq!(config.idle_timeout_secs, Some(30));
+        assert_eq!(config.decompress_threads, Some(2));
+        assert_eq!(config.clock_object_id, "0x6");
+        assert_eq!(config.keep_pinned, vec!["7xVHGwaG", "9aBcDeF"]);
+        assert_eq!(config.walrus_config_path, None);
+        assert_eq!(config.proxy, None);
+
+        let err = apply_key(&mut config, "epoch", "9").unwrap_err();
+        assert!(
+            err.to_string().contains("Unknown walrus setting"),
+            "{}",
+            err
+        );
+        assert!(apply_key(&mut config, "retentionrules", "refs/tags/*").is_err());
+    }
+
+    #[test]
+    fn test_parse_keys_without_values() {
+        let output =
+            b"walrus.readonly\0remote.my.remote.walrusepochs\n3\0remote.my.remote.url\nx\0";
+        let config = GitConfig::parse(output, Some("my.remote"));
+        assert_eq!(
+            config.global.get("readonly").map(String::as_str),
+            Some("true")
+        );
+        assert_eq!(config.remote.get("epochs").map(String::as_str), Some("3"));
+        assert_eq!(config.remote.len(), 1);
+    }
+}
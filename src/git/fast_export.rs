@@ -1,25 +1,56 @@
-use std::{collections::HashMap, io::BufRead};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::BufRead,
+};
 
 use anyhow::{Context, Result};
 
-/// Parse a fast-export stream and return the raw data plus ref updates
-/// Returns: (stream_bytes, ref_updates_map)
-pub fn parse_stream<R: BufRead>(
-    lines: &mut std::io::Lines<R>,
-) -> Result<(Vec<u8>, HashMap<String, String>)> {
+use crate::protocol::ProtocolReader;
+
+/// What a fast-export stream pushes
+#[derive(Debug)]
+pub struct ParsedStream {
+    /// The stream as read, `data` payloads included
+    pub bytes: Vec<u8>,
+    /// Ref name → SHA-1 (or mark) of each ref the stream updates
+    pub ref_updates: HashMap<String, String>,
+    /// Refs the stream updates whose names aren't UTF-8, as git sent them
+    pub non_utf8_refs: BTreeSet<Vec<u8>>,
+}
+
+/// Parse a fast-export stream into the raw data plus ref updates
+///
+/// Lines are read as bytes: ref names and author names need not be UTF-8, and only the
+/// command keywords are decoded to parse them.
+pub fn parse_stream<R: BufRead>(lines: &mut ProtocolReader<R>) -> Result<ParsedStream> {
     let mut stream_bytes = Vec::new();
     let mut ref_updates = HashMap::new();
+    let mut non_utf8_refs = BTreeSet::new();
     let mut current_ref: Option<String> = None;
     let mut commit_sha1: Option<String> = None;
+    let mut in_reset = false;
+    // Annotated tags name their target by mark, so they're recorded separately
+    let mut current_tag: Option<String> = None;
+    let mut tag_updates = HashMap::new();
 
-    #[allow(clippy::while_let_on_iterator)]
-    while let Some(line) = lines.next() {
-        let line = line.context("Failed to read line from fast-export stream")?;
+    while let Some(raw) = lines.next_bytes() {
+        let raw = raw.context("Failed to read line from fast-export stream")?;
 
         // Add line to our stored stream (with newline)
-        stream_bytes.extend_from_slice(line.as_bytes());
+        stream_bytes.extend_from_slice(&raw);
         stream_bytes.push(b'\n');
 
+        let Ok(line) = std::str::from_utf8(&raw) else {
+            // A ref that can't be stored; what follows it up to the next ref is ignored
+            if let Some(refname) = named_ref(raw.trim_ascii()) {
+                non_utf8_refs.insert(refname);
+                current_ref = None;
+                commit_sha1 = None;
+                in_reset = false;
+                current_tag = None;
+            }
+            continue;
+        };
         let trimmed = line.trim();
 
         // Check for 'done' command (end of stream)
@@ -31,17 +62,34 @@ pub fn parse_stream<R: BufRead>(
         if let Some(stripped) = trimmed.strip_prefix("reset ") {
             current_ref = Some(stripped.to_string());
             commit_sha1 = None;
+            in_reset = true;
         }
 
         // Parse commit lines to track which ref we're updating
         if let Some(stripped) = trimmed.strip_prefix("commit ") {
             current_ref = Some(stripped.to_string());
             commit_sha1 = None;
+            in_reset = false;
+        }
+
+        // Parse 'tag' lines, which name an annotated tag under refs/tags/
+        if let Some(stripped) = trimmed.strip_prefix("tag ") {
+            current_tag = Some(format!("refs/tags/{}", stripped));
         }
 
         // Parse 'from' lines which contain the Git SHA-1 of the commit
         if let Some(sha1_str) = trimmed.strip_prefix("from ") {
             let sha1 = sha1_str.trim();
+            if let Some(tag) = current_tag.take() {
+                tag_updates.insert(tag, sha1.to_string());
+                continue;
+            }
+            // A reset to a commit exported by an earlier push names it by its imported mark
+            if in_reset && sha1.starts_with(':') {
+                if let Some(ref refname) = current_ref {
+                    ref_updates.insert(refname.clone(), sha1.to_string());
+                }
+            }
             // Handle both marks (:1) and SHA-1s
             if !sha1.starts_with(':') && sha1.len() == 40 {
                 commit_sha1 = Some(sha1.to_string());
@@ -65,15 +113,9 @@ pub fn parse_stream<R: BufRead>(
                 .parse()
                 .context("Failed to parse data size in fast-export stream")?;
 
-            // Read exactly 'size' bytes
-            let buffer = vec![0u8; size];
-
-            // We need to read from the underlying reader, not lines
-            // This is a limitation - we'll need to refactor to handle this properly
-            // For now, let's store a placeholder
-
-            stream_bytes.extend_from_slice(&buffer);
-            stream_bytes.push(b'\n');
+            // Copy the payload verbatim, so binary blobs and message lines (such as a tag's
+            // signature) are neither rejected nor parsed as commands
+            stream_bytes.extend_from_slice(&lines.data(size)?);
         }
 
         // Empty line might signal end of a command
@@ -83,27 +125,58 @@ pub fn parse_stream<R: BufRead>(
         }
     }
 
-    // If we finished without explicit ref updates, try to extract from the stream
+    // Add refs of commits without explicit SHA-1s from the stream
     // This is a simplified implementation - a real parser would track marks properly
-    if ref_updates.is_empty() {
-        ref_updates = extract_refs_from_stream(&stream_bytes)?;
+    for (refname, sha1) in extract_refs_from_stream(&stream_bytes)? {
+        ref_updates.entry(refname).or_insert(sha1);
     }
+    ref_updates.extend(tag_updates);
+
+    Ok(ParsedStream {
+        bytes: stream_bytes,
+        ref_updates,
+        non_utf8_refs,
+    })
+}
 
-    Ok((stream_bytes, ref_updates))
+/// The full ref name a `commit`, `reset` or `tag` line names
+fn named_ref(line: &[u8]) -> Option<Vec<u8>> {
+    if let Some(tag) = line.strip_prefix(b"tag ") {
+        return Some([b"refs/tags/".as_slice(), tag].concat());
+    }
+    line.strip_prefix(b"commit ")
+        .or_else(|| line.strip_prefix(b"reset "))
+        .map(<[u8]>::to_vec)
 }
 
 /// Extract ref → SHA-1 mappings from the raw stream
 /// This is a helper for the simplified implementation
 fn extract_refs_from_stream(stream: &[u8]) -> Result<HashMap<String, String>> {
     let mut ref_updates = HashMap::new();
-    let stream_str = String::from_utf8_lossy(stream);
 
     let mut current_ref: Option<String> = None;
     let mut marks_to_sha: HashMap<String, String> = HashMap::new();
     let mut last_mark: Option<String> = None;
+    // Bytes left in the current data payload, which holds no commands
+    let mut payload_left: usize = 0;
 
-    for line in stream_str.lines() {
+    for raw in stream.split(|b| *b == b'\n') {
+        if payload_left > 0 {
+            payload_left = payload_left.saturating_sub(raw.len() + 1);
+            continue;
+        }
+        let Ok(line) = std::str::from_utf8(raw) else {
+            // Refs that aren't UTF-8 are refused, so nothing is recorded for their commits
+            if raw.trim_ascii().starts_with(b"commit ") {
+                current_ref = None;
+            }
+            continue;
+        };
         let trimmed = line.trim();
+        if let Some(size) = trimmed.strip_prefix("data ") {
+            payload_left = size.parse().unwrap_or(0);
+            continue;
+        }
 
         // Track which ref we're committing to
         if let Some(stripped) = trimmed.strip_prefix("commit ") {
@@ -166,3 +239,76 @@ fn generate_pseudo_sha1(ref_name: &str) -> String {
     // Take first 20 bytes (40 hex chars) to simulate a SHA-1
     hex::encode(&result[..20])
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_signed_tag_is_recorded_and_kept_verbatim() {
+        let message = "Release\n\
+                       from the tag message, not a command\n\
+                       -----BEGIN PGP SIGNATURE-----\n\
+                       iHUEABYIAB0WIQ\n\
+                       -----END PGP SIGNATURE-----\n";
+        let stream = format!(
+            "feature done\ntag v1\nfrom :2\ntagger Test <test@test.com> 0 +0000\n\
+             data {}\n{}\ndone\n",
+            message.len(),
+            message
+        );
+
+        let parsed = parse_stream(&mut ProtocolReader::new(Cursor::new(stream.clone()))).unwrap();
+        assert_eq!(String::from_utf8(parsed.bytes).unwrap(), stream);
+        assert_eq!(parsed.ref_updates.len(), 1);
+        assert!(parsed.ref_updates.contains_key("refs/tags/v1"));
+    }
+
+    #[test]
+    fn test_refs_of_previously_exported_commits() {
+        // With imported marks, a ref reset to an already-pushed commit names it by mark,
+        // alongside a new commit on another branch
+        let message = "commit refs/heads/not-a-ref\n";
+        let stream = format!(
+            "feature done\nreset refs/tags/v1\nfrom :1\n\n\
+             commit refs/heads/dev\nmark :2\nauthor A <a@a> 0 +0000\n\
+             committer A <a@a> 0 +0000\ndata {}\n{}from :1\n\ndone\n",
+            message.len(),
+            message
+        );
+
+        let parsed = parse_stream(&mut ProtocolReader::new(Cursor::new(stream))).unwrap();
+        let mut refs: Vec<_> = parsed.ref_updates.keys().map(String::as_str).collect();
+        refs.sort();
+        assert_eq!(refs, ["refs/heads/dev", "refs/tags/v1"]);
+    }
+
+    #[test]
+    fn test_refs_and_authors_that_are_not_utf8() {
+        // A Latin-1 author is kept as is; a Latin-1 ref is set aside rather than decoded
+        // lossily (and its `from` isn't taken for the ref before it)
+        let mut stream = b"feature done\nreset refs/tags/v1\nfrom :1\n\n".to_vec();
+        stream.extend_from_slice(
+            b"commit refs/heads/dev\nmark :2\nauthor Jos\xe9 <j@j> 0 +0000\n\
+              committer A <a@a> 0 +0000\ndata 2\nhi\nfrom :1\n\n\
+              reset refs/heads/caf\xe9\nfrom :2\n\ntag caf\xe9\nfrom :2\n\
+              tagger A <a@a> 0 +0000\ndata 0\n\ndone\n",
+        );
+
+        let parsed = parse_stream(&mut ProtocolReader::new(Cursor::new(stream.clone()))).unwrap();
+        assert_eq!(parsed.bytes, stream);
+        let mut refs: Vec<_> = parsed.ref_updates.keys().map(String::as_str).collect();
+        refs.sort();
+        assert_eq!(refs, ["refs/heads/dev", "refs/tags/v1"]);
+        assert_eq!(parsed.ref_updates["refs/tags/v1"], ":1");
+        assert_eq!(
+            parsed.non_utf8_refs.into_iter().collect::<Vec<_>>(),
+            [
+                b"refs/heads/caf\xe9".to_vec(),
+                b"refs/tags/caf\xe9".to_vec()
+            ]
+        );
+    }
+}
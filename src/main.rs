@@ -1,5 +1,4 @@
 #![deny(clippy::mod_module_files)]
-use std::path::PathBuf;
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
@@ -11,11 +10,22 @@ mod error;
 mod git;
 mod pack;
 mod protocol;
+mod proxy;
+mod remote;
+mod remote_resolution;
+mod session_env;
 mod storage;
+mod subcommands;
+mod subprocess;
 mod sui;
 mod walrus;
 
+use commands::{hooks::Hooks, namespace::RefNamespace, push_cert::PushCertPolicy};
+use protocol::SessionOptions;
+use remote::{parse_remote_url, resolve_helper_args, RemoteType};
+use remote_resolution::{Remote, RemoteArg};
 use storage::{FilesystemStorage, StorageBackend, WalrusStorage};
+use subprocess::CommandRunner;
 
 #[derive(Parser)]
 #[command(name = "git-remote-walrus")]
@@ -47,6 +57,9 @@ enum Command {
         /// Add addresses to the allowlist (can be specified multiple times)
         #[arg(long, value_name = "ADDRESS")]
         allow: Vec<String>,
+        /// On-chain refs storage: `table` (scales) or `inline` (faster for a handful of refs)
+        #[arg(long, default_value = "table")]
+        refs_layout: sui::RefsLayout,
     },
     /// Display or edit configuration
     Config {
@@ -54,12 +67,322 @@ enum Command {
         #[arg(short, long)]
         edit: bool,
     },
-}
-
-/// Remote storage backend type
-enum RemoteType {
-    Filesystem(PathBuf),
-    Sui(String), // Sui object ID as hex string
+    /// Copy a remote's objects, refs and metadata to a new Walrus or filesystem remote
+    ///
+    /// Resumable: progress is journaled in migration.yaml on the filesystem side.
+    Migrate {
+        /// Source git remote name, remote URL or filesystem remote path
+        #[arg(required_unless_present = "from")]
+        source: Option<String>,
+        /// Source remote, as an alternative to the positional argument
+        #[arg(long, value_name = "REMOTE", conflicts_with = "source")]
+        from: Option<String>,
+        /// Create a new RemoteState from this package ID and migrate to it
+        #[arg(
+            long,
+            value_name = "PACKAGE_ID",
+            required_unless_present_any = ["to_filesystem", "to"],
+            conflicts_with_all = ["to_filesystem", "to"]
+        )]
+        to_sui: Option<String>,
+        /// Migrate to a new filesystem remote at this path
+        #[arg(long, value_name = "PATH", conflicts_with = "to")]
+        to_filesystem: Option<std::path::PathBuf>,
+        /// Migrate to an existing remote nobody has pushed to yet (e.g. a git remote name or
+        /// walrus::0x...)
+        #[arg(long, value_name = "REMOTE")]
+        to: Option<String>,
+        /// Create a shared object (with --to-sui)
+        #[arg(long, requires = "to_sui")]
+        shared: bool,
+        /// Add addresses to the allowlist (with --to-sui; can be specified multiple times)
+        #[arg(long, value_name = "ADDRESS", requires = "to_sui")]
+        allow: Vec<String>,
+        /// On-chain refs storage for the new RemoteState: `table` or `inline`
+        #[arg(long, default_value = "table")]
+        refs_layout: sui::RefsLayout,
+    },
+    /// Check the config file and local cache, repairing problems with --fix
+    ///
+    /// Without --fix, only reports what would be repaired.
+    Doctor {
+        /// Apply the repairs
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Convert an inline-refs remote to the table layout
+    MigrateLayout {
+        #[command(flatten)]
+        remote: RemoteArg,
+    },
+    /// Show or set a remote's on-chain limits on refs and objects (setting is owner only)
+    ///
+    /// Pushes that would go over a limit are refused before anything is uploaded.
+    Policy {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Most refs the remote may hold, or `none`
+        #[arg(long, value_name = "N")]
+        max_refs: Option<subcommands::policy::Limit>,
+        /// Most objects the objects map may hold, or `none`
+        #[arg(long, value_name = "N")]
+        max_objects: Option<subcommands::policy::Limit>,
+    },
+    /// Delete deletable blobs no longer referenced by a remote's objects map
+    Reclaim {
+        #[command(flatten)]
+        remote: RemoteArg,
+    },
+    /// Remove dead entries (tombstoned refs, or refs whose tip is no longer stored) from a
+    /// remote's on-chain refs table, reclaiming their storage rebate
+    CompactRefs {
+        #[command(flatten)]
+        remote: RemoteArg,
+    },
+    /// Consolidate a remote's objects into as few maximum-size Walrus blobs as possible
+    ///
+    /// The old blobs are left to expire; `reclaim` deletes deletable ones sooner.
+    Repack {
+        #[command(flatten)]
+        remote: RemoteArg,
+    },
+    /// Rewrite a remote's objects map with only the objects its refs reach, after history
+    /// was rewritten by force-pushes
+    Compact {
+        #[command(flatten)]
+        remote: RemoteArg,
+    },
+    /// Report whether a push holds a remote's lock, and release it with --force
+    ///
+    /// For a push that crashed holding the lock; before it expires, only its holder may
+    /// release it.
+    Unlock {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Release the lock (breaking an expired lock another address holds)
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check that a remote's refs resolve and, with --full, that every object is intact
+    ///
+    /// Refs whose value isn't a git object ID are ignored by reads; --fix corrects or deletes
+    /// them.
+    Fsck {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Read back every object and check its bytes hash to its SHA-1
+        #[arg(long)]
+        full: bool,
+        /// Correct or delete invalid refs
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Estimate how much packing or chunking would save over a remote's stored blobs
+    ///
+    /// Successive versions of a file share most of their content-defined chunks, so the
+    /// chunked figures show what storing blobs as deduplicated chunks would cost.
+    DedupReport {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Read at most this many objects, spread evenly over the objects map
+        #[arg(long, value_name = "N")]
+        sample: Option<usize>,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show a remote's description, default branch and push history
+    Describe {
+        #[command(flatten)]
+        remote: RemoteArg,
+    },
+    /// Set a remote's description (and optionally its default branch)
+    SetDescription {
+        /// Git remote name, RemoteState object ID or remote URL (e.g. origin, 0x1234... or
+        /// walrus::/path)
+        remote: String,
+        /// New description
+        description: String,
+        /// Default branch to advertise (e.g. main or refs/heads/main)
+        #[arg(long)]
+        default_branch: Option<String>,
+    },
+    /// Print the commit graph reachable from a remote's refs (for external tooling)
+    Graph {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Output format: `json` or `dot` (Graphviz)
+        #[arg(long, default_value = "json")]
+        format: subcommands::graph::GraphFormat,
+    },
+    /// Print one file from a ref without cloning, reading only the trees along its path
+    Cat {
+        /// Git remote name or remote URL (e.g. origin or walrus::0x1234...)
+        remote: String,
+        /// `<ref>:<path>` (e.g. main:src/lib.rs; an empty ref means the remote's HEAD)
+        #[arg(value_name = "REF:PATH")]
+        spec: String,
+    },
+    /// List the commits that changed a path, without cloning
+    LogPath {
+        /// Git remote name or remote URL (e.g. origin or walrus::0x1234...)
+        remote: String,
+        /// Path of the file or directory
+        path: String,
+        /// Ref whose history is walked (default: the remote's HEAD)
+        #[arg(long = "ref", default_value = "HEAD")]
+        ref_name: String,
+    },
+    /// List a remote's refs
+    Refs {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Show who last updated each ref and when (read from the RemoteState's history)
+        #[arg(long, short)]
+        verbose: bool,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show, set or delete a symbolic ref the remote advertises (e.g. HEAD)
+    ///
+    /// Clones check out the branch HEAD points at, and `git remote set-head -a` follows it.
+    Symref {
+        /// Git remote name or remote URL (e.g. origin or walrus::0x1234...)
+        remote: String,
+        /// Symref name: HEAD or a full ref name (e.g. refs/heads/production)
+        name: String,
+        /// Ref to point it at (e.g. refs/heads/main, main or v1.0)
+        #[arg(conflicts_with = "delete")]
+        target: Option<String>,
+        /// Delete the symref
+        #[arg(long)]
+        delete: bool,
+    },
+    /// Summarize a remote's refs and objects and report when the blobs it references expire
+    ///
+    /// Exits 2 if any blob expires within --fail-if-expiring-within epochs, 1 on errors.
+    Status {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Exit with status 2 if any referenced blob expires within this many epochs
+        #[arg(long, value_name = "EPOCHS")]
+        fail_if_expiring_within: Option<u64>,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compare the remote's state with an older one: refs, objects map entries and symrefs
+    DiffState {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Object ID of the SharedBlob holding the older objects map (compares no refs)
+        #[arg(
+            long,
+            value_name = "OBJECTS_BLOB_OBJECT_ID",
+            required_unless_present = "at",
+            conflicts_with = "at"
+        )]
+        against: Option<String>,
+        /// Digest of a transaction; compares the whole state the remote had after it
+        #[arg(long, value_name = "TX_DIGEST")]
+        at: Option<String>,
+    },
+    /// Write the tree of a ref as a tar, tar.gz or zip archive, like `git archive`
+    Archive {
+        /// Git remote name or remote URL (e.g. origin or walrus::0x1234...)
+        remote: String,
+        /// Ref to archive (e.g. refs/heads/main, main or v1.0)
+        #[arg(value_name = "REF")]
+        ref_name: String,
+        /// `tar`, `tar.gz` (or `tgz`) or `zip`; inferred from --out, otherwise `tar`
+        #[arg(long)]
+        format: Option<pack::archive::ArchiveFormat>,
+        /// Output file (default: stdout)
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+        /// Prepend to every path; end it with `/` for a top-level directory
+        #[arg(long, default_value = "")]
+        prefix: String,
+    },
+    /// Write the files of a ref into a directory for upload as a Walrus Site
+    PublishSite {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Ref to publish (e.g. refs/heads/main, main or v1.0)
+        #[arg(long = "ref", default_value = "refs/heads/main")]
+        ref_name: String,
+        /// Output directory (must be empty or not exist)
+        #[arg(long)]
+        out: std::path::PathBuf,
+        /// Generate an index.html listing the files (unless the tree has one)
+        #[arg(long)]
+        index: bool,
+        /// Print a JSON manifest of files already stored whole in their own blob instead of
+        /// writing them out
+        #[arg(long)]
+        direct: bool,
+    },
+    /// Poll a remote for changes and report them (for mirroring daemons)
+    Watch {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Time between polls (e.g. 30s, 5m)
+        #[arg(long, default_value = "30s", value_parser = subcommands::watch::parse_interval)]
+        interval: std::time::Duration,
+        /// Shell command to run on each change instead of printing a JSON event
+        #[arg(long, value_name = "COMMAND")]
+        exec: Option<String>,
+    },
+    /// Serve a remote read-only over the git daemon protocol (git://) from a local mirror
+    Serve {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Address and port for `git daemon` to listen on
+        #[arg(long, default_value = "127.0.0.1:9418")]
+        listen: std::net::SocketAddr,
+        /// Bare mirror to serve (default: mirrors/<object ID>.git in the cache directory)
+        #[arg(long)]
+        mirror: Option<std::path::PathBuf>,
+        /// Time between checks for remote changes (e.g. 30s, 5m)
+        #[arg(long, default_value = "30s", value_parser = subcommands::watch::parse_interval)]
+        interval: std::time::Duration,
+    },
+    /// Keep a remote's blobs alive, extending those that are about to expire
+    AutoRenew {
+        #[command(flatten)]
+        remote: RemoteArg,
+        /// Time between renewal passes (e.g. 1h, 30m)
+        #[arg(long, default_value = "1h", value_parser = subcommands::watch::parse_interval)]
+        interval: std::time::Duration,
+        /// Run a single renewal pass and exit
+        #[arg(long)]
+        once: bool,
+    },
+    /// Show the wallet, address and balances pushes use, and whether they may push to a remote
+    Whoami {
+        /// Remote to check the address against (e.g. origin, 0x1234... or walrus::0x1234...)
+        #[arg(long)]
+        remote: Option<String>,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the remotes the active address owns, with their ref counts and earliest blob expiry
+    ListRepos {
+        /// Only list RemoteStates published by this package (default: any package)
+        #[arg(long)]
+        package: Option<String>,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Keep remotes open between git operations, serving helper sessions over a unix socket
+    Daemon {
+        /// Socket to listen on (default: $WALRUS_REMOTE_DAEMON_SOCKET, else in the runtime directory)
+        #[arg(long)]
+        socket: Option<std::path::PathBuf>,
+    },
 }
 
 /// Wrapper enum for different storage backends
@@ -85,6 +408,17 @@ impl storage::ImmutableStore for Storage {
         }
     }
 
+    fn write_objects_with_epochs(
+        &self,
+        contents: &[&[u8]],
+        epochs: Option<u32>,
+    ) -> Result<Vec<String>> {
+        match self {
+            Storage::Filesystem(s) => s.write_objects_with_epochs(contents, epochs),
+            Storage::Walrus(s) => s.write_objects_with_epochs(contents, epochs),
+        }
+    }
+
     fn read_object(&self, id: &str) -> Result<Vec<u8>> {
         match self {
             Storage::Filesystem(s) => s.read_object(id),
@@ -99,6 +433,13 @@ impl storage::ImmutableStore for Storage {
         }
     }
 
+    fn read_git_objects(&self, objects: &[(&str, &str)]) -> Result<Vec<Vec<u8>>> {
+        match self {
+            Storage::Filesystem(s) => s.read_git_objects(objects),
+            Storage::Walrus(s) => s.read_git_objects(objects),
+        }
+    }
+
     fn delete_object(&self, id: &str) -> Result<()> {
         match self {
             Storage::Filesystem(s) => s.delete_object(id),
@@ -112,6 +453,20 @@ impl storage::ImmutableStore for Storage {
             Storage::Walrus(s) => s.object_exists(id),
         }
     }
+
+    fn read_source(&self, id: &str) -> Option<storage::ReadSource> {
+        match self {
+            Storage::Filesystem(s) => s.read_source(id),
+            Storage::Walrus(s) => s.read_source(id),
+        }
+    }
+
+    fn cache_stored_objects(&self, objects: &[(&[u8], &str)]) -> Result<()> {
+        match self {
+            Storage::Filesystem(s) => s.cache_stored_objects(objects),
+            Storage::Walrus(s) => s.cache_stored_objects(objects),
+        }
+    }
 }
 
 impl storage::MutableState for Storage {
@@ -147,6 +502,62 @@ impl StorageBackend for Storage {
             Storage::Walrus(s) => s.initialize(),
         }
     }
+
+    fn pusher(&self) -> Option<String> {
+        match self {
+            Storage::Filesystem(s) => s.pusher(),
+            Storage::Walrus(s) => s.pusher(),
+        }
+    }
+
+    fn retention_for(&self, refname: &str) -> Option<config::Retention> {
+        match self {
+            Storage::Filesystem(s) => s.retention_for(refname),
+            Storage::Walrus(s) => s.retention_for(refname),
+        }
+    }
+
+    fn policy(&self) -> Result<sui::RemotePolicy> {
+        match self {
+            Storage::Filesystem(s) => s.policy(),
+            Storage::Walrus(s) => s.policy(),
+        }
+    }
+
+    fn list_banner(&self, state: &storage::State) -> Option<String> {
+        match self {
+            Storage::Filesystem(s) => s.list_banner(state),
+            Storage::Walrus(s) => s.list_banner(state),
+        }
+    }
+
+    fn prefetch_strategy(&self) -> pack::PrefetchStrategy {
+        match self {
+            Storage::Filesystem(s) => s.prefetch_strategy(),
+            Storage::Walrus(s) => s.prefetch_strategy(),
+        }
+    }
+
+    fn begin_session(&self) {
+        match self {
+            Storage::Filesystem(s) => s.begin_session(),
+            Storage::Walrus(s) => s.begin_session(),
+        }
+    }
+
+    fn atomic_ref_updates(&self) -> bool {
+        match self {
+            Storage::Filesystem(s) => s.atomic_ref_updates(),
+            Storage::Walrus(s) => s.atomic_ref_updates(),
+        }
+    }
+
+    fn repair_refs(&self, repairs: &[storage::RefRepair]) -> Result<()> {
+        match self {
+            Storage::Filesystem(s) => s.repair_refs(repairs),
+            Storage::Walrus(s) => s.repair_refs(repairs),
+        }
+    }
 }
 
 fn main() -> Result<()> {
@@ -167,62 +578,284 @@ fn main() -> Result<()> {
             package_id,
             shared,
             allow,
-        }) => handle_init(package_id, shared, allow),
+            refs_layout,
+        }) => handle_init(package_id, shared, allow, refs_layout),
         Some(Command::Config { edit }) => handle_config(edit),
-        None => {
-            // Git passes remote name and URL as positional arguments
-            let remote_url = cli
-                .remote_url
-                .ok_or_else(|| anyhow::anyhow!("Missing remote URL"))?;
-
-            // Parse the URL - format is walrus::<path or object-id>
-            let remote_type = parse_remote_url(&remote_url)?;
-
-            // Initialize storage backend based on type
-            let storage = match remote_type {
-                RemoteType::Filesystem(path) => {
-                    tracing::info!("Using filesystem storage: {:?}", path);
-                    let fs_storage = FilesystemStorage::new(path)?;
-                    Storage::Filesystem(fs_storage)
+        Some(Command::Migrate {
+            source,
+            from,
+            to_sui,
+            to_filesystem,
+            to,
+            shared,
+            allow,
+            refs_layout,
+        }) => {
+            let target = match (to_sui, to_filesystem, to) {
+                (Some(package_id), _, _) => subcommands::migrate::MigrationTarget::Sui {
+                    package_id,
+                    shared,
+                    allowlist: allow,
+                    refs_layout,
+                },
+                (None, Some(path), _) => subcommands::migrate::MigrationTarget::Filesystem(path),
+                (None, None, Some(to)) => {
+                    subcommands::migrate::MigrationTarget::Remote(Remote::resolve(&to)?)
                 }
-                RemoteType::Sui(object_id) => {
-                    tracing::info!("Using Walrus+Sui storage: {}", object_id);
-                    let walrus_storage = WalrusStorage::new(object_id)?;
-                    Storage::Walrus(Box::new(walrus_storage))
+                (None, None, None) => {
+                    unreachable!("clap requires --to-sui, --to-filesystem or --to")
                 }
             };
+            let source = source.or(from).expect("clap requires a source or --from");
+            subcommands::migrate::handle(&Remote::resolve(&source)?, target)
+        }
+        Some(Command::Doctor { fix }) => subcommands::doctor::handle(fix),
+        Some(Command::MigrateLayout { remote }) => {
+            subcommands::migrate_layout::handle(&remote.resolve()?)
+        }
+        Some(Command::Policy {
+            remote,
+            max_refs,
+            max_objects,
+        }) => subcommands::policy::handle(&remote.resolve()?, max_refs, max_objects),
+        Some(Command::Reclaim { remote }) => subcommands::reclaim::handle(&remote.resolve()?),
+        Some(Command::CompactRefs { remote }) => {
+            subcommands::compact_refs::handle(&remote.resolve()?)
+        }
+        Some(Command::Repack { remote }) => subcommands::repack::handle(&remote.resolve()?),
+        Some(Command::Compact { remote }) => subcommands::compact::handle(&remote.resolve()?),
+        Some(Command::Unlock { remote, force }) => {
+            subcommands::unlock::handle(&remote.resolve()?, force)
+        }
+        Some(Command::Fsck { remote, full, fix }) => {
+            subcommands::fsck::handle(&remote.resolve()?, full, fix)
+        }
+        Some(Command::DedupReport {
+            remote,
+            sample,
+            json,
+        }) => subcommands::dedup_report::handle(&remote.resolve()?, sample, json),
+        Some(Command::Describe { remote }) => subcommands::describe::handle(&remote.resolve()?),
+        Some(Command::SetDescription {
+            remote,
+            description,
+            default_branch,
+        }) => subcommands::set_description::handle(
+            &Remote::resolve(&remote)?,
+            description,
+            default_branch,
+        ),
+        Some(Command::Graph { remote, format }) => {
+            subcommands::graph::handle(&remote.resolve()?, format)
+        }
+        Some(Command::Cat { remote, spec }) => {
+            subcommands::cat::handle(&Remote::resolve(&remote)?, &spec)
+        }
+        Some(Command::LogPath {
+            remote,
+            path,
+            ref_name,
+        }) => subcommands::log_path::handle(&Remote::resolve(&remote)?, &path, &ref_name),
+        Some(Command::Refs {
+            remote,
+            verbose,
+            json,
+        }) => subcommands::refs::handle(&remote.resolve()?, verbose, json),
+        Some(Command::Symref {
+            remote,
+            name,
+            target,
+            delete,
+        }) => subcommands::symref::handle(
+            &Remote::resolve(&remote)?,
+            &name,
+            target.as_deref(),
+            delete,
+        ),
+        Some(Command::Status {
+            remote,
+            fail_if_expiring_within,
+            json,
+        }) => {
+            let code =
+                subcommands::status::handle(&remote.resolve()?, fail_if_expiring_within, json)?;
+            std::process::exit(code)
+        }
+        Some(Command::DiffState {
+            remote,
+            against,
+            at,
+        }) => {
+            let baseline = match (against, at) {
+                (Some(against), _) => subcommands::diff_state::Baseline::ObjectsMap(against),
+                (None, Some(at)) => subcommands::diff_state::Baseline::Transaction(at),
+                (None, None) => unreachable!("clap requires --against or --at"),
+            };
+            subcommands::diff_state::handle(&remote.resolve()?, &baseline)
+        }
+        Some(Command::Archive {
+            remote,
+            ref_name,
+            format,
+            out,
+            prefix,
+        }) => subcommands::archive::handle(
+            &Remote::resolve(&remote)?,
+            &ref_name,
+            format,
+            out.as_deref(),
+            &prefix,
+        ),
+        Some(Command::PublishSite {
+            remote,
+            ref_name,
+            out,
+            index,
+            direct,
+        }) => subcommands::publish_site::handle(&remote.resolve()?, &ref_name, &out, index, direct),
+        Some(Command::Watch {
+            remote,
+            interval,
+            exec,
+        }) => subcommands::watch::handle(&remote.resolve()?, interval, exec),
+        Some(Command::Serve {
+            remote,
+            listen,
+            mirror,
+            interval,
+        }) => subcommands::serve::handle(&remote.resolve()?, listen, mirror, interval),
+        Some(Command::AutoRenew {
+            remote,
+            interval,
+            once,
+        }) => subcommands::auto_renew::handle(&remote.resolve()?, interval, once),
+        Some(Command::Whoami { remote, json }) => {
+            let remote = remote.as_deref().map(Remote::resolve).transpose()?;
+            subcommands::whoami::handle(remote.as_ref(), json)
+        }
+        Some(Command::ListRepos { package, json }) => {
+            subcommands::list_repos::handle(package.as_deref(), json)
+        }
+        Some(Command::Daemon { socket }) => {
+            subcommands::daemon::handle(socket, open_storage, session_options)
+        }
+        None => {
+            // Git passes remote name and URL as positional arguments
+            let program = std::env::args_os().next().unwrap_or_default();
+            let (remote_name, remote_url) =
+                resolve_helper_args(&program.to_string_lossy(), cli.remote_name, cli.remote_url)?;
+
+            // Tag every log line with the remote this invocation serves
+            let _span = tracing::info_span!("remote", name = %remote_name).entered();
 
-            storage.initialize()?;
+            // A running daemon serves the session with clients it already has open
+            if let Some(socket) = subcommands::daemon::client_socket() {
+                if subcommands::daemon::forward(&socket, &remote_name, &remote_url)? {
+                    return Ok(());
+                }
+            }
+
+            let options = session_options(&remote_name, &remote_url)?;
+            if options.read_only {
+                // Without the export capability git refuses pushes before sending any command
+                tracing::info!("this remote is configured read-only; pushes are disabled");
+            }
+            let storage = open_storage(&remote_name, &remote_url)?;
 
             // Start protocol handler
-            protocol::handle_commands(storage)?;
+            protocol::handle_commands(storage, &remote_name, &options)?;
 
             Ok(())
         }
     }
 }
 
-fn parse_remote_url(url: &str) -> Result<RemoteType> {
-    tracing::debug!("Parsing URL: '{}'", url);
-
-    // Git strips the protocol prefix, so we might receive either:
-    // - "walrus::/path/to/storage" (user-specified format)
-    // - "/path/to/storage" (Git has already stripped "walrus::")
-    // - "walrus::0x1234..." (Sui object ID)
-    // - "0x1234..." (Git has already stripped "walrus::")
-    let path_str = url.strip_prefix("walrus::").unwrap_or(url);
+/// Namespace, read-only mode, listing settings, hooks and push signing policy for a helper session on `url`
+fn session_options(remote_name: &str, url: &str) -> Result<SessionOptions> {
+    let remote_url = parse_remote_url(url)?;
+    let config = match remote_url.remote_type {
+        RemoteType::Sui(_) => Some(
+            config::WalrusRemoteConfig::load_for_remote(Some(remote_name))
+                .context("Failed to load configuration")?,
+        ),
+        // Filesystem remotes don't use the config file
+        RemoteType::Filesystem(_) => None,
+    };
+    let (
+        read_only,
+        mut advertise_ref_patterns,
+        tag_only_head,
+        on_mapping_conflict,
+        decompress_threads,
+    ) = match &config {
+        Some(config) => (
+            config.read_only,
+            config.advertise_ref_patterns.clone(),
+            config.tag_only_head,
+            config.on_mapping_conflict,
+            config.decompress_threads,
+        ),
+        None => (
+            config::read_only_from_env()?.unwrap_or(false),
+            config::advertise_ref_patterns_from_env().unwrap_or_default(),
+            config::tag_only_head_from_env()?.unwrap_or_default(),
+            config::on_mapping_conflict_from_env()?.unwrap_or_default(),
+            config::decompress_threads_from_env()?,
+        ),
+    };
+    if remote_url.options.all_refs {
+        advertise_ref_patterns.clear();
+    }
+    // A state pinned with `@<digest>` is history, which pushes can't change
+    let read_only = read_only || remote_url.options.pinned_transaction.is_some();
+
+    Ok(SessionOptions {
+        namespace: RefNamespace::resolve(remote_url.options.namespace.as_deref())?,
+        read_only,
+        advertise_ref_patterns,
+        tag_only_head,
+        hooks: Hooks::resolve(config.as_ref(), remote_name, url)?,
+        push_certs: PushCertPolicy::resolve(config.as_ref())?,
+        on_mapping_conflict,
+        decompress_threads: decompress_threads.unwrap_or(0),
+        url: url.to_string(),
+        marks_file: session_env::var_os("GIT_DIR")
+            .map(|git_dir| session_env::absolute(std::path::Path::new(&git_dir)))
+            .transpose()?
+            .map(|git_dir| {
+                git_dir
+                    .join("walrus")
+                    .join(commands::capabilities::private_name(remote_name))
+                    .join("marks")
+            }),
+    })
+}
 
-    // Try to parse as Sui object ID (0x prefix + hex chars)
-    if path_str.starts_with("0x") && path_str.len() > 2 {
-        // Validate hex characters after 0x
-        let hex_part = &path_str[2..];
-        if hex_part.chars().all(|c| c.is_ascii_hexdigit()) {
-            return Ok(RemoteType::Sui(path_str.to_string()));
+/// Open and initialize the storage backend for a remote URL
+/// (format is walrus::<path or object-id>[?options])
+fn open_storage(remote_name: &str, url: &str) -> Result<Storage> {
+    let remote_url = parse_remote_url(url)?;
+
+    // Initialize storage backend based on type
+    let storage = match remote_url.remote_type {
+        RemoteType::Filesystem(path) => {
+            tracing::info!("Using filesystem storage: {:?}", path);
+            let fs_storage = FilesystemStorage::new(path)?;
+            Storage::Filesystem(fs_storage)
         }
-    }
+        RemoteType::Sui(object_id) => {
+            tracing::info!("Using Walrus+Sui storage: {}", object_id);
+            let mut config = config::WalrusRemoteConfig::load_for_remote(Some(remote_name))
+                .context("Failed to load configuration")?;
+            remote_url.options.apply(&mut config);
+            let walrus_storage = WalrusStorage::new(object_id, remote_name.to_string(), config)?;
+            Storage::Walrus(Box::new(walrus_storage))
+        }
+    };
+
+    storage.initialize()?;
 
-    // Treat as filesystem path
-    Ok(RemoteType::Filesystem(PathBuf::from(path_str)))
+    Ok(storage)
 }
 
 fn handle_deploy() -> Result<()> {
@@ -238,6 +871,11 @@ fn handle_deploy() -> Result<()> {
     println!("Configuration:");
     println!("  Wallet: {:?}\n", config.sui_wallet_path);
 
+    sui::ensure_spending_allowed(
+        sui::SuiNetwork::from_wallet(&config.sui_wallet_path)?,
+        config.allow_mainnet,
+    )?;
+
     // Get the move package directory
     let move_package_dir = std::env::current_dir()?.join("move").join("walrus_remote");
 
@@ -251,23 +889,18 @@ fn handle_deploy() -> Result<()> {
 
     // Step 1: Build the Move package
     println!("Step 1/2: Building Move package...");
-    let build_output = std::process::Command::new("sui")
+    CommandRunner::new("sui")
         .arg("move")
         .arg("build")
         .current_dir(&move_package_dir)
-        .output()
-        .context("Failed to execute 'sui move build'")?;
-
-    if !build_output.status.success() {
-        let stderr = String::from_utf8_lossy(&build_output.stderr);
-        anyhow::bail!("Move build failed:\n{}", stderr);
-    }
+        .run()
+        .context("Move build failed")?;
 
     println!("✓ Move package built successfully\n");
 
     // Step 2: Publish the package
     println!("Step 2/2: Publishing to Sui...");
-    let publish_output = std::process::Command::new("sui")
+    let publish_output = CommandRunner::new("sui")
         .arg("client")
         .arg("--client.config")
         .arg(&config.sui_wallet_path)
@@ -276,13 +909,8 @@ fn handle_deploy() -> Result<()> {
         .arg("--gas-budget")
         .arg("500000000") // 0.5 SUI
         .current_dir(&move_package_dir)
-        .output()
-        .context("Failed to execute 'sui client publish'")?;
-
-    if !publish_output.status.success() {
-        let stderr = String::from_utf8_lossy(&publish_output.stderr);
-        anyhow::bail!("Publish failed:\n{}", stderr);
-    }
+        .run()
+        .context("Publish failed")?;
 
     // Parse JSON output to extract package ID
     let stdout = String::from_utf8_lossy(&publish_output.stdout);
@@ -323,7 +951,30 @@ fn handle_deploy() -> Result<()> {
     Ok(())
 }
 
-fn handle_init(package_id: String, shared: bool, allowlist: Vec<String>) -> Result<()> {
+fn handle_init(
+    package_id: String,
+    shared: bool,
+    allowlist: Vec<String>,
+    refs_layout: sui::RefsLayout,
+) -> Result<()> {
+    let object_id = create_remote(package_id, shared, allowlist, refs_layout)?;
+
+    // Print instructions
+    println!("\n✓ Success! Your git remote is ready.");
+    println!("\nTo use this remote:");
+    println!("  git remote add storage walrus::{}", object_id);
+    println!("  git push storage main");
+
+    Ok(())
+}
+
+/// Create a RemoteState object (shared with `allowlist` if `shared`) and return its ID
+fn create_remote(
+    package_id: String,
+    shared: bool,
+    allowlist: Vec<String>,
+    refs_layout: sui::RefsLayout,
+) -> Result<String> {
     // Load configuration for RPC URL and wallet path
     let config = config::WalrusRemoteConfig::load()?;
 
@@ -331,6 +982,7 @@ fn handle_init(package_id: String, shared: bool, allowlist: Vec<String>) -> Resu
         package_id,
         shared,
         ?allowlist,
+        %refs_layout,
         sui_wallet_path = ?config.sui_wallet_path.display(),
         "creating new remote..."
     );
@@ -341,11 +993,18 @@ fn handle_init(package_id: String, shared: bool, allowlist: Vec<String>) -> Resu
     runtime.block_on(async {
         // Create Sui client
         println!("\nInitializing Sui client...");
-        let sui_client = sui::SuiClient::new_for_init(package_id, config.sui_wallet_path).await?;
+        let sui_client = sui::SuiClient::new_for_init(
+            package_id,
+            config.sui_wallet_path.clone(),
+            config.network_timeouts(),
+        )
+        .await?
+        .with_gas_reserve(config.gas_reserve_mist);
+        sui::ensure_spending_allowed(sui_client.network(), config.allow_mainnet)?;
 
         // Create RemoteState object
         println!("Creating RemoteState object...");
-        let object_id = sui_client.create_remote().await?;
+        let object_id = sui_client.create_remote(refs_layout).await?;
         println!("✓ RemoteState created: {}", object_id);
 
         // Share if requested
@@ -357,13 +1016,7 @@ fn handle_init(package_id: String, shared: bool, allowlist: Vec<String>) -> Resu
             println!("✓ RemoteState is now shared");
         }
 
-        // Print instructions
-        println!("\n✓ Success! Your git remote is ready.");
-        println!("\nTo use this remote:");
-        println!("  git remote add storage walrus::{}", object_id);
-        println!("  git push storage main");
-
-        Ok(())
+        Ok(object_id)
     })
 }
 
@@ -376,6 +1029,7 @@ fn handle_config(edit: bool) -> Result<()> {
 
         tracing::info!("Opening config file in {}: {:?}", editor, config_path);
 
+        // The editor is interactive and must inherit the terminal, so it bypasses CommandRunner
         let status = std::process::Command::new(&editor)
             .arg(&config_path)
             .status()
@@ -410,16 +1064,39 @@ fn handle_config(edit: bool) -> Result<()> {
         println!("Current configuration:");
         println!("  sui_wallet_path: {:?}", config.sui_wallet_path);
         println!("  walrus_config_path: {:?}", config.walrus_config_path);
+        println!("  walrus_binary: {:?}", config.walrus_binary);
         println!("  cache_dir: {:?}", config.cache_dir);
         println!("  default_epochs: {}", config.default_epochs);
         println!(
             "  expiration_warning_threshold: {}",
             config.expiration_warning_threshold
         );
+        println!("  allow_mainnet: {}", config.allow_mainnet);
+        println!("  blob_persistence: {}", config.blob_persistence);
+        println!("  walrus_encoding: {:?}", config.walrus_encoding);
+        println!("  skip_preflight: {}", config.skip_preflight);
+        println!("  wal_coin_type: {:?}", config.wal_coin_type);
+        println!("  gas_reserve_mist: {}", config.gas_reserve_mist);
+        println!("  read_only: {}", config.read_only);
+        println!("  max_objects_map_bytes: {}", config.max_objects_map_bytes);
+        println!("  max_blob_size: {:?}", config.max_blob_size);
+        println!("  max_blob_cache_bytes: {}", config.max_blob_cache_bytes);
+        println!("  cache_max_entries: {:?}", config.cache_max_entries);
+        let concurrency = config.concurrency();
+        println!("  upload_concurrency: {}", concurrency.upload);
+        println!("  download_concurrency: {}", concurrency.download);
+        println!("  prefetch_strategy: {}", config.prefetch_strategy);
+        println!(
+            "  advertise_ref_patterns: {:?}",
+            config.advertise_ref_patterns
+        );
+        println!("  proxy: {:?}", config.proxy);
+        println!("  no_proxy: {:?}", config.no_proxy);
 
         println!("\nEnvironment variable overrides:");
         println!("  SUI_WALLET: {:?}", std::env::var("SUI_WALLET").ok());
         println!("  WALRUS_CONFIG: {:?}", std::env::var("WALRUS_CONFIG").ok());
+        println!("  WALRUS_BINARY: {:?}", std::env::var("WALRUS_BINARY").ok());
         println!(
             "  WALRUS_REMOTE_CACHE_DIR: {:?}",
             std::env::var("WALRUS_REMOTE_CACHE_DIR").ok()
@@ -432,6 +1109,62 @@ fn handle_config(edit: bool) -> Result<()> {
             "  WALRUS_EXPIRATION_WARNING_THRESHOLD: {:?}",
             std::env::var("WALRUS_EXPIRATION_WARNING_THRESHOLD").ok()
         );
+        println!(
+            "  WALRUS_REMOTE_ALLOW_MAINNET: {:?}",
+            std::env::var("WALRUS_REMOTE_ALLOW_MAINNET").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_ENCODING: {:?}",
+            std::env::var("WALRUS_REMOTE_ENCODING").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_READ_ONLY: {:?}",
+            std::env::var("WALRUS_REMOTE_READ_ONLY").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_SKIP_PREFLIGHT: {:?}",
+            std::env::var("WALRUS_REMOTE_SKIP_PREFLIGHT").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_GAS_RESERVE_MIST: {:?}",
+            std::env::var("WALRUS_REMOTE_GAS_RESERVE_MIST").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_MAX_OBJECTS_MAP_BYTES: {:?}",
+            std::env::var("WALRUS_REMOTE_MAX_OBJECTS_MAP_BYTES").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_MAX_BLOB_SIZE: {:?}",
+            std::env::var("WALRUS_REMOTE_MAX_BLOB_SIZE").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_MAX_BLOB_CACHE_BYTES: {:?}",
+            std::env::var("WALRUS_REMOTE_MAX_BLOB_CACHE_BYTES").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_CACHE_MAX_ENTRIES: {:?}",
+            std::env::var("WALRUS_REMOTE_CACHE_MAX_ENTRIES").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_ADVERTISE_REF_PATTERNS: {:?}",
+            std::env::var("WALRUS_REMOTE_ADVERTISE_REF_PATTERNS").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_JOBS: {:?}",
+            std::env::var("WALRUS_REMOTE_JOBS").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_UPLOAD_CONCURRENCY: {:?}",
+            std::env::var("WALRUS_REMOTE_UPLOAD_CONCURRENCY").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_DOWNLOAD_CONCURRENCY: {:?}",
+            std::env::var("WALRUS_REMOTE_DOWNLOAD_CONCURRENCY").ok()
+        );
+        println!(
+            "  WALRUS_REMOTE_PREFETCH_STRATEGY: {:?}",
+            std::env::var("WALRUS_REMOTE_PREFETCH_STRATEGY").ok()
+        );
 
         Ok(())
     }
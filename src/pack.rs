@@ -4,9 +4,15 @@
 //! replacing the fast-import/fast-export approach to preserve GPG signatures
 //! and maintain exact SHA-1 hashes.
 
+pub mod archive;
+pub mod checkout;
+pub mod diagnose;
+pub mod graph;
+pub mod history;
 pub mod objects;
 pub mod receive;
 pub mod send;
+pub mod shallow;
 
-pub use receive::receive_pack;
-pub use send::send_pack;
+pub use receive::{read_pack, receive_pack_with_epochs};
+pub use send::{send_pack, send_pack_with_deepen, send_pack_with_haves, PrefetchStrategy};
@@ -0,0 +1,696 @@
+//! Tar and zip archives of a stored tree, laid out like `git archive` output
+
+use std::{fmt, io::Write, path::Path, str::FromStr};
+
+use anyhow::{Context, Result};
+use gix_object::Kind;
+
+use super::{
+    checkout::{list_tree, read_objects, resolve_tree},
+    objects::{ObjectId, MODE_GITLINK, MODE_SYMLINK, MODE_TREE},
+};
+use crate::storage::{State, StorageBackend};
+
+/// Tar records are 512 bytes, written in blocks of 20 records (as `git archive` does)
+const RECORD_SIZE: usize = 512;
+const BLOCK_SIZE: usize = RECORD_SIZE * 20;
+/// `git archive`'s default `tar.umask`
+const UMASK: u32 = 0o002;
+/// Largest size and mtime the octal ustar header fields hold
+const USTAR_MAX: u64 = 0o77777777777;
+
+/// File type bits of a mode
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+
+/// Output format of an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Format implied by an output file name, as `git archive -o` infers it
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        [".tar.gz", ".tgz", ".tar", ".zip"]
+            .into_iter()
+            .find(|ext| name.ends_with(ext))
+            .and_then(|ext| ext.trim_start_matches('.').parse().ok())
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tar" => Ok(ArchiveFormat::Tar),
+            "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+            "zip" => Ok(ArchiveFormat::Zip),
+            other => anyhow::bail!(
+                "invalid archive format {:?} (expected 'tar', 'tar.gz' or 'zip')",
+                other
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveFormat::Tar => write!(f, "tar"),
+            ArchiveFormat::TarGz => write!(f, "tar.gz"),
+            ArchiveFormat::Zip => write!(f, "zip"),
+        }
+    }
+}
+
+/// Write an archive of the tree `commitish` points at, with every path under `prefix`
+///
+/// Entries, modes and timestamps follow `git archive` with its default `tar.umask`, so tar
+/// output is byte-for-byte what `git archive --format=tar --prefix=<prefix>` produces.
+/// `export-ignore` and `export-subst` attributes are not applied. Returns the number of
+/// entries written.
+pub fn write_archive(
+    storage: &impl StorageBackend,
+    state: &State,
+    commitish: &str,
+    format: ArchiveFormat,
+    prefix: &str,
+    out: impl Write,
+) -> Result<usize> {
+    let (commit, time) = peel_to_commit(storage, state, commitish)?;
+    let root = resolve_tree(storage, state, commitish)?;
+    let entries = list_tree(storage, state, commitish)?;
+
+    // Read every blob in one batch
+    let blob_ids = entries
+        .iter()
+        .filter(|entry| !matches!(entry.mode, MODE_TREE | MODE_GITLINK))
+        .map(|entry| entry.id.as_str());
+    let mut blobs = read_objects(storage, state, blob_ids)?.into_iter();
+
+    let mut archive: Box<dyn ArchiveWriter> = match format {
+        ArchiveFormat::Tar => Box::new(TarWriter::new(out, time, commit.as_deref())?),
+        ArchiveFormat::TarGz => Box::new(TarWriter::new(
+            flate2::write::GzEncoder::new(out, flate2::Compression::default()),
+            time,
+            commit.as_deref(),
+        )?),
+        ArchiveFormat::Zip => Box::new(ZipWriter::new(out, time, commit.as_deref())),
+    };
+
+    let mut written = 0;
+    if let Some(dir) = prefix_dir(prefix) {
+        archive.add(dir, &root, MODE_TREE | 0o777, &[])?;
+        written += 1;
+    }
+    for entry in &entries {
+        let mut path = prefix.as_bytes().to_vec();
+        path.extend_from_slice(&path_bytes(&entry.path));
+
+        let data = match entry.mode {
+            MODE_TREE | MODE_GITLINK => {
+                path.push(b'/');
+                Vec::new()
+            }
+            _ => {
+                let blob = blobs.next().context("Missing blob for archived file")?;
+                if blob.kind != Kind::Blob {
+                    anyhow::bail!(
+                        "{} points at a {}, not a blob",
+                        entry.path.display(),
+                        blob.kind
+                    );
+                }
+                blob.data
+            }
+        };
+        archive
+            .add(&path, &entry.id, entry.mode, &data)
+            .with_context(|| format!("Failed to archive {}", entry.path.display()))?;
+        written += 1;
+    }
+
+    archive.finish()?;
+    Ok(written)
+}
+
+/// Commit `commitish` peels to (if any) and its committer time, or now for a bare tree (the
+/// archive's timestamp)
+pub fn peel_to_commit(
+    storage: &impl StorageBackend,
+    state: &State,
+    commitish: &str,
+) -> Result<(Option<ObjectId>, u64)> {
+    let mut id = commitish.to_string();
+    for _ in 0..16 {
+        let obj = read_objects(storage, state, std::iter::once(id.as_str()))?
+            .pop()
+            .context("Missing object")?;
+        match obj.kind {
+            Kind::Tag => id = obj.peel_target().context("Tag has no target")?,
+            Kind::Commit => {
+                let time = String::from_utf8_lossy(&obj.data)
+                    .lines()
+                    .take_while(|line| !line.is_empty())
+                    .find_map(|line| line.strip_prefix("committer "))
+                    // `Name <email> <seconds> <offset>`
+                    .and_then(|ident| ident.rsplit(' ').nth(1)?.parse().ok())
+                    .unwrap_or_default();
+                return Ok((Some(id), time));
+            }
+            _ => {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_secs());
+                return Ok((None, now));
+            }
+        }
+    }
+    anyhow::bail!("Too many levels of tags resolving {}", commitish)
+}
+
+/// The directory entry `git archive` writes for a prefix ending in `/` (repeated slashes
+/// collapsed)
+fn prefix_dir(prefix: &str) -> Option<&[u8]> {
+    let prefix = prefix.as_bytes();
+    if !prefix.ends_with(b"/") {
+        return None;
+    }
+    let mut len = prefix.len();
+    while len > 1 && prefix[len - 2] == b'/' {
+        len -= 1;
+    }
+    Some(&prefix[..len])
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    let path = path.to_string_lossy().replace('\\', "/");
+    path.into_bytes()
+}
+
+/// Sink for archive entries
+trait ArchiveWriter {
+    /// Add an entry; `path` ends in `/` for directories, `data` is a symlink's target
+    fn add(&mut self, path: &[u8], id: &str, mode: u32, data: &[u8]) -> Result<()>;
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// ustar writer matching `git archive`'s archive-tar.c
+struct TarWriter<W: Write> {
+    out: W,
+    /// Bytes written into the current block
+    offset: usize,
+    mtime: u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    /// Start an archive, with a pax global header naming `commit`
+    fn new(out: W, mtime: u64, commit: Option<&str>) -> Result<Self> {
+        let mut writer = Self {
+            out,
+            offset: 0,
+            mtime,
+        };
+
+        let mut ext = Vec::new();
+        if let Some(commit) = commit {
+            append_ext_header(&mut ext, "comment", commit.as_bytes());
+        }
+        if writer.mtime > USTAR_MAX {
+            append_ext_header(&mut ext, "mtime", writer.mtime.to_string().as_bytes());
+            writer.mtime = USTAR_MAX;
+        }
+        if !ext.is_empty() {
+            let header = writer.header(b"pax_global_header", b"", b"", b'g', 0o100666, ext.len());
+            writer.write_blocked(&header)?;
+            writer.write_blocked(&ext)?;
+        }
+        Ok(writer)
+    }
+
+    /// Write `data` padded to a whole number of records
+    fn write_blocked(&mut self, data: &[u8]) -> Result<()> {
+        self.out.write_all(data)?;
+        let tail = data.len() % RECORD_SIZE;
+        if tail != 0 {
+            self.out.write_all(&[0; RECORD_SIZE][tail..])?;
+        }
+        self.offset = (self.offset + data.len().div_ceil(RECORD_SIZE) * RECORD_SIZE) % BLOCK_SIZE;
+        Ok(())
+    }
+
+    /// A ustar header; `size` is only recorded for regular files
+    fn header(
+        &self,
+        name: &[u8],
+        prefix: &[u8],
+        linkname: &[u8],
+        typeflag: u8,
+        mode: u32,
+        size: usize,
+    ) -> [u8; RECORD_SIZE] {
+        let mut header = [0u8; RECORD_SIZE];
+        header[..name.len()].copy_from_slice(name);
+        put_octal(&mut header[100..108], u64::from(mode & 0o7777));
+        put_octal(&mut header[108..116], 0); // uid
+        put_octal(&mut header[116..124], 0); // gid
+        let size = if mode & S_IFMT == S_IFREG { size } else { 0 };
+        put_octal(&mut header[124..136], size as u64);
+        put_octal(&mut header[136..148], self.mtime);
+        header[156] = typeflag;
+        header[157..157 + linkname.len()].copy_from_slice(linkname);
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[265..269].copy_from_slice(b"root"); // uname
+        header[297..301].copy_from_slice(b"root"); // gname
+        put_octal(&mut header[329..337], 0); // devmajor
+        put_octal(&mut header[337..345], 0); // devminor
+        header[345..345 + prefix.len()].copy_from_slice(prefix);
+
+        // The checksum is computed with its own field filled with spaces
+        header[148..156].fill(b' ');
+        let checksum: u64 = header.iter().map(|&b| u64::from(b)).sum();
+        put_octal(&mut header[148..156], checksum);
+        header
+    }
+}
+
+impl<W: Write> ArchiveWriter for TarWriter<W> {
+    fn add(&mut self, path: &[u8], id: &str, mode: u32, data: &[u8]) -> Result<()> {
+        let (typeflag, mode) = match mode & S_IFMT {
+            MODE_TREE | MODE_GITLINK => (b'5', (MODE_TREE | mode | 0o777) & !UMASK),
+            MODE_SYMLINK => (b'2', mode | 0o777),
+            S_IFREG => {
+                let perm = if mode & 0o100 != 0 { 0o777 } else { 0o666 };
+                (b'0', (mode | perm) & !UMASK)
+            }
+            _ => anyhow::bail!("Unsupported mode {:o}", mode),
+        };
+
+        let mut ext = Vec::new();
+        let data_name;
+        let (name, prefix) = if path.len() > 100 {
+            let split = path_prefix_len(path, 155);
+            let rest = path.len() - split - 1;
+            if split > 0 && rest <= 100 {
+                (&path[split + 1..], &path[..split])
+            } else {
+                data_name = format!("{}.data", id);
+                append_ext_header(&mut ext, "path", path);
+                (data_name.as_bytes(), &b""[..])
+            }
+        } else {
+            (path, &b""[..])
+        };
+
+        let see_header;
+        let linkname = if typeflag != b'2' {
+            &b""[..]
+        } else if data.len() > 100 {
+            see_header = format!("see {}.paxheader", id);
+            append_ext_header(&mut ext, "linkpath", data);
+            see_header.as_bytes()
+        } else {
+            data
+        };
+
+        let mut size = data.len();
+        if typeflag == b'0' && size as u64 > USTAR_MAX {
+            append_ext_header(&mut ext, "size", size.to_string().as_bytes());
+            size = 0;
+        }
+
+        if !ext.is_empty() {
+            let ext_name = format!("{}.paxheader", id);
+            let header = self.header(ext_name.as_bytes(), b"", b"", b'x', 0o100666, ext.len());
+            self.write_blocked(&header)?;
+            self.write_blocked(&ext)?;
+        }
+        let header = self.header(name, prefix, linkname, typeflag, mode, size);
+        self.write_blocked(&header)?;
+        if typeflag == b'0' && !data.is_empty() {
+            self.write_blocked(data)?;
+        }
+        Ok(())
+    }
+
+    /// Pad the last block with zeros, leaving at least two zero records at the end
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let tail = BLOCK_SIZE - self.offset;
+        self.out.write_all(&vec![0; tail])?;
+        if tail < 2 * RECORD_SIZE {
+            self.out.write_all(&[0; BLOCK_SIZE])?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Length of the leading directories of `path` that fit a `max_len` ustar prefix field
+fn path_prefix_len(path: &[u8], max_len: usize) -> usize {
+    let mut i = path.len();
+    if i > 1 && path[i - 1] == b'/' {
+        i -= 1;
+    }
+    i = i.min(max_len);
+    loop {
+        i -= 1;
+        if i == 0 || path[i] == b'/' {
+            return i;
+        }
+    }
+}
+
+/// Append a `<length> <keyword>=<value>\n` pax record, where the length counts itself
+fn append_ext_header(ext: &mut Vec<u8>, keyword: &str, value: &[u8]) {
+    let mut len = 1 + 1 + keyword.len() + 1 + value.len() + 1;
+    let mut digits = 1;
+    while len / 10 >= digits {
+        len += 1;
+        digits *= 10;
+    }
+    ext.extend_from_slice(format!("{} {}=", len, keyword).as_bytes());
+    ext.extend_from_slice(value);
+    ext.push(b'\n');
+}
+
+/// Zero-padded octal digits filling all but the last (NUL) byte of `field`
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()..].fill(0);
+}
+
+/// Zip writer with the entry layout of `git archive --format=zip`
+///
+/// Every entry carries Unix permissions and an extended timestamp; regular files are
+/// deflated unless that doesn't make them smaller, and the commit ID is the archive comment.
+struct ZipWriter<W: Write> {
+    out: W,
+    offset: u64,
+    central: Vec<u8>,
+    entries: u64,
+    mtime: u32,
+    dos_time: u16,
+    dos_date: u16,
+    comment: Vec<u8>,
+}
+
+impl<W: Write> ZipWriter<W> {
+    fn new(out: W, mtime: u64, commit: Option<&str>) -> Self {
+        use chrono::{Datelike, TimeZone, Timelike};
+
+        // DOS timestamps are local time, with two-second resolution, from 1980
+        let local = chrono::Local
+            .timestamp_opt(mtime as i64, 0)
+            .single()
+            .unwrap_or_default();
+        let year = (local.year() - 1980).clamp(0, 127) as u16;
+        Self {
+            out,
+            offset: 0,
+            central: Vec::new(),
+            entries: 0,
+            mtime: mtime.min(u64::from(u32::MAX)) as u32,
+            dos_time: (local.hour() << 11 | local.minute() << 5 | (local.second() / 2)) as u16,
+            dos_date: year << 9 | (local.month() << 5 | local.day()) as u16,
+            comment: commit.unwrap_or_default().as_bytes().to_vec(),
+        }
+    }
+}
+
+impl<W: Write> ArchiveWriter for ZipWriter<W> {
+    fn add(&mut self, path: &[u8], _id: &str, mode: u32, data: &[u8]) -> Result<()> {
+        let (mode, dos_attr) = match mode & S_IFMT {
+            MODE_TREE | MODE_GITLINK => ((MODE_TREE | 0o777) & !UMASK, 0x10),
+            MODE_SYMLINK => (mode | 0o777, 0),
+            S_IFREG => {
+                let perm = if mode & 0o100 != 0 { 0o777 } else { 0o666 };
+                ((mode | perm) & !UMASK, 0)
+            }
+            _ => anyhow::bail!("Unsupported mode {:o}", mode),
+        };
+
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+        let mut method: u16 = 0;
+        let mut stored = std::borrow::Cow::Borrowed(data);
+        if mode & S_IFMT == S_IFREG && !data.is_empty() {
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            let deflated = encoder.finish()?;
+            if deflated.len() < data.len() {
+                method = 8;
+                stored = std::borrow::Cow::Owned(deflated);
+            }
+        }
+
+        let too_large = |n: usize| {
+            u32::try_from(n).context("Archive too large for zip (zip64 is not supported)")
+        };
+        let compressed_size = too_large(stored.len())?;
+        let size = too_large(data.len())?;
+        let offset = u32::try_from(self.offset)
+            .context("Archive too large for zip (zip64 is not supported)")?;
+        let name_len = u16::try_from(path.len()).context("Path too long for zip")?;
+        let version_needed: u16 = if method == 8 || dos_attr != 0 { 20 } else { 10 };
+        let flags: u16 = if !path.is_ascii() && std::str::from_utf8(path).is_ok() {
+            0x0800 // UTF-8 name
+        } else {
+            0
+        };
+        // Extended timestamp: modification time only
+        let mut extra = vec![0x55, 0x54, 5, 0, 1];
+        extra.extend_from_slice(&self.mtime.to_le_bytes());
+
+        let mut common = Vec::new();
+        common.extend_from_slice(&version_needed.to_le_bytes());
+        common.extend_from_slice(&flags.to_le_bytes());
+        common.extend_from_slice(&method.to_le_bytes());
+        common.extend_from_slice(&self.dos_time.to_le_bytes());
+        common.extend_from_slice(&self.dos_date.to_le_bytes());
+        common.extend_from_slice(&crc.sum().to_le_bytes());
+        common.extend_from_slice(&compressed_size.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&name_len.to_le_bytes());
+        common.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+
+        let mut local = 0x04034b50u32.to_le_bytes().to_vec();
+        local.extend_from_slice(&common);
+        local.extend_from_slice(path);
+        local.extend_from_slice(&extra);
+        self.out.write_all(&local)?;
+        self.out.write_all(&stored)?;
+
+        self.central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        self.central.extend_from_slice(&0x0317u16.to_le_bytes()); // made by: Unix
+        self.central.extend_from_slice(&common);
+        self.central.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        self.central.extend_from_slice(&0u16.to_le_bytes()); // disk number
+        self.central.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+        self.central
+            .extend_from_slice(&(mode << 16 | dos_attr).to_le_bytes());
+        self.central.extend_from_slice(&offset.to_le_bytes());
+        self.central.extend_from_slice(path);
+        self.central.extend_from_slice(&extra);
+
+        self.offset += (local.len() + stored.len()) as u64;
+        self.entries += 1;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let entries = u16::try_from(self.entries)
+            .context("Too many entries for zip (zip64 is not supported)")?;
+        let central_offset = u32::try_from(self.offset)
+            .context("Archive too large for zip (zip64 is not supported)")?;
+
+        let mut end = 0x06054b50u32.to_le_bytes().to_vec();
+        end.extend_from_slice(&0u16.to_le_bytes()); // this disk
+        end.extend_from_slice(&0u16.to_le_bytes()); // central directory disk
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&(self.central.len() as u32).to_le_bytes());
+        end.extend_from_slice(&central_offset.to_le_bytes());
+        end.extend_from_slice(&(self.comment.len() as u16).to_le_bytes());
+        end.extend_from_slice(&self.comment);
+
+        self.out.write_all(&self.central)?;
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{store_object_in, MemoryStorage};
+
+    fn tar(mtime: u64) -> TarWriter<Vec<u8>> {
+        TarWriter {
+            out: Vec::new(),
+            offset: 0,
+            mtime,
+        }
+    }
+
+    #[test]
+    fn test_ext_header_length_counts_itself() {
+        let mut ext = Vec::new();
+        append_ext_header(&mut ext, "comment", "a".repeat(40).as_bytes());
+        assert_eq!(ext, format!("52 comment={}\n", "a".repeat(40)).into_bytes());
+
+        // 99 bytes with a two-digit length, so the length needs three digits
+        let mut ext = Vec::new();
+        append_ext_header(&mut ext, "path", "p".repeat(92).as_bytes());
+        assert_eq!(ext.len(), 102);
+        assert!(ext.starts_with(b"102 path="));
+    }
+
+    #[test]
+    fn test_long_paths_split_into_prefix() {
+        let dir = "d".repeat(60);
+        let path = format!("{dir}/{dir}/file.txt");
+        assert_eq!(path_prefix_len(path.as_bytes(), 155), 121);
+
+        let mut writer = tar(0);
+        writer
+            .add(path.as_bytes(), &"1".repeat(40), 0o100644, b"x")
+            .unwrap();
+        let header = &writer.out[..RECORD_SIZE];
+        assert_eq!(&header[..9], b"file.txt\0");
+        assert_eq!(&header[345..345 + 121], &path.as_bytes()[..121]);
+
+        // A single component over 100 bytes goes into a pax header
+        let name = "n".repeat(120);
+        let mut writer = tar(0);
+        writer
+            .add(name.as_bytes(), &"2".repeat(40), 0o100755, b"x")
+            .unwrap();
+        assert_eq!(writer.out[156], b'x');
+        assert!(writer
+            .out
+            .starts_with(format!("{}.paxheader", "2".repeat(40)).as_bytes()));
+        let entry = &writer.out[2 * RECORD_SIZE..3 * RECORD_SIZE];
+        assert!(entry.starts_with(format!("{}.data\0", "2".repeat(40)).as_bytes()));
+        assert_eq!(&entry[100..108], b"0000775\0");
+    }
+
+    #[test]
+    fn test_header_fields_and_trailer() {
+        let mut writer = Box::new(tar(1_700_000_000));
+        writer
+            .add(b"dir/", &"3".repeat(40), MODE_TREE, b"")
+            .unwrap();
+        writer
+            .add(b"dir/link", &"4".repeat(40), MODE_SYMLINK, b"../target")
+            .unwrap();
+
+        let out = writer.out.clone();
+        let dir = &out[..RECORD_SIZE];
+        assert_eq!(&dir[100..108], b"0000775\0");
+        assert_eq!(&dir[124..136], b"00000000000\0");
+        assert_eq!(&dir[136..148], b"14524770400\0");
+        assert_eq!(dir[156], b'5');
+        let checksum: u32 = dir
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    u32::from(b)
+                }
+            })
+            .sum();
+        assert_eq!(&dir[148..156], format!("{:07o}\0", checksum).as_bytes());
+
+        let link = &out[RECORD_SIZE..2 * RECORD_SIZE];
+        assert_eq!(&link[100..108], b"0000777\0");
+        assert_eq!(link[156], b'2');
+        assert_eq!(&link[157..167], b"../target\0");
+
+        // Two records so far; the rest of the block is zeros
+        let mut finished = Vec::new();
+        Box::new(TarWriter {
+            out: &mut finished,
+            offset: writer.offset,
+            mtime: 0,
+        })
+        .finish()
+        .unwrap();
+        assert_eq!(finished.len(), BLOCK_SIZE - 2 * RECORD_SIZE);
+        assert!(finished.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_annotated_tag_archives_its_commit() {
+        let storage = MemoryStorage::new();
+        let mut state = State::default();
+        let tree = store_object_in(&storage, &mut state, Kind::Tree, String::new());
+        let commit = store_object_in(
+            &storage,
+            &mut state,
+            Kind::Commit,
+            format!(
+                "tree {tree}\nauthor A <a@a> 100 +0000\ncommitter A <a@a> 200 +0000\n\nEmpty\n"
+            ),
+        );
+        let tag = store_object_in(
+            &storage,
+            &mut state,
+            Kind::Tag,
+            format!("object {commit}\ntype commit\ntag v1\ntagger A <a@a> 300 +0000\n\nv1\n"),
+        );
+
+        let mut out = Vec::new();
+        let written =
+            write_archive(&storage, &state, &tag, ArchiveFormat::Tar, "v1/", &mut out).unwrap();
+        assert_eq!(written, 1);
+        // Global header, its comment record, the prefix directory, then zeros to one block
+        assert_eq!(out.len(), BLOCK_SIZE);
+        assert!(out.starts_with(b"pax_global_header\0"));
+        assert_eq!(
+            &out[RECORD_SIZE..RECORD_SIZE + 52],
+            format!("52 comment={commit}\n").as_bytes()
+        );
+        let dir = &out[2 * RECORD_SIZE..3 * RECORD_SIZE];
+        assert!(dir.starts_with(b"v1/\0"));
+        assert_eq!(&dir[136..148], b"00000000310\0");
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        for (name, format) in [
+            ("project.tar.gz", Some(ArchiveFormat::TarGz)),
+            ("project.tgz", Some(ArchiveFormat::TarGz)),
+            ("project.tar", Some(ArchiveFormat::Tar)),
+            ("project.zip", Some(ArchiveFormat::Zip)),
+            ("project", None),
+        ] {
+            assert_eq!(
+                ArchiveFormat::from_path(Path::new(name)),
+                format,
+                "{}",
+                name
+            );
+        }
+        assert!("rar".parse::<ArchiveFormat>().is_err());
+        assert_eq!(prefix_dir("project-1.2//"), Some(&b"project-1.2/"[..]));
+        assert_eq!(prefix_dir("project-"), None);
+    }
+}
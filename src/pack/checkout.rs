@@ -0,0 +1,272 @@
+//! Materialize the tree of a stored commit as plain files (no git repository needed)
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use gix_object::Kind;
+
+use super::objects::{
+    GitObject,
+    ObjectId,
+    TreeEntry,
+    MODE_EXECUTABLE,
+    MODE_FILE,
+    MODE_GITLINK,
+    MODE_SYMLINK,
+    MODE_TREE,
+};
+use crate::storage::{State, StorageBackend};
+
+/// A non-directory entry of a checked-out tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    /// Path relative to the root of the tree
+    pub path: PathBuf,
+    pub mode: u32,
+    pub id: ObjectId,
+}
+
+/// List every file, symlink and submodule under the tree `commitish` points at
+///
+/// `commitish` may be a commit, an annotated tag or a tree. Trees are read one level at a time
+/// so each level's objects come from storage in one batch.
+pub fn list_files(
+    storage: &impl StorageBackend,
+    state: &State,
+    commitish: &str,
+) -> Result<Vec<FileEntry>> {
+    let mut files: Vec<FileEntry> = list_tree(storage, state, commitish)?
+        .into_iter()
+        .filter(|entry| entry.mode != MODE_TREE)
+        .collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// List every entry under the tree `commitish` points at, directories included
+///
+/// Entries come in the order git walks trees: depth first, each directory right before its
+/// contents, and siblings in tree order (where `a.txt` sorts before the directory `a`).
+pub fn list_tree(
+    storage: &impl StorageBackend,
+    state: &State,
+    commitish: &str,
+) -> Result<Vec<FileEntry>> {
+    let root = resolve_tree(storage, state, commitish)?;
+
+    // Read every tree, a level at a time
+    let mut trees: HashMap<ObjectId, Vec<TreeEntry>> = HashMap::new();
+    let mut frontier: Vec<ObjectId> = vec![root.clone()];
+    while !frontier.is_empty() {
+        frontier.sort_unstable();
+        frontier.dedup();
+        let objects = read_objects(storage, state, frontier.iter().map(String::as_str))?;
+
+        let mut next = Vec::new();
+        for (id, tree) in frontier.into_iter().zip(objects) {
+            let entries = tree.tree_entries()?;
+            next.extend(
+                entries
+                    .iter()
+                    .filter(|entry| entry.mode == MODE_TREE && !trees.contains_key(&entry.id))
+                    .map(|entry| entry.id.clone()),
+            );
+            trees.insert(id, entries);
+        }
+        frontier = next;
+    }
+
+    let mut entries = Vec::new();
+    let mut stack: Vec<(PathBuf, &[TreeEntry])> = vec![(PathBuf::new(), &trees[&root])];
+    while let Some((dir, rest)) = stack.last_mut() {
+        let Some((entry, tail)) = rest.split_first() else {
+            stack.pop();
+            continue;
+        };
+        *rest = tail;
+
+        let path = dir.join(entry_name(&entry.name)?);
+        if entry.mode == MODE_TREE {
+            let subtree = trees
+                .get(&entry.id)
+                .with_context(|| format!("Tree {} was not read", entry.id))?;
+            stack.push((path.clone(), subtree));
+        }
+        entries.push(FileEntry {
+            path,
+            mode: entry.mode,
+            id: entry.id.clone(),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Write `files` below `out_dir` the way `git archive` would
+///
+/// Executable files get mode 755, symlinks are recreated, and submodules become empty
+/// directories.
+pub fn write_files(
+    storage: &impl StorageBackend,
+    state: &State,
+    files: &[FileEntry],
+    out_dir: &Path,
+) -> Result<()> {
+    let blob_ids = files
+        .iter()
+        .filter(|file| file.mode != MODE_GITLINK)
+        .map(|file| file.id.as_str());
+    let mut blobs = read_objects(storage, state, blob_ids)?.into_iter();
+
+    for file in files {
+        let path = out_dir.join(&file.path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+
+        if file.mode == MODE_GITLINK {
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("Failed to create directory {}", path.display()))?;
+            continue;
+        }
+
+        let blob = blobs.next().context("Missing blob for checked-out file")?;
+        if blob.kind != Kind::Blob {
+            anyhow::bail!(
+                "{} points at a {}, not a blob",
+                file.path.display(),
+                blob.kind
+            );
+        }
+        match file.mode {
+            MODE_SYMLINK => write_symlink(&blob.data, &path)?,
+            MODE_FILE | MODE_EXECUTABLE => {
+                std::fs::write(&path, &blob.data)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                set_executable(&path, file.mode == MODE_EXECUTABLE)?;
+            }
+            mode => anyhow::bail!("Unsupported mode {:o} for {}", mode, file.path.display()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Peel `commitish` (tag, commit or tree) down to a tree ID
+pub(super) fn resolve_tree(
+    storage: &impl StorageBackend,
+    state: &State,
+    commitish: &str,
+) -> Result<ObjectId> {
+    let mut id = commitish.to_string();
+    // Tags can point at tags; bound the chain so a cycle can't spin forever
+    for _ in 0..16 {
+        let obj = read_objects(storage, state, std::iter::once(id.as_str()))?
+            .pop()
+            .context("Missing object")?;
+        match obj.peel_target() {
+            Some(target) => id = target,
+            None if obj.kind == Kind::Tree => return Ok(id),
+            None => anyhow::bail!("{} is a {}, which has no tree", id, obj.kind),
+        }
+    }
+    anyhow::bail!("Too many levels of tags resolving {}", commitish)
+}
+
+/// Read and parse git objects by SHA-1 in one batch
+pub(super) fn read_objects<'a>(
+    storage: &impl StorageBackend,
+    state: &State,
+    ids: impl Iterator<Item = &'a str>,
+) -> Result<Vec<GitObject>> {
+    let objects = ids
+        .map(|id| {
+            state
+                .objects
+                .get(id)
+                .map(|content_id| (id, content_id.as_str()))
+                .with_context(|| format!("Object {} not found in state", id))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let contents = storage
+        .read_git_objects(&objects)
+        .context("Failed to read objects from storage")?;
+    objects
+        .iter()
+        .zip(contents)
+        .map(|((id, _), content)| {
+            GitObject::from_loose_format(&content)
+                .with_context(|| format!("Failed to parse object {}", id))
+        })
+        .collect()
+}
+
+/// A tree entry name as a single path component, refusing names that would escape `out_dir`
+fn entry_name(name: &[u8]) -> Result<&Path> {
+    let name_str = String::from_utf8_lossy(name);
+    if name.is_empty()
+        || name == b"."
+        || name == b".."
+        || name.contains(&b'/')
+        || name.contains(&b'\\')
+        || name.eq_ignore_ascii_case(b".git")
+    {
+        anyhow::bail!("Refusing to check out tree entry {:?}", name_str);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(Path::new(std::ffi::OsStr::from_bytes(name)))
+    }
+    #[cfg(not(unix))]
+    {
+        std::str::from_utf8(name)
+            .map(Path::new)
+            .with_context(|| format!("Tree entry {:?} is not valid UTF-8", name_str))
+    }
+}
+
+#[cfg(unix)]
+fn write_symlink(target: &[u8], path: &Path) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), path)
+        .with_context(|| format!("Failed to create symlink {}", path.display()))
+}
+
+/// Without symlinks, write the target as the file's content (like core.symlinks=false)
+#[cfg(not(unix))]
+fn write_symlink(target: &[u8], path: &Path) -> Result<()> {
+    std::fs::write(path, target).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path, executable: bool) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = if executable { 0o755 } else { 0o644 };
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions on {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path, _executable: bool) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_name_rejects_traversal() {
+        assert_eq!(entry_name(b"index.html").unwrap(), Path::new("index.html"));
+        for name in [&b""[..], b".", b"..", b"a/b", b".git", b".GIT"] {
+            assert!(entry_name(name).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Explain `git index-pack` rejecting a pack we generated, in terms of the stored objects
+
+use super::{objects::ObjectId, send::PackLayout};
+use crate::storage::{ImmutableStore, ParsedContentId, ReadSource};
+
+/// The part of a pack `git index-pack` blamed for rejecting it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackFault {
+    /// The entry covering this pack offset (`pack has bad object at offset N`)
+    Offset(u64),
+    /// An object named by SHA-1 (fsck errors, collisions, missing delta bases)
+    Object(ObjectId),
+}
+
+/// Find what index-pack's `stderr` blames, preferring a pack offset over an object ID
+pub fn parse_index_pack_error(stderr: &str) -> Option<PackFault> {
+    let offset = stderr.lines().find_map(|line| {
+        let rest = &line[line.find("at offset ")? + "at offset ".len()..];
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(rest, |end| &rest[..end]);
+        digits.parse().ok()
+    });
+    if let Some(offset) = offset {
+        return Some(PackFault::Offset(offset));
+    }
+    stderr
+        .split(|c: char| !c.is_ascii_hexdigit())
+        .find(|word| word.len() == 40 && !word.bytes().any(|b| b.is_ascii_uppercase()))
+        .map(|sha| PackFault::Object(sha.to_string()))
+}
+
+/// Describe the stored object behind index-pack's complaint, with what to run next
+///
+/// None when `stderr` names nothing in the pack, such as a truncated stream.
+pub fn diagnose(
+    stderr: &str,
+    layout: &PackLayout,
+    storage: &impl ImmutableStore,
+) -> Option<String> {
+    let sha = match parse_index_pack_error(stderr)? {
+        PackFault::Offset(offset) => layout.object_at(offset)?.clone(),
+        PackFault::Object(sha) => sha,
+    };
+    let Some(content_id) = layout.content_id(&sha) else {
+        return Some(format!(
+            "git rejected object {}, which this fetch did not send\n\
+             hint: run `git-remote-walrus fsck --full <remote>` to check the stored history",
+            sha
+        ));
+    };
+
+    let mut message = format!("git rejected object {} (ContentId {}", sha, content_id);
+    if let Some(blob) = ParsedContentId::parse(content_id)
+        .ok()
+        .as_ref()
+        .and_then(ParsedContentId::blob_object_id)
+    {
+        message.push_str(&format!(", blob object {}", blob));
+    }
+    let source = storage.read_source(content_id);
+    if let Some(source) = source {
+        message.push_str(&format!(", {}", source));
+    }
+    message.push_str(
+        ")\nhint: run `git-remote-walrus fsck --full <remote>` to check every stored object",
+    );
+    if source == Some(ReadSource::Cache) {
+        message.push_str(
+            "\nhint: the cached copy may be damaged; `git-remote-walrus doctor` checks the cache",
+        );
+    }
+    Some(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use gix_object::Kind;
+
+    use super::*;
+    use crate::{
+        pack::{objects::GitObject, send_pack},
+        storage::{MemoryStorage, MutableState},
+    };
+
+    const SHA: &str = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+
+    #[test]
+    fn test_parse_index_pack_errors() {
+        let object = |stderr: String| (stderr, Some(PackFault::Object(SHA.to_string())));
+        let cases = [
+            (
+                "error: inflate: data stream error (invalid distance too far back)\n\
+                 fatal: pack has bad object at offset 1234: inflate returned -3\n"
+                    .to_string(),
+                Some(PackFault::Offset(1234)),
+            ),
+            (
+                "fatal: pack has bad object at offset 12: unknown object type 0\n".to_string(),
+                Some(PackFault::Offset(12)),
+            ),
+            object(format!("fatal: SHA1 COLLISION FOUND WITH {} !\n", SHA)),
+            object(format!(
+                "error: object {}: badTimezone: invalid author/committer line - bad time zone\n\
+                 fatal: fsck error in packed object\n",
+                SHA
+            )),
+            object(format!("fatal: did not receive expected object {}\n", SHA)),
+            ("fatal: pack has 2 unresolved deltas\n".to_string(), None),
+            (
+                "fatal: premature end of pack file, 10 bytes missing\n".to_string(),
+                None,
+            ),
+            ("fatal: early EOF\n".to_string(), None),
+        ];
+        for (stderr, expected) in cases {
+            assert_eq!(parse_index_pack_error(&stderr), expected, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn test_diagnosis_names_the_stored_object() {
+        let storage = MemoryStorage::new();
+        let blob = GitObject::from_raw(Kind::Blob, b"corrupt me\n".to_vec()).unwrap();
+        let content_id = storage.write_object(&blob.to_loose_format()).unwrap();
+        storage
+            .update_state(|state| {
+                state.objects.insert(blob.id.clone(), content_id.clone());
+                state.refs.insert("refs/tags/b".into(), blob.id.clone());
+                Ok(())
+            })
+            .unwrap();
+        let mut pack = Vec::new();
+        let layout = send_pack(&["refs/tags/b".to_string()], &storage, &mut pack).unwrap();
+
+        let diagnosis = diagnose(
+            "fatal: pack has bad object at offset 14: inflate returned -3",
+            &layout,
+            &storage,
+        )
+        .unwrap();
+        assert!(diagnosis.starts_with(&format!(
+            "git rejected object {} (ContentId {})",
+            blob.id, content_id
+        )));
+        assert!(diagnosis.contains("fsck --full"));
+
+        // Objects outside the pack and faults naming nothing are reported as such
+        let other = diagnose(&format!("fatal: bad object {}", SHA), &layout, &storage).unwrap();
+        assert!(other.contains("which this fetch did not send"));
+        assert_eq!(diagnose("fatal: early EOF", &layout, &storage), None);
+    }
+}
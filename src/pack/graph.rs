@@ -0,0 +1,192 @@
+//! Commit graph of a stored repository, for external tooling
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::{Context, Result};
+use gix_object::Kind;
+use serde::Serialize;
+
+use super::{
+    checkout::read_objects,
+    objects::{GitObject, ObjectId},
+};
+use crate::storage::{State, StorageBackend};
+
+/// A commit and the refs pointing at it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommitNode {
+    pub id: ObjectId,
+    pub parents: Vec<ObjectId>,
+    /// Refs pointing at this commit, directly or through annotated tags
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub refs: Vec<String>,
+    /// `Name <email>` of the author
+    pub author: String,
+    /// Committer time, in seconds since the Unix epoch
+    pub timestamp: i64,
+    /// First line of the commit message
+    pub summary: String,
+}
+
+/// Every commit reachable from a set of refs, newest first
+#[derive(Debug, Clone, Serialize)]
+pub struct CommitGraph {
+    pub nodes: Vec<CommitNode>,
+}
+
+impl CommitGraph {
+    /// Build the graph reachable from `refs` (name -> object ID)
+    ///
+    /// Only tags and commits are read from storage, a level of the graph at a time; trees and
+    /// blobs are never downloaded. Refs that don't lead to a commit are left out.
+    pub fn build<'a>(
+        storage: &impl StorageBackend,
+        state: &State,
+        refs: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self> {
+        let mut loaded: HashMap<ObjectId, GitObject> = HashMap::new();
+
+        // Peel each ref to a commit; tags can point at tags, so bound the chain
+        let mut ref_names: BTreeMap<ObjectId, Vec<String>> = BTreeMap::new();
+        let mut pending: Vec<(String, ObjectId)> = refs
+            .into_iter()
+            .map(|(name, id)| (name.to_string(), id.to_string()))
+            .collect();
+        for _ in 0..16 {
+            if pending.is_empty() {
+                break;
+            }
+            load(
+                storage,
+                state,
+                pending.iter().map(|(_, id)| id),
+                &mut loaded,
+            )?;
+
+            let mut next = Vec::new();
+            for (name, id) in pending {
+                let obj = &loaded[&id];
+                match obj.kind {
+                    Kind::Commit => ref_names.entry(id).or_default().push(name),
+                    Kind::Tag => next.extend(obj.peel_target().map(|target| (name, target))),
+                    kind => tracing::debug!("Ref {} points at a {}, skipping", name, kind),
+                }
+            }
+            pending = next;
+        }
+
+        // Walk parents one generation at a time
+        let mut seen: HashSet<ObjectId> = ref_names.keys().cloned().collect();
+        let mut frontier: Vec<ObjectId> = seen.iter().cloned().collect();
+        let mut nodes = Vec::new();
+        while !frontier.is_empty() {
+            load(storage, state, frontier.iter(), &mut loaded)?;
+
+            let mut next = Vec::new();
+            for id in frontier {
+                let commit = loaded
+                    .remove(&id)
+                    .with_context(|| format!("Object {} was not read", id))?;
+                if commit.kind != Kind::Commit {
+                    anyhow::bail!("Parent {} is a {}, not a commit", id, commit.kind);
+                }
+                let mut node = parse_commit(&commit);
+                node.refs = ref_names.remove(&id).unwrap_or_default();
+                next.extend(
+                    node.parents
+                        .iter()
+                        .filter(|parent| seen.insert((*parent).clone()))
+                        .cloned(),
+                );
+                nodes.push(node);
+            }
+            frontier = next;
+        }
+
+        nodes.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| a.id.cmp(&b.id)));
+        Ok(Self { nodes })
+    }
+}
+
+/// Read the objects among `ids` that aren't in `loaded` yet, in one batch
+fn load<'a>(
+    storage: &impl StorageBackend,
+    state: &State,
+    ids: impl Iterator<Item = &'a ObjectId>,
+    loaded: &mut HashMap<ObjectId, GitObject>,
+) -> Result<()> {
+    let mut missing: Vec<&str> = ids
+        .filter(|id| !loaded.contains_key(*id))
+        .map(String::as_str)
+        .collect();
+    missing.sort_unstable();
+    missing.dedup();
+
+    for obj in read_objects(storage, state, missing.into_iter())? {
+        loaded.insert(obj.id.clone(), obj);
+    }
+    Ok(())
+}
+
+/// Parents, author, committer time and summary of a commit
+pub(super) fn parse_commit(commit: &GitObject) -> CommitNode {
+    let text = String::from_utf8_lossy(&commit.data);
+    let (headers, message) = text.split_once("\n\n").unwrap_or((&text, ""));
+
+    let mut node = CommitNode {
+        id: commit.id.clone(),
+        parents: Vec::new(),
+        refs: Vec::new(),
+        author: String::new(),
+        timestamp: 0,
+        summary: message.lines().next().unwrap_or_default().to_string(),
+    };
+    for line in headers.lines() {
+        match line.split_once(' ') {
+            Some(("parent", id)) => node.parents.push(id.trim().to_string()),
+            Some(("author", ident)) => {
+                node.author = ident
+                    .rfind('>')
+                    .map_or(ident, |end| &ident[..=end])
+                    .to_string()
+            }
+            Some(("committer", ident)) => {
+                // `Name <email> <seconds> <offset>`
+                node.timestamp = ident
+                    .rsplit(' ')
+                    .nth(1)
+                    .and_then(|seconds| seconds.parse().ok())
+                    .unwrap_or_default()
+            }
+            _ => {}
+        }
+    }
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commit() {
+        let commit = GitObject::from_raw(
+            Kind::Commit,
+            b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+              parent 1111111111111111111111111111111111111111\n\
+              parent 2222222222222222222222222222222222222222\n\
+              author A U Thor <a@example.com> 1700000000 +0100\n\
+              committer C O Mitter <c@example.com> 1700000100 -0500\n\
+              \n\
+              Merge things\n\nDetails\n"
+                .to_vec(),
+        )
+        .unwrap();
+
+        let node = parse_commit(&commit);
+        assert_eq!(node.parents, vec!["1".repeat(40), "2".repeat(40)]);
+        assert_eq!(node.author, "A U Thor <a@example.com>");
+        assert_eq!(node.timestamp, 1700000100);
+        assert_eq!(node.summary, "Merge things");
+    }
+}
@@ -0,0 +1,405 @@
+//! One path's contents and history in a stored repository, read without a checkout
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result};
+use gix_object::Kind;
+
+use super::{
+    checkout::{read_objects, resolve_tree},
+    graph::{parse_commit, CommitNode},
+    objects::{ObjectId, TreeEntry, MODE_GITLINK, MODE_TREE},
+};
+use crate::storage::{State, StorageBackend};
+
+/// Mode and object of a path at one tree (None where the path doesn't exist)
+type PathEntry = Option<(u32, ObjectId)>;
+
+/// A commit that changed a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathCommit {
+    pub commit: CommitNode,
+    /// Object at the path after the commit (None when the commit deleted it)
+    pub id: Option<ObjectId>,
+}
+
+/// Contents of the blob at `path` in the tree `commitish` points at
+///
+/// Only the trees along the path and the blob itself are read.
+pub fn read_path(
+    storage: &impl StorageBackend,
+    state: &State,
+    commitish: &str,
+    path: &str,
+) -> Result<Vec<u8>> {
+    let root = resolve_tree(storage, state, commitish)?;
+    let mut walker = PathWalker::new(storage, state, path)?;
+    let entry = walker.lookup(std::slice::from_ref(&root))?.remove(0);
+
+    let (mode, id) = entry.with_context(|| format!("{} does not exist in {}", path, commitish))?;
+    match mode {
+        MODE_TREE => anyhow::bail!("{} is a directory in {}", path, commitish),
+        MODE_GITLINK => anyhow::bail!("{} is a submodule in {}", path, commitish),
+        _ => {}
+    }
+    let blob = read_objects(storage, state, std::iter::once(id.as_str()))?
+        .pop()
+        .context("Missing object")?;
+    if blob.kind != Kind::Blob {
+        anyhow::bail!("{} points at a {}, not a blob", path, blob.kind);
+    }
+    Ok(blob.data)
+}
+
+/// Commits reachable from `commitish` that changed `path`, newest first
+///
+/// Like `git log -- <path>`, a merge that kept one parent's version of the path is left out
+/// and only that parent's history is followed; renames are not followed. Commits and the trees
+/// along the path are read a generation of history at a time, and no blobs are read at all.
+pub fn path_history(
+    storage: &impl StorageBackend,
+    state: &State,
+    commitish: &str,
+    path: &str,
+) -> Result<Vec<PathCommit>> {
+    let tip = peel_to_commit(storage, state, commitish)?;
+    let mut walker = PathWalker::new(storage, state, path)?;
+
+    // Commit and its tree, for every commit read so far
+    let mut commits: HashMap<ObjectId, (CommitNode, ObjectId)> = HashMap::new();
+    let mut seen: HashSet<ObjectId> = HashSet::from([tip.clone()]);
+    let mut frontier = vec![tip];
+    let mut changes = Vec::new();
+    while !frontier.is_empty() {
+        // The path is compared against each parent, so parents are read with their children
+        load_commits(storage, state, &frontier, &mut commits)?;
+        let parents: Vec<ObjectId> = frontier
+            .iter()
+            .flat_map(|id| commits[id].0.parents.iter().cloned())
+            .collect();
+        load_commits(storage, state, &parents, &mut commits)?;
+
+        let mut roots: Vec<ObjectId> = frontier
+            .iter()
+            .chain(&parents)
+            .map(|id| commits[id].1.clone())
+            .collect();
+        roots.sort_unstable();
+        roots.dedup();
+        let entries: HashMap<ObjectId, PathEntry> =
+            roots.iter().cloned().zip(walker.lookup(&roots)?).collect();
+        let entry_at = |commit: &ObjectId| &entries[&commits[commit].1];
+
+        let mut next = Vec::new();
+        for id in frontier {
+            let entry = entry_at(&id);
+            let parents = &commits[&id].0.parents;
+            let followed: Vec<&ObjectId> =
+                match parents.iter().find(|parent| entry_at(parent) == entry) {
+                    // Unchanged relative to this parent, whose history explains the path
+                    Some(same) => vec![same],
+                    None => {
+                        if entry.is_some() || !parents.is_empty() {
+                            changes.push(PathCommit {
+                                commit: commits[&id].0.clone(),
+                                id: entry.as_ref().map(|(_, id)| id.clone()),
+                            });
+                        }
+                        parents.iter().collect()
+                    }
+                };
+            next.extend(
+                followed
+                    .into_iter()
+                    .filter(|parent| seen.insert((*parent).clone()))
+                    .cloned(),
+            );
+        }
+        frontier = next;
+    }
+
+    changes.sort_by(|a, b| {
+        b.commit
+            .timestamp
+            .cmp(&a.commit.timestamp)
+            .then_with(|| a.commit.id.cmp(&b.commit.id))
+    });
+    Ok(changes)
+}
+
+/// Peel `commitish` (a commit or annotated tag) down to a commit ID
+fn peel_to_commit(
+    storage: &impl StorageBackend,
+    state: &State,
+    commitish: &str,
+) -> Result<ObjectId> {
+    let mut id = commitish.to_string();
+    // Tags can point at tags; bound the chain so a cycle can't spin forever
+    for _ in 0..16 {
+        let obj = read_objects(storage, state, std::iter::once(id.as_str()))?
+            .pop()
+            .context("Missing object")?;
+        match obj.kind {
+            Kind::Commit => return Ok(id),
+            Kind::Tag => id = obj.peel_target().context("Tag has no target")?,
+            kind => anyhow::bail!("{} is a {}, not a commit", id, kind),
+        }
+    }
+    anyhow::bail!("Too many levels of tags resolving {}", commitish)
+}
+
+/// Read the commits among `ids` that aren't in `commits` yet, in one batch
+fn load_commits(
+    storage: &impl StorageBackend,
+    state: &State,
+    ids: &[ObjectId],
+    commits: &mut HashMap<ObjectId, (CommitNode, ObjectId)>,
+) -> Result<()> {
+    let mut missing: Vec<&str> = ids
+        .iter()
+        .filter(|id| !commits.contains_key(*id))
+        .map(String::as_str)
+        .collect();
+    missing.sort_unstable();
+    missing.dedup();
+
+    for obj in read_objects(storage, state, missing.into_iter())? {
+        if obj.kind != Kind::Commit {
+            anyhow::bail!("{} is a {}, not a commit", obj.id, obj.kind);
+        }
+        let tree = obj
+            .peel_target()
+            .with_context(|| format!("Commit {} has no tree", obj.id))?;
+        commits.insert(obj.id.clone(), (parse_commit(&obj), tree));
+    }
+    Ok(())
+}
+
+/// Looks a path up in many trees, keeping the trees it reads since most commits share them
+struct PathWalker<'a, S> {
+    storage: &'a S,
+    state: &'a State,
+    components: Vec<Vec<u8>>,
+    trees: HashMap<ObjectId, Vec<TreeEntry>>,
+}
+
+impl<'a, S: StorageBackend> PathWalker<'a, S> {
+    fn new(storage: &'a S, state: &'a State, path: &str) -> Result<Self> {
+        let components: Vec<Vec<u8>> = path
+            .split('/')
+            .filter(|component| !component.is_empty() && *component != ".")
+            .map(|component| component.as_bytes().to_vec())
+            .collect();
+        if components.is_empty() {
+            anyhow::bail!("Path {:?} names no file", path);
+        }
+        Ok(Self {
+            storage,
+            state,
+            components,
+            trees: HashMap::new(),
+        })
+    }
+
+    /// The path's entry in each of `roots`, reading the trees along it a level at a time
+    fn lookup(&mut self, roots: &[ObjectId]) -> Result<Vec<PathEntry>> {
+        let mut current: Vec<Option<ObjectId>> = roots.iter().cloned().map(Some).collect();
+        let mut found = vec![None; roots.len()];
+        for (depth, component) in self.components.iter().enumerate() {
+            let mut missing: Vec<&str> = current
+                .iter()
+                .flatten()
+                .filter(|id| !self.trees.contains_key(*id))
+                .map(String::as_str)
+                .collect();
+            missing.sort_unstable();
+            missing.dedup();
+            for tree in read_objects(self.storage, self.state, missing.into_iter())? {
+                let entries = tree.tree_entries()?;
+                self.trees.insert(tree.id, entries);
+            }
+
+            let last = depth + 1 == self.components.len();
+            for (slot, found) in current.iter_mut().zip(&mut found) {
+                let Some(tree) = slot.take() else {
+                    continue;
+                };
+                match self.trees[&tree].iter().find(|e| e.name == *component) {
+                    Some(entry) if last => *found = Some((entry.mode, entry.id.clone())),
+                    Some(entry) if entry.mode == MODE_TREE => *slot = Some(entry.id.clone()),
+                    _ => {}
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pack::objects::{GitObject, MODE_FILE},
+        storage::{store_object_in, ImmutableStore, MemoryStorage},
+    };
+
+    struct Fixture {
+        storage: MemoryStorage,
+        state: State,
+        time: u32,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Self {
+                storage: MemoryStorage::new(),
+                state: State::default(),
+                time: 1_700_000_000,
+            }
+        }
+
+        fn store(&mut self, kind: Kind, data: Vec<u8>) -> String {
+            store_object_in(&self.storage, &mut self.state, kind, data)
+        }
+
+        fn tree(&mut self, entries: &[(u32, &str, &str)]) -> String {
+            let mut entries = entries.to_vec();
+            entries.sort_by_key(|(_, name, _)| *name);
+            let mut data = Vec::new();
+            for (mode, name, id) in entries {
+                data.extend_from_slice(format!("{:o} {}\0", mode, name).as_bytes());
+                data.extend_from_slice(&hex::decode(id).unwrap());
+            }
+            self.store(Kind::Tree, data)
+        }
+
+        /// A commit whose tree holds `docs/guide.txt` (if given) and `other.txt`
+        fn commit(&mut self, parents: &[&str], guide: Option<&str>, other: &str) -> String {
+            let other = self.store(Kind::Blob, other.as_bytes().to_vec());
+            let mut root = vec![(MODE_FILE, "other.txt", other.as_str())];
+            let docs;
+            if let Some(guide) = guide {
+                let guide = self.store(Kind::Blob, guide.as_bytes().to_vec());
+                docs = self.tree(&[(MODE_FILE, "guide.txt", &guide)]);
+                root.push((MODE_TREE, "docs", &docs));
+            }
+            let tree = self.tree(&root);
+
+            self.time += 60;
+            let mut data = format!("tree {}\n", tree);
+            for parent in parents {
+                data.push_str(&format!("parent {}\n", parent));
+            }
+            data.push_str(&format!(
+                "author A <a@example.com> {0} +0000\ncommitter A <a@example.com> {0} +0000\n\n\
+                 commit at {0}\n",
+                self.time
+            ));
+            self.store(Kind::Commit, data.into_bytes())
+        }
+
+        fn history(&self, tip: &str, path: &str) -> Vec<(String, Option<String>)> {
+            path_history(&self.storage, &self.state, tip, path)
+                .unwrap()
+                .into_iter()
+                .map(|change| (change.commit.id, change.id))
+                .collect()
+        }
+
+        fn blob_id(data: &str) -> String {
+            GitObject::from_raw(Kind::Blob, data.as_bytes().to_vec())
+                .unwrap()
+                .id
+        }
+    }
+
+    #[test]
+    fn test_read_path() {
+        let mut fx = Fixture::new();
+        let commit = fx.commit(&[], Some("v1"), "unrelated");
+
+        assert_eq!(
+            read_path(&fx.storage, &fx.state, &commit, "docs/guide.txt").unwrap(),
+            b"v1"
+        );
+        assert_eq!(
+            read_path(&fx.storage, &fx.state, &commit, "/docs/./guide.txt").unwrap(),
+            b"v1"
+        );
+
+        let err = |path| {
+            read_path(&fx.storage, &fx.state, &commit, path)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(err("docs").contains("is a directory"));
+        assert!(err("docs/missing.txt").contains("does not exist"));
+        assert!(err("other.txt/below").contains("does not exist"));
+        assert!(err("").contains("names no file"));
+    }
+
+    #[test]
+    fn test_path_history_skips_unrelated_commits() {
+        let mut fx = Fixture::new();
+        let added = fx.commit(&[], Some("v1"), "a");
+        let unrelated = fx.commit(&[&added], Some("v1"), "b");
+        let edited = fx.commit(&[&unrelated], Some("v2"), "b");
+        let deleted = fx.commit(&[&edited], None, "b");
+        let readded = fx.commit(&[&deleted], Some("v3"), "c");
+
+        assert_eq!(
+            fx.history(&readded, "docs/guide.txt"),
+            [
+                (readded, Some(Fixture::blob_id("v3"))),
+                (deleted, None),
+                (edited.clone(), Some(Fixture::blob_id("v2"))),
+                (added.clone(), Some(Fixture::blob_id("v1"))),
+            ]
+        );
+        assert_eq!(
+            fx.history(&edited, "other.txt"),
+            [
+                (unrelated, Some(Fixture::blob_id("b"))),
+                (added, Some(Fixture::blob_id("a"))),
+            ]
+        );
+        assert!(fx.history(&edited, "docs/missing.txt").is_empty());
+    }
+
+    #[test]
+    fn test_path_history_follows_the_parent_a_merge_kept() {
+        let mut fx = Fixture::new();
+        let base = fx.commit(&[], Some("v1"), "a");
+        // One side edits the guide, the other only other.txt
+        let edit = fx.commit(&[&base], Some("v2"), "a");
+        let side = fx.commit(&[&base], Some("v1"), "side");
+        let merge = fx.commit(&[&side, &edit], Some("v2"), "side");
+
+        // The merge kept the edit's version, so the side branch is never walked
+        assert_eq!(
+            fx.history(&merge, "docs/guide.txt"),
+            [
+                (edit, Some(Fixture::blob_id("v2"))),
+                (base, Some(Fixture::blob_id("v1"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_only_the_path_is_read() {
+        let mut fx = Fixture::new();
+        let first = fx.commit(&[], Some("v1"), "large unrelated content");
+        let tip = fx.commit(&[&first], Some("v2"), "more unrelated content");
+
+        // Lose every blob but the one asked for; nothing else may be downloaded
+        for other in ["large unrelated content", "more unrelated content", "v1"] {
+            let content_id = fx.state.objects[&Fixture::blob_id(other)].clone();
+            fx.storage.delete_object(&content_id).unwrap();
+        }
+        assert_eq!(
+            read_path(&fx.storage, &fx.state, &tip, "docs/guide.txt").unwrap(),
+            b"v2"
+        );
+        assert_eq!(fx.history(&tip, "docs/guide.txt").len(), 2);
+    }
+}
@@ -9,6 +9,25 @@ use sha1::{Digest, Sha1};
 /// Git object SHA-1 identifier (40 hex characters)
 pub type ObjectId = String;
 
+/// Tree entry mode of a subdirectory
+pub const MODE_TREE: u32 = 0o040000;
+/// Tree entry mode of a regular file
+pub const MODE_FILE: u32 = 0o100644;
+/// Tree entry mode of an executable file
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+/// Tree entry mode of a symbolic link (the blob holds the target)
+pub const MODE_SYMLINK: u32 = 0o120000;
+/// Tree entry mode of a submodule commit (gitlink)
+pub const MODE_GITLINK: u32 = 0o160000;
+
+/// One entry of a tree object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: Vec<u8>,
+    pub id: ObjectId,
+}
+
 /// Represents a Git object with its content
 #[derive(Debug, Clone)]
 pub struct GitObject {
@@ -70,10 +89,118 @@ impl GitObject {
     }
 
     /// Get the object data without header
-    #[allow(dead_code)]
     pub fn data(&self) -> &[u8] {
         &self.data
     }
+
+    /// Object IDs this object points at (tree/parents, tag target, tree entries)
+    ///
+    /// Submodule entries (gitlinks) are skipped: they name commits in another repository.
+    pub fn references(&self) -> Result<Vec<ObjectId>> {
+        match self.kind {
+            Kind::Blob => Ok(Vec::new()),
+            Kind::Commit => Ok(header_values(&self.data, &["tree", "parent"])),
+            Kind::Tag => Ok(header_values(&self.data, &["object"])),
+            Kind::Tree => Ok(self
+                .tree_entries()?
+                .into_iter()
+                .filter(|entry| entry.mode != MODE_GITLINK)
+                .map(|entry| entry.id)
+                .collect()),
+        }
+    }
+
+    /// Entries of a tree object, in stored order
+    pub fn tree_entries(&self) -> Result<Vec<TreeEntry>> {
+        if self.kind != Kind::Tree {
+            anyhow::bail!("Object {} is a {}, not a tree", self.id, self.kind);
+        }
+
+        let mut entries = Vec::new();
+        let mut rest = self.data.as_slice();
+        while !rest.is_empty() {
+            let space = rest
+                .iter()
+                .position(|&b| b == b' ')
+                .context("Malformed tree entry: missing mode")?;
+            let null = rest
+                .iter()
+                .position(|&b| b == 0)
+                .context("Malformed tree entry: missing name terminator")?;
+            if space > null || rest.len() < null + 21 {
+                anyhow::bail!("Malformed tree entry: truncated object ID");
+            }
+            let mode = std::str::from_utf8(&rest[..space])
+                .ok()
+                .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+                .context("Malformed tree entry: invalid mode")?;
+            entries.push(TreeEntry {
+                mode,
+                name: rest[space + 1..null].to_vec(),
+                id: hex::encode(&rest[null + 1..null + 21]),
+            });
+            rest = &rest[null + 21..];
+        }
+        Ok(entries)
+    }
+
+    /// Tree of a commit, or target of a tag (the object a checkout starts from)
+    pub fn peel_target(&self) -> Option<ObjectId> {
+        let key = match self.kind {
+            Kind::Commit => "tree",
+            Kind::Tag => "object",
+            Kind::Tree | Kind::Blob => return None,
+        };
+        header_values(&self.data, &[key]).into_iter().next()
+    }
+
+    /// Parents of a commit (none for other objects)
+    pub fn parents(&self) -> Vec<ObjectId> {
+        match self.kind {
+            Kind::Commit => header_values(&self.data, &["parent"]),
+            _ => Vec::new(),
+        }
+    }
+
+    /// First non-blank line of a commit or tag message, decoded lossily
+    pub fn subject(&self) -> Option<String> {
+        if !matches!(self.kind, Kind::Commit | Kind::Tag) {
+            return None;
+        }
+        // The header block ends at the first blank line (continuation lines start with a space)
+        let body = self.data.windows(2).position(|pair| pair == b"\n\n")? + 2;
+        let line = self.data[body..]
+            .split(|&b| b == b'\n')
+            .map(<[u8]>::trim_ascii)
+            .find(|line| !line.is_empty())?;
+        Some(String::from_utf8_lossy(line).into_owned())
+    }
+}
+
+/// Object IDs in the `key value` header lines of a commit or tag, for the given keys
+///
+/// Works on the raw bytes: headers can come in any order, and `encoding`, `gpgsig` or `mergetag`
+/// headers and messages in any encoding are skipped without being decoded. Continuation lines
+/// of multi-line headers start with a space, so they never match a key.
+fn header_values(data: &[u8], keys: &[&str]) -> Vec<ObjectId> {
+    data.split(|&b| b == b'\n')
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| {
+            let space = line.iter().position(|&b| b == b' ')?;
+            let (key, value) = (&line[..space], &line[space + 1..]);
+            keys.iter()
+                .any(|k| k.as_bytes() == key)
+                .then(|| hex_object_id(value))?
+        })
+        .collect()
+}
+
+/// `value` as an object ID, if it is a SHA-1 (40) or SHA-256 (64) name in lowercase hex
+fn hex_object_id(value: &[u8]) -> Option<ObjectId> {
+    let value = value.trim_ascii_end();
+    let is_hex = |b: &u8| b.is_ascii_digit() || (b'a'..=b'f').contains(b);
+    (matches!(value.len(), 40 | 64) && value.iter().all(is_hex))
+        .then(|| String::from_utf8_lossy(value).into_owned())
 }
 
 /// Compute Git SHA-1 object ID from object type and data
@@ -143,6 +270,125 @@ mod tests {
         assert_eq!(id, "9daeafb9864cf43055ae93beb0afd6c7d144bfa4");
     }
 
+    #[test]
+    fn test_references() {
+        let blob_id = "9daeafb9864cf43055ae93beb0afd6c7d144bfa4";
+        let sub_id = "1111111111111111111111111111111111111111";
+
+        let mut tree_data = b"100644 file.txt\0".to_vec();
+        tree_data.extend(hex::decode(blob_id).unwrap());
+        tree_data.extend(b"160000 submodule\0");
+        tree_data.extend(hex::decode(sub_id).unwrap());
+        let tree = GitObject::from_raw(Kind::Tree, tree_data).unwrap();
+        assert_eq!(tree.references().unwrap(), vec![blob_id.to_string()]);
+
+        let commit_data = format!(
+            "tree {}\nparent {}\nauthor A <a@a> 0 +0000\ncommitter A <a@a> 0 +0000\n\nparent not-a-header\n",
+            tree.id, sub_id
+        );
+        let commit = GitObject::from_raw(Kind::Commit, commit_data.into_bytes()).unwrap();
+        assert_eq!(
+            commit.references().unwrap(),
+            vec![tree.id.clone(), sub_id.to_string()]
+        );
+
+        let tag_data = format!(
+            "object {}\ntype commit\ntag v1\ntagger A <a@a> 0 +0000\n\nRelease\n",
+            commit.id
+        );
+        let tag = GitObject::from_raw(Kind::Tag, tag_data.into_bytes()).unwrap();
+        assert_eq!(tag.references().unwrap(), vec![commit.id.clone()]);
+        assert_eq!(tag.peel_target(), Some(commit.id.clone()));
+        assert_eq!(commit.peel_target(), Some(tree.id.clone()));
+        assert_eq!(tree.peel_target(), None);
+        assert_eq!(
+            tree.tree_entries().unwrap()[1],
+            TreeEntry {
+                mode: MODE_GITLINK,
+                name: b"submodule".to_vec(),
+                id: sub_id.to_string(),
+            }
+        );
+
+        let blob = GitObject::from_raw(Kind::Blob, b"test\n".to_vec()).unwrap();
+        assert!(blob.references().unwrap().is_empty());
+
+        assert_eq!(commit.subject().as_deref(), Some("parent not-a-header"));
+        assert_eq!(tag.subject().as_deref(), Some("Release"));
+        assert_eq!(blob.subject(), None);
+        let signed = format!(
+            "tree {}\ngpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n -----END PGP \
+             SIGNATURE-----\n\n\n  Caf\u{e9} subject \nbody\n",
+            tree.id
+        );
+        let signed = GitObject::from_raw(Kind::Commit, signed.into_bytes()).unwrap();
+        assert_eq!(signed.subject().as_deref(), Some("Caf\u{e9} subject"));
+    }
+
+    #[test]
+    fn test_commit_headers_from_raw_bytes() {
+        let tree = "a".repeat(40);
+        let (first, second) = ("b".repeat(40), "c".repeat(40));
+        let tagged = "d".repeat(40);
+
+        // A signed merge of a signed tag, with a Latin-1 author and message
+        let mut data = format!("tree {}\nparent {}\nparent {}\n", tree, first, second).into_bytes();
+        data.extend(b"author Ren\xe9 <r@r> 0 +0000\ncommitter Ren\xe9 <r@r> 0 +0000\n");
+        data.extend(b"encoding ISO-8859-1\n");
+        data.extend(
+            format!(
+                "mergetag object {}\n type commit\n tag v1\n tagger T <t@t> 0 +0000\n \n \
+                 Release\n -----BEGIN PGP SIGNATURE-----\n parent {}\n \
+                 -----END PGP SIGNATURE-----\n",
+                tagged, tagged
+            )
+            .as_bytes(),
+        );
+        data.extend(b"gpgsig -----BEGIN PGP SIGNATURE-----\n \n tree \xff\xfe\n");
+        data.extend(b" -----END PGP SIGNATURE-----\n");
+        data.extend(b"\nMerge tag 'v1' \xe0 la Ren\xe9\n\nparent ");
+        data.extend(tagged.as_bytes());
+        data.extend(b"\n");
+
+        let commit = GitObject::from_raw(Kind::Commit, data.clone()).unwrap();
+        assert_eq!(
+            commit.references().unwrap(),
+            vec![tree.clone(), first.clone(), second.clone()]
+        );
+        assert_eq!(commit.peel_target(), Some(tree.clone()));
+
+        // Headers can come in any order
+        let reordered = format!(
+            "parent {}\nencoding UTF-8\ntree {}\nparent {}\n\nReordered\n",
+            first, tree, second
+        );
+        let reordered = GitObject::from_raw(Kind::Commit, reordered.into_bytes()).unwrap();
+        assert_eq!(
+            reordered.references().unwrap(),
+            vec![first.clone(), tree.clone(), second.clone()]
+        );
+
+        // Values that aren't object IDs aren't followed
+        let malformed = format!("tree {}\nparent {}\nparent ABC\n\n", tree, &first[..39]);
+        let malformed = GitObject::from_raw(Kind::Commit, malformed.into_bytes()).unwrap();
+        assert_eq!(malformed.references().unwrap(), vec![tree.clone()]);
+
+        // SHA-256 names (32-byte IDs) are followed too
+        let (wide_tree, wide_parent) = ("e".repeat(64), "f".repeat(64));
+        let wide = format!("tree {}\nparent {}\n\nWide\n", wide_tree, wide_parent);
+        let wide = GitObject::from_raw(Kind::Commit, wide.into_bytes()).unwrap();
+        assert_eq!(
+            wide.references().unwrap(),
+            vec![wide_tree.clone(), wide_parent]
+        );
+        assert_eq!(wide.peel_target(), Some(wide_tree));
+
+        // The object's bytes, and so its ID, are kept as they were
+        let parsed = GitObject::from_loose_format(&commit.to_loose_format()).unwrap();
+        assert_eq!(parsed.data, data);
+        assert_eq!(parsed.id, commit.id);
+    }
+
     #[test]
     fn test_loose_format_roundtrip() {
         let obj = GitObject::from_raw(Kind::Blob, b"hello world\n".to_vec()).unwrap();
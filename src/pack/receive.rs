@@ -1,27 +1,53 @@
 //! Receive pack files during push operations
 
 use std::{
-    io::{Read, Write},
-    process::{Command, Stdio},
+    collections::BTreeMap,
+    io::{self, BufRead, Read},
+    path::Path,
 };
 
 use anyhow::{Context, Result};
+use rayon::prelude::*;
 use tempfile::TempDir;
 
 use super::objects::{read_loose_object, GitObject, ObjectId};
-use crate::storage::{ContentId, StorageBackend};
+use crate::{
+    session_env,
+    storage::{ContentId, StorageBackend},
+    subprocess::CommandRunner,
+};
+
+/// [`receive_pack_with_epochs`] into an empty remote, with the backend's default epochs and
+/// every available core
+#[cfg(test)]
+pub fn receive_pack<R: Read>(
+    pack_stream: &mut R,
+    storage: &impl StorageBackend,
+) -> Result<Vec<(ObjectId, ContentId)>> {
+    receive_pack_with_epochs(pack_stream, storage, &BTreeMap::new(), None, 0)
+}
 
 /// Receive a packfile from stdin, unpack it, and store objects in the backend
 ///
 /// Flow:
 /// 1. Receive packfile from stdin
-/// 2. Use `git index-pack` to unpack to temporary location
-/// 3. Read unpacked loose objects
+/// 2. Use `git unpack-objects` to unpack to temporary location
+/// 3. Read unpacked loose objects, checking the count against the pack header
 /// 4. Store each object in immutable storage
 /// 5. Return mapping of object IDs to storage content IDs
-pub fn receive_pack<R: Read>(
+///
+/// Newly uploaded objects are stored for `epochs` epochs (None uses the backend's default);
+/// unpacked objects are read back on `decompress_threads` threads (0 uses every available
+/// core).
+///
+/// Objects in `stored` (the remote's objects map) keep the ContentId it gives them and are
+/// only cached locally, so a push from a lost or fresh cache doesn't upload history again.
+pub fn receive_pack_with_epochs<R: Read>(
     pack_stream: &mut R,
     storage: &impl StorageBackend,
+    stored: &BTreeMap<ObjectId, ContentId>,
+    epochs: Option<u32>,
+    decompress_threads: usize,
 ) -> Result<Vec<(ObjectId, ContentId)>> {
     // Create temporary directory for unpacking
     let temp_dir = TempDir::new().context("Failed to create temp directory")?;
@@ -39,78 +65,222 @@ pub fn receive_pack<R: Read>(
 
     tracing::info!("Received pack of {} bytes", pack_data.len());
 
-    // Unpack using git unpack-objects (creates loose objects, not a pack)
-    let mut unpack = Command::new("git")
-        .arg("--git-dir")
-        .arg(&git_dir)
-        .arg("unpack-objects")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped()) // Capture stdout
-        .stderr(Stdio::piped()) // Capture stderr
-        .spawn()
-        .context("Failed to spawn git unpack-objects")?;
-
-    // Write pack data to git unpack-objects stdin
-    unpack
-        .stdin
-        .as_mut()
-        .unwrap()
-        .write_all(&pack_data)
-        .context("Failed to write pack to git unpack-objects")?;
-
-    let output = unpack
-        .wait_with_output()
-        .context("Failed to wait for git unpack-objects")?;
-
-    if !output.status.success() {
-        tracing::error!(
-            "git unpack-objects stdout: {}",
-            String::from_utf8_lossy(&output.stdout)
+    // Unpack into the fresh scratch repository and check nothing was skipped
+    let expected = unpack_objects(&git_dir, pack_data)?;
+    let objects = collect_unpacked_objects(&git_dir, expected, decompress_threads)?;
+    tracing::info!("Unpacked {} objects", objects.len());
+
+    // Store objects in immutable storage using batched write
+    // Collect all object contents first
+    let contents_owned: Vec<Vec<u8>> = objects.iter().map(|obj| obj.to_loose_format()).collect();
+
+    // Objects the remote already stores are settled before batching, so they never count
+    // towards a blob; the rest are uploaded in one batch
+    let mut already_stored: Vec<(&[u8], &str)> = Vec::new();
+    let mut contents_refs: Vec<&[u8]> = Vec::new();
+    for (obj, content) in objects.iter().zip(&contents_owned) {
+        match stored.get(&obj.id) {
+            Some(content_id) => already_stored.push((content.as_slice(), content_id.as_str())),
+            None => contents_refs.push(content.as_slice()),
+        }
+    }
+    if !already_stored.is_empty() {
+        tracing::info!(
+            "Skipping {} objects the remote already stores",
+            already_stored.len()
         );
-        tracing::error!(
-            "git unpack-objects stderr: {}",
-            String::from_utf8_lossy(&output.stderr)
+        if let Err(e) = storage.cache_stored_objects(&already_stored) {
+            tracing::warn!("Failed to cache objects the remote already stores: {:#}", e);
+        }
+    }
+    tracing::info!("Uploading {} new objects", contents_refs.len());
+
+    // Batch write all objects
+    let mut content_ids = storage
+        .write_objects_with_epochs(&contents_refs, epochs)
+        .context("Failed to store objects in batch")?
+        .into_iter();
+
+    // Create mappings from object IDs to content IDs, in pack order
+    let mut mappings: Vec<(ObjectId, ContentId)> = Vec::with_capacity(objects.len());
+    for obj in &objects {
+        let content_id = match stored.get(&obj.id) {
+            Some(content_id) => content_id.clone(),
+            None => content_ids
+                .next()
+                .context("Storage returned fewer content IDs than objects")?,
+        };
+        tracing::debug!("Stored object {} -> {}", obj.id, content_id);
+        mappings.push((obj.id.clone(), content_id));
+    }
+
+    Ok(mappings)
+}
+
+/// Read exactly one pack from `input`, leaving whatever follows it unread
+///
+/// Packs don't state their length, so each object's header is parsed and its zlib stream
+/// inflated to find where the next one starts.
+pub fn read_pack<R: BufRead>(input: &mut R) -> Result<Vec<u8>> {
+    let mut reader = Recorder {
+        inner: input,
+        bytes: Vec::new(),
+    };
+    let mut header = [0u8; PACK_HEADER_LEN];
+    reader
+        .read_exact(&mut header)
+        .context("Failed to read pack header")?;
+    let count = pack_object_count(&header)?;
+    for index in 0..count {
+        skip_pack_object(&mut reader)
+            .with_context(|| format!("Failed to read object {} of {} in pack", index, count))?;
+    }
+    let mut checksum = [0u8; PACK_CHECKSUM_LEN];
+    reader
+        .read_exact(&mut checksum)
+        .context("Failed to read pack checksum")?;
+    Ok(reader.bytes)
+}
+
+/// Size of a pack's trailing SHA-1 checksum
+const PACK_CHECKSUM_LEN: usize = 20;
+
+/// Read past one object entry of a pack
+fn skip_pack_object<R: BufRead>(reader: &mut R) -> Result<()> {
+    let mut byte = read_byte(reader)?;
+    let kind = (byte >> 4) & 0x7;
+    // The rest of the size varint
+    while byte & 0x80 != 0 {
+        byte = read_byte(reader)?;
+    }
+    match kind {
+        // commit, tree, blob, tag
+        1..=4 => {}
+        // OFS_DELTA: offset of the base as a varint
+        6 => while read_byte(reader)? & 0x80 != 0 {},
+        // REF_DELTA: SHA-1 of the base
+        7 => {
+            let mut base = [0u8; 20];
+            reader.read_exact(&mut base)?;
+        }
+        other => anyhow::bail!("Unknown pack object type {}", other),
+    }
+    // The bufread decoder consumes only the compressed stream itself
+    let mut inflated = flate2::bufread::ZlibDecoder::new(reader);
+    io::copy(&mut inflated, &mut io::sink()).context("Failed to inflate pack object")?;
+    Ok(())
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte).context("Pack ended early")?;
+    Ok(byte[0])
+}
+
+/// A reader that keeps a copy of every byte consumed from `inner`
+struct Recorder<'a, R> {
+    inner: &'a mut R,
+    bytes: Vec<u8>,
+}
+
+impl<R: BufRead> Read for Recorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Recorder<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // Callers consume from a buffer they just filled, so this reads nothing new
+        if let Ok(buffered) = self.inner.fill_buf() {
+            self.bytes
+                .extend_from_slice(&buffered[..amt.min(buffered.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// Size of a pack header: `PACK`, version and object count
+const PACK_HEADER_LEN: usize = 12;
+
+/// Object count declared in a pack's header
+fn pack_object_count(pack_data: &[u8]) -> Result<u32> {
+    let header: &[u8; PACK_HEADER_LEN] = pack_data
+        .get(..PACK_HEADER_LEN)
+        .and_then(|header| header.try_into().ok())
+        .with_context(|| format!("Pack is too short for a header ({} bytes)", pack_data.len()))?;
+    if &header[..4] != b"PACK" {
+        anyhow::bail!("Pack does not start with the PACK signature");
+    }
+    let version = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    if version != 2 && version != 3 {
+        anyhow::bail!("Unsupported pack version {}", version);
+    }
+    Ok(u32::from_be_bytes(header[8..12].try_into().unwrap()))
+}
+
+/// Unpack `pack_data` as loose objects into `git_dir`, returning the pack's object count
+///
+/// `git unpack-objects` silently skips objects the repository already has, so the scratch
+/// repository must start out empty for the count to be checked afterwards.
+fn unpack_objects(git_dir: &Path, pack_data: Vec<u8>) -> Result<u32> {
+    let expected = pack_object_count(&pack_data).context("Received an invalid pack")?;
+
+    let preexisting = loose_object_paths(git_dir)?.len();
+    if preexisting != 0 {
+        anyhow::bail!(
+            "Scratch repository {} already holds {} objects; refusing to unpack into it",
+            git_dir.display(),
+            preexisting
         );
-        anyhow::bail!("git unpack-objects failed with status: {}", output.status);
     }
 
+    // Unpack using git unpack-objects (creates loose objects, not a pack)
+    let output = CommandRunner::git_scratch(git_dir)
+        .arg("unpack-objects")
+        .stdin(pack_data)
+        .run()?;
+
     // Log the unpack-objects output to stderr
     tracing::debug!(
         "git unpack-objects: {}",
         String::from_utf8_lossy(&output.stderr)
     );
 
-    // Collect all unpacked objects from .git/objects
-    let objects = collect_loose_objects(&git_dir)?;
-    tracing::info!("Unpacked {} objects", objects.len());
-
-    // Store objects in immutable storage using batched write
-    // Collect all object contents first
-    let contents_owned: Vec<Vec<u8>> = objects.iter().map(|obj| obj.to_loose_format()).collect();
-
-    // Create slice references for write_objects
-    let contents_refs: Vec<&[u8]> = contents_owned.iter().map(|c| c.as_slice()).collect();
-
-    // Batch write all objects
-    let content_ids = storage
-        .write_objects(&contents_refs)
-        .context("Failed to store objects in batch")?;
-
-    // Create mappings from object IDs to content IDs
-    let mappings: Vec<(ObjectId, ContentId)> = objects
-        .iter()
-        .zip(content_ids.iter())
-        .map(|(obj, content_id)| {
-            tracing::debug!("Stored object {} -> {}", obj.id, content_id);
-            (obj.id.clone(), content_id.clone())
-        })
-        .collect();
+    Ok(expected)
+}
 
-    Ok(mappings)
+/// Collect the objects unpacked into `git_dir`, failing unless there are exactly `expected`
+///
+/// A shortfall means objects were skipped or unreadable; storing the rest would publish refs
+/// whose history can't be cloned.
+fn collect_unpacked_objects(
+    git_dir: &Path,
+    expected: u32,
+    threads: usize,
+) -> Result<Vec<GitObject>> {
+    let objects = collect_loose_objects(git_dir, threads)?;
+    if objects.len() != expected as usize {
+        anyhow::bail!(
+            "Pack declares {} objects but {} were unpacked from it; refusing to store an \
+             incomplete set of objects",
+            expected,
+            objects.len()
+        );
+    }
+    Ok(objects)
 }
 
 /// Initialize minimal bare repository structure
-fn init_bare_repo(git_dir: &std::path::Path) -> Result<()> {
+fn init_bare_repo(git_dir: &Path) -> Result<()> {
     std::fs::create_dir_all(git_dir.join("objects")).context("Failed to create objects dir")?;
     std::fs::create_dir_all(git_dir.join("refs")).context("Failed to create refs dir")?;
 
@@ -121,10 +291,54 @@ fn init_bare_repo(git_dir: &std::path::Path) -> Result<()> {
     Ok(())
 }
 
-/// Collect all loose objects from a git objects directory
-fn collect_loose_objects(git_dir: &std::path::Path) -> Result<Vec<GitObject>> {
+/// Threads used to read loose objects back when none are configured
+pub fn default_decompress_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Collect all loose objects from a git objects directory, sorted by object ID
+///
+/// Inflating thousands of objects dominates large pushes, so they are read on `threads`
+/// threads (0 uses every available core); sorting keeps the result, and so the batches
+/// built from it, independent of scheduling.
+fn collect_loose_objects(git_dir: &Path, threads: usize) -> Result<Vec<GitObject>> {
+    let paths = loose_object_paths(git_dir)?;
+    let threads = match threads {
+        0 => default_decompress_threads(),
+        threads => threads,
+    };
+
+    let read = |path: &std::path::PathBuf| match read_loose_object(path) {
+        Ok(obj) => Some(obj),
+        Err(e) => {
+            tracing::warn!("Failed to read object {}: {}", path.display(), e);
+            None
+        }
+    };
+    let mut objects: Vec<GitObject> = if threads == 1 {
+        paths.iter().filter_map(read).collect()
+    } else {
+        let session = session_env::snapshot();
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .context("Failed to start decompression threads")?
+            .install(|| {
+                paths
+                    .par_iter()
+                    .filter_map(|path| session_env::enter(session.clone(), || read(path)))
+                    .collect()
+            })
+    };
+    objects.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(objects)
+}
+
+/// Paths of the loose objects in a git objects directory
+fn loose_object_paths(git_dir: &Path) -> Result<Vec<std::path::PathBuf>> {
     let objects_dir = git_dir.join("objects");
-    let mut objects = Vec::new();
+    let mut paths = Vec::new();
 
     // Iterate over 2-char subdirectories (00..ff)
     for entry in std::fs::read_dir(&objects_dir)
@@ -146,28 +360,17 @@ fn collect_loose_objects(git_dir: &std::path::Path) -> Result<Vec<GitObject>> {
             continue;
         }
 
-        // Read objects in this subdirectory
         for obj_entry in std::fs::read_dir(&path)
             .with_context(|| format!("Failed to read object subdir: {}", path.display()))?
         {
-            let obj_entry = obj_entry.context("Failed to read object entry")?;
-            let obj_path = obj_entry.path();
-
-            if !obj_path.is_file() {
-                continue;
-            }
-
-            // Read the loose object
-            match read_loose_object(&obj_path) {
-                Ok(obj) => objects.push(obj),
-                Err(e) => {
-                    tracing::warn!("Failed to read object {}: {}", obj_path.display(), e);
-                }
+            let obj_path = obj_entry.context("Failed to read object entry")?.path();
+            if obj_path.is_file() {
+                paths.push(obj_path);
             }
         }
     }
 
-    Ok(objects)
+    Ok(paths)
 }
 
 #[cfg(test)]
@@ -184,4 +387,237 @@ mod tests {
         assert!(git_dir.join("refs").exists());
         assert!(git_dir.join("HEAD").exists());
     }
+
+    /// A pack holding `blobs`, built with git in a scratch repository
+    fn make_pack(temp: &Path, blobs: &[&str]) -> Vec<u8> {
+        let git_dir = temp.join("source.git");
+        init_bare_repo(&git_dir).unwrap();
+        let mut ids = String::new();
+        for blob in blobs {
+            let output = CommandRunner::git_scratch(&git_dir)
+                .args(["hash-object", "-w", "--stdin"])
+                .stdin(*blob)
+                .run()
+                .unwrap();
+            ids.push_str(&String::from_utf8_lossy(&output.stdout));
+        }
+        CommandRunner::git_scratch(&git_dir)
+            .args(["pack-objects", "--stdout"])
+            .stdin(ids)
+            .run()
+            .unwrap()
+            .stdout
+    }
+
+    #[test]
+    fn test_pack_object_count() {
+        let temp = TempDir::new().unwrap();
+        let pack = make_pack(temp.path(), &["one", "two", "three"]);
+        assert_eq!(pack_object_count(&pack).unwrap(), 3);
+
+        assert!(pack_object_count(&pack[..11]).is_err());
+        let mut bad = pack.clone();
+        bad[0] = b'X';
+        assert!(pack_object_count(&bad).is_err());
+        let mut bad = pack;
+        bad[7] = 9;
+        assert!(pack_object_count(&bad).is_err());
+    }
+
+    #[test]
+    fn test_unpacked_object_count_must_match_pack() {
+        let temp = TempDir::new().unwrap();
+        let pack = make_pack(temp.path(), &["one", "two", "three"]);
+
+        let git_dir = temp.path().join("scratch.git");
+        init_bare_repo(&git_dir).unwrap();
+        let expected = unpack_objects(&git_dir, pack.clone()).unwrap();
+        assert_eq!(
+            collect_unpacked_objects(&git_dir, expected, 0)
+                .unwrap()
+                .len(),
+            3
+        );
+
+        // Unpacking again into the same repository would skip everything
+        assert!(unpack_objects(&git_dir, pack)
+            .unwrap_err()
+            .to_string()
+            .contains("already holds 3 objects"));
+
+        // Lose one object before collection
+        let victim = collect_loose_objects(&git_dir, 0).unwrap()[0].id.clone();
+        std::fs::remove_file(
+            git_dir
+                .join("objects")
+                .join(&victim[..2])
+                .join(&victim[2..]),
+        )
+        .unwrap();
+        let err = collect_unpacked_objects(&git_dir, expected, 0).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Pack declares 3 objects but 2 were unpacked"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_parallel_collection_matches_sequential() {
+        use std::io::Write;
+
+        use flate2::{write::ZlibEncoder, Compression};
+
+        // A few thousand small loose objects, as a large push unpacks
+        let temp = TempDir::new().unwrap();
+        let git_dir = temp.path().join("scratch.git");
+        init_bare_repo(&git_dir).unwrap();
+        for i in 0..3000 {
+            let obj = GitObject::from_raw(
+                gix_object::Kind::Blob,
+                format!("object {}\n", i).repeat(20).into_bytes(),
+            )
+            .unwrap();
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&obj.to_loose_format()).unwrap();
+            let dir = git_dir.join("objects").join(&obj.id[..2]);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(&obj.id[2..]), encoder.finish().unwrap()).unwrap();
+        }
+
+        let sequential = collect_loose_objects(&git_dir, 1).unwrap();
+        let parallel = collect_loose_objects(&git_dir, 4).unwrap();
+
+        assert_eq!(parallel.len(), 3000);
+        let ids = |objects: &[GitObject]| objects.iter().map(|o| o.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&parallel), ids(&sequential));
+        assert!(ids(&parallel).windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(parallel
+            .iter()
+            .zip(&sequential)
+            .all(|(a, b)| a.to_loose_format() == b.to_loose_format()));
+    }
+
+    #[test]
+    fn test_read_pack_stops_at_its_end() {
+        let temp = TempDir::new().unwrap();
+        // Similar blobs, so git stores some as deltas
+        let text = "line of text\n".repeat(200);
+        let edited = format!("{}one more line\n", text);
+        let pack = make_pack(temp.path(), &[&text, &edited, "short"]);
+
+        let mut input = pack.clone();
+        input.extend_from_slice(b"push refs/heads/dev:refs/heads/dev\n");
+        let mut reader = io::BufReader::with_capacity(64, &input[..]);
+        assert_eq!(read_pack(&mut reader).unwrap(), pack);
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "push refs/heads/dev:refs/heads/dev\n");
+
+        // A truncated pack is an error, not a short read
+        assert!(read_pack(&mut &pack[..pack.len() - 1]).is_err());
+    }
+
+    /// Memory storage that records how objects are written
+    #[derive(Default)]
+    struct CountingStorage {
+        memory: crate::storage::MemoryStorage,
+        /// Object count of each batched write
+        batches: std::cell::RefCell<Vec<usize>>,
+        singles: std::cell::Cell<usize>,
+    }
+
+    impl crate::storage::ImmutableStore for CountingStorage {
+        fn write_object(&self, content: &[u8]) -> Result<ContentId> {
+            self.singles.set(self.singles.get() + 1);
+            self.memory.write_object(content)
+        }
+
+        fn write_objects(&self, contents: &[&[u8]]) -> Result<Vec<ContentId>> {
+            self.batches.borrow_mut().push(contents.len());
+            self.memory.write_objects(contents)
+        }
+
+        fn read_object(&self, id: &str) -> Result<Vec<u8>> {
+            self.memory.read_object(id)
+        }
+
+        fn read_objects(&self, ids: &[&str]) -> Result<Vec<Vec<u8>>> {
+            self.memory.read_objects(ids)
+        }
+
+        fn delete_object(&self, id: &str) -> Result<()> {
+            self.memory.delete_object(id)
+        }
+
+        fn object_exists(&self, id: &str) -> Result<bool> {
+            self.memory.object_exists(id)
+        }
+    }
+
+    impl crate::storage::MutableState for CountingStorage {
+        fn read_state(&self) -> Result<crate::storage::State> {
+            self.memory.read_state()
+        }
+
+        fn write_state(&self, state: &crate::storage::State) -> Result<()> {
+            self.memory.write_state(state)
+        }
+
+        fn update_state<F>(&self, update_fn: F) -> Result<()>
+        where
+            F: FnOnce(&mut crate::storage::State) -> Result<()>,
+        {
+            self.memory.update_state(update_fn)
+        }
+    }
+
+    impl StorageBackend for CountingStorage {
+        fn initialize(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pack_is_stored_in_one_batched_write() {
+        let temp = TempDir::new().unwrap();
+        let pack = make_pack(temp.path(), &["one", "two", "three", "four"]);
+        let storage = CountingStorage::default();
+
+        let mappings = receive_pack(&mut pack.as_slice(), &storage).unwrap();
+
+        // One batch for the whole pack, so the backend can pack objects into shared blobs
+        assert_eq!(mappings.len(), 4);
+        assert_eq!(*storage.batches.borrow(), [4]);
+        assert_eq!(storage.singles.get(), 0);
+    }
+
+    #[test]
+    fn test_objects_the_remote_stores_are_not_written() {
+        let temp = TempDir::new().unwrap();
+        let pack = make_pack(temp.path(), &["one", "two", "three", "four"]);
+        let first = receive_pack(&mut pack.as_slice(), &CountingStorage::default()).unwrap();
+
+        // The remote's map already lists two of the pack's objects, in blobs of its own
+        let stored: BTreeMap<ObjectId, ContentId> = first
+            .iter()
+            .take(2)
+            .enumerate()
+            .map(|(i, (id, _))| (id.clone(), format!("0xremote:{}:10", i * 10)))
+            .collect();
+        let storage = CountingStorage::default();
+        let mappings =
+            receive_pack_with_epochs(&mut pack.as_slice(), &storage, &stored, None, 0).unwrap();
+
+        assert_eq!(*storage.batches.borrow(), [2]);
+        let ids: Vec<&ObjectId> = mappings.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, first.iter().map(|(id, _)| id).collect::<Vec<_>>());
+        for (id, content_id) in &mappings {
+            match stored.get(id) {
+                Some(remote) => assert_eq!(content_id, remote),
+                None => assert!(storage.memory.object_exists(content_id).unwrap()),
+            }
+        }
+    }
 }
@@ -1,181 +1,962 @@
 //! Send pack files during fetch operations
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::OpenOptions,
     io::Write,
-    path::PathBuf,
-    process::{Command, Stdio},
+    path::Path,
+    str::FromStr,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result};
-use tempfile::TempDir;
+use flate2::{write::ZlibEncoder, Compression};
+use gix_object::Kind;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
 
-use super::objects::{write_loose_object, GitObject, ObjectId};
-use crate::storage::{State, StorageBackend};
+use super::{
+    objects::{GitObject, ObjectId, MODE_GITLINK, MODE_TREE},
+    shallow::{Deepen, ShallowCut},
+};
+use crate::{
+    session_env,
+    storage::{ContentId, State, StorageBackend},
+};
+
+/// Levels of the graph read ahead of the pack writer
+const PIPELINE_LEVELS: usize = 4;
+
+/// Blobs read per batch once only blobs are left, so the pack keeps growing as they arrive
+const BLOB_BATCH: usize = 500;
+
+/// Order in which a fetch reads the objects it sends, and so downloads the blobs holding them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrefetchStrategy {
+    /// Walk the commits first, then read trees and blobs from the oldest commit forward
+    ///
+    /// The default: every tree is read before any blob, so the pack's object count is known
+    /// and its first bytes are written while the blobs, most of a fetch, still download.
+    #[default]
+    Topo,
+    /// Read level by level from the wanted refs, downloading the largest blobs of each read first
+    Size,
+    /// Read level by level from the wanted refs, downloading blobs in the order requested
+    None,
+}
+
+impl FromStr for PrefetchStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "topo" => Ok(Self::Topo),
+            "size" => Ok(Self::Size),
+            "none" => Ok(Self::None),
+            other => anyhow::bail!(
+                "invalid prefetch strategy {:?} (expected 'topo', 'size' or 'none')",
+                other
+            ),
+        }
+    }
+}
+
+impl fmt::Display for PrefetchStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Topo => write!(f, "topo"),
+            Self::Size => write!(f, "size"),
+            Self::None => write!(f, "none"),
+        }
+    }
+}
+
+/// Where each object of a pack `send_pack` wrote landed, to trace git's complaints about the
+/// pack back to the stored objects
+#[derive(Debug, Default)]
+pub struct PackLayout {
+    /// Pack offset and SHA-1 of each object, by offset
+    offsets: Vec<(u64, ObjectId)>,
+    /// ContentId each packed object was read from
+    content_ids: HashMap<ObjectId, ContentId>,
+    /// Commits sent and where history was cut off, for a shallow fetch
+    pub shallow: Option<ShallowCut>,
+    /// How long the reads and the pack took
+    pub metrics: SendMetrics,
+}
+
+/// Timings of one pack sent, in milliseconds from the start of the send
+///
+/// Fetches append them to `WALRUS_REMOTE_METRICS_FILE`, one JSON object per line.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SendMetrics {
+    pub strategy: PrefetchStrategy,
+    pub objects: usize,
+    pub pack_bytes: u64,
+    /// Until the first objects were read
+    pub first_read_ms: Option<u64>,
+    /// Until the first byte of the pack was written
+    pub first_pack_byte_ms: Option<u64>,
+    /// Until the whole pack was written
+    pub total_ms: u64,
+}
+
+impl SendMetrics {
+    /// Append these metrics to `path` as a line of JSON
+    pub fn append_to(&self, path: &Path) -> Result<()> {
+        let mut line = serde_json::to_string(self).context("Failed to serialize metrics")?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("Failed to append metrics to {}", path.display()))
+    }
+}
+
+/// Milliseconds in `elapsed`, as metrics record them
+fn millis(elapsed: Duration) -> u64 {
+    elapsed.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+impl PackLayout {
+    /// The object whose entry covers pack `offset`
+    pub fn object_at(&self, offset: u64) -> Option<&ObjectId> {
+        let end = self.offsets.partition_point(|(start, _)| *start <= offset);
+        end.checked_sub(1).map(|i| &self.offsets[i].1)
+    }
+
+    /// ContentId the packed object `id` was read from
+    pub fn content_id(&self, id: &str) -> Option<&ContentId> {
+        self.content_ids.get(id)
+    }
+}
 
 /// Send a packfile to stdout for the requested refs
 ///
 /// Flow:
-/// 1. Determine which objects are needed (from wanted refs)
-/// 2. Retrieve objects from storage
-/// 3. Write objects as loose files to temporary git repo
-/// 4. Use `git pack-objects` to create packfile
-/// 5. Stream packfile to stdout
-pub fn send_pack<W: Write>(
+/// 1. Determine which objects are needed (reachable from wanted refs only)
+/// 2. Retrieve objects from storage, in the backend's prefetch order
+/// 3. Write each object to the pack on another thread as it arrives: from the start with
+///    `PrefetchStrategy::Topo`, which knows the object count before reading blobs, else once
+///    every object is read
+///
+/// Objects are packed whole, never as deltas.
+///
+/// Only the objects the walk reaches are downloaded, but the objects map itself is still read
+/// whole: the state format has no way to look up single object IDs.
+///
+/// Returns where each object landed in the pack.
+pub fn send_pack<W: Write + Send>(
     wanted_refs: &[String],
     storage: &impl StorageBackend,
     output: &mut W,
-) -> Result<()> {
-    let state = storage.read_state()?;
+) -> Result<PackLayout> {
+    send_pack_with_deepen(wanted_refs, storage, None, output)
+}
 
-    // Collect object IDs for all wanted refs
-    let wanted_objects = collect_wanted_objects(wanted_refs, &state)?;
-    tracing::info!("Need to send {} objects", wanted_objects.len());
+/// Send a packfile for the requested refs without the objects in `haves`
+///
+/// `haves` must be everything the receiver has, closed under reachability (as `git rev-list
+/// --objects` lists it): the walk stops at them, so an incremental update reads only what is
+/// new.
+pub fn send_pack_with_haves<W: Write + Send>(
+    wanted_refs: &[String],
+    haves: &HashSet<ObjectId>,
+    storage: &impl StorageBackend,
+    output: &mut W,
+) -> Result<PackLayout> {
+    send_pack_excluding(wanted_refs, storage, None, Some(haves), output)
+}
+
+/// Send a packfile for the requested refs, leaving out the history `deepen` cuts off
+///
+/// The layout's `shallow` holds the commits sent and the ones sent without their parents.
+pub fn send_pack_with_deepen<W: Write + Send>(
+    wanted_refs: &[String],
+    storage: &impl StorageBackend,
+    deepen: Option<&Deepen>,
+    output: &mut W,
+) -> Result<PackLayout> {
+    send_pack_excluding(wanted_refs, storage, deepen, None, output)
+}
 
-    if wanted_objects.is_empty() {
+/// [`send_pack_with_deepen`], leaving out `haves` as well
+fn send_pack_excluding<W: Write + Send>(
+    wanted_refs: &[String],
+    storage: &impl StorageBackend,
+    deepen: Option<&Deepen>,
+    haves: Option<&HashSet<ObjectId>>,
+    output: &mut W,
+) -> Result<PackLayout> {
+    let started = Instant::now();
+    let state = storage.read_state()?;
+    let mut roots = wanted_roots(wanted_refs, &state);
+    if let Some(haves) = haves {
+        roots.retain(|root| !haves.contains(root));
+    }
+    let shallow = deepen
+        .map(|deepen| deepen.cut(&roots, &state, storage))
+        .transpose()?;
+    if let Some(cut) = &shallow {
+        tracing::info!(
+            "Shallow fetch: {} commit(s), {} cut off from their parents",
+            cut.commits.len(),
+            cut.boundary.len()
+        );
+    }
+
+    // Pack the objects reachable from the wanted refs on another thread as they are read
+    let strategy = storage.prefetch_strategy();
+    let mut writer = PackWriter::new(output, started);
+    pipeline(
+        |send| {
+            let commits = shallow.as_ref().map(|cut| &cut.commits);
+            walk_reachable_objects(&roots, &state, storage, strategy, commits, haves, send)
+        },
+        |walked| writer.write_walked(walked),
+    )?;
+    let (offsets, mut metrics) = writer.finish()?;
+    metrics.strategy = strategy;
+    if offsets.is_empty() {
         tracing::info!("No objects to send");
+        return Ok(PackLayout {
+            shallow,
+            metrics,
+            ..Default::default()
+        });
+    }
+    tracing::info!(
+        "Pack of {} objects ({} bytes) written after {:?}",
+        offsets.len(),
+        metrics.pack_bytes,
+        started.elapsed()
+    );
+
+    let content_ids = offsets
+        .iter()
+        .filter_map(|(_, id)| {
+            let content_id = state.objects.get(id)?.clone();
+            Some((id.clone(), content_id))
+        })
+        .collect();
+    Ok(PackLayout {
+        offsets,
+        content_ids,
+        shallow,
+        metrics,
+    })
+}
+
+/// Run `produce` on this thread and `consume` on another, handing items over through a channel
+/// holding up to `PIPELINE_LEVELS` of them
+///
+/// The consumer's error wins: a producer failing to hand over only means the consumer stopped.
+fn pipeline<T: Send, R: Send>(
+    produce: impl FnOnce(&mut dyn FnMut(T) -> Result<()>) -> Result<()>,
+    consume: impl FnOnce(Receiver<T>) -> Result<R> + Send,
+) -> Result<R> {
+    let (sender, receiver) = mpsc::sync_channel(PIPELINE_LEVELS);
+    let session = session_env::snapshot();
+    thread::scope(|scope| {
+        let consumer = scope.spawn(move || session_env::enter(session, || consume(receiver)));
+        let produced = produce(&mut |item| {
+            sender
+                .send(item)
+                .map_err(|_| anyhow::anyhow!("Pipeline consumer stopped"))
+        });
+        drop(sender);
+        let consumed = consumer.join().expect("pipeline consumer panicked")?;
+        produced?;
+        Ok(consumed)
+    })
+}
+
+/// Writes a pack to `output` as the walk reads its objects
+///
+/// The pack header holds the object count, so entries are held back until the walk says how
+/// many objects it yields, and written as they arrive from then on.
+struct PackWriter<'a, W: Write> {
+    output: &'a mut W,
+    hasher: Sha1,
+    /// Bytes written so far
+    written: u64,
+    /// Object count the header declared, once it is written
+    declared: Option<usize>,
+    /// Entries encoded before the header could be written
+    held: Vec<(ObjectId, Vec<u8>)>,
+    /// Pack offset and SHA-1 of each entry written, by offset
+    offsets: Vec<(u64, ObjectId)>,
+    started: Instant,
+    metrics: SendMetrics,
+}
+
+impl<'a, W: Write> PackWriter<'a, W> {
+    fn new(output: &'a mut W, started: Instant) -> Self {
+        Self {
+            output,
+            hasher: Sha1::new(),
+            written: 0,
+            declared: None,
+            held: Vec::new(),
+            offsets: Vec::new(),
+            started,
+            metrics: SendMetrics::default(),
+        }
+    }
+
+    /// Pack everything the walk hands over
+    fn write_walked(&mut self, walked: Receiver<Walked>) -> Result<()> {
+        for item in walked {
+            match item {
+                Walked::Objects(level) => {
+                    if self.metrics.first_read_ms.is_none() {
+                        tracing::info!(
+                            "First {} object(s) read after {:?}",
+                            level.len(),
+                            self.started.elapsed()
+                        );
+                        self.metrics.first_read_ms = Some(millis(self.started.elapsed()));
+                    }
+                    for obj in level {
+                        let entry = encode_entry(&obj)
+                            .with_context(|| format!("Failed to pack object {}", obj.id))?;
+                        match self.declared {
+                            Some(_) => self.write_entry(obj.id, &entry)?,
+                            None => self.held.push((obj.id, entry)),
+                        }
+                    }
+                }
+                Walked::Total(count) => self.declare(count)?,
+            }
+            self.output.flush().context("Failed to write packfile")?;
+        }
+        Ok(())
+    }
+
+    /// Write the header for `count` objects, then the entries held back for it
+    fn declare(&mut self, count: usize) -> Result<()> {
+        if count == 0 {
+            return Ok(());
+        }
+        let count = u32::try_from(count).context("Too many objects for one pack")?;
+        let mut header = b"PACK".to_vec();
+        header.extend(2u32.to_be_bytes());
+        header.extend(count.to_be_bytes());
+        self.write(&header)?;
+        self.declared = Some(count as usize);
+        for (id, entry) in std::mem::take(&mut self.held) {
+            self.write_entry(id, &entry)?;
+        }
+        Ok(())
+    }
+
+    fn write_entry(&mut self, id: ObjectId, entry: &[u8]) -> Result<()> {
+        self.offsets.push((self.written, id));
+        self.write(entry)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.metrics.first_pack_byte_ms.is_none() {
+            tracing::info!(
+                "First pack bytes written after {:?}",
+                self.started.elapsed()
+            );
+            self.metrics.first_pack_byte_ms = Some(millis(self.started.elapsed()));
+        }
+        self.output
+            .write_all(bytes)
+            .context("Failed to write packfile")?;
+        self.hasher.update(bytes);
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Declare whatever was held back if the walk never gave a count, and end the pack with
+    /// its checksum; returns where each object landed, and the metrics
+    fn finish(mut self) -> Result<(Vec<(u64, ObjectId)>, SendMetrics)> {
+        if self.declared.is_none() {
+            self.declare(self.held.len())?;
+        }
+        if let Some(declared) = self.declared {
+            if self.offsets.len() != declared {
+                anyhow::bail!(
+                    "Pack declares {} objects but the walk read {}",
+                    declared,
+                    self.offsets.len()
+                );
+            }
+            let checksum = std::mem::take(&mut self.hasher).finalize();
+            self.write(&checksum)?;
+            self.output.flush().context("Failed to write packfile")?;
+        }
+        self.metrics.objects = self.offsets.len();
+        self.metrics.pack_bytes = self.written;
+        self.metrics.total_ms = millis(self.started.elapsed());
+        Ok((self.offsets, self.metrics))
+    }
+}
+
+/// `obj`'s pack entry: its type and size, then its data compressed (never a delta)
+fn encode_entry(obj: &GitObject) -> Result<Vec<u8>> {
+    let kind: u8 = match obj.kind {
+        Kind::Commit => 1,
+        Kind::Tree => 2,
+        Kind::Blob => 3,
+        Kind::Tag => 4,
+    };
+    let data = obj.data();
+    let mut size = data.len() as u64;
+    let mut entry = vec![(kind << 4) | (size & 0x0f) as u8];
+    size >>= 4;
+    while size > 0 {
+        *entry.last_mut().unwrap() |= 0x80;
+        entry.push((size & 0x7f) as u8);
+        size >>= 7;
+    }
+    let mut encoder = ZlibEncoder::new(entry, Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Object IDs the wanted refs point at (a wanted object ID stands for itself)
+///
+/// Only the wanted refs are walked, so a single-branch clone of a tag reads no other tag's
+/// objects (fetch adds the remaining tags itself for `git fetch --tags`).
+/// Annotated tags point at the tag object itself, so it is sent along with its target.
+fn wanted_roots(wanted_refs: &[String], state: &State) -> Vec<ObjectId> {
+    let mut seen = HashSet::new();
+    wanted_refs
+        .iter()
+        .filter_map(|wanted| {
+            state
+                .refs
+                .get(wanted)
+                .or_else(|| state.objects.get_key_value(wanted).map(|(id, _)| id))
+        })
+        .filter(|id| seen.insert(id.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Walk commits, trees and tags from `roots`, reading each level of the graph in one batch
+pub fn collect_reachable_objects(
+    roots: &[ObjectId],
+    state: &State,
+    storage: &impl StorageBackend,
+) -> Result<Vec<GitObject>> {
+    let mut result = Vec::new();
+    let mut collect = |walked: Walked| -> Result<()> {
+        if let Walked::Objects(level) = walked {
+            result.extend(level);
+        }
+        Ok(())
+    };
+    walk_reachable_objects(
+        roots,
+        state,
+        storage,
+        PrefetchStrategy::None,
+        None,
+        None,
+        &mut collect,
+    )?;
+    Ok(result)
+}
+
+/// What a walk hands over as it reads
+pub enum Walked {
+    /// A level of the graph, as read
+    Objects(Vec<GitObject>),
+    /// How many objects the walk hands over in all, once only blobs it knows of are left
+    Total(usize),
+}
+
+/// Walk the objects reachable from `roots` a level of the graph at a time, handing each level
+/// to `visit` as soon as it is read
+///
+/// `PrefetchStrategy::Topo` walks the commits and tags first, then the trees from the oldest
+/// commit's tree forward, setting their blobs aside; it gives the total before reading those
+/// blobs. The other strategies walk every kind of object together, from the roots back. With
+/// `commits`, only those commits are walked (a shallow fetch's cut); with `haves`, the walk
+/// never enters those objects.
+pub fn walk_reachable_objects(
+    roots: &[ObjectId],
+    state: &State,
+    storage: &impl StorageBackend,
+    strategy: PrefetchStrategy,
+    commits: Option<&HashSet<ObjectId>>,
+    haves: Option<&HashSet<ObjectId>>,
+    visit: &mut dyn FnMut(Walked) -> Result<()>,
+) -> Result<()> {
+    let parents = |obj: &GitObject| -> Vec<ObjectId> {
+        let mut parents = obj.parents();
+        if let Some(commits) = commits {
+            parents.retain(|parent| commits.contains(parent));
+        }
+        parents
+    };
+
+    let mut seen: HashSet<ObjectId> = roots
+        .iter()
+        .chain(haves.into_iter().flatten())
+        .cloned()
+        .collect();
+    if strategy != PrefetchStrategy::Topo {
+        walk_levels(
+            roots.to_vec(),
+            &mut seen,
+            state,
+            storage,
+            |obj| match (obj.kind, commits) {
+                (Kind::Commit, Some(_)) => {
+                    Ok(obj.peel_target().into_iter().chain(parents(obj)).collect())
+                }
+                _ => obj.references(),
+            },
+            visit,
+        )?;
         return Ok(());
     }
 
-    // Create temporary git repository
-    let temp_dir = TempDir::new().context("Failed to create temp directory")?;
-    let git_dir = temp_dir.path().join("repo.git");
-    std::fs::create_dir(&git_dir).context("Failed to create git dir")?;
-    init_bare_repo(&git_dir)?;
+    // Commits first, setting their trees (and anything a tag names directly) aside
+    let mut deferred = Vec::new();
+    let mut read = walk_levels(
+        roots.to_vec(),
+        &mut seen,
+        state,
+        storage,
+        |obj| match obj.kind {
+            Kind::Commit => {
+                deferred.extend(obj.peel_target());
+                Ok(parents(obj))
+            }
+            Kind::Tag => Ok(obj.peel_target().into_iter().collect()),
+            Kind::Tree | Kind::Blob => {
+                deferred.extend(obj.references()?);
+                Ok(Vec::new())
+            }
+        },
+        visit,
+    )?;
+
+    // Commits were found from the roots back, so the oldest trees were set aside last; their
+    // blobs are leaves, named by the trees, and left for last
+    deferred.reverse();
+    let frontier = deferred
+        .into_iter()
+        .filter(|id| seen.insert(id.clone()))
+        .collect();
+    let mut blobs = Vec::new();
+    read += walk_levels(
+        frontier,
+        &mut seen,
+        state,
+        storage,
+        |obj| match obj.kind {
+            Kind::Tree => {
+                let mut subtrees = Vec::new();
+                for entry in obj.tree_entries()? {
+                    match entry.mode {
+                        MODE_TREE => subtrees.push(entry.id),
+                        MODE_GITLINK => {}
+                        _ => blobs.push(entry.id),
+                    }
+                }
+                Ok(subtrees)
+            }
+            _ => obj.references(),
+        },
+        visit,
+    )?;
+    blobs.retain(|id| seen.insert(id.clone()));
 
-    // Retrieve objects from storage and write as loose objects
-    let objects_dir = git_dir.join("objects");
+    visit(Walked::Total(read + blobs.len()))?;
+    for batch in blobs.chunks(BLOB_BATCH) {
+        visit(Walked::Objects(read_level(batch, state, storage)?))?;
+    }
+    Ok(())
+}
 
-    // Collect all content IDs first for batch reading
-    let content_ids: Result<Vec<_>> = wanted_objects
+/// Read `frontier`, then the objects `follow` leads to from each object read, a level at a time;
+/// returns how many objects were read
+fn walk_levels(
+    mut frontier: Vec<ObjectId>,
+    seen: &mut HashSet<ObjectId>,
+    state: &State,
+    storage: &impl StorageBackend,
+    mut follow: impl FnMut(&GitObject) -> Result<Vec<ObjectId>>,
+    visit: &mut dyn FnMut(Walked) -> Result<()>,
+) -> Result<usize> {
+    let mut read = 0;
+    while !frontier.is_empty() {
+        let level = read_level(&frontier, state, storage)?;
+        let mut next = Vec::new();
+        for obj in &level {
+            for referenced in
+                follow(obj).with_context(|| format!("Failed to parse references of {}", obj.id))?
+            {
+                if seen.insert(referenced.clone()) {
+                    next.push(referenced);
+                }
+            }
+        }
+        read += level.len();
+        visit(Walked::Objects(level))?;
+        frontier = next;
+    }
+
+    Ok(read)
+}
+
+/// Read and parse `ids` in one batch (deduplicates blob fetches, verifies each object's SHA-1)
+fn read_level(
+    ids: &[ObjectId],
+    state: &State,
+    storage: &impl StorageBackend,
+) -> Result<Vec<GitObject>> {
+    let objects = ids
         .iter()
         .map(|obj_id| {
             state
                 .objects
                 .get(obj_id)
-                .map(|id| id.as_str())
+                .map(|id| (obj_id.as_str(), id.as_str()))
                 .with_context(|| format!("Object {} not found in state", obj_id))
         })
-        .collect();
-    let content_ids = content_ids?;
+        .collect::<Result<Vec<_>>>()?;
 
-    // Batch read all objects (deduplicates blob fetches)
-    tracing::info!(
-        "Batch reading {} objects from storage",
-        wanted_objects.len()
-    );
+    tracing::debug!("Batch reading {} objects from storage", objects.len());
     let contents = storage
-        .read_objects(&content_ids)
+        .read_git_objects(&objects)
         .context("Failed to batch read objects from storage")?;
+    ids.iter()
+        .zip(contents)
+        .map(|(obj_id, content)| {
+            GitObject::from_loose_format(&content)
+                .with_context(|| format!("Failed to parse object {}", obj_id))
+        })
+        .collect()
+}
 
-    // Write each object as a loose object
-    for (obj_id, content) in wanted_objects.iter().zip(contents.iter()) {
-        // Parse and write as loose object
-        let obj = GitObject::from_loose_format(content)
-            .with_context(|| format!("Failed to parse object {}", obj_id))?;
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::AtomicBool, Arc, Mutex};
 
-        write_loose_object(&obj, &objects_dir)
-            .with_context(|| format!("Failed to write loose object {}", obj_id))?;
+    use super::*;
+    use crate::storage::{store_object, ImmutableStore, MemoryStorage, MutableState};
 
-        tracing::debug!("Wrote object {} to temp repo", obj_id);
+    fn state() -> State {
+        let mut state = State::default();
+        state.refs.insert("refs/heads/main".into(), "c1".into());
+        state.refs.insert("refs/tags/v1".into(), "t1".into());
+        state.refs.insert("refs/tags/orphan".into(), "t2".into());
+        state.refs.insert("refs/tags/same".into(), "c1".into());
+        state
     }
 
-    // Create packfile using git pack-objects
-    create_packfile(&git_dir, &wanted_objects, output)?;
+    #[test]
+    fn test_wanted_roots_branches_only() {
+        let roots = wanted_roots(&["refs/heads/main".to_string()], &state());
+        assert_eq!(roots, vec!["c1".to_string()]);
+    }
 
-    Ok(())
-}
+    #[test]
+    fn test_wanted_roots_only_named_tags() {
+        // A clone of one tag doesn't pull in the others
+        let roots = wanted_roots(&["refs/tags/v1".to_string()], &state());
+        assert_eq!(roots, vec!["t1"]);
 
-/// Collect all objects reachable from wanted refs
-fn collect_wanted_objects(wanted_refs: &[String], state: &State) -> Result<Vec<ObjectId>> {
-    let mut result = Vec::new();
-    let mut seen = HashSet::new();
+        // Tags naming an object already wanted add nothing
+        let wanted: Vec<String> = ["refs/heads/main", "refs/tags/same", "refs/tags/orphan"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        assert_eq!(wanted_roots(&wanted, &state()), vec!["c1", "t2"]);
+    }
+
+    #[test]
+    fn test_wanted_object_ids() {
+        let mut state = state();
+        state.objects.insert("c0".into(), "0xblob:0:10".into());
+
+        // A detached HEAD is fetched by object ID; unknown names and IDs are ignored
+        let roots = wanted_roots(&["c0".to_string(), "missing".to_string()], &state);
+        assert_eq!(roots, vec!["c0"]);
+    }
+
+    #[test]
+    fn test_pack_layout_maps_offsets_to_objects() {
+        let storage = MemoryStorage::new();
+        let mut ids = Vec::new();
+        for data in ["first blob\n", "second blob\n"] {
+            let blob = GitObject::from_raw(Kind::Blob, data.as_bytes().to_vec()).unwrap();
+            let content_id = storage.write_object(&blob.to_loose_format()).unwrap();
+            ids.push((blob.id, content_id));
+        }
+        storage
+            .update_state(|state| {
+                for (i, (id, content_id)) in ids.iter().enumerate() {
+                    state.objects.insert(id.clone(), content_id.clone());
+                    state.refs.insert(format!("refs/tags/b{}", i), id.clone());
+                }
+                Ok(())
+            })
+            .unwrap();
 
-    for ref_name in wanted_refs {
-        if let Some(commit_id) = state.refs.get(ref_name) {
-            // For now, we'll do a simple approach: collect all objects in state
-            // TODO: Implement proper graph traversal
-            if seen.insert(commit_id.clone()) {
-                result.push(commit_id.clone());
+        let wanted = vec!["refs/tags/b0".to_string(), "refs/tags/b1".to_string()];
+        let mut pack = Vec::new();
+        let layout = send_pack(&wanted, &storage, &mut pack).unwrap();
+
+        // Entries start after the 12-byte pack header; the second starts where the first ends
+        assert_eq!(layout.offsets.len(), 2);
+        assert_eq!(layout.offsets[0].0, 12);
+        assert_eq!(layout.object_at(11), None);
+        assert_eq!(layout.object_at(12), Some(&layout.offsets[0].1));
+        let second = layout.offsets[1].0;
+        assert_eq!(layout.object_at(second - 1), Some(&layout.offsets[0].1));
+        assert_eq!(layout.object_at(second + 1), Some(&layout.offsets[1].1));
+        for (id, content_id) in &ids {
+            assert_eq!(layout.content_id(id), Some(content_id));
+        }
+    }
+
+    /// A chain of `len` commits, each with a tree holding one blob of its own; returns the
+    /// commits, trees and blobs, oldest first
+    fn history(storage: &MemoryStorage, len: usize) -> [Vec<ObjectId>; 3] {
+        let (mut commits, mut trees, mut blobs): (Vec<ObjectId>, Vec<_>, Vec<_>) =
+            Default::default();
+        for i in 0..len {
+            let blob = store_object(storage, Kind::Blob, format!("version {}\n", i).into_bytes());
+            let mut tree = b"100644 file\0".to_vec();
+            tree.extend(hex::decode(&blob).unwrap());
+            let tree = store_object(storage, Kind::Tree, tree);
+            let mut commit = format!("tree {}\n", tree);
+            if let Some(parent) = commits.last() {
+                commit.push_str(&format!("parent {}\n", parent));
             }
+            commit.push_str("author A <a@a> 0 +0000\ncommitter A <a@a> 0 +0000\n\nc\n");
+            commits.push(store_object(storage, Kind::Commit, commit.into_bytes()));
+            trees.push(tree);
+            blobs.push(blob);
         }
+        [commits, trees, blobs]
+    }
+
+    /// The levels `strategy` reads from `roots`
+    fn levels(
+        storage: &MemoryStorage,
+        roots: &[ObjectId],
+        strategy: PrefetchStrategy,
+    ) -> Vec<Vec<ObjectId>> {
+        let state = storage.read_state().unwrap();
+        let mut levels = Vec::new();
+        walk_reachable_objects(
+            roots,
+            &state,
+            storage,
+            strategy,
+            None,
+            None,
+            &mut |walked| {
+                if let Walked::Objects(level) = walked {
+                    levels.push(level.into_iter().map(|obj| obj.id).collect());
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+        levels
     }
 
-    // For now, return all objects in state (simplification)
-    // TODO: Implement proper reachability analysis
-    for obj_id in state.objects.keys() {
-        if seen.insert(obj_id.clone()) {
-            result.push(obj_id.clone());
+    #[test]
+    fn test_topo_prefetch_reads_oldest_commits_first() {
+        let storage = MemoryStorage::new();
+        let [c, t, b] = history(&storage, 3);
+        let tip = [c[2].clone()];
+
+        // Commits first, then trees and blobs from the oldest commit forward
+        assert_eq!(
+            levels(&storage, &tip, PrefetchStrategy::Topo),
+            vec![
+                vec![c[2].clone()],
+                vec![c[1].clone()],
+                vec![c[0].clone()],
+                t.clone(),
+                b.clone(),
+            ]
+        );
+
+        // Without it, each level mixes kinds, newest first
+        assert_eq!(
+            levels(&storage, &tip, PrefetchStrategy::None),
+            vec![
+                vec![c[2].clone()],
+                vec![t[2].clone(), c[1].clone()],
+                vec![b[2].clone(), t[1].clone(), c[0].clone()],
+                vec![b[1].clone(), t[0].clone()],
+                vec![b[0].clone()],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shallow_cut_limits_the_walk() {
+        let storage = MemoryStorage::new();
+        let [c, t, b] = history(&storage, 3);
+        let state = storage.read_state().unwrap();
+        let deepen = Deepen {
+            depth: Some(2),
+            ..Default::default()
+        };
+        let cut = deepen.cut(&[c[2].clone()], &state, &storage).unwrap();
+        assert_eq!(cut.boundary, vec![c[1].clone()]);
+
+        // Both strategies stop at the boundary commit, leaving its parent and snapshot out
+        for strategy in [PrefetchStrategy::Topo, PrefetchStrategy::None] {
+            let mut sent = Vec::new();
+            walk_reachable_objects(
+                &[c[2].clone()],
+                &state,
+                &storage,
+                strategy,
+                Some(&cut.commits),
+                None,
+                &mut |walked| {
+                    if let Walked::Objects(level) = walked {
+                        sent.extend(level.into_iter().map(|obj| obj.id));
+                    }
+                    Ok(())
+                },
+            )
+            .unwrap();
+            sent.sort();
+            let mut expected = [&c[1..], &t[1..], &b[1..]].concat();
+            expected.sort();
+            assert_eq!(sent, expected, "{}", strategy);
         }
     }
 
-    Ok(result)
-}
+    /// Collects a pack, announcing its first bytes
+    struct Announcing {
+        pack: Vec<u8>,
+        first_bytes: Option<mpsc::Sender<()>>,
+    }
 
-/// Initialize minimal bare repository structure
-fn init_bare_repo(git_dir: &std::path::Path) -> Result<()> {
-    std::fs::create_dir_all(git_dir.join("objects")).context("Failed to create objects dir")?;
-    std::fs::create_dir_all(git_dir.join("refs")).context("Failed to create refs dir")?;
+    impl Write for Announcing {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if let Some(first_bytes) = self.first_bytes.take() {
+                let _ = first_bytes.send(());
+            }
+            self.pack.extend_from_slice(buf);
+            Ok(buf.len())
+        }
 
-    std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n")
-        .context("Failed to write HEAD")?;
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
 
-    Ok(())
-}
+    #[test]
+    fn test_pack_streams_before_blobs_are_read() {
+        // Four commit levels and the trees are read first; the blobs, read sixth, are only
+        // served once the pack has started
+        let (first_bytes, started) = mpsc::channel();
+        let started = Mutex::new(started);
+        let streamed = Arc::new(AtomicBool::new(false));
+        let storage = MemoryStorage::with_read_hook(PrefetchStrategy::Topo, {
+            let streamed = streamed.clone();
+            move |batch| {
+                if batch == 6 {
+                    let arrived = started
+                        .lock()
+                        .unwrap()
+                        .recv_timeout(Duration::from_secs(10))
+                        .is_ok();
+                    streamed.store(arrived, std::sync::atomic::Ordering::SeqCst);
+                }
+            }
+        });
+        let [c, t, b] = history(&storage, 4);
+        storage
+            .update_state(|state| {
+                state.refs.insert("refs/heads/main".into(), c[3].clone());
+                Ok(())
+            })
+            .unwrap();
 
-/// Create packfile from loose objects using git pack-objects
-fn create_packfile<W: Write>(
-    git_dir: &PathBuf,
-    object_ids: &[ObjectId],
-    output: &mut W,
-) -> Result<()> {
-    // git pack-objects reads object IDs from stdin, one per line
-    // Without --revs, it expects object SHAs directly
-    let mut pack_objects = Command::new("git")
-        .arg("--git-dir")
-        .arg(git_dir)
-        .arg("pack-objects")
-        .arg("--stdout")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .context("Failed to spawn git pack-objects")?;
-
-    // Write object IDs to stdin and close it
-    {
-        let stdin = pack_objects.stdin.as_mut().unwrap();
-        for obj_id in object_ids {
-            writeln!(stdin, "{}", obj_id).context("Failed to write object ID to pack-objects")?;
-        }
-        // Explicitly drop stdin to close the pipe
-        drop(pack_objects.stdin.take());
-    }
-
-    // Wait for pack-objects to finish and get output
-    let pack_output = pack_objects
-        .wait_with_output()
-        .context("Failed to wait for git pack-objects")?;
-
-    if !pack_output.status.success() {
-        tracing::error!(
-            "git pack-objects stderr: {}",
-            String::from_utf8_lossy(&pack_output.stderr)
-        );
-        anyhow::bail!(
-            "git pack-objects failed with status: {}",
-            pack_output.status
-        );
+        let mut output = Announcing {
+            pack: Vec::new(),
+            first_bytes: Some(first_bytes),
+        };
+        let layout = send_pack(&["refs/heads/main".to_string()], &storage, &mut output).unwrap();
+        assert!(streamed.load(std::sync::atomic::Ordering::SeqCst));
+
+        // Commits from the tip back, then trees and blobs from the oldest commit forward
+        let packed: Vec<&ObjectId> = layout.offsets.iter().map(|(_, id)| id).collect();
+        let expected: Vec<&ObjectId> = c.iter().rev().chain(&t).chain(&b).collect();
+        assert_eq!(packed, expected);
+
+        let metrics = &layout.metrics;
+        assert_eq!(metrics.strategy, PrefetchStrategy::Topo);
+        assert_eq!(metrics.objects, 12);
+        assert_eq!(metrics.pack_bytes, output.pack.len() as u64);
+        assert!(metrics.first_read_ms <= metrics.first_pack_byte_ms);
+        assert!(metrics.first_pack_byte_ms <= Some(metrics.total_ms));
+
+        // The pack is one git accepts
+        let clone = MemoryStorage::new();
+        let received =
+            crate::pack::receive::receive_pack(&mut output.pack.as_slice(), &clone).unwrap();
+        assert_eq!(received.len(), 12);
     }
 
-    // Write packfile to output
-    output
-        .write_all(&pack_output.stdout)
-        .context("Failed to write packfile to output")?;
+    #[test]
+    fn test_metrics_are_appended_per_pack() {
+        // Without topo order the count, and so the pack, waits until everything is read
+        let storage = MemoryStorage::new();
+        let [c, ..] = history(&storage, 2);
+        storage
+            .update_state(|state| {
+                state.refs.insert("refs/heads/main".into(), c[1].clone());
+                Ok(())
+            })
+            .unwrap();
+        let mut pack = Vec::new();
+        let layout = send_pack(&["refs/heads/main".to_string()], &storage, &mut pack).unwrap();
+        assert_eq!(&pack[8..12], 6u32.to_be_bytes());
 
-    tracing::info!(
-        "Packfile created successfully ({} bytes)",
-        pack_output.stdout.len()
-    );
-    Ok(())
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.jsonl");
+        layout.metrics.append_to(&path).unwrap();
+        layout.metrics.append_to(&path).unwrap();
+        let lines = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+        let line: serde_json::Value = serde_json::from_str(lines.lines().next().unwrap()).unwrap();
+        assert_eq!(line["strategy"], "none");
+        assert_eq!(line["objects"], 6);
+        assert_eq!(line["pack_bytes"], pack.len());
+    }
+
+    #[test]
+    fn test_pipeline_reports_the_consumers_error() {
+        let result: Result<()> = pipeline(
+            |send| {
+                for i in 0..100 {
+                    send(i)?;
+                }
+                Ok(())
+            },
+            |items: Receiver<u32>| {
+                let first = items.recv()?;
+                anyhow::bail!("consumer failed at {}", first)
+            },
+        );
+        assert_eq!(result.unwrap_err().to_string(), "consumer failed at 0");
+    }
 }
@@ -0,0 +1,355 @@
+//! Where a shallow fetch cuts history off (`option depth`, `deepen-since` and `deepen-not`)
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result};
+use gix_object::Kind;
+
+use super::{checkout::read_objects, graph::parse_commit, objects::ObjectId};
+use crate::{
+    storage::{State, StorageBackend},
+    subprocess::CommandRunner,
+};
+
+/// Levels of tags peeled, at most, to reach a commit
+const MAX_TAG_DEPTH: usize = 16;
+
+/// How much history a fetch asked for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Deepen {
+    /// Commits sent from each wanted tip, counting the tip (`git clone --depth`;
+    /// `--unshallow` asks for 2^31 - 1)
+    pub depth: Option<u32>,
+    /// Oldest committer time sent, in seconds since the Unix epoch (`--shallow-since`)
+    pub since: Option<i64>,
+    /// Stored refs or object IDs whose history is left out (`--shallow-exclude`)
+    pub not: Vec<String>,
+    /// `depth` counts from the client's shallow commits rather than the wanted tips
+    /// (`git fetch --deepen`)
+    pub relative: bool,
+    /// Commits the client has without their parents (its `shallow` file), whose history isn't
+    /// sent unless one of the limits above asks for it
+    pub shallow: HashSet<ObjectId>,
+}
+
+/// The commits a shallow fetch sends
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShallowCut {
+    /// Every commit sent
+    pub commits: HashSet<ObjectId>,
+    /// Commits sent without (some of) their parents, sorted, for the client's `shallow` file
+    pub boundary: Vec<ObjectId>,
+}
+
+impl Deepen {
+    /// True if the fetch asked for a depth, date or exclusion
+    pub fn is_requested(&self) -> bool {
+        self.depth.is_some() || self.since.is_some() || !self.not.is_empty()
+    }
+
+    /// Commits reachable from `roots` within the limits
+    ///
+    /// Only tags and commits are read. The wanted tips themselves are always sent, however old.
+    pub fn cut(
+        &self,
+        roots: &[ObjectId],
+        state: &State,
+        storage: &impl StorageBackend,
+    ) -> Result<ShallowCut> {
+        if self.relative && !self.shallow.is_empty() {
+            return self.cut_below_shallow(roots, state, storage);
+        }
+        let excluded = self.excluded(state, storage)?;
+
+        let mut frontier = peel_to_commits(roots, state, storage)?;
+        let mut seen: HashSet<ObjectId> = frontier.iter().cloned().collect();
+        let mut parents: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+        let mut generation = 1;
+        while !frontier.is_empty() {
+            let mut next = Vec::new();
+            for commit in read_objects(storage, state, frontier.iter().map(String::as_str))? {
+                if commit.kind != Kind::Commit {
+                    anyhow::bail!("{} is a {}, not a commit", commit.id, commit.kind);
+                }
+                let node = parse_commit(&commit);
+                let too_old = self.since.is_some_and(|since| node.timestamp < since);
+                if generation > 1 && (too_old || excluded.contains(&node.id)) {
+                    continue;
+                }
+                let deeper = self.depth.is_none_or(|depth| generation < depth);
+                if deeper && !self.shallow.contains(&node.id) {
+                    next.extend(
+                        node.parents
+                            .iter()
+                            .filter(|parent| seen.insert((*parent).clone()))
+                            .cloned(),
+                    );
+                }
+                parents.insert(node.id, node.parents);
+            }
+            frontier = next;
+            generation += 1;
+        }
+
+        let commits: HashSet<ObjectId> = parents.keys().cloned().collect();
+        let mut boundary: Vec<ObjectId> = parents
+            .into_iter()
+            .filter(|(_, parents)| parents.iter().any(|parent| !commits.contains(parent)))
+            .map(|(id, _)| id)
+            .collect();
+        boundary.sort();
+        Ok(ShallowCut { commits, boundary })
+    }
+
+    /// [`Deepen::cut`] for a `relative` fetch: what the client has down to its shallow
+    /// commits, and `depth` generations below each of those it reaches
+    fn cut_below_shallow(
+        &self,
+        roots: &[ObjectId],
+        state: &State,
+        storage: &impl StorageBackend,
+    ) -> Result<ShallowCut> {
+        let have = Deepen {
+            shallow: self.shallow.clone(),
+            ..Default::default()
+        }
+        .cut(roots, state, storage)?;
+        // The shallow commits themselves count as the first generation
+        let below = Deepen {
+            depth: self.depth.map(|depth| depth.saturating_add(1)),
+            relative: false,
+            shallow: HashSet::new(),
+            ..self.clone()
+        }
+        .cut(&have.boundary, state, storage)?;
+
+        let mut commits = have.commits;
+        commits.extend(below.commits);
+        Ok(ShallowCut {
+            commits,
+            boundary: below.boundary,
+        })
+    }
+
+    /// Every commit reachable from the `not` refs
+    fn excluded(&self, state: &State, storage: &impl StorageBackend) -> Result<HashSet<ObjectId>> {
+        let mut roots = Vec::with_capacity(self.not.len());
+        for name in &self.not {
+            let id = state
+                .refs
+                .get(name)
+                .or_else(|| state.objects.get_key_value(name).map(|(id, _)| id))
+                .with_context(|| {
+                    format!("deepen-not {} is not a ref or object on the remote", name)
+                })?;
+            roots.push(id.clone());
+        }
+
+        let mut frontier = peel_to_commits(&roots, state, storage)?;
+        let mut excluded: HashSet<ObjectId> = frontier.iter().cloned().collect();
+        while !frontier.is_empty() {
+            let mut next = Vec::new();
+            for commit in read_objects(storage, state, frontier.iter().map(String::as_str))? {
+                next.extend(
+                    commit
+                        .parents()
+                        .into_iter()
+                        .filter(|parent| excluded.insert(parent.clone())),
+                );
+            }
+            frontier = next;
+        }
+        Ok(excluded)
+    }
+}
+
+/// Parse `option deepen-since`: seconds since the Unix epoch, or any date git understands
+/// (`--shallow-since` passes the user's words through)
+pub fn parse_since(value: &str) -> Result<i64> {
+    if let Ok(seconds) = value.parse() {
+        return Ok(seconds);
+    }
+    let output = CommandRunner::git()
+        .arg("rev-parse")
+        .arg(format!("--since={}", value))
+        .run()?;
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .strip_prefix("--max-age=")
+        .and_then(|seconds| seconds.parse().ok())
+        .with_context(|| format!("Invalid deepen-since date {:?}", value))
+}
+
+/// The commits `roots` name, peeling annotated tags (refs to trees and blobs are dropped)
+fn peel_to_commits(
+    roots: &[ObjectId],
+    state: &State,
+    storage: &impl StorageBackend,
+) -> Result<Vec<ObjectId>> {
+    let mut commits = Vec::new();
+    let mut pending = roots.to_vec();
+    for _ in 0..MAX_TAG_DEPTH {
+        if pending.is_empty() {
+            break;
+        }
+        let mut next = Vec::new();
+        for obj in read_objects(storage, state, pending.iter().map(String::as_str))? {
+            match obj.kind {
+                Kind::Commit if !commits.contains(&obj.id) => commits.push(obj.id),
+                Kind::Tag => next.extend(obj.peel_target()),
+                _ => {}
+            }
+        }
+        pending = next;
+    }
+    Ok(commits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{store_object, MemoryStorage, MutableState};
+
+    /// A commit at committer time `time` (its tree is never read)
+    fn commit(storage: &MemoryStorage, time: i64, parents: &[&str]) -> ObjectId {
+        let mut data = format!("tree {}\n", "4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+        for parent in parents {
+            data.push_str(&format!("parent {}\n", parent));
+        }
+        data.push_str(&format!(
+            "author A <a@a> {0} +0000\ncommitter A <a@a> {0} +0000\n\nc{0}\n",
+            time
+        ));
+        store_object(storage, Kind::Commit, data)
+    }
+
+    fn sorted(commits: &HashSet<ObjectId>) -> Vec<ObjectId> {
+        let mut commits: Vec<ObjectId> = commits.iter().cloned().collect();
+        commits.sort();
+        commits
+    }
+
+    #[test]
+    fn test_cut() {
+        // c1 - c2 - c3 - merge, with a side branch s2 off c1 merged in
+        let storage = MemoryStorage::new();
+        let c1 = commit(&storage, 100, &[]);
+        let c2 = commit(&storage, 200, &[&c1]);
+        let c3 = commit(&storage, 300, &[&c2]);
+        let s2 = commit(&storage, 250, &[&c1]);
+        let merge = commit(&storage, 400, &[&c3, &s2]);
+        let tag = store_object(
+            &storage,
+            Kind::Tag,
+            format!("object {}\ntype commit\ntag v1\n\nv1\n", merge),
+        );
+        storage
+            .update_state(|state| {
+                state.refs.insert("refs/heads/old".to_string(), c2.clone());
+                Ok(())
+            })
+            .unwrap();
+        let state = storage.read_state().unwrap();
+        let cut = |deepen: Deepen| deepen.cut(&[tag.clone()], &state, &storage).unwrap();
+
+        // --depth 1: the tip alone, cut off from both parents
+        let tip = cut(Deepen {
+            depth: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(sorted(&tip.commits), vec![merge.clone()]);
+        assert_eq!(tip.boundary, vec![merge.clone()]);
+
+        // --depth 2: both parents, each cut off
+        let two = cut(Deepen {
+            depth: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(two.commits.len(), 3);
+        let mut parents = vec![c3.clone(), s2.clone()];
+        parents.sort();
+        assert_eq!(two.boundary, parents);
+
+        // --shallow-since 260 leaves s2 out, so the merge is cut off from it too
+        let since = cut(Deepen {
+            since: Some(260),
+            ..Default::default()
+        });
+        let mut expected = vec![merge.clone(), c3.clone()];
+        expected.sort();
+        assert_eq!(sorted(&since.commits), expected);
+        assert_eq!(since.boundary, expected);
+
+        // --shallow-exclude old: everything c2 reaches is left out
+        let not = cut(Deepen {
+            not: vec!["refs/heads/old".to_string()],
+            ..Default::default()
+        });
+        let mut expected = vec![merge.clone(), c3.clone(), s2.clone()];
+        expected.sort();
+        assert_eq!(sorted(&not.commits), expected);
+        let mut boundary = vec![c3.clone(), s2.clone()];
+        boundary.sort();
+        assert_eq!(not.boundary, boundary);
+
+        // No limits (or --unshallow): everything, with no boundary
+        let full = cut(Deepen::default());
+        assert_eq!(full.commits.len(), 5);
+        assert!(full.boundary.is_empty());
+        let unshallow = cut(Deepen {
+            depth: Some(0x7fff_ffff),
+            ..Default::default()
+        });
+        assert_eq!(unshallow, full);
+
+        // A shallow client fetching without limits gets nothing behind its shallow commits
+        let kept = cut(Deepen {
+            shallow: [c3.clone()].into(),
+            ..Default::default()
+        });
+        let mut expected = vec![merge.clone(), c3.clone(), s2.clone(), c1.clone()];
+        expected.sort();
+        assert_eq!(sorted(&kept.commits), expected);
+        assert_eq!(kept.boundary, vec![c3.clone()]);
+
+        // --deepen 1 from a --depth 1 clone: one generation below the tip, as --depth 2 gets
+        let deepened = cut(Deepen {
+            depth: Some(1),
+            relative: true,
+            shallow: [merge.clone()].into(),
+            ..Default::default()
+        });
+        assert_eq!(deepened, two);
+        // and once more: c2 now has its parent c1, which s2 reaches, so nothing is cut off
+        let deepened = cut(Deepen {
+            depth: Some(1),
+            relative: true,
+            shallow: [c3.clone(), s2.clone()].into(),
+            ..Default::default()
+        });
+        assert_eq!(deepened, full);
+
+        let missing = Deepen {
+            not: vec!["refs/heads/gone".to_string()],
+            ..Default::default()
+        };
+        assert!(missing.cut(&[tag], &state, &storage).is_err());
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(parse_since("1700000000").unwrap(), 1_700_000_000);
+        assert_eq!(
+            parse_since("2023-11-14 22:13:20 +0000").unwrap(),
+            1_700_000_000
+        );
+
+        // Relative dates are resolved now, as git resolves them for native fetches
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let yesterday = parse_since("1 day ago").unwrap();
+        assert!((now - 86_400 - 60..=now - 86_400 + 60).contains(&yesterday));
+    }
+}
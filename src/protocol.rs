@@ -1,75 +1,295 @@
-use std::io::{self, BufRead, Write};
+use std::{
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
 
 use anyhow::Result;
 
-use crate::{commands, storage::StorageBackend};
+use crate::{
+    commands,
+    commands::{
+        capabilities::CapabilitySet,
+        hooks::Hooks,
+        list::TagOnlyHead,
+        namespace::RefNamespace,
+        push_cert::{PushCertMode, PushCertPolicy, PushSigning},
+        push_options::PushOptions,
+    },
+    pack::shallow::{parse_since, Deepen},
+    storage::{MappingConflict, StorageBackend},
+};
+
+mod reader;
+mod writer;
+
+pub use reader::ProtocolReader;
+pub use writer::ProtocolWriter;
+
+/// Per-remote settings for a helper session
+#[derive(Debug, Clone, Default)]
+pub struct SessionOptions {
+    /// Ref namespace from the remote URL's repo suffix
+    pub namespace: RefNamespace,
+    /// Advertise and accept only fetch-related commands
+    pub read_only: bool,
+    /// Globs limiting the refs `list` advertises (empty advertises every ref)
+    pub advertise_ref_patterns: Vec<String>,
+    /// HEAD advertised when the remote has only tags
+    pub tag_only_head: TagOnlyHead,
+    /// Pre-push and post-fetch hooks
+    pub hooks: Hooks,
+    /// Whether pushes must be signed, and by whom
+    pub push_certs: PushCertPolicy,
+    /// What pushes do with objects already stored under a different content ID
+    pub on_mapping_conflict: MappingConflict,
+    /// Threads reading pushed objects back after unpacking (0 uses every core)
+    pub decompress_threads: usize,
+    /// Remote URL git invoked the helper with
+    pub url: String,
+    /// Where git keeps fast-export marks for pushes (under `$GIT_DIR` when git runs the helper)
+    pub marks_file: Option<PathBuf>,
+}
+
+impl SessionOptions {
+    /// Signed push settings for a push after git sent `option pushcert <mode>`
+    pub fn signing(&self, mode: PushCertMode) -> PushSigning<'_> {
+        PushSigning {
+            mode,
+            policy: &self.push_certs,
+            pushee: &self.url,
+        }
+    }
+}
+
+/// What git asked for with `option` lines, for the pushes that follow
+#[derive(Debug, Clone)]
+pub struct PushRequest {
+    /// `option pushcert`, for `git push --signed`
+    pub cert_mode: PushCertMode,
+    /// `option atomic`, for `git push --atomic`
+    pub atomic: bool,
+    /// `option push-option`, for `git push --push-option`
+    pub push_options: PushOptions,
+    /// `option verbosity`: 0 for `git push --quiet`, 1 by default, one more per `--verbose`
+    pub verbosity: u32,
+}
+
+impl Default for PushRequest {
+    fn default() -> Self {
+        Self {
+            cert_mode: PushCertMode::default(),
+            atomic: false,
+            push_options: PushOptions::default(),
+            verbosity: 1,
+        }
+    }
+}
+
+/// What git asked for with `option` lines, for the fetches that follow
+#[derive(Debug, Clone, Default)]
+pub struct FetchRequest {
+    /// `option depth`, `deepen-since`, `deepen-not` and `deepen-relative`, for shallow fetches
+    pub deepen: Deepen,
+    /// `option followtags`: git fetches tags pointing into what it fetched itself, so tag refs
+    /// it asks for are all it wants (a single-branch clone of a tag)
+    pub follow_tags: bool,
+}
 
 /// Main protocol handler - reads commands from stdin and dispatches them
-pub fn handle_commands<S: StorageBackend>(storage: S) -> Result<()> {
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
-    let reader = stdin.lock();
+pub fn handle_commands<S: StorageBackend>(
+    storage: S,
+    remote_name: &str,
+    options: &SessionOptions,
+) -> Result<()> {
+    let mut output = ProtocolWriter::stdout();
+    run_session(
+        &storage,
+        remote_name,
+        options,
+        io::stdin().lock(),
+        &mut output,
+    )
+}
 
-    let mut lines = reader.lines();
+/// Serve one helper session read from `input` and answered on `output` (a daemon's
+/// connection to a helper) rather than stdio
+pub fn serve_session<S: StorageBackend, R: BufRead, W: Write>(
+    storage: &S,
+    remote_name: &str,
+    options: &SessionOptions,
+    input: R,
+    output: W,
+) -> Result<()> {
+    let mut output = ProtocolWriter::new(output);
+    run_session(storage, remote_name, options, input, &mut output)?;
+    output.flush()
+}
 
-    #[allow(clippy::while_let_on_iterator)]
-    while let Some(line) = lines.next() {
-        let line = line?;
-        let line = line.trim();
+/// Read commands from `input` and answer them on `output` until EOF
+fn run_session<S: StorageBackend, R: BufRead, W: Write>(
+    storage: &S,
+    remote_name: &str,
+    options: &SessionOptions,
+    input: R,
+    output: &mut ProtocolWriter<W>,
+) -> Result<()> {
+    let namespace = &options.namespace;
+    let capabilities = CapabilitySet::for_session(storage, remote_name, options);
+    let mut lines = ProtocolReader::new(input);
+    let mut request = PushRequest::default();
+    let mut fetch = FetchRequest::default();
+
+    while let Some(raw) = lines.next_bytes() {
+        let raw = raw?;
+        let raw = raw.trim_ascii();
 
         // Log commands to stderr for debugging
-        tracing::debug!("Received command: {}", line);
+        tracing::debug!("Received command: {}", raw.escape_ascii());
 
-        if line.is_empty() {
+        // A blank line ends a batch; git may send another batch, or start a new round with
+        // `list`, before closing stdin
+        if raw.is_empty() {
+            output.flush()?;
             continue;
         }
 
-        // Parse and dispatch command
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.is_empty() {
+        // Only the keyword is decoded up front: push lines name refs, which git allows to be
+        // any bytes, and are parsed as bytes
+        let keyword = raw
+            .split(u8::is_ascii_whitespace)
+            .next()
+            .unwrap_or_default();
+        let Ok(keyword) = std::str::from_utf8(keyword) else {
+            tracing::warn!("Unknown command: {}", keyword.escape_ascii());
             continue;
-        }
+        };
+        let line = || command_line(raw);
 
-        match parts[0] {
+        let _span = tracing::debug_span!("command", remote = remote_name, cmd = keyword).entered();
+
+        match keyword {
             "capabilities" => {
-                commands::capabilities::handle(&mut stdout)?;
+                commands::capabilities::handle(output, &capabilities)?;
+            }
+            "option" => {
+                // "option <name> <value>", for the options the capabilities allow
+                let mut option = line()?["option".len()..].trim().splitn(2, ' ');
+                match (option.next(), option.next()) {
+                    (Some("pushcert"), Some(value)) if capabilities.push_certs => {
+                        match PushCertMode::from_option(value) {
+                            Some(mode) => {
+                                request.cert_mode = mode;
+                                output.line("ok")?;
+                            }
+                            None => output.line("unsupported")?,
+                        }
+                    }
+                    (Some("atomic"), Some(value @ ("true" | "false"))) if capabilities.atomic => {
+                        request.atomic = value == "true";
+                        output.line("ok")?;
+                    }
+                    (Some("verbosity"), Some(value)) => match value.parse() {
+                        Ok(verbosity) => {
+                            request.verbosity = verbosity;
+                            output.line("ok")?;
+                        }
+                        Err(_) => output.line("unsupported")?,
+                    },
+                    (Some("followtags"), Some(value @ ("true" | "false"))) => {
+                        fetch.follow_tags = value == "true";
+                        output.line("ok")?;
+                    }
+                    (Some("depth"), Some(value)) => match value.parse::<u32>() {
+                        Ok(depth) if depth > 0 => {
+                            fetch.deepen.depth = Some(depth);
+                            output.line("ok")?;
+                        }
+                        _ => output.line("unsupported")?,
+                    },
+                    (Some("deepen-since"), Some(value)) => match parse_since(value) {
+                        Ok(since) => {
+                            fetch.deepen.since = Some(since);
+                            output.line("ok")?;
+                        }
+                        Err(e) => output.line(format_args!("error {:#}", e))?,
+                    },
+                    (Some("deepen-not"), Some(value)) => {
+                        fetch.deepen.not.push(value.to_string());
+                        output.line("ok")?;
+                    }
+                    (Some("deepen-relative"), Some(value @ ("true" | "false"))) => {
+                        fetch.deepen.relative = value == "true";
+                        output.line("ok")?;
+                    }
+                    (Some("push-option"), Some(value)) if capabilities.push_options => {
+                        match request.push_options.add(value) {
+                            Ok(()) => output.line("ok")?,
+                            Err(e) => {
+                                // git only reports that the option failed; say why
+                                tracing::error!("Refusing push option: {:#}", e);
+                                output.line(format_args!("error {}", e))?;
+                            }
+                        }
+                    }
+                    _ => output.line("unsupported")?,
+                }
             }
             "list" => {
-                let for_push = parts.get(1) == Some(&"for-push");
-                commands::list::handle(&storage, &mut stdout, for_push)?;
+                let for_push = line()?.split_whitespace().nth(1) == Some("for-push");
+                commands::list::handle(
+                    storage,
+                    output,
+                    namespace,
+                    &options.advertise_ref_patterns,
+                    options.tag_only_head,
+                    for_push,
+                )?;
             }
             "fetch" => {
-                let refs = read_fetch_refs(&mut lines)?;
-                commands::fetch::handle(&storage, &mut stdout, &refs)?;
+                // The command line itself is the first "fetch <sha1> <refname>" of the batch
+                let mut refs: Vec<String> = line()?
+                    .strip_prefix("fetch ")
+                    .and_then(fetch_wanted)
+                    .into_iter()
+                    .collect();
+                refs.extend(read_fetch_refs(&mut lines)?);
+                commands::fetch::handle(storage, output, &refs, namespace, &options.hooks, &fetch)?;
+            }
+            "push" | "export" if options.read_only => {
+                anyhow::bail!("this remote is configured read-only");
             }
             "push" => {
-                commands::push::handle(&storage, &mut stdout, &mut lines)?;
+                commands::push::handle(storage, output, raw, &mut lines, options, &request)?;
             }
             // Keep old import/export for backward compatibility (can be removed later)
             "import" => {
                 let refs = read_import_refs(&mut lines)?;
-                commands::import::handle(&storage, &mut stdout, &refs)?;
+                commands::import::handle(storage, output, &refs, namespace)?;
             }
             "export" => {
-                commands::export::handle(&storage, &mut stdout, &mut lines)?;
-            }
-            "" => {
-                // Empty line signals end of command batch
-                break;
+                commands::export::handle(storage, output, &mut lines, options, &request)?;
             }
             cmd => {
                 tracing::warn!("Unknown command: {}", cmd);
             }
         }
 
-        stdout.flush()?;
+        output.flush()?;
     }
 
     Ok(())
 }
 
-/// Read fetch ref list until empty line
-fn read_fetch_refs<R: BufRead>(lines: &mut std::io::Lines<R>) -> Result<Vec<String>> {
+/// A command line that isn't a push line, which must be UTF-8 text
+fn command_line(raw: &[u8]) -> Result<&str> {
+    std::str::from_utf8(raw)
+        .map_err(|_| anyhow::anyhow!("command is not valid UTF-8: {}", raw.escape_ascii()))
+}
+
+/// Read the rest of a fetch batch, up to the blank line ending it (or EOF)
+///
+/// Only the blank line is consumed, so the session goes on with whatever git sends next.
+fn read_fetch_refs<R: BufRead>(lines: &mut ProtocolReader<R>) -> Result<Vec<String>> {
     let mut refs = Vec::new();
 
     #[allow(clippy::while_let_on_iterator)]
@@ -82,20 +302,24 @@ fn read_fetch_refs<R: BufRead>(lines: &mut std::io::Lines<R>) -> Result<Vec<Stri
         }
 
         // Format: "fetch <sha1> <refname>"
-        if let Some(rest) = line.strip_prefix("fetch ") {
-            let parts: Vec<&str> = rest.split_whitespace().collect();
-            if parts.len() >= 2 {
-                let refname = parts[1].to_string();
-                refs.push(refname);
-            }
+        if let Some(wanted) = line.strip_prefix("fetch ").and_then(fetch_wanted) {
+            refs.push(wanted);
         }
     }
 
     Ok(refs)
 }
 
+/// What to send for a `<sha1> <refname>` fetch request: the ref, or the object itself for
+/// HEAD, which isn't a stored ref (and may be detached, on remotes with only tags)
+fn fetch_wanted(request: &str) -> Option<String> {
+    let mut parts = request.split_whitespace();
+    let (git_sha1, refname) = (parts.next()?, parts.next()?);
+    Some(if refname == "HEAD" { git_sha1 } else { refname }.to_string())
+}
+
 /// Read import ref list until empty line
-fn read_import_refs<R: BufRead>(lines: &mut std::io::Lines<R>) -> Result<Vec<String>> {
+fn read_import_refs<R: BufRead>(lines: &mut ProtocolReader<R>) -> Result<Vec<String>> {
     let mut refs = Vec::new();
 
     #[allow(clippy::while_let_on_iterator)]
@@ -114,3 +338,626 @@ fn read_import_refs<R: BufRead>(lines: &mut std::io::Lines<R>) -> Result<Vec<Str
 
     Ok(refs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        session_env::SessionEnv,
+        storage::{MemoryStorage, MutableState},
+    };
+
+    fn session(storage: &MemoryStorage, script: &str) -> String {
+        session_with(storage, &SessionOptions::default(), script).unwrap()
+    }
+
+    fn session_in(storage: &MemoryStorage, namespace: &RefNamespace, script: &str) -> String {
+        let options = SessionOptions {
+            namespace: namespace.clone(),
+            ..SessionOptions::default()
+        };
+        session_with(storage, &options, script).unwrap()
+    }
+
+    /// Run `f` as a session git started in `repo` (a work tree), so fetches index into it
+    fn in_repo<T>(repo: &std::path::Path, f: impl FnOnce() -> T) -> T {
+        let git_dir = repo.join(".git").display().to_string();
+        SessionEnv {
+            cwd: repo.to_path_buf(),
+            vars: [("GIT_DIR".to_string(), git_dir)].into(),
+        }
+        .scope(f)
+    }
+
+    fn session_with(
+        storage: &MemoryStorage,
+        options: &SessionOptions,
+        script: &str,
+    ) -> Result<String> {
+        let mut output = ProtocolWriter::new(Vec::new());
+        run_session(storage, "origin", options, script.as_bytes(), &mut output)?;
+        Ok(String::from_utf8(output.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn test_capabilities_and_list_session() {
+        let storage = MemoryStorage::new();
+
+        let main = "a".repeat(40);
+        let tag = "b".repeat(40);
+        storage
+            .update_state(|state| {
+                state
+                    .refs
+                    .insert("refs/heads/main".to_string(), main.clone());
+                state.refs.insert("refs/tags/v1".to_string(), tag.clone());
+                Ok(())
+            })
+            .unwrap();
+
+        let output = session(&storage, "capabilities\n\nlist\n\nlist for-push\n\n");
+        let list = format!(
+            "{} refs/heads/main\n{} refs/tags/v1\n@refs/heads/main HEAD\n\n",
+            main, tag
+        );
+        assert_eq!(
+            output,
+            format!(
+                "fetch\nexport\nsigned-tags\noption\n\
+                 refspec refs/heads/*:refs/walrus/origin/heads/*\n\
+                 refspec refs/tags/*:refs/walrus/origin/tags/*\n\n{}{}",
+                list, list
+            )
+        );
+    }
+
+    #[test]
+    fn test_capabilities_follow_backend_and_config() {
+        use crate::storage::FilesystemStorage;
+
+        let dir = tempfile::tempdir().unwrap();
+        let filesystem = FilesystemStorage::new(dir.path()).unwrap();
+        let memory = MemoryStorage::new();
+        let refspecs = "refspec refs/heads/*:refs/walrus/origin/heads/*\n\
+                        refspec refs/tags/*:refs/walrus/origin/tags/*\n\n";
+        /// Answers to `capabilities` and then to the push options
+        fn answers(storage: &impl StorageBackend, read_only: bool) -> String {
+            let options = SessionOptions {
+                read_only,
+                ..SessionOptions::default()
+            };
+            let script = "capabilities\n\noption atomic true\noption pushcert if-asked\n\n";
+            let mut output = ProtocolWriter::new(Vec::new());
+            run_session(storage, "origin", &options, script.as_bytes(), &mut output).unwrap();
+            String::from_utf8(output.into_inner()).unwrap()
+        }
+
+        // The state file is replaced atomically, so --atomic pushes are accepted
+        assert_eq!(
+            answers(&filesystem, false),
+            format!("fetch\nexport\nsigned-tags\noption\n{}ok\nok\n", refspecs)
+        );
+        // A backend that can't apply refs all at once only takes push certificates
+        assert_eq!(
+            answers(&memory, false),
+            format!(
+                "fetch\nexport\nsigned-tags\noption\n{}unsupported\nok\n",
+                refspecs
+            )
+        );
+        // Read-only sessions advertise and accept nothing push-related
+        assert_eq!(
+            answers(&filesystem, true),
+            format!("fetch\n{}unsupported\nunsupported\n", refspecs)
+        );
+    }
+
+    #[test]
+    fn test_verbosity_option_session() {
+        let storage = MemoryStorage::new();
+
+        // git sends the level for fetches and pushes alike
+        assert_eq!(
+            session(&storage, "option verbosity 2\noption verbosity loud\n\n"),
+            "ok\nunsupported\n"
+        );
+    }
+
+    #[test]
+    fn test_list_empty_remote_session() {
+        let storage = MemoryStorage::new();
+
+        assert_eq!(session(&storage, "list\n\n"), "\n");
+    }
+
+    #[test]
+    fn test_list_namespaced_session() {
+        let storage = MemoryStorage::new();
+
+        let a = "a".repeat(40);
+        let b = "b".repeat(40);
+        storage
+            .update_state(|state| {
+                state
+                    .refs
+                    .insert("refs/namespaces/one/refs/heads/main".to_string(), a.clone());
+                state
+                    .refs
+                    .insert("refs/namespaces/two/refs/heads/dev".to_string(), b.clone());
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(
+            session_in(&storage, &RefNamespace::new(Some("one")), "list\n\n"),
+            format!("{} refs/heads/main\n@refs/heads/main HEAD\n\n", a)
+        );
+        assert_eq!(
+            session_in(&storage, &RefNamespace::new(Some("two")), "list\n\n"),
+            format!("{} refs/heads/dev\n@refs/heads/dev HEAD\n\n", b)
+        );
+        // The root namespace sees neither
+        assert_eq!(session(&storage, "list\n\n"), "\n");
+    }
+
+    #[test]
+    fn test_list_advertises_only_matching_refs() {
+        let storage = MemoryStorage::new();
+
+        let dev = "a".repeat(40);
+        let v1 = "b".repeat(40);
+        storage
+            .update_state(|state| {
+                state.refs.insert("refs/heads/dev".to_string(), dev.clone());
+                state
+                    .refs
+                    .insert("refs/heads/main".to_string(), "c".repeat(40));
+                state.refs.insert("refs/tags/v1".to_string(), v1.clone());
+                state
+                    .refs
+                    .insert("refs/tags/old".to_string(), "d".repeat(40));
+                Ok(())
+            })
+            .unwrap();
+
+        // main is hidden, so HEAD falls back to the first advertised ref
+        let options = SessionOptions {
+            advertise_ref_patterns: vec!["refs/heads/d*".to_string(), "refs/tags/v*".to_string()],
+            ..SessionOptions::default()
+        };
+        assert_eq!(
+            session_with(&storage, &options, "list\n\n").unwrap(),
+            format!(
+                "{} refs/heads/dev\n{} refs/tags/v1\n@refs/heads/dev HEAD\n\n",
+                dev, v1
+            )
+        );
+    }
+
+    #[test]
+    fn test_read_only_session_rejects_export() {
+        let storage = MemoryStorage::new();
+        let main = "a".repeat(40);
+        storage
+            .update_state(|state| {
+                state
+                    .refs
+                    .insert("refs/heads/main".to_string(), main.clone());
+                Ok(())
+            })
+            .unwrap();
+
+        let options = SessionOptions {
+            read_only: true,
+            ..SessionOptions::default()
+        };
+
+        // No export capability, but listing works as usual
+        assert_eq!(
+            session_with(&storage, &options, "capabilities\n\nlist\n\n").unwrap(),
+            format!(
+                "fetch\nrefspec refs/heads/*:refs/walrus/origin/heads/*\n\
+                 refspec refs/tags/*:refs/walrus/origin/tags/*\n\n{} refs/heads/main\n@refs/heads/main HEAD\n\n",
+                main
+            )
+        );
+
+        for command in ["export", "push refs/heads/main:refs/heads/main"] {
+            let script = format!(
+                "{}\nreset refs/heads/main\nfrom {}\n\ndone\n",
+                command, main
+            );
+            let err = session_with(&storage, &options, &script).unwrap_err();
+            assert_eq!(err.to_string(), "this remote is configured read-only");
+        }
+        assert_eq!(storage.read_state().unwrap().refs.len(), 1);
+    }
+
+    #[test]
+    fn test_consecutive_push_batches() {
+        let repo = tempfile::tempdir().unwrap();
+        let git = |args: &[&str], stdin: &str| {
+            crate::subprocess::CommandRunner::git()
+                .current_dir(repo.path())
+                .args(["-c", "user.name=T", "-c", "user.email=t@example.com"])
+                .args(args)
+                .stdin(stdin.to_string())
+                .run()
+                .unwrap()
+                .stdout
+        };
+        let commit = |name: &str| {
+            std::fs::write(repo.path().join(name), name).unwrap();
+            git(&["add", name], "");
+            git(&["commit", "-q", "-m", name], "");
+            String::from_utf8(git(&["rev-parse", "HEAD"], "")).unwrap()
+        };
+        git(&["init", "-q"], "");
+        let main = commit("main.txt");
+        let dev = commit("dev.txt");
+
+        // Each batch of push lines is followed by its pack; the second pack only adds dev's
+        let mut script = b"push refs/heads/main:refs/heads/main\n\n".to_vec();
+        script.extend(git(&["pack-objects", "--revs", "--stdout"], &main));
+        script.extend_from_slice(b"push refs/heads/dev:refs/heads/dev\n\n");
+        script.extend(git(
+            &["pack-objects", "--revs", "--stdout"],
+            &format!("{}^{}", dev, main),
+        ));
+        script.extend_from_slice(b"\n");
+
+        let storage = MemoryStorage::new();
+        let mut output = ProtocolWriter::new(Vec::new());
+        run_session(
+            &storage,
+            "origin",
+            &SessionOptions::default(),
+            &script[..],
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output.into_inner()).unwrap(),
+            "ok refs/heads/main\n\nok refs/heads/dev\n\n"
+        );
+
+        let state = storage.read_state().unwrap();
+        assert_eq!(state.objects.len(), 6);
+        assert!(state.objects.contains_key(main.trim()));
+        assert!(state.objects.contains_key(dev.trim()));
+        assert_eq!(
+            state.refs.keys().collect::<Vec<_>>(),
+            ["refs/heads/dev", "refs/heads/main"]
+        );
+    }
+
+    #[test]
+    fn test_consecutive_fetch_batches() {
+        let repo = tempfile::tempdir().unwrap();
+        let git = |dir: &std::path::Path, args: &[&str], stdin: &str| {
+            crate::subprocess::CommandRunner::git()
+                .current_dir(dir)
+                .args(["-c", "user.name=T", "-c", "user.email=t@example.com"])
+                .args(args)
+                .stdin(stdin.to_string())
+                .run()
+                .unwrap()
+                .stdout
+        };
+        let commit = |name: &str| {
+            std::fs::write(repo.path().join(name), name).unwrap();
+            git(repo.path(), &["add", name], "");
+            git(repo.path(), &["commit", "-q", "-m", name], "");
+            String::from_utf8(git(repo.path(), &["rev-parse", "HEAD"], ""))
+                .unwrap()
+                .trim()
+                .to_string()
+        };
+        git(repo.path(), &["init", "-q"], "");
+        let main = commit("main.txt");
+        let dev = commit("dev.txt");
+
+        let pack = git(
+            repo.path(),
+            &["pack-objects", "--revs", "--stdout"],
+            &format!("{}\n", dev),
+        );
+        let storage = MemoryStorage::new();
+        let stored = crate::pack::receive::receive_pack(&mut pack.as_slice(), &storage).unwrap();
+        storage
+            .update_state(|state| {
+                state.objects.extend(stored);
+                state
+                    .refs
+                    .insert("refs/heads/main".to_string(), main.clone());
+                state.refs.insert("refs/heads/dev".to_string(), dev.clone());
+                Ok(())
+            })
+            .unwrap();
+
+        // A list, then one fetch batch per ref, each ended by a blank line
+        let clone = tempfile::tempdir().unwrap();
+        git(clone.path(), &["init", "-q"], "");
+        let script = format!(
+            "list\n\nfetch {} refs/heads/main\n\nfetch {} refs/heads/dev\n\n",
+            main, dev
+        );
+        let output = in_repo(clone.path(), || session(&storage, &script));
+        assert_eq!(
+            output,
+            format!(
+                "{} refs/heads/dev\n{} refs/heads/main\n@refs/heads/main HEAD\n\n\n\n",
+                dev, main
+            )
+        );
+
+        // The second batch's objects were indexed too
+        for path in [format!("{}:main.txt", main), format!("{}:dev.txt", dev)] {
+            git(clone.path(), &["cat-file", "-e", &path], "");
+        }
+    }
+
+    #[test]
+    fn test_shallow_fetch() {
+        let repo = tempfile::tempdir().unwrap();
+        let git = |dir: &std::path::Path, args: &[&str]| {
+            crate::subprocess::CommandRunner::git()
+                .current_dir(dir)
+                .args(["-c", "user.name=T", "-c", "user.email=t@example.com"])
+                .args(args)
+                .run()
+                .unwrap()
+                .stdout
+        };
+        git(repo.path(), &["init", "-q"]);
+        let mut commits = Vec::new();
+        for name in ["one", "two", "three"] {
+            git(repo.path(), &["commit", "-q", "--allow-empty", "-m", name]);
+            let head = git(repo.path(), &["rev-parse", "HEAD"]);
+            commits.push(String::from_utf8(head).unwrap().trim().to_string());
+        }
+
+        let pack = crate::subprocess::CommandRunner::git()
+            .current_dir(repo.path())
+            .args(["pack-objects", "--revs", "--stdout"])
+            .stdin(format!("{}\n", commits[2]))
+            .run()
+            .unwrap()
+            .stdout;
+        let storage = MemoryStorage::new();
+        let stored = crate::pack::receive::receive_pack(&mut pack.as_slice(), &storage).unwrap();
+        storage
+            .update_state(|state| {
+                state.objects.extend(stored);
+                state
+                    .refs
+                    .insert("refs/heads/main".to_string(), commits[2].clone());
+                Ok(())
+            })
+            .unwrap();
+
+        let clone = tempfile::tempdir().unwrap();
+        git(clone.path(), &["init", "-q"]);
+        let shallow_file = clone.path().join(".git/shallow");
+        let fetch = |script: String| in_repo(clone.path(), || session(&storage, &script));
+
+        // --depth 2 sends the tip and its parent, cut off from the first commit
+        let script = format!("option depth 2\nfetch {} refs/heads/main\n\n", commits[2]);
+        assert_eq!(fetch(script), "ok\n\n");
+        assert_eq!(
+            std::fs::read_to_string(&shallow_file).unwrap(),
+            format!("{}\n", commits[1])
+        );
+        git(clone.path(), &["cat-file", "-e", &commits[1]]);
+        let missing = crate::subprocess::CommandRunner::git()
+            .current_dir(clone.path())
+            .args(["cat-file", "-e", &commits[0]])
+            .output()
+            .unwrap();
+        assert!(!missing.status.success());
+
+        // --unshallow fetches the rest and removes the shallow file
+        let script = format!(
+            "option depth 2147483647\nfetch {} refs/heads/main\n\n",
+            commits[2]
+        );
+        assert_eq!(fetch(script), "ok\n\n");
+        assert!(!shallow_file.exists());
+        git(clone.path(), &["cat-file", "-e", &commits[0]]);
+    }
+
+    #[test]
+    fn test_tag_fetch_sends_every_tag_unless_following_tags() {
+        let repo = tempfile::tempdir().unwrap();
+        let git = |dir: &std::path::Path, args: &[&str]| {
+            let stdout = crate::subprocess::CommandRunner::git()
+                .current_dir(dir)
+                .args(["-c", "user.name=T", "-c", "user.email=t@example.com"])
+                .args(args)
+                .run()
+                .unwrap()
+                .stdout;
+            String::from_utf8(stdout).unwrap().trim().to_string()
+        };
+        git(repo.path(), &["init", "-q"]);
+        git(
+            repo.path(),
+            &["commit", "-q", "--allow-empty", "-m", "release"],
+        );
+        let release = git(repo.path(), &["rev-parse", "HEAD"]);
+        git(repo.path(), &["checkout", "-q", "--orphan", "side"]);
+        git(
+            repo.path(),
+            &["commit", "-q", "--allow-empty", "-m", "side"],
+        );
+        let side = git(repo.path(), &["rev-parse", "HEAD"]);
+
+        let pack = crate::subprocess::CommandRunner::git()
+            .current_dir(repo.path())
+            .args(["pack-objects", "--revs", "--stdout"])
+            .stdin(format!("{}\n{}\n", release, side))
+            .run()
+            .unwrap()
+            .stdout;
+        let storage = MemoryStorage::new();
+        let stored = crate::pack::receive::receive_pack(&mut pack.as_slice(), &storage).unwrap();
+        storage
+            .update_state(|state| {
+                state.objects.extend(stored);
+                state
+                    .refs
+                    .insert("refs/tags/v1".to_string(), release.clone());
+                state
+                    .refs
+                    .insert("refs/tags/v-side".to_string(), side.clone());
+                Ok(())
+            })
+            .unwrap();
+
+        let has_side = |script: &str| {
+            let clone = tempfile::tempdir().unwrap();
+            git(clone.path(), &["init", "-q"]);
+            in_repo(clone.path(), || session(&storage, script));
+            git(clone.path(), &["cat-file", "-e", &release]);
+            crate::subprocess::CommandRunner::git()
+                .current_dir(clone.path())
+                .args(["cat-file", "-e", &side])
+                .output()
+                .unwrap()
+                .status
+                .success()
+        };
+
+        // git fetch --tags: every tag comes, including ones it didn't name
+        assert!(has_side(&format!("fetch {} refs/tags/v1\n\n", release)));
+        // A single-branch clone of v1 follows tags itself and gets v1 alone
+        assert!(!has_side(&format!(
+            "option followtags true\nfetch {} refs/tags/v1\n\n",
+            release
+        )));
+    }
+
+    #[test]
+    fn test_push_resolves_mapping_conflicts() {
+        use crate::storage::{FilesystemStorage, ImmutableStore};
+
+        let repo = tempfile::tempdir().unwrap();
+        let git = |args: &[&str], stdin: &str| {
+            crate::subprocess::CommandRunner::git()
+                .current_dir(repo.path())
+                .args(["-c", "user.name=T", "-c", "user.email=t@example.com"])
+                .args(args)
+                .stdin(stdin.to_string())
+                .run()
+                .unwrap()
+                .stdout
+        };
+        git(&["init", "-q"], "");
+        std::fs::write(repo.path().join("file.txt"), "content").unwrap();
+        git(&["add", "file.txt"], "");
+        git(&["commit", "-q", "-m", "one"], "");
+        let head = String::from_utf8(git(&["rev-parse", "HEAD"], "")).unwrap();
+        let blob = String::from_utf8(git(&["rev-parse", "HEAD:file.txt"], "")).unwrap();
+        let blob = blob.trim();
+        let mut script = b"push refs/heads/main:refs/heads/main\n\n".to_vec();
+        script.extend(git(&["pack-objects", "--revs", "--stdout"], &head));
+        script.extend_from_slice(b"\n");
+
+        let push = |on_mapping_conflict: MappingConflict| {
+            let dir = tempfile::tempdir().unwrap();
+            let storage = FilesystemStorage::new(dir.path()).unwrap();
+            storage.initialize().unwrap();
+            // Another client stored the blob with a bad header
+            let drifted = storage.write_object(b"blob 99\0content").unwrap();
+            storage
+                .update_state(|state| {
+                    state.objects.insert(blob.to_string(), drifted.clone());
+                    Ok(())
+                })
+                .unwrap();
+
+            let options = SessionOptions {
+                on_mapping_conflict,
+                ..SessionOptions::default()
+            };
+            let mut output = ProtocolWriter::new(Vec::new());
+            let result = run_session(&storage, "origin", &options, &script[..], &mut output);
+            let state = storage.read_state().unwrap();
+            (result, storage, state, drifted, dir)
+        };
+        let readable = |storage: &FilesystemStorage, content_id: &str| {
+            storage.read_git_objects(&[(blob, content_id)]).is_ok()
+        };
+
+        // The first mapping wins, bad bytes and all
+        let (result, storage, state, drifted, _dir) = push(MappingConflict::Keep);
+        result.unwrap();
+        assert_eq!(state.objects[blob], drifted);
+        assert!(!readable(&storage, &drifted));
+        assert_eq!(state.objects.len(), 3);
+
+        let (result, storage, state, drifted, _dir) = push(MappingConflict::Replace);
+        result.unwrap();
+        assert_ne!(state.objects[blob], drifted);
+        assert!(readable(&storage, &state.objects[blob]));
+
+        let (result, _storage, state, drifted, _dir) = push(MappingConflict::Error);
+        let err = format!("{:#}", result.unwrap_err());
+        assert!(err.contains(blob), "{}", err);
+        assert_eq!(state.objects.len(), 1);
+        assert_eq!(state.objects[blob], drifted);
+        assert!(state.refs.is_empty());
+    }
+
+    #[test]
+    fn test_push_refuses_ref_names_that_are_not_utf8() {
+        let repo = tempfile::tempdir().unwrap();
+        let git = |args: &[&str], stdin: &str| {
+            crate::subprocess::CommandRunner::git()
+                .current_dir(repo.path())
+                .args(["-c", "user.name=T", "-c", "user.email=t@example.com"])
+                .args(args)
+                .stdin(stdin.to_string())
+                .run()
+                .unwrap()
+                .stdout
+        };
+        git(&["init", "-q"], "");
+        std::fs::write(repo.path().join("file.txt"), "content").unwrap();
+        git(&["add", "file.txt"], "");
+        git(&["commit", "-q", "-m", "one"], "");
+        let head = String::from_utf8(git(&["rev-parse", "HEAD"], "")).unwrap();
+        let pack = git(&["pack-objects", "--revs", "--stdout"], &head);
+
+        // A Latin-1 ref beside a UTF-8 one; only the Latin-1 one is refused, by its own bytes,
+        // and a batch of nothing but refused refs still reads its pack
+        let mut script = "push refs/heads/main:refs/heads/café\n".as_bytes().to_vec();
+        script.extend_from_slice(b"push refs/heads/main:refs/heads/caf\xe9\n\n");
+        script.extend_from_slice(&pack);
+        script.extend_from_slice(b"push refs/heads/main:refs/heads/caf\xe9\n\n");
+        script.extend_from_slice(&pack);
+        script.extend_from_slice(b"\n");
+
+        let storage = MemoryStorage::new();
+        let mut output = ProtocolWriter::new(Vec::new());
+        run_session(
+            &storage,
+            "origin",
+            &SessionOptions::default(),
+            &script[..],
+            &mut output,
+        )
+        .unwrap();
+        let refused: &[u8] = b"error refs/heads/caf\xe9 invalid ref name \"refs/heads/caf\\xe9\": \
+                               this remote stores only UTF-8 ref names\n";
+        let mut expected = "ok refs/heads/café\n".as_bytes().to_vec();
+        expected.extend_from_slice(refused);
+        expected.extend_from_slice(b"\n");
+        expected.extend_from_slice(refused);
+        expected.extend_from_slice(b"\n");
+        assert_eq!(output.into_inner(), expected);
+
+        let state = storage.read_state().unwrap();
+        assert_eq!(state.refs.keys().collect::<Vec<_>>(), ["refs/heads/café"]);
+    }
+}
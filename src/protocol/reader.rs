@@ -0,0 +1,112 @@
+use std::io::{self, BufRead};
+
+use anyhow::{Context, Result};
+
+use crate::pack::read_pack;
+
+/// Command lines from git during a helper session
+///
+/// Iterates like `BufRead::lines`, but can also read the exact-length `data` payloads of a
+/// fast-export stream, which may hold binary blobs that aren't valid UTF-8, and the packs
+/// that follow push batches. Lines naming refs can be read as raw bytes with
+/// [`next_bytes`](Self::next_bytes), since git allows ref names that aren't UTF-8.
+pub struct ProtocolReader<R: BufRead> {
+    inner: R,
+}
+
+impl<R: BufRead> ProtocolReader<R> {
+    /// Wrap `inner` (tests read from a `Cursor`)
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    /// Read exactly `size` bytes of payload
+    pub fn data(&mut self, size: usize) -> Result<Vec<u8>> {
+        let mut payload = vec![0u8; size];
+        self.inner
+            .read_exact(&mut payload)
+            .context("Failed to read data payload")?;
+        Ok(payload)
+    }
+
+    /// Read one pack, leaving the commands after it unread
+    pub fn pack(&mut self) -> Result<Vec<u8>> {
+        read_pack(&mut self.inner)
+    }
+
+    /// Read the next line as git sent it, without its line ending
+    pub fn next_bytes(&mut self) -> Option<io::Result<Vec<u8>>> {
+        let mut line = Vec::new();
+        match self.inner.read_until(b'\n', &mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.ends_with(b"\n") {
+                    line.pop();
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                }
+                Some(Ok(line))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for ProtocolReader<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = match self.next_bytes()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(String::from_utf8(line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line is not valid UTF-8: {}", e.as_bytes().escape_ascii()),
+            )
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_lines_around_binary_data() {
+        let mut input = b"export\r\ndata 4\n".to_vec();
+        input.extend_from_slice(&[0xff, b'\n', 0x00, 0x80]);
+        input.extend_from_slice(b"\ndone");
+        let mut reader = ProtocolReader::new(Cursor::new(input));
+
+        assert_eq!(reader.next().unwrap().unwrap(), "export");
+        assert_eq!(reader.next().unwrap().unwrap(), "data 4");
+        assert_eq!(reader.data(4).unwrap(), [0xff, b'\n', 0x00, 0x80]);
+        assert_eq!(reader.next().unwrap().unwrap(), "");
+        assert_eq!(reader.next().unwrap().unwrap(), "done");
+        assert!(reader.next().is_none());
+        assert!(reader.data(1).is_err());
+    }
+
+    #[test]
+    fn test_lines_that_are_not_utf8() {
+        let input = b"push refs/heads/caf\xe9:refs/heads/caf\xe9\r\nlist\n".to_vec();
+        let mut reader = ProtocolReader::new(Cursor::new(input.clone()));
+        assert_eq!(
+            reader.next_bytes().unwrap().unwrap(),
+            b"push refs/heads/caf\xe9:refs/heads/caf\xe9"
+        );
+        assert_eq!(reader.next().unwrap().unwrap(), "list");
+
+        // As text, the line is an error that shows its bytes, not a lossy conversion
+        let mut reader = ProtocolReader::new(Cursor::new(input));
+        let e = reader.next().unwrap().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("refs/heads/caf\\xe9"), "{}", e);
+        assert_eq!(reader.next().unwrap().unwrap(), "list");
+    }
+}
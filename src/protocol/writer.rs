@@ -0,0 +1,124 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+};
+
+use anyhow::{Context, Result};
+
+/// The only handle on stdout during a helper session
+///
+/// Everything written here is read by git as protocol, so writes are line-oriented and
+/// each outgoing line is logged at debug level.
+pub struct ProtocolWriter<W: Write = io::Stdout> {
+    inner: W,
+}
+
+impl ProtocolWriter {
+    /// Writer for the helper's stdout
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write> ProtocolWriter<W> {
+    /// Wrap `inner` (tests write into a `Vec<u8>`)
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Write one protocol line; `line` must not contain a newline
+    pub fn line(&mut self, line: impl Display) -> Result<()> {
+        let line = line.to_string();
+        if line.contains('\n') {
+            anyhow::bail!("protocol line contains a newline: {:?}", line);
+        }
+
+        tracing::debug!("> {}", line);
+        writeln!(self.inner, "{}", line).context("Failed to write protocol line")
+    }
+
+    /// Write an `error <ref> <why>` push status, echoing the ref name byte for byte since
+    /// git matches it against the refs it pushed (which need not be UTF-8)
+    pub fn ref_error(&mut self, refname: &[u8], why: impl Display) -> Result<()> {
+        let mut line = b"error ".to_vec();
+        line.extend_from_slice(refname);
+        line.push(b' ');
+        line.extend_from_slice(why.to_string().as_bytes());
+        if line.contains(&b'\n') {
+            anyhow::bail!("protocol line contains a newline: {}", line.escape_ascii());
+        }
+
+        tracing::debug!("> {}", line.escape_ascii());
+        line.push(b'\n');
+        self.inner
+            .write_all(&line)
+            .context("Failed to write protocol line")
+    }
+
+    /// Write the blank line that terminates a response
+    pub fn end(&mut self) -> Result<()> {
+        tracing::debug!(">");
+        writeln!(self.inner).context("Failed to write protocol line")
+    }
+
+    /// Write a pre-formatted data stream (e.g. fast-export output for `import`)
+    pub fn stream(&mut self, data: &[u8]) -> Result<()> {
+        if !data.is_empty() && !data.ends_with(b"\n") {
+            anyhow::bail!("protocol stream does not end with a newline");
+        }
+
+        tracing::debug!("> [{} byte stream]", data.len());
+        self.inner
+            .write_all(data)
+            .context("Failed to write protocol stream")
+    }
+
+    /// Flush buffered output to git
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner
+            .flush()
+            .context("Failed to flush protocol output")
+    }
+
+    /// Unwrap the underlying writer
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_and_terminator() {
+        let mut writer = ProtocolWriter::new(Vec::new());
+        writer.line("fetch").unwrap();
+        writer
+            .line(format_args!("ok {}", "refs/heads/main"))
+            .unwrap();
+        writer.end().unwrap();
+        assert_eq!(writer.into_inner(), b"fetch\nok refs/heads/main\n\n");
+    }
+
+    #[test]
+    fn test_rejects_embedded_newline() {
+        let mut writer = ProtocolWriter::new(Vec::new());
+        assert!(writer.line("ok a\nok b").is_err());
+        assert!(writer.stream(b"partial").is_err());
+        writer.stream(b"").unwrap();
+        writer.stream(b"commit refs/heads/main\ndone\n").unwrap();
+        assert_eq!(writer.into_inner(), b"commit refs/heads/main\ndone\n");
+    }
+
+    #[test]
+    fn test_ref_error_keeps_name_bytes() {
+        let mut writer = ProtocolWriter::new(Vec::new());
+        writer
+            .ref_error(b"refs/heads/caf\xe9", "not UTF-8")
+            .unwrap();
+        assert!(writer.ref_error(b"refs/heads/a", "two\nlines").is_err());
+        assert_eq!(writer.into_inner(), b"error refs/heads/caf\xe9 not UTF-8\n");
+    }
+}
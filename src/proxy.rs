@@ -0,0 +1,166 @@
+//! HTTP(S) proxy settings from the standard environment variables and the config file
+
+use crate::session_env;
+
+/// Proxies for outgoing HTTP and HTTPS connections
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxySettings {
+    /// Proxy for `http://` URLs
+    pub http: Option<String>,
+    /// Proxy for `https://` URLs
+    pub https: Option<String>,
+    /// Hosts (and their subdomains) reached directly; `*` matches every host
+    pub no_proxy: Vec<String>,
+}
+
+impl ProxySettings {
+    /// Settings from `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY` (upper or lower
+    /// case), falling back to the config's `proxy` and `no_proxy`
+    pub fn resolve(config_proxy: Option<&str>, config_no_proxy: &[String]) -> Self {
+        Self::from_lookup(session_env::var, config_proxy, config_no_proxy)
+    }
+
+    fn from_lookup(
+        lookup: impl Fn(&str) -> Option<String>,
+        config_proxy: Option<&str>,
+        config_no_proxy: &[String],
+    ) -> Self {
+        let var = |name: &str| {
+            lookup(name)
+                .or_else(|| lookup(&name.to_ascii_lowercase()))
+                .filter(|value| !value.trim().is_empty())
+        };
+        let fallback = var("ALL_PROXY").or_else(|| config_proxy.map(String::from));
+
+        let no_proxy = match var("NO_PROXY") {
+            Some(list) => list
+                .split(',')
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(String::from)
+                .collect(),
+            None => config_no_proxy.to_vec(),
+        };
+
+        Self {
+            http: var("HTTP_PROXY").or_else(|| fallback.clone()),
+            https: var("HTTPS_PROXY").or(fallback),
+            no_proxy,
+        }
+    }
+
+    /// Proxy to use for `url`, if any
+    pub fn for_url(&self, url: &str) -> Option<&str> {
+        let (scheme, rest) = url.split_once("://")?;
+        let proxy = match scheme.to_ascii_lowercase().as_str() {
+            "http" => self.http.as_deref(),
+            "https" => self.https.as_deref(),
+            _ => None,
+        }?;
+
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        let host_port = authority.rsplit('@').next().unwrap_or_default();
+        let host = match host_port.strip_prefix('[') {
+            // IPv6 literal
+            Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+            None => host_port.split(':').next().unwrap_or_default(),
+        };
+        (!self.bypasses(host)).then_some(proxy)
+    }
+
+    /// Whether `host` is listed in `no_proxy`
+    fn bypasses(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.no_proxy.iter().any(|entry| {
+            let entry = entry.trim_start_matches('.').to_ascii_lowercase();
+            entry == "*"
+                || host == entry
+                || host
+                    .strip_suffix(&entry)
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    }
+
+    /// Environment variables passing these settings on to a subprocess
+    pub fn env_vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = Vec::new();
+        if let Some(http) = &self.http {
+            vars.push(("HTTP_PROXY", http.clone()));
+        }
+        if let Some(https) = &self.https {
+            vars.push(("HTTPS_PROXY", https.clone()));
+        }
+        if !self.no_proxy.is_empty() {
+            vars.push(("NO_PROXY", self.no_proxy.join(",")));
+        }
+        vars
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn settings(vars: &[(&str, &str)], config_proxy: Option<&str>) -> ProxySettings {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        ProxySettings::from_lookup(|name| vars.get(name).cloned(), config_proxy, &[])
+    }
+
+    #[test]
+    fn test_env_vars_take_precedence_over_config() {
+        assert_eq!(settings(&[], None), ProxySettings::default());
+
+        let proxy = settings(&[], Some("http://config:3128"));
+        assert_eq!(proxy.http.as_deref(), Some("http://config:3128"));
+        assert_eq!(proxy.https.as_deref(), Some("http://config:3128"));
+
+        let proxy = settings(
+            &[
+                ("https_proxy", "http://secure:8080"),
+                ("ALL_PROXY", "socks5://all:1080"),
+            ],
+            Some("http://config:3128"),
+        );
+        assert_eq!(proxy.http.as_deref(), Some("socks5://all:1080"));
+        assert_eq!(proxy.https.as_deref(), Some("http://secure:8080"));
+    }
+
+    #[test]
+    fn test_for_url_honors_no_proxy() {
+        let proxy = settings(
+            &[
+                ("HTTPS_PROXY", "http://proxy:8080"),
+                ("NO_PROXY", "localhost, .internal.example,10.0.0.1"),
+            ],
+            None,
+        );
+        assert_eq!(
+            proxy.for_url("https://fullnode.testnet.sui.io:443"),
+            Some("http://proxy:8080")
+        );
+        assert_eq!(proxy.for_url("http://fullnode.testnet.sui.io"), None);
+        assert_eq!(proxy.for_url("https://localhost:9000/rpc"), None);
+        assert_eq!(proxy.for_url("https://agg.internal.example/v1"), None);
+        assert_eq!(proxy.for_url("https://user@10.0.0.1:9000"), None);
+        assert_eq!(
+            proxy.for_url("https://notinternal.example"),
+            Some("http://proxy:8080")
+        );
+
+        assert_eq!(
+            proxy.env_vars(),
+            vec![
+                ("HTTPS_PROXY", "http://proxy:8080".to_string()),
+                (
+                    "NO_PROXY",
+                    "localhost,.internal.example,10.0.0.1".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,490 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::{config::WalrusRemoteConfig, walrus::BlobPersistence};
+
+/// Remote storage backend type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteType {
+    Filesystem(PathBuf),
+    Sui(String), // Sui object ID as hex string
+}
+
+/// Backends a remote URL can name explicitly as `walrus+<scheme>::<address>`, and whether
+/// this build has them
+///
+/// Without a scheme, addresses that look like `0x<hex>` are Sui object IDs and anything else
+/// is a filesystem path.
+const SCHEMES: &[(&str, bool)] = &[("fs", true), ("sui", true), ("s3", false), ("mem", false)];
+
+/// Split `walrus+<scheme>::` (or plain `walrus::`) off a remote URL
+fn split_scheme(url: &str) -> Result<(Option<&str>, &str)> {
+    if let Some(address) = url.strip_prefix("walrus::") {
+        return Ok((None, address));
+    }
+    let Some((scheme, address)) = url
+        .strip_prefix("walrus+")
+        .and_then(|rest| rest.split_once("::"))
+    else {
+        return Ok((None, url));
+    };
+    match SCHEMES.iter().find(|(name, _)| *name == scheme) {
+        Some((_, true)) => Ok((Some(scheme), address)),
+        Some((_, false)) => anyhow::bail!(
+            "walrus+{}:: remotes are not supported yet: this build has no {} backend",
+            scheme,
+            scheme
+        ),
+        None => anyhow::bail!(
+            "Unknown storage scheme walrus+{}:: (expected one of {})",
+            scheme,
+            SCHEMES
+                .iter()
+                .map(|(name, _)| format!("walrus+{}::", name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Per-remote overrides given as URL query parameters
+/// (e.g. `walrus::0x1234?blob_persistence=deletable`)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteOptions {
+    /// Override for `blob_persistence`
+    pub blob_persistence: Option<BlobPersistence>,
+    /// Override for `walrus_encoding`
+    pub walrus_encoding: Option<String>,
+    /// Repo namespace on a shared RemoteState (`namespace=` or the `0x.../<repo>` path suffix)
+    pub namespace: Option<String>,
+    /// Advertise every ref, ignoring `advertise_ref_patterns` (`all_refs=true`)
+    pub all_refs: bool,
+    /// Override for `list_banner`
+    pub list_banner: Option<bool>,
+    /// Transaction digest whose resulting state reads are pinned to (the `0x...@<digest>` suffix)
+    pub pinned_transaction: Option<String>,
+}
+
+impl RemoteOptions {
+    /// Parse a `key=value&key=value` query string
+    fn parse(query: &str) -> Result<Self> {
+        let mut options = Self::default();
+
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .with_context(|| format!("Invalid remote URL parameter: {:?}", pair))?;
+
+            match key {
+                "blob_persistence" => {
+                    options.blob_persistence = Some(
+                        value
+                            .parse()
+                            .context("Invalid blob_persistence in remote URL")?,
+                    );
+                }
+                "walrus_encoding" => {
+                    if value.is_empty() {
+                        anyhow::bail!("Empty walrus_encoding in remote URL");
+                    }
+                    options.walrus_encoding = Some(value.to_string());
+                }
+                "namespace" => {
+                    validate_namespace(value)?;
+                    options.namespace = Some(value.to_string());
+                }
+                "all_refs" => {
+                    options.all_refs = value
+                        .parse()
+                        .context("Invalid all_refs in remote URL (expected true or false)")?;
+                }
+                "banner" => {
+                    options.list_banner = Some(
+                        value
+                            .parse()
+                            .context("Invalid banner in remote URL (expected true or false)")?,
+                    );
+                }
+                _ => anyhow::bail!("Unknown remote URL parameter: {:?}", key),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Apply these overrides on top of the loaded configuration
+    pub fn apply(&self, config: &mut WalrusRemoteConfig) {
+        if let Some(persistence) = self.blob_persistence {
+            config.blob_persistence = persistence;
+        }
+        if let Some(encoding) = &self.walrus_encoding {
+            config.walrus_encoding = Some(encoding.clone());
+        }
+        if let Some(banner) = self.list_banner {
+            config.list_banner = banner;
+        }
+        if let Some(digest) = &self.pinned_transaction {
+            // Historical states can be read but never written
+            config.pinned_transaction = Some(digest.clone());
+            config.read_only = true;
+        }
+    }
+}
+
+/// Check that a repo namespace is a single ref-safe path component
+fn validate_namespace(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && !name.ends_with(".lock")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        anyhow::bail!(
+            "Invalid repo namespace {:?} (use letters, digits, '-', '_' and '.')",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Check that a pinned transaction digest is base58, as Sui prints digests
+fn validate_digest(digest: &str) -> Result<()> {
+    const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    let valid = (32..=44).contains(&digest.len()) && digest.chars().all(|c| BASE58.contains(c));
+    if !valid {
+        anyhow::bail!(
+            "Invalid transaction digest {:?} in remote URL (expected a base58 Sui digest)",
+            digest
+        );
+    }
+    Ok(())
+}
+
+/// A parsed remote URL: backend plus per-remote options
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteUrl {
+    pub remote_type: RemoteType,
+    pub options: RemoteOptions,
+}
+
+/// Resolve the `<remote> <url>` arguments git invokes the helper with into (name, URL)
+///
+/// When the remote is configured by URL only (e.g. `git push walrus::0x1234 main`), git
+/// passes the URL as the name too, and may omit the second argument entirely.
+///
+/// Git runs `walrus+<scheme>::` remotes through a `git-remote-walrus+<scheme>` helper (a link
+/// to this binary) and passes only the address, so `program` (the helper's `argv[0]`) puts
+/// the scheme back.
+pub fn resolve_helper_args(
+    program: &str,
+    name: Option<String>,
+    url: Option<String>,
+) -> Result<(String, String)> {
+    let (name, url) = match (name, url) {
+        (Some(name), Some(url)) => (name, url),
+        (Some(url), None) => (url.clone(), url),
+        (None, _) => anyhow::bail!("Missing remote URL"),
+    };
+    let scheme = std::path::Path::new(program)
+        .file_name()
+        .and_then(|file| file.to_str())
+        .and_then(|file| file.strip_prefix("git-remote-walrus+"));
+    match scheme {
+        Some(scheme) if !url.starts_with("walrus::") && !url.starts_with("walrus+") => {
+            let url = format!("walrus+{}::{}", scheme, url);
+            Ok((name, url))
+        }
+        _ => Ok((name, url)),
+    }
+}
+
+pub fn parse_remote_url(url: &str) -> Result<RemoteUrl> {
+    tracing::debug!("Parsing URL: '{}'", url);
+
+    // Git strips the protocol prefix, so we might receive either:
+    // - "walrus::/path/to/storage" (user-specified format)
+    // - "/path/to/storage" (Git has already stripped "walrus::")
+    // - "walrus::0x1234..." (Sui object ID)
+    // - "0x1234..." (Git has already stripped "walrus::")
+    // - "walrus+fs::/path" or "walrus+sui::0x1234..." (explicit backend)
+    let (scheme, url) = split_scheme(url)?;
+
+    // Optional per-remote overrides after '?'
+    let (path_str, options) = match url.split_once('?') {
+        Some((path, query)) => (path, RemoteOptions::parse(query)?),
+        None => (url, RemoteOptions::default()),
+    };
+
+    // Try to parse as Sui object ID (0x prefix + hex chars), optionally followed by /<repo>
+    // and @<transaction digest>
+    if scheme != Some("fs") && path_str.starts_with("0x") && path_str.len() > 2 {
+        let (path_str, pinned) = match path_str.split_once('@') {
+            Some((path, digest)) => (path, Some(digest)),
+            None => (path_str, None),
+        };
+        let (object_id, repo) = match path_str.split_once('/') {
+            Some((object_id, repo)) => (object_id, Some(repo)),
+            None => (path_str, None),
+        };
+
+        // Validate hex characters after 0x
+        let hex_part = &object_id[2..];
+        if !hex_part.is_empty() && hex_part.chars().all(|c| c.is_ascii_hexdigit()) {
+            let mut options = options;
+            if let Some(repo) = repo {
+                validate_namespace(repo)?;
+                if options.namespace.is_some() {
+                    anyhow::bail!("Remote URL gives a repo namespace twice: {:?}", url);
+                }
+                options.namespace = Some(repo.to_string());
+            }
+            if let Some(digest) = pinned {
+                validate_digest(digest)?;
+                options.pinned_transaction = Some(digest.to_string());
+            }
+            return Ok(RemoteUrl {
+                remote_type: RemoteType::Sui(object_id.to_string()),
+                options,
+            });
+        }
+    }
+
+    if scheme == Some("sui") {
+        anyhow::bail!(
+            "Invalid Sui object ID in remote URL: {:?} (expected 0x<hex>[/<repo>])",
+            path_str
+        );
+    }
+
+    // Treat as filesystem path
+    Ok(RemoteUrl {
+        remote_type: RemoteType::Filesystem(PathBuf::from(path_str)),
+        options,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sui_and_filesystem() {
+        let url = parse_remote_url("walrus::0xabc123").unwrap();
+        assert_eq!(url.remote_type, RemoteType::Sui("0xabc123".to_string()));
+        assert_eq!(url.options, RemoteOptions::default());
+
+        let url = parse_remote_url("/tmp/remote").unwrap();
+        assert_eq!(
+            url.remote_type,
+            RemoteType::Filesystem(PathBuf::from("/tmp/remote"))
+        );
+    }
+
+    #[test]
+    fn test_resolve_helper_args() {
+        let helper = "git-remote-walrus";
+
+        // Named remote
+        let (name, url) =
+            resolve_helper_args(helper, Some("origin".into()), Some("walrus::0xabc".into()))
+                .unwrap();
+        assert_eq!(name, "origin");
+        assert_eq!(url, "walrus::0xabc");
+
+        // Remote configured by URL only: URL in both positions
+        let (name, url) =
+            resolve_helper_args(helper, Some("walrus::0xabc".into()), Some("0xabc".into()))
+                .unwrap();
+        assert_eq!(name, "walrus::0xabc");
+        assert_eq!(url, "0xabc");
+
+        // URL given only once
+        let (name, url) = resolve_helper_args(helper, Some("walrus::0xabc".into()), None).unwrap();
+        assert_eq!(name, "walrus::0xabc");
+        assert_eq!(url, "walrus::0xabc");
+
+        assert!(resolve_helper_args(helper, None, None).is_err());
+    }
+
+    #[test]
+    fn test_resolve_helper_args_restores_scheme() {
+        // git runs walrus+fs:: remotes through the git-remote-walrus+fs link, address only
+        let helper = "/usr/local/bin/git-remote-walrus+fs";
+        let (name, url) =
+            resolve_helper_args(helper, Some("origin".into()), Some("0xbeef".into())).unwrap();
+        assert_eq!(name, "origin");
+        assert_eq!(url, "walrus+fs::0xbeef");
+
+        let (_, url) = resolve_helper_args(helper, Some("walrus+fs::0xbeef".into()), None).unwrap();
+        assert_eq!(url, "walrus+fs::0xbeef");
+    }
+
+    #[test]
+    fn test_parse_explicit_schemes() {
+        // An all-hex directory is a path when the scheme says so
+        let url = parse_remote_url("walrus+fs::0xdeadbeef").unwrap();
+        assert_eq!(
+            url.remote_type,
+            RemoteType::Filesystem(PathBuf::from("0xdeadbeef"))
+        );
+        let url = parse_remote_url("walrus+fs::/tmp/remote?namespace=frontend").unwrap();
+        assert_eq!(
+            url.remote_type,
+            RemoteType::Filesystem(PathBuf::from("/tmp/remote"))
+        );
+        assert_eq!(url.options.namespace.as_deref(), Some("frontend"));
+
+        let url = parse_remote_url("walrus+sui::0xabc/myrepo?banner=false").unwrap();
+        assert_eq!(url.remote_type, RemoteType::Sui("0xabc".to_string()));
+        assert_eq!(url.options.namespace.as_deref(), Some("myrepo"));
+        let err = parse_remote_url("walrus+sui::/tmp/remote").unwrap_err();
+        assert!(err.to_string().contains("Invalid Sui object ID"), "{}", err);
+
+        for (url, message) in [
+            ("walrus+s3::bucket/prefix", "this build has no s3 backend"),
+            ("walrus+mem::scratch", "this build has no mem backend"),
+            (
+                "walrus+ftp::host/path",
+                "Unknown storage scheme walrus+ftp::",
+            ),
+        ] {
+            let err = parse_remote_url(url).unwrap_err().to_string();
+            assert!(err.contains(message), "{}: {}", url, err);
+        }
+    }
+
+    #[test]
+    fn test_parse_without_scheme_uses_heuristic() {
+        for url in ["0xabc", "walrus::0xabc", "walrus::0xABC123/repo"] {
+            assert!(
+                matches!(
+                    parse_remote_url(url).unwrap().remote_type,
+                    RemoteType::Sui(_)
+                ),
+                "{}",
+                url
+            );
+        }
+        for url in [
+            "/tmp/remote",
+            "walrus::relative/dir",
+            "0x",
+            "0xnothex",
+            "walrus::0xg1",
+        ] {
+            assert!(
+                matches!(
+                    parse_remote_url(url).unwrap().remote_type,
+                    RemoteType::Filesystem(_)
+                ),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_blob_persistence_param() {
+        let url = parse_remote_url("walrus::0xabc?blob_persistence=deletable").unwrap();
+        assert_eq!(url.remote_type, RemoteType::Sui("0xabc".to_string()));
+        assert_eq!(
+            url.options.blob_persistence,
+            Some(BlobPersistence::Deletable)
+        );
+
+        assert!(parse_remote_url("0xabc?blob_persistence=sometimes").is_err());
+        assert!(parse_remote_url("0xabc?colour=blue").is_err());
+    }
+
+    #[test]
+    fn test_parse_walrus_encoding_param() {
+        let url = parse_remote_url("0xabc?blob_persistence=deletable&walrus_encoding=RS2").unwrap();
+        assert_eq!(url.options.walrus_encoding.as_deref(), Some("RS2"));
+        assert!(parse_remote_url("0xabc?walrus_encoding=").is_err());
+    }
+
+    #[test]
+    fn test_parse_repo_namespace() {
+        let url = parse_remote_url("walrus::0xabc/myrepo").unwrap();
+        assert_eq!(url.remote_type, RemoteType::Sui("0xabc".to_string()));
+        assert_eq!(url.options.namespace.as_deref(), Some("myrepo"));
+
+        let url = parse_remote_url("0xabc/myrepo?blob_persistence=deletable").unwrap();
+        assert_eq!(url.options.namespace.as_deref(), Some("myrepo"));
+
+        // Filesystem remotes take it as a parameter
+        let url = parse_remote_url("/tmp/remote?namespace=other").unwrap();
+        assert_eq!(
+            url.remote_type,
+            RemoteType::Filesystem(PathBuf::from("/tmp/remote"))
+        );
+        assert_eq!(url.options.namespace.as_deref(), Some("other"));
+
+        assert!(parse_remote_url("0xabc/").is_err());
+        assert!(parse_remote_url("0xabc/a/b").is_err());
+        assert!(parse_remote_url("0xabc/..").is_err());
+        assert!(parse_remote_url("0xabc/one?namespace=two").is_err());
+    }
+
+    #[test]
+    fn test_parse_all_refs_param() {
+        assert!(!parse_remote_url("0xabc").unwrap().options.all_refs);
+        assert!(
+            parse_remote_url("0xabc/myrepo?all_refs=true")
+                .unwrap()
+                .options
+                .all_refs
+        );
+        assert!(parse_remote_url("/tmp/remote?all_refs=yes").is_err());
+    }
+
+    #[test]
+    fn test_parse_banner_param() {
+        assert_eq!(parse_remote_url("0xabc").unwrap().options.list_banner, None);
+        assert_eq!(
+            parse_remote_url("0xabc?banner=false")
+                .unwrap()
+                .options
+                .list_banner,
+            Some(false)
+        );
+        assert!(parse_remote_url("0xabc?banner=1").is_err());
+    }
+
+    #[test]
+    fn test_parse_pinned_transaction() {
+        let digest = "7xVHGwaGb3DVpZZmJQu8ngAJYEiJ9cACu5BhhWzAtrAS";
+        let url = parse_remote_url(&format!("walrus::0xabc@{}", digest)).unwrap();
+        assert_eq!(url.remote_type, RemoteType::Sui("0xabc".to_string()));
+        assert_eq!(url.options.pinned_transaction.as_deref(), Some(digest));
+        assert_eq!(
+            parse_remote_url("0xabc")
+                .unwrap()
+                .options
+                .pinned_transaction,
+            None
+        );
+
+        let url = parse_remote_url(&format!("0xabc/myrepo@{}?all_refs=true", digest)).unwrap();
+        assert_eq!(url.options.namespace.as_deref(), Some("myrepo"));
+        assert_eq!(url.options.pinned_transaction.as_deref(), Some(digest));
+        assert!(url.options.all_refs);
+
+        // Pinned remotes are read-only
+        let mut config: WalrusRemoteConfig =
+            serde_yaml::from_str("sui_wallet_path: /wallet\ncache_dir: /cache\n").unwrap();
+        url.options.apply(&mut config);
+        assert_eq!(config.pinned_transaction.as_deref(), Some(digest));
+        assert!(config.read_only);
+
+        assert!(parse_remote_url("0xabc@").is_err());
+        assert!(parse_remote_url("0xabc@0OIl0OIl0OIl0OIl0OIl0OIl0OIl0OIl0OIl").is_err());
+        // '@' is an ordinary character in filesystem paths
+        assert_eq!(
+            parse_remote_url("/tmp/me@host").unwrap().remote_type,
+            RemoteType::Filesystem(PathBuf::from("/tmp/me@host"))
+        );
+    }
+}
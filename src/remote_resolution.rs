@@ -0,0 +1,293 @@
+//! Which remote a subcommand works on: the URL, object ID or path given, or one of the current
+//! repository's git remotes
+
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Result;
+
+use crate::subprocess::CommandRunner;
+
+/// Git remote tried first when none is named
+const DEFAULT_REMOTE: &str = "origin";
+
+/// A remote to open: the name its `remote.<name>.walrus*` settings are read under, and its URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    /// Git remote name, or for a URL given outright the URL itself (as git names URL-only
+    /// remotes)
+    pub name: String,
+    pub url: String,
+}
+
+impl Remote {
+    /// Resolve a remote given on the command line: a git remote of the current repository by
+    /// that name, otherwise a URL, object ID or path
+    pub fn resolve(spec: &str) -> Result<Self> {
+        Self::resolve_in(spec, None)
+    }
+
+    fn resolve_in(spec: &str, repo: Option<&Path>) -> Result<Self> {
+        // Git remote names can't hold `::`, so URLs skip reading git config
+        if spec.contains("::") {
+            return Ok(Self::url(spec));
+        }
+        lookup(&read_remote_urls(repo)?, spec)
+    }
+
+    /// A remote known only by its URL
+    pub fn url(url: &str) -> Self {
+        Self {
+            name: url.to_string(),
+            url: url.to_string(),
+        }
+    }
+}
+
+/// A subcommand's remote, given outright or resolved from git config
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct RemoteArg {
+    /// Git remote name, remote URL, object ID or filesystem path (e.g. origin, walrus::0x1234...
+    /// or walrus::/path); default: the repository's walrus remote
+    #[arg(value_name = "REMOTE")]
+    pub url: Option<String>,
+    /// Git remote whose URL to use (default: origin, else the only walrus remote)
+    #[arg(long = "remote", value_name = "NAME", conflicts_with = "url")]
+    pub name: Option<String>,
+}
+
+impl RemoteArg {
+    /// The remote to open, reading git config from the current directory if needed
+    pub fn resolve(&self) -> Result<Remote> {
+        self.resolve_in(None)
+    }
+
+    fn resolve_in(&self, repo: Option<&Path>) -> Result<Remote> {
+        if let Some(spec) = &self.url {
+            return Remote::resolve_in(spec, repo);
+        }
+        let remotes = read_remote_urls(repo)?;
+        let remote = choose(&remotes, self.name.as_deref())?;
+        tracing::debug!("Resolved remote {} ({})", remote.name, remote.url);
+        Ok(remote)
+    }
+}
+
+/// True for URLs git hands to this helper (`walrus::` or `walrus+<scheme>::`)
+fn is_walrus_url(url: &str) -> bool {
+    url.starts_with("walrus::")
+        || url
+            .strip_prefix("walrus+")
+            .is_some_and(|rest| rest.contains("::"))
+}
+
+/// `remote.<name>.url` of every git remote `repo` (the current directory if None) has
+fn read_remote_urls(repo: Option<&Path>) -> Result<BTreeMap<String, String>> {
+    let mut runner =
+        CommandRunner::git().args(["config", "-z", "--get-regexp", r"^remote\..*\.url$"]);
+    if let Some(repo) = repo {
+        runner = runner.current_dir(repo);
+    }
+    let output = runner.output()?;
+    match output.status.code() {
+        Some(0) => {}
+        // No remotes (or not in a repository)
+        Some(1) => return Ok(BTreeMap::new()),
+        _ => anyhow::bail!(
+            "Failed to read git remotes: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+
+    // `<key>\n<value>\0`, where the remote name (the subsection) may itself contain dots
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter_map(|entry| {
+            let (key, url) = entry.split_once('\n')?;
+            let name = key.strip_prefix("remote.")?.strip_suffix(".url")?;
+            Some((name.to_string(), url.to_string()))
+        })
+        .collect())
+}
+
+/// The git remote `spec` names, if it is one of them, otherwise `spec` as a URL
+fn lookup(remotes: &BTreeMap<String, String>, spec: &str) -> Result<Remote> {
+    match remotes.get(spec) {
+        Some(_) => choose(remotes, Some(spec)),
+        None => Ok(Remote::url(spec)),
+    }
+}
+
+/// The remote `name`, or without one, origin or the only walrus remote
+fn choose(remotes: &BTreeMap<String, String>, name: Option<&str>) -> Result<Remote> {
+    let walrus: Vec<&String> = remotes
+        .iter()
+        .filter(|(_, url)| is_walrus_url(url))
+        .map(|(name, _)| name)
+        .collect();
+    let configured = || {
+        if walrus.is_empty() {
+            "this repository has no walrus remotes".to_string()
+        } else {
+            format!(
+                "walrus remotes: {}",
+                walrus
+                    .iter()
+                    .map(|name| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        }
+    };
+
+    let found = |name: &str, url: &str| Remote {
+        name: name.to_string(),
+        url: url.to_string(),
+    };
+    if let Some(name) = name {
+        return match remotes.get(name) {
+            Some(url) if is_walrus_url(url) => Ok(found(name, url)),
+            Some(url) => anyhow::bail!(
+                "Remote {} is not a walrus remote ({}); {}",
+                name,
+                url,
+                configured()
+            ),
+            None => anyhow::bail!("No git remote named {}; {}", name, configured()),
+        };
+    }
+
+    if let Some(url) = remotes.get(DEFAULT_REMOTE).filter(|url| is_walrus_url(url)) {
+        return Ok(found(DEFAULT_REMOTE, url));
+    }
+    match walrus.as_slice() {
+        [only] => Ok(found(only, &remotes[*only])),
+        [] => anyhow::bail!(
+            "No remote given and {}; pass a remote URL or object ID",
+            configured()
+        ),
+        _ => anyhow::bail!(
+            "No remote given and origin isn't a walrus remote; pick one with --remote <name> \
+             ({})",
+            configured()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A repository with `remotes` (name, URL) configured
+    fn repo(remotes: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            CommandRunner::git()
+                .current_dir(dir.path())
+                .args(args)
+                .run()
+                .unwrap();
+        };
+        git(&["init", "-q"]);
+        for (name, url) in remotes {
+            git(&["remote", "add", name, url]);
+        }
+        dir
+    }
+
+    fn resolve(repo: &tempfile::TempDir, name: Option<&str>) -> Result<String> {
+        RemoteArg {
+            url: None,
+            name: name.map(str::to_string),
+        }
+        .resolve_in(Some(repo.path()))
+        .map(|remote| remote.url)
+    }
+
+    #[test]
+    fn test_no_walrus_remotes() {
+        let repo = repo(&[("origin", "https://example.com/repo.git")]);
+        let err = resolve(&repo, None).unwrap_err().to_string();
+        assert!(err.contains("has no walrus remotes"), "{}", err);
+
+        let err = resolve(&repo, Some("origin")).unwrap_err().to_string();
+        assert!(err.contains("not a walrus remote"), "{}", err);
+
+        // A URL given outright needs no git remote at all
+        let given = RemoteArg {
+            url: Some("walrus::0x1234".to_string()),
+            name: None,
+        };
+        assert_eq!(
+            given.resolve_in(Some(repo.path())).unwrap(),
+            Remote::url("walrus::0x1234")
+        );
+    }
+
+    #[test]
+    fn test_one_walrus_remote() {
+        let repo = repo(&[
+            ("origin", "https://example.com/repo.git"),
+            ("storage", "walrus::0x1234"),
+        ]);
+        assert_eq!(resolve(&repo, None).unwrap(), "walrus::0x1234");
+        assert_eq!(resolve(&repo, Some("storage")).unwrap(), "walrus::0x1234");
+
+        let err = resolve(&repo, Some("backup")).unwrap_err().to_string();
+        assert!(err.contains("No git remote named backup"), "{}", err);
+        assert!(err.contains("walrus remotes: storage"), "{}", err);
+    }
+
+    #[test]
+    fn test_several_walrus_remotes() {
+        let several = repo(&[
+            ("mirror", "walrus+fs::/srv/mirror"),
+            ("storage", "walrus::0x1234"),
+        ]);
+        let err = resolve(&several, None).unwrap_err().to_string();
+        assert!(err.contains("--remote <name>"), "{}", err);
+        assert!(err.contains("walrus remotes: mirror, storage"), "{}", err);
+        assert_eq!(
+            resolve(&several, Some("mirror")).unwrap(),
+            "walrus+fs::/srv/mirror"
+        );
+
+        // origin wins when it's one of them
+        let with_origin = repo(&[("origin", "walrus::0xabcd"), ("storage", "walrus::0x1234")]);
+        assert_eq!(resolve(&with_origin, None).unwrap(), "walrus::0xabcd");
+    }
+
+    #[test]
+    fn test_resolved_remotes_keep_their_name() {
+        let repo = repo(&[("origin", "walrus::0xabcd"), ("mirror", "walrus::0x1234")]);
+        let remote = |name: &str, url: &str| Remote {
+            name: name.to_string(),
+            url: url.to_string(),
+        };
+
+        // Found through git config: settings are read under the git remote's name
+        let arg = |url: Option<&str>, name: Option<&str>| {
+            RemoteArg {
+                url: url.map(str::to_string),
+                name: name.map(str::to_string),
+            }
+            .resolve_in(Some(repo.path()))
+            .unwrap()
+        };
+        assert_eq!(arg(None, None), remote("origin", "walrus::0xabcd"));
+        assert_eq!(
+            arg(None, Some("mirror")),
+            remote("mirror", "walrus::0x1234")
+        );
+        assert_eq!(
+            arg(Some("mirror"), None),
+            remote("mirror", "walrus::0x1234")
+        );
+
+        // Anything else is a URL, object ID or path
+        let spec = |spec: &str| Remote::resolve_in(spec, Some(repo.path())).unwrap();
+        assert_eq!(spec("origin"), remote("origin", "walrus::0xabcd"));
+        assert_eq!(spec("walrus::0x1234"), Remote::url("walrus::0x1234"));
+        assert_eq!(spec("0x5678"), Remote::url("0x5678"));
+        assert_eq!(spec("/srv/storage"), Remote::url("/srv/storage"));
+    }
+}
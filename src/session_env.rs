@@ -0,0 +1,160 @@
+//! Working directory and environment a helper session runs with
+//!
+//! A helper runs with its own. A daemon serves several helpers' sessions at once, each on its
+//! own thread, so it scopes theirs to that thread instead of changing the process's: lookups
+//! here and every [`CommandRunner`](crate::subprocess::CommandRunner) subprocess see the
+//! session's, and the process's stay untouched.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::{Context, Result};
+
+thread_local! {
+    static SESSION: RefCell<Option<Rc<SessionEnv>>> = const { RefCell::new(None) };
+}
+
+/// A helper's working directory and the part of its environment sessions depend on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionEnv {
+    /// Working directory git ran the helper in
+    pub cwd: PathBuf,
+    /// The helper's variables for which [`is_session_var`] holds
+    pub vars: BTreeMap<String, String>,
+}
+
+/// Variables a session reads: git's (`GIT_DIR` above all), configuration overrides, the
+/// signing keyring and proxies (in either case)
+pub fn is_session_var(key: &str) -> bool {
+    ["GIT_", "WALRUS_", "SUI_"]
+        .iter()
+        .any(|prefix| key.starts_with(prefix))
+        || [
+            "GNUPGHOME",
+            "HTTP_PROXY",
+            "HTTPS_PROXY",
+            "ALL_PROXY",
+            "NO_PROXY",
+        ]
+        .contains(&key.to_ascii_uppercase().as_str())
+}
+
+impl SessionEnv {
+    /// This process's own working directory and session variables
+    pub fn current() -> Result<Self> {
+        Ok(Self {
+            cwd: env::current_dir().context("Failed to read the working directory")?,
+            vars: env::vars_os()
+                .filter_map(|(key, value)| {
+                    Some((key.into_string().ok()?, value.into_string().ok()?))
+                })
+                .filter(|(key, _)| is_session_var(key))
+                .collect(),
+        })
+    }
+
+    /// Run `f` on this thread as this session, restoring the previous one afterwards
+    pub fn scope<T>(self, f: impl FnOnce() -> T) -> T {
+        /// Restores the outer session even if `f` panics
+        struct Restore(Option<Rc<SessionEnv>>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                SESSION.with(|session| *session.borrow_mut() = self.0.take());
+            }
+        }
+
+        let _restore = Restore(SESSION.with(|session| session.replace(Some(Rc::new(self)))));
+        f()
+    }
+}
+
+/// The session this thread is serving, if any
+pub(crate) fn active() -> Option<Rc<SessionEnv>> {
+    SESSION.with(|session| session.borrow().clone())
+}
+
+/// The session this thread is serving, to hand to the worker threads its work fans out to
+/// (rayon pools, scoped threads), which start outside it
+pub fn snapshot() -> Option<SessionEnv> {
+    active().map(|session| SessionEnv::clone(&session))
+}
+
+/// Run `f` as `session` (from [`snapshot`]), or as the process if there is none
+pub fn enter<T>(session: Option<SessionEnv>, f: impl FnOnce() -> T) -> T {
+    match session {
+        Some(session) => session.scope(f),
+        None => f(),
+    }
+}
+
+/// `key` as the session sees it: from its own variables when serving one, else this process's
+pub fn var(key: &str) -> Option<String> {
+    match active() {
+        Some(session) if is_session_var(key) => session.vars.get(key).cloned(),
+        _ => env::var(key).ok(),
+    }
+}
+
+/// [`var`], for values that needn't be UTF-8 outside a session
+pub fn var_os(key: &str) -> Option<OsString> {
+    match active() {
+        Some(session) if is_session_var(key) => session.vars.get(key).map(OsString::from),
+        _ => env::var_os(key),
+    }
+}
+
+/// The session's working directory, else this process's
+pub fn current_dir() -> Result<PathBuf> {
+    match active() {
+        Some(session) => Ok(session.cwd.clone()),
+        None => env::current_dir().context("Failed to read the working directory"),
+    }
+}
+
+/// `path` resolved against the session's working directory if it's relative
+pub fn absolute(path: &Path) -> Result<PathBuf> {
+    Ok(current_dir()?.join(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_overrides_session_vars_on_this_thread_only() {
+        let session = SessionEnv {
+            cwd: PathBuf::from("/srv/repo"),
+            vars: [("GIT_DIR".to_string(), ".git".to_string())].into(),
+        };
+        let outside = var("GIT_DIR");
+
+        session.scope(|| {
+            assert_eq!(var("GIT_DIR").as_deref(), Some(".git"));
+            assert_eq!(current_dir().unwrap(), PathBuf::from("/srv/repo"));
+            assert_eq!(
+                absolute(Path::new(".git")).unwrap(),
+                PathBuf::from("/srv/repo/.git")
+            );
+            // Anything else is the daemon's own
+            assert_eq!(var("PATH"), env::var("PATH").ok());
+
+            let other = std::thread::spawn(|| var("GIT_DIR")).join().unwrap();
+            assert_eq!(other, outside);
+
+            // Worker threads see it once they enter a snapshot
+            let session = snapshot();
+            let worker = std::thread::spawn(|| enter(session, || var("GIT_DIR")))
+                .join()
+                .unwrap();
+            assert_eq!(worker.as_deref(), Some(".git"));
+        });
+        assert_eq!(var("GIT_DIR"), outside);
+        assert!(active().is_none());
+    }
+}
@@ -1,13 +1,52 @@
+mod blob_cache;
 mod cache_index;
+mod canonical;
 mod content_id;
 mod filesystem;
+mod format;
+mod manifest;
+#[cfg(test)]
+mod memory;
+pub mod metadata;
 mod state;
 mod traits;
 mod walrus;
 
-pub use cache_index::CacheIndex;
+pub use cache_index::{
+    CacheIndex,
+    CachedObjects,
+    ContentKind,
+    SiblingIndexes,
+    CACHE_INDEX_FILE,
+    REMOTES_DIR,
+};
+pub use canonical::to_canonical;
 pub use content_id::ParsedContentId;
 pub use filesystem::FilesystemStorage;
-pub use state::State;
-pub use traits::{ContentId, ImmutableStore, MutableState, StorageBackend};
+pub use format::{FormatHeader, FORMAT_KEY};
+pub use manifest::{
+    blob_count,
+    consolidate,
+    rewrite_consolidated,
+    rewrite_objects,
+    BlobManifest,
+    Repack,
+};
+#[cfg(test)]
+pub(crate) use memory::tests::{store_object, store_object_in};
+#[cfg(test)]
+pub use memory::MemoryStorage;
+pub use metadata::{PushCertificate, PushRecord, RepoMetadata};
+pub use state::{MappingConflict, ObjectsDiff, RefRepair, State};
+pub use traits::{
+    verify_git_object,
+    ContentId,
+    ImmutableStore,
+    MutableState,
+    ReadSource,
+    StateChanged,
+    StorageBackend,
+};
+#[cfg(test)]
+pub(crate) use walrus::tests::MockRemote;
 pub use walrus::WalrusStorage;
@@ -0,0 +1,193 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+/// Length of the SHA-256 of its blob each cached file starts with
+const DIGEST_LEN: usize = 32;
+
+/// Whole Walrus blobs cached by blob object ID, so every ContentId slicing a blob (legacy or
+/// batched, in this session or a later one) reads it without downloading it again
+///
+/// Blobs are kept under `<cache_dir>/blobs`, using at most `max_bytes` in total; the least
+/// recently read blobs are evicted first, and a limit of 0 disables the cache. Each file holds
+/// its blob's SHA-256 before the blob, and a blob that no longer matches it is dropped.
+#[derive(Debug, Clone)]
+pub struct BlobCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl BlobCache {
+    pub fn new(cache_dir: &Path, max_bytes: u64) -> Self {
+        Self {
+            dir: cache_dir.join("blobs"),
+            max_bytes,
+        }
+    }
+
+    /// Cached bytes of the blob `blob_object_id`, marking it recently used
+    ///
+    /// A damaged blob is removed, and read as missing.
+    pub fn get(&self, blob_object_id: &str) -> Option<Vec<u8>> {
+        let path = self.path(blob_object_id)?;
+        let mut bytes = fs::read(&path).ok()?;
+        if bytes.len() < DIGEST_LEN
+            || Sha256::digest(&bytes[DIGEST_LEN..])[..] != bytes[..DIGEST_LEN]
+        {
+            tracing::warn!(
+                "Cached blob {} is damaged; downloading it again",
+                blob_object_id
+            );
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        bytes.drain(..DIGEST_LEN);
+        // Eviction goes by modification time, so a read renews it
+        if let Ok(file) = fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        tracing::debug!("Blob cache hit for {}", blob_object_id);
+        Some(bytes)
+    }
+
+    /// Cache `bytes` as the blob `blob_object_id`, evicting older blobs over the size limit
+    ///
+    /// Blobs larger than the whole limit aren't cached.
+    pub fn insert(&self, blob_object_id: &str, bytes: &[u8]) -> Result<()> {
+        let Some(path) = self.path(blob_object_id) else {
+            return Ok(());
+        };
+        if bytes.len() as u64 > self.max_bytes {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+
+        // Written under a temporary name first, so a reader never sees part of a blob
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, [&Sha256::digest(bytes)[..], bytes].concat())
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        fs::rename(&temp_path, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        self.evict()
+    }
+
+    /// Drop the cached blob `blob_object_id`, if any
+    pub fn remove(&self, blob_object_id: &str) {
+        if let Some(path) = self.path(blob_object_id) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Remove the least recently used blobs until the cache fits its limit
+    fn evict(&self) -> Result<()> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to list {}", self.dir.display()))?
+        {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() && entry.path().extension().is_none() {
+                let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                let len = metadata.len().saturating_sub(DIGEST_LEN as u64);
+                entries.push((used, len, entry.path()));
+            }
+        }
+        entries.sort();
+
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        for (_, len, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to evict {}", path.display()))?;
+            tracing::debug!("Evicted {} from the blob cache", path.display());
+            total -= len;
+        }
+        Ok(())
+    }
+
+    /// File holding `blob_object_id`, or None when the cache is off or the ID isn't a
+    /// plain hex object ID
+    fn path(&self, blob_object_id: &str) -> Option<PathBuf> {
+        let hex = blob_object_id.strip_prefix("0x")?;
+        if self.max_bytes == 0 || hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(self.dir.join(blob_object_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_damaged_blob_is_dropped() {
+        let dir = tempdir().unwrap();
+        let cache = BlobCache::new(dir.path(), 1024);
+        cache
+            .insert("0xb10b", b"first objectsecond object")
+            .unwrap();
+        assert_eq!(
+            cache.get("0xb10b").unwrap(),
+            b"first objectsecond object".to_vec()
+        );
+
+        // Flip a byte of the cached blob: the next read misses, and the file is gone
+        let path = cache.path("0xb10b").unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        assert!(cache.get("0xb10b").is_none());
+        assert!(!path.exists());
+
+        // As does a file too short to hold its digest
+        fs::write(&path, b"short").unwrap();
+        assert!(cache.get("0xb10b").is_none());
+    }
+
+    #[test]
+    fn test_least_recently_used_blobs_are_evicted() {
+        let dir = tempdir().unwrap();
+        let cache = BlobCache::new(dir.path(), 10);
+        let mut used = SystemTime::now() - Duration::from_secs(60);
+        for id in ["0xa", "0xb"] {
+            cache.insert(id, b"12345").unwrap();
+            let file = fs::File::options()
+                .write(true)
+                .open(cache.path(id).unwrap())
+                .unwrap();
+            file.set_modified(used).unwrap();
+            used += Duration::from_secs(10);
+        }
+
+        // Reading 0xa makes 0xb the oldest, so it goes to make room for 0xc
+        assert!(cache.get("0xa").is_some());
+        cache.insert("0xc", b"12345").unwrap();
+        assert!(cache.get("0xa").is_some());
+        assert!(cache.get("0xb").is_none());
+        assert!(cache.get("0xc").is_some());
+        cache.remove("0xc");
+        assert!(cache.get("0xc").is_none());
+
+        // Blobs over the limit, IDs that aren't object IDs and a limit of 0 cache nothing
+        cache.insert("0xd", &[0; 11]).unwrap();
+        assert!(cache.get("0xd").is_none());
+        cache.insert("../escape", b"1").unwrap();
+        assert!(cache.get("../escape").is_none());
+        let off = BlobCache::new(dir.path(), 0);
+        off.insert("0xe", b"1").unwrap();
+        assert!(off.get("0xe").is_none());
+    }
+}
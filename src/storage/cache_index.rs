@@ -1,7 +1,74 @@
-use std::{collections::BTreeMap, fs, path::Path};
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::SystemTime,
+};
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Version of the byte layout hashed into cache entries.
+/// Bump whenever the bytes passed to `write_object` change shape.
+pub const CACHE_SCHEMA_VERSION: u32 = 1;
+
+/// Index entries and cached object files may differ by this many before the index is re-checked
+const INDEX_DIVERGENCE_THRESHOLD: usize = 64;
+
+/// File name of a remote's cache index
+pub const CACHE_INDEX_FILE: &str = "cache_index.yaml";
+
+/// Subdirectory of the cache dir holding one directory per remote, for its cache index and
+/// other files that only describe that remote
+pub const REMOTES_DIR: &str = "remotes";
+
+/// Stale recency entries tolerated, beyond one per cached object, before the queue is compacted
+const RECENCY_SLACK: usize = 64;
+
+/// Set once a stale cache entry has been reported, to avoid flooding the log
+static STALE_ENTRY_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// What kind of bytes a cached SHA-256 was computed over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContentKind {
+    /// Uncompressed loose object (`type size\0data`)
+    RawLoose,
+    /// Compressed loose object
+    #[allow(dead_code)]
+    Compressed,
+    /// Object stored as part of a packfile
+    #[allow(dead_code)]
+    PackMember,
+}
+
+/// Schema version and content kind recorded next to each cached SHA-256
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheEntryMeta {
+    pub schema: u32,
+    pub kind: ContentKind,
+}
+
+impl CacheEntryMeta {
+    /// Entries written before metadata existed were always raw loose objects at schema 1
+    const LEGACY: CacheEntryMeta = CacheEntryMeta {
+        schema: 1,
+        kind: ContentKind::RawLoose,
+    };
+
+    /// Metadata for an entry written by this version
+    fn current(kind: ContentKind) -> Self {
+        Self {
+            schema: CACHE_SCHEMA_VERSION,
+            kind,
+        }
+    }
+}
 
 /// Dual index for cache lookups
 /// Maps object_id <-> sha256 bidirectionally
@@ -15,6 +82,25 @@ pub struct CacheIndex {
     /// SHA-256 hash -> Sui object_id
     #[serde(default)]
     sha256_to_object: BTreeMap<String, String>,
+
+    /// SHA-256 hash -> schema version and content kind it was computed with
+    #[serde(default)]
+    entries: BTreeMap<String, CacheEntryMeta>,
+
+    /// Git SHA-1 -> SHA-256 hash of its raw loose object, so an object cached for one remote
+    /// is found when another remote stores it under a different object_id
+    #[serde(default)]
+    git_to_sha256: BTreeMap<String, String>,
+
+    /// SHA-256s this session inserted, oldest first, for LRU eviction; may hold removed or
+    /// repeated entries until the next compaction. Never saved (queues older indexes saved are
+    /// still read): the cached files' modification times order earlier sessions' entries
+    #[serde(default, skip_serializing)]
+    recency: VecDeque<String>,
+
+    /// Most cached objects to keep indexed (None: unlimited); set per session, never saved
+    #[serde(skip)]
+    max_entries: Option<usize>,
 }
 
 impl CacheIndex {
@@ -24,6 +110,20 @@ impl CacheIndex {
         Self::default()
     }
 
+    /// Create an empty index that keeps at most `max_entries` cached objects
+    #[cfg(test)]
+    pub fn with_capacity(max_entries: usize) -> Self {
+        Self {
+            max_entries: Some(max_entries),
+            ..Self::default()
+        }
+    }
+
+    /// Keep at most `max_entries` cached objects (None: unlimited)
+    pub fn set_capacity(&mut self, max_entries: Option<usize>) {
+        self.max_entries = max_entries;
+    }
+
     /// Load cache index from file
     pub fn load(path: &Path) -> Result<Self> {
         if !path.exists() {
@@ -55,21 +155,78 @@ impl CacheIndex {
         Ok(())
     }
 
-    /// Add a mapping between object_id and sha256
-    pub fn insert(&mut self, object_id: String, sha256: String) {
+    /// Add a mapping between object_id and sha256 of `kind` bytes
+    pub fn insert(&mut self, object_id: String, sha256: String, kind: ContentKind) {
         self.object_to_sha256
             .insert(object_id.clone(), sha256.clone());
-        self.sha256_to_object.insert(sha256, object_id);
+        self.entries
+            .insert(sha256.clone(), CacheEntryMeta::current(kind));
+        self.sha256_to_object.insert(sha256.clone(), object_id);
+        self.touch(&sha256);
+    }
+
+    /// Map another object_id to an already cached `sha256`, without changing the object_id
+    /// uploads of the same bytes reuse
+    pub fn insert_alias(&mut self, object_id: String, sha256: String) {
+        self.sha256_to_object
+            .entry(sha256.clone())
+            .or_insert_with(|| object_id.clone());
+        self.touch(&sha256);
+        self.object_to_sha256.insert(object_id, sha256);
+    }
+
+    /// Record that the raw loose object with `git_sha1` is cached as `sha256`
+    pub fn insert_git_sha1(&mut self, git_sha1: String, sha256: String) {
+        self.git_to_sha256.insert(git_sha1, sha256);
+    }
+
+    /// Get the SHA-256 of the cached raw loose object with `git_sha1`, under any object_id
+    pub fn get_sha256_by_git_sha1(&self, git_sha1: &str) -> Option<&String> {
+        let sha256 = self.git_to_sha256.get(git_sha1)?;
+        (self.sha256_to_object.contains_key(sha256)
+            && self.entry_matches(sha256, ContentKind::RawLoose))
+        .then_some(sha256)
     }
 
-    /// Get SHA-256 from object_id
-    pub fn get_sha256(&self, object_id: &str) -> Option<&String> {
-        self.object_to_sha256.get(object_id)
+    /// Get SHA-256 from object_id, if the entry matches the current schema and `kind`
+    pub fn get_sha256(&self, object_id: &str, kind: ContentKind) -> Option<&String> {
+        let sha256 = self.object_to_sha256.get(object_id)?;
+        self.entry_matches(sha256, kind).then_some(sha256)
     }
 
-    /// Get object_id from SHA-256
-    pub fn get_object_id(&self, sha256: &str) -> Option<&String> {
-        self.sha256_to_object.get(sha256)
+    /// Get object_id from SHA-256, if the entry matches the current schema and `kind`
+    pub fn get_object_id(&self, sha256: &str, kind: ContentKind) -> Option<&String> {
+        let object_id = self.sha256_to_object.get(sha256)?;
+        self.entry_matches(sha256, kind).then_some(object_id)
+    }
+
+    /// Metadata recorded for a SHA-256 (legacy entries have none and count as schema 1 raw loose)
+    pub fn entry_meta(&self, sha256: &str) -> CacheEntryMeta {
+        self.entries
+            .get(sha256)
+            .copied()
+            .unwrap_or(CacheEntryMeta::LEGACY)
+    }
+
+    /// Validate an entry; stale entries are treated as misses with a one-time warning
+    fn entry_matches(&self, sha256: &str, kind: ContentKind) -> bool {
+        let meta = self.entry_meta(sha256);
+        if meta == CacheEntryMeta::current(kind) {
+            return true;
+        }
+
+        if !STALE_ENTRY_WARNED.swap(true, Ordering::Relaxed) {
+            tracing::warn!(
+                "Ignoring cache entries written with a different layout \
+                 (e.g. {}...: schema {} {:?}, expected schema {} {:?}); affected objects will be re-fetched or re-uploaded",
+                &sha256[..std::cmp::min(sha256.len(), 8)],
+                meta.schema,
+                meta.kind,
+                CACHE_SCHEMA_VERSION,
+                kind
+            );
+        }
+        false
     }
 
     /// Check if object_id exists in index
@@ -79,30 +236,45 @@ impl CacheIndex {
     }
 
     /// Check if sha256 exists in index
-    #[allow(dead_code)]
     pub fn contains_sha256(&self, sha256: &str) -> bool {
         self.sha256_to_object.contains_key(sha256)
     }
 
-    /// Remove a mapping by object_id
-    #[allow(dead_code)]
+    /// Remove a mapping by object_id; the SHA-256 stays indexed while other object_ids share it
     pub fn remove_by_object_id(&mut self, object_id: &str) -> Option<String> {
-        if let Some(sha256) = self.object_to_sha256.remove(object_id) {
-            self.sha256_to_object.remove(&sha256);
-            Some(sha256)
-        } else {
-            None
+        let sha256 = self.object_to_sha256.remove(object_id)?;
+        if self.sha256_to_object.get(&sha256).map(String::as_str) == Some(object_id) {
+            let other = self
+                .object_to_sha256
+                .iter()
+                .find(|(_, shared)| **shared == sha256)
+                .map(|(other, _)| other.clone());
+            match other {
+                Some(other) => {
+                    self.sha256_to_object.insert(sha256.clone(), other);
+                }
+                None => self.forget_sha256s(&BTreeSet::from([sha256.clone()])),
+            }
         }
+        Some(sha256)
     }
 
-    /// Remove a mapping by SHA-256
-    #[allow(dead_code)]
+    /// Remove a SHA-256 and every object_id mapped to it, returning the one uploads reused
     pub fn remove_by_sha256(&mut self, sha256: &str) -> Option<String> {
-        if let Some(object_id) = self.sha256_to_object.remove(sha256) {
-            self.object_to_sha256.remove(&object_id);
-            Some(object_id)
-        } else {
-            None
+        let object_id = self.sha256_to_object.get(sha256).cloned()?;
+        self.forget_sha256s(&BTreeSet::from([sha256.to_string()]));
+        Some(object_id)
+    }
+
+    /// Remove `sha256s` and every object_id and git SHA-1 mapped to them, in one pass
+    fn forget_sha256s(&mut self, sha256s: &BTreeSet<String>) {
+        self.object_to_sha256
+            .retain(|_, sha256| !sha256s.contains(sha256));
+        self.git_to_sha256
+            .retain(|_, sha256| !sha256s.contains(sha256));
+        for sha256 in sha256s {
+            self.sha256_to_object.remove(sha256);
+            self.entries.remove(sha256);
         }
     }
 
@@ -124,11 +296,447 @@ impl CacheIndex {
         self.object_to_sha256.len()
     }
 
+    /// Number of distinct cached objects indexed (object_ids may share one)
+    fn cached_len(&self) -> usize {
+        self.sha256_to_object.len()
+    }
+
     /// Check if index is empty
     #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
         self.object_to_sha256.is_empty()
     }
+
+    /// Whether more cached objects are indexed than the capacity allows
+    pub fn over_capacity(&self) -> bool {
+        self.max_entries
+            .is_some_and(|max_entries| self.cached_len() > max_entries)
+    }
+
+    /// Once over capacity, forget the least recently inserted cached objects until the index
+    /// is at 80% of it, returning their SHA-256s so the caller can delete the object files
+    ///
+    /// Objects this session didn't insert are older than those it did, and ordered among
+    /// themselves by `cached_at` (objects it has no time for go first).
+    pub fn evict_lru(&mut self, cached_at: impl Fn(&str) -> Option<SystemTime>) -> Vec<String> {
+        let Some(max_entries) = self.max_entries else {
+            return Vec::new();
+        };
+        if self.cached_len() <= max_entries {
+            return Vec::new();
+        }
+
+        self.compact_recency();
+        let mut untracked: Vec<(Option<SystemTime>, String)> = {
+            let tracked: BTreeSet<&str> = self.recency.iter().map(String::as_str).collect();
+            self.sha256_to_object
+                .keys()
+                .filter(|sha256| !tracked.contains(sha256.as_str()))
+                .map(|sha256| (cached_at(sha256), sha256.clone()))
+                .collect()
+        };
+        untracked.sort();
+        let mut order: VecDeque<String> = untracked
+            .into_iter()
+            .map(|(_, sha256)| sha256)
+            .chain(self.recency.drain(..))
+            .collect();
+
+        let keep = max_entries * 4 / 5;
+        let count = self.cached_len().saturating_sub(keep);
+        let evicted: Vec<String> = order.drain(..count).collect();
+        self.recency = order;
+        self.forget_sha256s(&evicted.iter().cloned().collect());
+        evicted
+    }
+
+    /// Mark `sha256` as the most recently inserted
+    fn touch(&mut self, sha256: &str) {
+        self.recency.push_back(sha256.to_string());
+        if self.recency.len() > 2 * self.cached_len() + RECENCY_SLACK {
+            self.compact_recency();
+        }
+    }
+
+    /// Reduce the recency queue to each indexed SHA-256 once, at its latest position
+    fn compact_recency(&mut self) {
+        let mut seen = BTreeSet::new();
+        let mut latest: Vec<String> = Vec::new();
+        for sha256 in self.recency.iter().rev() {
+            if self.sha256_to_object.contains_key(sha256) && seen.insert(sha256.as_str()) {
+                latest.push(sha256.clone());
+            }
+        }
+        self.recency = latest.into_iter().rev().collect();
+    }
+}
+
+/// Repairs made by `CacheIndex::check_consistency`
+#[derive(Debug, Default)]
+pub struct IndexRepair {
+    /// Entries whose cached object file was missing
+    pub dropped: usize,
+    /// Entries taken from conflict copies
+    pub merged: usize,
+    /// Conflict copies absorbed into the index (and removed)
+    pub conflict_files: Vec<PathBuf>,
+}
+
+impl IndexRepair {
+    /// Whether the index needed no changes
+    pub fn is_empty(&self) -> bool {
+        self.dropped == 0 && self.merged == 0 && self.conflict_files.is_empty()
+    }
+}
+
+impl fmt::Display for IndexRepair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dropped {} entries with missing objects, merged {} entries from {} conflict \
+             copies",
+            self.dropped,
+            self.merged,
+            self.conflict_files.len()
+        )
+    }
+}
+
+impl CacheIndex {
+    /// Re-check the index at `index_path` against the cached objects in `objects_dir`
+    ///
+    /// Runs when conflict copies of the index exist (e.g. `cache_index.yaml.sync-conflict`
+    /// from a sync tool) or when the number of entries and of object files differ by more
+    /// than a threshold, as after restoring a cache from another machine. Conflict copies are
+    /// merged in, then entries whose object file is missing are dropped so their objects are
+    /// uploaded again instead of being skipped. Returns None when no check was needed.
+    pub fn check_consistency(index_path: &Path, objects_dir: &Path) -> Result<Option<IndexRepair>> {
+        let conflict_files = find_conflict_copies(index_path)?;
+        let object_files = count_files(objects_dir)?;
+        let index = Self::load(index_path)?;
+
+        if conflict_files.is_empty()
+            && index.cached_len().abs_diff(object_files) <= INDEX_DIVERGENCE_THRESHOLD
+        {
+            return Ok(None);
+        }
+        tracing::debug!(
+            "Checking cache index: {} entries, {} object files, {} conflict copies",
+            index.cached_len(),
+            object_files,
+            conflict_files.len()
+        );
+
+        Self::repair(index_path, objects_dir, false).map(Some)
+    }
+
+    /// Merge conflict copies into the index at `index_path` and drop entries whose object file
+    /// is missing from `objects_dir`
+    ///
+    /// With `dry_run`, only reports what would change.
+    pub fn repair(index_path: &Path, objects_dir: &Path, dry_run: bool) -> Result<IndexRepair> {
+        let mut index = Self::load(index_path)?;
+        let mut repair = IndexRepair::default();
+        for path in find_conflict_copies(index_path)? {
+            match Self::load(&path) {
+                Ok(copy) => {
+                    repair.merged += index.merge(&copy);
+                    repair.conflict_files.push(path);
+                }
+                Err(e) => tracing::warn!("Skipping unreadable cache index copy: {:#}", e),
+            }
+        }
+
+        let missing: BTreeSet<String> = index
+            .all_sha256s()
+            .filter(|sha256| !objects_dir.join(sha256).is_file())
+            .cloned()
+            .collect();
+        index.forget_sha256s(&missing);
+        repair.dropped = missing.len();
+
+        if dry_run || repair.is_empty() {
+            return Ok(repair);
+        }
+        index.save(index_path)?;
+        for path in &repair.conflict_files {
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove merged cache index copy {:?}", path))?;
+        }
+
+        Ok(repair)
+    }
+
+    /// Add the entries of `other` whose object ID and SHA-256 are both unknown here
+    fn merge(&mut self, other: &CacheIndex) -> usize {
+        let mut merged = 0;
+        for (object_id, sha256) in &other.object_to_sha256 {
+            if self.contains_object(object_id) || self.contains_sha256(sha256) {
+                continue;
+            }
+            self.object_to_sha256
+                .insert(object_id.clone(), sha256.clone());
+            self.sha256_to_object
+                .insert(sha256.clone(), object_id.clone());
+            self.entries
+                .insert(sha256.clone(), other.entry_meta(sha256));
+            self.touch(sha256);
+            merged += 1;
+        }
+        for (git_sha1, sha256) in &other.git_to_sha256 {
+            if self.contains_sha256(sha256) && !self.git_to_sha256.contains_key(git_sha1) {
+                self.git_to_sha256.insert(git_sha1.clone(), sha256.clone());
+            }
+        }
+        merged
+    }
+}
+
+/// Cached object files, checked against the SHA-256 they are named after
+#[derive(Debug, Default)]
+pub struct CachedObjects {
+    /// SHA-256s of files whose content matches their name
+    pub valid: BTreeSet<String>,
+    /// Files whose content doesn't match their name (truncated or overwritten)
+    pub corrupt: Vec<PathBuf>,
+}
+
+impl CachedObjects {
+    /// Hash every file in `objects_dir` (empty if it doesn't exist)
+    pub fn scan(objects_dir: &Path) -> Result<Self> {
+        let mut objects = Self::default();
+        if !objects_dir.is_dir() {
+            return Ok(objects);
+        }
+        for entry in fs::read_dir(objects_dir)
+            .with_context(|| format!("Failed to read {:?}", objects_dir))?
+        {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let content = fs::read(&path)
+                .with_context(|| format!("Failed to read cached object {:?}", path))?;
+            let sha256 = hex::encode(Sha256::digest(&content));
+            if entry.file_name().to_str() == Some(sha256.as_str()) {
+                objects.valid.insert(sha256);
+            } else {
+                objects.corrupt.push(path);
+            }
+        }
+        objects.corrupt.sort();
+        Ok(objects)
+    }
+}
+
+/// Result of `CacheIndex::rebuild`
+#[derive(Debug, Default)]
+pub struct IndexRebuild {
+    /// Entries salvaged from the damaged index whose object file checked out
+    pub recovered: usize,
+    /// Cached objects with no known object ID, mapped again when next read from Walrus
+    pub unmapped: usize,
+}
+
+impl fmt::Display for IndexRebuild {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "recovered {} entries, {} cached objects left to be re-mapped when next read",
+            self.recovered, self.unmapped
+        )
+    }
+}
+
+/// Result of `CacheIndex::migrate`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LayoutMigration {
+    /// Entries from before metadata existed, now recorded explicitly
+    pub upgraded: usize,
+    /// Entries written with another schema or content kind, which are never hits
+    pub dropped: usize,
+}
+
+impl LayoutMigration {
+    pub fn is_empty(&self) -> bool {
+        self.upgraded == 0 && self.dropped == 0
+    }
+}
+
+impl CacheIndex {
+    /// Replace an unreadable index at `index_path` with one rebuilt from `objects`
+    ///
+    /// Object IDs can't be derived from a cached file, so `object ID: SHA-256` pairs still
+    /// legible line by line in the damaged file are kept when their object is cached and
+    /// intact. Other cached objects stay on disk and are mapped again the next time they
+    /// are read from Walrus. The damaged file is kept as `cache_index.yaml.corrupt`.
+    pub fn rebuild(index_path: &Path, objects: &CachedObjects) -> Result<IndexRebuild> {
+        let damaged = fs::read(index_path).unwrap_or_default();
+        let mut index = Self::default();
+        for line in String::from_utf8_lossy(&damaged).lines() {
+            let Ok(pair) = serde_yaml::from_str::<BTreeMap<String, String>>(line.trim()) else {
+                continue;
+            };
+            for (key, value) in pair {
+                // Both directions of the index appear in the file
+                let (object_id, sha256) = if objects.valid.contains(&value) {
+                    (key, value)
+                } else if objects.valid.contains(&key) {
+                    (value, key)
+                } else {
+                    continue;
+                };
+                if !index.contains_object(&object_id) && !index.contains_sha256(&sha256) {
+                    index.insert(object_id, sha256, ContentKind::RawLoose);
+                }
+            }
+        }
+
+        if index_path.exists() {
+            let backup = index_path.with_extension("yaml.corrupt");
+            fs::rename(index_path, &backup)
+                .with_context(|| format!("Failed to move damaged cache index to {:?}", backup))?;
+        }
+        index.save(index_path)?;
+
+        Ok(IndexRebuild {
+            recovered: index.len(),
+            unmapped: objects.valid.len() - index.len(),
+        })
+    }
+
+    /// Bring entries up to the current layout: legacy entries get explicit metadata, and
+    /// entries from another schema are dropped rather than skipped on every lookup
+    pub fn migrate(&mut self) -> LayoutMigration {
+        let current = CacheEntryMeta::current(ContentKind::RawLoose);
+        let mut migration = LayoutMigration::default();
+        let sha256s: Vec<String> = self.all_sha256s().cloned().collect();
+        for sha256 in sha256s {
+            match self.entries.get(&sha256).copied() {
+                Some(meta) if meta == current => {}
+                None if CacheEntryMeta::LEGACY == current => {
+                    self.entries.insert(sha256, current);
+                    migration.upgraded += 1;
+                }
+                _ => {
+                    self.remove_by_sha256(&sha256);
+                    migration.dropped += 1;
+                }
+            }
+        }
+        migration
+    }
+}
+
+/// Conflict copies of the index left next to it by sync tools or backup restores
+///
+/// Matches names built from the index's file name or stem that mention a conflict, e.g.
+/// `cache_index.yaml.sync-conflict`, `cache_index.sync-conflict-20240101-120000-ABC.yaml`
+/// or `cache_index (conflicted copy).yaml`.
+fn find_conflict_copies(index_path: &Path) -> Result<Vec<PathBuf>> {
+    let (Some(dir), Some(stem), Some(name)) = (
+        index_path.parent(),
+        index_path.file_stem().and_then(|s| s.to_str()),
+        index_path.file_name().and_then(|s| s.to_str()),
+    ) else {
+        return Ok(Vec::new());
+    };
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut copies = Vec::new();
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to read cache directory {:?}", dir))?
+    {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if file_name != name
+            && file_name.starts_with(stem)
+            && file_name.to_ascii_lowercase().contains("conflict")
+            && path.is_file()
+        {
+            copies.push(path);
+        }
+    }
+    copies.sort();
+    Ok(copies)
+}
+
+/// Number of files in `dir` (zero if it doesn't exist)
+fn count_files(dir: &Path) -> Result<usize> {
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    let mut count = 0;
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        if entry?.file_type()?.is_file() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Other remotes' cache indexes in the same cache dir, read on first use
+///
+/// Every remote caches objects in the shared objects dir but indexes them under its own
+/// ContentIds; these find an object another remote cached, and keep its file from being
+/// deleted when this remote evicts it.
+#[derive(Debug, Default)]
+pub struct SiblingIndexes {
+    paths: Vec<PathBuf>,
+    loaded: OnceCell<Vec<CacheIndex>>,
+}
+
+impl SiblingIndexes {
+    /// The indexes named `file_name` in the subdirectories of `remotes_dir` other than `own`
+    pub fn in_dir(remotes_dir: &Path, own: &Path, file_name: &str) -> Self {
+        let paths = fs::read_dir(remotes_dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|dir| dir != own)
+            .map(|dir| dir.join(file_name))
+            .filter(|path| path.is_file())
+            .collect();
+        Self {
+            paths,
+            loaded: OnceCell::new(),
+        }
+    }
+
+    fn indexes(&self) -> &[CacheIndex] {
+        self.loaded.get_or_init(|| {
+            self.paths
+                .iter()
+                .filter_map(|path| match CacheIndex::load(path) {
+                    Ok(index) => Some(index),
+                    Err(e) => {
+                        tracing::debug!("Skipping unreadable cache index: {:#}", e);
+                        None
+                    }
+                })
+                .collect()
+        })
+    }
+
+    /// Get the SHA-256 another remote cached the raw loose object with `git_sha1` as
+    pub fn get_sha256_by_git_sha1(&self, git_sha1: &str) -> Option<&String> {
+        self.indexes()
+            .iter()
+            .find_map(|index| index.get_sha256_by_git_sha1(git_sha1))
+    }
+
+    /// Whether another remote indexes `sha256`
+    pub fn contains_sha256(&self, sha256: &str) -> bool {
+        self.indexes()
+            .iter()
+            .any(|index| index.contains_sha256(sha256))
+    }
 }
 
 #[cfg(test)]
@@ -137,15 +745,20 @@ mod tests {
 
     use super::*;
 
+    const RAW: ContentKind = ContentKind::RawLoose;
+
     #[test]
     fn test_insert_and_lookup() {
         let mut index = CacheIndex::new();
 
-        index.insert("0x1".to_string(), "sha256_1".to_string());
-        index.insert("0x2".to_string(), "sha256_2".to_string());
+        index.insert("0x1".to_string(), "sha256_1".to_string(), RAW);
+        index.insert("0x2".to_string(), "sha256_2".to_string(), RAW);
 
-        assert_eq!(index.get_sha256("0x1"), Some(&"sha256_1".to_string()));
-        assert_eq!(index.get_object_id("sha256_2"), Some(&"0x2".to_string()));
+        assert_eq!(index.get_sha256("0x1", RAW), Some(&"sha256_1".to_string()));
+        assert_eq!(
+            index.get_object_id("sha256_2", RAW),
+            Some(&"0x2".to_string())
+        );
         assert_eq!(index.len(), 2);
     }
 
@@ -153,20 +766,23 @@ mod tests {
     fn test_bidirectional_lookup() {
         let mut index = CacheIndex::new();
 
-        index.insert("0xabc".to_string(), "sha_xyz".to_string());
+        index.insert("0xabc".to_string(), "sha_xyz".to_string(), RAW);
 
         assert!(index.contains_object("0xabc"));
         assert!(index.contains_sha256("sha_xyz"));
-        assert_eq!(index.get_sha256("0xabc"), Some(&"sha_xyz".to_string()));
-        assert_eq!(index.get_object_id("sha_xyz"), Some(&"0xabc".to_string()));
+        assert_eq!(index.get_sha256("0xabc", RAW), Some(&"sha_xyz".to_string()));
+        assert_eq!(
+            index.get_object_id("sha_xyz", RAW),
+            Some(&"0xabc".to_string())
+        );
     }
 
     #[test]
     fn test_remove() {
         let mut index = CacheIndex::new();
 
-        index.insert("0x1".to_string(), "sha1".to_string());
-        index.insert("0x2".to_string(), "sha2".to_string());
+        index.insert("0x1".to_string(), "sha1".to_string(), RAW);
+        index.insert("0x2".to_string(), "sha2".to_string(), RAW);
 
         assert_eq!(index.remove_by_object_id("0x1"), Some("sha1".to_string()));
         assert!(!index.contains_object("0x1"));
@@ -177,20 +793,286 @@ mod tests {
         assert!(index.is_empty());
     }
 
+    #[test]
+    fn test_object_ids_sharing_a_sha256() {
+        let mut index = CacheIndex::new();
+        index.insert("0xa:0:5".to_string(), "shared".to_string(), RAW);
+        index.insert_git_sha1("git".to_string(), "shared".to_string());
+        index.insert_alias("0xb:3:5".to_string(), "shared".to_string());
+
+        // The alias doesn't take over the object_id uploads reuse
+        assert_eq!(
+            index.get_object_id("shared", RAW),
+            Some(&"0xa:0:5".to_string())
+        );
+        assert_eq!(
+            index.get_sha256("0xb:3:5", RAW),
+            Some(&"shared".to_string())
+        );
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.cached_len(), 1);
+
+        index.remove_by_object_id("0xa:0:5");
+        assert_eq!(
+            index.get_object_id("shared", RAW),
+            Some(&"0xb:3:5".to_string())
+        );
+        assert_eq!(
+            index.get_sha256_by_git_sha1("git"),
+            Some(&"shared".to_string())
+        );
+
+        index.remove_by_object_id("0xb:3:5");
+        assert!(!index.contains_sha256("shared"));
+        assert!(index.get_sha256_by_git_sha1("git").is_none());
+        assert!(index.is_empty());
+    }
+
+    #[test]
+    fn test_evict_lru_drops_the_oldest_entries() {
+        let mut index = CacheIndex::with_capacity(10);
+        for i in 0..12 {
+            index.insert(format!("0x{}", i), format!("sha{}", i), RAW);
+            index.insert_git_sha1(format!("git{}", i), format!("sha{}", i));
+        }
+        // Inserting sha2 again makes it recent; an alias of sha3 shares its entry
+        index.insert("0x2".to_string(), "sha2".to_string(), RAW);
+        index.insert_alias("0x3:7:5".to_string(), "sha3".to_string());
+        assert!(index.over_capacity());
+
+        // Down to 80% of capacity, oldest first
+        assert_eq!(index.evict_lru(|_| None), ["sha0", "sha1", "sha4", "sha5"]);
+        assert!(!index.over_capacity());
+        assert_eq!(index.cached_len(), 8);
+        assert_eq!(index.len(), 9);
+        assert!(index.evict_lru(|_| None).is_empty());
+
+        // Both directions agree on what is left
+        for i in 0..12 {
+            let (object_id, sha256) = (format!("0x{}", i), format!("sha{}", i));
+            let kept = ![0, 1, 4, 5].contains(&i);
+            assert_eq!(index.get_sha256(&object_id, RAW).is_some(), kept);
+            assert_eq!(index.get_object_id(&sha256, RAW).is_some(), kept);
+            assert_eq!(
+                index.get_sha256_by_git_sha1(&format!("git{}", i)).is_some(),
+                kept
+            );
+        }
+        assert_eq!(index.get_sha256("0x3:7:5", RAW), Some(&"sha3".to_string()));
+        for (object_id, sha256) in &index.object_to_sha256 {
+            assert!(index.sha256_to_object.contains_key(sha256), "{}", object_id);
+        }
+
+        // The queue isn't saved: a later session orders the entries by when they were
+        // cached, oldest first, before any it inserts itself
+        let dir = tempdir().unwrap();
+        let index_path = dir.path().join("cache_index.yaml");
+        index.save(&index_path).unwrap();
+        assert!(!fs::read_to_string(&index_path).unwrap().contains("recency"));
+        let mut loaded = CacheIndex::load(&index_path).unwrap();
+        loaded.set_capacity(Some(5));
+        loaded.insert("0xnew".to_string(), "sha_new".to_string(), RAW);
+        let cached_at = |sha256: &str| {
+            let i: u64 = sha256.strip_prefix("sha")?.parse().ok()?;
+            Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(100 - i))
+        };
+        assert_eq!(
+            loaded.evict_lru(cached_at),
+            ["sha11", "sha10", "sha9", "sha8", "sha7"]
+        );
+        assert!(loaded.get_sha256("0xnew", RAW).is_some());
+
+        // Queues saved by older versions still load
+        let legacy = dir.path().join("legacy.yaml");
+        fs::write(
+            &legacy,
+            "object_to_sha256:\n  '0x1': sha1\nsha256_to_object:\n  sha1: '0x1'\n\
+             recency:\n- sha1\n- sha1\n",
+        )
+        .unwrap();
+        assert_eq!(CacheIndex::load(&legacy).unwrap().len(), 1);
+    }
+
     #[test]
     fn test_save_and_load() {
         let dir = tempdir().unwrap();
         let index_path = dir.path().join("cache_index.yaml");
 
         let mut index = CacheIndex::new();
-        index.insert("0x1".to_string(), "sha1".to_string());
-        index.insert("0x2".to_string(), "sha2".to_string());
+        index.insert("0x1".to_string(), "sha1".to_string(), RAW);
+        index.insert("0x2".to_string(), "sha2".to_string(), RAW);
 
         index.save(&index_path).unwrap();
 
         let loaded = CacheIndex::load(&index_path).unwrap();
         assert_eq!(loaded.len(), 2);
-        assert_eq!(loaded.get_sha256("0x1"), Some(&"sha1".to_string()));
-        assert_eq!(loaded.get_object_id("sha2"), Some(&"0x2".to_string()));
+        assert_eq!(loaded.get_sha256("0x1", RAW), Some(&"sha1".to_string()));
+        assert_eq!(loaded.get_object_id("sha2", RAW), Some(&"0x2".to_string()));
+    }
+
+    #[test]
+    fn test_legacy_index_without_metadata_is_raw_loose() {
+        let dir = tempdir().unwrap();
+        let index_path = dir.path().join("cache_index.yaml");
+        std::fs::write(
+            &index_path,
+            "object_to_sha256:\n  '0x1': sha1\nsha256_to_object:\n  sha1: '0x1'\n",
+        )
+        .unwrap();
+
+        let loaded = CacheIndex::load(&index_path).unwrap();
+        assert_eq!(loaded.get_object_id("sha1", RAW), Some(&"0x1".to_string()));
+        assert_eq!(loaded.get_sha256("0x1", RAW), Some(&"sha1".to_string()));
+    }
+
+    #[test]
+    fn test_stale_schema_is_a_miss() {
+        let dir = tempdir().unwrap();
+        let index_path = dir.path().join("cache_index.yaml");
+        std::fs::write(
+            &index_path,
+            "object_to_sha256:\n  '0x1': sha1\nsha256_to_object:\n  sha1: '0x1'\n\
+             entries:\n  sha1:\n    schema: 0\n    kind: raw-loose\n",
+        )
+        .unwrap();
+
+        let loaded = CacheIndex::load(&index_path).unwrap();
+        assert_eq!(loaded.get_object_id("sha1", RAW), None);
+        assert_eq!(loaded.get_sha256("0x1", RAW), None);
+    }
+
+    #[test]
+    fn test_kind_mismatch_is_a_miss() {
+        let mut index = CacheIndex::new();
+        index.insert(
+            "0x1".to_string(),
+            "sha1".to_string(),
+            ContentKind::Compressed,
+        );
+
+        assert_eq!(index.get_object_id("sha1", RAW), None);
+        assert_eq!(
+            index.get_object_id("sha1", ContentKind::Compressed),
+            Some(&"0x1".to_string())
+        );
+
+        // Re-inserting with the current kind repairs the entry
+        index.insert("0x1".to_string(), "sha1".to_string(), RAW);
+        assert_eq!(index.get_object_id("sha1", RAW), Some(&"0x1".to_string()));
+    }
+
+    #[test]
+    fn test_migrate_to_current_layout() {
+        let mut index: CacheIndex = serde_yaml::from_str(
+            "object_to_sha256:\n  '0x1': sha1\n  '0x2': sha2\n  '0x3': sha3\n\
+             sha256_to_object:\n  sha1: '0x1'\n  sha2: '0x2'\n  sha3: '0x3'\n\
+             entries:\n  sha2:\n    schema: 0\n    kind: raw-loose\n  \
+             sha3:\n    schema: 1\n    kind: raw-loose\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            index.migrate(),
+            LayoutMigration {
+                upgraded: 1,
+                dropped: 1
+            }
+        );
+        assert_eq!(index.get_object_id("sha1", RAW), Some(&"0x1".to_string()));
+        assert!(!index.contains_object("0x2"));
+        assert!(index.migrate().is_empty());
+    }
+
+    /// Cache dir with `objects` files and an index mapping `0x<n>` to each of `entries`
+    fn cache_with(objects: &[&str], entries: &[&str]) -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let dir = tempdir().unwrap();
+        let objects_dir = dir.path().join("objects");
+        fs::create_dir(&objects_dir).unwrap();
+        for sha256 in objects {
+            fs::write(objects_dir.join(sha256), sha256).unwrap();
+        }
+
+        let mut index = CacheIndex::new();
+        for (i, sha256) in entries.iter().enumerate() {
+            index.insert(format!("0x{}", i), sha256.to_string(), RAW);
+        }
+        let index_path = dir.path().join("cache_index.yaml");
+        index.save(&index_path).unwrap();
+        (dir, index_path, objects_dir)
+    }
+
+    #[test]
+    fn test_consistent_index_is_left_alone() {
+        let (_dir, index_path, objects_dir) = cache_with(&["a", "b"], &["a"]);
+
+        assert!(CacheIndex::check_consistency(&index_path, &objects_dir)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_divergent_index_drops_missing_objects() {
+        // Restored index from a machine that cached many more objects
+        let shas: Vec<String> = (0..100).map(|i| format!("sha{:03}", i)).collect();
+        let entries: Vec<&str> = shas.iter().map(String::as_str).collect();
+        let (_dir, index_path, objects_dir) = cache_with(&entries[..10], &entries);
+
+        let repair = CacheIndex::check_consistency(&index_path, &objects_dir)
+            .unwrap()
+            .unwrap();
+        assert_eq!(repair.dropped, 90);
+        assert_eq!(repair.merged, 0);
+
+        let index = CacheIndex::load(&index_path).unwrap();
+        assert_eq!(index.len(), 10);
+        assert!(index.contains_sha256("sha009"));
+        assert!(!index.contains_sha256("sha010"));
+
+        // Consistent now
+        assert!(CacheIndex::check_consistency(&index_path, &objects_dir)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_conflict_copies_are_merged() {
+        let (dir, index_path, objects_dir) = cache_with(&["a", "b", "c"], &["a"]);
+
+        // Two conflict copies: one knows b (cached here) and d (not cached here), the other
+        // maps a to a different object ID
+        let mut copy = CacheIndex::new();
+        copy.insert("0xb".to_string(), "b".to_string(), RAW);
+        copy.insert("0xd".to_string(), "d".to_string(), RAW);
+        let syncthing = dir
+            .path()
+            .join("cache_index.sync-conflict-20240101-120000-ABCDEFG.yaml");
+        copy.save(&syncthing).unwrap();
+
+        let mut copy = CacheIndex::new();
+        copy.insert("0xother".to_string(), "a".to_string(), RAW);
+        copy.insert("0xc".to_string(), "c".to_string(), RAW);
+        let suffixed = dir.path().join("cache_index.yaml.sync-conflict");
+        copy.save(&suffixed).unwrap();
+
+        // Not a conflict copy
+        fs::write(dir.path().join("cache_index.yaml.bak"), "junk").unwrap();
+
+        let repair = CacheIndex::check_consistency(&index_path, &objects_dir)
+            .unwrap()
+            .unwrap();
+        assert_eq!(repair.merged, 3);
+        assert_eq!(repair.dropped, 1);
+        assert_eq!(
+            repair.conflict_files,
+            vec![syncthing.clone(), suffixed.clone()]
+        );
+        assert!(!syncthing.exists() && !suffixed.exists());
+        assert!(dir.path().join("cache_index.yaml.bak").exists());
+
+        let index = CacheIndex::load(&index_path).unwrap();
+        assert_eq!(index.get_object_id("a", RAW), Some(&"0x0".to_string()));
+        assert_eq!(index.get_object_id("b", RAW), Some(&"0xb".to_string()));
+        assert_eq!(index.get_object_id("c", RAW), Some(&"0xc".to_string()));
+        assert!(!index.contains_sha256("d"));
     }
 }
@@ -0,0 +1,239 @@
+//! Canonical serialization of an objects map
+//!
+//! Maps are written here rather than by serde_yaml so the same map is always the same bytes,
+//! whichever serde_yaml version built the writer: a version line, then one `key: value` line
+//! per entry in key order. A scalar is plain unless YAML could read it as anything but a
+//! string, and double-quoted otherwise. Two maps then diff line by line, and a push that
+//! leaves the map unchanged can point at the blob already stored.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use super::ContentId;
+
+/// First line of every canonical map; a YAML comment, so older readers skip it
+pub const CANONICAL_HEADER: &str = "# git-remote-walrus objects map v1\n";
+
+/// Plain scalars YAML 1.1 or 1.2 would read as booleans or null
+const RESERVED: &[&str] = &["true", "false", "yes", "no", "on", "off", "y", "n", "null"];
+
+/// Serialize `map` in canonical form
+pub fn to_canonical(map: &BTreeMap<String, ContentId>) -> String {
+    let mut out = String::with_capacity(
+        CANONICAL_HEADER.len()
+            + map
+                .iter()
+                .map(|(key, value)| key.len() + value.len() + 3)
+                .sum::<usize>(),
+    );
+    out.push_str(CANONICAL_HEADER);
+    for (key, value) in map {
+        push_scalar(&mut out, key);
+        out.push_str(": ");
+        push_scalar(&mut out, value);
+        out.push('\n');
+    }
+    out
+}
+
+/// True if `yaml` starts with the canonical header (a map written before it was introduced
+/// is still read, but never byte-compared)
+pub fn is_canonical(yaml: &[u8]) -> bool {
+    yaml.starts_with(CANONICAL_HEADER.as_bytes())
+}
+
+fn push_scalar(out: &mut String, s: &str) {
+    if !needs_quotes(s) {
+        out.push_str(s);
+        return;
+    }
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            // YAML treats these as line breaks or byte-order marks even inside quotes
+            c if c.is_control() || matches!(c, '\u{2028}' | '\u{2029}' | '\u{feff}') => {
+                if (c as u32) <= 0xff {
+                    let _ = write!(out, "\\x{:02X}", c as u32);
+                } else {
+                    let _ = write!(out, "\\u{:04X}", c as u32);
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// True unless `s` reads back as the same string when written plain
+fn needs_quotes(s: &str) -> bool {
+    let Some(first) = s.bytes().next() else {
+        return true;
+    };
+    if !first.is_ascii_alphanumeric()
+        || s.ends_with(':')
+        || !s
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"_.:/-=;".contains(&b))
+    {
+        return true;
+    }
+
+    let lower = s.to_ascii_lowercase();
+    if RESERVED.contains(&lower.as_str()) {
+        return true;
+    }
+
+    // Integers, floats, sexagesimals and timestamps in either YAML version
+    let digits_only = s
+        .bytes()
+        .all(|b| b.is_ascii_digit() || b"_:.-".contains(&b));
+    let float = s.replace('_', "").parse::<f64>().is_ok();
+    let prefixed = ["0x", "0o", "0b"].iter().any(|prefix| {
+        lower.strip_prefix(prefix).is_some_and(|rest| {
+            !rest.is_empty() && rest.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+        })
+    });
+    digits_only || float || prefixed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use sha1::{Digest, Sha1};
+
+    use super::*;
+    use crate::storage::ParsedContentId;
+
+    /// Loose-format objects of a one-file commit, with their SHA-1s
+    fn commit_objects() -> Vec<(String, Vec<u8>)> {
+        let loose = |kind: &str, data: &[u8]| {
+            let mut object = format!("{} {}\0", kind, data.len()).into_bytes();
+            object.extend_from_slice(data);
+            (hex::encode(Sha1::digest(&object)), object)
+        };
+
+        let blob = loose("blob", b"hello\n");
+        let mut tree = b"100644 hello.txt\0".to_vec();
+        tree.extend_from_slice(&hex::decode(&blob.0).unwrap());
+        let tree = loose("tree", &tree);
+        let commit = loose(
+            "commit",
+            format!(
+                "tree {}\n\
+                 author A U Thor <author@example.com> 1700000000 +0000\n\
+                 committer A U Thor <author@example.com> 1700000000 +0000\n\
+                 \n\
+                 Initial commit\n",
+                tree.0
+            )
+            .as_bytes(),
+        );
+        vec![blob, tree, commit]
+    }
+
+    /// The map a push of [`commit_objects`] writes: the objects batched into one blob stored
+    /// through the mock Walrus CLI, the constants and a metadata blob (symrefs live on-chain)
+    fn golden_map() -> BTreeMap<String, ContentId> {
+        let dir = tempfile::tempdir().unwrap();
+        let client = crate::walrus::mock_client(dir.path());
+        let objects = commit_objects();
+
+        let batch: Vec<u8> = objects
+            .iter()
+            .flat_map(|(_, object)| object.clone())
+            .collect();
+        let batch = client.store(&batch).unwrap().shared_object_id;
+        let metadata = client
+            .store(b"default_branch: refs/heads/main\n")
+            .unwrap()
+            .shared_object_id;
+
+        let mut map = BTreeMap::new();
+        let mut offset = 0;
+        for (sha, object) in &objects {
+            let id = ParsedContentId::batched(batch.clone(), offset, object.len() as u64);
+            map.insert(sha.clone(), id.encode());
+            offset += object.len() as u64;
+        }
+        for object in [&b"blob 0\0"[..], b"tree 0\0"] {
+            let id = ParsedContentId::constant(object).unwrap();
+            map.insert(hex::encode(Sha1::digest(object)), id.encode());
+        }
+        map.insert("metadata".to_string(), metadata);
+        map
+    }
+
+    /// Run with `UPDATE_GOLDEN=1` to rewrite the golden file after a deliberate format change
+    #[test]
+    fn test_canonical_matches_golden_file() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/objects-map-v1.yaml");
+        let map = golden_map();
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, to_canonical(&map)).unwrap();
+        }
+
+        let golden = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(to_canonical(&map), golden);
+        assert!(is_canonical(golden.as_bytes()));
+
+        let parsed: BTreeMap<String, ContentId> = serde_yaml::from_str(&golden).unwrap();
+        assert_eq!(parsed, map);
+        let generic = serde_yaml::to_string(&map).unwrap();
+        assert!(!is_canonical(generic.as_bytes()));
+    }
+
+    #[test]
+    fn test_ambiguous_scalars_round_trip() {
+        let tricky = [
+            "",
+            "true",
+            "No",
+            "null",
+            "~",
+            "-",
+            "12",
+            "1.5",
+            "1e5",
+            "1_000",
+            "12:30",
+            "0x1f",
+            "0o17",
+            "inf",
+            ".nan",
+            "2001-12-14",
+            "a: b",
+            "a:",
+            "# x",
+            "it's",
+            "say \"hi\"",
+            "back\\slash",
+            "line\nbreak",
+            "tab\there",
+            "bell\u{7}",
+            "sep\u{2028}",
+            "héllo",
+            "- item",
+            "refs/heads/main",
+            "features=f;writer=0.1.0;requires=0.1.0",
+            // SHA-1s YAML would read as numbers
+            "1234567890123456789012345678901234567890",
+            "3e10000000000000000000000000000000000000",
+        ];
+        let original: BTreeMap<String, ContentId> = tricky
+            .iter()
+            .enumerate()
+            .map(|(n, s)| (s.to_string(), format!("{}{}", s, n)))
+            .collect();
+        let yaml = to_canonical(&original);
+        let parsed: BTreeMap<String, ContentId> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed, original);
+
+        // Only the header and the entry separators span lines
+        assert_eq!(yaml.lines().count(), original.len() + 1);
+        assert!(yaml.ends_with('\n'));
+    }
+}
@@ -1,10 +1,23 @@
 use anyhow::{Context, Result};
 
+/// Objects that are never uploaded, by the name in their `const:{name}` ContentId
+///
+/// The empty blob and tree turn up in most repositories, and zero-length content can be
+/// neither a batch slice nor a Walrus blob of its own.
+const CONSTANT_OBJECTS: &[(&str, &[u8])] = &[
+    // e69de29bb2d1d6434b8b29ae775ad8c2e48c5391
+    ("empty-blob", b"blob 0\0"),
+    // 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+    ("empty-tree", b"tree 0\0"),
+    ("empty", b""),
+];
+
 /// Parsed representation of a ContentId
 ///
-/// ContentId can be in two formats:
+/// ContentId can be in three formats:
 /// - Legacy: `{blob_object_id}` - simple object ID
 /// - Batched: `{blob_object_id}:{offset}:{length}` - object within a batched blob
+/// - Constant: `const:{name}` - well-known content served without touching Walrus
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParsedContentId {
     /// Legacy format: simple blob object ID
@@ -15,6 +28,11 @@ pub enum ParsedContentId {
         offset: u64,
         length: u64,
     },
+    /// Constant format: one of the built-in objects
+    Constant {
+        name: &'static str,
+        content: &'static [u8],
+    },
 }
 
 impl ParsedContentId {
@@ -23,6 +41,11 @@ impl ParsedContentId {
         let parts: Vec<&str> = content_id.split(':').collect();
 
         match parts.len() {
+            2 if parts[0] == "const" => CONSTANT_OBJECTS
+                .iter()
+                .find(|(name, _)| *name == parts[1])
+                .map(|&(name, content)| ParsedContentId::Constant { name, content })
+                .with_context(|| format!("Unknown constant ContentId: {}", content_id)),
             1 => {
                 // Legacy format: just the blob object ID
                 Ok(ParsedContentId::Legacy {
@@ -65,11 +88,28 @@ impl ParsedContentId {
         ParsedContentId::Legacy { blob_object_id }
     }
 
-    /// Get the blob object ID
-    pub fn blob_object_id(&self) -> &str {
+    /// The constant ContentId for `content`, if it is one of the built-in objects
+    pub fn constant(content: &[u8]) -> Option<Self> {
+        CONSTANT_OBJECTS
+            .iter()
+            .find(|(_, constant)| *constant == content)
+            .map(|&(name, content)| ParsedContentId::Constant { name, content })
+    }
+
+    /// Content of a constant ContentId
+    pub fn constant_content(&self) -> Option<&'static [u8]> {
+        match self {
+            ParsedContentId::Constant { content, .. } => Some(content),
+            _ => None,
+        }
+    }
+
+    /// Get the blob object ID (constants have none)
+    pub fn blob_object_id(&self) -> Option<&str> {
         match self {
-            ParsedContentId::Legacy { blob_object_id } => blob_object_id,
-            ParsedContentId::Batched { blob_object_id, .. } => blob_object_id,
+            ParsedContentId::Legacy { blob_object_id } => Some(blob_object_id),
+            ParsedContentId::Batched { blob_object_id, .. } => Some(blob_object_id),
+            ParsedContentId::Constant { .. } => None,
         }
     }
 
@@ -88,6 +128,7 @@ impl ParsedContentId {
                 offset,
                 length,
             } => format!("{}:{}:{}", blob_object_id, offset, length),
+            ParsedContentId::Constant { name, .. } => format!("const:{}", name),
         }
     }
 }
@@ -108,7 +149,7 @@ mod tests {
             }
         );
         assert!(!parsed.is_batched());
-        assert_eq!(parsed.blob_object_id(), "0xabc123");
+        assert_eq!(parsed.blob_object_id(), Some("0xabc123"));
         assert_eq!(parsed.encode(), content_id);
     }
 
@@ -126,7 +167,7 @@ mod tests {
             }
         );
         assert!(parsed.is_batched());
-        assert_eq!(parsed.blob_object_id(), "0xabc123");
+        assert_eq!(parsed.blob_object_id(), Some("0xabc123"));
         assert_eq!(parsed.encode(), content_id);
     }
 
@@ -146,6 +187,31 @@ mod tests {
         assert!(!parsed.is_batched());
     }
 
+    #[test]
+    fn test_constants() {
+        let parsed = ParsedContentId::constant(b"blob 0\0").unwrap();
+        assert_eq!(parsed.encode(), "const:empty-blob");
+        assert_eq!(ParsedContentId::parse("const:empty-blob").unwrap(), parsed);
+        assert_eq!(parsed.blob_object_id(), None);
+        assert_eq!(parsed.constant_content(), Some(&b"blob 0\0"[..]));
+
+        let parsed = ParsedContentId::parse("const:empty").unwrap();
+        assert_eq!(parsed.constant_content(), Some(&b""[..]));
+        assert_eq!(
+            ParsedContentId::constant(b"tree 0\0").unwrap().encode(),
+            "const:empty-tree"
+        );
+
+        assert_eq!(ParsedContentId::constant(b"blob 1\0x"), None);
+        assert!(ParsedContentId::parse("const:other").is_err());
+        assert_eq!(
+            ParsedContentId::parse("0xabc123")
+                .unwrap()
+                .constant_content(),
+            None
+        );
+    }
+
     #[test]
     fn test_parse_invalid_format() {
         let result = ParsedContentId::parse("0xabc:100");
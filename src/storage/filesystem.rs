@@ -1,6 +1,7 @@
 use std::{
     fs,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
 use anyhow::Result;
@@ -8,6 +9,7 @@ use sha2::{Digest, Sha256};
 
 use super::{
     traits::{ContentId, ImmutableStore, MutableState, StorageBackend},
+    ObjectsDiff,
     State,
 };
 
@@ -29,13 +31,20 @@ impl FilesystemStorage {
         self.base_path.join("objects")
     }
 
+    /// When the object `id` was written, if it is stored
+    pub(super) fn object_modified(&self, id: &str) -> Option<SystemTime> {
+        fs::metadata(self.objects_dir().join(id))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
     /// Get the path to the state file
     fn state_path(&self) -> PathBuf {
         self.base_path.join("state.yaml")
     }
 
     /// Compute SHA-256 hash of content
-    fn compute_hash(content: &[u8]) -> String {
+    pub(super) fn compute_hash(content: &[u8]) -> String {
         let mut hasher = Sha256::new();
         hasher.update(content);
         hex::encode(hasher.finalize())
@@ -96,7 +105,9 @@ impl MutableState for FilesystemStorage {
         let state_path = self.state_path();
         if state_path.exists() {
             let content = fs::read_to_string(&state_path)?;
-            Ok(serde_yaml::from_str(&content)?)
+            let mut state: State = serde_yaml::from_str(&content)?;
+            state.set_aside_invalid_refs();
+            Ok(state)
         } else {
             Ok(State::default())
         }
@@ -106,8 +117,18 @@ impl MutableState for FilesystemStorage {
         let state_path = self.state_path();
         let temp_path = self.base_path.join(".state.yaml.tmp");
 
-        // 1. Write to temp file
-        let yaml = serde_yaml::to_string(state)?;
+        if state_path.exists() {
+            let previous = self.read_state()?;
+            tracing::info!(
+                "Objects map: {}",
+                ObjectsDiff::compute(&previous.objects, &state.objects)
+            );
+        }
+
+        // 1. Write to temp file, keeping invalid refs until they're repaired
+        let mut state = state.clone();
+        state.restore_invalid_refs();
+        let yaml = serde_yaml::to_string(&state)?;
         fs::write(&temp_path, yaml)?;
 
         // 2. Atomic rename (atomic on POSIX systems)
@@ -138,6 +159,11 @@ impl StorageBackend for FilesystemStorage {
         fs::create_dir_all(self.objects_dir())?;
         Ok(())
     }
+
+    /// The state file is replaced by a rename
+    fn atomic_ref_updates(&self) -> bool {
+        true
+    }
 }
 
 #[cfg(test)]
@@ -175,6 +201,23 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_read_git_objects_verifies_sha1() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let storage = FilesystemStorage::new(temp_dir.path())?;
+        storage.initialize()?;
+
+        // `git hash-object` of "hello"
+        let git_sha1 = "b6fc4c620b67d95f953a5c1c1230aaab5db5a1b0";
+        let id = storage.write_object(b"blob 5\0hello")?;
+        let contents = storage.read_git_objects(&[(git_sha1, &id)])?;
+        assert_eq!(contents, vec![b"blob 5\0hello".to_vec()]);
+
+        let other = storage.write_object(b"blob 5\0world")?;
+        assert!(storage.read_git_objects(&[(git_sha1, &other)]).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_state_persistence() -> Result<()> {
         let temp_dir = TempDir::new()?;
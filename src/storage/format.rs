@@ -0,0 +1,467 @@
+//! Registry of the storage format features a Walrus remote's objects map may use
+//!
+//! Every write records the features its objects map relies on, with the writer's version, in
+//! the RemoteState's `last_writer` dynamic field. Readers check that header before downloading
+//! the map, so a collaborator on an older helper gets an "upgrade" error naming the versions
+//! involved instead of a parse failure on an entry it doesn't understand. The header stays out
+//! of the map itself, whose readers since v0.1.0 refuse keys that aren't object IDs.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, Result};
+
+use super::{ParsedContentId, State};
+
+/// Reserved key holding the format header of a chunked objects map's manifest, and of objects
+/// maps written by development builds before the header moved on-chain; like the metadata
+/// key, it can never collide with a hex git SHA-1
+pub const FORMAT_KEY: &str = "format";
+
+/// A format feature: its bit in the header's bitset and the first version able to read it
+///
+/// Bits are never reused, so a feature added later is unknown (and refused) by older readers
+/// rather than mistaken for another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Feature {
+    /// Bit in the header's feature set
+    pub bit: u32,
+    /// Name used in messages
+    pub name: &'static str,
+    /// First version able to read maps using it
+    pub since: Version,
+}
+
+/// Objects stored as `{blob}:{offset}:{length}` slices of a batched blob
+pub const BATCHED: Feature = Feature {
+    bit: 0,
+    name: "batched",
+    since: Version(0, 1, 0),
+};
+
+/// Built-in objects stored as `const:{name}` and never uploaded
+pub const CONSTANT: Feature = Feature {
+    bit: 1,
+    name: "const",
+    since: Version(0, 2, 0),
+};
+
+/// Symrefs stored under `symref:` keys, as development builds did; releases keep symrefs in the
+/// RemoteState's `symrefs` field instead and never write this
+pub const SYMREFS: Feature = Feature {
+    bit: 2,
+    name: "symrefs",
+    since: Version(0, 2, 0),
+};
+
+/// Repository metadata blob stored under the `metadata` key
+pub const METADATA: Feature = Feature {
+    bit: 3,
+    name: "metadata",
+    since: Version(0, 2, 0),
+};
+
+/// An objects map too large for one blob, split into parts listed by a manifest blob whose
+/// header has only this feature
+pub const CHUNKED: Feature = Feature {
+    bit: 4,
+    name: "chunked",
+    since: Version(0, 2, 0),
+};
+
+/// Every feature this version can read
+pub const FEATURES: &[Feature] = &[BATCHED, CONSTANT, SYMREFS, METADATA, CHUNKED];
+
+/// A `major.minor.patch` helper version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(pub u32, pub u32, pub u32);
+
+impl Version {
+    /// Version of this binary
+    pub fn current() -> Self {
+        env!("CARGO_PKG_VERSION")
+            .parse()
+            .expect("package version is major.minor.patch")
+    }
+}
+
+impl FromStr for Version {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // Pre-release and build suffixes don't change the format
+        let core = s.split(['-', '+']).next().unwrap_or_default();
+        let mut parts = core.split('.').map(str::parse::<u32>);
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) => {
+                Ok(Version(major, minor, patch))
+            }
+            _ => anyhow::bail!("invalid version {:?} (expected major.minor.patch)", s),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// A set of features, by bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FeatureSet(pub u64);
+
+impl FeatureSet {
+    /// The features this version can read
+    pub fn supported() -> Self {
+        FEATURES
+            .iter()
+            .fold(Self::default(), |set, feature| set.with(*feature))
+    }
+
+    /// The known features `version` can read
+    fn supported_by(version: Version) -> Self {
+        FEATURES
+            .iter()
+            .filter(|feature| feature.since <= version)
+            .fold(Self::default(), |set, feature| set.with(*feature))
+    }
+
+    /// This set plus `feature`
+    pub fn with(self, feature: Feature) -> Self {
+        Self(self.0 | 1 << feature.bit)
+    }
+
+    /// Whether `feature` is in this set
+    pub fn contains(self, feature: Feature) -> bool {
+        self.0 & 1 << feature.bit != 0
+    }
+
+    /// Bits of this set that `other` lacks
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// Whether this set has no features
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The newest `since` among the known features in this set
+    fn requires(self) -> Version {
+        FEATURES
+            .iter()
+            .filter(|feature| self.contains(**feature))
+            .map(|feature| feature.since)
+            .max()
+            .unwrap_or(Version(0, 1, 0))
+    }
+}
+
+/// Features by name, and bits this version has no name for as `bit N`
+impl fmt::Display for FeatureSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = (0..u64::BITS)
+            .filter(|bit| self.0 & 1 << bit != 0)
+            .map(|bit| {
+                let known = FEATURES.iter().find(|feature| feature.bit == bit);
+                known.map_or(format!("bit {}", bit), |feature| feature.name.to_string())
+            })
+            .collect();
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(", "))
+        }
+    }
+}
+
+/// What the last writer of an objects map recorded about its format
+///
+/// Stored as `features=<hex bitset>;writer=<version>;requires=<version>`, where `requires`
+/// is the oldest version able to read the map. Unrecognized fields are ignored, so later
+/// versions can add some.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatHeader {
+    /// Features the map uses
+    pub features: FeatureSet,
+    /// Version that wrote the map
+    pub writer: Version,
+    /// Oldest version able to read the map
+    pub requires: Version,
+}
+
+impl FormatHeader {
+    /// Header for writing `state`'s objects map with this version
+    pub fn for_state(state: &State) -> Self {
+        let mut features = FeatureSet::default();
+        for content_id in state.objects.values() {
+            match ParsedContentId::parse(content_id) {
+                Ok(ParsedContentId::Batched { .. }) => features = features.with(BATCHED),
+                Ok(ParsedContentId::Constant { .. }) => features = features.with(CONSTANT),
+                _ => {}
+            }
+        }
+        if state.metadata.is_some() {
+            features = features.with(METADATA);
+        }
+        Self {
+            features,
+            writer: Version::current(),
+            requires: features.requires(),
+        }
+    }
+
+    /// Header assumed for an objects map whose writer recorded none: v0.1.0, the last
+    /// release before headers, which knew only batched objects
+    pub fn unrecorded() -> Self {
+        let features = FeatureSet::default().with(BATCHED);
+        Self {
+            features,
+            writer: Version(0, 1, 0),
+            requires: features.requires(),
+        }
+    }
+
+    /// This header for a map stored in parts behind a manifest
+    pub fn stored_in_parts(self) -> Self {
+        let features = self.features.with(CHUNKED);
+        Self {
+            features,
+            requires: features.requires(),
+            ..self
+        }
+    }
+
+    /// Header for the manifest of an objects map split into parts
+    pub fn chunked() -> Self {
+        let features = FeatureSet::default().with(CHUNKED);
+        Self {
+            features,
+            writer: Version::current(),
+            requires: features.requires(),
+        }
+    }
+
+    /// Whether this is the header of a chunked objects map's manifest
+    pub fn is_chunked(&self) -> bool {
+        self.features.contains(CHUNKED)
+    }
+
+    /// Parse a header as [`Self::encode`] writes it
+    pub fn parse(value: &str) -> Result<Self> {
+        let (mut features, mut writer, mut requires) = (None, None, None);
+        for field in value.split(';') {
+            let (name, value) = field
+                .split_once('=')
+                .with_context(|| format!("Invalid format header field {:?}", field))?;
+            match name {
+                "features" => {
+                    let bits = u64::from_str_radix(value, 16)
+                        .with_context(|| format!("Invalid format header features {:?}", value))?;
+                    features = Some(FeatureSet(bits));
+                }
+                "writer" => writer = Some(value.parse()?),
+                "requires" => requires = Some(value.parse()?),
+                _ => {}
+            }
+        }
+        match (features, writer, requires) {
+            (Some(features), Some(writer), Some(requires)) => Ok(Self {
+                features,
+                writer,
+                requires,
+            }),
+            _ => anyhow::bail!("Incomplete format header {:?}", value),
+        }
+    }
+
+    /// The header as recorded on-chain and under a manifest's [`FORMAT_KEY`]
+    pub fn encode(&self) -> String {
+        format!(
+            "features={:x};writer={};requires={}",
+            self.features.0, self.writer, self.requires
+        )
+    }
+
+    /// Refuse a map this version can't read, and warn when a newer version wrote it
+    pub fn check_readable(&self) -> Result<()> {
+        let current = Version::current();
+        let unknown = self.features.without(FeatureSet::supported());
+        if !unknown.is_empty() || self.requires > current {
+            // A writer that used unknown features but claims an old enough reader can at
+            // least read its own map
+            let upgrade_to = if self.requires > current {
+                self.requires
+            } else {
+                self.writer
+            };
+            anyhow::bail!(
+                "this remote uses format features this version can't read ({}) written by \
+                 git-remote-walrus v{}; upgrade to at least v{} (this is v{})",
+                if unknown.is_empty() {
+                    self.features
+                } else {
+                    unknown
+                },
+                self.writer,
+                upgrade_to,
+                current
+            );
+        }
+        if self.writer > current {
+            tracing::warn!(
+                "git-remote-walrus: this remote was last written by v{}, newer than this v{}; \
+                 upgrade before its format moves on",
+                self.writer,
+                current
+            );
+        }
+        Ok(())
+    }
+
+    /// Warn when `previous` (the last writer's header) is from a version too old to read what
+    /// this header describes, since collaborators still on it will be locked out
+    pub fn warn_if_unreadable_by(&self, previous: &FormatHeader) {
+        if previous.writer < self.requires {
+            let new = self
+                .features
+                .without(FeatureSet::supported_by(previous.writer));
+            tracing::warn!(
+                "git-remote-walrus: this write uses format features ({}) that v{}, which last \
+                 wrote this remote, can't read; collaborators on it must upgrade to at least v{}",
+                new,
+                previous.writer,
+                self.requires
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Headers as each release records them, for a map using every feature it writes (v0.1.0
+    /// recorded none)
+    const RELEASE_HEADERS: &[(&str, &str)] =
+        &[("0.2.0", "features=1b;writer=0.2.0;requires=0.2.0")];
+
+    #[test]
+    fn test_release_headers_parse_and_are_readable() {
+        for (release, value) in RELEASE_HEADERS {
+            let header = FormatHeader::parse(value).unwrap();
+            assert_eq!(header.writer, release.parse().unwrap());
+            assert_eq!(header.encode(), *value);
+            assert_eq!(
+                header
+                    .features
+                    .without(FeatureSet::supported_by(header.writer)),
+                FeatureSet::default(),
+                "{} uses a feature {} doesn't know",
+                value,
+                release
+            );
+            header.check_readable().unwrap();
+        }
+        FormatHeader::unrecorded().check_readable().unwrap();
+    }
+
+    #[test]
+    fn test_features_newer_than_the_last_writer_are_reported() {
+        let mut state = State::default();
+        state
+            .objects
+            .insert("a".repeat(40), "0xabc:0:10".to_string());
+        // A map v0.1.0 could read
+        let header = FormatHeader::for_state(&state);
+        assert_eq!(header.requires, Version(0, 1, 0));
+        assert_eq!(
+            header
+                .features
+                .without(FeatureSet::supported_by(Version(0, 1, 0))),
+            FeatureSet::default()
+        );
+
+        // Constants, and storing the map in parts, lock v0.1.0 readers out
+        state
+            .objects
+            .insert("b".repeat(40), "const:empty-tree".to_string());
+        let header = FormatHeader::for_state(&state).stored_in_parts();
+        assert_eq!(header.requires, Version(0, 2, 0));
+        assert_eq!(
+            header
+                .features
+                .without(FeatureSet::supported_by(FormatHeader::unrecorded().writer))
+                .to_string(),
+            "const, chunked"
+        );
+    }
+
+    #[test]
+    fn test_header_for_state() {
+        let mut state = State::default();
+        let header = FormatHeader::for_state(&state);
+        assert_eq!(header.features, FeatureSet::default());
+        assert_eq!(header.writer, Version::current());
+
+        state
+            .objects
+            .insert("a".repeat(40), "0xabc:0:10".to_string());
+        state
+            .objects
+            .insert("b".repeat(40), "const:empty-tree".to_string());
+        state.objects.insert("c".repeat(40), "0xdef".to_string());
+        state
+            .symrefs
+            .insert("HEAD".to_string(), "refs/heads/main".to_string());
+        // Symrefs live on-chain, outside the map
+        let header = FormatHeader::for_state(&state);
+        assert_eq!(header.features.to_string(), "batched, const");
+        assert_eq!(FormatHeader::parse(&header.encode()).unwrap(), header);
+        assert!(!header.is_chunked());
+
+        let manifest = FormatHeader::chunked();
+        assert_eq!(manifest.features.to_string(), "chunked");
+        assert!(FormatHeader::parse(&manifest.encode())
+            .unwrap()
+            .is_chunked());
+    }
+
+    #[test]
+    fn test_newer_format_is_refused_with_versions() {
+        // A later release adding two features; unrecognized fields are ignored
+        let header =
+            FormatHeader::parse("features=7f;writer=0.9.0;requires=0.8.0;compression=zstd")
+                .unwrap();
+        let err = header.check_readable().unwrap_err().to_string();
+        assert_eq!(
+            err,
+            format!(
+                "this remote uses format features this version can't read (bit 5, bit 6) \
+                 written by git-remote-walrus v0.9.0; upgrade to at least v0.8.0 (this is v{})",
+                Version::current()
+            )
+        );
+
+        // A newer writer that used only known features is readable
+        FormatHeader::parse("features=3;writer=0.9.0;requires=0.1.0")
+            .unwrap()
+            .check_readable()
+            .unwrap();
+
+        for value in [
+            "",
+            "features=zz;writer=0.1.0;requires=0.1.0",
+            "features=1;writer=1",
+        ] {
+            assert!(FormatHeader::parse(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_version_order_and_parse() {
+        assert!("0.10.0".parse::<Version>().unwrap() > "0.9.3".parse().unwrap());
+        assert_eq!("1.2.3-beta.1".parse::<Version>().unwrap(), Version(1, 2, 3));
+        assert!("1.2".parse::<Version>().is_err());
+    }
+}
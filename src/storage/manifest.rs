@@ -0,0 +1,555 @@
+//! Which objects were packed where in each batched blob, so sparse blobs can be repacked
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{verify_git_object, ContentId, ParsedContentId};
+
+/// Blobs with less than this fraction of their bytes live are repacked rather than renewed
+const REPACK_LIVE_FRACTION: f64 = 0.5;
+
+/// One object in a batched blob
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    pub git_sha1: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl ManifestEntry {
+    /// Batched ContentId of the object in the blob `blob_object_id`
+    pub fn content_id(&self, blob_object_id: &str) -> ContentId {
+        ParsedContentId::batched(blob_object_id.to_string(), self.offset, self.length).encode()
+    }
+}
+
+/// The objects packed into one batched blob, in the order they were concatenated
+///
+/// Written to `blob_manifests/<blob object ID>.yaml` under the cache directory at upload time.
+/// The objects map holds the same information, so a missing manifest is rebuilt from it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlobManifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl BlobManifest {
+    /// Manifest of objects concatenated in order, each named by its git SHA-1
+    pub fn packed(objects: impl IntoIterator<Item = (String, u64)>) -> Self {
+        let mut offset = 0;
+        let entries = objects
+            .into_iter()
+            .map(|(git_sha1, length)| {
+                let entry = ManifestEntry {
+                    git_sha1,
+                    offset,
+                    length,
+                };
+                offset += length;
+                entry
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Rebuild the manifest of `blob_object_id` from the batched entries of an objects map
+    pub fn from_objects(blob_object_id: &str, objects: &BTreeMap<String, ContentId>) -> Self {
+        let mut entries: Vec<ManifestEntry> = objects
+            .iter()
+            .filter_map(
+                |(git_sha1, content_id)| match ParsedContentId::parse(content_id) {
+                    Ok(ParsedContentId::Batched {
+                        blob_object_id: id,
+                        offset,
+                        length,
+                    }) if id == blob_object_id => Some(ManifestEntry {
+                        git_sha1: git_sha1.clone(),
+                        offset,
+                        length,
+                    }),
+                    _ => None,
+                },
+            )
+            .collect();
+        entries.sort_by_key(|entry| entry.offset);
+        Self { entries }
+    }
+
+    /// The manifest saved under `cache_dir`, or one rebuilt from `objects` if there is none
+    pub fn load_or_rebuild(
+        cache_dir: &Path,
+        blob_object_id: &str,
+        objects: &BTreeMap<String, ContentId>,
+    ) -> Result<Self> {
+        let path = Self::path(cache_dir, blob_object_id);
+        if !path.exists() {
+            tracing::debug!("No manifest for {}; rebuilding it", blob_object_id);
+            return Ok(Self::from_objects(blob_object_id, objects));
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read blob manifest from {:?}", path))?;
+        serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse blob manifest from {:?}", path))
+    }
+
+    /// Save under `cache_dir`
+    pub fn save(&self, cache_dir: &Path, blob_object_id: &str) -> Result<()> {
+        let path = Self::path(cache_dir, blob_object_id);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {:?}", parent))?;
+        }
+        let content = serde_yaml::to_string(self).context("Failed to serialize blob manifest")?;
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write blob manifest to {:?}", path))
+    }
+
+    fn path(cache_dir: &Path, blob_object_id: &str) -> PathBuf {
+        cache_dir
+            .join("blob_manifests")
+            .join(format!("{}.yaml", blob_object_id))
+    }
+
+    /// ContentIds of the entries once this manifest is uploaded as `blob_object_id`
+    ///
+    /// Like any upload, a blob holding a single object is that object's whole ContentId.
+    pub fn uploaded_content_ids<'a>(
+        &'a self,
+        blob_object_id: &'a str,
+    ) -> impl Iterator<Item = (&'a ManifestEntry, ContentId)> + 'a {
+        self.entries.iter().map(move |entry| {
+            let content_id = match self.entries.len() {
+                1 => ParsedContentId::legacy(blob_object_id.to_string()).encode(),
+                _ => entry.content_id(blob_object_id),
+            };
+            (entry, content_id)
+        })
+    }
+
+    /// Bytes of all the objects in the blob
+    pub fn size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.length).sum()
+    }
+
+    /// Entries the objects map still reads from this blob and that `live` accepts
+    pub fn live<'a>(
+        &'a self,
+        blob_object_id: &str,
+        objects: &BTreeMap<String, ContentId>,
+        live: impl Fn(&str) -> bool,
+    ) -> Vec<&'a ManifestEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| {
+                objects.get(&entry.git_sha1) == Some(&entry.content_id(blob_object_id))
+                    && live(&entry.git_sha1)
+            })
+            .collect()
+    }
+
+    /// Whether so little of the blob is live that copying the live objects out beats renewing
+    pub fn worth_repacking(&self, live: &[&ManifestEntry]) -> bool {
+        let live_bytes: u64 = live.iter().map(|entry| entry.length).sum();
+        (live_bytes as f64) < self.size() as f64 * REPACK_LIVE_FRACTION
+    }
+}
+
+/// Live objects of a blob copied into the content of a new one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repack {
+    pub content: Vec<u8>,
+    pub manifest: BlobManifest,
+}
+
+impl Repack {
+    /// Concatenate the `live` slices of `blob`, checking each against its git SHA-1 so a
+    /// corrupt object isn't carried into the new blob
+    pub fn build(blob: &[u8], live: &[&ManifestEntry]) -> Result<Self> {
+        let mut content = Vec::new();
+        for entry in live {
+            let slice = usize::try_from(entry.offset)
+                .ok()
+                .zip(usize::try_from(entry.offset + entry.length).ok())
+                .and_then(|(start, end)| blob.get(start..end))
+                .with_context(|| {
+                    format!(
+                        "Object {} at {}+{} lies outside the {}-byte blob",
+                        entry.git_sha1,
+                        entry.offset,
+                        entry.length,
+                        blob.len()
+                    )
+                })?;
+            verify_git_object(&entry.git_sha1, slice)
+                .with_context(|| format!("Object {} is corrupt in its blob", entry.git_sha1))?;
+            content.extend_from_slice(slice);
+        }
+        let manifest = BlobManifest::packed(
+            live.iter()
+                .map(|entry| (entry.git_sha1.clone(), entry.length)),
+        );
+        Ok(Self { content, manifest })
+    }
+
+    /// Each object's git SHA-1 and loose bytes, in the order they're concatenated
+    pub fn slices(&self) -> impl Iterator<Item = (&str, &[u8])> + '_ {
+        self.manifest.entries.iter().map(|entry| {
+            let start = entry.offset as usize;
+            (
+                entry.git_sha1.as_str(),
+                &self.content[start..start + entry.length as usize],
+            )
+        })
+    }
+}
+
+/// How many distinct blobs the objects map reads from (objects stored as constants need none)
+pub fn blob_count(objects: &BTreeMap<String, ContentId>) -> usize {
+    objects
+        .values()
+        .filter_map(|content_id| ParsedContentId::parse(content_id).ok())
+        .filter_map(|parsed| parsed.blob_object_id().map(str::to_string))
+        .collect::<std::collections::BTreeSet<_>>()
+        .len()
+}
+
+/// Pack `objects` (git SHA-1 and loose bytes) into as few blobs of at most `max_blob_size`
+/// bytes as first-fit decreasing finds; an object over the limit gets a blob of its own
+pub fn consolidate(mut objects: Vec<(String, Vec<u8>)>, max_blob_size: u64) -> Vec<Repack> {
+    objects.sort_by(|(a_sha, a), (b_sha, b)| b.len().cmp(&a.len()).then(a_sha.cmp(b_sha)));
+    let mut bins: Vec<(u64, Vec<(String, Vec<u8>)>)> = Vec::new();
+    for (git_sha1, content) in objects {
+        let length = content.len() as u64;
+        match bins
+            .iter_mut()
+            .find(|(used, _)| used + length <= max_blob_size)
+        {
+            Some((used, members)) => {
+                *used += length;
+                members.push((git_sha1, content));
+            }
+            None => bins.push((length, vec![(git_sha1, content)])),
+        }
+    }
+    bins.into_iter()
+        .map(|(_, members)| Repack {
+            manifest: BlobManifest::packed(
+                members
+                    .iter()
+                    .map(|(git_sha1, content)| (git_sha1.clone(), content.len() as u64)),
+            ),
+            content: members
+                .into_iter()
+                .flat_map(|(_, content)| content)
+                .collect(),
+        })
+        .collect()
+}
+
+/// Point objects at their `consolidated` ContentIds, skipping any the map no longer reads as
+/// it did in `read`; returns how many moved
+pub fn rewrite_consolidated(
+    objects: &mut BTreeMap<String, ContentId>,
+    read: &BTreeMap<String, ContentId>,
+    consolidated: &BTreeMap<String, ContentId>,
+) -> usize {
+    let mut moved = 0;
+    for (git_sha1, content_id) in consolidated {
+        match objects.get_mut(git_sha1) {
+            Some(current) if read.get(git_sha1) == Some(current) => {
+                *current = content_id.clone();
+                moved += 1;
+            }
+            _ => {}
+        }
+    }
+    moved
+}
+
+/// Point the objects the map reads from `old_id` at their place in `new` (None when nothing
+/// was live), dropping those that weren't copied; returns how many moved and were dropped
+pub fn rewrite_objects(
+    objects: &mut BTreeMap<String, ContentId>,
+    old_id: &str,
+    old: &BlobManifest,
+    new: Option<(&str, &BlobManifest)>,
+) -> (usize, usize) {
+    let moved: BTreeMap<&str, ContentId> = new
+        .map(|(new_id, manifest)| {
+            manifest
+                .uploaded_content_ids(new_id)
+                .map(|(entry, content_id)| (entry.git_sha1.as_str(), content_id))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let (mut rewritten, mut dropped) = (0, 0);
+    for entry in &old.entries {
+        if objects.get(&entry.git_sha1) != Some(&entry.content_id(old_id)) {
+            continue;
+        }
+        match moved.get(entry.git_sha1.as_str()) {
+            Some(content_id) => {
+                objects.insert(entry.git_sha1.clone(), content_id.clone());
+                rewritten += 1;
+            }
+            None => {
+                objects.remove(&entry.git_sha1);
+                dropped += 1;
+            }
+        }
+    }
+    (rewritten, dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use gix_object::Kind;
+
+    use super::*;
+    use crate::{pack::objects::GitObject, walrus::mock_client};
+
+    /// Loose objects for `names`, their git SHA-1s, and a map batching them into `blob_id`
+    fn batch(
+        blob_id: &str,
+        names: &[&str],
+    ) -> (Vec<Vec<u8>>, Vec<String>, BTreeMap<String, ContentId>) {
+        let contents: Vec<Vec<u8>> = names
+            .iter()
+            .map(|name| {
+                GitObject::from_raw(Kind::Blob, name.repeat(100).into_bytes())
+                    .unwrap()
+                    .to_loose_format()
+            })
+            .collect();
+        let shas: Vec<String> = contents
+            .iter()
+            .map(|content| GitObject::from_loose_format(content).unwrap().id)
+            .collect();
+        let manifest = BlobManifest::packed(
+            shas.iter()
+                .zip(&contents)
+                .map(|(sha, content)| (sha.clone(), content.len() as u64)),
+        );
+        let objects = manifest
+            .entries
+            .iter()
+            .map(|entry| (entry.git_sha1.clone(), entry.content_id(blob_id)))
+            .collect();
+        (contents, shas, objects)
+    }
+
+    #[test]
+    fn test_manifest_is_rebuilt_from_the_objects_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let (contents, shas, mut objects) = batch("0xold", &["a", "b", "c"]);
+        objects.insert("f".repeat(40), "0xother:0:10".to_string());
+
+        let saved = BlobManifest::packed(
+            shas.iter()
+                .zip(&contents)
+                .map(|(sha, content)| (sha.clone(), content.len() as u64)),
+        );
+        let rebuilt = BlobManifest::load_or_rebuild(dir.path(), "0xold", &objects).unwrap();
+        assert_eq!(rebuilt, saved);
+
+        saved.save(dir.path(), "0xold").unwrap();
+        assert_eq!(
+            BlobManifest::load_or_rebuild(dir.path(), "0xold", &BTreeMap::new()).unwrap(),
+            saved
+        );
+    }
+
+    #[test]
+    fn test_live_entries_and_repack_threshold() {
+        let (_, shas, mut objects) = batch("0xold", &["a", "b", "c", "d"]);
+        let manifest = BlobManifest::from_objects("0xold", &objects);
+
+        let all = manifest.live("0xold", &objects, |_| true);
+        assert_eq!(all.len(), 4);
+        assert!(!manifest.worth_repacking(&all));
+
+        // One object remapped elsewhere, two unreachable: a quarter of the blob is live
+        objects.insert(shas[0].clone(), "0xnewer".to_string());
+        let reachable: HashSet<&str> = [shas[0].as_str(), shas[3].as_str()].into();
+        let live = manifest.live("0xold", &objects, |sha| reachable.contains(sha));
+        assert_eq!(live, [&manifest.entries[3]]);
+        assert!(manifest.worth_repacking(&live));
+    }
+
+    #[test]
+    fn test_rewritten_content_ids_read_the_same_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+        let (contents, shas, objects) = batch("0xold", &["a", "b", "c", "d", "e"]);
+        let blob: Vec<u8> = contents.concat();
+        let old = BlobManifest::from_objects("0xold", &objects);
+
+        // Keep b and d
+        let live = old.live("0xold", &objects, |sha| sha == shas[1] || sha == shas[3]);
+        let repack = Repack::build(&blob, &live).unwrap();
+        assert_eq!(repack.content.len(), contents[1].len() + contents[3].len());
+        let stored = client.store(&repack.content).unwrap();
+
+        let mut rewritten = objects.clone();
+        let counts = rewrite_objects(
+            &mut rewritten,
+            "0xold",
+            &old,
+            Some((&stored.shared_object_id, &repack.manifest)),
+        );
+        assert_eq!(counts, (2, 3));
+        assert_eq!(rewritten.len(), 2);
+
+        let new_blob = client.read(&stored.blob_id).unwrap();
+        for i in [1, 3] {
+            let content_id = &rewritten[&shas[i]];
+            let ParsedContentId::Batched {
+                blob_object_id,
+                offset,
+                length,
+            } = ParsedContentId::parse(content_id).unwrap()
+            else {
+                panic!("{} is not batched", content_id);
+            };
+            assert_eq!(blob_object_id, stored.shared_object_id);
+            let slice = &new_blob[offset as usize..(offset + length) as usize];
+            assert_eq!(slice, contents[i]);
+            verify_git_object(&shas[i], slice).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_repack_of_one_object_is_a_whole_blob() {
+        let (contents, shas, mut objects) = batch("0xold", &["a", "b"]);
+        let old = BlobManifest::from_objects("0xold", &objects);
+        let live = old.live("0xold", &objects, |sha| sha == shas[0]);
+        let repack = Repack::build(&contents.concat(), &live).unwrap();
+        assert_eq!(repack.content, contents[0]);
+
+        rewrite_objects(
+            &mut objects,
+            "0xold",
+            &old,
+            Some(("0xnew", &repack.manifest)),
+        );
+        assert_eq!(objects[&shas[0]], "0xnew");
+
+        // Nothing live: every entry is dropped
+        let (_, _, mut objects) = batch("0xold", &["a", "b"]);
+        assert_eq!(rewrite_objects(&mut objects, "0xold", &old, None), (0, 2));
+        assert!(objects.is_empty());
+    }
+
+    #[test]
+    fn test_consolidating_single_object_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+
+        // Ten objects pushed one at a time, each in a blob of its own
+        let names = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+        let mut objects = BTreeMap::new();
+        let mut loose = Vec::new();
+        for name in names {
+            let content = GitObject::from_raw(Kind::Blob, name.repeat(100).into_bytes())
+                .unwrap()
+                .to_loose_format();
+            let sha = GitObject::from_loose_format(&content).unwrap().id;
+            let stored = client.store(&content).unwrap();
+            objects.insert(sha.clone(), stored.shared_object_id);
+            loose.push((sha, content));
+        }
+        objects.insert("e".repeat(40), "const:empty-blob".to_string());
+        assert_eq!(blob_count(&objects), 10);
+
+        // Room for four objects per blob
+        let size = loose[0].1.len() as u64;
+        let blobs = consolidate(loose.clone(), size * 4);
+        assert_eq!(blobs.len(), 3);
+        let mut consolidated = BTreeMap::new();
+        let mut blob_ids = BTreeMap::new();
+        for blob in blobs {
+            let stored = client.store(&blob.content).unwrap();
+            blob_ids.insert(stored.shared_object_id.clone(), stored.blob_id);
+            for (entry, content_id) in blob.manifest.uploaded_content_ids(&stored.shared_object_id)
+            {
+                consolidated.insert(entry.git_sha1.clone(), content_id);
+            }
+        }
+
+        let read = objects.clone();
+        // A concurrent push remapped one object; it keeps its new mapping
+        objects.insert(loose[0].0.clone(), "0xconcurrent".to_string());
+        assert_eq!(rewrite_consolidated(&mut objects, &read, &consolidated), 9);
+        assert_eq!(objects[&loose[0].0], "0xconcurrent");
+        assert_eq!(objects[&"e".repeat(40)], "const:empty-blob");
+        assert_eq!(blob_count(&objects), 4);
+
+        // Every moved object reads back from its new blob
+        for (sha, content) in &loose[1..] {
+            let ParsedContentId::Batched {
+                blob_object_id,
+                offset,
+                length,
+            } = ParsedContentId::parse(&objects[sha]).unwrap()
+            else {
+                panic!("{} is not batched", objects[sha]);
+            };
+            let blob = client.read(&blob_ids[&blob_object_id]).unwrap();
+            assert_eq!(&blob[offset as usize..(offset + length) as usize], content);
+        }
+    }
+
+    #[test]
+    fn test_consolidate_gives_oversized_objects_their_own_blob() {
+        let objects = vec![
+            ("a".repeat(40), vec![0; 10]),
+            ("b".repeat(40), vec![1; 3]),
+            ("c".repeat(40), vec![2; 4]),
+        ];
+        let blobs = consolidate(objects, 8);
+        let shas: Vec<Vec<&str>> = blobs
+            .iter()
+            .map(|blob| {
+                blob.manifest
+                    .entries
+                    .iter()
+                    .map(|entry| &entry.git_sha1[..1])
+                    .collect()
+            })
+            .collect();
+        assert_eq!(shas, [vec!["a"], vec!["c", "b"]]);
+        assert_eq!(blobs[1].content, [2, 2, 2, 2, 1, 1, 1]);
+        let slices: Vec<(&str, &[u8])> = blobs[1].slices().collect();
+        assert_eq!(
+            slices,
+            [
+                (&"c".repeat(40)[..], &[2; 4][..]),
+                (&"b".repeat(40)[..], &[1; 3][..])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repack_refuses_corrupt_slices() {
+        let (contents, _, objects) = batch("0xold", &["a", "b"]);
+        let old = BlobManifest::from_objects("0xold", &objects);
+        let mut blob = contents.concat();
+        let last = blob.len() - 1;
+        blob[last] ^= 1;
+
+        let live = old.live("0xold", &objects, |_| true);
+        let err = Repack::build(&blob, &live).unwrap_err();
+        assert!(format!("{:#}", err).contains("corrupt"), "{:#}", err);
+        assert!(Repack::build(&blob[..10], &live).is_err());
+    }
+}
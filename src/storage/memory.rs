@@ -0,0 +1,306 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::{Context, Result};
+
+use super::{
+    traits::{ContentId, ImmutableStore, MutableState, StorageBackend},
+    FilesystemStorage,
+    State,
+    StateChanged,
+};
+use crate::pack::PrefetchStrategy;
+
+/// Called with the number of each batch read (from 1) before it is served
+type ReadHook = Box<dyn Fn(usize) + Send + Sync>;
+
+/// In-process storage backend for tests, content-addressed by SHA-256 like
+/// [`FilesystemStorage`]
+#[derive(Default)]
+pub struct MemoryStorage {
+    objects: Mutex<HashMap<ContentId, Vec<u8>>>,
+    state: Mutex<State>,
+    /// Number of state writes so far, only changed while `state` is locked
+    writes: AtomicU64,
+    /// Batch reads so far
+    batch_reads: AtomicUsize,
+    read_hook: Option<ReadHook>,
+    /// Order fetches read in, if not the walk's own
+    prefetch_strategy: Option<PrefetchStrategy>,
+}
+
+impl MemoryStorage {
+    /// Create an empty storage backend
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty storage backend fetched in `strategy` order, which calls `hook` with the
+    /// number of each batch read (from 1) before serving it, standing in for a network read
+    pub fn with_read_hook(
+        strategy: PrefetchStrategy,
+        hook: impl Fn(usize) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            read_hook: Some(Box::new(hook)),
+            prefetch_strategy: Some(strategy),
+            ..Self::default()
+        }
+    }
+
+    /// Number of distinct objects stored
+    pub fn object_count(&self) -> usize {
+        self.objects.lock().unwrap().len()
+    }
+}
+
+impl ImmutableStore for MemoryStorage {
+    fn write_object(&self, content: &[u8]) -> Result<ContentId> {
+        let id = FilesystemStorage::compute_hash(content);
+        self.objects
+            .lock()
+            .unwrap()
+            .entry(id.clone())
+            .or_insert_with(|| content.to_vec());
+        Ok(id)
+    }
+
+    fn write_objects(&self, contents: &[&[u8]]) -> Result<Vec<ContentId>> {
+        contents
+            .iter()
+            .map(|content| self.write_object(content))
+            .collect()
+    }
+
+    fn read_object(&self, id: &str) -> Result<Vec<u8>> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .with_context(|| format!("Object {} not found", id))
+    }
+
+    fn read_objects(&self, ids: &[&str]) -> Result<Vec<Vec<u8>>> {
+        let batch = self.batch_reads.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(hook) = &self.read_hook {
+            hook(batch);
+        }
+        ids.iter().map(|id| self.read_object(id)).collect()
+    }
+
+    fn delete_object(&self, id: &str) -> Result<()> {
+        self.objects.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn object_exists(&self, id: &str) -> Result<bool> {
+        Ok(self.objects.lock().unwrap().contains_key(id))
+    }
+}
+
+impl MutableState for MemoryStorage {
+    fn read_state(&self) -> Result<State> {
+        let mut state = self.state.lock().unwrap().clone();
+        state.set_aside_invalid_refs();
+        Ok(state)
+    }
+
+    fn write_state(&self, state: &State) -> Result<()> {
+        let mut state = state.clone();
+        state.restore_invalid_refs();
+        let mut current = self.state.lock().unwrap();
+        *current = state;
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn update_state<F>(&self, update_fn: F) -> Result<()>
+    where
+        F: FnOnce(&mut State) -> Result<()>,
+    {
+        // Like a remote, the update runs unlocked and the write is a compare-and-swap: a write
+        // by anyone else in between refuses it, leaving the state untouched
+        let (mut state, read) = {
+            let current = self.state.lock().unwrap();
+            (current.clone(), self.writes.load(Ordering::SeqCst))
+        };
+        state.set_aside_invalid_refs();
+        update_fn(&mut state)?;
+        state.restore_invalid_refs();
+
+        let mut current = self.state.lock().unwrap();
+        if self.writes.load(Ordering::SeqCst) != read {
+            return Err(StateChanged.into());
+        }
+        *current = state;
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    fn initialize(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn prefetch_strategy(&self) -> PrefetchStrategy {
+        self.prefetch_strategy.unwrap_or(PrefetchStrategy::None)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use gix_object::Kind;
+
+    use super::*;
+    use crate::pack::{
+        objects::{GitObject, ObjectId},
+        receive::receive_pack,
+        send_pack,
+    };
+
+    /// Store an object and add it to `storage`'s objects map
+    pub(crate) fn store_object(
+        storage: &MemoryStorage,
+        kind: Kind,
+        data: impl Into<Vec<u8>>,
+    ) -> ObjectId {
+        let obj = GitObject::from_raw(kind, data.into()).unwrap();
+        let content_id = storage.write_object(&obj.to_loose_format()).unwrap();
+        storage
+            .update_state(|state| {
+                state.objects.insert(obj.id.clone(), content_id);
+                Ok(())
+            })
+            .unwrap();
+        obj.id
+    }
+
+    /// Store an object and add it to `state`'s objects map, leaving `storage`'s own alone
+    pub(crate) fn store_object_in(
+        storage: &MemoryStorage,
+        state: &mut State,
+        kind: Kind,
+        data: impl Into<Vec<u8>>,
+    ) -> ObjectId {
+        let obj = GitObject::from_raw(kind, data.into()).unwrap();
+        let content_id = storage.write_object(&obj.to_loose_format()).unwrap();
+        state.objects.insert(obj.id.clone(), content_id);
+        obj.id
+    }
+
+    #[test]
+    fn test_content_addressing() {
+        let storage = MemoryStorage::new();
+        let id = storage.write_object(b"Hello, World!").unwrap();
+        assert_eq!(id, storage.write_object(b"Hello, World!").unwrap());
+        assert_eq!(
+            id,
+            "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+        );
+        assert_eq!(storage.object_count(), 1);
+        assert_eq!(storage.read_object(&id).unwrap(), b"Hello, World!");
+
+        storage.delete_object(&id).unwrap();
+        assert!(!storage.object_exists(&id).unwrap());
+        assert!(storage.read_object(&id).is_err());
+    }
+
+    #[test]
+    fn test_failed_update_leaves_state_unchanged() {
+        let storage = MemoryStorage::new();
+        storage
+            .update_state(|state| {
+                state.refs.insert("refs/heads/main".into(), "a".repeat(40));
+                Ok(())
+            })
+            .unwrap();
+
+        let result = storage.update_state(|state| {
+            state.refs.clear();
+            anyhow::bail!("conflict")
+        });
+        assert!(result.is_err());
+        assert_eq!(storage.read_state().unwrap().refs.len(), 1);
+    }
+
+    #[test]
+    fn test_push_then_fetch_round_trip() {
+        // A repository on one backend: a commit with a nested tree
+        let source = MemoryStorage::new();
+        let readme = store_object(&source, Kind::Blob, "# Readme\n");
+        let lib = store_object(&source, Kind::Blob, "fn main() {}\n");
+        let tree_entry = |mode: &str, name: &str, id: &str| {
+            [
+                format!("{mode} {name}\0").into_bytes(),
+                hex::decode(id).unwrap(),
+            ]
+            .concat()
+        };
+        let src = store_object(&source, Kind::Tree, tree_entry("100644", "lib.rs", &lib));
+        let root = store_object(
+            &source,
+            Kind::Tree,
+            [
+                tree_entry("100644", "README.md", &readme),
+                tree_entry("40000", "src", &src),
+            ]
+            .concat(),
+        );
+        let commit = store_object(
+            &source,
+            Kind::Commit,
+            format!(
+                "tree {}\nauthor A <a@a> 100 +0000\ncommitter A <a@a> 100 +0000\n\nInitial\n",
+                root
+            ),
+        );
+        source
+            .update_state(|state| {
+                state.refs.insert("refs/heads/main".into(), commit.clone());
+                Ok(())
+            })
+            .unwrap();
+
+        // Push: the pack a client sends is received into a fresh backend, as push does
+        let wanted = vec!["refs/heads/main".to_string()];
+        let mut pack = Vec::new();
+        send_pack(&wanted, &source, &mut pack).unwrap();
+        let remote = MemoryStorage::new();
+        let mappings = receive_pack(&mut pack.as_slice(), &remote).unwrap();
+        assert_eq!(mappings.len(), 5);
+        remote
+            .update_state(|state| {
+                state.objects.extend(mappings);
+                state.refs.insert("refs/heads/main".into(), commit.clone());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(remote.object_count(), 5);
+
+        // Fetch: the remote serves the same objects back
+        let mut fetched = Vec::new();
+        send_pack(&wanted, &remote, &mut fetched).unwrap();
+        let clone = MemoryStorage::new();
+        let mut ids: Vec<String> = receive_pack(&mut fetched.as_slice(), &clone)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        ids.sort();
+        let mut expected: Vec<String> = source.read_state().unwrap().objects.into_keys().collect();
+        expected.sort();
+        assert_eq!(ids, expected);
+
+        let state = remote.read_state().unwrap();
+        let content_id = state.objects[&commit].as_str();
+        let raw = remote.read_git_objects(&[(&commit, content_id)]).unwrap();
+        assert!(raw[0].ends_with(b"\n\nInitial\n"));
+    }
+}
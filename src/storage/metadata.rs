@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    traits::{ImmutableStore, StorageBackend},
+    State,
+};
+
+/// Reserved objects-map key under which Walrus remotes store the metadata blob's content ID
+/// (git SHA-1 keys are hex, so this can never collide with an object)
+pub const METADATA_KEY: &str = "metadata";
+
+/// Branches preferred as the default branch, in order
+const DEFAULT_BRANCH_CANDIDATES: &[&str] = &["refs/heads/main", "refs/heads/master"];
+
+/// Pushes `history` keeps; older ones are forgotten
+const MAX_PUSH_HISTORY: usize = 100;
+
+/// Self-describing repository metadata, stored as a small YAML blob
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RepoMetadata {
+    /// Free-form repository description
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Branch clients should check out by default (e.g. refs/heads/main)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_branch: Option<String>,
+    /// RFC 3339 time of the first push
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    /// RFC 3339 time of the most recent push
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_push_at: Option<String>,
+    /// Address (or identity) of the most recent pusher
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pusher: Option<String>,
+    /// Verified certificate of the most recent push, if it was signed (`git push --signed`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push_certificate: Option<PushCertificate>,
+    /// Push options of the most recent push (`git push --push-option`), for automation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub push_options: Vec<String>,
+    /// The most recent pushes, oldest first (at most `MAX_PUSH_HISTORY`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<PushRecord>,
+}
+
+/// One push in the metadata's `history`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PushRecord {
+    /// RFC 3339 time of the push
+    pub at: String,
+    /// Address (or identity) of the pusher
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pusher: Option<String>,
+    /// Refs the push set, and what it set them to
+    pub refs: BTreeMap<String, String>,
+    /// User ID and fingerprint of the key that signed the push, if it was signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+    /// Push options the push was given (`git push --push-option`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub push_options: Vec<String>,
+}
+
+/// A signed push certificate and the key it was verified against
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PushCertificate {
+    /// User ID and fingerprint of the signing key
+    pub signer: String,
+    /// Certificate text and its armored signature, as signed
+    pub certificate: String,
+}
+
+impl RepoMetadata {
+    /// Read the metadata referenced by `state`, or empty metadata if there is none yet
+    pub fn load<S: ImmutableStore>(storage: &S, state: &State) -> Result<Self> {
+        let Some(content_id) = &state.metadata else {
+            return Ok(Self::default());
+        };
+
+        let bytes = storage
+            .read_object(content_id)
+            .with_context(|| format!("Failed to read metadata blob {}", content_id))?;
+        serde_yaml::from_slice(&bytes).context("Failed to parse repository metadata")
+    }
+
+    /// Write the metadata as a new blob and point `state` at it
+    pub fn store<S: ImmutableStore>(&self, storage: &S, state: &mut State) -> Result<()> {
+        let yaml =
+            serde_yaml::to_string(self).context("Failed to serialize repository metadata")?;
+        let content_id = storage
+            .write_object(yaml.as_bytes())
+            .context("Failed to write metadata blob")?;
+        state.metadata = Some(content_id);
+        Ok(())
+    }
+
+    /// Update push bookkeeping for a push that set `pushed` refs, leaving `refs`; picks a
+    /// default branch from `refs` if none is set
+    pub fn record_push(
+        &mut self,
+        refs: &BTreeMap<String, String>,
+        pushed: &BTreeMap<String, String>,
+        pusher: Option<String>,
+        push_certificate: Option<PushCertificate>,
+        push_options: &[String],
+        now: &str,
+    ) {
+        self.created_at.get_or_insert_with(|| now.to_string());
+        self.last_push_at = Some(now.to_string());
+        self.history.push(PushRecord {
+            at: now.to_string(),
+            pusher: pusher.clone(),
+            refs: pushed.clone(),
+            signer: push_certificate.as_ref().map(|cert| cert.signer.clone()),
+            push_options: push_options.to_vec(),
+        });
+        let excess = self.history.len().saturating_sub(MAX_PUSH_HISTORY);
+        self.history.drain(..excess);
+        if pusher.is_some() {
+            self.pusher = pusher;
+        }
+        // An unsigned push clears the previous push's certificate, as a push without options does
+        // its options
+        self.push_certificate = push_certificate;
+        self.push_options = push_options.to_vec();
+
+        let default_is_valid = self
+            .default_branch
+            .as_ref()
+            .is_some_and(|branch| refs.contains_key(branch));
+        if !default_is_valid {
+            self.default_branch = DEFAULT_BRANCH_CANDIDATES
+                .iter()
+                .find(|branch| refs.contains_key(**branch))
+                .map(|branch| branch.to_string())
+                .or_else(|| {
+                    refs.keys()
+                        .find(|name| name.starts_with("refs/heads/"))
+                        .cloned()
+                })
+                .or(self.default_branch.take());
+        }
+    }
+
+    /// The most recent recorded push that set `ref_name` to `git_sha1`
+    pub fn push_of(&self, ref_name: &str, git_sha1: &str) -> Option<&PushRecord> {
+        self.history
+            .iter()
+            .rev()
+            .find(|push| push.refs.get(ref_name).map(String::as_str) == Some(git_sha1))
+    }
+}
+
+/// Refresh the metadata blob for a push that set `pushed` refs and produced `state` (call
+/// inside `update_state`)
+pub fn record_push<S: StorageBackend>(
+    storage: &S,
+    state: &mut State,
+    pushed: &BTreeMap<String, String>,
+    push_certificate: Option<PushCertificate>,
+    push_options: &[String],
+) -> Result<()> {
+    let mut metadata = RepoMetadata::load(storage, state)?;
+    metadata.record_push(
+        &state.refs,
+        pushed,
+        storage.pusher(),
+        push_certificate,
+        push_options,
+        &chrono::Utc::now().to_rfc3339(),
+    );
+    metadata.store(storage, state)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::storage::{FilesystemStorage, MutableState};
+
+    fn refs(names: &[&str]) -> BTreeMap<String, String> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), "a".repeat(40)))
+            .collect()
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let dir = tempdir().unwrap();
+        let storage = FilesystemStorage::new(dir.path()).unwrap();
+        storage.initialize().unwrap();
+
+        let mut state = State::default();
+        assert_eq!(
+            RepoMetadata::load(&storage, &state).unwrap(),
+            RepoMetadata::default()
+        );
+
+        let metadata = RepoMetadata {
+            description: Some("A test repository".to_string()),
+            default_branch: Some("refs/heads/main".to_string()),
+            created_at: Some("2025-01-01T00:00:00+00:00".to_string()),
+            last_push_at: Some("2025-01-02T00:00:00+00:00".to_string()),
+            pusher: Some("0xabc".to_string()),
+            push_certificate: Some(PushCertificate {
+                signer: "Test <test@test.com> (0123)".to_string(),
+                certificate: "certificate version 0.1\n".to_string(),
+            }),
+            push_options: vec!["ci.skip".to_string()],
+            history: vec![PushRecord {
+                at: "2025-01-02T00:00:00+00:00".to_string(),
+                pusher: Some("0xabc".to_string()),
+                refs: refs(&["refs/heads/main"]),
+                signer: Some("Test <test@test.com> (0123)".to_string()),
+                push_options: vec!["ci.skip".to_string()],
+            }],
+        };
+        metadata.store(&storage, &mut state).unwrap();
+        assert!(state.metadata.is_some());
+
+        assert_eq!(RepoMetadata::load(&storage, &state).unwrap(), metadata);
+    }
+
+    #[test]
+    fn test_record_push() {
+        let mut metadata = RepoMetadata {
+            description: Some("keep me".to_string()),
+            ..Default::default()
+        };
+
+        metadata.record_push(
+            &refs(&["refs/heads/feature", "refs/heads/main"]),
+            &refs(&["refs/heads/main"]),
+            Some("0x1".to_string()),
+            Some(PushCertificate {
+                signer: "Test <test@test.com> (0123)".to_string(),
+                certificate: "certificate version 0.1\n".to_string(),
+            }),
+            &["ci.skip".to_string()],
+            "t1",
+        );
+        assert_eq!(metadata.created_at.as_deref(), Some("t1"));
+        assert_eq!(metadata.last_push_at.as_deref(), Some("t1"));
+        assert_eq!(metadata.pusher.as_deref(), Some("0x1"));
+        assert!(metadata.push_certificate.is_some());
+        assert_eq!(metadata.push_options, ["ci.skip"]);
+        assert_eq!(metadata.default_branch.as_deref(), Some("refs/heads/main"));
+
+        // Later pushes keep created_at, the description and an explicit default branch
+        metadata.default_branch = Some("refs/heads/feature".to_string());
+        let feature = BTreeMap::from([("refs/heads/feature".to_string(), "b".repeat(40))]);
+        metadata.record_push(
+            &refs(&["refs/heads/feature", "refs/heads/main"]),
+            &feature,
+            None,
+            None,
+            &[],
+            "t2",
+        );
+        assert_eq!(metadata.created_at.as_deref(), Some("t1"));
+        assert_eq!(metadata.last_push_at.as_deref(), Some("t2"));
+        assert_eq!(metadata.pusher.as_deref(), Some("0x1"));
+        assert_eq!(metadata.description.as_deref(), Some("keep me"));
+        assert_eq!(metadata.push_certificate, None);
+        assert!(metadata.push_options.is_empty());
+        assert_eq!(
+            metadata.default_branch.as_deref(),
+            Some("refs/heads/feature")
+        );
+
+        // Each push keeps its own signer and options, though only the last push's certificate
+        // and options are kept at the top level
+        assert_eq!(metadata.history.len(), 2);
+        let signed = metadata
+            .push_of("refs/heads/main", &"a".repeat(40))
+            .unwrap();
+        assert_eq!(signed.at, "t1");
+        assert_eq!(
+            signed.signer.as_deref(),
+            Some("Test <test@test.com> (0123)")
+        );
+        let unsigned = metadata
+            .push_of("refs/heads/feature", &"b".repeat(40))
+            .unwrap();
+        assert_eq!(signed.push_options, ["ci.skip"]);
+        assert_eq!((unsigned.at.as_str(), &unsigned.signer), ("t2", &None));
+        assert!(unsigned.push_options.is_empty());
+        assert!(metadata
+            .push_of("refs/heads/feature", &"a".repeat(40))
+            .is_none());
+
+        // Only the most recent pushes are kept
+        for i in 0..MAX_PUSH_HISTORY {
+            metadata.record_push(&BTreeMap::new(), &feature, None, None, &[], &i.to_string());
+        }
+        assert_eq!(metadata.history.len(), MAX_PUSH_HISTORY);
+        assert_eq!(metadata.history[0].at, "0");
+        assert!(metadata
+            .push_of("refs/heads/main", &"a".repeat(40))
+            .is_none());
+    }
+
+    #[test]
+    fn test_record_push_updates_stored_metadata() {
+        let dir = tempdir().unwrap();
+        let storage = FilesystemStorage::new(dir.path()).unwrap();
+        storage.initialize().unwrap();
+
+        storage
+            .update_state(|state| {
+                state.refs = refs(&["refs/heads/master"]);
+                record_push(
+                    &storage,
+                    state,
+                    &refs(&["refs/heads/master"]),
+                    None,
+                    &["ci.skip".to_string()],
+                )
+            })
+            .unwrap();
+        storage
+            .update_state(|state| {
+                state.refs = refs(&["refs/heads/dev", "refs/heads/master"]);
+                record_push(
+                    &storage,
+                    state,
+                    &refs(&["refs/heads/dev"]),
+                    None,
+                    &[
+                        "notify=team".to_string(),
+                        "merge-request.create".to_string(),
+                    ],
+                )
+            })
+            .unwrap();
+
+        let state = storage.read_state().unwrap();
+        let metadata = RepoMetadata::load(&storage, &state).unwrap();
+        assert_eq!(
+            metadata.default_branch.as_deref(),
+            Some("refs/heads/master")
+        );
+        assert!(metadata.created_at.is_some());
+        assert!(metadata.created_at <= metadata.last_push_at);
+        assert_eq!(metadata.history.len(), 2);
+        assert_eq!(metadata.history[0].refs, refs(&["refs/heads/master"]));
+
+        // Both pushes' options survive the second push
+        assert_eq!(metadata.history[0].push_options, ["ci.skip"]);
+        assert_eq!(
+            metadata.history[1].push_options,
+            ["notify=team", "merge-request.create"]
+        );
+        assert_eq!(metadata.push_options, metadata.history[1].push_options);
+    }
+}
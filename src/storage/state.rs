@@ -1,5 +1,6 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, fmt, str::FromStr};
 
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 use super::ContentId;
@@ -19,5 +20,373 @@ pub struct State {
     #[serde(default)]
     pub objects: BTreeMap<String, ContentId>, // git_sha1 -> backend_content_id
 
-                                              // Removed import_marks and export_marks - not needed for pack format
+    /// Maps symbolic ref names (`HEAD` or `refs/...`) to the ref they point at
+    /// Kept apart from `refs`, which only ever holds SHA-1s
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub symrefs: BTreeMap<String, String>, // symref_name -> target ref_name
+
+    /// Content ID of the repository metadata blob, if one has been written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ContentId>,
+
+    /// Refs set aside on read because their name or value can't be advertised to git
+    /// (e.g. written by an older, buggy client); kept as stored until `fsck --fix` repairs them
+    #[serde(skip)]
+    pub invalid_refs: BTreeMap<String, String>,
+    // Removed import_marks and export_marks - not needed for pack format
+}
+
+/// What a push does with an object the objects map already has under a different content ID
+///
+/// Objects are immutable by SHA-1, so the first mapping is authoritative; a second one means a
+/// client re-sent the object or wrote different bytes for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MappingConflict {
+    /// Keep the existing mapping
+    #[default]
+    Keep,
+    /// Point the object at the new content ID
+    Replace,
+    /// Fail the push
+    Error,
+}
+
+impl FromStr for MappingConflict {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "replace" => Ok(Self::Replace),
+            "error" => Ok(Self::Error),
+            other => anyhow::bail!(
+                "invalid mapping conflict policy {:?} (expected 'keep', 'replace' or 'error')",
+                other
+            ),
+        }
+    }
+}
+
+/// Whether `value` is a git object ID: 40 (SHA-1) or 64 (SHA-256) lowercase hex digits
+pub fn is_object_id(value: &str) -> bool {
+    matches!(value.len(), 40 | 64)
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Whether `name` can be advertised on a `list` line: non-empty, without whitespace or control
+/// characters
+fn is_listable_ref_name(name: &str) -> bool {
+    !name.is_empty() && !name.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// How `fsck --fix` repairs an invalid ref
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefRepair {
+    /// Point the ref at the object ID its stored value spells with stray case or whitespace
+    Correct { name: String, git_sha1: String },
+    /// Remove the ref
+    Delete { name: String },
+}
+
+impl RefRepair {
+    /// The repair for invalid ref `name` stored as `value`
+    pub fn for_ref(name: &str, value: &str) -> Self {
+        let normalized = value.trim().to_ascii_lowercase();
+        if is_listable_ref_name(name) && is_object_id(&normalized) {
+            RefRepair::Correct {
+                name: name.to_string(),
+                git_sha1: normalized,
+            }
+        } else {
+            RefRepair::Delete {
+                name: name.to_string(),
+            }
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            RefRepair::Correct { name, .. } | RefRepair::Delete { name } => name,
+        }
+    }
+
+    /// Apply the repair to `state`, whose reads set the ref aside as invalid
+    pub fn apply(&self, state: &mut State) {
+        state.invalid_refs.remove(self.name());
+        if let RefRepair::Correct { name, git_sha1 } = self {
+            state.refs.insert(name.clone(), git_sha1.clone());
+        }
+    }
+}
+
+impl fmt::Display for RefRepair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefRepair::Correct { name, git_sha1 } => {
+                write!(f, "correct {:?} to {}", name, git_sha1)
+            }
+            RefRepair::Delete { name } => write!(f, "delete {:?}", name),
+        }
+    }
+}
+
+impl State {
+    /// Move refs whose name or value git can't be sent into `invalid_refs`, warning about each
+    ///
+    /// Backends call this on read, so `list` never advertises a value that would make git fail
+    /// for every user of the remote.
+    pub fn set_aside_invalid_refs(&mut self) {
+        let invalid: Vec<String> = self
+            .refs
+            .iter()
+            .filter(|(name, value)| !is_listable_ref_name(name) || !is_object_id(value))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in invalid {
+            let value = self.refs.remove(&name).unwrap_or_default();
+            tracing::warn!(
+                "git-remote-walrus: ignoring ref {:?}: {:?} is not a git object ID \
+                 (run `git-remote-walrus fsck --fix` to repair it)",
+                name,
+                truncate(&value)
+            );
+            self.invalid_refs.insert(name, value);
+        }
+    }
+
+    /// Put refs set aside by [`State::set_aside_invalid_refs`] back, for backends that write
+    /// the whole refs map, so only a repair removes them
+    pub fn restore_invalid_refs(&mut self) {
+        for (name, value) in std::mem::take(&mut self.invalid_refs) {
+            self.refs.entry(name).or_insert(value);
+        }
+    }
+
+    /// Add a push's object mappings (git SHA-1 -> content ID), resolving any that remap an
+    /// existing object by `on_conflict`; returns how many conflicted
+    ///
+    /// `stores(git_sha1, content_id)` tells whether an existing content ID reads back as the
+    /// object. A mapping that only stores the same object elsewhere (re-sent, or batched
+    /// differently) is no conflict, and the pushed content ID replaces it.
+    pub fn insert_objects<'a>(
+        &mut self,
+        mappings: impl IntoIterator<Item = &'a (String, ContentId)>,
+        on_conflict: MappingConflict,
+        mut stores: impl FnMut(&str, &str) -> bool,
+    ) -> Result<usize> {
+        let mut conflicts = 0;
+        for (git_sha1, content_id) in mappings {
+            match self.objects.get(git_sha1) {
+                Some(existing) if existing != content_id && stores(git_sha1, existing) => {
+                    self.objects.insert(git_sha1.clone(), content_id.clone());
+                }
+                Some(existing) if existing != content_id => {
+                    conflicts += 1;
+                    tracing::warn!(
+                        "object {} is already stored as {} but was pushed again as {} ({})",
+                        git_sha1,
+                        existing,
+                        content_id,
+                        match on_conflict {
+                            MappingConflict::Keep => "keeping the existing mapping",
+                            MappingConflict::Replace => "replacing the existing mapping",
+                            MappingConflict::Error => "refusing the push",
+                        }
+                    );
+                    match on_conflict {
+                        MappingConflict::Keep => {}
+                        MappingConflict::Replace => {
+                            self.objects.insert(git_sha1.clone(), content_id.clone());
+                        }
+                        MappingConflict::Error => anyhow::bail!(
+                            "object {} is already stored as {}, not {}; refusing to remap it \
+                             (set on_mapping_conflict to keep or replace to push anyway)",
+                            git_sha1,
+                            existing,
+                            content_id
+                        ),
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    self.objects.insert(git_sha1.clone(), content_id.clone());
+                }
+            }
+        }
+        Ok(conflicts)
+    }
+}
+
+/// At most the first 80 characters of `s`, for messages about oversized values
+fn truncate(s: &str) -> &str {
+    s.char_indices().nth(80).map_or(s, |(end, _)| &s[..end])
+}
+
+/// Difference between two objects maps, keyed by git SHA-1
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectsDiff {
+    /// Git SHA-1s only in the new map
+    pub added: Vec<String>,
+    /// Git SHA-1s only in the old map
+    pub removed: Vec<String>,
+    /// Git SHA-1s in both maps but pointing at a different content ID
+    pub remapped: Vec<String>,
+}
+
+impl ObjectsDiff {
+    /// Compute the diff from `old` to `new`
+    pub fn compute(old: &BTreeMap<String, ContentId>, new: &BTreeMap<String, ContentId>) -> Self {
+        let mut diff = Self::default();
+
+        for (git_sha1, content_id) in new {
+            match old.get(git_sha1) {
+                None => diff.added.push(git_sha1.clone()),
+                Some(old_id) if old_id != content_id => diff.remapped.push(git_sha1.clone()),
+                Some(_) => {}
+            }
+        }
+
+        diff.removed = old
+            .keys()
+            .filter(|git_sha1| !new.contains_key(*git_sha1))
+            .cloned()
+            .collect();
+
+        diff
+    }
+
+    /// True if both maps have identical entries
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.remapped.is_empty()
+    }
+}
+
+impl fmt::Display for ObjectsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "+{} objects, -{}", self.added.len(), self.removed.len())?;
+        if !self.remapped.is_empty() {
+            write!(f, ", ~{} remapped", self.remapped.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(entries: &[(&str, &str)]) -> BTreeMap<String, ContentId> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_objects_diff_added_and_removed() {
+        let old = map(&[("a", "1"), ("b", "2")]);
+        let new = map(&[("b", "2"), ("c", "3"), ("d", "4")]);
+
+        let diff = ObjectsDiff::compute(&old, &new);
+        assert_eq!(diff.added, vec!["c", "d"]);
+        assert_eq!(diff.removed, vec!["a"]);
+        assert!(diff.remapped.is_empty());
+        assert_eq!(diff.to_string(), "+2 objects, -1");
+    }
+
+    #[test]
+    fn test_objects_diff_remapped() {
+        let old = map(&[("a", "1")]);
+        let new = map(&[("a", "0xblob:0:10")]);
+
+        let diff = ObjectsDiff::compute(&old, &new);
+        assert!(diff.added.is_empty());
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.remapped, vec!["a"]);
+        assert_eq!(diff.to_string(), "+0 objects, -0, ~1 remapped");
+    }
+
+    #[test]
+    fn test_insert_objects_on_conflict() {
+        let existing = map(&[("a", "id1"), ("b", "id2")]);
+        let pushed: Vec<(String, ContentId)> = [("a", "id1"), ("b", "other"), ("c", "id3")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let state = || State {
+            objects: existing.clone(),
+            ..Default::default()
+        };
+
+        // id2 doesn't read back as b
+        let differs = |_: &str, _: &str| false;
+
+        let mut kept = state();
+        assert_eq!(
+            kept.insert_objects(&pushed, MappingConflict::Keep, differs)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            kept.objects,
+            map(&[("a", "id1"), ("b", "id2"), ("c", "id3")])
+        );
+
+        let mut replaced = state();
+        assert_eq!(
+            replaced
+                .insert_objects(&pushed, MappingConflict::Replace, differs)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            replaced.objects,
+            map(&[("a", "id1"), ("b", "other"), ("c", "id3")])
+        );
+
+        let err = state()
+            .insert_objects(&pushed, MappingConflict::Error, differs)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("already stored as id2, not other"), "{}", err);
+
+        // The same object stored again elsewhere is no conflict, whatever the setting, and
+        // the pushed mapping is kept
+        let mut checked = Vec::new();
+        let mut resent = state();
+        assert_eq!(
+            resent
+                .insert_objects(&pushed, MappingConflict::Error, |git_sha1, content_id| {
+                    checked.push((git_sha1.to_string(), content_id.to_string()));
+                    true
+                })
+                .unwrap(),
+            0
+        );
+        assert_eq!(checked, [("b".to_string(), "id2".to_string())]);
+        assert_eq!(
+            resent.objects,
+            map(&[("a", "id1"), ("b", "other"), ("c", "id3")])
+        );
+
+        assert_eq!(
+            "replace".parse::<MappingConflict>().unwrap(),
+            MappingConflict::Replace
+        );
+        assert!("overwrite".parse::<MappingConflict>().is_err());
+    }
+
+    #[test]
+    fn test_objects_diff_empty() {
+        let old = map(&[("a", "1")]);
+        assert!(ObjectsDiff::compute(&old, &old).is_empty());
+        assert!(ObjectsDiff::compute(&BTreeMap::new(), &BTreeMap::new()).is_empty());
+
+        let diff = ObjectsDiff::compute(&BTreeMap::new(), &old);
+        assert_eq!(diff.to_string(), "+1 objects, -0");
+    }
 }
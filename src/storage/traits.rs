@@ -1,6 +1,10 @@
+use std::fmt;
+
 use anyhow::Result;
+use sha1::{Digest, Sha1};
 
-use super::State;
+use super::{RefRepair, State};
+use crate::{config::Retention, pack::PrefetchStrategy, sui::RemotePolicy};
 
 /// Opaque content identifier returned by storage backend.
 /// Could be a SHA-256 hash, UUID, URI, or any backend-specific format.
@@ -18,6 +22,16 @@ pub trait ImmutableStore {
     #[allow(dead_code)]
     fn write_objects(&self, contents: &[&[u8]]) -> Result<Vec<ContentId>>;
 
+    /// Write multiple objects, storing newly uploaded ones for `epochs` epochs
+    /// (None uses the backend's default). Backends whose objects don't expire ignore it.
+    fn write_objects_with_epochs(
+        &self,
+        contents: &[&[u8]],
+        _epochs: Option<u32>,
+    ) -> Result<Vec<ContentId>> {
+        self.write_objects(contents)
+    }
+
     /// Read object by content identifier into memory.
     /// Returns error if object doesn't exist.
     fn read_object(&self, id: &str) -> Result<Vec<u8>>;
@@ -28,6 +42,17 @@ pub trait ImmutableStore {
     #[allow(dead_code)]
     fn read_objects(&self, ids: &[&str]) -> Result<Vec<Vec<u8>>>;
 
+    /// Read git objects given as (git SHA-1, content identifier) pairs.
+    /// Each object is checked against its git SHA-1; returns error on mismatch.
+    fn read_git_objects(&self, objects: &[(&str, &str)]) -> Result<Vec<Vec<u8>>> {
+        let ids: Vec<&str> = objects.iter().map(|(_, id)| *id).collect();
+        let contents = self.read_objects(&ids)?;
+        for ((git_sha1, _), content) in objects.iter().zip(&contents) {
+            verify_git_object(git_sha1, content)?;
+        }
+        Ok(contents)
+    }
+
     /// Delete object by content identifier.
     /// Returns Ok(()) even if object didn't exist.
     #[allow(dead_code)]
@@ -36,8 +61,67 @@ pub trait ImmutableStore {
     /// Check if object exists by identifier.
     #[allow(dead_code)]
     fn object_exists(&self, id: &str) -> Result<bool>;
+
+    /// Where this process last read `id` from, for backends that keep a local cache
+    fn read_source(&self, _id: &str) -> Option<ReadSource> {
+        None
+    }
+
+    /// Record objects the remote already stores, as (loose content, content identifier) pairs,
+    /// so later writes and reads of them stay local. Backends without a local cache ignore it.
+    fn cache_stored_objects(&self, _objects: &[(&[u8], &str)]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Where a read object's bytes came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadSource {
+    /// Encoded in the ContentId itself
+    Constant,
+    /// The local object cache
+    Cache,
+    /// Downloaded from Walrus
+    Walrus,
+}
+
+impl fmt::Display for ReadSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadSource::Constant => write!(f, "built in"),
+            ReadSource::Cache => write!(f, "read from the local cache"),
+            ReadSource::Walrus => write!(f, "downloaded from Walrus"),
+        }
+    }
 }
 
+/// Check that loose-format object content (`"<type> <size>\0<data>"`) hashes to `git_sha1`
+pub fn verify_git_object(git_sha1: &str, content: &[u8]) -> Result<()> {
+    let actual = hex::encode(Sha1::digest(content));
+    if actual != git_sha1 {
+        anyhow::bail!(
+            "Object checksum mismatch: expected {} but content hashes to {}",
+            git_sha1,
+            actual
+        );
+    }
+    Ok(())
+}
+
+/// A state write refused because another writer changed the remote state since it was read
+///
+/// Nothing was written: reading the state again and redoing the update can succeed.
+#[derive(Debug)]
+pub struct StateChanged;
+
+impl fmt::Display for StateChanged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the remote state changed since it was read")
+    }
+}
+
+impl std::error::Error for StateChanged {}
+
 /// Trait for mutable state management
 pub trait MutableState {
     /// Read the current state.
@@ -59,4 +143,47 @@ pub trait MutableState {
 pub trait StorageBackend: ImmutableStore + MutableState {
     /// Initialize storage (create directories, verify access, etc.)
     fn initialize(&self) -> Result<()>;
+
+    /// Identity recorded as the pusher in repository metadata, if the backend has one
+    fn pusher(&self) -> Option<String> {
+        None
+    }
+
+    /// Retention for objects a push introduces through `refname`, if objects expire
+    fn retention_for(&self, _refname: &str) -> Option<Retention> {
+        None
+    }
+
+    /// Limits on the refs and objects the remote may hold (unlimited by default)
+    fn policy(&self) -> Result<RemotePolicy> {
+        Ok(RemotePolicy::default())
+    }
+
+    /// Health banner to log while listing `state`'s refs, if one is enabled and due
+    fn list_banner(&self, _state: &State) -> Option<String> {
+        None
+    }
+
+    /// Order in which fetches read objects (backends without downloads keep the walk's order)
+    fn prefetch_strategy(&self) -> PrefetchStrategy {
+        PrefetchStrategy::None
+    }
+
+    /// Forget what earlier sessions read, before a long-lived process serves another one
+    fn begin_session(&self) {}
+
+    /// Whether one state write updates all of a push's refs or none of them
+    fn atomic_ref_updates(&self) -> bool {
+        false
+    }
+
+    /// Correct or delete refs that reads set aside as invalid
+    fn repair_refs(&self, repairs: &[RefRepair]) -> Result<()> {
+        self.update_state(|state| {
+            for repair in repairs {
+                repair.apply(state);
+            }
+            Ok(())
+        })
+    }
 }
@@ -1,28 +1,156 @@
-use std::{cell::RefCell, collections::BTreeMap, path::PathBuf};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
 use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use sha1::Sha1;
 use sha2::{Digest, Sha256};
 
 use super::{
-    traits::{ContentId, ImmutableStore, MutableState, StorageBackend},
+    blob_count,
+    blob_cache::BlobCache,
+    canonical::{is_canonical, to_canonical},
+    consolidate,
+    metadata::METADATA_KEY,
+    rewrite_consolidated,
+    rewrite_objects,
+    traits::{
+        verify_git_object,
+        ContentId,
+        ImmutableStore,
+        MutableState,
+        ReadSource,
+        StateChanged,
+        StorageBackend,
+    },
+    BlobManifest,
     CacheIndex,
+    ContentKind,
     FilesystemStorage,
+    FormatHeader,
+    ObjectsDiff,
     ParsedContentId,
+    RefRepair,
+    Repack,
+    SiblingIndexes,
     State,
+    CACHE_INDEX_FILE,
+    FORMAT_KEY,
+    REMOTES_DIR,
 };
 use crate::{
-    config::WalrusRemoteConfig,
-    sui::SuiClient,
-    walrus::{BlobTracker, WalrusClient, WalrusNetworkInfo},
+    commands::{
+        capabilities::private_name,
+        namespace::{check_ref_name, check_symref_name},
+    },
+    config::{Retention, WalrusRemoteConfig},
+    pack::{
+        send::{collect_reachable_objects, walk_reachable_objects, Walked},
+        PrefetchStrategy,
+    },
+    session_env,
+    subprocess::CommandRunner,
+    sui::{dead_refs, DeadRef, RefHistoryCache, RefUpdate, RemotePolicy, SuiClient},
+    walrus::{
+        describe_expiry,
+        estimate_expiry,
+        format_amount,
+        Banner,
+        BannerLog,
+        BlobPersistence,
+        BlobTracker,
+        CostEstimate,
+        EpochInfo,
+        ExpiryReport,
+        RefImpact,
+        RefImpactCache,
+        RenewalPass,
+        Repacked,
+        StoredBlob,
+        TopUp,
+        TrackedBlob,
+        UploadPlan,
+        WalletBalances,
+        WalrusClient,
+        WalrusEpochProvider,
+        WalrusNetworkInfo,
+        FAUCET_MAX_ATTEMPTS,
+        MIST_PER_SUI,
+    },
 };
 
+/// How long a faucet request may take
+const FAUCET_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Batches uploaded per `walrus store` invocation
+const BATCHES_PER_STORE: usize = 8;
+
+/// Objects `consolidate_blobs` reads at a time
+const CONSOLIDATE_READ_BATCH: usize = 1000;
+
+/// Reserved objects-map key prefix under which development builds stored symrefs (`symref:HEAD`
+/// maps to the target ref); still read, but symrefs now live in the RemoteState's `symrefs`
+/// field, where older readers don't trip over them
+const SYMREF_KEY_PREFIX: &str = "symref:";
+
+/// Key prefix of a chunked objects map's manifest entries: `part:{n}` maps to the
+/// `{blob_object_id}:{blob_id}:{length}` of the map's n-th slice
+const PART_KEY_PREFIX: &str = "part:";
+
+/// A slice of an objects map too large for one blob, as listed by its manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ObjectsMapPart {
+    object_id: String,
+    blob_id: String,
+    length: u64,
+}
+
+/// Outcome of reclaiming unreferenced deletable blobs
+#[derive(Debug, Default)]
+pub struct ReclaimReport {
+    /// Object IDs of the deleted blobs
+    pub deleted: Vec<String>,
+    /// Total size of the deleted blobs, where known
+    pub bytes: u64,
+}
+
+/// Outcome of compacting the on-chain refs table
+#[derive(Debug, Default)]
+pub struct RefCompaction {
+    /// Entries removed from the table
+    pub removed: Vec<DeadRef>,
+    /// Storage rebate net of the storage the transactions paid for, in MIST
+    pub storage_rebate_mist: u64,
+}
+
+/// Outcome of consolidating a remote's objects into fewer blobs
+#[derive(Debug, Default)]
+pub struct Consolidation {
+    /// Distinct blobs the objects map read from before
+    pub blobs_before: usize,
+    /// Distinct blobs the objects map reads from now
+    pub blobs_after: usize,
+    /// Objects now read from a consolidated blob
+    pub moved: usize,
+    /// Bytes uploaded in the consolidated blobs
+    pub bytes: u64,
+}
+
 /// Storage backend using Walrus for immutable objects and Sui for mutable state
 ///
 /// Architecture:
 /// - Git objects -> Walrus blobs (with local filesystem cache)
 /// - Git refs -> Sui on-chain (RemoteState.refs table)
-/// - Objects map -> Walrus blob (RemoteState.objects_blob_object_id points to it)
+/// - Objects map -> Walrus blob (RemoteState.objects_blob_object_id points to it, or to a
+///   manifest listing the parts of a map larger than the network's maximum blob size)
 /// - Lock -> Sui on-chain (RemoteState.lock)
 pub struct WalrusStorage {
     /// Configuration
@@ -31,9 +159,15 @@ pub struct WalrusStorage {
     /// Sui object ID for RemoteState
     state_object_id: String,
 
+    /// Git remote name this storage serves (the URL for URL-only remotes)
+    remote_name: String,
+
     /// Local filesystem cache
     cache: FilesystemStorage,
 
+    /// Whole blobs read from Walrus, shared by every ContentId slicing them
+    blob_cache: BlobCache,
+
     /// Walrus client for blob operations
     walrus_client: WalrusClient,
 
@@ -55,18 +189,57 @@ pub struct WalrusStorage {
     /// Cached network info
     network_info: RefCell<Option<WalrusNetworkInfo>>,
 
+    /// Current Walrus epoch, read once per command
+    epochs: WalrusEpochProvider,
+
     /// Cached state to avoid redundant reads during single operation
     /// (e.g., list followed by fetch both need state)
     cached_state: RefCell<Option<State>>,
+
+    /// Objects of the cached state grouped by the blob holding them (built on first download)
+    blob_members: RefCell<Option<BlobMembers>>,
+
+    /// Format header of the objects map last read, to warn when a write locks its writer out
+    last_format: RefCell<Option<FormatHeader>>,
+
+    /// Blob object IDs of the parts of the objects map last read, if it was chunked
+    objects_map_parts: RefCell<Vec<String>>,
+
+    /// SharedBlob object ID and sha256 of the objects map last read, if it was canonical, so
+    /// a write of the same map points at it again instead of uploading a copy
+    last_objects_map: RefCell<Option<(String, String)>>,
+
+    /// Refs, symrefs and objects map object ID the cached state was read from, to refuse a write
+    /// over what another push wrote since
+    read_from: RefCell<Option<StateSource>>,
+
+    /// Where each object read by this process came from, to explain a bad object
+    read_sources: RefCell<HashMap<ContentId, ReadSource>>,
+}
+
+/// Directory under `cache_dir` of the files only `remote_name` uses
+fn remote_cache_dir(cache_dir: &Path, remote_name: &str) -> PathBuf {
+    cache_dir.join(REMOTES_DIR).join(private_name(remote_name))
 }
 
+/// Refs, symrefs (None if the RemoteState has no `symrefs` field) and objects map object ID of
+/// the RemoteState
+type StateSource = (
+    BTreeMap<String, String>,
+    Option<BTreeMap<String, String>>,
+    Option<String>,
+);
+
+/// Git SHA-1 and ContentId of each stored object, keyed by the blob object ID holding it
+type BlobMembers = HashMap<String, Vec<(String, ContentId)>>;
+
 impl WalrusStorage {
     /// Create a new WalrusStorage instance
-    pub fn new(state_object_id: String) -> Result<Self> {
-        // Load configuration
-        let walrus_remote_config =
-            WalrusRemoteConfig::load().context("Failed to load configuration")?;
-
+    pub fn new(
+        state_object_id: String,
+        remote_name: String,
+        walrus_remote_config: WalrusRemoteConfig,
+    ) -> Result<Self> {
         // Ensure cache directory exists
         let cache_dir = walrus_remote_config.ensure_cache_dir()?;
 
@@ -77,26 +250,60 @@ impl WalrusStorage {
         let walrus_client = WalrusClient::new(
             walrus_remote_config.walrus_config_path.clone(),
             walrus_remote_config.default_epochs,
-        );
+        )
+        .with_binary(walrus_remote_config.walrus_binary.clone())
+        .with_persistence(walrus_remote_config.blob_persistence)
+        .with_encoding(walrus_remote_config.walrus_encoding.clone())
+        .with_proxy(walrus_remote_config.proxy_settings())
+        .with_idle_timeout(walrus_remote_config.network_timeouts().idle);
 
         // Create tokio runtime for async operations
         let runtime = tokio::runtime::Runtime::new().context("Failed to create tokio runtime")?;
 
         // Create Sui client (need to block on async constructor)
-        let sui_client = runtime.block_on(SuiClient::new(
-            state_object_id.clone(),
-            walrus_remote_config.sui_wallet_path.clone(),
-        ))?;
+        let sui_client = runtime
+            .block_on(SuiClient::new(
+                state_object_id.clone(),
+                walrus_remote_config.sui_wallet_path.clone(),
+                walrus_remote_config.network_timeouts(),
+            ))?
+            .with_gas_reserve(walrus_remote_config.gas_reserve_mist)
+            .with_clock_object_id(&walrus_remote_config.clock_object_id)?;
+
+        // The Sui SDK's JSON-RPC client has no proxy support; say so rather than hang silently
+        if let Some(rpc_url) = sui_client.rpc_url() {
+            if let Some(proxy) = walrus_remote_config.proxy_settings().for_url(rpc_url) {
+                tracing::warn!(
+                    "Proxy {} is configured, but Sui RPC requests to {} are sent directly \
+                     (the Sui SDK client cannot use a proxy); add the host to NO_PROXY to \
+                     silence this warning",
+                    proxy,
+                    rpc_url
+                );
+            }
+        }
 
-        // Set up paths
-        let cache_index_path = cache_dir.join("cache_index.yaml");
-        let blob_tracker_path = cache_dir.join("blob_tracker.yaml");
-        let network_info_path = cache_dir.join("network_info.yaml");
+        // Objects are cached once for every remote, but each keeps its own index of them and
+        // of its blobs, so remotes on other networks or sharing a cache dir don't clash
+        let remote_dir = remote_cache_dir(&cache_dir, &remote_name);
+        let cache_index_path = remote_dir.join(CACHE_INDEX_FILE);
+        let legacy_index_path = cache_dir.join(CACHE_INDEX_FILE);
+        if !cache_index_path.exists() && legacy_index_path.exists() {
+            // Indexes from before the split were shared; each remote starts from a copy
+            fs::create_dir_all(&remote_dir)
+                .and_then(|()| fs::copy(&legacy_index_path, &cache_index_path))
+                .with_context(|| format!("Failed to copy cache index to {:?}", remote_dir))?;
+        }
+        let blob_tracker_path = remote_dir.join("blob_tracker.yaml");
+        let network_info_path = remote_dir.join("network_info.yaml");
+        let blob_cache = BlobCache::new(&cache_dir, walrus_remote_config.max_blob_cache_bytes);
 
         Ok(Self {
             config: walrus_remote_config,
             state_object_id,
+            remote_name,
             cache,
+            blob_cache,
             walrus_client,
             sui_client,
             runtime,
@@ -104,410 +311,561 @@ impl WalrusStorage {
             blob_tracker_path,
             network_info_path,
             network_info: RefCell::new(None),
+            epochs: WalrusEpochProvider::default(),
             cached_state: RefCell::new(None),
+            blob_members: RefCell::new(None),
+            last_format: RefCell::new(None),
+            objects_map_parts: RefCell::new(Vec::new()),
+            last_objects_map: RefCell::new(None),
+            read_from: RefCell::new(None),
+            read_sources: RefCell::new(HashMap::new()),
         })
     }
 
-    /// Compute SHA-256 hash of content
-    fn compute_sha256(content: &[u8]) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(content);
-        hex::encode(hasher.finalize())
-    }
-
-    /// Load cache index
-    fn load_cache_index(&self) -> Result<CacheIndex> {
-        CacheIndex::load(&self.cache_index_path).context("Failed to load cache index")
-    }
-
-    /// Save cache index
-    fn save_cache_index(&self, index: &CacheIndex) -> Result<()> {
-        index
-            .save(&self.cache_index_path)
-            .context("Failed to save cache index")
+    /// Refuse to spend on mainnet unless the config opts in, and on a pinned historical state
+    fn ensure_spending_allowed(&self) -> Result<()> {
+        if let Some(digest) = &self.config.pinned_transaction {
+            anyhow::bail!(
+                "remote {:?} is pinned to transaction {}; write through the URL without @{}",
+                self.remote_name,
+                digest,
+                digest
+            );
+        }
+        crate::sui::ensure_spending_allowed(self.sui_client.network(), self.config.allow_mainnet)
+            .with_context(|| format!("remote {:?}", self.remote_name))
     }
 
-    /// Load blob tracker
-    fn load_blob_tracker(&self) -> Result<BlobTracker> {
-        BlobTracker::load(&self.blob_tracker_path).context("Failed to load blob tracker")
-    }
+    /// Estimate the cost of storing `blob_sizes` and updating `refs` refs, and fail fast if
+    /// the wallet can't cover it (before anything is uploaded or locked)
+    fn preflight(&self, blob_sizes: &[u64], epochs: u32, refs: usize) -> Result<()> {
+        if self.config.skip_preflight {
+            return Ok(());
+        }
 
-    /// Save blob tracker
-    fn save_blob_tracker(&self, tracker: &BlobTracker) -> Result<()> {
-        tracker
-            .save(&self.blob_tracker_path)
-            .context("Failed to save blob tracker")
-    }
+        let network_info = self.get_network_info()?;
+        let Some(prices) = &network_info.price_info else {
+            tracing::warn!(
+                "Walrus did not report storage prices; skipping pre-flight balance check"
+            );
+            return Ok(());
+        };
 
-    /// Get network info (lazy-loaded and cached)
-    fn get_network_info(&self) -> Result<WalrusNetworkInfo> {
-        // Check if we have cached network info
-        if let Some(cached) = self.network_info.borrow().as_ref() {
-            return Ok(cached.clone());
-        }
+        let plan = UploadPlan {
+            blob_sizes: blob_sizes.to_vec(),
+            epochs,
+            refs,
+        };
+        let estimate =
+            CostEstimate::estimate(&plan, prices, network_info.size_info.storage_unit_size);
 
-        // Try to load from file
-        let network_info = if let Some(info) = WalrusNetworkInfo::load(&self.network_info_path)? {
-            tracing::debug!("Loaded network info from cache");
-            info
-        } else {
-            // Query from Walrus CLI
-            tracing::info!("Querying Walrus network info...");
-            let info = WalrusNetworkInfo::query(self.config.walrus_config_path.as_ref())
-                .context("Failed to query Walrus network info")?;
+        let wal_coin_type = self.config.wal_coin_type_on(self.sui_client.network());
 
-            // Save for future use
-            info.save(&self.network_info_path)
-                .context("Failed to save network info")?;
+        let mut attempts = 0;
+        loop {
+            let balances = self
+                .runtime
+                .block_on(async {
+                    let sui_mist = self.sui_client.balance(None).await?;
+                    let wal_frost = match &wal_coin_type {
+                        Some(coin_type) => {
+                            Some(self.sui_client.balance(Some(coin_type.clone())).await?)
+                        }
+                        None => None,
+                    };
+                    anyhow::Ok(WalletBalances {
+                        address: self.sui_client.sender().to_string(),
+                        sui_mist,
+                        wal_frost,
+                    })
+                })
+                .context(
+                    "Pre-flight balance check failed (set WALRUS_REMOTE_SKIP_PREFLIGHT=1 to bypass)",
+                )?;
 
-            tracing::info!(
-                "Network limits: max_blob_size={} bytes ({:.2} MB)",
-                info.max_blob_size(),
-                info.max_blob_size() as f64 / (1024.0 * 1024.0)
+            tracing::debug!(
+                "Pre-flight estimate: {:?}, balances: {:?}",
+                estimate,
+                balances
             );
+            let Err(shortfall) = estimate.check(&balances) else {
+                return Ok(());
+            };
+            if !self.config.auto_faucet {
+                return Err(shortfall);
+            }
+            if attempts == FAUCET_MAX_ATTEMPTS {
+                return Err(shortfall.context(format!(
+                    "still short after {} auto_faucet attempt(s)",
+                    attempts
+                )));
+            }
 
-            info
-        };
+            attempts += 1;
+            for top_up in estimate.top_ups(&balances, self.sui_client.network())? {
+                self.request_faucet(top_up, &balances.address)?;
+            }
+        }
+    }
 
-        // Cache it
-        *self.network_info.borrow_mut() = Some(network_info.clone());
+    /// Top up the wallet from the network's faucet
+    fn request_faucet(&self, top_up: TopUp, address: &str) -> Result<()> {
+        match top_up {
+            TopUp::Sui => {
+                tracing::info!("Requesting SUI from the faucet for {}", address);
+                CommandRunner::new("sui")
+                    .arg("client")
+                    .arg("--client.config")
+                    .arg(&self.config.sui_wallet_path)
+                    .arg("faucet")
+                    .timeout(FAUCET_TIMEOUT)
+                    .run()
+                    .context("sui client faucet failed")?;
+            }
+            TopUp::Wal { mist } => {
+                tracing::info!(
+                    "Exchanging {} SUI for WAL for {}",
+                    format_amount(mist, MIST_PER_SUI),
+                    address
+                );
+                self.walrus_client.get_wal(mist)?;
+            }
+        }
+        Ok(())
+    }
 
-        Ok(network_info)
+    /// Check the configured walrus encoding (if any) against what the network supports
+    fn ensure_encoding_supported(&self) -> Result<()> {
+        match self.walrus_client.encoding() {
+            Some(encoding) => self.get_network_info()?.check_encoding(encoding),
+            None => Ok(()),
+        }
     }
 
-    /// Get the actual maximum blob size for this Walrus network
-    fn get_max_blob_size(&self) -> Result<u64> {
-        let network_info = self.get_network_info()?;
-        Ok(network_info.max_blob_size())
+    /// Build tracker info for a blob uploaded by this remote
+    fn uploaded_blob(
+        &self,
+        object_id: String,
+        blob_id: String,
+        end_epoch: u64,
+        size: u64,
+        retention_epochs: Option<u32>,
+    ) -> TrackedBlob {
+        TrackedBlob {
+            object_id,
+            blob_id,
+            end_epoch,
+            size: Some(size),
+            persistence: self.walrus_client.persistence(),
+            remote: Some(self.state_object_id.clone()),
+            retention_epochs,
+        }
     }
 
-    /// Extract unique blob_object_ids from ContentIds (handles batched format)
-    fn extract_blob_object_ids(content_ids: &[&str]) -> Vec<String> {
-        use std::collections::HashSet;
+    /// Compute SHA-256 hash of content
+    fn compute_sha256(content: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(content);
+        hex::encode(hasher.finalize())
+    }
 
-        let mut blob_ids: HashSet<String> = HashSet::new();
+    /// Split `contents` into ContentIds already in the cache index and (index, content, sha256)
+    /// entries that still need uploading. Stale index entries count as misses.
+    #[allow(clippy::type_complexity)]
+    fn partition_cached<'a>(
+        cache_index: &CacheIndex,
+        contents: &[&'a [u8]],
+    ) -> (Vec<Option<ContentId>>, Vec<(usize, &'a [u8], String)>) {
+        let mut cached: Vec<Option<ContentId>> = vec![None; contents.len()];
+        let mut to_upload = Vec::new();
 
-        for content_id in content_ids {
-            if let Ok(parsed) = ParsedContentId::parse(content_id) {
-                blob_ids.insert(parsed.blob_object_id().to_string());
+        for (i, content) in contents.iter().enumerate() {
+            // Built-in objects (the empty blob, tree and content) are never uploaded
+            if let Some(constant) = ParsedContentId::constant(content) {
+                cached[i] = Some(constant.encode());
+                continue;
+            }
+
+            let sha256 = Self::compute_sha256(content);
+            if let Some(existing_content_id) =
+                cache_index.get_object_id(&sha256, ContentKind::RawLoose)
+            {
+                tracing::debug!("Object {}... already cached", &sha256[..8]);
+                cached[i] = Some(existing_content_id.clone());
+            } else {
+                to_upload.push((i, *content, sha256));
             }
         }
 
-        blob_ids.into_iter().collect()
+        (cached, to_upload)
     }
 
-    /// Rehydrate blob_tracker from objects map (lazy discovery)
-    /// This allows any client to discover blob expiration info from on-chain state
-    fn rehydrate_blob_tracker(&self, objects: &BTreeMap<String, ContentId>) -> Result<()> {
-        if objects.is_empty() {
-            return Ok(());
+    /// Parse an objects map blob, refusing oversized documents and malformed entries
+    ///
+    /// The map is written by any allowlisted collaborator, so nothing in it is trusted: each
+    /// key must be a git SHA (or the metadata key) and each value a well-formed ContentId;
+    /// symref entries must name valid refs.
+    fn parse_objects_map(yaml: &[u8], max_bytes: u64) -> Result<BTreeMap<String, ContentId>> {
+        if yaml.len() as u64 > max_bytes {
+            anyhow::bail!(
+                "Objects map is {} bytes, over the {} byte limit (max_objects_map_bytes)",
+                yaml.len(),
+                max_bytes
+            );
         }
 
-        // Extract all unique blob_object_ids from the objects map
-        let content_ids: Vec<&str> = objects.values().map(|s| s.as_str()).collect();
-        let blob_object_ids = Self::extract_blob_object_ids(&content_ids);
+        // libyaml reads the whole event stream before serde's recursion limit applies, so
+        // refuse nesting, anchors and tags up front rather than after minutes of parsing
+        Self::check_flat_yaml(yaml)?;
+        let objects: BTreeMap<String, ContentId> =
+            serde_yaml::from_slice(yaml).context("Failed to parse objects map YAML")?;
+
+        // A map written with features this version lacks is refused before its entries fail
+        // validation in less helpful ways (the header is on-chain, but development builds
+        // wrote it into the map)
+        if let Some(header) = objects.get(FORMAT_KEY) {
+            FormatHeader::parse(header)?.check_readable()?;
+        }
 
-        if blob_object_ids.is_empty() {
-            return Ok(());
+        for (key, content_id) in &objects {
+            Self::validate_objects_entry(key, content_id).with_context(|| {
+                format!(
+                    "Invalid objects map entry {:?}: {:?}",
+                    truncate(key),
+                    truncate(content_id)
+                )
+            })?;
         }
 
-        tracing::info!(
-            "  Rehydrating blob tracker from {} unique blob(s)...",
-            blob_object_ids.len()
-        );
+        Ok(objects)
+    }
 
-        // Load current tracker to check what we already have
-        let mut tracker = self.load_blob_tracker()?;
+    /// Check that `yaml` outside quoted scalars has no flow collections, anchors, aliases,
+    /// tags or block scalars (an objects map is a flat mapping of plain or quoted strings)
+    fn check_flat_yaml(yaml: &[u8]) -> Result<()> {
+        let mut quote = None;
+        let mut escaped = false;
+        for (i, &b) in yaml.iter().enumerate() {
+            match quote {
+                Some(b'"') if escaped => escaped = false,
+                Some(b'"') if b == b'\\' => escaped = true,
+                Some(q) if b == q => quote = None,
+                Some(_) => {}
+                None if b == b'"' || b == b'\'' => quote = Some(b),
+                None if b"[]{}&*!|>".contains(&b) => anyhow::bail!(
+                    "Objects map is not a flat mapping (unexpected {:?} at byte {})",
+                    b as char,
+                    i
+                ),
+                None => {}
+            }
+        }
+        Ok(())
+    }
 
-        // Filter to only blob_object_ids we don't already have
-        let blobs_to_query: Vec<String> = blob_object_ids
-            .into_iter()
-            .filter(|blob_id| tracker.get_blob(blob_id).is_none())
-            .collect();
+    /// Check one objects map entry: a git SHA (or the metadata key) mapped to a ContentId
+    fn validate_objects_entry(key: &str, content_id: &str) -> Result<()> {
+        let is_hex = |s: &str| {
+            s.bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        };
 
-        if blobs_to_query.is_empty() {
-            tracing::debug!("  All blobs already tracked");
+        if let Some(name) = key.strip_prefix(SYMREF_KEY_PREFIX) {
+            check_symref_name(name)?;
+            return check_ref_name(content_id);
+        }
+        if key == FORMAT_KEY {
+            // Checked before any entry
             return Ok(());
         }
+        if key != METADATA_KEY && !(matches!(key.len(), 40 | 64) && is_hex(key)) {
+            anyhow::bail!("key is not a git object ID");
+        }
 
-        tracing::info!("  Querying Sui for {} new blob(s)...", blobs_to_query.len());
-
-        // Create progress bar for batch queries
-        let pb = if blobs_to_query.len() > 10 {
-            let bar = ProgressBar::new(blobs_to_query.len() as u64);
-            bar.set_style(
-                ProgressStyle::default_bar()
-                    .template("  {msg} [{bar:40.cyan/blue}] {pos}/{len} blobs ({eta})")
-                    .expect("Failed to create progress template")
-                    .progress_chars("█▓░"),
-            );
-            bar.set_message("Querying blob statuses");
-            Some(bar)
-        } else {
-            None
-        };
-
-        // Batch query Sui for all blob statuses with progress tracking
-        let results = {
-            let pb_clone = pb.clone();
-            self.runtime
-                .block_on(self.sui_client.get_shared_blob_statuses_batch(
-                    &blobs_to_query,
-                    Some(move |count| {
-                        if let Some(ref bar) = pb_clone {
-                            bar.inc(count as u64);
-                        }
-                    }),
-                ))?
+        let parsed = ParsedContentId::parse(content_id)?;
+        let Some(blob_object_id) = parsed.blob_object_id() else {
+            // Constant
+            return Ok(());
         };
-
-        // Finish progress bar
-        if let Some(ref bar) = pb {
-            bar.finish_with_message("Blob query complete");
+        if !is_sui_object_id(blob_object_id) {
+            anyhow::bail!("{:?} is not a Sui object ID", truncate(blob_object_id));
         }
-
-        // Process results
-        let mut discovered_count = 0;
-        let mut failed_count = 0;
-
-        for (i, result) in results.into_iter().enumerate() {
-            match result {
-                Ok(status) => {
-                    tracker.track_blob(
-                        status.object_id,
-                        status.blob_id,
-                        status.end_epoch,
-                        None, // We don't know size from just object ID
-                    );
-                    discovered_count += 1;
-                }
-                Err(e) => {
-                    let blob_id = &blobs_to_query[i];
-                    tracing::debug!(
-                        "Could not get blob status for {}: {}",
-                        &blob_id[..std::cmp::min(blob_id.len(), 16)],
-                        e
-                    );
-                    failed_count += 1;
-                }
+        if let ParsedContentId::Batched { offset, length, .. } = parsed {
+            if length == 0 || offset.checked_add(length).is_none() {
+                anyhow::bail!("invalid byte range {}+{}", offset, length);
             }
         }
 
-        if discovered_count > 0 {
-            tracing::info!("  Discovered {} new blob(s) for tracking", discovered_count);
-            if failed_count > 0 {
-                tracing::debug!("  Failed to query {} blob(s)", failed_count);
+        Ok(())
+    }
+
+    /// Check an objects map about to be written, so a bug elsewhere fails the push instead of
+    /// storing a map no reader accepts: every entry as [`Self::parse_objects_map`] checks it,
+    /// every git object ID the same length (one object format per repository), and no
+    /// symref keys (releases before 0.2.0 reject them)
+    fn validate_objects_map(objects_map: &BTreeMap<String, ContentId>) -> Result<()> {
+        let mut id_len = None;
+        for (key, content_id) in objects_map {
+            if key.starts_with(SYMREF_KEY_PREFIX) {
+                anyhow::bail!(
+                    "Symref entry {:?} belongs in the RemoteState's symrefs field",
+                    truncate(key)
+                );
+            }
+            Self::validate_objects_entry(key, content_id).with_context(|| {
+                format!(
+                    "Invalid objects map entry {:?}: {:?}",
+                    truncate(key),
+                    truncate(content_id)
+                )
+            })?;
+            if key == METADATA_KEY || key == FORMAT_KEY {
+                continue;
+            }
+            match id_len {
+                None => id_len = Some(key.len()),
+                Some(len) if len != key.len() => anyhow::bail!(
+                    "Objects map mixes {}- and {}-character object IDs (at {:?})",
+                    len,
+                    key.len(),
+                    truncate(key)
+                ),
+                Some(_) => {}
             }
-            self.save_blob_tracker(&tracker)?;
         }
-
         Ok(())
     }
 
-    /// Check for blob expiration warnings and emit to stderr
-    /// If `relevant_blob_ids` is provided, only check those specific blobs
-    fn check_blob_expiration(&self, relevant_blob_ids: Option<&Vec<String>>) -> Result<()> {
-        tracing::debug!("Checking blob expiration...");
-        let tracker = self.load_blob_tracker()?;
+    /// Store a serialized objects map, returning the blob whose object ID goes on-chain and
+    /// every blob stored, with its size
+    ///
+    /// A map over `max_blob_size` is split into parts of at most that size, and the blob
+    /// returned is a manifest listing them.
+    fn store_objects_map(
+        walrus_client: &WalrusClient,
+        yaml: &[u8],
+        max_blob_size: u64,
+    ) -> Result<(StoredBlob, Vec<(StoredBlob, u64)>)> {
+        if yaml.len() as u64 <= max_blob_size {
+            let blob = walrus_client.store(yaml)?;
+            return Ok((blob.clone(), vec![(blob, yaml.len() as u64)]));
+        }
 
-        if tracker.count() == 0 {
-            return Ok(());
+        let chunks: Vec<&[u8]> = yaml.chunks(max_blob_size as usize).collect();
+        tracing::info!(
+            "  Objects map is over the {} byte blob limit; storing it in {} parts",
+            max_blob_size,
+            chunks.len()
+        );
+        let mut stored = Vec::with_capacity(chunks.len() + 1);
+        let mut manifest = BTreeMap::new();
+        manifest.insert(FORMAT_KEY.to_string(), FormatHeader::chunked().encode());
+        for (n, chunk) in chunks.iter().enumerate() {
+            let blob = walrus_client.store(chunk).with_context(|| {
+                format!(
+                    "Failed to upload objects map part {} of {}",
+                    n + 1,
+                    chunks.len()
+                )
+            })?;
+            manifest.insert(
+                format!("{}{}", PART_KEY_PREFIX, n),
+                format!("{}:{}:{}", blob.shared_object_id, blob.blob_id, chunk.len()),
+            );
+            stored.push((blob, chunk.len() as u64));
         }
 
-        // Get current Walrus epoch
-        let current_epoch = match self.walrus_client.current_epoch() {
-            Ok(info) => info.current_epoch,
-            Err(e) => {
-                tracing::warn!("Failed to get current Walrus epoch: {}", e);
-                return Ok(());
-            }
-        };
+        // `format` sorts before the `part:` keys, so the header is the manifest's first line
+        let manifest_yaml =
+            serde_yaml::to_string(&manifest).context("Failed to serialize objects map manifest")?;
+        let blob = walrus_client
+            .store(manifest_yaml.as_bytes())
+            .context("Failed to upload objects map manifest")?;
+        stored.push((blob.clone(), manifest_yaml.len() as u64));
+        Ok((blob, stored))
+    }
 
-        // Check for expiration warnings (filtered to relevant blobs if provided)
-        let (should_warn, min_epoch, expiring_soon) = tracker.check_expiration_warning(
-            current_epoch,
-            self.config.expiration_warning_threshold,
-            relevant_blob_ids,
+    /// Upload a serialized objects map (in parts if needed) and track its blobs, returning the
+    /// SharedBlob object ID that goes on-chain
+    fn upload_objects_map(&self, objects_yaml: &[u8], max_blob_size: u64) -> Result<String> {
+        tracing::info!(
+            "  Uploading objects map to Walrus ({} bytes)...",
+            objects_yaml.len()
         );
+        let (objects_blob_info, stored) =
+            Self::store_objects_map(&self.walrus_client, objects_yaml, max_blob_size)
+                .context("Failed to upload objects map to Walrus")?;
 
-        if should_warn {
-            tracing::warn!("WARNING: {} blob(s) expiring soon!", expiring_soon.len());
-            tracing::warn!("  Current Walrus epoch: {}", current_epoch);
-            tracing::warn!(
-                "  Warning threshold: {} epochs",
-                self.config.expiration_warning_threshold
-            );
-
-            if let Some(min) = min_epoch {
-                tracing::warn!("  Earliest expiration: epoch {}", min);
-            }
-
-            // List expiring blobs
-            for blob in expiring_soon.iter().take(5) {
-                let epochs_remaining = blob.end_epoch.saturating_sub(current_epoch);
-                tracing::warn!(
-                    "    - {} expires in {} epoch(s)",
-                    &blob.blob_id[..16],
-                    epochs_remaining
-                );
-            }
+        tracing::info!(
+            "  Objects shared object ID: {} (blob: {})",
+            &objects_blob_info.shared_object_id,
+            &objects_blob_info.blob_id
+        );
 
-            if expiring_soon.len() > 5 {
-                tracing::warn!("    ... and {} more", expiring_soon.len() - 5);
+        // Track the objects map blobs so superseded maps can be reclaimed later
+        let mut tracker = self.load_blob_tracker()?;
+        for (blob, size) in stored {
+            if let Ok(status) = self.runtime.block_on(
+                self.sui_client
+                    .get_shared_blob_status(&blob.shared_object_id),
+            ) {
+                tracker.insert(self.uploaded_blob(
+                    status.object_id,
+                    status.blob_id,
+                    status.end_epoch,
+                    size,
+                    None,
+                ));
             }
-
-            tracing::warn!(
-                "  Action required: Re-upload expiring blobs or repository may become inaccessible"
-            );
-        } else {
-            tracing::info!(
-                "Tracking {} blob(s), earliest expiration at epoch {} (current: {})",
-                tracker.count(),
-                min_epoch.unwrap_or(0),
-                current_epoch
-            );
         }
-
-        Ok(())
+        self.save_blob_tracker(&tracker)?;
+        Ok(objects_blob_info.shared_object_id)
     }
-}
-
-impl ImmutableStore for WalrusStorage {
-    fn write_object(&self, content: &[u8]) -> Result<ContentId> {
-        let sha256 = Self::compute_sha256(content);
 
-        // 1. Check if already in cache (by sha256)
-        let mut cache_index = self.load_cache_index()?;
+    /// Read the objects map in blob `blob_id`, reassembling it from its parts when the blob is
+    /// a manifest; also returns the parts' blob object IDs
+    fn read_objects_map(
+        walrus_client: &WalrusClient,
+        blob_id: &str,
+        max_bytes: u64,
+    ) -> Result<(Vec<u8>, Vec<String>)> {
+        let blob = walrus_client.read_limited(blob_id, max_bytes)?;
+        let Some(parts) = Self::parse_objects_map_manifest(&blob)? else {
+            return Ok((blob, Vec::new()));
+        };
 
-        if let Some(object_id) = cache_index.get_object_id(&sha256) {
-            // Already cached, return object_id
-            tracing::debug!(
-                "Object '{}...' already cached as '{}...'",
-                &sha256[..8],
-                &object_id[..16]
+        let total = parts
+            .iter()
+            .fold(0u64, |total, part| total.saturating_add(part.length));
+        if total > max_bytes {
+            anyhow::bail!(
+                "Objects map is {} bytes in {} parts, over the {} byte limit \
+                 (max_objects_map_bytes); refusing to download it",
+                total,
+                parts.len(),
+                max_bytes
             );
-            return Ok(object_id.clone());
         }
 
-        // 2. Upload to Walrus
-        tracing::info!(
-            "Uploading object '{}...' ({} bytes)",
-            &sha256[..8],
-            content.len()
-        );
-        let blob_info = self
-            .walrus_client
-            .store(content)
-            .context("Failed to store object in Walrus")?;
-
-        // 3. Store in local cache
-        self.cache
-            .write_object(content)
-            .context("Failed to cache object locally")?;
-
-        // 4. Update cache index (use shared_object_id as ContentId)
-        cache_index.insert(blob_info.shared_object_id.clone(), sha256.clone());
-        self.save_cache_index(&cache_index)?;
-
-        // 5. Get blob status from Sui and track expiration
-        match self.runtime.block_on(
-            self.sui_client
-                .get_shared_blob_status(&blob_info.shared_object_id),
-        ) {
-            Ok(status) => {
-                let mut tracker = self.load_blob_tracker()?;
-                tracker.track_blob(
-                    status.object_id.clone(),
-                    status.blob_id,
-                    status.end_epoch,
-                    Some(content.len() as u64),
-                );
-                self.save_blob_tracker(&tracker)?;
-            }
-            Err(e) => {
-                tracing::warn!(
-                    "Failed to get blob status from Sui: {} [shared_object_id: {}]",
-                    e,
-                    blob_info.shared_object_id
+        let mut yaml = Vec::with_capacity(total as usize);
+        for (n, part) in parts.iter().enumerate() {
+            let bytes = walrus_client
+                .read_limited(&part.blob_id, part.length)
+                .with_context(|| {
+                    format!(
+                        "Failed to read objects map part {} (blob: {}, object: {})",
+                        n, part.blob_id, part.object_id
+                    )
+                })?;
+            if bytes.len() as u64 != part.length {
+                anyhow::bail!(
+                    "Objects map part {} is {} bytes, but its manifest lists {}",
+                    n,
+                    bytes.len(),
+                    part.length
                 );
             }
+            yaml.extend_from_slice(&bytes);
         }
 
-        Ok(blob_info.shared_object_id)
+        Ok((yaml, parts.into_iter().map(|part| part.object_id).collect()))
     }
 
-    fn write_objects(&self, contents: &[&[u8]]) -> Result<Vec<ContentId>> {
-        if contents.is_empty() {
-            return Ok(Vec::new());
-        }
-
-        // If batching is disabled, fall back to sequential writes
-        if !self.config.enable_batching {
-            tracing::debug!("Batching disabled, using sequential writes");
-            return contents
-                .iter()
-                .map(|content| self.write_object(content))
-                .collect();
+    /// The parts `blob` lists if it is a chunked objects map's manifest rather than a map
+    ///
+    /// A manifest's first line is its format header, which no map's header shares the
+    /// chunked feature with.
+    fn parse_objects_map_manifest(blob: &[u8]) -> Result<Option<Vec<ObjectsMapPart>>> {
+        let first_line = blob.split(|&b| b == b'\n').next().unwrap_or_default();
+        let header = std::str::from_utf8(first_line)
+            .ok()
+            .and_then(|line| line.strip_prefix(FORMAT_KEY)?.strip_prefix(": "))
+            .and_then(|header| FormatHeader::parse(header.trim_matches(['\'', '"'])).ok());
+        if !header.is_some_and(|header| header.is_chunked()) {
+            return Ok(None);
         }
 
-        // Get the effective max blob size (minimum of config and network limit)
-        let network_max_blob_size = self
-            .get_max_blob_size()
-            .context("Failed to get network blob size limit")?;
-        let max_batch_blob_size =
-            std::cmp::min(self.config.max_batch_blob_size, network_max_blob_size);
-
-        tracing::info!(
-            "Processing {} objects (batching enabled, effective max blob size: {:.2} MB, config: {:.2} MB, network: {:.2} MB)",
-            contents.len(),
-            max_batch_blob_size as f64 / (1024.0 * 1024.0),
-            self.config.max_batch_blob_size as f64 / (1024.0 * 1024.0),
-            network_max_blob_size as f64 / (1024.0 * 1024.0)
-        );
-
-        // Load cache index once for all lookups
-        let mut cache_index = self.load_cache_index()?;
-        let mut blob_tracker = self.load_blob_tracker()?;
-
-        // Track result ContentIds (in same order as input)
-        let mut result_content_ids: Vec<Option<ContentId>> = vec![None; contents.len()];
-
-        // Separate already-cached objects from those that need uploading
-        let mut objects_to_upload: Vec<(usize, &[u8], String)> = Vec::new(); // (index, content, sha256)
-
-        for (i, content) in contents.iter().enumerate() {
-            let sha256 = Self::compute_sha256(content);
-
-            if let Some(existing_content_id) = cache_index.get_object_id(&sha256) {
-                // Already cached
-                tracing::debug!("Object {}... already cached", &sha256[..8]);
-                result_content_ids[i] = Some(existing_content_id.clone());
-            } else {
-                // Needs uploading
-                objects_to_upload.push((i, content, sha256));
+        Self::check_flat_yaml(blob)?;
+        let entries: BTreeMap<String, String> =
+            serde_yaml::from_slice(blob).context("Failed to parse objects map manifest YAML")?;
+        let mut parts = BTreeMap::new();
+        for (key, value) in &entries {
+            if key == FORMAT_KEY {
+                FormatHeader::parse(value)?.check_readable()?;
+                continue;
+            }
+            let (n, part) = Self::parse_objects_map_part(key, value).with_context(|| {
+                format!(
+                    "Invalid objects map manifest entry {:?}: {:?}",
+                    truncate(key),
+                    truncate(value)
+                )
+            })?;
+            if parts.insert(n, part).is_some() {
+                anyhow::bail!("Objects map manifest lists part {} twice", n);
             }
         }
 
-        if objects_to_upload.is_empty() {
-            tracing::info!("All {} objects already cached", contents.len());
-            return Ok(result_content_ids
-                .into_iter()
-                .map(|id| id.unwrap())
-                .collect());
+        if parts.is_empty() {
+            anyhow::bail!("Objects map manifest lists no parts");
+        }
+        if let Some(missing) = (0..parts.len()).find(|n| !parts.contains_key(n)) {
+            anyhow::bail!("Objects map manifest is missing part {}", missing);
         }
+        Ok(Some(parts.into_values().collect()))
+    }
 
-        tracing::info!(
-            "Need to upload {} new objects ({} already cached)",
-            objects_to_upload.len(),
-            contents.len() - objects_to_upload.len()
-        );
+    /// Parse a `part:{n}` manifest entry
+    fn parse_objects_map_part(key: &str, value: &str) -> Result<(usize, ObjectsMapPart)> {
+        let n = key
+            .strip_prefix(PART_KEY_PREFIX)
+            .filter(|n| n.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|n| n.parse::<usize>().ok())
+            .context("key is not a part number")?;
+
+        let mut fields = value.splitn(3, ':');
+        let (Some(object_id), Some(blob_id), Some(length)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            anyhow::bail!("expected {{blob_object_id}}:{{blob_id}}:{{length}}");
+        };
+        if !is_sui_object_id(object_id) {
+            anyhow::bail!("{:?} is not a Sui object ID", truncate(object_id));
+        }
+        if blob_id.is_empty()
+            || !blob_id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            anyhow::bail!("{:?} is not a Walrus blob ID", truncate(blob_id));
+        }
+        let length = length
+            .parse::<u64>()
+            .ok()
+            .filter(|length| *length > 0)
+            .with_context(|| format!("invalid part length {:?}", truncate(length)))?;
+
+        Ok((
+            n,
+            ObjectsMapPart {
+                object_id: object_id.to_string(),
+                blob_id: blob_id.to_string(),
+                length,
+            },
+        ))
+    }
 
-        // Group objects into batches respecting network max blob size
+    /// Group `(index, content, sha256)` objects, in order, into batches of at most
+    /// `max_batch_blob_size` bytes (an object larger than that goes alone)
+    fn group_into_batches(
+        objects: Vec<(usize, &[u8], String)>,
+        max_batch_blob_size: u64,
+    ) -> Vec<Vec<(usize, &[u8], String)>> {
         let mut batches: Vec<Vec<(usize, &[u8], String)>> = Vec::new();
         let mut current_batch: Vec<(usize, &[u8], String)> = Vec::new();
         let mut current_batch_size: u64 = 0;
 
-        for (idx, content, sha256) in objects_to_upload {
+        for (idx, content, sha256) in objects {
             let content_len = content.len() as u64;
 
             // If adding this object would exceed max batch size AND we have objects in the batch,
@@ -526,188 +884,412 @@ impl ImmutableStore for WalrusStorage {
             batches.push(current_batch);
         }
 
+        batches
+    }
+
+    /// Split batches of `batch_sizes` bytes into runs uploaded by one `walrus store` each: at
+    /// most [`BATCHES_PER_STORE`] batches and `max_bytes` in total (or a single batch)
+    fn store_invocations(batch_sizes: &[u64], max_bytes: u64) -> Vec<Range<usize>> {
+        let mut runs: Vec<Range<usize>> = Vec::new();
+        let mut size = 0;
+        for (i, &batch_size) in batch_sizes.iter().enumerate() {
+            match runs.last_mut() {
+                Some(run) if run.len() < BATCHES_PER_STORE && size + batch_size <= max_bytes => {
+                    run.end = i + 1;
+                    size += batch_size;
+                }
+                _ => {
+                    runs.push(i..i + 1);
+                    size = batch_size;
+                }
+            }
+        }
+        runs
+    }
+
+    /// Upload `objects` ((index, content, SHA-256) entries) in batches of at most
+    /// `max_batch_blob_size` bytes, setting each one's ContentId at its index in `content_ids`
+    ///
+    /// Every batch is tried; the cache index and blob tracker are saved with what did upload,
+    /// so a retry skips it, before the first failure is returned.
+    fn upload_batches(
+        &self,
+        objects: Vec<(usize, &[u8], String)>,
+        epochs: Option<u32>,
+        max_batch_blob_size: u64,
+        cache_index: &mut CacheIndex,
+        content_ids: &mut [Option<ContentId>],
+    ) -> Result<()> {
+        let mut blob_tracker = self.load_blob_tracker()?;
+
+        // Group objects into batches respecting network max blob size
+        let batches = Self::group_into_batches(objects, max_batch_blob_size);
+
         tracing::info!("Created {} batch(es) for upload", batches.len());
 
-        // Upload each batch
-        for (batch_num, batch) in batches.iter().enumerate() {
-            let batch_size: usize = batch.iter().map(|(_, content, _)| content.len()).sum();
+        // Reserve gas for the state update that follows these uploads
+        let batch_sizes: Vec<u64> = batches
+            .iter()
+            .map(|batch| {
+                batch
+                    .iter()
+                    .map(|(_, content, _)| content.len() as u64)
+                    .sum()
+            })
+            .collect();
+        let store_epochs = epochs.unwrap_or(self.config.default_epochs);
+        self.preflight(&batch_sizes, store_epochs, 1)?;
+
+        // Upload several batches per walrus CLI invocation to amortize its startup cost; an
+        // invocation holds at most one maximum-size blob's worth of data in memory, and up to
+        // `upload_concurrency` invocations run at once
+        let invocations = Self::store_invocations(&batch_sizes, max_batch_blob_size);
+        let client = &self.walrus_client;
+        let uploads = map_bounded(&invocations, self.config.concurrency().upload, |range| {
+            let chunk = &batches[range.clone()];
+            // Single objects are stored as is (no batching overhead); others are concatenated
+            let blobs: Vec<Cow<[u8]>> = chunk
+                .iter()
+                .map(|batch| match batch.as_slice() {
+                    [(_, content, _)] => Cow::Borrowed(*content),
+                    batch => Cow::Owned(
+                        batch
+                            .iter()
+                            .flat_map(|(_, content, _)| *content)
+                            .copied()
+                            .collect(),
+                    ),
+                })
+                .collect();
+            let blob_refs: Vec<&[u8]> = blobs.iter().map(|blob| blob.as_ref()).collect();
             tracing::info!(
-                "Uploading batch {}/{} ({} objects, {} bytes)",
-                batch_num + 1,
+                "Uploading batch(es) {}-{}/{} ({} bytes)",
+                range.start + 1,
+                range.end,
                 batches.len(),
-                batch.len(),
-                batch_size
+                blob_refs.iter().map(|blob| blob.len()).sum::<usize>()
             );
-
-            if batch.len() == 1 {
-                // Single object in batch - use legacy format (no batching overhead)
-                let (idx, content, sha256) = &batch[0];
-
-                let blob_info = self
-                    .walrus_client
-                    .store(content)
-                    .context("Failed to store object in Walrus")?;
-
-                let content_id =
-                    ParsedContentId::legacy(blob_info.shared_object_id.clone()).encode();
+            client.store_many_with_epochs(&blob_refs, store_epochs)
+        })?;
+
+        let mut failures = Vec::new();
+        for (range, results) in invocations.into_iter().zip(uploads) {
+            for (batch_num, result) in (range.start + 1..).zip(results) {
+                let batch = &batches[batch_num - 1];
+                let blob_len = batch_sizes[batch_num - 1];
+                let blob_info = match result {
+                    Ok(blob_info) => blob_info,
+                    Err(e) => {
+                        tracing::warn!(
+                            "Batch {}/{} ({} objects) failed to upload: {:#}",
+                            batch_num,
+                            batches.len(),
+                            batch.len(),
+                            e
+                        );
+                        failures.push(e);
+                        continue;
+                    }
+                };
 
                 // Cache locally
-                let _ = self.cache.write_object(content); // Ignore errors
+                for (_, content, _) in batch {
+                    let _ = self.cache.write_object(content); // Ignore errors
+                }
 
-                // Update cache index
-                cache_index.insert(blob_info.shared_object_id.clone(), sha256.clone());
+                if let [(idx, content, sha256)] = batch.as_slice() {
+                    // Single object in batch - use legacy format
+                    let content_id =
+                        ParsedContentId::legacy(blob_info.shared_object_id.clone()).encode();
+                    Self::index_cached(
+                        cache_index,
+                        blob_info.shared_object_id.clone(),
+                        sha256.clone(),
+                        content,
+                    );
+                    content_ids[*idx] = Some(content_id);
+                } else {
+                    // Multiple objects in batch - batched ContentIds into the concatenation
+                    let mut offset = 0;
+                    for (idx, content, sha256) in batch {
+                        let length = content.len() as u64;
+                        let content_id = ParsedContentId::batched(
+                            blob_info.shared_object_id.clone(),
+                            offset,
+                            length,
+                        )
+                        .encode();
+                        offset += length;
+
+                        // Update cache index with batched ContentId
+                        Self::index_cached(
+                            cache_index,
+                            content_id.clone(),
+                            sha256.clone(),
+                            content,
+                        );
+                        content_ids[*idx] = Some(content_id);
+                    }
+                    // Record where each object went, so renewal can repack the live ones
+                    let manifest = BlobManifest::packed(batch.iter().map(|(_, content, _)| {
+                        (hex::encode(Sha1::digest(content)), content.len() as u64)
+                    }));
+                    if let Err(e) =
+                        manifest.save(&self.config.cache_dir, &blob_info.shared_object_id)
+                    {
+                        tracing::warn!("Failed to save blob manifest: {:#}", e);
+                    }
+                    tracing::info!(
+                        "Batch {}/{} uploaded to {} ({} objects batched)",
+                        batch_num,
+                        batches.len(),
+                        &blob_info.shared_object_id[..16],
+                        batch.len()
+                    );
+                }
 
                 // Track blob expiration
                 if let Ok(status) = self.runtime.block_on(
                     self.sui_client
                         .get_shared_blob_status(&blob_info.shared_object_id),
                 ) {
-                    blob_tracker.track_blob(
+                    blob_tracker.insert(self.uploaded_blob(
                         status.object_id,
                         status.blob_id,
                         status.end_epoch,
-                        Some(content.len() as u64),
-                    );
+                        blob_len,
+                        epochs,
+                    ));
                 }
+            }
+        }
 
-                result_content_ids[*idx] = Some(content_id);
-            } else {
-                // Multiple objects in batch - concatenate and use batched format
-                let mut concatenated = Vec::with_capacity(batch_size);
-                let mut offsets: Vec<(usize, u64, u64, String)> = Vec::new(); // (index, offset, length, sha256)
-
-                for (idx, content, sha256) in batch {
-                    let offset = concatenated.len() as u64;
-                    let length = content.len() as u64;
-                    concatenated.extend_from_slice(content);
-                    offsets.push((*idx, offset, length, sha256.clone()));
-
-                    // Cache individual object locally
-                    let _ = self.cache.write_object(content); // Ignore errors
-                }
+        // Save updated cache index and blob tracker, keeping what did upload for a retry
+        self.save_cache_index(cache_index)?;
+        self.save_blob_tracker(&blob_tracker)?;
 
-                // Upload concatenated batch to Walrus
-                let blob_info = self
-                    .walrus_client
-                    .store(&concatenated)
-                    .context("Failed to store batched blob in Walrus")?;
+        let failed = failures.len();
+        if let Some(first) = failures.into_iter().next() {
+            return Err(first.context(format!(
+                "{} of {} batch(es) failed to upload to Walrus",
+                failed,
+                batches.len()
+            )));
+        }
 
-                // Create batched ContentIds for each object
-                for (idx, offset, length, sha256) in offsets {
-                    let content_id = ParsedContentId::batched(
-                        blob_info.shared_object_id.clone(),
-                        offset,
-                        length,
-                    )
-                    .encode();
+        Ok(())
+    }
 
-                    // Update cache index with batched ContentId
-                    cache_index.insert(content_id.clone(), sha256);
+    /// Group cache misses by the blob holding them, in the order the objects were requested
+    ///
+    /// Callers list the objects they need soonest first (fetches walk history from the refs
+    /// back), so downloading in this order gets recent history into the cache before older blobs.
+    fn download_order(
+        misses: impl IntoIterator<Item = (usize, ParsedContentId)>,
+    ) -> Vec<(String, Vec<(usize, ParsedContentId)>)> {
+        let mut groups: Vec<(String, Vec<(usize, ParsedContentId)>)> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for (idx, parsed_id) in misses {
+            let Some(blob_object_id) = parsed_id.blob_object_id() else {
+                continue;
+            };
+            let position = *positions
+                .entry(blob_object_id.to_string())
+                .or_insert_with(|| {
+                    groups.push((blob_object_id.to_string(), Vec::new()));
+                    groups.len() - 1
+                });
+            groups[position].1.push((idx, parsed_id));
+        }
+        groups
+    }
 
-                    result_content_ids[idx] = Some(content_id);
-                }
+    /// Bytes of each of `blob_object_ids` and where they came from: the blob cache, or Walrus
+    /// (up to `download_concurrency` downloads at once, each added to the blob cache)
+    fn read_blobs(&self, blob_object_ids: &[&str]) -> Result<Vec<(Vec<u8>, ReadSource)>> {
+        let mut blobs: Vec<Option<(Vec<u8>, ReadSource)>> = blob_object_ids
+            .iter()
+            .map(|id| {
+                self.blob_cache
+                    .get(id)
+                    .map(|blob| (blob, ReadSource::Cache))
+            })
+            .collect();
+        let missing: Vec<usize> = (0..blobs.len()).filter(|&i| blobs[i].is_none()).collect();
 
-                // Track blob expiration for the batched blob
-                if let Ok(status) = self.runtime.block_on(
-                    self.sui_client
-                        .get_shared_blob_status(&blob_info.shared_object_id),
-                ) {
-                    blob_tracker.track_blob(
-                        status.object_id,
-                        status.blob_id,
-                        status.end_epoch,
-                        Some(concatenated.len() as u64),
-                    );
-                }
+        // Get each blob_id from Sui
+        let mut blob_ids = Vec::with_capacity(missing.len());
+        for &i in &missing {
+            let blob_object_id = blob_object_ids[i];
+            tracing::debug!(
+                "Querying Sui for blob_id (object: {})",
+                &blob_object_id[..std::cmp::min(blob_object_id.len(), 16)]
+            );
+            let blob_status = self
+                .runtime
+                .block_on(self.sui_client.get_shared_blob_status(blob_object_id))
+                .with_context(|| {
+                    format!(
+                        "Failed to get SharedBlob status for object {}",
+                        blob_object_id
+                    )
+                })?;
+            blob_ids.push((blob_object_id, blob_status.blob_id));
+        }
 
+        // Download each blob once for all objects that need it
+        let client = &self.walrus_client;
+        let downloads = map_bounded(
+            &blob_ids,
+            self.config.concurrency().download,
+            |(blob_object_id, blob_id)| {
                 tracing::info!(
-                    "Batch {}/{} uploaded to {} ({} objects batched)",
-                    batch_num + 1,
-                    batches.len(),
-                    &blob_info.shared_object_id[..16],
-                    batch.len()
+                    "Downloading blob {}",
+                    &blob_id[..std::cmp::min(blob_id.len(), 16)]
                 );
+                client.read(blob_id).with_context(|| {
+                    format!(
+                        "Failed to read blob {} from Walrus (object: {})",
+                        blob_id, blob_object_id
+                    )
+                })
+            },
+        )?;
+        for (i, download) in missing.into_iter().zip(downloads) {
+            let blob = download?;
+            if let Err(e) = self.blob_cache.insert(blob_object_ids[i], &blob) {
+                tracing::warn!("Failed to cache blob {}: {:#}", blob_object_ids[i], e);
             }
+            blobs[i] = Some((blob, ReadSource::Walrus));
         }
 
-        // Save updated cache index and blob tracker
-        self.save_cache_index(&cache_index)?;
-        self.save_blob_tracker(&blob_tracker)?;
-
-        // Ensure all results are populated
-        Ok(result_content_ids
+        Ok(blobs
             .into_iter()
-            .map(|id| id.expect("All ContentIds should be populated"))
+            .map(|blob| blob.expect("every blob is cached or downloaded"))
             .collect())
     }
 
-    fn read_object(&self, id: &str) -> Result<Vec<u8>> {
-        // Parse ContentId to detect batched vs legacy format
-        let parsed_id = ParsedContentId::parse(id)
-            .with_context(|| format!("Invalid ContentId format: {}", id))?;
+    /// Order blob groups largest blob first, estimating each blob's size from the end of the
+    /// furthest slice read from it (whole-blob ContentIds don't say, so they keep their place
+    /// after the batched blobs)
+    fn largest_first(groups: &mut [(String, Vec<(usize, ParsedContentId)>)]) {
+        groups.sort_by_key(|(_, items)| {
+            let size = items
+                .iter()
+                .filter_map(|(_, parsed_id)| match *parsed_id {
+                    ParsedContentId::Batched { offset, length, .. } => Some(offset + length),
+                    _ => None,
+                })
+                .max();
+            std::cmp::Reverse(size)
+        });
+    }
 
-        // 1. Try to read from cache (by sha256)
-        let cache_index = self.load_cache_index()?;
+    /// Index the objects of `objects` (git SHA-1 -> ContentId) by the blob holding them
+    fn index_blob_members(objects: &BTreeMap<String, ContentId>) -> BlobMembers {
+        let mut members = BlobMembers::new();
+        for (git_sha1, content_id) in objects {
+            if let Some(blob_object_id) = ParsedContentId::parse(content_id)
+                .ok()
+                .and_then(|parsed| parsed.blob_object_id().map(str::to_string))
+            {
+                members
+                    .entry(blob_object_id)
+                    .or_default()
+                    .push((git_sha1.clone(), content_id.clone()));
+            }
+        }
+        members
+    }
 
-        if let Some(sha256) = cache_index.get_sha256(id) {
-            // Try cache hit
-            match self.cache.read_object(sha256) {
-                Ok(content) => {
-                    tracing::debug!(
-                        "Cache hit for ContentId {}",
-                        &id[..std::cmp::min(id.len(), 16)]
-                    );
-                    return Ok(content);
-                }
-                Err(_) => {
-                    // Cache miss, continue to Walrus
-                    tracing::debug!(
-                        "Cache miss for ContentId {}",
-                        &id[..std::cmp::min(id.len(), 16)]
-                    );
-                }
-            }
-        }
-
-        // 2. Get the blob_object_id (same for both legacy and batched)
-        let blob_object_id = parsed_id.blob_object_id();
+    /// Git SHA-1 the cached state maps to ContentId `id`, if any
+    fn git_sha1_of(&self, id: &str) -> Option<String> {
+        let state = self.cached_state.borrow();
+        state
+            .as_ref()?
+            .objects
+            .iter()
+            .find(|(_, content_id)| content_id.as_str() == id)
+            .map(|(git_sha1, _)| git_sha1.clone())
+    }
 
-        // 3. Get blob_id from Sui object
-        tracing::debug!(
-            "Querying Sui for blob_id (object: {})",
-            &blob_object_id[..std::cmp::min(blob_object_id.len(), 16)]
-        );
-        let blob_status = self
-            .runtime
-            .block_on(self.sui_client.get_shared_blob_status(blob_object_id))
-            .with_context(|| {
-                format!(
-                    "Failed to get SharedBlob status for object {}",
-                    blob_object_id
+    /// Cache the other objects the cached state places in a downloaded blob, so reads of
+    /// later history levels don't download it again
+    ///
+    /// Objects that fail to extract or verify are skipped; they are only read when requested.
+    fn cache_blob_members(
+        &self,
+        cache_index: &mut CacheIndex,
+        blob_object_id: &str,
+        full_blob: &[u8],
+    ) {
+        let state = self.cached_state.borrow();
+        let Some(state) = state.as_ref() else {
+            return;
+        };
+        let mut members = self.blob_members.borrow_mut();
+        let members = members.get_or_insert_with(|| Self::index_blob_members(&state.objects));
+
+        let mut cached = 0;
+        for (git_sha1, content_id) in members.get(blob_object_id).into_iter().flatten() {
+            if cache_index
+                .get_sha256(content_id, ContentKind::RawLoose)
+                .is_some()
+            {
+                continue;
+            }
+            let extracted = ParsedContentId::parse(content_id).and_then(|parsed_id| {
+                Self::extract_and_cache(
+                    &self.cache,
+                    cache_index,
+                    content_id,
+                    &parsed_id,
+                    full_blob,
+                    Some(git_sha1),
                 )
-            })?;
+            });
+            match extracted {
+                Ok(_) => cached += 1,
+                Err(e) => tracing::debug!("Not caching {} from its blob: {:#}", git_sha1, e),
+            }
+        }
+        if cached > 0 {
+            tracing::debug!("Cached {} more object(s) from the same blob", cached);
+        }
+    }
 
-        // 4. Read from Walrus using blob_id
-        tracing::info!(
-            "Downloading from Walrus: {}",
-            &blob_status.blob_id[..std::cmp::min(blob_status.blob_id.len(), 16)]
-        );
-        let full_blob = self
-            .walrus_client
-            .read(&blob_status.blob_id)
-            .with_context(|| {
-                format!(
-                    "Failed to read blob {} from Walrus (object: {})",
-                    blob_status.blob_id, blob_object_id
-                )
-            })?;
+    /// Move the symref entries of a parsed objects map into their own map
+    fn take_symrefs(objects: &mut BTreeMap<String, ContentId>) -> BTreeMap<String, String> {
+        let (symrefs, rest) = std::mem::take(objects)
+            .into_iter()
+            .partition(|(key, _)| key.starts_with(SYMREF_KEY_PREFIX));
+        *objects = rest;
+        symrefs
+            .into_iter()
+            .map(|(key, target): (String, String)| {
+                (key[SYMREF_KEY_PREFIX.len()..].to_string(), target)
+            })
+            .collect()
+    }
 
-        // 5. Extract the appropriate content based on ContentId format
-        let content = match parsed_id {
+    /// Extract an object from its downloaded blob, verify it and add it to the cache
+    ///
+    /// With `expected_git_sha1`, content whose git SHA-1 doesn't match is rejected before it
+    /// reaches the cache, so a bad offset or corrupted blob can't poison later reads.
+    fn extract_and_cache(
+        cache: &FilesystemStorage,
+        cache_index: &mut CacheIndex,
+        id: &str,
+        parsed_id: &ParsedContentId,
+        full_blob: &[u8],
+        expected_git_sha1: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let content = match *parsed_id {
             ParsedContentId::Legacy { .. } => {
                 // Legacy format: entire blob is the object
-                full_blob
+                full_blob.to_vec()
             }
+            ParsedContentId::Constant { content, .. } => content.to_vec(),
             ParsedContentId::Batched { offset, length, .. } => {
                 // Batched format: extract slice from concatenated blob
                 let start = offset as usize;
@@ -733,277 +1315,1751 @@ impl ImmutableStore for WalrusStorage {
             }
         };
 
-        // 6. Cache it locally
-        let sha256 = Self::compute_sha256(&content);
-        let _ = self.cache.write_object(&content); // Ignore errors on cache write
+        if let Some(git_sha1) = expected_git_sha1 {
+            verify_git_object(git_sha1, &content).with_context(|| {
+                format!(
+                    "Object read from Walrus is corrupt (ContentId {}); not caching it",
+                    id
+                )
+            })?;
+        }
 
-        // 7. Update cache index
-        let mut cache_index = self.load_cache_index()?;
-        cache_index.insert(id.to_string(), sha256);
-        let _ = self.save_cache_index(&cache_index); // Ignore errors on index write
+        // Cache the extracted content locally
+        let sha256 = Self::compute_sha256(&content);
+        let _ = cache.write_object(&content); // Ignore errors on cache write
+        Self::index_cached(cache_index, id.to_string(), sha256, &content);
 
         Ok(content)
     }
 
-    fn read_objects(&self, ids: &[&str]) -> Result<Vec<Vec<u8>>> {
-        if ids.is_empty() {
-            return Ok(Vec::new());
-        }
-
-        // Parse all ContentIds and group by blob_object_id to deduplicate blob fetches
-        use std::collections::HashMap;
-
-        // Store results in original order
-        let mut results: Vec<Option<Vec<u8>>> = vec![None; ids.len()];
-
-        // Parse all ContentIds first
-        let parsed_ids: Result<Vec<ParsedContentId>> = ids
-            .iter()
-            .map(|id| {
-                ParsedContentId::parse(id)
-                    .with_context(|| format!("Invalid ContentId format: {}", id))
-            })
-            .collect();
-        let parsed_ids = parsed_ids?;
-
-        // Load cache index once for all lookups
-        let cache_index = self.load_cache_index()?;
-
-        // Group ContentIds by blob_object_id and track which indices need each blob
-        let mut blob_groups: HashMap<String, Vec<(usize, ParsedContentId)>> = HashMap::new();
-        let mut cache_hits = 0;
-
-        for (idx, parsed_id) in parsed_ids.into_iter().enumerate() {
-            // Check if this object is already in cache
-            if let Some(sha256) = cache_index.get_sha256(ids[idx]) {
-                if let Ok(content) = self.cache.read_object(sha256) {
-                    tracing::debug!(
-                        "Cache hit for ContentId {}",
-                        &ids[idx][..std::cmp::min(ids[idx].len(), 16)]
-                    );
-                    results[idx] = Some(content);
-                    cache_hits += 1;
-                    continue;
+    /// Read `id` from the cache, under its own ContentId or, given its git SHA-1, as cached
+    /// under a different ContentId, by this remote or another in `siblings` (`id` is then
+    /// mapped to it as well)
+    fn read_cached(
+        cache: &FilesystemStorage,
+        cache_index: &mut CacheIndex,
+        siblings: &SiblingIndexes,
+        id: &str,
+        git_sha1: Option<&str>,
+    ) -> Option<Vec<u8>> {
+        if let Some(sha256) = cache_index.get_sha256(id, ContentKind::RawLoose).cloned() {
+            if let Ok(content) = cache.read_object(&sha256) {
+                // A damaged file is dropped for every ContentId sharing it; a file that is
+                // intact but isn't the expected object only loses this mapping
+                if Self::compute_sha256(&content) != sha256 {
+                    tracing::warn!("Cached object {} is damaged; reading it again", sha256);
+                    let _ = cache.delete_object(&sha256);
+                    cache_index.remove_by_sha256(&sha256);
+                } else if let Some(e) =
+                    git_sha1.and_then(|git_sha1| verify_git_object(git_sha1, &content).err())
+                {
+                    tracing::warn!("Ignoring cache entry for ContentId {}: {:#}", id, e);
+                    cache_index.remove_by_object_id(id);
+                } else {
+                    return Some(content);
                 }
             }
-
-            // Cache miss - need to fetch from Walrus
-            let blob_object_id = parsed_id.blob_object_id().to_string();
-            blob_groups
-                .entry(blob_object_id)
-                .or_default()
-                .push((idx, parsed_id));
-        }
-
-        if cache_hits > 0 {
-            tracing::debug!("{} cache hits out of {} objects", cache_hits, ids.len());
-        }
-
-        if blob_groups.is_empty() {
-            // All cache hits
-            return Ok(results.into_iter().map(|r| r.unwrap()).collect());
         }
 
-        tracing::info!(
-            "Batch reading {} objects from {} unique blob(s)",
-            ids.len() - cache_hits,
-            blob_groups.len()
-        );
-
-        // Process each unique blob
-        for (blob_object_id, items) in blob_groups {
-            // Get blob_id from Sui
-            tracing::debug!(
-                "Querying Sui for blob_id (object: {})",
-                &blob_object_id[..std::cmp::min(blob_object_id.len(), 16)]
-            );
-            let blob_status = self
-                .runtime
-                .block_on(self.sui_client.get_shared_blob_status(&blob_object_id))
-                .with_context(|| {
-                    format!(
-                        "Failed to get SharedBlob status for object {}",
-                        blob_object_id
-                    )
-                })?;
-
-            // Download blob once for all objects that need it
-            tracing::info!(
-                "Downloading blob {} (needed by {} object(s))",
-                &blob_status.blob_id[..std::cmp::min(blob_status.blob_id.len(), 16)],
-                items.len()
-            );
-            let full_blob = self
-                .walrus_client
-                .read(&blob_status.blob_id)
-                .with_context(|| {
-                    format!(
-                        "Failed to read blob {} from Walrus (object: {})",
-                        blob_status.blob_id, blob_object_id
-                    )
-                })?;
-
-            // Extract content for each object that needs this blob
-            for (idx, parsed_id) in items {
-                let content = match parsed_id {
-                    ParsedContentId::Legacy { .. } => {
-                        // Legacy format: entire blob is the object
-                        full_blob.clone()
-                    }
-                    ParsedContentId::Batched { offset, length, .. } => {
-                        // Batched format: extract slice from concatenated blob
-                        let start = offset as usize;
-                        let end = (offset + length) as usize;
-
-                        if end > full_blob.len() {
-                            anyhow::bail!(
-                                "Batched ContentId specifies range {}..{} but blob is only {} bytes",
-                                start,
-                                end,
-                                full_blob.len()
-                            );
-                        }
-
-                        tracing::debug!(
-                            "Extracting batched object: bytes {}..{} from blob of {} bytes",
-                            start,
-                            end,
-                            full_blob.len()
-                        );
-
-                        full_blob[start..end].to_vec()
-                    }
-                };
-
-                // Cache the extracted content locally
-                let sha256 = Self::compute_sha256(&content);
-                let _ = self.cache.write_object(&content); // Ignore errors on cache write
+        let git_sha1 = git_sha1?;
+        let sha256 = cache_index
+            .get_sha256_by_git_sha1(git_sha1)
+            .or_else(|| siblings.get_sha256_by_git_sha1(git_sha1))?
+            .clone();
+        let content = cache.read_object(&sha256).ok()?;
+        verify_git_object(git_sha1, &content).ok()?;
+        cache_index.insert_alias(id.to_string(), sha256);
+        Some(content)
+    }
 
-                // Update cache index
-                let mut cache_index = self.load_cache_index()?;
-                cache_index.insert(ids[idx].to_string(), sha256);
-                let _ = self.save_cache_index(&cache_index); // Ignore errors on index write
+    /// Index cached `content` (stored as `sha256`) under ContentId `id` and under its git
+    /// SHA-1, by which a remote storing the same object under another ContentId finds it
+    fn index_cached(cache_index: &mut CacheIndex, id: String, sha256: String, content: &[u8]) {
+        cache_index.insert_git_sha1(hex::encode(Sha1::digest(content)), sha256.clone());
+        cache_index.insert(id, sha256, ContentKind::RawLoose);
+    }
 
-                results[idx] = Some(content);
+    /// Cache each (content, ContentId) pair the index doesn't know yet, returning how many
+    fn seed_cache(
+        cache: &FilesystemStorage,
+        cache_index: &mut CacheIndex,
+        objects: &[(&[u8], &str)],
+    ) -> Result<usize> {
+        let mut seeded = 0;
+        for (content, id) in objects {
+            if ParsedContentId::constant(content).is_some()
+                || cache_index.get_sha256(id, ContentKind::RawLoose).is_some()
+            {
+                continue;
             }
+            let sha256 = cache
+                .write_object(content)
+                .context("Failed to cache object locally")?;
+            Self::index_cached(cache_index, id.to_string(), sha256, content);
+            seeded += 1;
         }
+        Ok(seeded)
+    }
 
-        // Ensure all results are populated
-        Ok(results
-            .into_iter()
-            .map(|r| r.expect("All results should be populated"))
-            .collect())
+    /// Remember where `id` was just read from, for `read_source`
+    fn note_source(&self, id: &str, source: ReadSource) {
+        self.read_sources
+            .borrow_mut()
+            .insert(id.to_string(), source);
     }
 
-    fn delete_object(&self, id: &str) -> Result<()> {
-        // Walrus is immutable, so we only delete from cache
-        let cache_index = self.load_cache_index()?;
+    /// Load cache index
+    fn load_cache_index(&self) -> Result<CacheIndex> {
+        let mut index =
+            CacheIndex::load(&self.cache_index_path).context("Failed to load cache index")?;
+        index.set_capacity(self.config.cache_max_entries);
+        Ok(index)
+    }
 
-        if let Some(sha256) = cache_index.get_sha256(id) {
-            self.cache.delete_object(sha256)?;
+    /// Save cache index, first evicting the least recently cached objects if it is over
+    /// `cache_max_entries` (by when their files were written, for those cached by earlier
+    /// sessions)
+    fn save_cache_index(&self, index: &mut CacheIndex) -> Result<()> {
+        let evicted = index.evict_lru(|sha256| self.cache.object_modified(sha256));
+        if !evicted.is_empty() {
+            tracing::debug!("Evicting {} objects from the local cache", evicted.len());
+        }
+        // Files another remote still indexes stay cached for it
+        let siblings = self.sibling_indexes();
+        for sha256 in evicted
+            .iter()
+            .filter(|sha256| !siblings.contains_sha256(sha256))
+        {
+            if let Err(e) = self.cache.delete_object(sha256) {
+                tracing::warn!("Failed to remove evicted cached object {}: {:#}", sha256, e);
+            }
         }
+        index
+            .save(&self.cache_index_path)
+            .context("Failed to save cache index")
+    }
 
-        // Note: We don't remove from cache_index or blob_tracker
-        // as the blob still exists on Walrus
+    /// The other remotes' cache indexes in this cache dir
+    fn sibling_indexes(&self) -> SiblingIndexes {
+        let remote_dir = self.cache_index_path.parent().unwrap_or(Path::new(""));
+        SiblingIndexes::in_dir(
+            &self.config.cache_dir.join(REMOTES_DIR),
+            remote_dir,
+            CACHE_INDEX_FILE,
+        )
+    }
 
-        Ok(())
+    /// Load blob tracker
+    fn load_blob_tracker(&self) -> Result<BlobTracker> {
+        BlobTracker::load(&self.blob_tracker_path).context("Failed to load blob tracker")
     }
 
-    fn object_exists(&self, id: &str) -> Result<bool> {
-        // Check cache index
-        let cache_index = self.load_cache_index()?;
+    /// Save blob tracker
+    fn save_blob_tracker(&self, tracker: &BlobTracker) -> Result<()> {
+        tracker
+            .save(&self.blob_tracker_path)
+            .context("Failed to save blob tracker")
+    }
 
-        if cache_index.contains_object(id) {
-            return Ok(true);
+    /// Get network info (lazy-loaded and cached)
+    fn get_network_info(&self) -> Result<WalrusNetworkInfo> {
+        // Check if we have cached network info
+        if let Some(cached) = self.network_info.borrow().as_ref() {
+            return Ok(cached.clone());
         }
 
-        // Could query Sui for object, but for now assume not exists
-        Ok(false)
-    }
-}
+        // Try to load from file (info cached before encoding types were recorded
+        // is re-queried when an encoding needs validating)
+        let cached = WalrusNetworkInfo::load(&self.network_info_path)?.filter(|info| {
+            (self.walrus_client.encoding().is_none() || !info.encoding_types.is_empty())
+                && (self.config.skip_preflight || info.price_info.is_some())
+        });
+        let network_info = if let Some(info) = cached {
+            tracing::debug!("Loaded network info from cache");
+            info
+        } else {
+            // Query from Walrus CLI
+            tracing::info!("Querying Walrus network info...");
+            let info = WalrusNetworkInfo::query(&self.walrus_client)
+                .context("Failed to query Walrus network info")?;
 
-impl MutableState for WalrusStorage {
-    fn read_state(&self) -> Result<State> {
-        // Check if we have a cached state
-        if let Some(cached) = self.cached_state.borrow().as_ref() {
-            tracing::debug!(
-                "git-remote-walrus: Using cached state ({} refs, {} objects)",
-                cached.refs.len(),
-                cached.objects.len()
+            // Save for future use
+            info.save(&self.network_info_path)
+                .context("Failed to save network info")?;
+
+            tracing::info!(
+                "Network limits: max_blob_size={} bytes ({:.2} MB)",
+                info.max_blob_size(),
+                info.max_blob_size() as f64 / (1024.0 * 1024.0)
             );
-            return Ok(cached.clone());
-        }
 
-        tracing::info!(
-            "git-remote-walrus: Reading state from {}",
-            &self.state_object_id
-        );
+            info
+        };
+
+        // Cache it
+        *self.network_info.borrow_mut() = Some(network_info.clone());
+
+        Ok(network_info)
+    }
 
-        // Read refs from Sui on-chain
+    /// Refs, symrefs and objects map object ID the RemoteState holds now
+    fn state_source(&self) -> Result<StateSource> {
+        if let Some(digest) = &self.config.pinned_transaction {
+            tracing::info!("  Pinned to the state after transaction {}", digest);
+            return self
+                .runtime
+                .block_on(self.sui_client.state_at(digest))
+                .with_context(|| format!("Failed to read the state after transaction {}", digest));
+        }
         let refs = self
             .runtime
             .block_on(self.sui_client.read_refs())
             .context("Failed to read refs from Sui")?;
-
-        tracing::info!("  Retrieved {} refs from Sui", refs.len());
-
-        // Get objects_blob_object_id from Sui
+        let symrefs = self
+            .runtime
+            .block_on(self.sui_client.read_symrefs())
+            .context("Failed to read symrefs from Sui")?;
         let objects_object_id = self
             .runtime
             .block_on(self.sui_client.get_objects_blob_object_id())
             .context("Failed to get objects object ID from Sui")?;
+        Ok((refs, symrefs, objects_object_id))
+    }
 
-        // Download objects map from Walrus if it exists
-        let objects = if let Some(object_id) = objects_object_id {
-            tracing::info!(
-                "  Downloading objects map from Walrus (object_id: {})",
-                &object_id
+    /// Download and parse the objects map in SharedBlob `object_id`; also returns the blob
+    /// object IDs of its parts and, if it is canonical, the sha256 of its bytes
+    fn download_objects_map(
+        &self,
+        object_id: &str,
+    ) -> Result<(BTreeMap<String, ContentId>, Vec<String>, Option<String>)> {
+        tracing::info!(
+            "  Downloading objects map from Walrus (object_id: {})",
+            object_id
+        );
+
+        // Get blob_id from Sui
+        let blob_status = self
+            .runtime
+            .block_on(self.sui_client.get_shared_blob_status(object_id))
+            .with_context(|| {
+                format!(
+                    "Failed to get SharedBlob status for objects map (object: {})",
+                    object_id
+                )
+            })?;
+
+        // Any allowlisted writer controls this blob; refuse to download a huge one
+        let max_bytes = self.config.max_objects_map_bytes;
+        if let Some(size) = blob_status.size.filter(|size| *size > max_bytes) {
+            anyhow::bail!(
+                "Objects map blob {} is {} bytes, over the {} byte limit \
+                 (max_objects_map_bytes); refusing to download it",
+                object_id,
+                size,
+                max_bytes
             );
+        }
 
-            // Get blob_id from Sui
-            let blob_status = self
-                .runtime
-                .block_on(self.sui_client.get_shared_blob_status(&object_id))
+        // Read from Walrus using blob_id (reassembling a map stored in parts)
+        let (objects_yaml, parts) =
+            Self::read_objects_map(&self.walrus_client, &blob_status.blob_id, max_bytes)
                 .with_context(|| {
                     format!(
-                        "Failed to get SharedBlob status for objects map (object: {})",
-                        object_id
+                        "Failed to read objects map from Walrus (blob: {}, object: {})",
+                        blob_status.blob_id, object_id
                     )
                 })?;
+        if !parts.is_empty() {
+            tracing::info!("  Reassembled objects map from {} parts", parts.len());
+        }
+        let objects = Self::parse_objects_map(&objects_yaml, max_bytes)?;
+        let sha256 = is_canonical(&objects_yaml).then(|| Self::compute_sha256(&objects_yaml));
+        Ok((objects, parts, sha256))
+    }
 
-            // Read from Walrus using blob_id
-            let objects_yaml =
-                self.walrus_client
-                    .read(&blob_status.blob_id)
-                    .with_context(|| {
-                        format!(
-                            "Failed to read objects map from Walrus (blob: {}, object: {})",
-                            blob_status.blob_id, object_id
-                        )
-                    })?;
-            serde_yaml::from_slice(&objects_yaml).context("Failed to parse objects map YAML")?
-        } else {
-            tracing::info!("  No objects object ID found, starting with empty objects map");
-            BTreeMap::new()
+    /// The format header the last writer recorded on-chain for the objects map in SharedBlob
+    /// `object_id`, refused if this version can't read it
+    ///
+    /// None when the map's writer recorded no header (it may have left one in the map).
+    fn recorded_format(&self, object_id: &str) -> Result<Option<FormatHeader>> {
+        let last_writer = self
+            .runtime
+            .block_on(self.sui_client.last_writer())
+            .context("Failed to read the remote's last writer")?;
+        let Some(format) = last_writer
+            .as_ref()
+            .and_then(|last_writer| last_writer.format_of(object_id))
+        else {
+            return Ok(None);
         };
+        let header = FormatHeader::parse(format)?;
+        header.check_readable()?;
+        Ok(Some(header))
+    }
 
-        tracing::info!("  Retrieved {} objects mappings", objects.len());
+    /// The objects and metadata of the objects map in SharedBlob `object_id`, which may be a
+    /// map the remote has since replaced, with any symrefs a development build stored in it
+    pub fn objects_map_state(&self, object_id: &str) -> Result<State> {
+        let (mut objects, _, _) = self.download_objects_map(object_id)?;
+        let symrefs = Self::take_symrefs(&mut objects);
+        objects.remove(FORMAT_KEY);
+        let metadata = objects.remove(METADATA_KEY);
+        Ok(State {
+            objects,
+            symrefs,
+            metadata,
+            ..Default::default()
+        })
+    }
 
-        // Lazy rehydration: discover blob expiration info from objects map
-        // This allows any client (including fresh clones) to track blob expiration
-        if !objects.is_empty() {
-            let _ = self.rehydrate_blob_tracker(&objects); // Best effort, don't fail on errors
-        }
+    /// Object ID of the SharedBlob holding the remote's current objects map
+    pub fn objects_map_object_id(&self) -> Result<Option<String>> {
+        Ok(self.state_source()?.2)
+    }
 
-        let state = State { refs, objects };
+    /// The state, with refs, and the SharedBlob holding its objects map (None if it has none)
+    pub fn read_state_and_objects_map(&self) -> Result<(State, Option<String>)> {
+        let state = self.read_state()?;
+        let objects_object_id = match self.read_from.borrow().as_ref() {
+            Some((_, _, objects_object_id)) => objects_object_id.clone(),
+            None => self.state_source()?.2,
+        };
+        Ok((state, objects_object_id))
+    }
 
-        // Cache the state for subsequent reads
-        *self.cached_state.borrow_mut() = Some(state.clone());
+    /// Get the maximum blob size for this Walrus network, lowered to `max_blob_size` if set
+    fn get_max_blob_size(&self) -> Result<u64> {
+        let network_info = self.get_network_info()?;
+        Ok(Self::effective_max_blob_size(
+            network_info.max_blob_size(),
+            self.config.max_blob_size,
+        ))
+    }
 
-        Ok(state)
+    /// The network's maximum blob size, or `simulated` if that is smaller
+    fn effective_max_blob_size(network: u64, simulated: Option<u64>) -> u64 {
+        match simulated {
+            Some(simulated) if simulated < network => {
+                tracing::debug!(
+                    "Simulating a {} byte blob size limit (network: {} bytes)",
+                    simulated,
+                    network
+                );
+                simulated
+            }
+            _ => network,
+        }
     }
 
-    fn write_state(&self, state: &State) -> Result<()> {
+    /// Extract unique blob_object_ids from ContentIds (handles batched format)
+    fn extract_blob_object_ids(content_ids: &[&str]) -> Vec<String> {
+        use std::collections::HashSet;
+
+        let mut blob_ids: HashSet<String> = HashSet::new();
+
+        for content_id in content_ids {
+            if let Some(blob_object_id) = ParsedContentId::parse(content_id)
+                .ok()
+                .as_ref()
+                .and_then(ParsedContentId::blob_object_id)
+            {
+                blob_ids.insert(blob_object_id.to_string());
+            }
+        }
+
+        blob_ids.into_iter().collect()
+    }
+
+    /// Rehydrate blob_tracker from objects map (lazy discovery)
+    /// This allows any client to discover blob expiration info from on-chain state
+    fn rehydrate_blob_tracker(&self, objects: &BTreeMap<String, ContentId>) -> Result<()> {
+        if objects.is_empty() {
+            return Ok(());
+        }
+
+        // Extract all unique blob_object_ids from the objects map
+        let content_ids: Vec<&str> = objects.values().map(|s| s.as_str()).collect();
+        self.track_blob_object_ids(Self::extract_blob_object_ids(&content_ids))
+    }
+
+    /// Query Sui for any of `blob_object_ids` the tracker doesn't know yet and track them
+    fn track_blob_object_ids(&self, blob_object_ids: Vec<String>) -> Result<()> {
+        if blob_object_ids.is_empty() {
+            return Ok(());
+        }
+
+        tracing::info!(
+            "  Rehydrating blob tracker from {} unique blob(s)...",
+            blob_object_ids.len()
+        );
+
+        // Load current tracker to check what we already have
+        let mut tracker = self.load_blob_tracker()?;
+
+        // Filter to only blob_object_ids we don't already have
+        let blobs_to_query: Vec<String> = blob_object_ids
+            .into_iter()
+            .filter(|blob_id| tracker.get_blob(blob_id).is_none())
+            .collect();
+
+        if blobs_to_query.is_empty() {
+            tracing::debug!("  All blobs already tracked");
+            return Ok(());
+        }
+
+        tracing::info!("  Querying Sui for {} new blob(s)...", blobs_to_query.len());
+
+        // Create progress bar for batch queries
+        let pb = if blobs_to_query.len() > 10 {
+            let bar = ProgressBar::new(blobs_to_query.len() as u64);
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template("  {msg} [{bar:40.cyan/blue}] {pos}/{len} blobs ({eta})")
+                    .expect("Failed to create progress template")
+                    .progress_chars("█▓░"),
+            );
+            bar.set_message("Querying blob statuses");
+            Some(bar)
+        } else {
+            None
+        };
+
+        // Batch query Sui for all blob statuses with progress tracking
+        let results = {
+            let pb_clone = pb.clone();
+            self.runtime
+                .block_on(self.sui_client.get_shared_blob_statuses_batch(
+                    &blobs_to_query,
+                    Some(move |count| {
+                        if let Some(ref bar) = pb_clone {
+                            bar.inc(count as u64);
+                        }
+                    }),
+                ))?
+        };
+
+        // Finish progress bar
+        if let Some(ref bar) = pb {
+            bar.finish_with_message("Blob query complete");
+        }
+
+        // Process results
+        let mut discovered_count = 0;
+        let mut failed_count = 0;
+
+        for (i, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(status) => {
+                    tracker.track_blob(
+                        status.object_id,
+                        status.blob_id,
+                        status.end_epoch,
+                        None, // We don't know size from just object ID
+                    );
+                    discovered_count += 1;
+                }
+                Err(e) => {
+                    let blob_id = &blobs_to_query[i];
+                    tracing::debug!(
+                        "Could not get blob status for {}: {}",
+                        &blob_id[..std::cmp::min(blob_id.len(), 16)],
+                        e
+                    );
+                    failed_count += 1;
+                }
+            }
+        }
+
+        if discovered_count > 0 {
+            tracing::info!("  Discovered {} new blob(s) for tracking", discovered_count);
+            if failed_count > 0 {
+                tracing::debug!("  Failed to query {} blob(s)", failed_count);
+            }
+            self.save_blob_tracker(&tracker)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check for blob expiration warnings and emit to stderr
+    ///
+    /// Only blobs holding `state`'s objects are checked. Each warning estimates when the blob
+    /// expires and names the refs whose objects it holds.
+    fn check_blob_expiration(&self, state: &State) -> Result<()> {
+        tracing::debug!("Checking blob expiration...");
+        let tracker = self.load_blob_tracker()?;
+
+        if tracker.count() == 0 {
+            return Ok(());
+        }
+
+        let Some(fetched) = self
+            .epochs
+            .advisory(&self.walrus_client, "blob expiration warnings")
+        else {
+            return Ok(());
+        };
+        let epoch = fetched.info;
+        let current_epoch = epoch.current_epoch;
+
+        // Check for expiration warnings (filtered to this repo's blobs)
+        let content_ids: Vec<&str> = state.objects.values().map(|s| s.as_str()).collect();
+        let relevant_blob_ids = Self::extract_blob_object_ids(&content_ids);
+        let (should_warn, min_epoch, expiring_soon) = tracker.check_expiration_warning(
+            current_epoch,
+            self.config.expiration_warning_threshold,
+            Some(&relevant_blob_ids),
+        );
+
+        if should_warn {
+            tracing::warn!("WARNING: {} blob(s) expiring soon!", expiring_soon.len());
+            tracing::warn!("  Current Walrus epoch: {}", current_epoch);
+            tracing::warn!(
+                "  Warning threshold: {} epochs",
+                self.config.expiration_warning_threshold
+            );
+
+            if let Some(min) = min_epoch {
+                tracing::warn!("  Earliest expiration: epoch {}", min);
+            }
+
+            // Only the blobs listed below are mapped to refs
+            let listed: BTreeSet<&str> = expiring_soon
+                .iter()
+                .take(5)
+                .map(|blob| blob.object_id.as_str())
+                .collect();
+            let impact = self
+                .ref_impact(state, &listed, current_epoch)
+                .inspect_err(|e| tracing::debug!("Could not map blobs to refs: {:#}", e))
+                .ok();
+
+            // List expiring blobs
+            let now = chrono::Utc::now();
+            for blob in expiring_soon.iter().take(5) {
+                let epochs_remaining = blob.end_epoch.saturating_sub(current_epoch);
+                let when = estimate_expiry(&epoch, epochs_remaining, now)
+                    .map(|at| format!(" ({})", describe_expiry(at, now)))
+                    .unwrap_or_default();
+                tracing::warn!(
+                    "    - {} expires in {} epoch(s){}",
+                    &blob.blob_id[..16],
+                    epochs_remaining,
+                    when
+                );
+                if let Some(refs) = impact.as_ref().map(|i| i.refs_in(&blob.object_id)) {
+                    if !refs.is_empty() {
+                        tracing::warn!(
+                            "      objects for {} would become unreadable",
+                            list_refs(&refs)
+                        );
+                    }
+                }
+            }
+
+            if expiring_soon.len() > 5 {
+                tracing::warn!("    ... and {} more", expiring_soon.len() - 5);
+            }
+
+            tracing::warn!(
+                "  Action required: Re-upload expiring blobs or repository may become inaccessible"
+            );
+        } else {
+            tracing::info!(
+                "Tracking {} blob(s), earliest expiration at epoch {} (current: {})",
+                tracker.count(),
+                min_epoch.unwrap_or(0),
+                current_epoch
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Which of `state`'s refs have objects in each of `blob_object_ids`, from
+    /// `ref_impact.yaml` when they were mapped earlier in the `current_epoch`, else by walking
+    /// every ref
+    fn ref_impact(
+        &self,
+        state: &State,
+        blob_object_ids: &BTreeSet<&str>,
+        current_epoch: u64,
+    ) -> Result<RefImpact> {
+        let path = self.config.cache_dir.join("ref_impact.yaml");
+        let mut cache = RefImpactCache::load(&path)?;
+        if let Some(impact) = cache.get(&self.state_object_id, blob_object_ids, current_epoch) {
+            return Ok(impact);
+        }
+
+        let impact = RefImpact::build(state, blob_object_ids, |tip| {
+            let objects = collect_reachable_objects(&[tip.to_string()], state, self)?;
+            Ok(objects.into_iter().map(|obj| obj.id).collect())
+        })?;
+        cache.insert(&self.state_object_id, &impact, current_epoch);
+        cache.save(&path)?;
+        Ok(impact)
+    }
+}
+
+/// The RemoteState lock this client holds, released when dropped: on every early return or
+/// error until whatever transaction releases it succeeds and the guard is forgotten
+struct HeldLock<'a> {
+    storage: &'a WalrusStorage,
+}
+
+impl Drop for HeldLock<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self
+            .storage
+            .runtime
+            .block_on(self.storage.sui_client.release_lock())
+        {
+            tracing::warn!("Failed to release lock: {:#}", e);
+        }
+    }
+}
+
+/// `refs/heads/main, refs/tags/v1 and 3 more`
+fn list_refs(refs: &[&str]) -> String {
+    const SHOWN: usize = 3;
+    let mut listed = refs[..refs.len().min(SHOWN)].join(", ");
+    if refs.len() > SHOWN {
+        listed.push_str(&format!(" and {} more", refs.len() - SHOWN));
+    }
+    listed
+}
+
+impl WalrusStorage {
+    /// Blob object IDs reachable from the current state (the state at the pin, for a pinned
+    /// remote): objects, metadata and the objects map (with its parts, if chunked)
+    fn referenced_blob_object_ids(&self) -> Result<BTreeSet<String>> {
+        let state = self.read_state()?;
+        let content_ids: Vec<&str> = state
+            .objects
+            .values()
+            .chain(&state.metadata)
+            .map(|s| s.as_str())
+            .collect();
+        let mut referenced: BTreeSet<String> = Self::extract_blob_object_ids(&content_ids)
+            .into_iter()
+            .collect();
+        // The objects map the state was read from; a state this process wrote was not read
+        let read_from = self
+            .read_from
+            .borrow()
+            .as_ref()
+            .map(|(_, _, objects_object_id)| objects_object_id.clone());
+        let objects_object_id = match read_from {
+            Some(objects_object_id) => objects_object_id,
+            None => self.state_source()?.2,
+        };
+        referenced.extend(objects_object_id);
+        referenced.extend(self.objects_map_parts.borrow().iter().cloned());
+        Ok(referenced)
+    }
+
+    /// Expiry of every blob the current state references
+    ///
+    /// Blobs missing from the local tracker are looked up on Sui first; any that still can't
+    /// be found are listed as untracked.
+    pub fn expiry_report(&self) -> Result<ExpiryReport> {
+        let referenced = self.referenced_blob_object_ids()?;
+        self.track_blob_object_ids(referenced.iter().cloned().collect())?;
+
+        let tracker = self.load_blob_tracker()?;
+        let epoch = self
+            .epochs
+            .required(&self.walrus_client, "Reporting blob expiry")?;
+
+        Ok(ExpiryReport::build(
+            &tracker,
+            &referenced,
+            &epoch.info,
+            epoch.fetched_at,
+        ))
+    }
+
+    /// Health banner for `state`, unless one was shown for this remote within the last hour
+    ///
+    /// When the banner is last shown is recorded in `banner.yaml` under the cache directory.
+    fn banner(&self, state: &State) -> Result<Option<Banner>> {
+        let path = self.config.cache_dir.join("banner.yaml");
+        let mut log = BannerLog::load(&path)?;
+        if !log.take_due(&self.state_object_id, chrono::Utc::now()) {
+            return Ok(None);
+        }
+        log.save(&path)?;
+
+        let report = self.expiry_report()?;
+        Ok(Some(Banner {
+            state_object_id: self.state_object_id.clone(),
+            objects: state.objects.len(),
+            earliest: report.blobs.into_iter().next(),
+            untracked: report.untracked.len(),
+        }))
+    }
+
+    /// Who last updated each of `refs` (full ref name -> SHA-1), where the history shows it
+    ///
+    /// Results are cached in `ref_history.yaml` under the cache directory, so only refs that
+    /// moved since the last call are looked up on chain.
+    pub fn ref_updates(
+        &self,
+        refs: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, RefUpdate>> {
+        let path = self.config.cache_dir.join("ref_history.yaml");
+        let mut cache = RefHistoryCache::load(&path)?;
+
+        let mut found = BTreeMap::new();
+        let mut missing = BTreeMap::new();
+        for (name, sha) in refs {
+            match cache.get(&self.state_object_id, name, sha) {
+                Some(update) => {
+                    found.insert(name.clone(), update.clone());
+                }
+                None => {
+                    missing.insert(name.clone(), sha.clone());
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(found);
+        }
+
+        tracing::info!("Reading history for {} ref(s)...", missing.len());
+        let updates = self
+            .runtime
+            .block_on(self.sui_client.ref_updates(&missing))?;
+        for (name, update) in updates {
+            cache.insert(
+                &self.state_object_id,
+                &name,
+                &missing[&name],
+                update.clone(),
+            );
+            found.insert(name, update);
+        }
+        cache.save(&path)?;
+        Ok(found)
+    }
+
+    /// Extend referenced blobs expiring within `expiration_warning_threshold` epochs
+    ///
+    /// Each blob is extended by `default_epochs`; the tracker is saved even if some
+    /// extensions fail, so those are simply retried on the next pass.
+    pub fn renew_expiring(&self) -> Result<RenewalPass> {
+        self.ensure_spending_allowed()?;
+
+        // The blobs kept pinned states read are renewed too, and never repacked
+        let pinned = self.pinned_blob_object_ids()?;
+        let mut referenced = self.referenced_blob_object_ids()?;
+        referenced.extend(pinned.iter().cloned());
+        self.track_blob_object_ids(referenced.iter().cloned().collect())?;
+
+        let epoch = self
+            .epochs
+            .required(&self.walrus_client, "Renewing blobs")?
+            .info;
+
+        let tracker = self.load_blob_tracker()?;
+        let expiring: Vec<TrackedBlob> =
+            ExpiryReport::build(&tracker, &referenced, &epoch, chrono::Utc::now())
+                .expiring_within(self.config.expiration_warning_threshold)
+                .into_iter()
+                .filter(|blob| blob.end_epoch > epoch.current_epoch)
+                .filter_map(|blob| tracker.get_blob(&blob.object_id).cloned())
+                .collect();
+        let repacked = match self.repack_sparse_blobs(expiring, &pinned) {
+            Ok(repacked) => repacked,
+            Err(e) => {
+                tracing::warn!("Not repacking sparse blobs this pass: {:#}", e);
+                Vec::new()
+            }
+        };
+        if !repacked.is_empty() {
+            // The repacked blobs are no longer referenced, and their replacements are fresh
+            referenced = self.referenced_blob_object_ids()?;
+            referenced.extend(pinned.iter().cloned());
+        }
+
+        let mut tracker = self.load_blob_tracker()?;
+        let mut pass = crate::walrus::renew_expiring(
+            &self.walrus_client,
+            &mut tracker,
+            &referenced,
+            &epoch,
+            self.config.expiration_warning_threshold,
+            self.config.default_epochs,
+        );
+        pass.repacked = repacked;
+        self.save_blob_tracker(&tracker)?;
+
+        Ok(pass)
+    }
+
+    /// Repack every referenced batched blob that is mostly dead, however far off its expiry
+    ///
+    /// `compact` drops unreachable objects from the map, leaving the batches that held them
+    /// sparse; this copies what is still live out of them. The old blobs are left to expire
+    /// (or to `reclaim`).
+    pub fn repack_sparse(&self) -> Result<Vec<Repacked>> {
+        self.ensure_spending_allowed()?;
+
+        let referenced = self.referenced_blob_object_ids()?;
+        self.track_blob_object_ids(referenced.iter().cloned().collect())?;
+        let epoch = self
+            .epochs
+            .required(&self.walrus_client, "Repacking blobs")?
+            .info;
+        let tracker = self.load_blob_tracker()?;
+        let candidates: Vec<TrackedBlob> = referenced
+            .iter()
+            .filter_map(|object_id| tracker.get_blob(object_id))
+            .filter(|blob| blob.end_epoch > epoch.current_epoch)
+            .cloned()
+            .collect();
+        self.repack_sparse_blobs(candidates, &self.pinned_blob_object_ids()?)
+    }
+
+    /// Blob object IDs the states of the `keep_pinned` transactions read from
+    fn pinned_blob_object_ids(&self) -> Result<BTreeSet<String>> {
+        let mut pinned = BTreeSet::new();
+        for digest in &self.config.keep_pinned {
+            let mut config = self.config.clone();
+            config.pinned_transaction = Some(digest.clone());
+            let storage = Self::new(
+                self.state_object_id.clone(),
+                self.remote_name.clone(),
+                config,
+            )?;
+            pinned.extend(
+                storage
+                    .referenced_blob_object_ids()
+                    .with_context(|| format!("Failed to read the state kept at {}", digest))?,
+            );
+        }
+        Ok(pinned)
+    }
+
+    /// Copy the live objects of `candidates` that are mostly dead batched blobs into new blobs
+    ///
+    /// An object is live while the objects map still reads it from the blob and a ref reaches
+    /// it, or a value a ref held earlier in the remote's history does (what a URL pinned to
+    /// that transaction checks out). Blobs in `pinned` are left as they are: the kept pinned
+    /// states read them under their old ContentIds. The map is rewritten to read live objects
+    /// from the new blob and forgets dead ones, so the old blob is no longer referenced and
+    /// isn't renewed.
+    fn repack_sparse_blobs(
+        &self,
+        candidates: Vec<TrackedBlob>,
+        pinned: &BTreeSet<String>,
+    ) -> Result<Vec<Repacked>> {
+        let candidates: Vec<TrackedBlob> = candidates
+            .into_iter()
+            .filter(|blob| !pinned.contains(&blob.object_id))
+            .collect();
+        if candidates.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut tracker = self.load_blob_tracker()?;
+        let state = self.read_state()?;
+        let history = self
+            .runtime
+            .block_on(self.sui_client.ref_history_tips())
+            .context("Failed to read the remote's ref history")?;
+        let tips: Vec<String> = state
+            .refs
+            .values()
+            .cloned()
+            .chain(
+                history
+                    .into_iter()
+                    .filter(|tip| state.objects.contains_key(tip)),
+            )
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let reachable: HashSet<String> = collect_reachable_objects(&tips, &state, self)?
+            .into_iter()
+            .map(|obj| obj.id)
+            .collect();
+
+        let mut planned = Vec::new();
+        for blob in candidates {
+            let manifest = BlobManifest::load_or_rebuild(
+                &self.config.cache_dir,
+                &blob.object_id,
+                &state.objects,
+            )?;
+            if manifest.entries.is_empty() {
+                continue;
+            }
+            let live = manifest.live(&blob.object_id, &state.objects, |sha| {
+                reachable.contains(sha)
+            });
+            if !manifest.worth_repacking(&live) {
+                continue;
+            }
+
+            let new = if live.is_empty() {
+                None
+            } else {
+                let content = self
+                    .walrus_client
+                    .read(&blob.blob_id)
+                    .with_context(|| format!("Failed to read blob {}", blob.object_id))?;
+                let repack = Repack::build(&content, &live)?;
+                let epochs = blob.retention_epochs.unwrap_or(self.config.default_epochs);
+                self.preflight(&[repack.content.len() as u64], epochs, 1)?;
+                tracing::info!(
+                    "Repacking {} live object(s) of {} ({} of {} bytes)",
+                    live.len(),
+                    blob.object_id,
+                    repack.content.len(),
+                    manifest.size()
+                );
+                let stored = self
+                    .walrus_client
+                    .store_with_epochs(&repack.content, epochs)
+                    .context("Failed to store repacked blob in Walrus")?;
+                if let Ok(status) = self.runtime.block_on(
+                    self.sui_client
+                        .get_shared_blob_status(&stored.shared_object_id),
+                ) {
+                    tracker.insert(self.uploaded_blob(
+                        status.object_id,
+                        status.blob_id,
+                        status.end_epoch,
+                        repack.content.len() as u64,
+                        blob.retention_epochs,
+                    ));
+                }
+                if repack.manifest.entries.len() > 1 {
+                    if let Err(e) = repack
+                        .manifest
+                        .save(&self.config.cache_dir, &stored.shared_object_id)
+                    {
+                        tracing::warn!("Failed to save blob manifest: {:#}", e);
+                    }
+                }
+                Some((stored.shared_object_id, repack))
+            };
+            let saved = manifest.size() - new.as_ref().map_or(0, |(_, r)| r.content.len() as u64);
+            planned.push((blob.object_id, manifest, new, saved));
+        }
+        if planned.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Track the new blobs even if the state update below fails
+        self.save_blob_tracker(&tracker)?;
+
+        let mut repacked = Vec::new();
+        self.update_state(|current| {
+            if current.refs != state.refs {
+                anyhow::bail!("refs changed while repacking; the next pass will retry");
+            }
+            for (old_id, old, new, bytes_saved) in &planned {
+                let (moved, dropped) = rewrite_objects(
+                    &mut current.objects,
+                    old_id,
+                    old,
+                    new.as_ref()
+                        .map(|(id, repack)| (id.as_str(), &repack.manifest)),
+                );
+                repacked.push(Repacked {
+                    old_object_id: old_id.clone(),
+                    new_object_id: new.as_ref().map(|(id, _)| id.clone()),
+                    moved,
+                    dropped,
+                    bytes_saved: *bytes_saved,
+                });
+            }
+            Ok(())
+        })?;
+
+        let mut cache_index = self.load_cache_index()?;
+        for (old_id, _, new, _) in &planned {
+            Self::forget_blob(&mut cache_index, old_id);
+            let Some((new_id, repack)) = new else {
+                continue;
+            };
+            for (entry, content_id) in repack.manifest.uploaded_content_ids(new_id) {
+                let slice =
+                    &repack.content[entry.offset as usize..(entry.offset + entry.length) as usize];
+                cache_index.insert(
+                    content_id,
+                    Self::compute_sha256(slice),
+                    ContentKind::RawLoose,
+                );
+            }
+        }
+        self.save_cache_index(&mut cache_index)?;
+
+        Ok(repacked)
+    }
+
+    /// Forget cached ContentIds that point into `blob_object_id` so they are not reused
+    fn forget_blob(cache_index: &mut CacheIndex, blob_object_id: &str) {
+        let stale: Vec<String> = cache_index
+            .all_object_ids()
+            .filter(|id| {
+                ParsedContentId::parse(id)
+                    .is_ok_and(|parsed| parsed.blob_object_id() == Some(blob_object_id))
+            })
+            .cloned()
+            .collect();
+        for id in stale {
+            cache_index.remove_by_object_id(&id);
+        }
+    }
+
+    /// Delete deletable blobs uploaded by this remote that the current state no longer references
+    pub fn reclaim(&self) -> Result<ReclaimReport> {
+        if self.walrus_client.persistence() == BlobPersistence::Permanent {
+            anyhow::bail!(
+                "remote {} stores permanent blobs, which cannot be deleted before they expire; \
+                 set `blob_persistence: deletable` (or `?blob_persistence=deletable` in the URL) \
+                 for remotes whose storage you want to reclaim",
+                self.state_object_id
+            );
+        }
+        self.ensure_spending_allowed()?;
+
+        // Everything reachable from the current state must be kept
+        let referenced: HashSet<String> = self.referenced_blob_object_ids()?.into_iter().collect();
+
+        let mut tracker = self.load_blob_tracker()?;
+        let candidates: Vec<TrackedBlob> = tracker
+            .reclaimable(&self.state_object_id, &referenced)
+            .into_iter()
+            .cloned()
+            .collect();
+
+        tracing::info!(
+            "Reclaiming {} unreferenced deletable blob(s) ({} referenced)",
+            candidates.len(),
+            referenced.len()
+        );
+
+        let mut cache_index = self.load_cache_index()?;
+        let mut report = ReclaimReport::default();
+        for blob in candidates {
+            self.walrus_client
+                .delete(&blob.object_id)
+                .with_context(|| format!("Failed to delete blob object {}", blob.object_id))?;
+
+            Self::forget_blob(&mut cache_index, &blob.object_id);
+
+            tracker.untrack_blob(&blob.object_id);
+            report.bytes += blob.size.unwrap_or(0);
+            report.deleted.push(blob.object_id);
+        }
+
+        self.save_cache_index(&mut cache_index)?;
+        self.save_blob_tracker(&tracker)?;
+
+        Ok(report)
+    }
+
+    /// Remove dead entries (tombstoned refs, or refs whose tip the objects map lacks) from the
+    /// on-chain refs table, reclaiming their storage
+    ///
+    /// The state is re-read once the push lock is held, so a ref a concurrent push just updated
+    /// is judged by its new tip; live refs are never removed.
+    pub fn compact_refs(&self) -> Result<RefCompaction> {
+        self.ensure_spending_allowed()?;
+
+        tracing::info!("  Acquiring lock on RemoteState...");
+        let lock = self
+            .runtime
+            .block_on(self.sui_client.acquire_lock(300_000))
+            .context("Failed to acquire lock on RemoteState")?;
+        if let Some(stale) = &lock.broke_stale {
+            tracing::warn!(
+                "git-remote-walrus: broke a stale lock left by an unfinished push ({})",
+                stale
+            );
+        }
+
+        let held = HeldLock { storage: self };
+
+        *self.cached_state.borrow_mut() = None;
+        let state = self.read_state()?;
+        // An unreadable or missing objects map would make every ref look dangling
+        if state.objects.is_empty() && !state.refs.is_empty() {
+            anyhow::bail!("the objects map is empty; refusing to treat every ref as dead");
+        }
+        // Empty values are tombstones too, though reads set them aside as invalid
+        let refs: BTreeMap<String, String> = state
+            .refs
+            .iter()
+            .chain(state.invalid_refs.iter().filter(|(_, sha)| sha.is_empty()))
+            .map(|(name, sha)| (name.clone(), sha.clone()))
+            .collect();
+        let removed = dead_refs(&refs, &state.objects);
+        tracing::info!(
+            "Compacting {} of {} refs table entries",
+            removed.len(),
+            state.refs.len()
+        );
+
+        let names: Vec<String> = removed.iter().map(|dead| dead.name.clone()).collect();
+        let storage_rebate_mist = self
+            .runtime
+            .block_on(
+                self.sui_client
+                    .delete_refs_and_release_lock(&names, lock.state_ref),
+            )
+            .context("Failed to remove dead refs")?;
+        // The last batch released the lock
+        std::mem::forget(held);
+        *self.cached_state.borrow_mut() = None;
+
+        Ok(RefCompaction {
+            removed,
+            storage_rebate_mist,
+        })
+    }
+
+    /// Re-upload every object the objects map reads from a blob into as few blobs of the
+    /// maximum size as possible, and point the map at them
+    ///
+    /// Objects are grouped by the epochs `retention_rules` give the refs that reach them, and
+    /// each group is read `CONSOLIDATE_READ_BATCH` objects at a time, so no more than about
+    /// two maximum-size blobs are held in memory. Objects a concurrent push remapped keep their
+    /// new mapping. The old blobs aren't deleted; nothing references them any more, so they're
+    /// left to expire (or to `reclaim`).
+    pub fn consolidate_blobs(&self) -> Result<Consolidation> {
+        self.ensure_spending_allowed()?;
+        self.ensure_encoding_supported()?;
+
+        *self.cached_state.borrow_mut() = None;
+        let state = self.read_state()?;
+        let stored: Vec<(&str, &str)> = state
+            .objects
+            .iter()
+            .filter(|(_, content_id)| {
+                ParsedContentId::parse(content_id)
+                    .is_ok_and(|parsed| parsed.blob_object_id().is_some())
+            })
+            .map(|(git_sha1, content_id)| (git_sha1.as_str(), content_id.as_str()))
+            .collect();
+        let blobs_before = blob_count(&state.objects);
+        let max_blob_size = std::cmp::min(
+            self.config.max_batch_blob_size,
+            self.get_max_blob_size()
+                .context("Failed to get network blob size limit")?,
+        );
+
+        let mut cache_index = self.load_cache_index()?;
+        let mut consolidated: BTreeMap<String, ContentId> = BTreeMap::new();
+        let mut bytes = 0;
+        for (epochs, objects) in self.objects_by_retention(&state, &stored)? {
+            let group: BTreeMap<String, ContentId> = objects
+                .iter()
+                .map(|(git_sha1, content_id)| (git_sha1.to_string(), content_id.to_string()))
+                .collect();
+            if blob_count(&group) <= 1 {
+                continue;
+            }
+            tracing::info!(
+                "Reading {} objects kept for {} epochs from {} blob(s)...",
+                objects.len(),
+                epochs,
+                blob_count(&group)
+            );
+
+            let mut pending: Vec<(String, Vec<u8>)> = Vec::new();
+            let mut pending_bytes = 0;
+            let mut read = 0;
+            for chunk in objects.chunks(CONSOLIDATE_READ_BATCH) {
+                let contents = self
+                    .read_git_objects(chunk)
+                    .context("Failed to read the remote's objects")?;
+                read += chunk.len();
+                pending_bytes += contents.iter().map(|c| c.len() as u64).sum::<u64>();
+                pending.extend(
+                    chunk
+                        .iter()
+                        .map(|(git_sha1, _)| git_sha1.to_string())
+                        .zip(contents),
+                );
+                let last = read == objects.len();
+                if pending_bytes < max_blob_size && !last {
+                    continue;
+                }
+
+                let mut blobs = consolidate(std::mem::take(&mut pending), max_blob_size);
+                // The least full blob waits for the next objects, unless these are the last
+                if !last {
+                    if let Some(rest) = blobs.pop() {
+                        pending = rest
+                            .slices()
+                            .map(|(git_sha1, content)| (git_sha1.to_string(), content.to_vec()))
+                            .collect();
+                    }
+                }
+                pending_bytes = pending.iter().map(|(_, c)| c.len() as u64).sum();
+
+                let uploads: Vec<(&str, &[u8])> = blobs.iter().flat_map(Repack::slices).collect();
+                let to_upload = uploads
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (_, content))| (i, *content, Self::compute_sha256(content)))
+                    .collect();
+                let mut content_ids = vec![None; uploads.len()];
+                self.upload_batches(
+                    to_upload,
+                    Some(epochs),
+                    max_blob_size,
+                    &mut cache_index,
+                    &mut content_ids,
+                )
+                .context("Failed to store consolidated objects in Walrus")?;
+                for ((git_sha1, content), content_id) in uploads.iter().zip(content_ids) {
+                    bytes += content.len() as u64;
+                    consolidated.insert(
+                        git_sha1.to_string(),
+                        content_id.expect("every uploaded object has a ContentId"),
+                    );
+                }
+            }
+        }
+        if consolidated.is_empty() {
+            tracing::info!("Already in {} blob(s); nothing to repack", blobs_before);
+            return Ok(Consolidation {
+                blobs_before,
+                blobs_after: blobs_before,
+                ..Default::default()
+            });
+        }
+
+        // Re-read, so objects pushed while uploading keep their mappings
+        *self.cached_state.borrow_mut() = None;
+        let (mut moved, mut blobs_after) = (0, 0);
+        self.update_state(|current| {
+            moved = rewrite_consolidated(&mut current.objects, &state.objects, &consolidated);
+            blobs_after = blob_count(&current.objects);
+            Ok(())
+        })?;
+
+        for (git_sha1, content_id) in &stored {
+            if !consolidated.contains_key(*git_sha1) {
+                continue;
+            }
+            if let Some(blob_object_id) = ParsedContentId::parse(content_id)
+                .ok()
+                .and_then(|parsed| parsed.blob_object_id().map(str::to_string))
+            {
+                Self::forget_blob(&mut cache_index, &blob_object_id);
+            }
+        }
+        self.save_cache_index(&mut cache_index)?;
+
+        Ok(Consolidation {
+            blobs_before,
+            blobs_after,
+            moved,
+            bytes,
+        })
+    }
+
+    /// The `stored` objects grouped by the epochs to keep them for, most first
+    ///
+    /// An object is kept as long as the longest `retention_rules` entry among the refs that
+    /// reach it, as a push of those refs would have stored it; objects no ref reaches get
+    /// `default_epochs`.
+    #[allow(clippy::type_complexity)]
+    fn objects_by_retention<'a>(
+        &self,
+        state: &State,
+        stored: &[(&'a str, &'a str)],
+    ) -> Result<Vec<(u32, Vec<(&'a str, &'a str)>)>> {
+        let mut tips_by_epochs: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+        for (refname, tip) in &state.refs {
+            tips_by_epochs
+                .entry(self.config.retention_for(refname).epochs)
+                .or_default()
+                .push(tip.clone());
+        }
+
+        // Longest retention first; each walk stops at what a longer one reached
+        let mut epochs_of: HashMap<String, u32> = HashMap::new();
+        for (epochs, tips) in tips_by_epochs.into_iter().rev() {
+            let reached: HashSet<String> = epochs_of.keys().cloned().collect();
+            let mut visit = |walked: Walked| -> Result<()> {
+                if let Walked::Objects(level) = walked {
+                    for obj in level {
+                        epochs_of.entry(obj.id).or_insert(epochs);
+                    }
+                }
+                Ok(())
+            };
+            walk_reachable_objects(
+                &tips,
+                state,
+                self,
+                PrefetchStrategy::None,
+                None,
+                Some(&reached),
+                &mut visit,
+            )?;
+        }
+
+        let mut groups: BTreeMap<u32, Vec<(&str, &str)>> = BTreeMap::new();
+        for &(git_sha1, content_id) in stored {
+            let epochs = epochs_of
+                .get(git_sha1)
+                .copied()
+                .unwrap_or(self.config.default_epochs);
+            groups
+                .entry(epochs)
+                .or_default()
+                .push((git_sha1, content_id));
+        }
+        Ok(groups.into_iter().rev().collect())
+    }
+
+    /// Read objects, grouping cache misses by blob so each blob is downloaded once
+    ///
+    /// Blobs are downloaded in the order their objects were requested, and the other objects
+    /// the state places in a downloaded blob are cached with it, so a fetch reading history a
+    /// level at a time never downloads the same blob twice.
+    ///
+    /// Cache hits are checked against the SHA-256 they are cached by and, with
+    /// `expected_git_sha1s` (parallel to `ids`), every object against its git SHA-1; a
+    /// mismatched hit is read again, and a mismatched download is never cached.
+    fn read_objects_checked(
+        &self,
+        ids: &[&str],
+        expected_git_sha1s: Option<&[&str]>,
+    ) -> Result<Vec<Vec<u8>>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Store results in original order
+        let mut results: Vec<Option<Vec<u8>>> = vec![None; ids.len()];
+
+        // Parse all ContentIds first
+        let parsed_ids: Result<Vec<ParsedContentId>> = ids
+            .iter()
+            .map(|id| {
+                ParsedContentId::parse(id)
+                    .with_context(|| format!("Invalid ContentId format: {}", id))
+            })
+            .collect();
+        let parsed_ids = parsed_ids?;
+
+        // Load cache index once for all lookups
+        let mut cache_index = self.load_cache_index()?;
+        let siblings = self.sibling_indexes();
+        let indexed = cache_index.len();
+
+        let mut misses = Vec::new();
+        let mut cache_hits = 0;
+
+        for (idx, parsed_id) in parsed_ids.into_iter().enumerate() {
+            if let Some(content) = parsed_id.constant_content() {
+                if let Some(shas) = expected_git_sha1s {
+                    verify_git_object(shas[idx], content)?;
+                }
+                self.note_source(ids[idx], ReadSource::Constant);
+                results[idx] = Some(content.to_vec());
+                cache_hits += 1;
+                continue;
+            }
+
+            // Check if this object is already in cache
+            let git_sha1 = expected_git_sha1s.map(|shas| shas[idx]);
+            if let Some(content) =
+                Self::read_cached(&self.cache, &mut cache_index, &siblings, ids[idx], git_sha1)
+            {
+                tracing::debug!(
+                    "Cache hit for ContentId {}",
+                    &ids[idx][..std::cmp::min(ids[idx].len(), 16)]
+                );
+                self.note_source(ids[idx], ReadSource::Cache);
+                results[idx] = Some(content);
+                cache_hits += 1;
+                continue;
+            }
+
+            // Cache miss - need to fetch from Walrus
+            misses.push((idx, parsed_id));
+        }
+        if cache_index.len() != indexed {
+            let _ = self.save_cache_index(&mut cache_index); // Ignore errors on index write
+        }
+
+        // Group misses by blob so each is downloaded once, in the order they were requested
+        let mut blob_groups = Self::download_order(misses);
+        if self.config.prefetch_strategy == PrefetchStrategy::Size {
+            Self::largest_first(&mut blob_groups);
+        }
+
+        if cache_hits > 0 {
+            tracing::debug!("{} cache hits out of {} objects", cache_hits, ids.len());
+        }
+
+        if blob_groups.is_empty() {
+            // All cache hits
+            return Ok(results.into_iter().map(|r| r.unwrap()).collect());
+        }
+
+        tracing::info!(
+            "Batch reading {} objects from {} unique blob(s)",
+            ids.len() - cache_hits,
+            blob_groups.len()
+        );
+
+        // Download up to `download_concurrency` blobs at once, holding at most that many in
+        // memory before their objects are extracted and cached
+        let download_concurrency = self.config.concurrency().download;
+        let mut blob_groups = blob_groups.into_iter().peekable();
+        while blob_groups.peek().is_some() {
+            let wave: Vec<_> = blob_groups.by_ref().take(download_concurrency).collect();
+            let blob_object_ids: Vec<&str> = wave.iter().map(|(id, _)| id.as_str()).collect();
+            let blobs = self.read_blobs(&blob_object_ids)?;
+
+            // Extract, verify and cache each object that needs each blob
+            for ((blob_object_id, items), (full_blob, source)) in wave.into_iter().zip(blobs) {
+                let mut cache_index = self.load_cache_index()?;
+                for (idx, parsed_id) in items {
+                    let extracted = Self::extract_and_cache(
+                        &self.cache,
+                        &mut cache_index,
+                        ids[idx],
+                        &parsed_id,
+                        &full_blob,
+                        expected_git_sha1s.map(|shas| shas[idx]),
+                    );
+                    if extracted.is_err() && source == ReadSource::Cache {
+                        // A damaged cached blob is downloaded again by the next read
+                        self.blob_cache.remove(&blob_object_id);
+                    }
+                    let content = extracted?;
+                    self.note_source(ids[idx], source);
+                    results[idx] = Some(content);
+                }
+                self.cache_blob_members(&mut cache_index, &blob_object_id, &full_blob);
+                let _ = self.save_cache_index(&mut cache_index); // Ignore errors on index write
+            }
+        }
+
+        // Ensure all results are populated
+        Ok(results
+            .into_iter()
+            .map(|r| r.expect("All results should be populated"))
+            .collect())
+    }
+
+    /// Write one object, uploading it for `epochs` epochs (None uses `default_epochs`)
+    fn write_object_with_epochs(&self, content: &[u8], epochs: Option<u32>) -> Result<ContentId> {
+        if let Some(constant) = ParsedContentId::constant(content) {
+            return Ok(constant.encode());
+        }
+        let sha256 = Self::compute_sha256(content);
+
+        // 1. Check if already in cache (by sha256)
+        let mut cache_index = self.load_cache_index()?;
+
+        if let Some(object_id) = cache_index.get_object_id(&sha256, ContentKind::RawLoose) {
+            // Already cached, return object_id
+            tracing::debug!(
+                "Object '{}...' already cached as '{}...'",
+                &sha256[..8],
+                &object_id[..16]
+            );
+            return Ok(object_id.clone());
+        }
+
+        // 2. Upload to Walrus
+        self.ensure_spending_allowed()?;
+        self.ensure_encoding_supported()?;
+        let store_epochs = epochs.unwrap_or(self.config.default_epochs);
+        self.preflight(&[content.len() as u64], store_epochs, 0)?;
+        tracing::info!(
+            "Uploading object '{}...' ({} bytes)",
+            &sha256[..8],
+            content.len()
+        );
+        let blob_info = self
+            .walrus_client
+            .store_with_epochs(content, store_epochs)
+            .context("Failed to store object in Walrus")?;
+
+        // 3. Store in local cache
+        self.cache
+            .write_object(content)
+            .context("Failed to cache object locally")?;
+
+        // 4. Update cache index (use shared_object_id as ContentId)
+        Self::index_cached(
+            &mut cache_index,
+            blob_info.shared_object_id.clone(),
+            sha256.clone(),
+            content,
+        );
+        self.save_cache_index(&mut cache_index)?;
+
+        // 5. Get blob status from Sui and track expiration
+        match self.runtime.block_on(
+            self.sui_client
+                .get_shared_blob_status(&blob_info.shared_object_id),
+        ) {
+            Ok(status) => {
+                let mut tracker = self.load_blob_tracker()?;
+                tracker.insert(self.uploaded_blob(
+                    status.object_id,
+                    status.blob_id,
+                    status.end_epoch,
+                    content.len() as u64,
+                    epochs,
+                ));
+                self.save_blob_tracker(&tracker)?;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to get blob status from Sui: {} [shared_object_id: {}]",
+                    e,
+                    blob_info.shared_object_id
+                );
+            }
+        }
+
+        Ok(blob_info.shared_object_id)
+    }
+}
+
+impl ImmutableStore for WalrusStorage {
+    fn write_object(&self, content: &[u8]) -> Result<ContentId> {
+        self.write_object_with_epochs(content, None)
+    }
+
+    fn write_objects(&self, contents: &[&[u8]]) -> Result<Vec<ContentId>> {
+        self.write_objects_with_epochs(contents, None)
+    }
+
+    fn write_objects_with_epochs(
+        &self,
+        contents: &[&[u8]],
+        epochs: Option<u32>,
+    ) -> Result<Vec<ContentId>> {
+        if contents.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // If batching is disabled, fall back to sequential writes
+        if !self.config.enable_batching {
+            tracing::debug!("Batching disabled, using sequential writes");
+            return contents
+                .iter()
+                .map(|content| self.write_object_with_epochs(content, epochs))
+                .collect();
+        }
+
+        // Get the effective max blob size (minimum of config and network limit)
+        let network_max_blob_size = self
+            .get_max_blob_size()
+            .context("Failed to get network blob size limit")?;
+        let max_batch_blob_size =
+            std::cmp::min(self.config.max_batch_blob_size, network_max_blob_size);
+
+        tracing::info!(
+            "Processing {} objects (batching enabled, effective max blob size: {:.2} MB, config: {:.2} MB, network: {:.2} MB)",
+            contents.len(),
+            max_batch_blob_size as f64 / (1024.0 * 1024.0),
+            self.config.max_batch_blob_size as f64 / (1024.0 * 1024.0),
+            network_max_blob_size as f64 / (1024.0 * 1024.0)
+        );
+
+        // Load cache index once for all lookups
+        let mut cache_index = self.load_cache_index()?;
+
+        // Separate already-cached objects from those that need uploading
+        // (result ContentIds are kept in the same order as input)
+        let (mut result_content_ids, objects_to_upload) =
+            Self::partition_cached(&cache_index, contents);
+
+        if objects_to_upload.is_empty() {
+            tracing::info!("All {} objects already cached", contents.len());
+            return Ok(result_content_ids
+                .into_iter()
+                .map(|id| id.unwrap())
+                .collect());
+        }
+
+        self.ensure_spending_allowed()?;
+        self.ensure_encoding_supported()?;
+
+        tracing::info!(
+            "Need to upload {} new objects ({} already cached)",
+            objects_to_upload.len(),
+            contents.len() - objects_to_upload.len()
+        );
+
+        self.upload_batches(
+            objects_to_upload,
+            epochs,
+            max_batch_blob_size,
+            &mut cache_index,
+            &mut result_content_ids,
+        )?;
+
+        // Ensure all results are populated
+        Ok(result_content_ids
+            .into_iter()
+            .map(|id| id.expect("All ContentIds should be populated"))
+            .collect())
+    }
+
+    fn read_object(&self, id: &str) -> Result<Vec<u8>> {
+        // With the git SHA-1 the state maps to `id`, the object is verified and can be found
+        // cached under another remote's ContentId
+        let git_sha1 = self.git_sha1_of(id);
+        let expected = git_sha1.as_deref().map(|git_sha1| [git_sha1]);
+        Ok(self
+            .read_objects_checked(&[id], expected.as_ref().map(|expected| &expected[..]))?
+            .pop()
+            .expect("one object per ID"))
+    }
+
+    fn read_objects(&self, ids: &[&str]) -> Result<Vec<Vec<u8>>> {
+        self.read_objects_checked(ids, None)
+    }
+
+    fn read_git_objects(&self, objects: &[(&str, &str)]) -> Result<Vec<Vec<u8>>> {
+        let (git_sha1s, ids): (Vec<&str>, Vec<&str>) = objects.iter().copied().unzip();
+        self.read_objects_checked(&ids, Some(&git_sha1s))
+    }
+
+    fn delete_object(&self, id: &str) -> Result<()> {
+        // Walrus is immutable, so we only delete from cache
+        let cache_index = self.load_cache_index()?;
+
+        if let Some(sha256) = cache_index.get_sha256(id, ContentKind::RawLoose) {
+            self.cache.delete_object(sha256)?;
+        }
+
+        // Note: We don't remove from cache_index or blob_tracker
+        // as the blob still exists on Walrus
+
+        Ok(())
+    }
+
+    fn object_exists(&self, id: &str) -> Result<bool> {
+        // Check cache index
+        let cache_index = self.load_cache_index()?;
+
+        if cache_index.contains_object(id) {
+            return Ok(true);
+        }
+
+        // Could query Sui for object, but for now assume not exists
+        Ok(false)
+    }
+
+    fn read_source(&self, id: &str) -> Option<ReadSource> {
+        self.read_sources.borrow().get(id).copied()
+    }
+
+    fn cache_stored_objects(&self, objects: &[(&[u8], &str)]) -> Result<()> {
+        let mut cache_index = self.load_cache_index()?;
+        let seeded = Self::seed_cache(&self.cache, &mut cache_index, objects)?;
+        if seeded > 0 {
+            tracing::debug!("Cached {} objects the remote already stores", seeded);
+            self.save_cache_index(&mut cache_index)?;
+        }
+        Ok(())
+    }
+}
+
+impl MutableState for WalrusStorage {
+    fn read_state(&self) -> Result<State> {
+        // Check if we have a cached state
+        if let Some(cached) = self.cached_state.borrow().as_ref() {
+            tracing::debug!(
+                "git-remote-walrus: Using cached state ({} refs, {} objects)",
+                cached.refs.len(),
+                cached.objects.len()
+            );
+            return Ok(cached.clone());
+        }
+
+        tracing::info!(
+            "git-remote-walrus: Reading state from {}",
+            &self.state_object_id
+        );
+
+        // Read refs and objects_blob_object_id from Sui on-chain
+        let (refs, on_chain_symrefs, objects_object_id) = self.state_source()?;
+        *self.read_from.borrow_mut() = Some((
+            refs.clone(),
+            on_chain_symrefs.clone(),
+            objects_object_id.clone(),
+        ));
+
+        tracing::info!("  Retrieved {} refs from Sui", refs.len());
+
+        // Download objects map from Walrus if it exists, once its format is known to be readable
+        let mut recorded_format = None;
+        let mut objects: BTreeMap<String, ContentId> = if let Some(object_id) = objects_object_id {
+            recorded_format = Some(self.recorded_format(&object_id)?);
+            let (objects, parts, sha256) = self.download_objects_map(&object_id)?;
+            *self.objects_map_parts.borrow_mut() = parts;
+            *self.last_objects_map.borrow_mut() = sha256.map(|sha256| (object_id, sha256));
+            objects
+        } else {
+            tracing::info!("  No objects object ID found, starting with empty objects map");
+            self.objects_map_parts.borrow_mut().clear();
+            self.last_objects_map.borrow_mut().take();
+            BTreeMap::new()
+        };
+
+        // Symrefs live in the RemoteState's `symrefs` field; maps from development builds may
+        // still carry them, and a format header, under reserved keys
+        let in_map_symrefs = Self::take_symrefs(&mut objects);
+        let symrefs = on_chain_symrefs.unwrap_or(in_map_symrefs);
+        let in_map = objects
+            .remove(FORMAT_KEY)
+            .map(|header| FormatHeader::parse(&header))
+            .transpose()?;
+        *self.last_format.borrow_mut() = recorded_format
+            .map(|recorded| recorded.or(in_map).unwrap_or_else(FormatHeader::unrecorded));
+
+        // Lazy rehydration: discover blob expiration info from objects map
+        // This allows any client (including fresh clones) to track blob expiration
+        if !objects.is_empty() {
+            let _ = self.rehydrate_blob_tracker(&objects); // Best effort, don't fail on errors
+        }
+
+        // The metadata blob rides along in the objects map under a reserved key
+        let metadata = objects.remove(METADATA_KEY);
+
+        tracing::info!("  Retrieved {} objects mappings", objects.len());
+
+        let mut state = State {
+            refs,
+            objects,
+            symrefs,
+            metadata,
+            ..Default::default()
+        };
+        state.set_aside_invalid_refs();
+
+        // Cache the state for subsequent reads
+        *self.cached_state.borrow_mut() = Some(state.clone());
+        *self.blob_members.borrow_mut() = None;
+
+        Ok(state)
+    }
+
+    fn write_state(&self, state: &State) -> Result<()> {
+        self.ensure_spending_allowed()?;
+        self.ensure_encoding_supported()?;
+
         tracing::info!(
             "git-remote-walrus: Writing state to {} ({} refs, {} objects)",
             self.state_object_id,
@@ -1011,112 +3067,1607 @@ impl MutableState for WalrusStorage {
             state.objects.len()
         );
 
-        // Invalidate cached state since we're writing new state
-        *self.cached_state.borrow_mut() = None;
+        // Invalidate cached state since we're writing new state, logging what changed since it was read
+        *self.blob_members.borrow_mut() = None;
+        if let Some(previous) = self.cached_state.borrow_mut().take() {
+            tracing::info!(
+                "  Objects map: {}",
+                ObjectsDiff::compute(&previous.objects, &state.objects)
+            );
+        }
+
+        // Check for blob expiration warnings (scoped to this repo's blobs)
+        let _ = self.check_blob_expiration(state);
+
+        // Inline-layout remotes hold a bounded number of refs; refuse before taking the lock
+        let refs: Vec<(String, String)> = state
+            .refs
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        self.runtime
+            .block_on(self.sui_client.ensure_refs_fit(&refs))?;
+
+        tracing::info!("  Serializing objects map...");
+        let mut objects_map = state.objects.clone();
+        if let Some(metadata) = &state.metadata {
+            objects_map.insert(METADATA_KEY.to_string(), metadata.clone());
+        }
+        Self::validate_objects_map(&objects_map)
+            .context("Refusing to write a malformed objects map")?;
+        let objects_yaml_str = to_canonical(&objects_map);
+        let objects_yaml = objects_yaml_str.as_bytes();
+
+        // A map identical to the one the state was read with is already stored; the PTB points
+        // at that blob again (the lock check below refuses if another push replaced it)
+        let sha256 = Self::compute_sha256(objects_yaml);
+        let read_from_id = self
+            .read_from
+            .borrow()
+            .as_ref()
+            .and_then(|(_, _, id)| id.clone());
+        let unchanged = self
+            .last_objects_map
+            .borrow()
+            .clone()
+            .filter(|(object_id, last)| *last == sha256 && read_from_id.as_ref() == Some(object_id))
+            .map(|(object_id, _)| object_id);
+
+        // A map too large for one blob is stored in parts behind a manifest
+        let max_blob_size = self
+            .get_max_blob_size()
+            .context("Failed to get network blob size limit")?;
+        let mut format = FormatHeader::for_state(state);
+        if objects_yaml.len() as u64 > max_blob_size {
+            format = format.stored_in_parts();
+        }
+        if let Some(previous) = self.last_format.borrow().as_ref() {
+            format.warn_if_unreadable_by(previous);
+        }
+
+        // Make sure the wallet can pay for the objects map and the PTB before locking (the
+        // manifest of a chunked map is a few bytes per part)
+        let blob_sizes: Vec<u64> = match unchanged {
+            Some(_) => Vec::new(),
+            None => objects_yaml
+                .chunks(max_blob_size as usize)
+                .map(|chunk| chunk.len() as u64)
+                .collect(),
+        };
+        self.preflight(&blob_sizes, self.config.default_epochs, refs.len())?;
+
+        // Step 1: Acquire lock on RemoteState (5 minute timeout)
+        // This ensures no one else can modify the state while we upload to Walrus
+        tracing::info!("  Acquiring lock on RemoteState...");
+        let lock = self
+            .runtime
+            .block_on(self.sui_client.acquire_lock(300_000))
+            .context("Failed to acquire lock on RemoteState")?;
+        if let Some(stale) = &lock.broke_stale {
+            tracing::warn!(
+                "git-remote-walrus: broke a stale lock left by an unfinished push ({})",
+                stale
+            );
+        }
+
+        // Refuse to overwrite what another push wrote since the state was read
+        if let Some(read_from) = self.read_from.borrow_mut().take() {
+            let changed = self.state_source().map(|current| current != read_from);
+            if !matches!(changed, Ok(false)) {
+                self.runtime
+                    .block_on(self.sui_client.release_lock())
+                    .context("Failed to release lock on RemoteState")?;
+                changed?;
+                return Err(StateChanged.into());
+            }
+        }
+
+        // Step 2: Upload objects map to Walrus (while holding lock), unless it is already stored
+        let objects_object_id = match unchanged {
+            Some(object_id) => {
+                tracing::info!("  Objects map unchanged; reusing {}", object_id);
+                object_id
+            }
+            None => self.upload_objects_map(objects_yaml, max_blob_size)?,
+        };
+
+        // Step 3: Execute atomic PTB: update refs + update objects_blob_object_id + release lock
+        tracing::info!(
+            "  Executing atomic PTB (update {} refs + objects object + release lock)...",
+            refs.len()
+        );
+        self.runtime
+            .block_on(self.sui_client.upsert_refs_and_update_objects(
+                refs,
+                objects_object_id.clone(),
+                state.symrefs.clone(),
+                format.encode(),
+                lock.state_ref,
+            ))
+            .context("Failed to execute atomic PTB")?;
+        *self.last_format.borrow_mut() = Some(format);
+        *self.last_objects_map.borrow_mut() = Some((objects_object_id, sha256));
+
+        tracing::info!("  State successfully written to Sui");
+
+        Ok(())
+    }
+
+    fn update_state<F>(&self, update_fn: F) -> Result<()>
+    where
+        F: FnOnce(&mut State) -> Result<()>,
+    {
+        // Standard read-modify-write pattern
+        let mut state = self.read_state()?;
+        update_fn(&mut state)?;
+        self.write_state(&state)?;
+        Ok(())
+    }
+}
+
+impl StorageBackend for WalrusStorage {
+    fn initialize(&self) -> Result<()> {
+        tracing::info!("git-remote-walrus: Initializing Walrus storage");
+        tracing::info!("  State object: {}", self.state_object_id);
+        tracing::info!("  Cache dir: {:?}", self.config.cache_dir);
+        tracing::info!("  Wallet: {:?}", self.config.sui_wallet_path);
+
+        // Initialize cache
+        self.cache
+            .initialize()
+            .context("Failed to initialize cache")?;
+
+        // Synced or restored cache dirs can leave the index out of step with the objects
+        match CacheIndex::check_consistency(
+            &self.cache_index_path,
+            &self.config.cache_dir.join("objects"),
+        ) {
+            Ok(Some(repair)) => tracing::info!("Repaired cache index: {}", repair),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Failed to check cache index consistency: {:#}", e),
+        }
+
+        Ok(())
+    }
+
+    fn pusher(&self) -> Option<String> {
+        Some(self.sui_client.sender().to_string())
+    }
+
+    /// Refs and the objects map are updated by one PTB
+    fn atomic_ref_updates(&self) -> bool {
+        true
+    }
+
+    fn prefetch_strategy(&self) -> PrefetchStrategy {
+        self.config.prefetch_strategy
+    }
+
+    fn begin_session(&self) {
+        // Another process may have pushed since, so state and epoch are read afresh; the
+        // clients, caches and network info stay warm
+        self.cached_state.take();
+        self.blob_members.take();
+        self.last_format.take();
+        self.objects_map_parts.take();
+        self.last_objects_map.take();
+        self.read_from.take();
+        self.read_sources.borrow_mut().clear();
+        self.epochs.reset();
+    }
+
+    fn retention_for(&self, refname: &str) -> Option<Retention> {
+        Some(self.config.retention_for(refname))
+    }
+
+    fn policy(&self) -> Result<RemotePolicy> {
+        self.runtime
+            .block_on(self.sui_client.policy())
+            .context("Failed to read the remote's policy from Sui")
+    }
+
+    /// Repairs are made under the push lock, skipping any ref a concurrent writer has since
+    /// made valid
+    fn repair_refs(&self, repairs: &[RefRepair]) -> Result<()> {
+        self.ensure_spending_allowed()?;
+
+        tracing::info!("  Acquiring lock on RemoteState...");
+        let lock = self
+            .runtime
+            .block_on(self.sui_client.acquire_lock(300_000))
+            .context("Failed to acquire lock on RemoteState")?;
+
+        *self.cached_state.borrow_mut() = None;
+        let result = self.read_state().and_then(|state| {
+            let (mut corrections, mut deletions) = (Vec::new(), Vec::new());
+            for repair in repairs {
+                if !state.invalid_refs.contains_key(repair.name()) {
+                    continue;
+                }
+                match repair {
+                    RefRepair::Correct { name, git_sha1 } => {
+                        corrections.push((name.clone(), git_sha1.clone()))
+                    }
+                    RefRepair::Delete { name } => deletions.push(name.clone()),
+                }
+            }
+            self.runtime
+                .block_on(self.sui_client.repair_refs_and_release_lock(
+                    &corrections,
+                    &deletions,
+                    lock.state_ref,
+                ))
+        });
+        *self.cached_state.borrow_mut() = None;
+        if result.is_err() {
+            if let Err(release) = self.runtime.block_on(self.sui_client.release_lock()) {
+                tracing::warn!("Failed to release lock: {:#}", release);
+            }
+        }
+        result
+    }
+
+    fn list_banner(&self, state: &State) -> Option<String> {
+        if !self.config.list_banner {
+            return None;
+        }
+        // The banner is informational; never let it break a listing
+        match self.banner(state) {
+            Ok(banner) => banner.map(|banner| banner.to_string()),
+            Err(e) => {
+                tracing::debug!("Skipping list banner: {:#}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Whether `value` is `0x` followed by 1 to 64 lowercase hex digits
+fn is_sui_object_id(value: &str) -> bool {
+    value.strip_prefix("0x").is_some_and(|hex| {
+        (1..=64).contains(&hex.len())
+            && hex
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    })
+}
+
+/// `f` applied to each of `items` on at most `limit` threads, in input order
+///
+/// Each call runs as the calling thread's session, so walrus CLI commands see its directory
+/// and environment.
+fn map_bounded<T, R, F>(items: &[T], limit: usize, f: F) -> Result<Vec<R>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if limit <= 1 || items.len() <= 1 {
+        return Ok(items.iter().map(f).collect());
+    }
+    let session = session_env::snapshot();
+    Ok(rayon::ThreadPoolBuilder::new()
+        .num_threads(limit.min(items.len()))
+        .build()
+        .context("Failed to start transfer threads")?
+        .install(|| {
+            items
+                .par_iter()
+                .map(|item| session_env::enter(session.clone(), || f(item)))
+                .collect()
+        }))
+}
+
+/// At most 80 characters of `s`, for error messages quoting untrusted input
+fn truncate(s: &str) -> &str {
+    s.char_indices().nth(80).map_or(s, |(end, _)| &s[..end])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use gix_object::Kind;
+
+    use super::*;
+    use crate::{config::RetentionRule, pack::objects::GitObject, sui::mock_rpc::MockSui};
+
+    // The walrus CLI side runs against tests/mock/walrus, and the Sui side against the mock
+    // JSON-RPC node in tests/mock/sui_rpc.rs (see MockRemote).
+
+    /// A remote on the mock Sui RPC with its blobs in the mock walrus CLI, all in a temp dir
+    pub(crate) struct MockRemote {
+        pub dir: tempfile::TempDir,
+        pub sui: MockSui,
+        pub owner: sui_types::base_types::SuiAddress,
+        pub state: sui_types::base_types::ObjectID,
+        pub config: WalrusRemoteConfig,
+    }
+
+    impl MockRemote {
+        pub fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let walrus_dir = dir.path().join("walrus");
+            std::fs::create_dir_all(&walrus_dir).unwrap();
+            let walrus_config = walrus_dir.join("client.yaml");
+            std::fs::write(&walrus_config, "").unwrap();
+
+            let sui = MockSui::start(&walrus_dir);
+            let (wallet, owner) = sui.wallet(&dir.path().join("sui"));
+            let state = sui.create_remote(owner, false);
+            let config = serde_yaml::from_str(&format!(
+                "sui_wallet_path: \"{}\"\nwalrus_config_path: \"{}\"\nwalrus_binary: \"{}\"\n\
+                 cache_dir: \"{}\"\nskip_preflight: true\n",
+                wallet.display(),
+                walrus_config.display(),
+                Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("tests/mock/walrus")
+                    .display(),
+                dir.path().join("cache").display()
+            ))
+            .unwrap();
+            Self {
+                dir,
+                sui,
+                owner,
+                state,
+                config,
+            }
+        }
+
+        /// The mock walrus CLI's directory
+        pub fn walrus_dir(&self) -> PathBuf {
+            self.dir.path().join("walrus")
+        }
+
+        /// Open the remote as the helper does
+        pub fn storage(&self) -> WalrusStorage {
+            self.storage_of(self.state, "origin", &self.config)
+        }
+
+        /// Open another remote on the same mock network, as remote `name` with `config`
+        pub fn storage_of(
+            &self,
+            state: sui_types::base_types::ObjectID,
+            name: &str,
+            config: &WalrusRemoteConfig,
+        ) -> WalrusStorage {
+            WalrusStorage::new(state.to_string(), name.to_string(), config.clone()).unwrap()
+        }
+
+        /// How many blobs the mock walrus CLI has been asked to read
+        pub fn walrus_reads(&self) -> usize {
+            std::fs::read_to_string(self.walrus_dir().join("calls.log"))
+                .unwrap_or_default()
+                .lines()
+                .filter(|line| line.starts_with("read "))
+                .count()
+        }
+    }
+
+    #[test]
+    fn test_state_round_trips_through_mock_sui() {
+        let remote = MockRemote::new();
+        let storage = remote.storage();
+        let content: &[u8] = b"blob 5\0hello";
+        let sha1 = "b6fc4c620b67d95f953a5c1c1230aaab5db5a1b0";
+        let id = storage.write_object(content).unwrap();
+
+        let mut state = State::default();
+        state
+            .refs
+            .insert("refs/heads/main".to_string(), "a".repeat(40));
+        state.objects.insert(sha1.to_string(), id.clone());
+        state
+            .symrefs
+            .insert("HEAD".to_string(), "refs/heads/main".to_string());
+        storage.write_state(&state).unwrap();
+
+        assert_eq!(remote.sui.refs(remote.state), state.refs);
+        assert_eq!(
+            remote.sui.symrefs(remote.state),
+            Some(state.symrefs.clone())
+        );
+        assert_eq!(remote.sui.lock_holder(remote.state), None);
+        let objects = remote.sui.objects_blob_object_id(remote.state).unwrap();
+        assert!(remote.walrus_dir().join("objects").join(objects).exists());
+
+        // Another machine, without the cache, reads it all back from Sui and Walrus
+        std::fs::remove_dir_all(remote.dir.path().join("cache")).unwrap();
+        let reader = remote.storage();
+        let read = reader.read_state().unwrap();
+        assert_eq!(read.refs, state.refs);
+        assert_eq!(read.objects, state.objects);
+        assert_eq!(read.symrefs, state.symrefs);
+        assert_eq!(reader.read_object(&id).unwrap(), content);
+    }
+
+    #[test]
+    fn test_state_write_refused_after_another_push() {
+        let remote = MockRemote::new();
+        let state_with = |tip: &str| {
+            let mut state = State::default();
+            state
+                .refs
+                .insert("refs/heads/main".to_string(), tip.repeat(40));
+            state
+        };
+        remote.storage().write_state(&state_with("a")).unwrap();
+
+        // Two pushes read the same state; the second to write loses
+        let mine = remote.storage();
+        let theirs = remote.storage();
+        mine.read_state().unwrap();
+        theirs.read_state().unwrap();
+        theirs.write_state(&state_with("b")).unwrap();
+        let err = mine.write_state(&state_with("c")).unwrap_err();
+        assert!(err.is::<StateChanged>(), "{:#}", err);
+
+        // Nothing was written, and the lock it took is released
+        assert_eq!(remote.sui.refs(remote.state), state_with("b").refs);
+        assert_eq!(remote.sui.lock_holder(remote.state), None);
+
+        // Read again, the write goes through
+        mine.update_state(|state| {
+            state
+                .refs
+                .insert("refs/heads/topic".to_string(), "c".repeat(40));
+            Ok(())
+        })
+        .unwrap();
+        let refs = remote.sui.refs(remote.state);
+        assert_eq!(refs["refs/heads/main"], "b".repeat(40));
+        assert_eq!(refs["refs/heads/topic"], "c".repeat(40));
+    }
+
+    #[test]
+    fn test_pinned_remote_reads_its_historical_state() {
+        let mut remote = MockRemote::new();
+        let write = |storage: &WalrusStorage, tip: &str, content: &[u8]| {
+            let id = storage.write_object(content).unwrap();
+            let mut state = State::default();
+            state
+                .refs
+                .insert("refs/heads/main".to_string(), tip.repeat(40));
+            state.objects.insert(hex::encode(Sha1::digest(content)), id);
+            storage.write_state(&state).unwrap();
+            state
+        };
+        let old = write(&remote.storage(), "a", b"blob 3\0old");
+        let old_objects_map = remote.sui.objects_blob_object_id(remote.state).unwrap();
+        let pin = remote.sui.transactions(remote.state).pop().unwrap();
+        let new = write(&remote.storage(), "b", b"blob 3\0new");
+        let new_objects_map = remote.sui.objects_blob_object_id(remote.state).unwrap();
+        assert_ne!(old.refs, new.refs);
+
+        remote.config.pinned_transaction = Some(pin.clone());
+        let pinned = remote.storage();
+        let read = pinned.read_state().unwrap();
+        assert_eq!(read.refs, old.refs);
+        assert_eq!(read.objects, old.objects);
+        let (git_sha1, id) = read.objects.iter().next().unwrap();
+        verify_git_object(git_sha1, &pinned.read_object(id).unwrap()).unwrap();
+
+        // Blob reports cover the blobs of the pinned state, not the current one
+        let referenced = pinned.referenced_blob_object_ids().unwrap();
+        assert!(referenced.contains(&old_objects_map));
+        assert!(!referenced.contains(&new_objects_map));
+
+        // History can't be written
+        let err = pinned.write_state(&new).unwrap_err();
+        assert!(format!("{:#}", err).contains(&pin), "{:#}", err);
+    }
+
+    #[test]
+    fn test_compute_sha256() {
+        let content = b"Hello, World!";
+        let hash = WalrusStorage::compute_sha256(content);
+
+        // Known SHA-256 of "Hello, World!"
+        assert_eq!(
+            hash,
+            "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+        );
+    }
+
+    #[test]
+    fn test_stale_cache_entry_is_uploaded_again() {
+        let content: &[u8] = b"blob 5\0hello";
+        let sha256 = WalrusStorage::compute_sha256(content);
+
+        // Index written by an older layout: same sha256, but a different schema version
+        let dir = tempfile::tempdir().unwrap();
+        let index_path = dir.path().join("cache_index.yaml");
+        std::fs::write(
+            &index_path,
+            format!(
+                "object_to_sha256:\n  '0xold': {sha}\nsha256_to_object:\n  {sha}: '0xold'\n\
+                 entries:\n  {sha}:\n    schema: 0\n    kind: raw-loose\n",
+                sha = sha256
+            ),
+        )
+        .unwrap();
+        let cache_index = CacheIndex::load(&index_path).unwrap();
+
+        let (cached, to_upload) = WalrusStorage::partition_cached(&cache_index, &[content]);
+        assert_eq!(cached, vec![None]);
+        assert_eq!(to_upload.len(), 1);
+        assert_eq!(to_upload[0].2, sha256);
+
+        // A current entry is a hit
+        let mut cache_index = cache_index;
+        cache_index.insert("0xnew".to_string(), sha256, ContentKind::RawLoose);
+        let (cached, to_upload) = WalrusStorage::partition_cached(&cache_index, &[content]);
+        assert_eq!(cached, vec![Some("0xnew".to_string())]);
+        assert!(to_upload.is_empty());
+    }
 
-        // Check for blob expiration warnings (scoped to this repo's blobs)
-        let content_ids: Vec<&str> = state.objects.values().map(|s| s.as_str()).collect();
-        let relevant_blob_ids = Self::extract_blob_object_ids(&content_ids);
-        let _ = self.check_blob_expiration(Some(&relevant_blob_ids));
+    #[test]
+    fn test_empty_objects_are_never_uploaded() {
+        let contents: [&[u8]; 4] = [b"blob 0\0", b"blob 5\0hello", b"", b"tree 0\0"];
+        let (cached, to_upload) = WalrusStorage::partition_cached(&CacheIndex::new(), &contents);
+        assert_eq!(
+            cached,
+            vec![
+                Some("const:empty-blob".to_string()),
+                None,
+                Some("const:empty".to_string()),
+                Some("const:empty-tree".to_string()),
+            ]
+        );
+        assert_eq!(to_upload.len(), 1);
+        assert_eq!(to_upload[0].0, 1);
 
-        // Step 1: Acquire lock on RemoteState (5 minute timeout)
-        // This ensures no one else can modify the state while we upload to Walrus
-        tracing::info!("  Acquiring lock on RemoteState...");
-        self.runtime
-            .block_on(self.sui_client.acquire_lock(300_000))
-            .context("Failed to acquire lock on RemoteState")?;
+        // Constants are valid objects map entries and are read without a download
+        let yaml = format!(
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391: const:empty-blob\n{}: const:empty-tree\n",
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904"
+        );
+        let objects = WalrusStorage::parse_objects_map(yaml.as_bytes(), 1024).unwrap();
+        assert_eq!(
+            WalrusStorage::extract_blob_object_ids(&["const:empty-blob"]),
+            Vec::<String>::new()
+        );
+        assert_eq!(objects.len(), 2);
+    }
 
-        // Step 2: Serialize and upload objects map to Walrus (while holding lock)
-        tracing::info!("  Serializing objects map...");
-        let objects_yaml_str = serde_yaml::to_string(&state.objects)
-            .context("Failed to serialize objects map to YAML")?;
-        let objects_yaml = objects_yaml_str.as_bytes();
+    #[test]
+    fn test_parse_objects_map_validates_entries() {
+        let sha = "a".repeat(40);
+        let yaml = format!("{}: 0xabc:0:10\nmetadata: 0xdef\n", sha);
+        let objects = WalrusStorage::parse_objects_map(yaml.as_bytes(), 1024).unwrap();
+        assert_eq!(objects[&sha], "0xabc:0:10");
+
+        // Whatever write_state serializes parses back (all-digit SHAs come out quoted), as do
+        // maps serialized by serde_yaml before the canonical form
+        let written: BTreeMap<String, ContentId> = [
+            ("1".repeat(40), "0xabc:0:10".to_string()),
+            ("e".repeat(64), "0xdef".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        for yaml_out in [
+            to_canonical(&written),
+            serde_yaml::to_string(&written).unwrap(),
+        ] {
+            assert_eq!(
+                WalrusStorage::parse_objects_map(yaml_out.as_bytes(), 1024).unwrap(),
+                written
+            );
+        }
 
-        tracing::info!(
-            "  Uploading objects map to Walrus ({} bytes)...",
-            objects_yaml.len()
+        // Oversized documents are refused before parsing
+        let err = WalrusStorage::parse_objects_map(yaml.as_bytes(), 10).unwrap_err();
+        assert!(
+            err.to_string().contains("over the 10 byte limit"),
+            "{}",
+            err
         );
-        let objects_blob_info = self
-            .walrus_client
-            .store(objects_yaml)
-            .context("Failed to upload objects map to Walrus")?;
 
-        tracing::info!(
-            "  Objects shared object ID: {} (blob: {})",
-            &objects_blob_info.shared_object_id,
-            &objects_blob_info.blob_id
+        for (yaml, offending) in [
+            (format!("not-a-sha: 0xabc\n{}: 0xabc", sha), "not-a-sha"),
+            (format!("{}: 0xabc:1", sha), "0xabc:1"),
+            (format!("{}: ../../etc/passwd", sha), "../../etc/passwd"),
+            (format!("{}: 0xabc:5:0", sha), "0xabc:5:0"),
+            (format!("{}: const:nothing", sha), "const:nothing"),
+            ("symref:HEAD: main".to_string(), "must start with refs/"),
+            ("symref:ORIG_HEAD: refs/heads/main".to_string(), "ORIG_HEAD"),
+            (
+                format!("{}: 0xabc:1:18446744073709551615", sha),
+                "18446744073709551615",
+            ),
+        ] {
+            let err = WalrusStorage::parse_objects_map(yaml.as_bytes(), 1024).unwrap_err();
+            let message = format!("{:#}", err);
+            assert!(message.contains(offending), "{}", message);
+        }
+    }
+
+    #[test]
+    fn test_write_rejects_malformed_objects_map() {
+        let sha = "a".repeat(40);
+        let valid = |entries: &[(&str, &str)]| -> BTreeMap<String, ContentId> {
+            let mut map: BTreeMap<String, ContentId> = [
+                (sha.clone(), "0xabc:0:10".to_string()),
+                ("b".repeat(40), "const:empty-tree".to_string()),
+                (METADATA_KEY.to_string(), "0xdef".to_string()),
+            ]
+            .into_iter()
+            .collect();
+            map.extend(entries.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+            map
+        };
+        WalrusStorage::validate_objects_map(&valid(&[])).unwrap();
+
+        let short = "c".repeat(39);
+        let upper = "D".repeat(40);
+        let long = "e".repeat(64);
+        for (entry, offending) in [
+            (("not-a-sha", "0xabc:0:10"), "not-a-sha"),
+            ((short.as_str(), "0xabc:0:10"), short.as_str()),
+            ((upper.as_str(), "0xabc:0:10"), upper.as_str()),
+            ((sha.as_str(), "garbage"), "garbage"),
+            ((sha.as_str(), "0xabc:10"), "0xabc:10"),
+            ((sha.as_str(), "0xabc:5:0"), "0xabc:5:0"),
+            ((long.as_str(), "0xabc:0:10"), "40- and 64-character"),
+            (("symref:HEAD", "refs/heads/main"), "symrefs field"),
+        ] {
+            let err = WalrusStorage::validate_objects_map(&valid(&[entry])).unwrap_err();
+            let message = format!("{:#}", err);
+            assert!(message.contains(offending), "{}", message);
+        }
+    }
+
+    /// Development builds wrote the format header into the map, where it is still honoured
+    #[test]
+    fn test_parse_objects_map_checks_format_header_first() {
+        let sha = "a".repeat(40);
+        let mut state = State::default();
+        state.objects.insert(sha.clone(), "0xabc:0:10".to_string());
+        let header = FormatHeader::for_state(&state).encode();
+        let yaml = format!("{}: 0xabc:0:10\n{}: {}\n", sha, FORMAT_KEY, header);
+        let objects = WalrusStorage::parse_objects_map(yaml.as_bytes(), 1024).unwrap();
+        assert_eq!(objects[FORMAT_KEY], header);
+
+        // An entry in a format from a later release isn't reported as malformed
+        let yaml = format!(
+            "{}: 0xabc:0:10:zstd\n{}: features=21;writer=0.9.0;requires=0.8.0\n",
+            sha, FORMAT_KEY
+        );
+        let err = WalrusStorage::parse_objects_map(yaml.as_bytes(), 1024).unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains(
+                "(bit 5) written by git-remote-walrus v0.9.0; upgrade to at least v0.8.0"
+            ),
+            "{}",
+            message
         );
+    }
 
-        // Step 3: Convert refs to Vec for PTB
-        let refs: Vec<(String, String)> = state
-            .refs
+    /// A serialized objects map of `count` batched entries, as `write_state` stores it
+    fn objects_map_yaml(count: usize) -> (BTreeMap<String, ContentId>, String) {
+        let objects: BTreeMap<String, ContentId> = (0..count)
+            .map(|i| {
+                let sha = format!("{:040x}", i);
+                (sha, format!("0x{:064x}:{}:{}", i / 16, i % 16 * 100, 100))
+            })
+            .collect();
+        let yaml = to_canonical(&objects);
+        (objects, yaml)
+    }
+
+    #[test]
+    fn test_objects_map_over_blob_limit_round_trips_in_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("max_blob_size"), "4096").unwrap();
+        let client = crate::walrus::mock_client(dir.path());
+        let max_blob_size = WalrusNetworkInfo::query(&client).unwrap().max_blob_size();
+        assert_eq!(max_blob_size, 4096);
+
+        let (objects, yaml) = objects_map_yaml(300);
+        assert!(yaml.len() > 4 * 4096);
+        // Stored whole, the map would be refused
+        assert!(client.store(yaml.as_bytes()).is_err());
+
+        let (manifest, stored) =
+            WalrusStorage::store_objects_map(&client, yaml.as_bytes(), max_blob_size).unwrap();
+        let parts = yaml.len().div_ceil(4096);
+        assert_eq!(stored.len(), parts + 1);
+        assert!(stored.iter().all(|(_, size)| *size <= max_blob_size));
+        assert_eq!(
+            stored.iter().map(|(_, size)| size).sum::<u64>() - stored[parts].1,
+            yaml.len() as u64
+        );
+        assert_eq!(stored[parts].0.blob_id, manifest.blob_id);
+
+        let (read, part_object_ids) =
+            WalrusStorage::read_objects_map(&client, &manifest.blob_id, 1 << 20).unwrap();
+        assert_eq!(read, yaml.as_bytes());
+        let expected: Vec<String> = stored[..parts]
             .iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
+            .map(|(blob, _)| blob.shared_object_id.clone())
             .collect();
+        assert_eq!(part_object_ids, expected);
+        assert_eq!(
+            WalrusStorage::parse_objects_map(&read, 1 << 20).unwrap(),
+            objects
+        );
 
-        // Step 4: Execute atomic PTB: update refs + update objects_blob_object_id + release lock
-        tracing::info!(
-            "  Executing atomic PTB (update {} refs + objects object + release lock)...",
-            refs.len()
+        // The reassembled size counts against max_objects_map_bytes before any part is read
+        let reads = || {
+            std::fs::read_to_string(dir.path().join("calls.log"))
+                .unwrap()
+                .lines()
+                .filter(|line| line.starts_with("read"))
+                .count()
+        };
+        let before = reads();
+        let err =
+            WalrusStorage::read_objects_map(&client, &manifest.blob_id, yaml.len() as u64 - 1)
+                .unwrap_err();
+        assert!(err.to_string().contains("over the"), "{:#}", err);
+        assert_eq!(reads(), before + 1);
+    }
+
+    #[test]
+    fn test_single_blob_objects_maps_still_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = crate::walrus::mock_client(dir.path());
+
+        // Written before chunking existed, with and without a format header
+        let (objects, yaml) = objects_map_yaml(20);
+        let legacy = format!("{}: 0xabc\n", "a".repeat(40));
+        for yaml in [yaml.as_str(), legacy.as_str()] {
+            let blob = client.store(yaml.as_bytes()).unwrap();
+            let (read, parts) =
+                WalrusStorage::read_objects_map(&client, &blob.blob_id, 1 << 20).unwrap();
+            assert_eq!(read, yaml.as_bytes());
+            assert!(parts.is_empty());
+        }
+
+        // A map that fits is stored as a single blob, as before
+        let (map, stored) =
+            WalrusStorage::store_objects_map(&client, yaml.as_bytes(), 1 << 20).unwrap();
+        assert_eq!(stored.len(), 1);
+        let (read, parts) =
+            WalrusStorage::read_objects_map(&client, &map.blob_id, 1 << 20).unwrap();
+        assert_eq!(
+            WalrusStorage::parse_objects_map(&read, 1 << 20).unwrap(),
+            objects
         );
-        self.runtime
-            .block_on(
-                self.sui_client
-                    .upsert_refs_and_update_objects(refs, objects_blob_info.shared_object_id),
-            )
-            .context("Failed to execute atomic PTB")?;
+        assert!(parts.is_empty());
 
-        tracing::info!("  State successfully written to Sui");
+        // A development build's map started with its format header, but isn't a manifest
+        let empty = format!(
+            "{}: {}\n",
+            FORMAT_KEY,
+            FormatHeader::for_state(&State::default()).encode()
+        );
+        assert!(WalrusStorage::parse_objects_map_manifest(empty.as_bytes())
+            .unwrap()
+            .is_none());
+    }
 
-        Ok(())
+    #[test]
+    fn test_objects_map_manifest_is_validated() {
+        let header = format!("{}: {}\n", FORMAT_KEY, FormatHeader::chunked().encode());
+        let part = |n: &str, value: &str| format!("{}{}: {}\n", PART_KEY_PREFIX, n, value);
+
+        let manifest = format!(
+            "{}{}{}",
+            header,
+            part("0", "0xab:blob-A_1:10"),
+            part("1", "0xcd:blobB:5")
+        );
+        let parts = WalrusStorage::parse_objects_map_manifest(manifest.as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            parts[0],
+            ObjectsMapPart {
+                object_id: "0xab".to_string(),
+                blob_id: "blob-A_1".to_string(),
+                length: 10,
+            }
+        );
+        assert_eq!(parts[1].length, 5);
+
+        for (entries, expected) in [
+            (String::new(), "lists no parts"),
+            (part("1", "0xab:blob:10"), "missing part 0"),
+            (
+                part("0", "0xab:blob:10") + &part("00", "0xcd:blob:10"),
+                "lists part 0 twice",
+            ),
+            (part("+0", "0xab:blob:10"), "not a part number"),
+            (part("0", "0xzz:blob:10"), "not a Sui object ID"),
+            (part("0", "0xab:bl/ob:10"), "not a Walrus blob ID"),
+            (part("0", "0xab:blob:0"), "invalid part length"),
+            (part("0", "0xab:blob"), "expected {blob_object_id}"),
+            ("metadata: 0xab\n".to_string(), "not a part number"),
+        ] {
+            let manifest = format!("{}{}", header, entries);
+            let err = WalrusStorage::parse_objects_map_manifest(manifest.as_bytes()).unwrap_err();
+            assert!(format!("{:#}", err).contains(expected), "{:#}", err);
+        }
     }
 
-    fn update_state<F>(&self, update_fn: F) -> Result<()>
-    where
-        F: FnOnce(&mut State) -> Result<()>,
-    {
-        // Standard read-modify-write pattern
-        let mut state = self.read_state()?;
-        update_fn(&mut state)?;
-        self.write_state(&state)?;
-        Ok(())
+    #[test]
+    fn test_simulated_blob_limit_splits_batches_that_reassemble() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = crate::walrus::mock_client(dir.path());
+        let network = WalrusNetworkInfo::query(&client).unwrap().max_blob_size();
+
+        // The override only ever lowers the network's limit
+        assert_eq!(
+            WalrusStorage::effective_max_blob_size(network, None),
+            network
+        );
+        assert_eq!(
+            WalrusStorage::effective_max_blob_size(network, Some(network * 2)),
+            network
+        );
+        let max_blob_size = WalrusStorage::effective_max_blob_size(network, Some(64));
+        assert_eq!(max_blob_size, 64);
+
+        let objects: Vec<Vec<u8>> = (0..10)
+            .map(|i| format!("blob 20\0object number {:06}", i).into_bytes())
+            .collect();
+        let contents: Vec<&[u8]> = objects.iter().map(|object| object.as_slice()).collect();
+        let (_, to_upload) = WalrusStorage::partition_cached(&CacheIndex::new(), &contents);
+        let batches = WalrusStorage::group_into_batches(to_upload, max_blob_size);
+        assert_eq!(batches.len(), 5);
+        let blobs: Vec<Vec<u8>> = batches
+            .iter()
+            .map(|batch| {
+                batch
+                    .iter()
+                    .flat_map(|(_, content, _)| *content)
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        assert!(blobs.iter().all(|blob| blob.len() as u64 <= max_blob_size));
+
+        let blob_refs: Vec<&[u8]> = blobs.iter().map(|blob| blob.as_slice()).collect();
+        let stored: Vec<_> = client
+            .store_many(&blob_refs)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+
+        // Each object reads back from its slice of the blob its batch went into
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = FilesystemStorage::new(cache_dir.path()).unwrap();
+        cache.initialize().unwrap();
+        let mut cache_index = CacheIndex::new();
+        let mut read = vec![Vec::new(); objects.len()];
+        for (batch, blob) in batches.iter().zip(&stored) {
+            let full_blob = client.read(&blob.blob_id).unwrap();
+            let mut offset = 0;
+            for (idx, content, _) in batch {
+                let id = ParsedContentId::batched(
+                    blob.shared_object_id.clone(),
+                    offset,
+                    content.len() as u64,
+                )
+                .encode();
+                offset += content.len() as u64;
+                let git_sha1 = hex::encode(Sha1::digest(content));
+                read[*idx] = WalrusStorage::extract_and_cache(
+                    &cache,
+                    &mut cache_index,
+                    &id,
+                    &ParsedContentId::parse(&id).unwrap(),
+                    &full_blob,
+                    Some(&git_sha1),
+                )
+                .unwrap();
+            }
+        }
+        assert_eq!(read, objects);
+
+        // An object over the limit still goes alone rather than being refused
+        let large = vec![0u8; 100];
+        let batches = WalrusStorage::group_into_batches(
+            vec![
+                (0, contents[0], String::new()),
+                (1, large.as_slice(), String::new()),
+                (2, contents[1], String::new()),
+            ],
+            max_blob_size,
+        );
+        let indexes: Vec<Vec<usize>> = batches
+            .iter()
+            .map(|batch| batch.iter().map(|(idx, _, _)| *idx).collect())
+            .collect();
+        assert_eq!(indexes, vec![vec![0], vec![1], vec![2]]);
     }
-}
 
-impl StorageBackend for WalrusStorage {
-    fn initialize(&self) -> Result<()> {
-        tracing::info!("git-remote-walrus: Initializing Walrus storage");
-        tracing::info!("  State object: {}", self.state_object_id);
-        tracing::info!("  Cache dir: {:?}", self.config.cache_dir);
-        tracing::info!("  Wallet: {:?}", self.config.sui_wallet_path);
+    #[test]
+    fn test_store_invocations() {
+        assert!(WalrusStorage::store_invocations(&[], 100).is_empty());
+        // Small batches share an invocation up to the byte limit; a full one goes alone
+        assert_eq!(
+            WalrusStorage::store_invocations(&[10, 20, 70, 5, 100, 1], 100),
+            vec![0..3, 3..4, 4..5, 5..6]
+        );
+        // ...and at most BATCHES_PER_STORE share one
+        let many = vec![1; BATCHES_PER_STORE + 1];
+        assert_eq!(
+            WalrusStorage::store_invocations(&many, 100),
+            vec![
+                0..BATCHES_PER_STORE,
+                BATCHES_PER_STORE..BATCHES_PER_STORE + 1
+            ]
+        );
+    }
 
-        // Initialize cache
-        self.cache
-            .initialize()
-            .context("Failed to initialize cache")?;
+    #[test]
+    fn test_history_walk_downloads_each_blob_once_newest_first() {
+        // Three pushes, each uploading its commit and tree in one blob; commit N's tree is
+        // requested with commit N-1, as a level-by-level walk from the ref would
+        let content_id = |push: u64, slot: u64| {
+            ParsedContentId::batched(format!("0xb{}", push), slot * 100, 100).encode()
+        };
+        let mut objects = BTreeMap::new();
+        for push in 0..3 {
+            objects.insert(format!("commit{}", push), content_id(push, 0));
+            objects.insert(format!("tree{}", push), content_id(push, 1));
+        }
+        let members = WalrusStorage::index_blob_members(&objects);
+        assert_eq!(members["0xb1"].len(), 2);
+
+        let levels = [
+            vec!["commit2"],
+            vec!["tree2", "commit1"],
+            vec!["tree1", "commit0"],
+            vec!["tree0"],
+        ];
+        let mut cached: HashSet<ContentId> = HashSet::new();
+        let mut downloads = Vec::new();
+        for level in levels {
+            let misses = level
+                .iter()
+                .map(|sha| &objects[*sha])
+                .filter(|id| !cached.contains(*id))
+                .map(|id| ParsedContentId::parse(id).unwrap())
+                .enumerate();
+            for (blob_object_id, _) in WalrusStorage::download_order(misses) {
+                cached.extend(members[&blob_object_id].iter().map(|(_, id)| id.clone()));
+                downloads.push(blob_object_id);
+            }
+        }
+        assert_eq!(downloads, ["0xb2", "0xb1", "0xb0"]);
 
-        Ok(())
+        // Within one read, blobs follow the first object requested from each
+        let misses = ["tree0", "commit2", "commit0", "tree1"]
+            .iter()
+            .map(|sha| ParsedContentId::parse(&objects[*sha]).unwrap())
+            .enumerate();
+        let order = WalrusStorage::download_order(misses);
+        assert_eq!(
+            order
+                .iter()
+                .map(|(blob, items)| (blob.as_str(), items.iter().map(|(idx, _)| *idx).collect()))
+                .collect::<Vec<(&str, Vec<usize>)>>(),
+            vec![("0xb0", vec![0, 2]), ("0xb2", vec![1]), ("0xb1", vec![3])]
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_size_prefetch_downloads_largest_blobs_first() {
+        let misses = [
+            ParsedContentId::legacy("0xwhole".to_string()),
+            ParsedContentId::batched("0xsmall".to_string(), 0, 10),
+            ParsedContentId::batched("0xlarge".to_string(), 500, 100),
+            ParsedContentId::batched("0xsmall".to_string(), 10, 20),
+            ParsedContentId::batched("0xmedium".to_string(), 0, 300),
+        ];
+        let mut order = WalrusStorage::download_order(misses.into_iter().enumerate());
+        WalrusStorage::largest_first(&mut order);
+        assert_eq!(
+            order
+                .iter()
+                .map(|(blob, _)| blob.as_str())
+                .collect::<Vec<_>>(),
+            ["0xlarge", "0xmedium", "0xsmall", "0xwhole"]
+        );
+    }
 
-    // Note: These tests are limited until we have:
-    // 1. Mock Sui client
-    // 2. Mock Walrus client
-    // 3. Localnet setup
+    /// Development builds stored symrefs in the objects map, where they are still read
+    #[test]
+    fn test_symrefs_are_read_from_development_build_maps() {
+        let sha = "a".repeat(40);
+        let yaml = format!(
+            "{}: 0xabc\nsymref:HEAD: refs/heads/dev\nsymref:refs/heads/prod: refs/heads/v2\n",
+            sha
+        );
+        let mut objects = WalrusStorage::parse_objects_map(yaml.as_bytes(), 1024).unwrap();
+        let symrefs = WalrusStorage::take_symrefs(&mut objects);
+        assert_eq!(objects.keys().collect::<Vec<_>>(), [&sha]);
+        assert_eq!(symrefs["HEAD"], "refs/heads/dev");
+        assert_eq!(symrefs["refs/heads/prod"], "refs/heads/v2");
+    }
 
     #[test]
-    fn test_compute_sha256() {
-        let content = b"Hello, World!";
-        let hash = WalrusStorage::compute_sha256(content);
+    fn test_parse_objects_map_rejects_hostile_documents() {
+        let max = 1024 * 1024;
+        let nested = format!("{}: {}", "a".repeat(40), "[".repeat(1_000_000));
+        let aliases = (0..20).fold("a0: &a0 [x]\n".to_string(), |yaml, i| {
+            format!("{}a{}: &a{} [*a{}, *a{}]\n", yaml, i + 1, i + 1, i, i)
+        });
+        let long_key = format!("{}: 0xabc", "f".repeat(max as usize - 10));
+
+        for yaml in [nested, aliases, long_key, "- not\n- a map\n".to_string()] {
+            assert!(WalrusStorage::parse_objects_map(yaml.as_bytes(), max).is_err());
+        }
 
-        // Known SHA-256 of "Hello, World!"
+        // Random byte soup never panics
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        for _ in 0..200 {
+            let doc: Vec<u8> = (0..256)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    let alphabet = b"ab0x:{}[]&*- \n\"'";
+                    alphabet[(seed % alphabet.len() as u64) as usize]
+                })
+                .collect();
+            let _ = WalrusStorage::parse_objects_map(&doc, max);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_batched_object_is_not_cached() {
+        let first: &[u8] = b"blob 5\0hello";
+        let second: &[u8] = b"blob 5\0world";
+        let full_blob = [first, second].concat();
+        let git_sha1 = |content: &[u8]| hex::encode(sha1::Sha1::digest(content));
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FilesystemStorage::new(dir.path()).unwrap();
+        cache.initialize().unwrap();
+        let mut cache_index = CacheIndex::new();
+
+        // An off-by-one offset slices the wrong bytes; the SHA-1 check must catch it
+        let id = format!("0xblob:1:{}", second.len());
+        let parsed_id = ParsedContentId::parse(&id).unwrap();
+        let err = WalrusStorage::extract_and_cache(
+            &cache,
+            &mut cache_index,
+            &id,
+            &parsed_id,
+            &full_blob,
+            Some(&git_sha1(second)),
+        )
+        .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("checksum mismatch"),
+            "{:#}",
+            err
+        );
+        assert!(cache_index.get_sha256(&id, ContentKind::RawLoose).is_none());
+        assert!(dir
+            .path()
+            .join("objects")
+            .read_dir()
+            .unwrap()
+            .next()
+            .is_none());
+
+        // The right offset verifies and is cached
+        let id = format!("0xblob:{}:{}", first.len(), second.len());
+        let parsed_id = ParsedContentId::parse(&id).unwrap();
+        let content = WalrusStorage::extract_and_cache(
+            &cache,
+            &mut cache_index,
+            &id,
+            &parsed_id,
+            &full_blob,
+            Some(&git_sha1(second)),
+        )
+        .unwrap();
+        assert_eq!(content, second);
+        let sha256 = cache_index.get_sha256(&id, ContentKind::RawLoose).unwrap();
+        assert_eq!(cache.read_object(sha256).unwrap(), second);
+    }
+
+    #[test]
+    fn test_second_remote_reads_a_shared_object_from_the_cache() {
+        let content: &[u8] = b"blob 6\0shared";
+        let git_sha1 = hex::encode(sha1::Sha1::digest(content));
+        let sha256 = WalrusStorage::compute_sha256(content);
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FilesystemStorage::new(dir.path()).unwrap();
+        cache.initialize().unwrap();
+        let mut cache_index = CacheIndex::new();
+
+        // A fork downloads the object from a blob of its own
+        let fork_id = format!("0xfork:0:{}", content.len());
+        let fork_blob = [content, b"blob 5\0other"].concat();
+        WalrusStorage::extract_and_cache(
+            &cache,
+            &mut cache_index,
+            &fork_id,
+            &ParsedContentId::parse(&fork_id).unwrap(),
+            &fork_blob,
+            Some(&git_sha1),
+        )
+        .unwrap();
+
+        // Upstream stores it elsewhere; knowing the git SHA-1, its read is a hit, after which
+        // its own ContentId finds the same cached file
+        let upstream_id = format!("0xupstream:7:{}", content.len());
+        let siblings = SiblingIndexes::default();
+        let mut read = |id: &str, git_sha1: Option<&str>| {
+            WalrusStorage::read_cached(&cache, &mut cache_index, &siblings, id, git_sha1)
+        };
+        assert!(read(&upstream_id, None).is_none());
+        assert_eq!(read(&upstream_id, Some(&git_sha1)).unwrap(), content);
+        assert_eq!(read(&upstream_id, None).unwrap(), content);
+        assert_eq!(dir.path().join("objects").read_dir().unwrap().count(), 1);
+
+        // Uploads of the same bytes still reuse the ContentId they were first cached under
         assert_eq!(
-            hash,
-            "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+            cache_index.get_object_id(&sha256, ContentKind::RawLoose),
+            Some(&fork_id)
+        );
+
+        // Forgetting the fork's blob leaves the object cached for upstream
+        WalrusStorage::forget_blob(&mut cache_index, "0xfork");
+        assert!(cache_index
+            .get_sha256(&fork_id, ContentKind::RawLoose)
+            .is_none());
+        assert_eq!(
+            cache_index.get_object_id(&sha256, ContentKind::RawLoose),
+            Some(&upstream_id)
+        );
+        assert_eq!(cache_index.get_sha256_by_git_sha1(&git_sha1), Some(&sha256));
+    }
+
+    #[test]
+    fn test_two_remotes_read_a_shared_object_from_the_cache() {
+        let remote = MockRemote::new();
+        let content: &[u8] = b"blob 6\0shared";
+        let git_sha1 = hex::encode(sha1::Sha1::digest(content));
+        let write = |storage: &WalrusStorage| {
+            let id = storage.write_object(content).unwrap();
+            let mut state = State::default();
+            state.objects.insert(git_sha1.clone(), id.clone());
+            storage.write_state(&state).unwrap();
+            id
+        };
+
+        // A fork pushed the object from another machine, into a blob of its own
+        let fork_state = remote.sui.create_remote(remote.owner, false);
+        let mut elsewhere = remote.config.clone();
+        elsewhere.cache_dir = remote.dir.path().join("elsewhere");
+        let fork_id = write(&remote.storage_of(fork_state, "fork", &elsewhere));
+
+        // Upstream's copy is cached here
+        let upstream_id = write(&remote.storage());
+        assert_ne!(upstream_id, fork_id);
+        let upstream = remote.storage();
+        upstream.read_state().unwrap();
+        assert_eq!(upstream.read_object(&upstream_id).unwrap(), content);
+
+        // Fetching the fork here reads the object by the git SHA-1 its state maps it to, which
+        // finds upstream's cached copy without downloading the fork's blob
+        let fork = remote.storage_of(fork_state, "fork", &remote.config);
+        fork.read_state().unwrap();
+        let reads = remote.walrus_reads();
+        assert_eq!(fork.read_object(&fork_id).unwrap(), content);
+        assert_eq!(fork.read_source(&fork_id), Some(ReadSource::Cache));
+        assert_eq!(remote.walrus_reads(), reads);
+
+        // The fork's own ContentId now finds it too, without the state
+        let fork = remote.storage_of(fork_state, "fork", &remote.config);
+        assert_eq!(fork.read_object(&fork_id).unwrap(), content);
+        assert_eq!(remote.walrus_reads(), reads);
+    }
+
+    #[test]
+    fn test_shared_blob_is_downloaded_once() {
+        let remote = MockRemote::new();
+        let contents: [&[u8]; 2] = [b"blob 5\0first", b"blob 6\0second"];
+        let ids = remote.storage().write_objects(&contents).unwrap();
+        let blob_object_id = |id: &str| {
+            ParsedContentId::parse(id)
+                .unwrap()
+                .blob_object_id()
+                .map(str::to_string)
+        };
+        assert_eq!(blob_object_id(&ids[0]), blob_object_id(&ids[1]));
+
+        // Each read is a new session that has lost its object cache, but not the blob cache
+        let objects_dir = remote.dir.path().join("cache/objects");
+        let read_fresh = |id: &str| {
+            let _ = std::fs::remove_dir_all(&objects_dir);
+            remote.storage().read_object(id).unwrap()
+        };
+        let reads = remote.walrus_reads();
+        assert_eq!(read_fresh(&ids[0]), contents[0]);
+        assert_eq!(remote.walrus_reads(), reads + 1);
+        assert_eq!(read_fresh(&ids[1]), contents[1]);
+        assert_eq!(remote.walrus_reads(), reads + 1);
+
+        // A damaged cached blob is downloaded again rather than trusted
+        let blob_path = remote
+            .dir
+            .path()
+            .join("cache/blobs")
+            .join(blob_object_id(&ids[0]).unwrap());
+        let mut bytes = std::fs::read(&blob_path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        std::fs::write(&blob_path, bytes).unwrap();
+        assert_eq!(read_fresh(&ids[1]), contents[1]);
+        assert_eq!(remote.walrus_reads(), reads + 2);
+    }
+
+    #[test]
+    fn test_cache_evicts_and_deletes_through_storage() {
+        let mut remote = MockRemote::new();
+        remote.config.cache_max_entries = Some(5);
+        let contents: Vec<Vec<u8>> = (0..8)
+            .map(|i| format!("blob 8\0object {}", i).into_bytes())
+            .collect();
+        let contents: Vec<&[u8]> = contents.iter().map(Vec::as_slice).collect();
+        let storage = remote.storage();
+        let ids = storage.write_objects(&contents).unwrap();
+
+        // Over capacity, the first cached go until 80% of it is left, files and all
+        let cached_file = |content: &[u8]| {
+            remote
+                .dir
+                .path()
+                .join("cache/objects")
+                .join(WalrusStorage::compute_sha256(content))
+        };
+        for (i, (id, content)) in ids.iter().zip(&contents).enumerate() {
+            assert_eq!(storage.object_exists(id).unwrap(), i >= 4, "{}", i);
+            assert_eq!(cached_file(content).exists(), i >= 4, "{}", i);
+        }
+        let index = std::fs::read_to_string(
+            remote_cache_dir(&remote.config.cache_dir, "origin").join(CACHE_INDEX_FILE),
+        )
+        .unwrap();
+        assert!(!index.contains("recency"));
+
+        // An evicted object is downloaded again, a kept one is still a cache hit
+        let reads = remote.walrus_reads();
+        let storage = remote.storage();
+        assert_eq!(storage.read_object(&ids[0]).unwrap(), contents[0]);
+        assert_eq!(storage.read_source(&ids[0]), Some(ReadSource::Walrus));
+        assert_eq!(remote.walrus_reads(), reads + 1);
+        assert_eq!(storage.read_object(&ids[7]).unwrap(), contents[7]);
+        assert_eq!(storage.read_source(&ids[7]), Some(ReadSource::Cache));
+
+        // Deleting an object only drops its cached copy
+        storage.delete_object(&ids[7]).unwrap();
+        assert!(!cached_file(contents[7]).exists());
+        assert_eq!(remote.storage().read_object(&ids[7]).unwrap(), contents[7]);
+    }
+
+    #[test]
+    fn test_cache_hits_are_verified() {
+        let content: &[u8] = b"blob 4\0good";
+        let git_sha1 = hex::encode(sha1::Sha1::digest(content));
+        let other_sha1 = hex::encode(sha1::Sha1::digest(b"blob 5\0other"));
+        let sha256 = WalrusStorage::compute_sha256(content);
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FilesystemStorage::new(dir.path()).unwrap();
+        cache.initialize().unwrap();
+        let siblings = SiblingIndexes::default();
+        let mut cache_index = CacheIndex::new();
+        let seed = [(content, "0xa:0:11"), (content, "0xb:0:11")];
+        WalrusStorage::seed_cache(&cache, &mut cache_index, &seed).unwrap();
+
+        // A hit for a different object than expected only drops that mapping
+        assert!(WalrusStorage::read_cached(
+            &cache,
+            &mut cache_index,
+            &siblings,
+            "0xa:0:11",
+            Some(&other_sha1)
+        )
+        .is_none());
+        assert!(!cache_index.contains_object("0xa:0:11"));
+        assert_eq!(
+            WalrusStorage::read_cached(
+                &cache,
+                &mut cache_index,
+                &siblings,
+                "0xb:0:11",
+                Some(&git_sha1)
+            )
+            .unwrap(),
+            content
+        );
+
+        // A damaged file is a miss even without a git SHA-1, and is removed
+        std::fs::write(dir.path().join("objects").join(&sha256), b"blob 4\0evil").unwrap();
+        assert!(
+            WalrusStorage::read_cached(&cache, &mut cache_index, &siblings, "0xb:0:11", None)
+                .is_none()
+        );
+        assert!(!cache_index.contains_sha256(&sha256));
+        assert!(!cache.object_exists(&sha256).unwrap());
+    }
+
+    #[test]
+    fn test_remotes_keep_their_own_cache_files() {
+        let content: &[u8] = b"blob 6\0shared";
+        let git_sha1 = hex::encode(sha1::Sha1::digest(content));
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FilesystemStorage::new(dir.path()).unwrap();
+        cache.initialize().unwrap();
+        let remotes_dir = dir.path().join(REMOTES_DIR);
+        let fork_dir = remote_cache_dir(dir.path(), "fork");
+        let upstream_dir = remote_cache_dir(dir.path(), "upstream");
+        assert_ne!(fork_dir, upstream_dir);
+        assert_eq!(
+            remote_cache_dir(dir.path(), "walrus::0xabc"),
+            remotes_dir.join(private_name("walrus::0xabc"))
+        );
+
+        // The fork caches the object under its ContentId, in its own index
+        let mut fork_index = CacheIndex::new();
+        let fork_id = format!("0xfork:0:{}", content.len());
+        WalrusStorage::extract_and_cache(
+            &cache,
+            &mut fork_index,
+            &fork_id,
+            &ParsedContentId::parse(&fork_id).unwrap(),
+            content,
+            Some(&git_sha1),
+        )
+        .unwrap();
+        fork_index.save(&fork_dir.join(CACHE_INDEX_FILE)).unwrap();
+
+        // Upstream's index doesn't know it, but the fork's finds the cached file
+        let mut upstream_index = CacheIndex::new();
+        let upstream_id = format!("0xupstream:0:{}", content.len());
+        let siblings = SiblingIndexes::in_dir(&remotes_dir, &upstream_dir, CACHE_INDEX_FILE);
+        let mut read = |id: &str, git_sha1: Option<&str>| {
+            WalrusStorage::read_cached(&cache, &mut upstream_index, &siblings, id, git_sha1)
+        };
+        assert!(read(&upstream_id, None).is_none());
+        assert_eq!(read(&upstream_id, Some(&git_sha1)).unwrap(), content);
+        assert_eq!(read(&upstream_id, None).unwrap(), content);
+        assert!(upstream_index.contains_object(&upstream_id));
+        assert!(!upstream_index.contains_object(&fork_id));
+
+        // A remote doesn't count itself among its siblings
+        let own = SiblingIndexes::in_dir(&remotes_dir, &fork_dir, CACHE_INDEX_FILE);
+        assert!(own.get_sha256_by_git_sha1(&git_sha1).is_none());
+        assert!(siblings.contains_sha256(&WalrusStorage::compute_sha256(content)));
+    }
+
+    #[test]
+    fn test_seeded_objects_are_not_uploaded_again() {
+        let stored: &[u8] = b"blob 3\0old";
+        let new: &[u8] = b"blob 3\0new";
+        let empty: &[u8] = b"blob 0\0";
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FilesystemStorage::new(dir.path()).unwrap();
+        cache.initialize().unwrap();
+        let mut cache_index = CacheIndex::new();
+
+        // A lost cache is refilled from a pack's objects the remote's map already lists
+        let stored_id = "0xhistory:0:10";
+        let seed = [(stored, stored_id), (empty, "const:empty-blob")];
+        assert_eq!(
+            WalrusStorage::seed_cache(&cache, &mut cache_index, &seed).unwrap(),
+            1
+        );
+        assert_eq!(
+            WalrusStorage::seed_cache(&cache, &mut cache_index, &seed).unwrap(),
+            0
+        );
+
+        let (cached, to_upload) = WalrusStorage::partition_cached(&cache_index, &[stored, new]);
+        let siblings = SiblingIndexes::default();
+        assert_eq!(cached, [Some(stored_id.to_string()), None]);
+        assert_eq!(to_upload.len(), 1);
+        assert_eq!(to_upload[0].1, new);
+        assert_eq!(
+            WalrusStorage::read_cached(&cache, &mut cache_index, &siblings, stored_id, None)
+                .unwrap(),
+            stored
+        );
+    }
+
+    #[test]
+    fn test_transfer_pools_are_bounded_independently() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let config: WalrusRemoteConfig = serde_yaml::from_str(
+            "sui_wallet_path: /wallet\ncache_dir: /cache\njobs: 2\ndownload_concurrency: 5\n",
+        )
+        .unwrap();
+        let concurrency = config.concurrency();
+
+        // Most transfers running at once, over more items than any limit
+        let peak = |limit| {
+            let (running, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+            let items: Vec<usize> = (0..20).collect();
+            let doubled = map_bounded(&items, limit, |&i| {
+                peak.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                i * 2
+            })
+            .unwrap();
+            assert_eq!(doubled, (0..20).map(|i| i * 2).collect::<Vec<_>>());
+            peak.into_inner()
+        };
+        assert_eq!(peak(concurrency.upload), 2);
+        assert_eq!(peak(concurrency.download), 5);
+        assert_eq!(peak(1), 1);
+    }
+
+    #[test]
+    fn test_consolidation_keeps_each_ref_retention() {
+        let mut remote = MockRemote::new();
+        remote.config.default_epochs = 5;
+        remote.config.retention_rules = vec![RetentionRule {
+            refs: "refs/tags/*".to_string(),
+            epochs: 50,
+        }];
+        let storage = remote.storage();
+
+        // Two commits (the second on top of the first) and an object no ref reaches, each
+        // object pushed in a blob of its own
+        let mut state = State::default();
+        let mut store = |kind: Kind, content: Vec<u8>| -> String {
+            let loose = GitObject::from_raw(kind, content)
+                .unwrap()
+                .to_loose_format();
+            let git_sha1 = hex::encode(Sha1::digest(&loose));
+            let id = storage.write_object(&loose).unwrap();
+            state.objects.insert(git_sha1.clone(), id);
+            git_sha1
+        };
+        let mut commits: Vec<Vec<String>> = Vec::new();
+        for i in 0..2 {
+            let blob = store(Kind::Blob, format!("version {}\n", i).into_bytes());
+            let mut tree = b"100644 file\0".to_vec();
+            tree.extend(hex::decode(&blob).unwrap());
+            let tree = store(Kind::Tree, tree);
+            let mut commit = format!("tree {}\n", tree);
+            if let Some(parent) = commits.last() {
+                commit.push_str(&format!("parent {}\n", parent[0]));
+            }
+            commit.push_str("author A <a@a> 0 +0000\ncommitter A <a@a> 0 +0000\n\nc\n");
+            let commit = store(Kind::Commit, commit.into_bytes());
+            commits.push(vec![commit, tree, blob]);
+        }
+        let orphan = store(Kind::Blob, b"unreachable\n".to_vec());
+        state
+            .refs
+            .insert("refs/tags/v1".to_string(), commits[0][0].clone());
+        state
+            .refs
+            .insert("refs/heads/main".to_string(), commits[1][0].clone());
+        storage.write_state(&state).unwrap();
+
+        let report = storage.consolidate_blobs().unwrap();
+        assert_eq!(report.blobs_before, 7);
+        assert_eq!(report.moved, 7);
+        // One blob for the tagged commit, one for the rest
+        assert_eq!(report.blobs_after, 2);
+
+        let consolidated = remote.storage().read_state().unwrap();
+        let end_epoch = |git_sha1: &str| {
+            let parsed = ParsedContentId::parse(&consolidated.objects[git_sha1]).unwrap();
+            storage
+                .runtime
+                .block_on(
+                    storage
+                        .sui_client
+                        .get_shared_blob_status(parsed.blob_object_id().unwrap()),
+                )
+                .unwrap()
+                .end_epoch
+        };
+        // The tag keeps the commit it reaches for its 50 epochs, though main reaches it too
+        let tagged = end_epoch(&commits[0][0]);
+        for git_sha1 in commits[0].iter().chain(&commits[1]).chain([&orphan]) {
+            let expected = if commits[0].contains(git_sha1) {
+                tagged
+            } else {
+                tagged - 45
+            };
+            assert_eq!(end_epoch(git_sha1), expected, "{}", git_sha1);
+        }
+
+        // Everything reads back from its new blob, on a client with no cache
+        std::fs::remove_dir_all(&remote.config.cache_dir).unwrap();
+        let fresh = remote.storage();
+        let objects: Vec<(&str, &str)> = consolidated
+            .objects
+            .iter()
+            .map(|(git_sha1, id)| (git_sha1.as_str(), id.as_str()))
+            .collect();
+        assert_eq!(fresh.read_git_objects(&objects).unwrap().len(), 7);
+
+        // Consolidated, there's nothing left to repack
+        let report = fresh.consolidate_blobs().unwrap();
+        assert_eq!((report.moved, report.blobs_after), (0, 2));
+    }
+
+    /// Git SHA-1 and loose bytes of an object
+    fn loose_object(kind: Kind, data: Vec<u8>) -> (String, Vec<u8>) {
+        let loose = GitObject::from_raw(kind, data).unwrap().to_loose_format();
+        (hex::encode(Sha1::digest(&loose)), loose)
+    }
+
+    /// Commit, tree and blob of a root commit whose tree holds one file of `content`
+    fn commit_objects(content: &str) -> [(String, Vec<u8>); 3] {
+        let blob = loose_object(Kind::Blob, content.as_bytes().to_vec());
+        let mut tree = b"100644 file\0".to_vec();
+        tree.extend(hex::decode(&blob.0).unwrap());
+        let tree = loose_object(Kind::Tree, tree);
+        let commit = format!(
+            "tree {}\nauthor A <a@a> 0 +0000\ncommitter A <a@a> 0 +0000\n\nc\n",
+            tree.0
         );
+        [loose_object(Kind::Commit, commit.into_bytes()), tree, blob]
+    }
+
+    #[test]
+    fn test_repack_keeps_history_and_pinned_blobs() {
+        let mut remote = MockRemote::new();
+
+        // Two unrelated commits and a large object nothing reaches, batched into one blob
+        let old = commit_objects("old\n");
+        let new = commit_objects("new\n");
+        let junk = loose_object(Kind::Blob, vec![b'x'; 10_000]);
+        let objects: Vec<&(String, Vec<u8>)> = old.iter().chain(&new).chain([&junk]).collect();
+        let contents: Vec<&[u8]> = objects.iter().map(|(_, loose)| loose.as_slice()).collect();
+        let ids = remote.storage().write_objects(&contents).unwrap();
+        let mut state = State::default();
+        for ((git_sha1, _), id) in objects.iter().zip(ids) {
+            state.objects.insert(git_sha1.clone(), id);
+        }
+
+        // main held the old commit before it was force-pushed to the new one
+        state
+            .refs
+            .insert("refs/heads/main".to_string(), old[0].0.clone());
+        remote.storage().write_state(&state).unwrap();
+        let pin = remote.sui.transactions(remote.state).pop().unwrap();
+        state
+            .refs
+            .insert("refs/heads/main".to_string(), new[0].0.clone());
+        remote.storage().write_state(&state).unwrap();
+
+        // The pinned state reads the batch, so while it is kept the batch stays as it is
+        remote.config.keep_pinned = vec![pin];
+        assert!(remote.storage().repack_sparse().unwrap().is_empty());
+
+        // Otherwise only the object no ref ever reached is dropped
+        remote.config.keep_pinned.clear();
+        let repacked = remote.storage().repack_sparse().unwrap();
+        assert_eq!(repacked.len(), 1);
+        assert_eq!((repacked[0].moved, repacked[0].dropped), (6, 1));
+
+        let current = remote.storage().read_state().unwrap();
+        assert!(!current.objects.contains_key(&junk.0));
+        std::fs::remove_dir_all(&remote.config.cache_dir).unwrap();
+        let fresh = remote.storage();
+        for (git_sha1, loose) in old.iter().chain(&new) {
+            let id = &current.objects[git_sha1];
+            assert_eq!(
+                ParsedContentId::parse(id).unwrap().blob_object_id(),
+                repacked[0].new_object_id.as_deref()
+            );
+            assert_eq!(&fresh.read_object(id).unwrap(), loose);
+        }
     }
 }
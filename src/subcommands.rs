@@ -0,0 +1,28 @@
+pub mod archive;
+pub mod auto_renew;
+pub mod cat;
+pub mod compact;
+pub mod compact_refs;
+pub mod daemon;
+pub mod dedup_report;
+pub mod describe;
+pub mod diff_state;
+pub mod doctor;
+pub mod fsck;
+pub mod graph;
+pub mod list_repos;
+pub mod log_path;
+pub mod migrate;
+pub mod migrate_layout;
+pub mod policy;
+pub mod publish_site;
+pub mod reclaim;
+pub mod refs;
+pub mod repack;
+pub mod serve;
+pub mod set_description;
+pub mod status;
+pub mod symref;
+pub mod unlock;
+pub mod watch;
+pub mod whoami;
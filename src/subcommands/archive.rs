@@ -0,0 +1,78 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+
+use super::publish_site::resolve_ref;
+use crate::{
+    commands::namespace::RefNamespace,
+    pack::archive::{self, ArchiveFormat},
+    remote::parse_remote_url,
+    remote_resolution::Remote,
+    storage::MutableState,
+};
+
+/// Handle the `archive` subcommand
+/// Writes the tree of `ref_name` as an archive to `out` (stdout when `None`), without a
+/// local repository
+pub fn handle(
+    remote: &Remote,
+    ref_name: &str,
+    format: Option<ArchiveFormat>,
+    out: Option<&Path>,
+    prefix: &str,
+) -> Result<()> {
+    let format = format
+        .or_else(|| out.and_then(ArchiveFormat::from_path))
+        .unwrap_or(ArchiveFormat::Tar);
+    let namespace =
+        RefNamespace::resolve(parse_remote_url(&remote.url)?.options.namespace.as_deref())?;
+
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    let state = storage.read_state()?;
+    let (full_ref, commit) = resolve_ref(&state.refs, &namespace, ref_name)?;
+
+    let Some(out) = out else {
+        let stdout = std::io::stdout().lock();
+        archive::write_archive(
+            &storage,
+            &state,
+            commit,
+            format,
+            prefix,
+            BufWriter::new(stdout),
+        )
+        .with_context(|| format!("Failed to archive {}", full_ref))?;
+        return Ok(());
+    };
+
+    let file = File::create(out).with_context(|| format!("Failed to create {}", out.display()))?;
+    let mut writer = BufWriter::new(file);
+    let written = archive::write_archive(&storage, &state, commit, format, prefix, &mut writer)
+        .and_then(|written| {
+            writer.flush()?;
+            Ok(written)
+        });
+    let written = match written {
+        Ok(written) => written,
+        Err(e) => {
+            // Don't leave a truncated archive behind
+            drop(writer);
+            let _ = std::fs::remove_file(out);
+            return Err(e.context(format!("Failed to archive {}", full_ref)));
+        }
+    };
+
+    println!(
+        "✓ Wrote {} archive of {} ({}) with {} entries to {}",
+        format,
+        full_ref,
+        commit,
+        written,
+        out.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use std::{future::Future, pin::pin, task::Poll, time::Duration};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    config::WalrusRemoteConfig,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    storage::{StorageBackend, WalrusStorage},
+};
+
+/// Handle the `auto-renew` subcommand
+/// Extends a remote's expiring blobs every `interval` until interrupted (or once with `once`)
+pub fn handle(remote: &Remote, interval: Duration, once: bool) -> Result<()> {
+    let remote_url = parse_remote_url(&remote.url)?;
+    let object_id = match remote_url.remote_type {
+        RemoteType::Sui(object_id) => object_id,
+        RemoteType::Filesystem(path) => anyhow::bail!(
+            "auto-renew only applies to Walrus remotes, not filesystem remote {:?}",
+            path
+        ),
+    };
+
+    let mut config = WalrusRemoteConfig::load_for_remote(Some(&remote.name))
+        .context("Failed to load configuration")?;
+    remote_url.options.apply(&mut config);
+
+    let storage = WalrusStorage::new(object_id, remote.name.clone(), config)?;
+    storage.initialize()?;
+
+    let shutdown = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+        tracing::info!("Interrupted, stopping auto-renew");
+    };
+
+    renew_loop(|| renew_once(&storage), interval, once, shutdown)
+}
+
+/// Run one renewal pass and report it; fails if any blob could not be renewed
+fn renew_once(storage: &WalrusStorage) -> Result<()> {
+    let pass = storage.renew_expiring()?;
+
+    for renewal in &pass.renewed {
+        println!(
+            "  renewed {} (end epoch {} -> {})",
+            renewal.object_id, renewal.old_end_epoch, renewal.new_end_epoch
+        );
+    }
+    for repacked in &pass.repacked {
+        match &repacked.new_object_id {
+            Some(new_object_id) => println!(
+                "  repacked {} into {} ({} live object(s), {} dropped, {} bytes saved)",
+                repacked.old_object_id,
+                new_object_id,
+                repacked.moved,
+                repacked.dropped,
+                repacked.bytes_saved
+            ),
+            None => println!(
+                "  dropped {} ({} unreachable object(s), {} bytes saved)",
+                repacked.old_object_id, repacked.dropped, repacked.bytes_saved
+            ),
+        }
+    }
+    for object_id in &pass.expired {
+        println!("  expired {} (cannot be renewed)", object_id);
+    }
+    println!(
+        "✓ Renewed {} blob(s) at epoch {}",
+        pass.renewed.len(),
+        pass.current_epoch
+    );
+
+    if !pass.failed.is_empty() {
+        anyhow::bail!("{} blob(s) could not be renewed", pass.failed.len());
+    }
+    Ok(())
+}
+
+/// Call `pass` every `interval` until `shutdown` completes, or just once if `once`
+///
+/// Passes run synchronously and are never interrupted; `shutdown` is polled before the first
+/// pass so a signal received during a pass stops the loop before the next one. A failed pass
+/// is logged and retried after `interval`, except with `once`, where it is returned.
+pub fn renew_loop(
+    mut pass: impl FnMut() -> Result<()>,
+    interval: Duration,
+    once: bool,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let mut shutdown = pin!(shutdown);
+
+    let stopped = runtime.block_on(std::future::poll_fn(|cx| {
+        Poll::Ready(shutdown.as_mut().poll(cx).is_ready())
+    }));
+    if stopped {
+        return Ok(());
+    }
+
+    loop {
+        match pass() {
+            Ok(()) => {}
+            Err(e) if once => return Err(e),
+            Err(e) => {
+                tracing::warn!("Renewal pass failed (retrying in {:?}): {:#}", interval, e)
+            }
+        }
+        if once {
+            return Ok(());
+        }
+
+        let stopped = runtime.block_on(async {
+            tokio::select! {
+                biased;
+                _ = shutdown.as_mut() => true,
+                _ = tokio::time::sleep(interval) => false,
+            }
+        });
+        if stopped {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+
+    #[test]
+    fn test_once_runs_a_single_pass_and_returns_its_error() {
+        let calls = Cell::new(0);
+        renew_loop(
+            || {
+                calls.set(calls.get() + 1);
+                Ok(())
+            },
+            Duration::ZERO,
+            true,
+            std::future::pending(),
+        )
+        .unwrap();
+        assert_eq!(calls.get(), 1);
+
+        let err = renew_loop(
+            || anyhow::bail!("network down"),
+            Duration::ZERO,
+            true,
+            std::future::pending(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "network down");
+    }
+
+    #[test]
+    fn test_failed_pass_is_retried_until_shutdown() {
+        let results = RefCell::new(vec![Ok(()), Err(anyhow::anyhow!("timeout"))]);
+        let calls = Cell::new(0);
+        let stop = Cell::new(false);
+        renew_loop(
+            || {
+                calls.set(calls.get() + 1);
+                // Stop after the script runs out: the signal arrives mid-pass
+                results.borrow_mut().pop().unwrap_or_else(|| {
+                    stop.set(true);
+                    Ok(())
+                })
+            },
+            Duration::ZERO,
+            false,
+            std::future::poll_fn(|_| {
+                if stop.get() {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            }),
+        )
+        .unwrap();
+        assert_eq!(calls.get(), 3);
+    }
+}
@@ -0,0 +1,50 @@
+use std::io::Write;
+
+use anyhow::{Context, Result};
+
+use super::publish_site::resolve_ref;
+use crate::{
+    commands::namespace::RefNamespace,
+    pack::history,
+    remote::parse_remote_url,
+    remote_resolution::Remote,
+    storage::{MutableState, State},
+};
+
+/// Handle the `cat` subcommand
+/// Writes the file at `<ref>:<path>` to stdout, reading only the trees along the path
+pub fn handle(remote: &Remote, spec: &str) -> Result<()> {
+    let (ref_name, path) = spec
+        .split_once(':')
+        .with_context(|| format!("Expected <ref>:<path>, got {:?}", spec))?;
+    let namespace =
+        RefNamespace::resolve(parse_remote_url(&remote.url)?.options.namespace.as_deref())?;
+
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    let state = storage.read_state()?;
+    let (full_ref, commit) = resolve_revision(&state, &namespace, ref_name)?;
+
+    let data = history::read_path(&storage, &state, commit, path)
+        .with_context(|| format!("Failed to read {} at {}", path, full_ref))?;
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&data)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Resolve `name` like [`resolve_ref`], where `HEAD` (or an empty name) is the remote's HEAD:
+/// its stored target, otherwise `refs/heads/main`
+pub(super) fn resolve_revision<'a>(
+    state: &'a State,
+    namespace: &RefNamespace,
+    name: &str,
+) -> Result<(String, &'a str)> {
+    if !name.is_empty() && name != "HEAD" {
+        return resolve_ref(&state.refs, namespace, name);
+    }
+    let target = namespace
+        .local_symrefs(&state.symrefs)
+        .find(|(name, _)| *name == "HEAD")
+        .map_or("refs/heads/main", |(_, target)| target);
+    resolve_ref(&state.refs, namespace, target)
+}
@@ -0,0 +1,230 @@
+use std::collections::{BTreeSet, HashSet};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    pack::send::collect_reachable_objects,
+    remote_resolution::Remote,
+    storage::{to_canonical, State, StorageBackend},
+};
+
+/// Size of a remote's objects map before and after `compact`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Compaction {
+    pub entries_before: usize,
+    pub entries_after: usize,
+    /// Serialized size of the entries, in bytes
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+/// Handle the `compact` subcommand
+/// Rewrites a remote's objects map with only the objects its refs reach, then repacks the
+/// Walrus batches left mostly dead
+pub fn handle(remote: &Remote) -> Result<()> {
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    let compaction = compact(&storage)?;
+
+    if compaction.entries_after == compaction.entries_before {
+        println!(
+            "Nothing to compact: all {} objects are reachable from refs.",
+            compaction.entries_before
+        );
+    } else {
+        println!(
+            "✓ Compacted the objects map from {} entries ({} bytes) to {} ({} bytes)",
+            compaction.entries_before,
+            compaction.bytes_before,
+            compaction.entries_after,
+            compaction.bytes_after
+        );
+        println!("  Blobs left unreferenced will expire (or run `reclaim`).");
+    }
+
+    // Batches that now hold mostly dropped objects are copied down to their live ones
+    if let crate::Storage::Walrus(walrus) = &storage {
+        let repacked = walrus.repack_sparse()?;
+        if !repacked.is_empty() {
+            println!(
+                "✓ Repacked {} sparse blob(s), saving {} bytes",
+                repacked.len(),
+                repacked.iter().map(|r| r.bytes_saved).sum::<u64>()
+            );
+            println!(
+                "  The old blobs are no longer referenced and will expire (or run `reclaim`)."
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Drop the objects no ref reaches from `storage`'s objects map
+///
+/// Refs are read afresh, and the map is replaced by the single state update at the end, so an
+/// interrupted run leaves the old map in place.
+pub fn compact(storage: &impl StorageBackend) -> Result<Compaction> {
+    storage.begin_session();
+    let state = storage.read_state()?;
+    let live = live_objects(storage, &state)?;
+    if live.len() == state.objects.len() {
+        let bytes = to_canonical(&state.objects).len();
+        return Ok(Compaction {
+            entries_before: live.len(),
+            entries_after: live.len(),
+            bytes_before: bytes,
+            bytes_after: bytes,
+        });
+    }
+
+    // The update reads the state again, rather than the copy the walk started from
+    storage.begin_session();
+    apply(storage, &state, &live)
+}
+
+/// Object IDs reachable from `state`'s refs (including refs set aside as invalid whose value
+/// is a stored object)
+fn live_objects(storage: &impl StorageBackend, state: &State) -> Result<HashSet<String>> {
+    let tips: Vec<String> = state
+        .refs
+        .values()
+        .chain(
+            state
+                .invalid_refs
+                .values()
+                .filter(|value| state.objects.contains_key(*value)),
+        )
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    tracing::info!(
+        "Walking {} objects from {} tip(s)...",
+        state.objects.len(),
+        tips.len()
+    );
+    let reachable = collect_reachable_objects(&tips, state, storage)
+        .context("Failed to walk the objects reachable from refs (run fsck --full)")?;
+    Ok(reachable.into_iter().map(|obj| obj.id).collect())
+}
+
+/// Keep only `live` objects, refusing if the refs differ from those in `read`
+fn apply(
+    storage: &impl StorageBackend,
+    read: &State,
+    live: &HashSet<String>,
+) -> Result<Compaction> {
+    let mut compaction = Compaction::default();
+    storage.update_state(|current| {
+        if current.refs != read.refs || current.invalid_refs != read.invalid_refs {
+            anyhow::bail!("refs changed on the remote while compacting; run compact again");
+        }
+        compaction.entries_before = current.objects.len();
+        compaction.bytes_before = to_canonical(&current.objects).len();
+        current
+            .objects
+            .retain(|git_sha1, _| live.contains(git_sha1));
+        compaction.entries_after = current.objects.len();
+        compaction.bytes_after = to_canonical(&current.objects).len();
+        Ok(())
+    })?;
+    Ok(compaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use gix_object::Kind;
+
+    use super::*;
+    use crate::{
+        pack::{receive::receive_pack, send_pack},
+        storage::{store_object, MemoryStorage, MutableState},
+    };
+
+    /// A commit of one file with `content`, on top of `parent`
+    fn commit(storage: &MemoryStorage, content: &str, parent: Option<&str>) -> String {
+        let blob = store_object(storage, Kind::Blob, content);
+        let tree = store_object(
+            storage,
+            Kind::Tree,
+            [b"100644 file.txt\0".to_vec(), hex::decode(&blob).unwrap()].concat(),
+        );
+        let parent = parent.map_or(String::new(), |p| format!("parent {}\n", p));
+        store_object(
+            storage,
+            Kind::Commit,
+            format!(
+                "tree {}\n{}author A <a@a> 100 +0000\ncommitter A <a@a> 100 +0000\n\n{}\n",
+                tree, parent, content
+            ),
+        )
+    }
+
+    fn set_ref(storage: &MemoryStorage, name: &str, tip: Option<&str>) {
+        storage
+            .update_state(|state| {
+                match tip {
+                    Some(tip) => state.refs.insert(name.to_string(), tip.to_string()),
+                    None => state.refs.remove(name),
+                };
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_compact_keeps_what_a_fetch_needs() {
+        let remote = MemoryStorage::new();
+        let base = commit(&remote, "base", None);
+
+        // Rebased and force-pushed three times, with a topic branch pushed and deleted
+        let mut tip = base.clone();
+        for round in 0..3 {
+            tip = commit(&remote, &format!("rebased {}", round), Some(&base));
+            set_ref(&remote, "refs/heads/main", Some(&tip));
+        }
+        let topic = commit(&remote, "topic", Some(&tip));
+        set_ref(&remote, "refs/heads/topic", Some(&topic));
+        set_ref(&remote, "refs/heads/topic", None);
+        assert_eq!(remote.read_state().unwrap().objects.len(), 15);
+
+        let compaction = compact(&remote).unwrap();
+        assert_eq!(compaction.entries_before, 15);
+        assert_eq!(compaction.entries_after, 6);
+        assert!(compaction.bytes_after < compaction.bytes_before);
+
+        // A full fetch of main still finds every object it needs, and only those remain
+        let wanted = vec!["refs/heads/main".to_string()];
+        let mut pack = Vec::new();
+        send_pack(&wanted, &remote, &mut pack).unwrap();
+        let mut fetched: Vec<String> = receive_pack(&mut pack.as_slice(), &MemoryStorage::new())
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        fetched.sort();
+        let kept: Vec<String> = remote.read_state().unwrap().objects.into_keys().collect();
+        assert_eq!(fetched, kept);
+        assert!(kept.contains(&base) && kept.contains(&tip));
+
+        // A second run has nothing left to drop
+        assert_eq!(compact(&remote).unwrap().entries_after, 6);
+    }
+
+    #[test]
+    fn test_compact_refuses_when_refs_moved() {
+        let remote = MemoryStorage::new();
+        let first = commit(&remote, "first", None);
+        set_ref(&remote, "refs/heads/main", Some(&first));
+        let read = remote.read_state().unwrap();
+        let live = live_objects(&remote, &read).unwrap();
+
+        // A push lands between the walk and the update
+        let second = commit(&remote, "second", Some(&first));
+        set_ref(&remote, "refs/heads/main", Some(&second));
+
+        let err = apply(&remote, &read, &live).unwrap_err();
+        assert!(err.to_string().contains("refs changed"), "{}", err);
+        assert_eq!(remote.read_state().unwrap().objects.len(), 6);
+    }
+}
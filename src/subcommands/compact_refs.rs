@@ -0,0 +1,45 @@
+use anyhow::{Context, Result};
+
+use crate::{
+    config::WalrusRemoteConfig,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    storage::{StorageBackend, WalrusStorage},
+};
+
+/// Handle the `compact-refs` subcommand
+/// Removes tombstoned and dangling entries from a remote's on-chain refs table
+pub fn handle(remote: &Remote) -> Result<()> {
+    let remote_url = parse_remote_url(&remote.url)?;
+    let state_object_id = match remote_url.remote_type {
+        RemoteType::Sui(state_object_id) => state_object_id,
+        RemoteType::Filesystem(path) => anyhow::bail!(
+            "compact-refs only applies to Walrus remotes, not filesystem remote {:?}",
+            path
+        ),
+    };
+
+    let mut config = WalrusRemoteConfig::load_for_remote(Some(&remote.name))
+        .context("Failed to load configuration")?;
+    remote_url.options.apply(&mut config);
+
+    let storage = WalrusStorage::new(state_object_id, remote.name.clone(), config)?;
+    storage.initialize()?;
+
+    let report = storage.compact_refs()?;
+
+    if report.removed.is_empty() {
+        println!("Nothing to compact: every ref points at a stored object.");
+    } else {
+        for dead in &report.removed {
+            println!("  removed {} ({}, {})", dead.name, dead.reason, dead.sha);
+        }
+        println!(
+            "✓ Compacted {} ref(s), reclaiming {} MIST of storage",
+            report.removed.len(),
+            report.storage_rebate_mist
+        );
+    }
+
+    Ok(())
+}
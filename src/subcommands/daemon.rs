@@ -0,0 +1,458 @@
+//! Helper sessions served by a long-running process over a unix socket, so successive git
+//! operations reuse one storage (and its Sui and Walrus clients) per remote
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::Shutdown,
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::WalrusRemoteConfig,
+    protocol::{self, SessionOptions},
+    session_env::SessionEnv,
+    storage::StorageBackend,
+};
+
+/// Socket the daemon listens on and helpers connect to
+pub const SOCKET_ENV: &str = "WALRUS_REMOTE_DAEMON_SOCKET";
+
+/// Set to run every helper session in-process, even when a daemon is listening
+pub const NO_DAEMON_ENV: &str = "WALRUS_REMOTE_NO_DAEMON";
+
+/// How long a session may wait on its helper before the daemon drops it, so a helper that
+/// hangs can't hold its remote's storage from the sessions queued behind it
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// What a helper sends, as one JSON line, before forwarding git's commands
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRequest {
+    pub remote_name: String,
+    pub url: String,
+    /// Working directory git ran the helper in
+    pub cwd: PathBuf,
+    /// The helper's environment that sessions depend on (see
+    /// [`is_session_var`](crate::session_env::is_session_var))
+    pub env: BTreeMap<String, String>,
+}
+
+impl SessionRequest {
+    /// Request for this process's own working directory and environment
+    pub fn current(remote_name: &str, url: &str) -> Result<Self> {
+        let SessionEnv { cwd, vars } = SessionEnv::current()?;
+        Ok(Self {
+            remote_name: remote_name.to_string(),
+            url: url.to_string(),
+            cwd,
+            env: vars,
+        })
+    }
+
+    /// The helper's working directory and environment, for the session to run in
+    fn session_env(&self) -> SessionEnv {
+        SessionEnv {
+            cwd: self.cwd.clone(),
+            vars: self.env.clone(),
+        }
+    }
+
+    /// Sessions share a storage when they name the same remote from the same repository
+    /// with the same configuration overrides
+    fn storage_key(&self) -> StorageKey {
+        (self.remote_name.clone(), self.url.clone(), self.env.clone())
+    }
+}
+
+/// Remote name, URL and forwarded environment of a storage's sessions
+type StorageKey = (String, String, BTreeMap<String, String>);
+
+/// A storage, once a session has opened it; each session holds the lock while it runs
+type StorageSlot<S> = Arc<Mutex<Option<S>>>;
+
+/// Storages kept open between sessions, opened on first use
+pub struct Daemon<S, Open, Options> {
+    storages: Mutex<HashMap<StorageKey, StorageSlot<S>>>,
+    open: Open,
+    options: Options,
+}
+
+impl<S, Open, Options> Daemon<S, Open, Options>
+where
+    S: StorageBackend + Send,
+    Open: Fn(&str, &str) -> Result<S> + Sync,
+    Options: Fn(&str, &str) -> Result<SessionOptions> + Sync,
+{
+    /// Daemon opening storages with `open` and reading each session's options with `options`,
+    /// both given the remote name and URL
+    pub fn new(open: Open, options: Options) -> Self {
+        Self {
+            storages: Mutex::new(HashMap::new()),
+            open,
+            options,
+        }
+    }
+
+    /// Serve every helper in `streams`, each session on its own thread
+    ///
+    /// Sessions on one storage take turns; sessions on different ones run alongside.
+    pub fn serve(&self, streams: impl IntoIterator<Item = io::Result<UnixStream>>) {
+        thread::scope(|scope| {
+            for stream in streams {
+                match stream {
+                    Ok(stream) => {
+                        scope.spawn(move || {
+                            if let Err(e) = self.serve_one(stream) {
+                                tracing::warn!("Session failed: {:#}", e);
+                            }
+                        });
+                    }
+                    Err(e) => tracing::warn!("Failed to accept a helper: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Serve one helper's session on `stream`, answering `ok` or `error <why>` to its request
+    ///
+    /// The session runs in the helper's working directory and environment, scoped to this
+    /// thread.
+    pub fn serve_one(&self, stream: UnixStream) -> Result<()> {
+        stream.set_read_timeout(Some(SESSION_IDLE_TIMEOUT))?;
+        let mut input = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        input.read_line(&mut line)?;
+        let request: SessionRequest =
+            serde_json::from_str(&line).context("Invalid session request")?;
+        let _span = tracing::info_span!("remote", name = %request.remote_name).entered();
+
+        let slot = self.slot(&request);
+        let mut storage = match slot.lock() {
+            Ok(storage) => storage,
+            // A session panicked midway; its storage may be half-updated, so start afresh
+            Err(poisoned) => {
+                let mut storage = poisoned.into_inner();
+                *storage = None;
+                storage
+            }
+        };
+
+        request.session_env().scope(|| {
+            let mut output = &stream;
+            let (storage, options) = match self.prepare(&request, &mut storage) {
+                Ok(prepared) => prepared,
+                Err(e) => {
+                    // The helper runs the session in-process instead, and reports the error
+                    // itself
+                    writeln!(output, "error {}", format!("{:#}", e).replace('\n', " "))?;
+                    return Err(e);
+                }
+            };
+            writeln!(output, "ok")?;
+
+            storage.begin_session();
+            protocol::serve_session(storage, &request.remote_name, &options, input, output)
+        })
+    }
+
+    /// The slot of the storage `request`'s session uses, added empty if it's the first
+    fn slot(&self, request: &SessionRequest) -> StorageSlot<S> {
+        self.storages
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(request.storage_key())
+            .or_default()
+            .clone()
+    }
+
+    /// Read the session's options, and open its storage unless an earlier session has
+    fn prepare<'a>(
+        &self,
+        request: &SessionRequest,
+        storage: &'a mut Option<S>,
+    ) -> Result<(&'a S, SessionOptions)> {
+        let options = (self.options)(&request.remote_name, &request.url)?;
+        let opened = match storage.take() {
+            Some(opened) => opened,
+            None => (self.open)(&request.remote_name, &request.url)?,
+        };
+        Ok((storage.insert(opened), options))
+    }
+}
+
+/// Handle the `daemon` subcommand
+/// Serves helper sessions on `socket`, each on its own thread, until interrupted
+pub fn handle<S: StorageBackend + Send>(
+    socket: Option<PathBuf>,
+    open: impl Fn(&str, &str) -> Result<S> + Sync,
+    options: impl Fn(&str, &str) -> Result<SessionOptions> + Sync,
+) -> Result<()> {
+    let socket = match socket {
+        Some(socket) => socket,
+        None => default_socket()?,
+    };
+    let listener = bind(&socket)?;
+    println!(
+        "Serving helper sessions on {} (Ctrl-C to stop)",
+        socket.display()
+    );
+
+    Daemon::new(open, options).serve(listener.incoming());
+    Ok(())
+}
+
+/// `$WALRUS_REMOTE_DAEMON_SOCKET`, else `git-remote-walrus.sock` in the user's runtime
+/// directory, else `daemon.sock` in the cache directory
+pub fn default_socket() -> Result<PathBuf> {
+    if let Some(socket) = env::var_os(SOCKET_ENV) {
+        return Ok(PathBuf::from(socket));
+    }
+    if let Some(runtime_dir) = dirs::runtime_dir() {
+        return Ok(runtime_dir.join("git-remote-walrus.sock"));
+    }
+    let config = WalrusRemoteConfig::load().context("Failed to load configuration")?;
+    Ok(config.cache_dir.join("daemon.sock"))
+}
+
+/// Socket a helper should try, or None when `WALRUS_REMOTE_NO_DAEMON` is set
+pub fn client_socket() -> Option<PathBuf> {
+    if env::var_os(NO_DAEMON_ENV).is_some() {
+        return None;
+    }
+    default_socket().ok()
+}
+
+/// Listen on `socket`, replacing a stale socket file but not a running daemon
+fn bind(socket: &Path) -> Result<UnixListener> {
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            anyhow::bail!("a daemon is already listening on {}", socket.display());
+        }
+        fs::remove_file(socket)
+            .with_context(|| format!("Failed to remove stale socket {}", socket.display()))?;
+    }
+    if let Some(parent) = socket.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let listener = UnixListener::bind(socket)
+        .with_context(|| format!("Failed to listen on {}", socket.display()))?;
+    // Sessions push with the daemon's wallet, so only its owner may connect
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict {}", socket.display()))?;
+    Ok(listener)
+}
+
+/// Forward this helper's session (stdin and stdout) to the daemon on `socket`
+///
+/// False when no daemon is listening or it refused the session, which then runs in-process.
+pub fn forward(socket: &Path, remote_name: &str, url: &str) -> Result<bool> {
+    let Ok(stream) = UnixStream::connect(socket) else {
+        return Ok(false);
+    };
+    tracing::debug!("Forwarding session to the daemon on {}", socket.display());
+    let request = SessionRequest::current(remote_name, url)?;
+    relay(stream, &request, io::stdin(), &mut io::stdout())
+}
+
+/// Send `request` on `stream`, then copy `input` to the daemon and its answers to `output`
+fn relay<R, W>(
+    stream: UnixStream,
+    request: &SessionRequest,
+    input: R,
+    output: &mut W,
+) -> Result<bool>
+where
+    R: Read + Send + 'static,
+    W: Write,
+{
+    let mut to_daemon = stream.try_clone()?;
+    writeln!(to_daemon, "{}", serde_json::to_string(request)?)?;
+
+    let mut from_daemon = BufReader::new(stream);
+    let mut reply = String::new();
+    from_daemon.read_line(&mut reply)?;
+    match reply.trim_end() {
+        "ok" => {}
+        reply => {
+            tracing::warn!(
+                "Daemon refused the session ({}); running it in-process",
+                reply.strip_prefix("error ").unwrap_or("no reply")
+            );
+            return Ok(false);
+        }
+    }
+
+    // Git waits for each answer before sending more, so answers are flushed as they arrive
+    thread::spawn(move || {
+        let mut input = input;
+        let _ = io::copy(&mut input, &mut to_daemon);
+        let _ = to_daemon.shutdown(Shutdown::Write);
+    });
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let read = from_daemon.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        output.write_all(&buf[..read])?;
+        output.flush()?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Cursor,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        session_env,
+        storage::{MemoryStorage, MutableState},
+    };
+
+    const SHA: &str = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+
+    fn request() -> SessionRequest {
+        SessionRequest::current("origin", "walrus::0x1234").unwrap()
+    }
+
+    /// A storage holding one branch
+    fn storage() -> Result<MemoryStorage> {
+        let storage = MemoryStorage::new();
+        storage.update_state(|state| {
+            state.refs.insert("refs/heads/main".into(), SHA.into());
+            Ok(())
+        })?;
+        Ok(storage)
+    }
+
+    #[test]
+    fn test_daemon_opens_storage_once_for_two_sessions() {
+        let dir = tempdir().unwrap();
+        let socket = dir.path().join("daemon.sock");
+        let listener = bind(&socket).unwrap();
+        let opened = Arc::new(AtomicUsize::new(0));
+
+        let server = {
+            let opened = opened.clone();
+            thread::spawn(move || {
+                let open = |_: &str, _: &str| {
+                    opened.fetch_add(1, Ordering::SeqCst);
+                    storage()
+                };
+                let daemon = Daemon::new(open, |_: &str, _: &str| Ok(SessionOptions::default()));
+                for _ in 0..2 {
+                    let (stream, _) = listener.accept().unwrap();
+                    daemon.serve_one(stream).unwrap();
+                }
+            })
+        };
+
+        for _ in 0..2 {
+            let stream = UnixStream::connect(&socket).unwrap();
+            let mut output = Vec::new();
+            let input = Cursor::new(b"list\n\n".to_vec());
+            assert!(relay(stream, &request(), input, &mut output).unwrap());
+            let output = String::from_utf8(output).unwrap();
+            assert!(
+                output.contains(&format!("{} refs/heads/main\n", SHA)),
+                "{}",
+                output
+            );
+        }
+        server.join().unwrap();
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+
+        // A second daemon can't take over the socket while the first is listening
+        let _listener = bind(&socket).unwrap();
+        assert!(bind(&socket).is_err());
+    }
+
+    #[test]
+    fn test_helper_falls_back_without_a_daemon() {
+        let dir = tempdir().unwrap();
+        let socket = dir.path().join("daemon.sock");
+        assert!(!forward(&socket, "origin", "walrus::0x1234").unwrap());
+
+        // A daemon that can't open the remote sends the helper back to running it in-process
+        let listener = bind(&socket).unwrap();
+        let server = thread::spawn(move || {
+            let daemon = Daemon::new(
+                |_: &str, _: &str| -> Result<MemoryStorage> { anyhow::bail!("no wallet") },
+                |_: &str, _: &str| Ok(SessionOptions::default()),
+            );
+            let (stream, _) = listener.accept().unwrap();
+            daemon.serve_one(stream).unwrap_err()
+        });
+        let stream = UnixStream::connect(&socket).unwrap();
+        let mut output = Vec::new();
+        let input = Cursor::new(b"list\n\n".to_vec());
+        assert!(!relay(stream, &request(), input, &mut output).unwrap());
+        assert!(output.is_empty());
+        assert_eq!(server.join().unwrap().to_string(), "no wallet");
+    }
+
+    #[test]
+    fn test_sessions_on_different_remotes_run_alongside() {
+        let dir = tempdir().unwrap();
+        let socket = dir.path().join("daemon.sock");
+        let listener = bind(&socket).unwrap();
+        let server = thread::spawn(move || {
+            // Each session sees its own helper's working directory, not the daemon's
+            let options = |remote_name: &str, _: &str| {
+                let cwd = session_env::current_dir()?;
+                anyhow::ensure!(cwd.ends_with(remote_name), "session ran in {:?}", cwd);
+                Ok(SessionOptions::default())
+            };
+            Daemon::new(|_: &str, _: &str| storage(), options).serve(listener.incoming().take(2));
+        });
+        let request = |remote_name: &str| SessionRequest {
+            remote_name: remote_name.to_string(),
+            url: "walrus::0x1234".to_string(),
+            cwd: dir.path().join(remote_name),
+            env: BTreeMap::new(),
+        };
+
+        // The first helper's session stays open while the second one's runs to the end
+        let mut first = UnixStream::connect(&socket).unwrap();
+        let line = serde_json::to_string(&request("first")).unwrap();
+        writeln!(first, "{}", line).unwrap();
+        let mut reply = String::new();
+        BufReader::new(&first).read_line(&mut reply).unwrap();
+        assert_eq!(reply, "ok\n");
+
+        let stream = UnixStream::connect(&socket).unwrap();
+        let mut output = Vec::new();
+        let input = Cursor::new(b"list\n\n".to_vec());
+        assert!(relay(stream, &request("second"), input, &mut output).unwrap());
+        assert!(String::from_utf8(output).unwrap().contains(SHA));
+
+        first.write_all(b"list\n\n").unwrap();
+        first.shutdown(Shutdown::Write).unwrap();
+        let mut output = String::new();
+        first.read_to_string(&mut output).unwrap();
+        assert!(output.contains(SHA), "{}", output);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+use std::{collections::HashSet, io::Write};
+
+use anyhow::{Context, Result};
+use flate2::{write::ZlibEncoder, Compression};
+use gix_object::Kind;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    pack::objects::GitObject,
+    remote_resolution::Remote,
+    storage::{MutableState, State, StorageBackend},
+};
+
+/// Objects read per batch
+const CHUNK_SIZE: usize = 1000;
+
+/// Content-defined chunks are never shorter than this (except at the end of a blob)
+const MIN_CHUNK: usize = 256;
+/// Chunks end where the rolling hash has these bits clear, about every 1 KiB
+const BOUNDARY_MASK: u64 = 0x3ff << 54;
+/// Chunks are cut here even without a boundary
+const MAX_CHUNK: usize = 8 * 1024;
+
+/// Random value per byte for the gear rolling hash (splitmix64, so it's the same every run)
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut i = 0;
+    while i < 256 {
+        seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// What a remote's blobs cost as stored, and what packed or chunked storage would cost
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct DedupReport {
+    /// Objects in the objects map
+    pub objects: usize,
+    /// Objects read for the report
+    pub sampled: usize,
+    /// Blobs among the sampled objects
+    pub blobs: usize,
+    /// Bytes the sampled blobs take as stored (uncompressed loose objects)
+    pub stored_bytes: u64,
+    /// Bytes with each blob zlib-compressed on its own, as in a pack without deltas
+    pub compressed_bytes: u64,
+    /// Bytes of the distinct content-defined chunks of the sampled blobs
+    pub chunked_bytes: u64,
+    /// Bytes of the distinct chunks, each zlib-compressed
+    pub chunked_compressed_bytes: u64,
+}
+
+impl DedupReport {
+    /// Fraction of `stored_bytes` that `bytes` would save
+    fn savings(&self, bytes: u64) -> f64 {
+        if self.stored_bytes == 0 {
+            return 0.0;
+        }
+        1.0 - bytes as f64 / self.stored_bytes as f64
+    }
+}
+
+/// Handle the `dedup-report` subcommand
+/// Estimates what compressing and chunking a remote's blobs would save
+pub fn handle(remote: &Remote, sample: Option<usize>, json: bool) -> Result<()> {
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    let state = storage.read_state()?;
+    let report = compute(&storage, &state, sample)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!(
+        "Sampled {} of {} objects ({} blobs)",
+        report.sampled, report.objects, report.blobs
+    );
+    let line = |label: &str, bytes: u64| {
+        println!(
+            "  {:<28}{:>14} bytes  ({:.1}% saved)",
+            label,
+            bytes,
+            report.savings(bytes) * 100.0
+        )
+    };
+    line("Stored (loose objects)", report.stored_bytes);
+    line("Compressed (packed)", report.compressed_bytes);
+    line("Chunked", report.chunked_bytes);
+    line("Chunked and compressed", report.chunked_compressed_bytes);
+    if report.sampled < report.objects {
+        println!("Chunks are only shared between sampled blobs, so savings are understated");
+    }
+    Ok(())
+}
+
+/// Report on `state`'s blobs, reading at most `sample` objects spread evenly over the map
+pub fn compute(
+    storage: &impl StorageBackend,
+    state: &State,
+    sample: Option<usize>,
+) -> Result<DedupReport> {
+    let stride = match sample {
+        Some(0) => anyhow::bail!("--sample must be at least 1"),
+        Some(sample) => state.objects.len().div_ceil(sample).max(1),
+        None => 1,
+    };
+    let picked: Vec<(&String, &String)> = state.objects.iter().step_by(stride).collect();
+
+    let mut report = DedupReport {
+        objects: state.objects.len(),
+        sampled: picked.len(),
+        ..Default::default()
+    };
+    let mut seen_chunks: HashSet<[u8; 32]> = HashSet::new();
+    for batch in picked.chunks(CHUNK_SIZE) {
+        let ids: Vec<&str> = batch.iter().map(|(_, id)| id.as_str()).collect();
+        let contents = storage
+            .read_objects(&ids)
+            .context("Failed to read objects")?;
+        for ((git_sha1, _), content) in batch.iter().zip(contents) {
+            let obj = GitObject::from_loose_format(&content)
+                .with_context(|| format!("Failed to parse object {}", git_sha1))?;
+            if obj.kind != Kind::Blob {
+                continue;
+            }
+            report.blobs += 1;
+            report.stored_bytes += content.len() as u64;
+            report.compressed_bytes += compressed_len(&content)?;
+            for chunk in chunks(obj.data()) {
+                if seen_chunks.insert(Sha256::digest(chunk).into()) {
+                    report.chunked_bytes += chunk.len() as u64;
+                    report.chunked_compressed_bytes += compressed_len(chunk)?;
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Split `data` where a gear rolling hash hits a boundary, so an edit only changes the chunks
+/// around it rather than shifting every chunk after it
+fn chunks(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut hash: u64 = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let len = i + 1 - start;
+        if (len >= MIN_CHUNK && hash & BOUNDARY_MASK == 0) || len >= MAX_CHUNK {
+            chunks.push(&data[start..=i]);
+            start = i + 1;
+            hash = 0;
+        }
+    }
+    if start < data.len() {
+        chunks.push(&data[start..]);
+    }
+    chunks
+}
+
+/// Size of `data` after zlib compression
+fn compressed_len(data: &[u8]) -> Result<u64> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{store_object_in, MemoryStorage};
+
+    /// Incompressible bytes, so only chunking can shrink them
+    fn noise(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks_resync_after_an_edit() {
+        let data = noise(64 * 1024, 1);
+        let mut edited = data.clone();
+        edited.splice(100..100, b"inserted".iter().copied());
+
+        let before: HashSet<&[u8]> = chunks(&data).into_iter().collect();
+        let after = chunks(&edited);
+        assert_eq!(after.concat(), edited);
+        assert!(after.iter().all(|chunk| chunk.len() <= MAX_CHUNK));
+        // Only the chunk holding the insertion differs
+        let changed = after
+            .iter()
+            .filter(|chunk| !before.contains(*chunk))
+            .count();
+        assert!(changed <= 2, "{} chunks changed", changed);
+    }
+
+    #[test]
+    fn test_report_over_successive_versions() {
+        let storage = MemoryStorage::new();
+        let mut state = State::default();
+        // Ten versions of a 32 KiB file, each a small edit of the last
+        let mut file = noise(32 * 1024, 7);
+        for version in 0..10 {
+            let at = 1000 + version * 3000;
+            file.splice(at..at, format!("edit {}", version).bytes());
+            store_object_in(&storage, &mut state, Kind::Blob, file.clone());
+        }
+        store_object_in(&storage, &mut state, Kind::Tree, Vec::new());
+
+        let report = compute(&storage, &state, None).unwrap();
+        assert_eq!((report.objects, report.sampled, report.blobs), (11, 11, 10));
+        assert!(report.stored_bytes > 10 * 32 * 1024);
+        // Noise doesn't compress, but the versions share almost every chunk
+        assert!(report.savings(report.compressed_bytes) < 0.01);
+        assert!(
+            report.savings(report.chunked_bytes) > 0.7,
+            "chunking saved only {:.2}",
+            report.savings(report.chunked_bytes)
+        );
+        assert!(report.chunked_compressed_bytes >= report.chunked_bytes);
+    }
+
+    #[test]
+    fn test_report_samples_evenly() {
+        let storage = MemoryStorage::new();
+        let mut state = State::default();
+        for i in 0..10u8 {
+            store_object_in(&storage, &mut state, Kind::Blob, vec![i; 100]);
+        }
+
+        let report = compute(&storage, &state, Some(4)).unwrap();
+        assert_eq!((report.objects, report.sampled, report.blobs), (10, 4, 4));
+        assert!(compute(&storage, &state, Some(0)).is_err());
+        assert_eq!(compute(&storage, &state, Some(100)).unwrap().sampled, 10);
+    }
+}
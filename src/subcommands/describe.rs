@@ -0,0 +1,92 @@
+use anyhow::Result;
+
+use crate::{
+    remote_resolution::Remote,
+    storage::{MutableState, PushRecord, RepoMetadata},
+};
+
+/// Pushes from the metadata's history that `describe` lists, newest first
+const RECENT_PUSHES: usize = 10;
+
+/// Handle the `describe` subcommand
+/// Prints the remote's metadata blob
+pub fn handle(remote: &Remote) -> Result<()> {
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    let state = storage.read_state()?;
+    let metadata = RepoMetadata::load(&storage, &state)?;
+
+    let field = |value: &Option<String>| value.clone().unwrap_or_else(|| "(not set)".to_string());
+
+    println!("Remote: {}", remote.url);
+    println!("  Description: {}", field(&metadata.description));
+    println!("  Default branch: {}", field(&metadata.default_branch));
+    println!("  Created at: {}", field(&metadata.created_at));
+    println!("  Last push at: {}", field(&metadata.last_push_at));
+    println!("  Last pusher: {}", field(&metadata.pusher));
+    println!(
+        "  Last push signed by: {}",
+        field(&metadata.push_certificate.map(|cert| cert.signer))
+    );
+    if !metadata.push_options.is_empty() {
+        println!("  Last push options: {}", metadata.push_options.join(", "));
+    }
+    println!("  Refs: {}", state.refs.len());
+    if !metadata.history.is_empty() {
+        println!("  Recent pushes:");
+        for push in metadata.history.iter().rev().take(RECENT_PUSHES) {
+            println!("    {}", describe_push(push));
+        }
+    }
+
+    Ok(())
+}
+
+/// `<time> <pusher> <ref>=<sha> ... [options: ...] [signed by ...]`
+fn describe_push(push: &PushRecord) -> String {
+    let mut text = format!(
+        "{} {}",
+        push.at,
+        push.pusher.as_deref().unwrap_or("(unknown pusher)")
+    );
+    for (name, sha) in &push.refs {
+        text.push_str(&format!(" {}={}", name, &sha[..sha.len().min(12)]));
+    }
+    if !push.push_options.is_empty() {
+        text.push_str(&format!(" [options: {}]", push.push_options.join(", ")));
+    }
+    if let Some(signer) = &push.signer {
+        text.push_str(&format!(" [signed by {}]", signer));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn test_describe_push() {
+        let mut push = PushRecord {
+            at: "2025-01-02T00:00:00+00:00".to_string(),
+            pusher: Some("0xabc".to_string()),
+            refs: BTreeMap::from([("refs/heads/main".to_string(), "a".repeat(40))]),
+            signer: None,
+            push_options: vec![],
+        };
+        assert_eq!(
+            describe_push(&push),
+            "2025-01-02T00:00:00+00:00 0xabc refs/heads/main=aaaaaaaaaaaa"
+        );
+
+        push.pusher = None;
+        push.push_options = vec!["ci.skip".to_string(), "notify=team".to_string()];
+        push.signer = Some("Test <test@test.com> (0123)".to_string());
+        assert_eq!(
+            describe_push(&push),
+            "2025-01-02T00:00:00+00:00 (unknown pusher) refs/heads/main=aaaaaaaaaaaa \
+             [options: ci.skip, notify=team] [signed by Test <test@test.com> (0123)]"
+        );
+    }
+}
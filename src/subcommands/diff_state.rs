@@ -0,0 +1,274 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    config::WalrusRemoteConfig,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    storage::{ObjectsDiff, State, StorageBackend, WalrusStorage},
+};
+
+/// What `diff-state` compares the remote's current state with
+pub enum Baseline {
+    /// The objects map in a SharedBlob, which holds no refs
+    ObjectsMap(String),
+    /// The remote's whole state after a transaction, refs included
+    Transaction(String),
+}
+
+/// Handle the `diff-state` subcommand
+/// Compares `baseline` with the remote's current state, listing the refs added, removed and
+/// moved (against a transaction), the object mappings added, removed and changed, and the
+/// symrefs that moved
+pub fn handle(remote: &Remote, baseline: &Baseline) -> Result<()> {
+    let remote_url = parse_remote_url(&remote.url)?;
+    let object_id = match remote_url.remote_type {
+        RemoteType::Sui(object_id) => object_id,
+        RemoteType::Filesystem(path) => anyhow::bail!(
+            "diff-state only applies to Walrus remotes, not filesystem remote {:?}",
+            path
+        ),
+    };
+
+    let mut config = WalrusRemoteConfig::load_for_remote(Some(&remote.name))
+        .context("Failed to load configuration")?;
+    remote_url.options.apply(&mut config);
+
+    let storage = WalrusStorage::new(object_id.clone(), remote.name.clone(), config.clone())?;
+    storage.initialize()?;
+
+    let text = match baseline {
+        Baseline::ObjectsMap(against) => {
+            let current = storage.objects_map_object_id()?;
+            let new = match &current {
+                Some(object_id) => storage.objects_map_state(object_id)?,
+                None => State::default(),
+            };
+            let old = storage
+                .objects_map_state(against)
+                .with_context(|| format!("Failed to read objects map {}", against))?;
+            render(against, current.as_deref().unwrap_or("(none)"), &old, &new)
+        }
+        Baseline::Transaction(digest) => {
+            config.pinned_transaction = Some(digest.clone());
+            let pinned = WalrusStorage::new(object_id, remote.name.clone(), config)?;
+            diff_transaction(&pinned, &storage, digest)?
+        }
+    };
+    print!("{}", text);
+    Ok(())
+}
+
+/// The diff from `pinned`, the remote pinned to transaction `digest`, to `current`
+fn diff_transaction(
+    pinned: &WalrusStorage,
+    current: &WalrusStorage,
+    digest: &str,
+) -> Result<String> {
+    let (old, old_map) = pinned
+        .read_state_and_objects_map()
+        .with_context(|| format!("Failed to read the state after transaction {}", digest))?;
+    let (new, new_map) = current.read_state_and_objects_map()?;
+    let label = |at: &str, objects_map: Option<String>| {
+        format!(
+            "{} (objects map {})",
+            at,
+            objects_map.as_deref().unwrap_or("(none)")
+        )
+    };
+    Ok(render(
+        &label(digest, old_map),
+        &label("current", new_map),
+        &old,
+        &new,
+    ))
+}
+
+/// The diff from `old` (labelled `old_id`) to `new` (labelled `new_id`), one line per change
+fn render(old_id: &str, new_id: &str, old: &State, new: &State) -> String {
+    let diff = ObjectsDiff::compute(&old.objects, &new.objects);
+    let mut text = format!("--- {}\n+++ {}\n", old_id, new_id);
+
+    for (name, (was, now)) in changes(&old.refs, &new.refs) {
+        let _ = match (old.refs.contains_key(name), new.refs.contains_key(name)) {
+            (false, _) => writeln!(text, "ref {}: added at {}", name, now),
+            (_, false) => writeln!(text, "ref {}: removed from {}", name, was),
+            _ => writeln!(text, "ref {}: moved {} -> {}", name, was, now),
+        };
+    }
+
+    for git_sha1 in &diff.added {
+        let _ = writeln!(text, "+ {} {}", git_sha1, new.objects[git_sha1]);
+    }
+    for git_sha1 in &diff.removed {
+        let _ = writeln!(text, "- {} {}", git_sha1, old.objects[git_sha1]);
+    }
+    for git_sha1 in &diff.remapped {
+        let _ = writeln!(
+            text,
+            "~ {} {} -> {}",
+            git_sha1, old.objects[git_sha1], new.objects[git_sha1]
+        );
+    }
+
+    for (name, (was, now)) in changes(&old.symrefs, &new.symrefs) {
+        let _ = writeln!(text, "symref {}: {} -> {}", name, was, now);
+    }
+    if old.metadata != new.metadata {
+        let _ = writeln!(
+            text,
+            "metadata: {} -> {}",
+            old.metadata.as_deref().unwrap_or("(none)"),
+            new.metadata.as_deref().unwrap_or("(none)")
+        );
+    }
+
+    let _ = writeln!(text, "{}", diff);
+    text
+}
+
+/// Entries that differ between `old` and `new`, with `(none)` for a missing side
+fn changes<'a>(
+    old: &'a BTreeMap<String, String>,
+    new: &'a BTreeMap<String, String>,
+) -> BTreeMap<&'a str, (&'a str, &'a str)> {
+    old.keys()
+        .chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(|key| {
+            let side = |map: &'a BTreeMap<String, String>| {
+                map.get(key).map(String::as_str).unwrap_or("(none)")
+            };
+            (key.as_str(), (side(old), side(new)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest, Sha1};
+
+    use super::*;
+    use crate::storage::{ImmutableStore, MockRemote, MutableState};
+
+    fn state(objects: &[(&str, &str)], symrefs: &[(&str, &str)], metadata: &str) -> State {
+        let map = |entries: &[(&str, &str)]| -> BTreeMap<String, String> {
+            entries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        State {
+            objects: map(objects),
+            symrefs: map(symrefs),
+            metadata: Some(metadata.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_state_diff() {
+        let old = state(
+            &[
+                ("aaaa", "0xb1:0:10"),
+                ("bbbb", "0xb1:10:5"),
+                ("cccc", "0xb1"),
+            ],
+            &[("HEAD", "refs/heads/main")],
+            "0xm1",
+        );
+        let new = state(
+            &[
+                ("aaaa", "0xb1:0:10"),
+                ("bbbb", "0xb2:0:5"),
+                ("dddd", "0xb2:5:7"),
+            ],
+            &[
+                ("HEAD", "refs/heads/trunk"),
+                ("refs/heads/docs", "refs/heads/gh-pages"),
+            ],
+            "0xm2",
+        );
+
+        assert_eq!(
+            render("0xold", "0xnew", &old, &new),
+            "--- 0xold\n\
+             +++ 0xnew\n\
+             + dddd 0xb2:5:7\n\
+             - cccc 0xb1\n\
+             ~ bbbb 0xb1:10:5 -> 0xb2:0:5\n\
+             symref HEAD: refs/heads/main -> refs/heads/trunk\n\
+             symref refs/heads/docs: (none) -> refs/heads/gh-pages\n\
+             metadata: 0xm1 -> 0xm2\n\
+             +1 objects, -1, ~1 remapped\n"
+        );
+
+        // Identical maps print only the headers and an empty summary
+        assert_eq!(
+            render("0xold", "0xold", &old, &old),
+            "--- 0xold\n+++ 0xold\n+0 objects, -0\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_against_a_transaction() {
+        let remote = MockRemote::new();
+        let old_blob: &[u8] = b"blob 3\0old";
+        let new_blob: &[u8] = b"blob 3\0new";
+        let write = |refs: &[(&str, &str)], contents: &[&[u8]]| {
+            let storage = remote.storage();
+            let mut state = State::default();
+            for (name, tip) in refs {
+                state.refs.insert(name.to_string(), tip.repeat(40));
+            }
+            for content in contents {
+                let id = storage.write_object(content).unwrap();
+                state.objects.insert(hex::encode(Sha1::digest(content)), id);
+            }
+            storage.write_state(&state).unwrap();
+        };
+
+        write(
+            &[("refs/heads/main", "a"), ("refs/tags/old", "d")],
+            &[old_blob],
+        );
+        let pin = remote.sui.transactions(remote.state).pop().unwrap();
+        write(
+            &[("refs/heads/main", "b"), ("refs/heads/new", "c")],
+            &[old_blob, new_blob],
+        );
+
+        let mut config = remote.config.clone();
+        config.pinned_transaction = Some(pin.clone());
+        let pinned = remote.storage_of(remote.state, "origin", &config);
+        let text = diff_transaction(&pinned, &remote.storage(), &pin).unwrap();
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with(&format!("--- {} (objects map 0x", pin)));
+        assert!(lines[1].starts_with("+++ current (objects map 0x"));
+        assert_eq!(
+            lines[2..5],
+            [
+                format!(
+                    "ref refs/heads/main: moved {} -> {}",
+                    "a".repeat(40),
+                    "b".repeat(40)
+                ),
+                format!("ref refs/heads/new: added at {}", "c".repeat(40)),
+                format!("ref refs/tags/old: removed from {}", "d".repeat(40)),
+            ]
+        );
+        let new_sha1 = hex::encode(Sha1::digest(new_blob));
+        assert!(
+            lines[5].starts_with(&format!("+ {} ", new_sha1)),
+            "{}",
+            text
+        );
+        assert!(text.ends_with("+1 objects, -0\n"), "{}", text);
+
+        // The pinned state against itself has nothing to report
+        let text = diff_transaction(&pinned, &pinned, &pin).unwrap();
+        assert_eq!(text.lines().count(), 3, "{}", text);
+    }
+}
@@ -0,0 +1,672 @@
+use std::{
+    fmt,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    config::WalrusRemoteConfig,
+    storage::{CacheIndex, CachedObjects, CACHE_INDEX_FILE, REMOTES_DIR},
+    sui::RefHistoryCache,
+    walrus::{BannerLog, BlobTracker, RefImpactCache, WalrusNetworkInfo},
+};
+
+/// Config fields holding paths
+const PATH_FIELDS: &[&str] = &[
+    "sui_wallet_path",
+    "walrus_config_path",
+    "walrus_binary",
+    "cache_dir",
+    "push_cert_keyring",
+];
+
+/// Leftover lock and temporary files younger than this may belong to a running helper
+const STALE_FILE_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Checks that a cache file parses
+type Loader = fn(&Path) -> Result<()>;
+
+/// Cache files rebuilt on demand, with the loader that must be able to parse each
+const DERIVED_CACHES: &[(&str, Loader)] = &[
+    ("network_info.yaml", |path| {
+        WalrusNetworkInfo::load(path).map(drop)
+    }),
+    ("blob_tracker.yaml", |path| {
+        BlobTracker::load(path).map(drop)
+    }),
+    ("ref_impact.yaml", |path| {
+        RefImpactCache::load(path).map(drop)
+    }),
+    ("ref_history.yaml", |path| {
+        RefHistoryCache::load(path).map(drop)
+    }),
+    ("banner.yaml", |path| BannerLog::load(path).map(drop)),
+];
+
+/// Derived caches kept per remote, in its directory under `remotes/`
+const REMOTE_CACHES: &[&str] = &["network_info.yaml", "blob_tracker.yaml"];
+
+/// What a repair step found and did
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Nothing to repair (with why)
+    Skipped(String),
+    /// A repair `--fix` would make
+    Pending(String),
+    Applied(String),
+    Failed(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Skipped(detail) => write!(f, "[skipped] {}", detail),
+            Outcome::Pending(detail) => write!(f, "[would fix] {}", detail),
+            Outcome::Applied(detail) => write!(f, "[applied] {}", detail),
+            Outcome::Failed(detail) => write!(f, "[failed] {}", detail),
+        }
+    }
+}
+
+/// One repair step's outcome
+#[derive(Debug)]
+pub struct Report {
+    pub step: String,
+    pub outcome: Outcome,
+}
+
+impl Report {
+    /// Run `step`, turning an error into a failed outcome so later steps still run
+    fn run(step: impl Into<String>, f: impl FnOnce() -> Result<Outcome>) -> Self {
+        Self {
+            step: step.into(),
+            outcome: f().unwrap_or_else(|e| Outcome::Failed(format!("{:#}", e))),
+        }
+    }
+}
+
+/// Handle the `doctor` subcommand
+/// Check the config file and cache directory, repairing what it can with `fix`
+pub fn handle(fix: bool) -> Result<()> {
+    let config_path = WalrusRemoteConfig::config_file_path()?;
+    let mut reports = vec![Report::run("config paths", || {
+        config_paths(&config_path, fix)
+    })];
+    // Read after the paths step, so a fixed cache_dir is used
+    let config = WalrusRemoteConfig::load().context("Failed to load configuration")?;
+    reports.extend(check_cache(&config.cache_dir, fix));
+
+    for report in &reports {
+        println!("{}: {}", report.step, report.outcome);
+    }
+
+    let pending = count(&reports, |o| matches!(o, Outcome::Pending(_)));
+    let applied = count(&reports, |o| matches!(o, Outcome::Applied(_)));
+    let failed = count(&reports, |o| matches!(o, Outcome::Failed(_)));
+    if pending > 0 {
+        println!(
+            "\n{} problem(s) found; run `git-remote-walrus doctor --fix` to repair them",
+            pending
+        );
+    } else if applied > 0 {
+        println!("\n✓ Applied {} repair(s)", applied);
+    } else if failed == 0 {
+        println!("\n✓ No problems found");
+    }
+    if failed > 0 {
+        anyhow::bail!("{} step(s) failed", failed);
+    }
+    Ok(())
+}
+
+fn count(reports: &[Report], f: impl Fn(&Outcome) -> bool) -> usize {
+    reports.iter().filter(|report| f(&report.outcome)).count()
+}
+
+/// Run every cache repair step against `cache_dir`, in order
+///
+/// Each step is idempotent: a second run with `fix` skips everything.
+pub fn check_cache(cache_dir: &Path, fix: bool) -> Vec<Report> {
+    let mut reports = vec![Report::run("cache directory", || {
+        cache_directory(cache_dir, fix)
+    })];
+    if !cache_dir.is_dir() {
+        // Only reachable without --fix, or when creating it failed
+        return reports;
+    }
+
+    let objects_dir = cache_dir.join("objects");
+    let index_path = cache_dir.join(CACHE_INDEX_FILE);
+    reports.push(Report::run("stale lock files", || {
+        stale_files(cache_dir, fix)
+    }));
+    reports.push(Report::run("cached objects", || {
+        cached_objects(&objects_dir, fix)
+    }));
+    reports.push(Report::run("cache index", || {
+        cache_index(&index_path, &objects_dir, fix)
+    }));
+    reports.push(Report::run("cache layout", || {
+        cache_layout(&index_path, fix)
+    }));
+    for (name, load) in DERIVED_CACHES {
+        let path = cache_dir.join(name);
+        reports.push(Report::run(*name, || derived_cache(&path, *load, fix)));
+    }
+
+    // Each remote's own index and derived caches, named after its directory
+    for remote_dir in remote_dirs(cache_dir) {
+        let remote = remote_dir.file_name().unwrap_or_default().to_string_lossy();
+        let index_path = remote_dir.join(CACHE_INDEX_FILE);
+        reports.push(Report::run(format!("{}: cache index", remote), || {
+            cache_index(&index_path, &objects_dir, fix)
+        }));
+        reports.push(Report::run(format!("{}: cache layout", remote), || {
+            cache_layout(&index_path, fix)
+        }));
+        for (name, load) in DERIVED_CACHES
+            .iter()
+            .filter(|(name, _)| REMOTE_CACHES.contains(name))
+        {
+            let path = remote_dir.join(name);
+            reports.push(Report::run(format!("{}: {}", remote, name), || {
+                derived_cache(&path, *load, fix)
+            }));
+        }
+    }
+    reports
+}
+
+/// Each remote's directory under `cache_dir`, in name order
+fn remote_dirs(cache_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = fs::read_dir(cache_dir.join(REMOTES_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// Relative paths in the config file resolve against whichever repository git runs the
+/// helper in; rewrite them relative to the home directory (`~/...`)
+///
+/// Tildes are already expanded when the config is loaded, so they're left alone, as is a
+/// bare `walrus_binary` name, which is looked up on PATH. The original is kept as
+/// `config.yaml.bak`.
+fn config_paths(config_path: &Path, fix: bool) -> Result<Outcome> {
+    if !config_path.exists() {
+        return Ok(Outcome::Skipped(format!(
+            "no config file at {:?}",
+            config_path
+        )));
+    }
+    let content = fs::read_to_string(config_path)
+        .with_context(|| format!("Failed to read config file: {:?}", config_path))?;
+    let mut config: serde_yaml::Mapping = serde_yaml::from_str(&content)
+        .with_context(|| format!("Failed to parse config file: {:?}", config_path))?;
+
+    let mut changes = Vec::new();
+    for field in PATH_FIELDS {
+        let Some(serde_yaml::Value::String(path)) = config.get_mut(*field) else {
+            continue;
+        };
+        if let Some(normalized) = normalize_path(field, path) {
+            changes.push(format!("{}: {} -> {}", field, path, normalized));
+            *path = normalized;
+        }
+    }
+
+    if changes.is_empty() {
+        return Ok(Outcome::Skipped("all paths are absolute".to_string()));
+    }
+    if !fix {
+        return Ok(Outcome::Pending(changes.join(", ")));
+    }
+    let backup = config_path.with_extension("yaml.bak");
+    fs::copy(config_path, &backup)
+        .with_context(|| format!("Failed to back up config file to {:?}", backup))?;
+    let content = serde_yaml::to_string(&config).context("Failed to serialize config")?;
+    fs::write(config_path, content)
+        .with_context(|| format!("Failed to write config file: {:?}", config_path))?;
+    Ok(Outcome::Applied(format!(
+        "{} (original saved as {:?})",
+        changes.join(", "),
+        backup
+    )))
+}
+
+/// `~/`-relative form of a relative path in `field`, or None if it needs no change
+fn normalize_path(field: &str, path: &str) -> Option<String> {
+    let is_bare_command = field == "walrus_binary" && !path.contains(['/', '\\']);
+    if path.is_empty() || path.starts_with('~') || Path::new(path).is_absolute() || is_bare_command
+    {
+        return None;
+    }
+    Some(format!("~/{}", path.trim_start_matches("./")))
+}
+
+fn cache_directory(cache_dir: &Path, fix: bool) -> Result<Outcome> {
+    if cache_dir.is_dir() {
+        return Ok(Outcome::Skipped(format!("{:?} exists", cache_dir)));
+    }
+    if !fix {
+        return Ok(Outcome::Pending(format!("would create {:?}", cache_dir)));
+    }
+    fs::create_dir_all(cache_dir.join("objects"))
+        .with_context(|| format!("Failed to create cache directory: {:?}", cache_dir))?;
+    Ok(Outcome::Applied(format!("created {:?}", cache_dir)))
+}
+
+/// Remove `*.lock` and `*.tmp` files left in the cache directory by interrupted writes or
+/// sync tools, once they are old enough not to belong to a running helper
+fn stale_files(cache_dir: &Path, fix: bool) -> Result<Outcome> {
+    let now = SystemTime::now();
+    let mut stale = Vec::new();
+    for entry in fs::read_dir(cache_dir)
+        .with_context(|| format!("Failed to read cache directory {:?}", cache_dir))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+        let leftover = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("lock" | "tmp")
+        );
+        let modified = entry.metadata()?.modified()?;
+        let old = now.duration_since(modified).unwrap_or_default() >= STALE_FILE_AGE;
+        if leftover && old && entry.file_type()?.is_file() {
+            stale.push(path);
+        }
+    }
+
+    if stale.is_empty() {
+        return Ok(Outcome::Skipped("none found".to_string()));
+    }
+    if !fix {
+        return Ok(Outcome::Pending(format!(
+            "would remove {}",
+            list_paths(&stale)
+        )));
+    }
+    for path in &stale {
+        fs::remove_file(path).with_context(|| format!("Failed to remove {:?}", path))?;
+    }
+    Ok(Outcome::Applied(format!("removed {}", list_paths(&stale))))
+}
+
+/// Remove cached objects whose content no longer matches their SHA-256 name
+///
+/// Their index entries are dropped by the next step, so the objects are fetched again.
+fn cached_objects(objects_dir: &Path, fix: bool) -> Result<Outcome> {
+    let objects = CachedObjects::scan(objects_dir)?;
+    if objects.corrupt.is_empty() {
+        return Ok(Outcome::Skipped(format!(
+            "{} object(s) verified",
+            objects.valid.len()
+        )));
+    }
+    if !fix {
+        return Ok(Outcome::Pending(format!(
+            "would remove {} corrupt object(s)",
+            objects.corrupt.len()
+        )));
+    }
+    for path in &objects.corrupt {
+        fs::remove_file(path).with_context(|| format!("Failed to remove {:?}", path))?;
+    }
+    Ok(Outcome::Applied(format!(
+        "removed {} corrupt object(s)",
+        objects.corrupt.len()
+    )))
+}
+
+/// Rebuild an unreadable index, then merge conflict copies and drop entries for missing
+/// objects
+fn cache_index(index_path: &Path, objects_dir: &Path, fix: bool) -> Result<Outcome> {
+    let mut applied = Vec::new();
+    if let Err(e) = CacheIndex::load(index_path) {
+        if !fix {
+            return Ok(Outcome::Pending(format!(
+                "unreadable ({:#}); would rebuild it from the cached objects",
+                e
+            )));
+        }
+        let rebuild = CacheIndex::rebuild(index_path, &CachedObjects::scan(objects_dir)?)?;
+        applied.push(format!("rebuilt: {}", rebuild));
+    }
+
+    let repair = CacheIndex::repair(index_path, objects_dir, !fix)?;
+    if !repair.is_empty() {
+        if !fix {
+            return Ok(Outcome::Pending(format!("would have {}", repair)));
+        }
+        applied.push(repair.to_string());
+    }
+
+    if applied.is_empty() {
+        Ok(Outcome::Skipped(
+            "consistent with the cached objects".to_string(),
+        ))
+    } else {
+        Ok(Outcome::Applied(applied.join("; ")))
+    }
+}
+
+/// Migrate index entries written by older versions to the current layout
+fn cache_layout(index_path: &Path, fix: bool) -> Result<Outcome> {
+    let mut index = match CacheIndex::load(index_path) {
+        Ok(index) => index,
+        // Only without --fix, where the index step left it unreadable
+        Err(_) => return Ok(Outcome::Skipped("cache index is unreadable".to_string())),
+    };
+    let migration = index.migrate();
+    if migration.is_empty() {
+        return Ok(Outcome::Skipped("up to date".to_string()));
+    }
+    let detail = format!(
+        "{} legacy entries upgraded, {} entries from another layout dropped",
+        migration.upgraded, migration.dropped
+    );
+    if !fix {
+        return Ok(Outcome::Pending(format!("would have {}", detail)));
+    }
+    index.save(index_path)?;
+    Ok(Outcome::Applied(detail))
+}
+
+/// Move an unreadable cache aside as `<name>.corrupt`; it's rebuilt the next time it's
+/// needed (network info is queried again, the blob tracker is refilled on fetch)
+fn derived_cache(path: &Path, load: Loader, fix: bool) -> Result<Outcome> {
+    if !path.exists() {
+        return Ok(Outcome::Skipped("not created yet".to_string()));
+    }
+    let Err(e) = load(path) else {
+        return Ok(Outcome::Skipped("readable".to_string()));
+    };
+    if !fix {
+        return Ok(Outcome::Pending(format!(
+            "unreadable ({:#}); would move it aside to be rebuilt",
+            e
+        )));
+    }
+    let backup = corrupt_path(path);
+    fs::rename(path, &backup).with_context(|| format!("Failed to move {:?} aside", path))?;
+    Ok(Outcome::Applied(format!(
+        "moved to {:?}; rebuilt when next needed",
+        backup
+    )))
+}
+
+fn corrupt_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".corrupt");
+    path.with_file_name(name)
+}
+
+/// File names of `paths`, comma separated
+fn list_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.file_name().unwrap_or_default().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use sha2::{Digest, Sha256};
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::storage::ContentKind;
+
+    /// Cache dir holding `objects` and an index mapping `0x<n>` to each
+    fn healthy_cache(objects: &[&str]) -> (TempDir, PathBuf, Vec<String>) {
+        let dir = TempDir::new().unwrap();
+        let cache_dir = dir.path().join("cache");
+        fs::create_dir_all(cache_dir.join("objects")).unwrap();
+
+        let mut index = CacheIndex::new();
+        let mut sha256s = Vec::new();
+        for (i, content) in objects.iter().enumerate() {
+            let sha256 = hex::encode(Sha256::digest(content.as_bytes()));
+            fs::write(cache_dir.join("objects").join(&sha256), content).unwrap();
+            index.insert(format!("0x{}", i), sha256.clone(), ContentKind::RawLoose);
+            sha256s.push(sha256);
+        }
+        index.save(&cache_dir.join("cache_index.yaml")).unwrap();
+        (dir, cache_dir, sha256s)
+    }
+
+    fn outcomes(reports: &[Report]) -> Vec<(&str, &Outcome)> {
+        reports
+            .iter()
+            .map(|report| (report.step.as_str(), &report.outcome))
+            .collect()
+    }
+
+    fn is_pending(outcome: &Outcome) -> bool {
+        matches!(outcome, Outcome::Pending(_))
+    }
+
+    fn is_applied(outcome: &Outcome) -> bool {
+        matches!(outcome, Outcome::Applied(_))
+    }
+
+    fn is_skipped(outcome: &Outcome) -> bool {
+        matches!(outcome, Outcome::Skipped(_))
+    }
+
+    fn old_file(path: &Path) {
+        let file = File::create(path).unwrap();
+        file.set_modified(SystemTime::now() - STALE_FILE_AGE * 2)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_missing_cache_dir() {
+        let dir = TempDir::new().unwrap();
+        let cache_dir = dir.path().join("cache");
+
+        let reports = check_cache(&cache_dir, false);
+        assert_eq!(reports.len(), 1);
+        assert!(is_pending(&reports[0].outcome));
+        assert!(!cache_dir.exists());
+
+        let reports = check_cache(&cache_dir, true);
+        assert!(is_applied(&reports[0].outcome));
+        assert!(cache_dir.join("objects").is_dir());
+        assert!(reports[1..].iter().all(|r| is_skipped(&r.outcome)));
+    }
+
+    #[test]
+    fn test_healthy_cache_needs_nothing() {
+        let (_dir, cache_dir, _) = healthy_cache(&["one", "two"]);
+        for report in check_cache(&cache_dir, true) {
+            assert!(is_skipped(&report.outcome), "{:?}", report);
+        }
+    }
+
+    #[test]
+    fn test_checks_each_remotes_files() {
+        let (_dir, cache_dir, _) = healthy_cache(&["one"]);
+        let origin = cache_dir.join(REMOTES_DIR).join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        fs::copy(
+            cache_dir.join(CACHE_INDEX_FILE),
+            origin.join(CACHE_INDEX_FILE),
+        )
+        .unwrap();
+        fs::write(origin.join("network_info.yaml"), "size_info: {").unwrap();
+
+        let reports = check_cache(&cache_dir, false);
+        let pending: Vec<&str> = outcomes(&reports)
+            .into_iter()
+            .filter(|(_, outcome)| is_pending(outcome))
+            .map(|(step, _)| step)
+            .collect();
+        assert_eq!(pending, ["origin: network_info.yaml"]);
+        assert!(outcomes(&reports)
+            .iter()
+            .any(|(step, outcome)| *step == "origin: cache index" && is_skipped(outcome)));
+
+        check_cache(&cache_dir, true);
+        assert!(origin.join("network_info.yaml.corrupt").exists());
+        for report in check_cache(&cache_dir, true) {
+            assert!(is_skipped(&report.outcome), "{:?}", report);
+        }
+    }
+
+    #[test]
+    fn test_repairs_restore_a_working_cache() {
+        let (_dir, cache_dir, sha256s) = healthy_cache(&["one", "two", "three"]);
+        let objects_dir = cache_dir.join("objects");
+        let index_path = cache_dir.join("cache_index.yaml");
+
+        // Truncate the index mid-write (keeping the first two entries legible), overwrite
+        // one cached object, and leave junk in the derived caches and a stale lock
+        let index = fs::read_to_string(&index_path).unwrap();
+        let cut = index.find(&sha256s[1]).unwrap() + sha256s[1].len();
+        fs::write(
+            &index_path,
+            format!("{}\n  '0x9': [unclosed", &index[..cut]),
+        )
+        .unwrap();
+        fs::write(objects_dir.join(&sha256s[2]), "tampered").unwrap();
+        fs::write(cache_dir.join("network_info.yaml"), "size_info: {").unwrap();
+        fs::write(cache_dir.join("blob_tracker.yaml"), "blobs: 12").unwrap();
+        old_file(&cache_dir.join("index.lock"));
+        // Recent temporaries may belong to a running helper
+        File::create(cache_dir.join("fresh.tmp")).unwrap();
+
+        // Dry run reports without touching anything
+        let snapshot = fs::read(&index_path).unwrap();
+        let reports = check_cache(&cache_dir, false);
+        let pending: Vec<&str> = outcomes(&reports)
+            .into_iter()
+            .filter(|(_, outcome)| is_pending(outcome))
+            .map(|(step, _)| step)
+            .collect();
+        assert_eq!(
+            pending,
+            [
+                "stale lock files",
+                "cached objects",
+                "cache index",
+                "network_info.yaml",
+                "blob_tracker.yaml"
+            ]
+        );
+        assert_eq!(fs::read(&index_path).unwrap(), snapshot);
+        assert!(cache_dir.join("index.lock").exists());
+
+        let reports = check_cache(&cache_dir, true);
+        for report in &reports {
+            assert!(
+                !matches!(report.outcome, Outcome::Failed(_) | Outcome::Pending(_)),
+                "{:?}",
+                report
+            );
+        }
+
+        // Salvaged entries whose objects are intact come back; the tampered object is gone
+        let index = CacheIndex::load(&index_path).unwrap();
+        let raw = ContentKind::RawLoose;
+        assert_eq!(index.get_object_id(&sha256s[0], raw), Some(&"0x0".into()));
+        assert_eq!(index.get_object_id(&sha256s[1], raw), Some(&"0x1".into()));
+        assert_eq!(index.len(), 2);
+        assert!(!objects_dir.join(&sha256s[2]).exists());
+        assert!(cache_dir.join("cache_index.yaml.corrupt").exists());
+        assert!(
+            WalrusNetworkInfo::load(&cache_dir.join("network_info.yaml"))
+                .unwrap()
+                .is_none()
+        );
+        assert!(cache_dir.join("network_info.yaml.corrupt").exists());
+        assert!(BlobTracker::load(&cache_dir.join("blob_tracker.yaml")).is_ok());
+        assert!(!cache_dir.join("index.lock").exists());
+        assert!(cache_dir.join("fresh.tmp").exists());
+
+        // Idempotent
+        for report in check_cache(&cache_dir, true) {
+            assert!(is_skipped(&report.outcome), "{:?}", report);
+        }
+    }
+
+    #[test]
+    fn test_index_missing_objects_and_legacy_layout() {
+        let (_dir, cache_dir, sha256s) = healthy_cache(&["one", "two"]);
+        let index_path = cache_dir.join("cache_index.yaml");
+        // Legacy entries without metadata, one of them for an object no longer cached
+        fs::write(
+            &index_path,
+            format!(
+                "object_to_sha256:\n  '0x0': {a}\n  '0x1': {b}\n  '0x2': {c}\n\
+                 sha256_to_object:\n  {a}: '0x0'\n  {b}: '0x1'\n  {c}: '0x2'\n",
+                a = sha256s[0],
+                b = sha256s[1],
+                c = "f".repeat(64)
+            ),
+        )
+        .unwrap();
+
+        let reports = check_cache(&cache_dir, true);
+        let outcomes = outcomes(&reports);
+        assert!(is_applied(outcomes[3].1), "{:?}", outcomes[3]);
+        assert_eq!(
+            outcomes[4],
+            (
+                "cache layout",
+                &Outcome::Applied(
+                    "2 legacy entries upgraded, 0 entries from another layout dropped".into()
+                )
+            )
+        );
+        let mut index = CacheIndex::load(&index_path).unwrap();
+        assert_eq!(index.len(), 2);
+        assert!(index.migrate().is_empty());
+    }
+
+    #[test]
+    fn test_config_paths() {
+        let dir = TempDir::new().unwrap();
+        let config_path = dir.path().join("config.yaml");
+        fs::write(
+            &config_path,
+            "sui_wallet_path: ~/.sui/sui_config/client.yaml\n\
+             walrus_config_path: ./walrus/client_config.yaml\n\
+             walrus_binary: walrus\n\
+             cache_dir: .cache/git-remote-walrus\n\
+             default_epochs: 5\n",
+        )
+        .unwrap();
+
+        let outcome = config_paths(&config_path, false).unwrap();
+        assert_eq!(
+            outcome,
+            Outcome::Pending(
+                "walrus_config_path: ./walrus/client_config.yaml -> ~/walrus/client_config.yaml, \
+                 cache_dir: .cache/git-remote-walrus -> ~/.cache/git-remote-walrus"
+                    .into()
+            )
+        );
+
+        assert!(is_applied(&config_paths(&config_path, true).unwrap()));
+        let config = WalrusRemoteConfig::load_from_file(&config_path).unwrap();
+        if let Some(home) = dirs::home_dir() {
+            assert_eq!(config.cache_dir, home.join(".cache/git-remote-walrus"));
+        }
+        assert_eq!(config.walrus_binary, Some(PathBuf::from("walrus")));
+        assert_eq!(config.default_epochs, 5);
+        assert!(dir.path().join("config.yaml.bak").exists());
+
+        assert!(is_skipped(&config_paths(&config_path, true).unwrap()));
+    }
+}
@@ -0,0 +1,350 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    pack::objects::GitObject,
+    remote_resolution::Remote,
+    storage::{verify_git_object, MutableState, RefRepair, State, StorageBackend},
+};
+
+/// Objects read per batch with `--full`
+const CHUNK_SIZE: usize = 1000;
+
+/// Something wrong with a remote's refs or objects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A ref's name or value can't be a ref advertised to git, so reads ignore it
+    InvalidRef { ref_name: String, value: String },
+    /// A ref points at an object the objects map doesn't have
+    MissingTip { ref_name: String, git_sha1: String },
+    /// A symref points at a ref that doesn't exist
+    DanglingSymref { name: String, target: String },
+    /// An object's stored bytes don't hash to its git SHA-1
+    Corrupt {
+        git_sha1: String,
+        content_id: String,
+    },
+    /// An object references an object the objects map doesn't have
+    MissingReference {
+        git_sha1: String,
+        referenced: String,
+    },
+    /// An object can't be read, e.g. its content ID doesn't parse or its blob is gone
+    Unreadable {
+        git_sha1: String,
+        content_id: String,
+        error: String,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::InvalidRef { ref_name, value } => {
+                write!(f, "ref {:?} has invalid value {:?}", ref_name, value)
+            }
+            Problem::MissingTip { ref_name, git_sha1 } => {
+                write!(f, "ref {} points at missing object {}", ref_name, git_sha1)
+            }
+            Problem::DanglingSymref { name, target } => {
+                write!(f, "symref {} points at missing ref {}", name, target)
+            }
+            Problem::Corrupt {
+                git_sha1,
+                content_id,
+            } => write!(
+                f,
+                "object {} is stored as {}, whose bytes hash to something else",
+                git_sha1, content_id
+            ),
+            Problem::MissingReference {
+                git_sha1,
+                referenced,
+            } => write!(
+                f,
+                "object {} references missing object {}",
+                git_sha1, referenced
+            ),
+            Problem::Unreadable {
+                git_sha1,
+                content_id,
+                error,
+            } => write!(
+                f,
+                "object {} is stored as {}, which can't be read: {}",
+                git_sha1, content_id, error
+            ),
+        }
+    }
+}
+
+/// Handle the `fsck` subcommand
+/// Checks refs resolve and, with `full`, that every object reads back intact
+///
+/// With `fix`, invalid refs are corrected (when their value is an object ID with stray case or
+/// whitespace) or deleted.
+pub fn handle(remote: &Remote, full: bool, fix: bool) -> Result<()> {
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    let state = storage.read_state()?;
+
+    let mut problems = check(&storage, &state, full)?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    let repairs: Vec<RefRepair> = state
+        .invalid_refs
+        .iter()
+        .map(|(name, value)| RefRepair::for_ref(name, value))
+        .collect();
+    if fix && !repairs.is_empty() {
+        storage.repair_refs(&repairs)?;
+        for repair in &repairs {
+            println!("Repaired ref: {}", repair);
+        }
+        problems.retain(|problem| !matches!(problem, Problem::InvalidRef { .. }));
+    } else if !repairs.is_empty() {
+        for repair in &repairs {
+            println!("Would {}", repair);
+        }
+        println!("Run with --fix to repair invalid refs");
+    }
+    println!(
+        "Checked {} refs{}",
+        state.refs.len(),
+        if full {
+            format!(" and {} objects", state.objects.len())
+        } else {
+            String::new()
+        }
+    );
+    if !problems.is_empty() {
+        anyhow::bail!("found {} problem(s)", problems.len());
+    }
+    Ok(())
+}
+
+/// Problems with `state`'s refs, and with `full` its objects as read from `storage`
+///
+/// Objects are checked against their key whichever content ID the objects map holds for them,
+/// so a mapping conflict resolved the wrong way shows up here.
+pub fn check(storage: &impl StorageBackend, state: &State, full: bool) -> Result<Vec<Problem>> {
+    let mut problems: Vec<Problem> = state
+        .invalid_refs
+        .iter()
+        .map(|(ref_name, value)| Problem::InvalidRef {
+            ref_name: ref_name.clone(),
+            value: value.clone(),
+        })
+        .collect();
+    for (ref_name, git_sha1) in &state.refs {
+        if !state.objects.contains_key(git_sha1) {
+            problems.push(Problem::MissingTip {
+                ref_name: ref_name.clone(),
+                git_sha1: git_sha1.clone(),
+            });
+        }
+    }
+    for (name, target) in &state.symrefs {
+        if !state.refs.contains_key(target) {
+            problems.push(Problem::DanglingSymref {
+                name: name.clone(),
+                target: target.clone(),
+            });
+        }
+    }
+    if !full {
+        return Ok(problems);
+    }
+
+    let objects: Vec<(&String, &String)> = state.objects.iter().collect();
+    for chunk in objects.chunks(CHUNK_SIZE) {
+        let ids: Vec<&str> = chunk.iter().map(|(_, id)| id.as_str()).collect();
+        // A batch that fails is read again object by object, so one bad content ID is
+        // reported without hiding the rest
+        let contents: Vec<Result<Vec<u8>>> = match storage.read_objects(&ids) {
+            Ok(contents) => contents.into_iter().map(Ok).collect(),
+            Err(_) => ids.iter().map(|id| storage.read_object(id)).collect(),
+        };
+        for ((git_sha1, content_id), content) in chunk.iter().zip(contents) {
+            let content = match content {
+                Ok(content) => content,
+                Err(e) => {
+                    problems.push(Problem::Unreadable {
+                        git_sha1: git_sha1.to_string(),
+                        content_id: content_id.to_string(),
+                        error: format!("{:#}", e),
+                    });
+                    continue;
+                }
+            };
+            if verify_git_object(git_sha1, &content).is_err() {
+                problems.push(Problem::Corrupt {
+                    git_sha1: git_sha1.to_string(),
+                    content_id: content_id.to_string(),
+                });
+                continue;
+            }
+            let obj = GitObject::from_loose_format(&content)
+                .with_context(|| format!("Failed to parse object {}", git_sha1))?;
+            for referenced in obj.references()? {
+                if !state.objects.contains_key(&referenced) {
+                    problems.push(Problem::MissingReference {
+                        git_sha1: git_sha1.to_string(),
+                        referenced,
+                    });
+                }
+            }
+        }
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use gix_object::Kind;
+
+    use super::*;
+    use crate::storage::{store_object_in, ImmutableStore, MemoryStorage};
+
+    #[test]
+    fn test_fsck_refs_and_full() {
+        let storage = MemoryStorage::new();
+        let mut state = State::default();
+        let blob = store_object_in(&storage, &mut state, Kind::Blob, b"content");
+        state.refs.insert("refs/heads/main".into(), blob.clone());
+        state
+            .symrefs
+            .insert("HEAD".into(), "refs/heads/main".into());
+        assert_eq!(check(&storage, &state, true).unwrap(), []);
+
+        state.refs.insert("refs/heads/gone".into(), "f".repeat(40));
+        state
+            .symrefs
+            .insert("HEAD".into(), "refs/heads/nope".into());
+        assert_eq!(
+            check(&storage, &state, false).unwrap(),
+            [
+                Problem::MissingTip {
+                    ref_name: "refs/heads/gone".into(),
+                    git_sha1: "f".repeat(40),
+                },
+                Problem::DanglingSymref {
+                    name: "HEAD".into(),
+                    target: "refs/heads/nope".into(),
+                },
+            ]
+        );
+
+        // Remap the blob to bytes that don't hash to it; only --full reads them
+        let drifted = storage.write_object(b"blob 99\0content").unwrap();
+        let mut state = State::default();
+        state.objects.insert(blob.clone(), drifted.clone());
+        assert_eq!(check(&storage, &state, false).unwrap(), []);
+        assert_eq!(
+            check(&storage, &state, true).unwrap(),
+            [Problem::Corrupt {
+                git_sha1: blob.clone(),
+                content_id: drifted.clone(),
+            }]
+        );
+
+        // An object that can't be read is reported, and the rest are still checked
+        let mut state = State::default();
+        state.objects.insert(blob.clone(), "0xblob:zero:1".into());
+        state.objects.insert("a".repeat(40), drifted.clone());
+        let problems = check(&storage, &state, true).unwrap();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems.iter().any(|problem| matches!(
+            problem,
+            Problem::Unreadable { git_sha1, content_id, .. }
+                if *git_sha1 == blob && content_id == "0xblob:zero:1"
+        )));
+        assert!(problems.contains(&Problem::Corrupt {
+            git_sha1: "a".repeat(40),
+            content_id: drifted,
+        }));
+    }
+
+    #[test]
+    fn test_fsck_reports_and_repairs_invalid_refs() {
+        let storage = MemoryStorage::new();
+        let mut state = State::default();
+        let blob = store_object_in(&storage, &mut state, Kind::Blob, b"content");
+        state.refs.insert("refs/heads/main".into(), blob.clone());
+        state.refs.insert(
+            "refs/heads/padded".into(),
+            format!(" {} ", blob.to_uppercase()),
+        );
+        state.refs.insert("refs/heads/junk".into(), "x".repeat(300));
+        storage.write_state(&state).unwrap();
+
+        let state = storage.read_state().unwrap();
+        assert_eq!(
+            check(&storage, &state, false).unwrap(),
+            [
+                Problem::InvalidRef {
+                    ref_name: "refs/heads/junk".into(),
+                    value: "x".repeat(300),
+                },
+                Problem::InvalidRef {
+                    ref_name: "refs/heads/padded".into(),
+                    value: format!(" {} ", blob.to_uppercase()),
+                },
+            ]
+        );
+
+        // Writes keep invalid refs until they're repaired
+        storage.update_state(|_| Ok(())).unwrap();
+        assert_eq!(storage.read_state().unwrap().invalid_refs.len(), 2);
+
+        let repairs: Vec<RefRepair> = state
+            .invalid_refs
+            .iter()
+            .map(|(name, value)| RefRepair::for_ref(name, value))
+            .collect();
+        assert_eq!(
+            repairs,
+            [
+                RefRepair::Delete {
+                    name: "refs/heads/junk".into(),
+                },
+                RefRepair::Correct {
+                    name: "refs/heads/padded".into(),
+                    git_sha1: blob.clone(),
+                },
+            ]
+        );
+        storage.repair_refs(&repairs).unwrap();
+        let state = storage.read_state().unwrap();
+        assert!(state.invalid_refs.is_empty());
+        assert_eq!(state.refs.len(), 2);
+        assert_eq!(state.refs["refs/heads/padded"], blob);
+        assert_eq!(check(&storage, &state, false).unwrap(), []);
+    }
+
+    #[test]
+    fn test_fsck_missing_reference() {
+        let storage = MemoryStorage::new();
+        let mut state = State::default();
+        let missing_tree = "e".repeat(40);
+        let commit = store_object_in(
+            &storage,
+            &mut state,
+            Kind::Commit,
+            format!(
+                "tree {}\nauthor A <a@a> 0 +0000\ncommitter A <a@a> 0 +0000\n\nmsg\n",
+                missing_tree
+            ),
+        );
+        state.refs.insert("refs/heads/main".into(), commit.clone());
+
+        assert_eq!(
+            check(&storage, &state, true).unwrap(),
+            [Problem::MissingReference {
+                git_sha1: commit,
+                referenced: missing_tree,
+            }]
+        );
+    }
+}
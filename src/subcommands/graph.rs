@@ -0,0 +1,195 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::Result;
+
+use crate::{
+    commands::namespace::RefNamespace,
+    pack::graph::CommitGraph,
+    remote::parse_remote_url,
+    remote_resolution::Remote,
+    storage::MutableState,
+};
+
+/// Output format of the `graph` subcommand
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// `{"nodes": [...]}`, one node per commit with its parents and refs
+    #[default]
+    Json,
+    /// Graphviz DOT
+    Dot,
+}
+
+impl FromStr for GraphFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(GraphFormat::Json),
+            "dot" => Ok(GraphFormat::Dot),
+            other => anyhow::bail!(
+                "invalid graph format {:?} (expected 'json' or 'dot')",
+                other
+            ),
+        }
+    }
+}
+
+impl fmt::Display for GraphFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphFormat::Json => write!(f, "json"),
+            GraphFormat::Dot => write!(f, "dot"),
+        }
+    }
+}
+
+/// Handle the `graph` subcommand
+/// Prints the commit graph reachable from the remote's refs
+pub fn handle(remote: &Remote, format: GraphFormat) -> Result<()> {
+    let namespace =
+        RefNamespace::resolve(parse_remote_url(&remote.url)?.options.namespace.as_deref())?;
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    let state = storage.read_state()?;
+
+    let refs = namespace
+        .local_refs(&state.refs)
+        .map(|(name, id)| (name, id.as_str()));
+    let graph = CommitGraph::build(&storage, &state, refs)?;
+
+    match format {
+        GraphFormat::Json => println!("{}", serde_json::to_string_pretty(&graph)?),
+        GraphFormat::Dot => print!("{}", render_dot(&graph)),
+    }
+    Ok(())
+}
+
+/// Graphviz DOT with an edge from each commit to its parents and from each ref to its commit
+fn render_dot(graph: &CommitGraph) -> String {
+    let mut dot = String::from("digraph commits {\n  rankdir=BT;\n  node [shape=box];\n");
+    for node in &graph.nodes {
+        dot.push_str(&format!(
+            "  \"{}\" [label=\"{}\\n{}\"];\n",
+            node.id,
+            &node.id[..node.id.len().min(7)],
+            dot_escape(&node.summary)
+        ));
+        for parent in &node.parents {
+            dot.push_str(&format!("  \"{}\" -> \"{}\";\n", node.id, parent));
+        }
+        for name in &node.refs {
+            dot.push_str(&format!(
+                "  \"{0}\" [shape=ellipse];\n  \"{0}\" -> \"{1}\";\n",
+                dot_escape(name),
+                node.id
+            ));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use gix_object::Kind;
+
+    use super::*;
+    use crate::storage::{store_object_in, MemoryStorage, State};
+
+    fn commit(
+        storage: &MemoryStorage,
+        state: &mut State,
+        parents: &[&str],
+        time: u32,
+        message: &str,
+    ) -> String {
+        let mut data = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n".to_string();
+        for parent in parents {
+            data.push_str(&format!("parent {}\n", parent));
+        }
+        data.push_str(&format!(
+            "author A <a@a> {time} +0000\ncommitter A <a@a> {time} +0000\n\n{message}\n"
+        ));
+        store_object_in(storage, state, Kind::Commit, data)
+    }
+
+    #[test]
+    fn test_json_graph_of_small_repo() {
+        let storage = MemoryStorage::new();
+        let mut state = State::default();
+
+        //   root <- left  <- merge (main, v1 via an annotated tag)
+        //        <- right <-/
+        //   unreferenced (not in the graph)
+        let root = commit(&storage, &mut state, &[], 100, "Root");
+        let left = commit(&storage, &mut state, &[&root], 200, "Left");
+        let right = commit(&storage, &mut state, &[&root], 300, "Right");
+        let merge = commit(&storage, &mut state, &[&left, &right], 400, "Merge");
+        commit(&storage, &mut state, &[&root], 500, "Unreferenced");
+        let tag = store_object_in(
+            &storage,
+            &mut state,
+            Kind::Tag,
+            format!("object {merge}\ntype commit\ntag v1\ntagger A <a@a> 400 +0000\n\nv1\n"),
+        );
+        state.refs.insert("refs/heads/main".into(), merge.clone());
+        state.refs.insert("refs/heads/right".into(), right.clone());
+        state.refs.insert("refs/tags/v1".into(), tag);
+
+        let refs = state
+            .refs
+            .iter()
+            .map(|(name, id)| (name.as_str(), id.as_str()));
+        let graph = CommitGraph::build(&storage, &state, refs).unwrap();
+        let json: serde_json::Value = serde_json::to_value(&graph).unwrap();
+
+        let nodes = json["nodes"].as_array().unwrap();
+        let summary: Vec<(&str, Vec<&str>, Vec<&str>)> = nodes
+            .iter()
+            .map(|node| {
+                let strings = |key: &str| -> Vec<&str> {
+                    node[key]
+                        .as_array()
+                        .map(|values| values.iter().map(|v| v.as_str().unwrap()).collect())
+                        .unwrap_or_default()
+                };
+                (
+                    node["summary"].as_str().unwrap(),
+                    strings("parents"),
+                    strings("refs"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "Merge",
+                    vec![left.as_str(), right.as_str()],
+                    vec!["refs/heads/main", "refs/tags/v1"]
+                ),
+                ("Right", vec![root.as_str()], vec!["refs/heads/right"]),
+                ("Left", vec![root.as_str()], vec![]),
+                ("Root", vec![], vec![]),
+            ]
+        );
+        assert_eq!(nodes[0]["id"], merge.as_str());
+        assert_eq!(nodes[0]["timestamp"], 400);
+
+        let dot = render_dot(&graph);
+        assert!(
+            dot.contains(&format!("\"{}\" -> \"{}\";", merge, right)),
+            "{}",
+            dot
+        );
+        assert!(
+            dot.contains(&format!("\"refs/tags/v1\" -> \"{}\";", merge)),
+            "{}",
+            dot
+        );
+    }
+}
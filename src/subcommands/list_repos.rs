@@ -0,0 +1,225 @@
+use std::fmt::Write;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{
+    config::WalrusRemoteConfig,
+    storage::{StorageBackend, WalrusStorage},
+    sui::SuiClient,
+};
+
+/// One remote the active address owns or may push to, as `list-repos` reports it
+#[derive(Debug, Default, Serialize)]
+struct RepoSummary {
+    object_id: String,
+    /// None when the remote couldn't be read
+    refs: Option<usize>,
+    /// End epoch of the soonest-expiring blob the remote references
+    earliest_expiry: Option<u64>,
+    epochs_remaining: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Handle the `list-repos` subcommand
+/// Lists the remotes the active address owns or is allowlisted on, with each one's ref count
+/// and earliest blob expiry
+pub fn handle(package_id: Option<&str>, json: bool) -> Result<()> {
+    let config = WalrusRemoteConfig::load().context("Failed to load configuration")?;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let listed = runtime.block_on(list(&config, package_id))?;
+    // Each remote's storage runs its own runtime
+    drop(runtime);
+
+    let repos: Vec<RepoSummary> = listed
+        .into_iter()
+        .map(|(object_id, refs)| {
+            let summary = refs.and_then(|refs| summarize(&object_id, refs, &config));
+            summary.unwrap_or_else(|e| {
+                tracing::warn!("Failed to read remote {}: {:#}", object_id, e);
+                RepoSummary {
+                    object_id,
+                    error: Some(format!("{:#}", e)),
+                    ..Default::default()
+                }
+            })
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&repos)?);
+    } else {
+        print!("{}", render(&repos));
+    }
+    Ok(())
+}
+
+/// The remotes the active address owns or may push to, each with its ref count read from Sui
+async fn list(
+    config: &WalrusRemoteConfig,
+    package_id: Option<&str>,
+) -> Result<Vec<(String, Result<usize>)>> {
+    let wallet = config.sui_wallet_path.clone();
+    let sui_client = match package_id {
+        Some(package_id) => {
+            SuiClient::new_for_init(package_id.to_string(), wallet, config.network_timeouts())
+                .await?
+        }
+        None => SuiClient::for_wallet(wallet, config.network_timeouts()).await?,
+    };
+
+    let mut listed = Vec::new();
+    for object_id in sui_client.get_owned_remote_states().await? {
+        let refs = async {
+            let client = SuiClient::new(
+                object_id.clone(),
+                config.sui_wallet_path.clone(),
+                config.network_timeouts(),
+            )
+            .await?;
+            anyhow::Ok(client.read_refs().await?.len())
+        }
+        .await;
+        listed.push((object_id, refs));
+    }
+    Ok(listed)
+}
+
+/// One remote's summary, with the blob expiry read through its storage
+fn summarize(object_id: &str, refs: usize, config: &WalrusRemoteConfig) -> Result<RepoSummary> {
+    let storage = WalrusStorage::new(
+        object_id.to_string(),
+        format!("walrus::{}", object_id),
+        config.clone(),
+    )?;
+    storage.initialize()?;
+
+    let report = storage.expiry_report()?;
+    let earliest = report.blobs.iter().min_by_key(|blob| blob.end_epoch);
+    Ok(RepoSummary {
+        object_id: object_id.to_string(),
+        refs: Some(refs),
+        earliest_expiry: earliest.map(|blob| blob.end_epoch),
+        epochs_remaining: earliest.map(|blob| blob.epochs_remaining),
+        error: None,
+    })
+}
+
+fn render(repos: &[RepoSummary]) -> String {
+    if repos.is_empty() {
+        return "No remotes owned by or shared with the active address.\n".to_string();
+    }
+
+    let mut text = format!("{:<68}{:>6}  {}\n", "OBJECT ID", "REFS", "EARLIEST EXPIRY");
+    for repo in repos {
+        let refs = repo.refs.map_or("?".to_string(), |refs| refs.to_string());
+        let expiry = match (&repo.error, repo.earliest_expiry, repo.epochs_remaining) {
+            (Some(error), _, _) => format!("error: {}", error),
+            (None, Some(end_epoch), Some(left)) => {
+                format!("epoch {} ({} epoch(s) left)", end_epoch, left)
+            }
+            (None, _, _) => "-".to_string(),
+        };
+        let _ = writeln!(text, "{:<68}{:>6}  {}", repo.object_id, refs, expiry);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sui::mock_rpc::{MockSui, PACKAGE_ID};
+
+    #[test]
+    fn test_list_finds_owned_and_shared_remotes() {
+        let dir = tempfile::tempdir().unwrap();
+        let walrus_dir = dir.path().join("walrus");
+        std::fs::create_dir_all(&walrus_dir).unwrap();
+        let sui = MockSui::start(&walrus_dir);
+        let (owner_wallet, owner) = sui.wallet(&dir.path().join("owner"));
+        let (allowed_wallet, allowed) = sui.wallet(&dir.path().join("allowed"));
+        let (_, stranger) = sui.wallet(&dir.path().join("stranger"));
+
+        let owned = sui.create_remote(owner, false);
+        let shared = sui.create_remote(owner, true);
+        sui.share(shared, owner, &[allowed]);
+        let others = sui.create_remote(stranger, false);
+        sui.share(others, stranger, &[]);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let list_for = |wallet: &std::path::Path, package_id: Option<&str>| {
+            let config: WalrusRemoteConfig = serde_yaml::from_str(&format!(
+                "sui_wallet_path: \"{}\"\ncache_dir: \"{}\"\n",
+                wallet.display(),
+                dir.path().join("cache").display()
+            ))
+            .unwrap();
+            let mut listed: Vec<(String, usize)> = runtime
+                .block_on(list(&config, package_id))
+                .unwrap()
+                .into_iter()
+                .map(|(object_id, refs)| (object_id, refs.unwrap()))
+                .collect();
+            listed.sort();
+            listed
+        };
+        let expected = |ids: &[sui_types::base_types::ObjectID]| {
+            let mut ids: Vec<(String, usize)> =
+                ids.iter().map(|id| (id.to_hex_literal(), 0)).collect();
+            ids.sort();
+            ids
+        };
+
+        // The owner finds the remote it still owns and the one it shared
+        assert_eq!(list_for(&owner_wallet, None), expected(&[owned, shared]));
+        // The allowlisted address finds the remote shared with it through the package's
+        // sharing transactions, but not the stranger's
+        assert_eq!(
+            list_for(&allowed_wallet, Some(PACKAGE_ID)),
+            expected(&[shared])
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let object_id = |n: u8| format!("0x{}", format!("{:02x}", n).repeat(32));
+        let repos = [
+            RepoSummary {
+                object_id: object_id(1),
+                refs: Some(12),
+                earliest_expiry: Some(120),
+                epochs_remaining: Some(3),
+                error: None,
+            },
+            RepoSummary {
+                object_id: object_id(2),
+                refs: Some(0),
+                ..Default::default()
+            },
+            RepoSummary {
+                object_id: object_id(3),
+                error: Some("RemoteState object not found".to_string()),
+                ..Default::default()
+            },
+        ];
+
+        let text = render(&repos);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("OBJECT ID"));
+        assert!(lines[1].starts_with(&object_id(1)));
+        assert!(lines[1].ends_with("    12  epoch 120 (3 epoch(s) left)"));
+        assert!(lines[2].ends_with("     0  -"));
+        assert!(lines[3].ends_with("     ?  error: RemoteState object not found"));
+
+        let json = serde_json::to_value(&repos[2]).unwrap();
+        assert_eq!(json["refs"], serde_json::Value::Null);
+        assert!(serde_json::to_value(&repos[0])
+            .unwrap()
+            .get("error")
+            .is_none());
+        assert!(render(&[]).starts_with("No remotes"));
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat};
+
+use super::cat::resolve_revision;
+use crate::{
+    commands::namespace::RefNamespace,
+    pack::history::{self, PathCommit},
+    remote::parse_remote_url,
+    remote_resolution::Remote,
+    storage::MutableState,
+};
+
+/// Handle the `log-path` subcommand
+/// Lists the commits reachable from `ref_name` that changed `path`, newest first, reading
+/// only commits and the trees along the path
+pub fn handle(remote: &Remote, path: &str, ref_name: &str) -> Result<()> {
+    let namespace =
+        RefNamespace::resolve(parse_remote_url(&remote.url)?.options.namespace.as_deref())?;
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    let state = storage.read_state()?;
+    let (full_ref, commit) = resolve_revision(&state, &namespace, ref_name)?;
+
+    let changes = history::path_history(&storage, &state, commit, path)
+        .with_context(|| format!("Failed to read the history of {} from {}", path, full_ref))?;
+    for change in &changes {
+        println!("{}", format_change(change));
+    }
+    Ok(())
+}
+
+/// `<sha> <committer date> <author>`, with `(deleted)` after commits that removed the path
+fn format_change(change: &PathCommit) -> String {
+    let date = DateTime::from_timestamp(change.commit.timestamp, 0)
+        .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default();
+    let mut line = format!("{} {} {}", change.commit.id, date, change.commit.author);
+    if change.id.is_none() {
+        line.push_str(" (deleted)");
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::graph::CommitNode;
+
+    #[test]
+    fn test_format_change() {
+        let mut change = PathCommit {
+            commit: CommitNode {
+                id: "a".repeat(40),
+                parents: Vec::new(),
+                refs: Vec::new(),
+                author: "A U Thor <a@example.com>".to_string(),
+                timestamp: 1_700_000_000,
+                summary: "Add it".to_string(),
+            },
+            id: Some("b".repeat(40)),
+        };
+        assert_eq!(
+            format_change(&change),
+            format!(
+                "{} 2023-11-14T22:13:20Z A U Thor <a@example.com>",
+                "a".repeat(40)
+            )
+        );
+        change.id = None;
+        assert!(format_change(&change).ends_with("<a@example.com> (deleted)"));
+    }
+}
@@ -0,0 +1,518 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    pack::objects::GitObject,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    storage::{ContentId, MutableState, State, StorageBackend},
+    sui::RefsLayout,
+};
+
+/// Journal file, kept in the filesystem side of a migration
+const JOURNAL_FILE: &str = "migration.yaml";
+
+/// Objects copied (and verified) per batch; the journal is saved after each one
+const CHUNK_SIZE: usize = 1000;
+
+/// Where `migrate` copies a remote to
+#[derive(Debug, Clone)]
+pub enum MigrationTarget {
+    /// A new RemoteState created from `package_id` (as `init` would)
+    Sui {
+        package_id: String,
+        shared: bool,
+        allowlist: Vec<String>,
+        refs_layout: RefsLayout,
+    },
+    /// A filesystem remote at this path (empty, or holding an unfinished migration)
+    Filesystem(PathBuf),
+    /// An existing remote, holding no refs yet
+    ///
+    /// Objects already uploaded to it are skipped as the destination's cache index knows them.
+    Remote(Remote),
+}
+
+/// Progress of a migration, so an interrupted one resumes where it stopped
+///
+/// Once the migration succeeds it doubles as the report mapping each git SHA-1 to its
+/// ContentId on the target.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MigrationJournal {
+    /// Source remote URL
+    pub source: String,
+    /// Target remote URL, once the target exists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Objects already copied: git SHA-1 -> ContentId on the target
+    #[serde(default)]
+    pub objects: BTreeMap<String, ContentId>,
+    /// Metadata blob ContentId on the target, once copied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ContentId>,
+    /// Whether the migration has been verified
+    #[serde(default)]
+    pub complete: bool,
+}
+
+impl MigrationJournal {
+    fn load(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => serde_yaml::from_str(&content)
+                .map(Some)
+                .with_context(|| format!("Failed to parse migration journal {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)
+                .with_context(|| format!("Failed to read migration journal {}", path.display())),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let yaml = serde_yaml::to_string(self).context("Failed to serialize migration journal")?;
+        let temp_path = path.with_extension("yaml.tmp");
+        std::fs::write(&temp_path, yaml)
+            .and_then(|()| std::fs::rename(&temp_path, path))
+            .with_context(|| format!("Failed to write migration journal {}", path.display()))
+    }
+}
+
+/// Handle the `migrate` subcommand
+/// Copies every object, the refs and the metadata of `source` to a new remote
+pub fn handle(source: &Remote, target: MigrationTarget) -> Result<()> {
+    let source_url = parse_remote_url(&source.url)?;
+    if source_url.options.namespace.is_some() {
+        anyhow::bail!(
+            "migrate copies a whole remote; drop the repo suffix from {}",
+            source.url
+        );
+    }
+
+    // The journal lives on whichever side is a local directory
+    let target_path = match &target {
+        MigrationTarget::Filesystem(path) => Some(path.clone()),
+        MigrationTarget::Remote(remote) => {
+            if remote.url == source.url {
+                anyhow::bail!(
+                    "migrate needs two different remotes, not {} twice",
+                    source.url
+                );
+            }
+            match parse_remote_url(&remote.url)?.remote_type {
+                RemoteType::Filesystem(path) => Some(path),
+                RemoteType::Sui(_) => None,
+            }
+        }
+        MigrationTarget::Sui { .. } => None,
+    };
+    let journal_dir = match (&source_url.remote_type, target_path) {
+        (_, Some(path)) => path,
+        (RemoteType::Filesystem(path), None) => path.clone(),
+        (RemoteType::Sui(_), None) => {
+            anyhow::bail!(
+                "migrate copies to or from a filesystem remote, not between Walrus remotes"
+            )
+        }
+    };
+    let journal_path = journal_dir.join(JOURNAL_FILE);
+
+    let mut journal = match MigrationJournal::load(&journal_path)? {
+        Some(journal) if journal.source != source.url => anyhow::bail!(
+            "{} belongs to a migration from {}; remove it to start over",
+            journal_path.display(),
+            journal.source
+        ),
+        Some(journal) => {
+            tracing::info!(
+                "Resuming migration: {} objects already copied",
+                journal.objects.len()
+            );
+            journal
+        }
+        None => MigrationJournal {
+            source: source.url.clone(),
+            ..Default::default()
+        },
+    };
+    if journal.complete {
+        println!(
+            "Already migrated {} to {}",
+            source.url,
+            journal.target.as_deref().unwrap_or("?")
+        );
+        return Ok(());
+    }
+
+    let target = match (&journal.target, target) {
+        (Some(url), MigrationTarget::Remote(to)) if *url != to.url => anyhow::bail!(
+            "{} belongs to a migration to {}, not {}; remove it to start over",
+            journal_path.display(),
+            url,
+            to.url
+        ),
+        (Some(_), MigrationTarget::Remote(to)) => to,
+        (Some(url), _) => Remote::url(url),
+        (None, MigrationTarget::Remote(to)) => {
+            let existing = crate::open_storage(&to.name, &to.url)?.read_state()?;
+            if !existing.refs.is_empty() {
+                anyhow::bail!(
+                    "{} already holds refs; migrate into a remote nobody has pushed to",
+                    to.url
+                );
+            }
+            to
+        }
+        (None, MigrationTarget::Filesystem(path)) => {
+            if path.join("state.yaml").exists() {
+                anyhow::bail!(
+                    "{} already holds a remote; migrate into a new directory",
+                    path.display()
+                );
+            }
+            Remote::url(&format!("walrus::{}", path.display()))
+        }
+        (
+            None,
+            MigrationTarget::Sui {
+                package_id,
+                shared,
+                allowlist,
+                refs_layout,
+            },
+        ) => {
+            let object_id = crate::create_remote(package_id, shared, allowlist, refs_layout)?;
+            Remote::url(&format!("walrus::{}", object_id))
+        }
+    };
+    if journal.target.is_none() {
+        std::fs::create_dir_all(&journal_dir)
+            .with_context(|| format!("Failed to create {}", journal_dir.display()))?;
+        journal.target = Some(target.url.clone());
+        journal.save(&journal_path)?;
+    }
+
+    let source_storage = crate::open_storage(&source.name, &source.url)?;
+    let target_storage = crate::open_storage(&target.name, &target.url)?;
+
+    let state = source_storage.read_state()?;
+    copy_remote(
+        &source_storage,
+        &target_storage,
+        &state,
+        &mut journal,
+        |journal| journal.save(&journal_path),
+    )?;
+
+    println!(
+        "Verifying {} objects on {}...",
+        state.objects.len(),
+        target.url
+    );
+    verify_remote(&target_storage, &state)?;
+    journal.complete = true;
+    journal.save(&journal_path)?;
+
+    println!(
+        "✓ Migrated {} refs and {} objects to {}",
+        state.refs.len(),
+        state.objects.len(),
+        target.url
+    );
+    println!("  Object mapping: {}", journal_path.display());
+    println!("\nTo use the new remote:");
+    println!("  git remote add walrus {}", target.url);
+    println!("Or, in existing clones, switch their remote over:");
+    println!("  git remote set-url <remote> {}", target.url);
+
+    Ok(())
+}
+
+/// Copy `state`'s objects and metadata from `source` to `target`, then its refs
+///
+/// Objects already in `journal` are skipped; `save` is called after each batch.
+pub fn copy_remote(
+    source: &impl StorageBackend,
+    target: &impl StorageBackend,
+    state: &State,
+    journal: &mut MigrationJournal,
+    mut save: impl FnMut(&MigrationJournal) -> Result<()>,
+) -> Result<()> {
+    let pending: Vec<(&str, &str)> = state
+        .objects
+        .iter()
+        .filter(|(git_sha1, _)| !journal.objects.contains_key(*git_sha1))
+        .map(|(git_sha1, content_id)| (git_sha1.as_str(), content_id.as_str()))
+        .collect();
+    tracing::info!(
+        "Copying {} of {} objects",
+        pending.len(),
+        state.objects.len()
+    );
+
+    for chunk in pending.chunks(CHUNK_SIZE) {
+        let contents = source
+            .read_git_objects(chunk)
+            .context("Failed to read objects from the source")?;
+        let contents: Vec<&[u8]> = contents.iter().map(Vec::as_slice).collect();
+        let content_ids = target
+            .write_objects(&contents)
+            .context("Failed to write objects to the target")?;
+
+        for ((git_sha1, _), content_id) in chunk.iter().zip(content_ids) {
+            journal.objects.insert(git_sha1.to_string(), content_id);
+        }
+        save(journal)?;
+        tracing::info!(
+            "Copied {}/{} objects",
+            journal.objects.len(),
+            state.objects.len()
+        );
+    }
+
+    if let (Some(content_id), None) = (&state.metadata, &journal.metadata) {
+        let metadata = source
+            .read_object(content_id)
+            .context("Failed to read the metadata blob from the source")?;
+        journal.metadata = Some(
+            target
+                .write_object(&metadata)
+                .context("Failed to write the metadata blob to the target")?,
+        );
+        save(journal)?;
+    }
+
+    target.update_state(|target_state| {
+        if !target_state.refs.is_empty() && target_state.refs != state.refs {
+            anyhow::bail!("The target already has different refs; refusing to overwrite them");
+        }
+        target_state.refs = state.refs.clone();
+        target_state.objects.extend(
+            journal
+                .objects
+                .iter()
+                .filter(|(git_sha1, _)| state.objects.contains_key(*git_sha1))
+                .map(|(git_sha1, content_id)| (git_sha1.clone(), content_id.clone())),
+        );
+        target_state.metadata = journal.metadata.clone();
+        Ok(())
+    })
+}
+
+/// Check that `target` holds `expected`'s refs and every one of its objects, intact
+///
+/// Every object is read back and checked against its SHA-1, and every ref and every object an
+/// object references must be present, as `git fsck` would require.
+pub fn verify_remote(target: &impl StorageBackend, expected: &State) -> Result<()> {
+    let state = target.read_state()?;
+    if state.refs != expected.refs {
+        anyhow::bail!(
+            "Refs differ after migration: expected {:?}, found {:?}",
+            expected.refs,
+            state.refs
+        );
+    }
+    if let Some(missing) = expected
+        .objects
+        .keys()
+        .find(|git_sha1| !state.objects.contains_key(*git_sha1))
+    {
+        anyhow::bail!("Object {} is missing after migration", missing);
+    }
+    if let Some((name, _)) = state
+        .refs
+        .iter()
+        .find(|(_, git_sha1)| !state.objects.contains_key(*git_sha1))
+    {
+        anyhow::bail!("Ref {} points at a missing object", name);
+    }
+
+    let objects: Vec<(&str, &str)> = state
+        .objects
+        .iter()
+        .map(|(git_sha1, content_id)| (git_sha1.as_str(), content_id.as_str()))
+        .collect();
+    for chunk in objects.chunks(CHUNK_SIZE) {
+        let contents = target
+            .read_git_objects(chunk)
+            .context("Failed to read back migrated objects")?;
+        for ((git_sha1, _), content) in chunk.iter().zip(contents) {
+            let obj = GitObject::from_loose_format(&content)
+                .with_context(|| format!("Failed to parse object {}", git_sha1))?;
+            for referenced in obj.references()? {
+                if !state.objects.contains_key(&referenced) {
+                    anyhow::bail!(
+                        "Object {} references missing object {}",
+                        git_sha1,
+                        referenced
+                    );
+                }
+            }
+        }
+    }
+
+    if let Some(content_id) = &state.metadata {
+        target
+            .read_object(content_id)
+            .context("Failed to read back the metadata blob")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use gix_object::Kind;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        pack::objects::ObjectId,
+        storage::{FilesystemStorage, ImmutableStore, RepoMetadata},
+    };
+
+    /// A filesystem remote holding a blob, a tree and a commit on refs/heads/main
+    fn source_remote(dir: &Path) -> (FilesystemStorage, State) {
+        let storage = FilesystemStorage::new(dir).unwrap();
+        storage.initialize().unwrap();
+
+        let blob = GitObject::from_raw(Kind::Blob, b"hello\n".to_vec()).unwrap();
+        let mut tree_data = b"100644 hello.txt\0".to_vec();
+        tree_data.extend(hex::decode(&blob.id).unwrap());
+        let tree = GitObject::from_raw(Kind::Tree, tree_data).unwrap();
+        let commit = GitObject::from_raw(
+            Kind::Commit,
+            format!(
+                "tree {}\nauthor A <a@a> 0 +0000\ncommitter A <a@a> 0 +0000\n\nOne\n",
+                tree.id
+            )
+            .into_bytes(),
+        )
+        .unwrap();
+
+        let mut state = State::default();
+        for obj in [&blob, &tree, &commit] {
+            let content_id = storage.write_object(&obj.to_loose_format()).unwrap();
+            state.objects.insert(obj.id.clone(), content_id);
+        }
+        state
+            .refs
+            .insert("refs/heads/main".into(), commit.id.clone());
+        RepoMetadata {
+            description: Some("migrated".into()),
+            ..Default::default()
+        }
+        .store(&storage, &mut state)
+        .unwrap();
+        storage.write_state(&state).unwrap();
+        (storage, state)
+    }
+
+    #[test]
+    fn test_copy_and_verify() {
+        let temp = TempDir::new().unwrap();
+        let (source, state) = source_remote(&temp.path().join("source"));
+        let target = FilesystemStorage::new(temp.path().join("target")).unwrap();
+        target.initialize().unwrap();
+
+        let mut journal = MigrationJournal::default();
+        let mut saves = 0;
+        copy_remote(&source, &target, &state, &mut journal, |_| {
+            saves += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(saves, 2);
+        verify_remote(&target, &state).unwrap();
+
+        let copied = target.read_state().unwrap();
+        assert_eq!(copied.refs, state.refs);
+        assert_eq!(
+            RepoMetadata::load(&target, &copied).unwrap().description,
+            Some("migrated".into())
+        );
+
+        // A lost object is caught
+        let commit: &ObjectId = &state.refs["refs/heads/main"];
+        let dropped = state.objects.keys().find(|id| *id != commit).unwrap();
+        let mut broken = copied.clone();
+        broken.objects.remove(dropped);
+        target.write_state(&broken).unwrap();
+        assert!(verify_remote(&target, &state).is_err());
+    }
+
+    #[test]
+    fn test_migrate_into_existing_remote() {
+        let temp = TempDir::new().unwrap();
+        let (_, state) = source_remote(&temp.path().join("source"));
+        let source = Remote::url(&format!("walrus::{}", temp.path().join("source").display()));
+        let remote = |name: &str| {
+            let path = temp.path().join(name);
+            FilesystemStorage::new(&path).unwrap().initialize().unwrap();
+            Remote::url(&format!("walrus::{}", path.display()))
+        };
+
+        let target = remote("target");
+        handle(&source, MigrationTarget::Remote(target.clone())).unwrap();
+        let migrated = crate::open_storage(&target.name, &target.url).unwrap();
+        verify_remote(&migrated, &state).unwrap();
+        let journal = MigrationJournal::load(&temp.path().join("target").join(JOURNAL_FILE))
+            .unwrap()
+            .unwrap();
+        assert!(journal.complete);
+        assert_eq!(journal.target, Some(target.url.clone()));
+
+        // Remotes that already have refs are left alone
+        let err = handle(&target, MigrationTarget::Remote(source.clone())).unwrap_err();
+        assert!(err.to_string().contains("already holds refs"), "{:#}", err);
+        let err = handle(&source, MigrationTarget::Remote(source.clone())).unwrap_err();
+        assert!(
+            err.to_string().contains("two different remotes"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_copy_resumes_from_journal() {
+        let temp = TempDir::new().unwrap();
+        let (source, state) = source_remote(&temp.path().join("source"));
+        let target = FilesystemStorage::new(temp.path().join("target")).unwrap();
+        target.initialize().unwrap();
+
+        // Corrupt one object so the first attempt fails
+        let (bad_sha, bad_id) = state.objects.iter().next().unwrap();
+        let path = temp.path().join("source/objects").join(bad_id);
+        let good = std::fs::read(&path).unwrap();
+        std::fs::write(&path, b"blob 3\0bad").unwrap();
+
+        let mut journal = MigrationJournal::default();
+        let err = copy_remote(&source, &target, &state, &mut journal, |_| Ok(())).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("checksum mismatch"),
+            "{:#}",
+            err
+        );
+        assert!(target.read_state().unwrap().refs.is_empty());
+
+        // Once the journal records the object as copied, it is not read again
+        let content_id = target.write_object(&good).unwrap();
+        journal.objects.insert(bad_sha.clone(), content_id);
+        journal
+            .objects
+            .insert("f".repeat(40), "left over from an earlier run".into());
+        copy_remote(&source, &target, &state, &mut journal, |_| Ok(())).unwrap();
+        verify_remote(&target, &state).unwrap();
+
+        // Journal entries for objects the source doesn't have are not published
+        assert!(!target
+            .read_state()
+            .unwrap()
+            .objects
+            .contains_key(&"f".repeat(40)));
+    }
+}
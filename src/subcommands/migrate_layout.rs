@@ -0,0 +1,53 @@
+use anyhow::{Context, Result};
+
+use crate::{
+    config::WalrusRemoteConfig,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    sui::{ensure_spending_allowed, RefsLayout, SuiClient},
+};
+
+/// Handle the `migrate-layout` subcommand
+/// Moves an inline-layout remote's refs into the on-chain table
+pub fn handle(remote: &Remote) -> Result<()> {
+    let object_id = match parse_remote_url(&remote.url)?.remote_type {
+        RemoteType::Sui(object_id) => object_id,
+        RemoteType::Filesystem(path) => anyhow::bail!(
+            "migrate-layout only applies to Walrus remotes, not filesystem remote {:?}",
+            path
+        ),
+    };
+
+    let config = WalrusRemoteConfig::load_for_remote(Some(&remote.name))
+        .context("Failed to load configuration")?;
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    runtime.block_on(async {
+        let sui_client = SuiClient::new(
+            object_id.clone(),
+            config.sui_wallet_path.clone(),
+            config.network_timeouts(),
+        )
+        .await?
+        .with_gas_reserve(config.gas_reserve_mist);
+
+        match sui_client.refs_layout().await? {
+            RefsLayout::Table => {
+                println!("Remote {} already uses the table layout.", object_id);
+                Ok(())
+            }
+            RefsLayout::Inline => {
+                ensure_spending_allowed(sui_client.network(), config.allow_mainnet)?;
+
+                let refs = sui_client.read_refs().await?;
+                println!("Migrating {} ref(s) to the table layout...", refs.len());
+                sui_client
+                    .migrate_refs_to_table()
+                    .await
+                    .context("Failed to migrate refs to table layout")?;
+                println!("✓ Remote {} now uses the table layout", object_id);
+                Ok(())
+            }
+        }
+    })
+}
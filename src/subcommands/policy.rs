@@ -0,0 +1,84 @@
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    config::WalrusRemoteConfig,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    sui::{ensure_spending_allowed, RemotePolicy, SuiClient},
+};
+
+/// A limit given on the command line: a count, or `none` to lift it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit(pub Option<u64>);
+
+impl FromStr for Limit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" | "unlimited" => Ok(Limit(None)),
+            n => n
+                .parse()
+                .map(|n| Limit(Some(n)))
+                .map_err(|_| anyhow::anyhow!("invalid limit {:?} (expected a count or 'none')", n)),
+        }
+    }
+}
+
+/// Handle the `policy` subcommand
+/// Shows the remote's on-chain limits, or sets them (remote owner only)
+pub fn handle(remote: &Remote, max_refs: Option<Limit>, max_objects: Option<Limit>) -> Result<()> {
+    let object_id = match parse_remote_url(&remote.url)?.remote_type {
+        RemoteType::Sui(object_id) => object_id,
+        RemoteType::Filesystem(path) => anyhow::bail!(
+            "policy only applies to Walrus remotes, not filesystem remote {:?}",
+            path
+        ),
+    };
+
+    let config = WalrusRemoteConfig::load_for_remote(Some(&remote.name))
+        .context("Failed to load configuration")?;
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    runtime.block_on(async {
+        let sui_client = SuiClient::new(
+            object_id.clone(),
+            config.sui_wallet_path.clone(),
+            config.network_timeouts(),
+        )
+        .await?
+        .with_gas_reserve(config.gas_reserve_mist);
+
+        let current = sui_client.policy().await?;
+        if max_refs.is_none() && max_objects.is_none() {
+            println!("Policy for {}: {}", object_id, current);
+            return Ok(());
+        }
+
+        let policy = RemotePolicy {
+            max_refs: max_refs.map_or(current.max_refs, |Limit(n)| n),
+            max_objects: max_objects.map_or(current.max_objects, |Limit(n)| n),
+        };
+        ensure_spending_allowed(sui_client.network(), config.allow_mainnet)?;
+        sui_client
+            .set_policy(policy)
+            .await
+            .context("Failed to set policy (only the remote's owner can)")?;
+        println!("✓ Policy for {} set: {}", object_id, policy);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limit() {
+        assert_eq!("100".parse::<Limit>().unwrap(), Limit(Some(100)));
+        assert_eq!("none".parse::<Limit>().unwrap(), Limit(None));
+        assert!("-1".parse::<Limit>().is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{
+    commands::namespace::RefNamespace,
+    pack::{
+        checkout::{self, FileEntry},
+        objects::{MODE_EXECUTABLE, MODE_FILE},
+    },
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    storage::{MutableState, ParsedContentId},
+};
+
+/// A file left in place on Walrus instead of being written out (`--direct`)
+#[derive(Debug, Serialize)]
+struct DirectEntry {
+    path: String,
+    mode: String,
+    git_sha1: String,
+    /// Un-batched ContentId: the blob holds the loose object (`blob <size>\0<data>`)
+    content_id: String,
+}
+
+/// Handle the `publish-site` subcommand
+/// Writes the tree of `ref_name` into `out` so it can be uploaded as a Walrus Site
+pub fn handle(
+    remote: &Remote,
+    ref_name: &str,
+    out: &Path,
+    index: bool,
+    direct: bool,
+) -> Result<()> {
+    let remote_url = parse_remote_url(&remote.url)?;
+    if direct && matches!(remote_url.remote_type, RemoteType::Filesystem(_)) {
+        anyhow::bail!("--direct only applies to Walrus remotes");
+    }
+    let namespace = RefNamespace::resolve(remote_url.options.namespace.as_deref())?;
+
+    ensure_empty_dir(out)?;
+
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    let state = storage.read_state()?;
+    let (full_ref, commit) = resolve_ref(&state.refs, &namespace, ref_name)?;
+
+    let files = checkout::list_files(&storage, &state, commit)
+        .with_context(|| format!("Failed to read the tree of {}", full_ref))?;
+
+    // Regular files stored whole in their own blob can be served from Walrus as-is
+    let (manifest, to_write): (Vec<&FileEntry>, Vec<&FileEntry>) = files.iter().partition(|file| {
+        direct
+            && matches!(file.mode, MODE_FILE | MODE_EXECUTABLE)
+            && state.objects.get(&file.id).is_some_and(|content_id| {
+                matches!(
+                    ParsedContentId::parse(content_id),
+                    Ok(ParsedContentId::Legacy { .. })
+                )
+            })
+    });
+    let to_write: Vec<FileEntry> = to_write.into_iter().cloned().collect();
+    checkout::write_files(&storage, &state, &to_write, out)?;
+
+    if index {
+        if files
+            .iter()
+            .any(|file| file.path == Path::new("index.html"))
+        {
+            tracing::warn!("{} already has an index.html; not generating one", full_ref);
+        } else {
+            std::fs::write(out.join("index.html"), render_index(&full_ref, &files))
+                .context("Failed to write index.html")?;
+        }
+    }
+
+    tracing::info!(
+        "Wrote {} of {} files from {} ({}) to {}",
+        to_write.len(),
+        files.len(),
+        full_ref,
+        commit,
+        out.display()
+    );
+
+    if direct {
+        let entries: Vec<DirectEntry> = manifest
+            .into_iter()
+            .map(|file| DirectEntry {
+                path: file.path.to_string_lossy().into_owned(),
+                mode: format!("{:o}", file.mode),
+                git_sha1: file.id.clone(),
+                content_id: state.objects[&file.id].clone(),
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        println!(
+            "✓ Published {} files from {} to {}",
+            files.len(),
+            full_ref,
+            out.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Refuse to write into a directory that already has content
+fn ensure_empty_dir(out: &Path) -> Result<()> {
+    if out.exists() {
+        let mut entries = std::fs::read_dir(out)
+            .with_context(|| format!("Failed to read output directory {}", out.display()))?;
+        if entries.next().is_some() {
+            anyhow::bail!("Output directory {} is not empty", out.display());
+        }
+    }
+    std::fs::create_dir_all(out)
+        .with_context(|| format!("Failed to create output directory {}", out.display()))
+}
+
+/// Find `name` (a full ref, branch or tag name) among the refs visible in `namespace`
+pub(super) fn resolve_ref<'a>(
+    refs: &'a BTreeMap<String, String>,
+    namespace: &RefNamespace,
+    name: &str,
+) -> Result<(String, &'a str)> {
+    let candidates = if name.starts_with("refs/") {
+        vec![name.to_string()]
+    } else {
+        vec![
+            format!("refs/heads/{}", name),
+            format!("refs/tags/{}", name),
+        ]
+    };
+
+    candidates
+        .into_iter()
+        .find_map(|candidate| {
+            refs.get(&namespace.to_remote(&candidate))
+                .map(|sha| (candidate, sha.as_str()))
+        })
+        .with_context(|| format!("Ref {} not found on the remote", name))
+}
+
+/// A minimal index.html linking every file in the tree
+fn render_index(ref_name: &str, files: &[FileEntry]) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+         <body>\n<h1>{title}</h1>\n<ul>\n",
+        title = html_escape(ref_name)
+    );
+    for file in files {
+        let path: Vec<String> = file
+            .path
+            .iter()
+            .map(|component| component.to_string_lossy().into_owned())
+            .collect();
+        let href: Vec<String> = path.iter().map(|component| url_escape(component)).collect();
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            html_escape(&href.join("/")),
+            html_escape(&path.join("/"))
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Percent-encode a path component for use in a relative link
+fn url_escape(component: &str) -> String {
+    component
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_ref() {
+        let refs: BTreeMap<String, String> = [
+            ("refs/heads/main", "c1"),
+            ("refs/tags/v1", "t1"),
+            ("refs/namespaces/site/refs/heads/main", "c2"),
+        ]
+        .into_iter()
+        .map(|(name, sha)| (name.to_string(), sha.to_string()))
+        .collect();
+
+        let root = RefNamespace::default();
+        assert_eq!(
+            resolve_ref(&refs, &root, "main").unwrap(),
+            ("refs/heads/main".to_string(), "c1")
+        );
+        assert_eq!(
+            resolve_ref(&refs, &root, "v1").unwrap(),
+            ("refs/tags/v1".to_string(), "t1")
+        );
+        assert!(resolve_ref(&refs, &root, "refs/heads/missing").is_err());
+
+        let site = RefNamespace::new(Some("site"));
+        assert_eq!(
+            resolve_ref(&refs, &site, "refs/heads/main").unwrap(),
+            ("refs/heads/main".to_string(), "c2")
+        );
+    }
+
+    #[test]
+    fn test_render_index_escapes_names() {
+        let files = vec![FileEntry {
+            path: PathBuf::from("docs/a b&<c>.html"),
+            mode: MODE_FILE,
+            id: "0".repeat(40),
+        }];
+        let html = render_index("refs/heads/main", &files);
+        assert!(
+            html.contains("<a href=\"docs/a%20b%26%3Cc%3E.html\">docs/a b&amp;&lt;c&gt;.html</a>"),
+            "{}",
+            html
+        );
+    }
+}
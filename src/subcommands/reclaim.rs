@@ -0,0 +1,45 @@
+use anyhow::{Context, Result};
+
+use crate::{
+    config::WalrusRemoteConfig,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    storage::{StorageBackend, WalrusStorage},
+};
+
+/// Handle the `reclaim` subcommand
+/// Deletes deletable blobs that the remote's current objects map no longer references
+pub fn handle(remote: &Remote) -> Result<()> {
+    let remote_url = parse_remote_url(&remote.url)?;
+    let object_id = match remote_url.remote_type {
+        RemoteType::Sui(object_id) => object_id,
+        RemoteType::Filesystem(path) => anyhow::bail!(
+            "reclaim only applies to Walrus remotes, not filesystem remote {:?}",
+            path
+        ),
+    };
+
+    let mut config = WalrusRemoteConfig::load_for_remote(Some(&remote.name))
+        .context("Failed to load configuration")?;
+    remote_url.options.apply(&mut config);
+
+    let storage = WalrusStorage::new(object_id, remote.name.clone(), config)?;
+    storage.initialize()?;
+
+    let report = storage.reclaim()?;
+
+    if report.deleted.is_empty() {
+        println!("Nothing to reclaim: all deletable blobs are still referenced.");
+    } else {
+        for object_id in &report.deleted {
+            println!("  deleted {}", object_id);
+        }
+        println!(
+            "✓ Reclaimed {} blob(s) ({} bytes)",
+            report.deleted.len(),
+            report.bytes
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    commands::namespace::RefNamespace,
+    remote::parse_remote_url,
+    remote_resolution::Remote,
+    storage::{MutableState, RepoMetadata},
+    sui::RefUpdate,
+    Storage,
+};
+
+/// A ref in the `refs` subcommand's JSON output
+#[derive(Debug, Serialize)]
+struct RefEntry {
+    name: String,
+    sha: String,
+    #[serde(flatten)]
+    update: Option<RefUpdate>,
+    /// Signer of the recorded push that set the ref, if it was signed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signed_by: Option<String>,
+}
+
+/// Handle the `refs` subcommand
+/// Lists the remote's refs; with `verbose`, also who last updated each one, when, and who
+/// signed the push
+pub fn handle(remote: &Remote, verbose: bool, json: bool) -> Result<()> {
+    let namespace =
+        RefNamespace::resolve(parse_remote_url(&remote.url)?.options.namespace.as_deref())?;
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    let state = storage.read_state()?;
+    let refs: Vec<(&str, &String)> = namespace.local_refs(&state.refs).collect();
+
+    let mut updates = BTreeMap::new();
+    let mut metadata = RepoMetadata::default();
+    if verbose {
+        metadata = RepoMetadata::load(&storage, &state)?;
+        match &storage {
+            Storage::Walrus(walrus) => {
+                let remote_refs = refs
+                    .iter()
+                    .map(|(name, sha)| (namespace.to_remote(name), (*sha).clone()))
+                    .collect();
+                updates = walrus.ref_updates(&remote_refs)?;
+            }
+            Storage::Filesystem(_) => {
+                tracing::warn!("Ref history is only recorded on Walrus remotes")
+            }
+        }
+    }
+
+    let entries: Vec<RefEntry> = refs
+        .into_iter()
+        .map(|(name, sha)| {
+            let remote_name = namespace.to_remote(name);
+            RefEntry {
+                name: name.to_string(),
+                sha: sha.clone(),
+                signed_by: metadata
+                    .push_of(&remote_name, sha)
+                    .and_then(|push| push.signer.clone()),
+                update: updates.remove(&remote_name),
+            }
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+    let now_ms = chrono::Utc::now().timestamp_millis().max(0) as u64;
+    for entry in &entries {
+        let signed = entry
+            .signed_by
+            .as_ref()
+            .map(|signer| format!(", signed by {}", signer))
+            .unwrap_or_default();
+        match (&entry.update, verbose) {
+            (Some(update), _) => println!(
+                "{} {} (updated by {}{})",
+                entry.name,
+                entry.sha,
+                describe_update(update, now_ms),
+                signed
+            ),
+            (None, true) => println!(
+                "{} {} (no update found in recent history{})",
+                entry.name, entry.sha, signed
+            ),
+            (None, false) => println!("{} {}", entry.name, entry.sha),
+        }
+    }
+    Ok(())
+}
+
+/// `0xdef1…89ab 2h ago`
+fn describe_update(update: &RefUpdate, now_ms: u64) -> String {
+    let address = &update.updated_by;
+    let mut text = if address.len() > 12 {
+        format!("{}…{}", &address[..6], &address[address.len() - 4..])
+    } else {
+        address.clone()
+    };
+    if let Some(timestamp_ms) = update.timestamp_ms {
+        text.push(' ');
+        text.push_str(&format_age(now_ms.saturating_sub(timestamp_ms) / 1000));
+    }
+    text
+}
+
+/// Coarse age: `just now`, `5m ago`, `2h ago` or `3d ago`
+fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_update() {
+        let update = RefUpdate {
+            updated_by: format!("0xdef1{}89ab", "0".repeat(56)),
+            timestamp_ms: Some(1_000_000),
+            digest: "digest".to_string(),
+        };
+        let two_hours_later = 1_000_000 + 2 * 3600 * 1000 + 59_000;
+        assert_eq!(
+            describe_update(&update, two_hours_later),
+            "0xdef1…89ab 2h ago"
+        );
+
+        let update = RefUpdate {
+            updated_by: "0x1".to_string(),
+            timestamp_ms: None,
+            ..update
+        };
+        assert_eq!(describe_update(&update, 0), "0x1");
+
+        assert_eq!(format_age(59), "just now");
+        assert_eq!(format_age(60), "1m ago");
+        assert_eq!(format_age(3 * 86400 + 5), "3d ago");
+    }
+
+    #[test]
+    fn test_json_entry_flattens_update() {
+        let entry = RefEntry {
+            name: "refs/heads/main".to_string(),
+            sha: "a".repeat(40),
+            update: Some(RefUpdate {
+                updated_by: "0xbob".to_string(),
+                timestamp_ms: Some(5),
+                digest: "digest".to_string(),
+            }),
+            signed_by: Some("Bob <bob@test.com> (0123)".to_string()),
+        };
+        assert_eq!(
+            serde_json::to_value(&entry).unwrap(),
+            serde_json::json!({
+                "name": "refs/heads/main",
+                "sha": "a".repeat(40),
+                "updated_by": "0xbob",
+                "timestamp_ms": 5,
+                "digest": "digest",
+                "signed_by": "Bob <bob@test.com> (0123)",
+            })
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::{Context, Result};
+
+use crate::{
+    config::WalrusRemoteConfig,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    storage::{StorageBackend, WalrusStorage},
+};
+
+/// Handle the `repack` subcommand
+/// Consolidates a remote's objects into as few maximum-size Walrus blobs as possible
+pub fn handle(remote: &Remote) -> Result<()> {
+    let remote_url = parse_remote_url(&remote.url)?;
+    let state_object_id = match remote_url.remote_type {
+        RemoteType::Sui(state_object_id) => state_object_id,
+        RemoteType::Filesystem(path) => anyhow::bail!(
+            "repack only applies to Walrus remotes, not filesystem remote {:?}",
+            path
+        ),
+    };
+
+    let mut config = WalrusRemoteConfig::load_for_remote(Some(&remote.name))
+        .context("Failed to load configuration")?;
+    remote_url.options.apply(&mut config);
+
+    let storage = WalrusStorage::new(state_object_id, remote.name.clone(), config)?;
+    storage.initialize()?;
+
+    let report = storage.consolidate_blobs()?;
+
+    if report.moved == 0 {
+        println!(
+            "Nothing to repack: the objects map reads from {} blob(s).",
+            report.blobs_before
+        );
+    } else {
+        println!(
+            "✓ Repacked {} object(s) into {} bytes: {} blob(s) before, {} after",
+            report.moved, report.bytes, report.blobs_before, report.blobs_after
+        );
+        println!("  The old blobs are no longer referenced and will expire (or run `reclaim`).");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,505 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+use super::watch::{RemoteProbe, StateProbe};
+use crate::{
+    commands::{list::is_advertised, namespace::RefNamespace},
+    config::WalrusRemoteConfig,
+    pack::send_pack_with_haves,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    storage::StorageBackend,
+    subprocess::CommandRunner,
+};
+
+/// Restarts allowed for a `git daemon` that keeps exiting before giving up
+const MAX_DAEMON_RESTARTS: u32 = 5;
+
+/// How long a freshly started `git daemon` must stay up to count as listening
+const DAEMON_STARTUP: Duration = Duration::from_millis(300);
+
+/// Refs changed by one mirror sync
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MirrorSync {
+    pub updated: usize,
+    pub deleted: usize,
+}
+
+/// Handle the `serve` subcommand
+/// Serves a remote read-only over the git daemon protocol from a local bare mirror, resyncing
+/// the mirror whenever the remote's state changes
+pub fn handle(
+    remote: &Remote,
+    listen: SocketAddr,
+    mirror: Option<PathBuf>,
+    interval: Duration,
+) -> Result<()> {
+    let mirror = match mirror {
+        Some(mirror) => mirror,
+        None => default_mirror(remote)?,
+    };
+    let runtime = tokio::runtime::Runtime::new()?;
+    let mut probe = runtime.block_on(RemoteProbe::open(remote))?;
+
+    // Fingerprint before syncing, so a push during the first sync is picked up by the next poll
+    let mut last = runtime.block_on(probe.fingerprint())?;
+    let synced = sync(remote, &mirror)?;
+    tracing::info!(
+        "Mirrored {} ref(s) into {}",
+        synced.updated,
+        mirror.display()
+    );
+
+    let mut daemon = Daemon::start(listen, &mirror)?;
+    println!(
+        "Serving {} read-only at {} (Ctrl-C to stop)",
+        remote.url,
+        daemon.url()
+    );
+
+    let mut shutdown = std::pin::pin!(async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+        tracing::info!("Interrupted, stopping serve");
+    });
+    loop {
+        let stopped = runtime.block_on(async {
+            tokio::select! {
+                biased;
+                _ = shutdown.as_mut() => true,
+                _ = tokio::time::sleep(interval) => false,
+            }
+        });
+        if stopped {
+            break;
+        }
+
+        daemon.keep_alive()?;
+        match runtime.block_on(probe.fingerprint()) {
+            Ok(current) if current != last => match sync(remote, &mirror) {
+                Ok(synced) => {
+                    tracing::info!(
+                        "Remote changed; mirror updated {} and deleted {} ref(s)",
+                        synced.updated,
+                        synced.deleted
+                    );
+                    last = current;
+                }
+                Err(e) => tracing::warn!("Failed to sync mirror (retrying): {:#}", e),
+            },
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to poll remote: {:#}", e),
+        }
+    }
+
+    daemon.stop();
+    Ok(())
+}
+
+/// `mirrors/<object ID>.git` under the cache directory for Walrus remotes
+fn default_mirror(remote: &Remote) -> Result<PathBuf> {
+    match parse_remote_url(&remote.url)?.remote_type {
+        RemoteType::Sui(object_id) => {
+            let config = WalrusRemoteConfig::load_for_remote(Some(&remote.name))
+                .context("Failed to load configuration")?;
+            Ok(config
+                .cache_dir
+                .join("mirrors")
+                .join(format!("{}.git", object_id)))
+        }
+        RemoteType::Filesystem(path) => anyhow::bail!(
+            "filesystem remote {:?} has no cache directory; pass --mirror <dir>",
+            path
+        ),
+    }
+}
+
+/// Open the remote afresh (so its state isn't cached) and sync `mirror` with it
+fn sync(remote: &Remote, mirror: &Path) -> Result<MirrorSync> {
+    let options = crate::session_options(&remote.name, &remote.url)?;
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    sync_mirror(
+        &storage,
+        &options.namespace,
+        &options.advertise_ref_patterns,
+        mirror,
+    )
+}
+
+/// Bring the bare repository `mirror` up to date with the refs a clone of the remote would see
+///
+/// Only refs whose tips the mirror lacks are packed, using the same pack as a fetch without
+/// what the mirror's refs already reach; refs the remote no longer advertises are deleted, and
+/// HEAD follows the remote's.
+pub fn sync_mirror(
+    storage: &impl StorageBackend,
+    namespace: &RefNamespace,
+    patterns: &[String],
+    mirror: &Path,
+) -> Result<MirrorSync> {
+    if !mirror.join("HEAD").exists() {
+        std::fs::create_dir_all(mirror)
+            .with_context(|| format!("Failed to create mirror {}", mirror.display()))?;
+        CommandRunner::git_scratch(mirror)
+            .args(["init", "--bare", "--quiet"])
+            .run()
+            .context("Failed to initialize mirror")?;
+    }
+    // git daemon only serves repositories that opt in
+    std::fs::write(mirror.join("git-daemon-export-ok"), "")
+        .context("Failed to mark mirror as exported")?;
+
+    let state = storage.read_state()?;
+    let wanted: BTreeMap<&str, &String> = namespace
+        .local_refs(&state.refs)
+        .filter(|(refname, _)| is_advertised(patterns, refname))
+        .collect();
+    let current = mirror_refs(mirror)?;
+
+    let changed: Vec<(&str, &String)> = wanted
+        .iter()
+        .filter(|(refname, sha)| current.get(**refname) != Some(**sha))
+        .map(|(refname, sha)| (*refname, *sha))
+        .collect();
+    let missing = missing_objects(mirror, changed.iter().map(|(_, sha)| sha.as_str()))?;
+    let to_pack: Vec<String> = changed
+        .iter()
+        .filter(|(_, sha)| missing.contains(sha.as_str()))
+        .map(|(refname, _)| namespace.to_remote(refname))
+        .collect();
+    if !to_pack.is_empty() {
+        let haves = mirror_objects(mirror, current.values())?;
+        let mut packfile = Vec::new();
+        send_pack_with_haves(&to_pack, &haves, storage, &mut packfile)?;
+        CommandRunner::git_scratch(mirror)
+            .args(["index-pack", "--stdin", "--fix-thin"])
+            .stdin(packfile)
+            .run()
+            .context("Failed to index pack into mirror")?;
+    }
+
+    let deleted: Vec<&String> = current
+        .keys()
+        .filter(|refname| !wanted.contains_key(refname.as_str()))
+        .collect();
+    let mut commands = String::new();
+    for (refname, sha) in &changed {
+        commands.push_str(&format!("update {} {}\n", refname, sha));
+    }
+    for refname in &deleted {
+        commands.push_str(&format!("delete {}\n", refname));
+    }
+    if !commands.is_empty() {
+        CommandRunner::git_scratch(mirror)
+            .args(["update-ref", "--stdin"])
+            .stdin(commands)
+            .run()
+            .context("Failed to update mirror refs")?;
+    }
+
+    // HEAD as `list` advertises it: the stored HEAD, else main, else the first ref
+    let head = namespace
+        .local_symrefs(&state.symrefs)
+        .find(|(name, target)| *name == "HEAD" && wanted.contains_key(target))
+        .map(|(_, target)| target)
+        .or_else(|| {
+            wanted
+                .contains_key("refs/heads/main")
+                .then_some("refs/heads/main")
+        })
+        .or_else(|| wanted.keys().next().copied());
+    if let Some(head) = head {
+        CommandRunner::git_scratch(mirror)
+            .args(["symbolic-ref", "HEAD", head])
+            .run()
+            .context("Failed to set mirror HEAD")?;
+    }
+
+    Ok(MirrorSync {
+        updated: changed.len(),
+        deleted: deleted.len(),
+    })
+}
+
+/// Refs in the mirror and the objects they point at
+fn mirror_refs(mirror: &Path) -> Result<BTreeMap<String, String>> {
+    let output = CommandRunner::git_scratch(mirror)
+        .args(["for-each-ref", "--format=%(objectname) %(refname)"])
+        .run()
+        .context("Failed to list mirror refs")?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(sha, refname)| (refname.to_string(), sha.to_string()))
+        .collect())
+}
+
+/// Every object reachable from `tips` in the mirror, which a sync needn't send again
+fn mirror_objects<'a>(
+    mirror: &Path,
+    tips: impl Iterator<Item = &'a String>,
+) -> Result<HashSet<String>> {
+    let input: String = tips.map(|sha| format!("{}\n", sha)).collect();
+    if input.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let output = CommandRunner::git_scratch(mirror)
+        .args(["rev-list", "--objects", "--stdin"])
+        .stdin(input)
+        .run()
+        .context("Failed to list mirror objects")?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split(' ').next())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Which of `shas` the mirror doesn't have
+fn missing_objects<'a>(
+    mirror: &Path,
+    shas: impl Iterator<Item = &'a str>,
+) -> Result<BTreeSet<String>> {
+    let input: String = shas.map(|sha| format!("{}\n", sha)).collect();
+    if input.is_empty() {
+        return Ok(BTreeSet::new());
+    }
+    let output = CommandRunner::git_scratch(mirror)
+        .args(["cat-file", "--batch-check"])
+        .stdin(input)
+        .run()
+        .context("Failed to check mirror objects")?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.strip_suffix(" missing"))
+        .map(str::to_string)
+        .collect())
+}
+
+/// A `git daemon` serving only the mirror, restarted if it exits
+struct Daemon {
+    listen: SocketAddr,
+    mirror: PathBuf,
+    child: Child,
+    restarts: u32,
+}
+
+impl Daemon {
+    fn start(listen: SocketAddr, mirror: &Path) -> Result<Self> {
+        let mirror = mirror
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve mirror {}", mirror.display()))?;
+        let child = Self::spawn(listen, &mirror)?;
+        Ok(Self {
+            listen,
+            mirror,
+            child,
+            restarts: 0,
+        })
+    }
+
+    /// Start `git daemon` and check it stays up (it exits at once if the port is taken)
+    fn spawn(listen: SocketAddr, mirror: &Path) -> Result<Child> {
+        let base = mirror.parent().context("Mirror has no parent directory")?;
+        // Run git-daemon itself rather than the `git daemon` wrapper, so killing the child
+        // stops the daemon. Only upload-pack is enabled by default: clients can't push.
+        let exec_path = CommandRunner::git()
+            .arg("--exec-path")
+            .run()
+            .context("Failed to find git's exec path")?;
+        let exec_path = PathBuf::from(String::from_utf8_lossy(&exec_path.stdout).trim());
+        let mut child = Command::new(exec_path.join("git-daemon"))
+            .arg("--reuseaddr")
+            .arg(format!("--listen={}", listen.ip()))
+            .arg(format!("--port={}", listen.port()))
+            .arg(format!("--base-path={}", base.display()))
+            .arg(mirror)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .context("Failed to start git daemon")?;
+        std::thread::sleep(DAEMON_STARTUP);
+        if let Some(status) = child.try_wait()? {
+            anyhow::bail!(
+                "git daemon exited at startup ({}); is {} in use?",
+                status,
+                listen
+            );
+        }
+        Ok(child)
+    }
+
+    /// Clone URL of the mirror
+    fn url(&self) -> String {
+        let name = self.mirror.file_name().unwrap_or_default();
+        format!("git://{}/{}", self.listen, name.to_string_lossy())
+    }
+
+    /// Restart the daemon if it has exited, giving up after `MAX_DAEMON_RESTARTS`
+    fn keep_alive(&mut self) -> Result<()> {
+        let Some(status) = self.child.try_wait()? else {
+            return Ok(());
+        };
+        if self.restarts >= MAX_DAEMON_RESTARTS {
+            anyhow::bail!(
+                "git daemon exited ({}) after {} restarts; giving up",
+                status,
+                self.restarts
+            );
+        }
+        self.restarts += 1;
+        tracing::warn!("git daemon exited ({}); restarting", status);
+        self.child = Self::spawn(self.listen, &self.mirror)?;
+        Ok(())
+    }
+
+    fn stop(mut self) {
+        if let Err(e) = self.child.kill() {
+            tracing::warn!("Failed to stop git daemon: {}", e);
+        }
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gix_object::Kind;
+
+    use super::*;
+    use crate::storage::{store_object_in, MemoryStorage, MutableState, State};
+
+    /// Store a commit with a one-file tree; returns its ID
+    fn commit(storage: &MemoryStorage, state: &mut State, message: &str) -> String {
+        let blob = store_object_in(storage, state, Kind::Blob, message);
+        let mut tree = b"100644 file\0".to_vec();
+        tree.extend(hex::decode(&blob).unwrap());
+        let tree = store_object_in(storage, state, Kind::Tree, tree);
+        store_object_in(
+            storage,
+            state,
+            Kind::Commit,
+            format!(
+                "tree {}\nauthor A <a@example.com> 0 +0000\n\
+                 committer A <a@example.com> 0 +0000\n\n{}\n",
+                tree, message
+            ),
+        )
+    }
+
+    fn rev_parse(mirror: &Path, rev: &str) -> Option<String> {
+        let output = CommandRunner::git_scratch(mirror)
+            .args(["rev-parse", "--verify", "--quiet", rev])
+            .output()
+            .unwrap();
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    #[test]
+    fn test_mirror_follows_the_remote() {
+        let storage = MemoryStorage::new();
+        let mut state = State::default();
+        let main = commit(&storage, &mut state, "main");
+        let dev = commit(&storage, &mut state, "dev");
+        state.refs.insert("refs/heads/main".into(), main.clone());
+        state.refs.insert("refs/heads/dev".into(), dev.clone());
+        storage.write_state(&state).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mirror = dir.path().join("remote.git");
+        let namespace = RefNamespace::default();
+        let synced = sync_mirror(&storage, &namespace, &[], &mirror).unwrap();
+        assert_eq!(
+            synced,
+            MirrorSync {
+                updated: 2,
+                deleted: 0
+            }
+        );
+        assert_eq!(rev_parse(&mirror, "HEAD"), Some(main.clone()));
+        assert_eq!(rev_parse(&mirror, "refs/heads/dev"), Some(dev));
+        assert!(mirror.join("git-daemon-export-ok").exists());
+
+        // Nothing changed
+        let synced = sync_mirror(&storage, &namespace, &[], &mirror).unwrap();
+        assert_eq!(synced, MirrorSync::default());
+
+        // A ref deleted on the remote goes from the mirror; hidden refs are never mirrored
+        state.refs.remove("refs/heads/dev");
+        let tag = commit(&storage, &mut state, "tag");
+        state.refs.insert("refs/tags/v1".into(), tag);
+        storage.write_state(&state).unwrap();
+        let patterns = vec!["refs/heads/*".to_string()];
+        let synced = sync_mirror(&storage, &namespace, &patterns, &mirror).unwrap();
+        assert_eq!(
+            synced,
+            MirrorSync {
+                updated: 0,
+                deleted: 1
+            }
+        );
+        assert_eq!(rev_parse(&mirror, "refs/heads/dev"), None);
+        assert_eq!(rev_parse(&mirror, "refs/tags/v1"), None);
+        assert_eq!(rev_parse(&mirror, "refs/heads/main"), Some(main));
+    }
+
+    #[test]
+    fn test_mirror_sync_sends_only_new_objects() {
+        let storage = MemoryStorage::new();
+        let mut state = State::default();
+        let main = commit(&storage, &mut state, "main");
+        state.refs.insert("refs/heads/main".into(), main.clone());
+        storage.write_state(&state).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mirror = dir.path().join("remote.git");
+        let namespace = RefNamespace::default();
+        let in_pack = || {
+            let output = CommandRunner::git_scratch(&mirror)
+                .args(["count-objects", "-v"])
+                .run()
+                .unwrap();
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .find_map(|line| line.strip_prefix("in-pack: "))
+                .unwrap()
+                .parse::<usize>()
+                .unwrap()
+        };
+        sync_mirror(&storage, &namespace, &[], &mirror).unwrap();
+        assert_eq!(in_pack(), 3);
+
+        // A child keeping main's tree: only the new commit is packed, not its history again
+        let tree = rev_parse(&mirror, "main^{tree}").unwrap();
+        let next = store_object_in(
+            &storage,
+            &mut state,
+            Kind::Commit,
+            format!(
+                "tree {}\nparent {}\nauthor A <a@example.com> 0 +0000\n\
+                 committer A <a@example.com> 0 +0000\n\nnext\n",
+                tree, main
+            ),
+        );
+        state.refs.insert("refs/heads/main".into(), next.clone());
+        storage.write_state(&state).unwrap();
+        sync_mirror(&storage, &namespace, &[], &mirror).unwrap();
+        assert_eq!(in_pack(), 4);
+        assert_eq!(rev_parse(&mirror, "refs/heads/main"), Some(next));
+        CommandRunner::git_scratch(&mirror)
+            .args(["fsck", "--no-dangling"])
+            .run()
+            .unwrap();
+    }
+}
@@ -0,0 +1,29 @@
+use anyhow::Result;
+
+use crate::{
+    remote_resolution::Remote,
+    storage::{MutableState, RepoMetadata},
+};
+
+/// Handle the `set-description` subcommand
+/// Rewrites the remote's metadata blob; the state update takes the remote's lock
+pub fn handle(remote: &Remote, description: String, default_branch: Option<String>) -> Result<()> {
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+
+    storage.update_state(|state| {
+        let mut metadata = RepoMetadata::load(&storage, state)?;
+        metadata.description = Some(description);
+        if let Some(branch) = default_branch {
+            metadata.default_branch = Some(if branch.starts_with("refs/") {
+                branch
+            } else {
+                format!("refs/heads/{}", branch)
+            });
+        }
+        metadata.store(&storage, state)
+    })?;
+
+    println!("✓ Description updated for {}", remote.url);
+
+    Ok(())
+}
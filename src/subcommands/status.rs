@@ -0,0 +1,201 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{
+    config::WalrusRemoteConfig,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    storage::{FilesystemStorage, MutableState, State, StorageBackend, WalrusStorage},
+    walrus::{ExpiryReport, EXIT_OK},
+};
+
+/// `status --json` output: the remote's refs and object count, then its blob expiry report
+#[derive(Serialize)]
+struct StatusJson<'a> {
+    refs: &'a BTreeMap<String, String>,
+    objects: usize,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    expiry: Option<&'a ExpiryReport>,
+}
+
+/// Handle the `status` subcommand
+/// Summarizes the remote's refs and objects and reports the expiry of every blob it
+/// references, returning the process exit code
+///
+/// Filesystem remotes have no blobs, so only the refs and objects are shown.
+pub fn handle(remote: &Remote, fail_if_expiring_within: Option<u64>, json: bool) -> Result<i32> {
+    let remote_url = parse_remote_url(&remote.url)?;
+    let object_id = match remote_url.remote_type {
+        RemoteType::Sui(object_id) => object_id,
+        RemoteType::Filesystem(path) => {
+            let storage = FilesystemStorage::new(path)?;
+            storage.initialize()?;
+            let state = storage.read_state()?;
+            print_status(&state, None, json)?;
+            return Ok(EXIT_OK);
+        }
+    };
+
+    let mut config = WalrusRemoteConfig::load_for_remote(Some(&remote.name))
+        .context("Failed to load configuration")?;
+    remote_url.options.apply(&mut config);
+
+    let storage = WalrusStorage::new(object_id, remote.name.clone(), config)?;
+    storage.initialize()?;
+
+    let state = storage.read_state()?;
+    let report = storage.expiry_report()?;
+
+    print_status(&state, Some(&report), json)?;
+    if !json {
+        println!();
+        print_report(&report, fail_if_expiring_within);
+    }
+
+    Ok(report.exit_code(fail_if_expiring_within))
+}
+
+fn print_status(state: &State, report: Option<&ExpiryReport>, json: bool) -> Result<()> {
+    if json {
+        let status = StatusJson {
+            refs: &state.refs,
+            objects: state.objects.len(),
+            expiry: report,
+        };
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    } else {
+        print!("{}", summary(state, report));
+    }
+    Ok(())
+}
+
+/// One `name  value...` line per fact, for scripts to pick apart by the first column
+fn summary(state: &State, report: Option<&ExpiryReport>) -> String {
+    let mut text = String::new();
+    let _ = writeln!(text, "{:<16}{}", "refs", state.refs.len());
+    for (name, git_sha1) in &state.refs {
+        let _ = writeln!(text, "{:<16}{} {}", "ref", git_sha1, name);
+    }
+    let _ = writeln!(text, "{:<16}{}", "objects", state.objects.len());
+
+    let Some(report) = report else {
+        return text;
+    };
+    let _ = writeln!(text, "{:<16}{}", "blobs", report.blobs.len());
+    let _ = writeln!(text, "{:<16}{}", "current_epoch", report.current_epoch);
+    match report.blobs.iter().min_by_key(|blob| blob.end_epoch) {
+        Some(blob) => {
+            let _ = writeln!(
+                text,
+                "{:<16}{} {}",
+                "earliest_expiry", blob.end_epoch, blob.epochs_remaining
+            );
+        }
+        None => {
+            let _ = writeln!(text, "{:<16}-", "earliest_expiry");
+        }
+    }
+    text
+}
+
+/// Human-readable summary, soonest expiry first
+fn print_report(report: &ExpiryReport, fail_if_expiring_within: Option<u64>) {
+    println!("Current Walrus epoch: {}", report.current_epoch);
+
+    if report.blobs.is_empty() && report.untracked.is_empty() {
+        println!("No blobs referenced.");
+        return;
+    }
+
+    for blob in &report.blobs {
+        let expiring = fail_if_expiring_within.is_some_and(|n| blob.epochs_remaining <= n);
+        println!(
+            "{} {}  end epoch {} ({} epoch(s) left{})",
+            if expiring { "⚠" } else { " " },
+            blob.object_id,
+            blob.end_epoch,
+            blob.epochs_remaining,
+            blob.expires_at
+                .as_deref()
+                .map(|at| format!(", ≈{}", at))
+                .unwrap_or_default()
+        );
+    }
+    for object_id in &report.untracked {
+        println!("? {}  expiry unknown", object_id);
+    }
+
+    if let Some(epochs) = fail_if_expiring_within {
+        let expiring = report.expiring_within(epochs).len();
+        if expiring > 0 {
+            println!(
+                "{} blob(s) expire within {} epoch(s); run `walrus extend` to keep them",
+                expiring, epochs
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::walrus::BlobExpiry;
+
+    fn blob(object_id: &str, end_epoch: u64) -> BlobExpiry {
+        BlobExpiry {
+            object_id: object_id.to_string(),
+            blob_id: format!("blob-{}", object_id),
+            end_epoch,
+            epochs_remaining: end_epoch - 40,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn test_summary_columns() {
+        let mut state = State::default();
+        state
+            .refs
+            .insert("refs/heads/main".to_string(), "a".repeat(40));
+        state
+            .refs
+            .insert("refs/tags/v1".to_string(), "b".repeat(40));
+        state
+            .objects
+            .insert("a".repeat(40), "0xabc:0:10".to_string());
+
+        // Filesystem remotes stop after the objects
+        assert_eq!(
+            summary(&state, None),
+            format!(
+                "refs            2\n\
+                 ref             {} refs/heads/main\n\
+                 ref             {} refs/tags/v1\n\
+                 objects         1\n",
+                "a".repeat(40),
+                "b".repeat(40)
+            )
+        );
+
+        let report = ExpiryReport {
+            current_epoch: 40,
+            epoch_duration_secs: None,
+            blobs: vec![blob("0x2", 52), blob("0x1", 45)],
+            untracked: Vec::new(),
+        };
+        let text = summary(&state, Some(&report));
+        assert!(text.ends_with(
+            "objects         1\n\
+             blobs           2\n\
+             current_epoch   40\n\
+             earliest_expiry 45 5\n"
+        ));
+        let empty = ExpiryReport {
+            blobs: Vec::new(),
+            ..report
+        };
+        assert!(summary(&state, Some(&empty)).ends_with("earliest_expiry -\n"));
+    }
+}
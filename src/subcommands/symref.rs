@@ -0,0 +1,52 @@
+use anyhow::{Context, Result};
+
+use super::publish_site::resolve_ref;
+use crate::{
+    commands::namespace::{check_symref_name, RefNamespace},
+    remote::parse_remote_url,
+    remote_resolution::Remote,
+    storage::MutableState,
+};
+
+/// Handle the `symref` subcommand
+/// Prints where symref `name` points, points it at `target`, or deletes it
+pub fn handle(remote: &Remote, name: &str, target: Option<&str>, delete: bool) -> Result<()> {
+    check_symref_name(name)?;
+    let namespace =
+        RefNamespace::resolve(parse_remote_url(&remote.url)?.options.namespace.as_deref())?;
+    let storage = crate::open_storage(&remote.name, &remote.url)?;
+    let state = storage.read_state()?;
+    let key = namespace.to_remote(name);
+
+    match target {
+        Some(target) => {
+            if state.refs.contains_key(&key) {
+                anyhow::bail!("{} is a regular ref on the remote", name);
+            }
+            let (target, _) = resolve_ref(&state.refs, &namespace, target)?;
+            storage.update_state(|state| {
+                state.symrefs.insert(key, namespace.to_remote(&target));
+                Ok(())
+            })?;
+            println!("✓ {} now points at {}", name, target);
+        }
+        None => {
+            let target = state
+                .symrefs
+                .get(&key)
+                .and_then(|target| namespace.to_local(target))
+                .with_context(|| format!("{} is not a symref on the remote", name))?;
+            if delete {
+                storage.update_state(|state| {
+                    state.symrefs.remove(&key);
+                    Ok(())
+                })?;
+                println!("✓ Deleted symref {} (was {})", name, target);
+            } else {
+                println!("{}", target);
+            }
+        }
+    }
+
+    Ok(())
+}
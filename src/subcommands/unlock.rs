@@ -0,0 +1,80 @@
+use anyhow::{Context, Result};
+
+use crate::{
+    config::WalrusRemoteConfig,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    sui::{ensure_spending_allowed, SuiClient},
+};
+
+/// Lock timeout when taking an expired lock only to release it
+const BREAK_LOCK_TIMEOUT_MS: u64 = 60_000;
+
+/// Handle the `unlock` subcommand
+/// Reports whether a push holds the remote's lock and, with `force`, releases it
+///
+/// Only the holder may release a lock before it expires; anyone who may push can break an
+/// expired one by taking it and releasing it.
+pub fn handle(remote: &Remote, force: bool) -> Result<()> {
+    let remote_url = parse_remote_url(&remote.url)?;
+    let object_id = match remote_url.remote_type {
+        RemoteType::Sui(object_id) => object_id,
+        RemoteType::Filesystem(path) => anyhow::bail!(
+            "unlock only applies to Walrus remotes, not filesystem remote {:?}",
+            path
+        ),
+    };
+
+    let mut config = WalrusRemoteConfig::load_for_remote(Some(&remote.name))
+        .context("Failed to load configuration")?;
+    remote_url.options.apply(&mut config);
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    runtime.block_on(async {
+        let sui_client = SuiClient::new(
+            object_id.clone(),
+            config.sui_wallet_path.clone(),
+            config.network_timeouts(),
+        )
+        .await?
+        .with_gas_reserve(config.gas_reserve_mist)
+        .with_clock_object_id(&config.clock_object_id)?;
+
+        let Some(lock) = sui_client.lock_info().await? else {
+            println!("Remote {} is not locked.", object_id);
+            return Ok(());
+        };
+        let now_ms = sui_client.clock_timestamp_ms().await?;
+        println!("Remote {} is locked: {}", object_id, lock.describe(now_ms));
+        if !force {
+            println!("Run with --force to release it.");
+            return Ok(());
+        }
+
+        ensure_spending_allowed(sui_client.network(), config.allow_mainnet)?;
+        if lock.held_by(&sui_client.sender().to_string()) {
+            sui_client
+                .release_lock()
+                .await
+                .context("Failed to release lock")?;
+        } else if lock.is_expired(now_ms) {
+            sui_client
+                .acquire_lock(BREAK_LOCK_TIMEOUT_MS)
+                .await
+                .context("Failed to take the expired lock")?;
+            sui_client
+                .release_lock()
+                .await
+                .context("Failed to release lock")?;
+        } else {
+            anyhow::bail!(
+                "only {} can release the lock before it expires; wait {}s and run unlock \
+                 --force again",
+                lock.holder,
+                (lock.expires_ms - now_ms).div_ceil(1000)
+            );
+        }
+        println!("✓ Released the lock on {}", object_id);
+        Ok(())
+    })
+}
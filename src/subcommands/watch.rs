@@ -0,0 +1,382 @@
+use std::{future::Future, path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::WalrusRemoteConfig,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    subprocess::CommandRunner,
+    sui::SuiClient,
+};
+
+/// Longest delay between polls while the RPC keeps failing
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Cheap change detector for a remote's refs and objects map
+pub trait StateProbe {
+    /// Opaque token that changes whenever the remote's state changes
+    fn fingerprint(&mut self) -> impl Future<Output = Result<String>>;
+}
+
+/// Polls the RemoteState object's version and digest (one RPC per poll)
+pub struct SuiProbe {
+    client: SuiClient,
+}
+
+impl StateProbe for SuiProbe {
+    async fn fingerprint(&mut self) -> Result<String> {
+        self.client.state_fingerprint().await
+    }
+}
+
+/// Hashes a filesystem remote's state.yaml
+pub struct FilesystemProbe {
+    state_path: PathBuf,
+}
+
+impl StateProbe for FilesystemProbe {
+    async fn fingerprint(&mut self) -> Result<String> {
+        let content = match std::fs::read(&self.state_path) {
+            Ok(content) => content,
+            // Not pushed to yet
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read {}", self.state_path.display()))
+            }
+        };
+        Ok(hex::encode(Sha256::digest(&content)))
+    }
+}
+
+/// The probe suited to a remote URL
+pub enum RemoteProbe {
+    Sui(Box<SuiProbe>),
+    Filesystem(FilesystemProbe),
+}
+
+impl RemoteProbe {
+    /// Connect to the remote's RemoteState object, or find its state.yaml
+    pub async fn open(remote: &Remote) -> Result<Self> {
+        match parse_remote_url(&remote.url)?.remote_type {
+            RemoteType::Sui(object_id) => {
+                let config = WalrusRemoteConfig::load_for_remote(Some(&remote.name))
+                    .context("Failed to load configuration")?;
+                let client = SuiClient::new(
+                    object_id,
+                    config.sui_wallet_path.clone(),
+                    config.network_timeouts(),
+                )
+                .await?;
+                Ok(RemoteProbe::Sui(Box::new(SuiProbe { client })))
+            }
+            RemoteType::Filesystem(path) => Ok(RemoteProbe::Filesystem(FilesystemProbe {
+                state_path: path.join("state.yaml"),
+            })),
+        }
+    }
+}
+
+impl StateProbe for RemoteProbe {
+    async fn fingerprint(&mut self) -> Result<String> {
+        match self {
+            RemoteProbe::Sui(probe) => probe.fingerprint().await,
+            RemoteProbe::Filesystem(probe) => probe.fingerprint().await,
+        }
+    }
+}
+
+/// Emitted when a remote's state changes between two polls
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangeEvent {
+    pub event: &'static str,
+    pub remote: String,
+    pub previous: String,
+    pub current: String,
+    pub detected_at: String,
+}
+
+/// Handle the `watch` subcommand
+/// Polls a remote until interrupted, reporting each change as JSON or by running `exec`
+pub fn handle(remote: &Remote, interval: Duration, exec: Option<String>) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    runtime.block_on(async {
+        let on_change = |event: &ChangeEvent| match &exec {
+            Some(command) => run_exec(command, event),
+            None => {
+                println!("{}", serde_json::to_string(event)?);
+                Ok(())
+            }
+        };
+        let shutdown = async {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                tracing::warn!("Failed to listen for SIGINT: {}", e);
+                std::future::pending::<()>().await;
+            }
+            tracing::info!("Interrupted, stopping watch");
+        };
+
+        let mut probe = RemoteProbe::open(remote).await?;
+        watch_loop(&mut probe, &remote.url, interval, on_change, shutdown).await
+    })
+}
+
+/// Poll `probe` every `interval` until `shutdown` completes, calling `on_change` for each change
+///
+/// The first successful poll only records a baseline. Probe errors back off exponentially
+/// (capped at `MAX_BACKOFF`); `on_change` errors are logged and watching continues.
+pub async fn watch_loop<P, F>(
+    probe: &mut P,
+    remote: &str,
+    interval: Duration,
+    mut on_change: F,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()>
+where
+    P: StateProbe,
+    F: FnMut(&ChangeEvent) -> Result<()>,
+{
+    tokio::pin!(shutdown);
+
+    let mut last: Option<String> = None;
+    let mut delay = Duration::ZERO;
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => return Ok(()),
+            _ = tokio::time::sleep(delay) => {}
+        }
+
+        match probe.fingerprint().await {
+            Ok(current) => {
+                delay = interval;
+                match last.replace(current.clone()) {
+                    None => tracing::info!(remote, fingerprint = %current, "watching remote"),
+                    Some(previous) if previous != current => {
+                        let event = ChangeEvent {
+                            event: "changed",
+                            remote: remote.to_string(),
+                            previous,
+                            current,
+                            detected_at: chrono::Utc::now().to_rfc3339(),
+                        };
+                        if let Err(e) = on_change(&event) {
+                            tracing::warn!("Change handler failed: {:#}", e);
+                        }
+                    }
+                    Some(_) => {}
+                }
+            }
+            Err(e) => {
+                delay = next_backoff(delay, interval);
+                tracing::warn!("Failed to poll remote (retrying in {:?}): {:#}", delay, e);
+            }
+        }
+    }
+}
+
+/// Delay before the next poll after a failed one
+fn next_backoff(delay: Duration, interval: Duration) -> Duration {
+    (delay * 2).max(interval).min(MAX_BACKOFF)
+}
+
+/// Run `command` through the shell with the event in its environment
+fn run_exec(command: &str, event: &ChangeEvent) -> Result<()> {
+    let output = CommandRunner::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("WALRUS_WATCH_REMOTE", &event.remote)
+        .env("WALRUS_WATCH_FINGERPRINT", &event.current)
+        .run()?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.trim().is_empty() {
+        tracing::info!("{}", stdout.trim());
+    }
+    Ok(())
+}
+
+/// Parse a poll interval such as `30s`, `5m`, `1h`, `500ms` or bare seconds
+pub fn parse_interval(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: u64 = number
+        .parse()
+        .with_context(|| format!("invalid interval {:?}", s))?;
+
+    let interval = match unit {
+        "ms" => Duration::from_millis(value),
+        "" | "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        "h" => Duration::from_secs(value * 3600),
+        other => anyhow::bail!("invalid interval unit {:?} (expected ms, s, m or h)", other),
+    };
+
+    if interval.is_zero() {
+        anyhow::bail!("interval must be greater than zero");
+    }
+    Ok(interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    /// Scripted RPC responses; signals shutdown once the script runs out
+    struct MockProbe {
+        responses: VecDeque<Result<String>>,
+        done: Option<oneshot::Sender<()>>,
+    }
+
+    impl MockProbe {
+        fn new(responses: Vec<Result<String>>) -> (Self, oneshot::Receiver<()>) {
+            let (tx, rx) = oneshot::channel();
+            let probe = MockProbe {
+                responses: responses.into(),
+                done: Some(tx),
+            };
+            (probe, rx)
+        }
+    }
+
+    impl StateProbe for MockProbe {
+        async fn fingerprint(&mut self) -> Result<String> {
+            let response = self
+                .responses
+                .pop_front()
+                .unwrap_or_else(|| Err(anyhow::anyhow!("script exhausted")));
+            if self.responses.is_empty() {
+                if let Some(done) = self.done.take() {
+                    let _ = done.send(());
+                }
+            }
+            response
+        }
+    }
+
+    async fn run(responses: Vec<Result<String>>) -> Vec<ChangeEvent> {
+        let (mut probe, done) = MockProbe::new(responses);
+        let mut events = Vec::new();
+        watch_loop(
+            &mut probe,
+            "walrus::0x1",
+            Duration::ZERO,
+            |event| {
+                events.push(event.clone());
+                Ok(())
+            },
+            async {
+                let _ = done.await;
+            },
+        )
+        .await
+        .unwrap();
+        events
+    }
+
+    #[tokio::test]
+    async fn test_ref_change_emits_event() {
+        let events = run(vec![
+            Ok("5:a".to_string()),
+            Ok("5:a".to_string()),
+            Ok("6:b".to_string()),
+            Ok("6:b".to_string()),
+        ])
+        .await;
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "changed");
+        assert_eq!(events[0].remote, "walrus::0x1");
+        assert_eq!(events[0].previous, "5:a");
+        assert_eq!(events[0].current, "6:b");
+    }
+
+    #[tokio::test]
+    async fn test_rpc_errors_do_not_emit_or_reset_baseline() {
+        let events = run(vec![
+            Err(anyhow::anyhow!("connection refused")),
+            Ok("5:a".to_string()),
+            Err(anyhow::anyhow!("timeout")),
+            Ok("5:a".to_string()),
+            Ok("7:c".to_string()),
+        ])
+        .await;
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].previous, "5:a");
+        assert_eq!(events[0].current, "7:c");
+    }
+
+    #[tokio::test]
+    async fn test_filesystem_probe_detects_push() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut probe = FilesystemProbe {
+            state_path: dir.path().join("state.yaml"),
+        };
+
+        let empty = probe.fingerprint().await.unwrap();
+        std::fs::write(dir.path().join("state.yaml"), "refs: {}\n").unwrap();
+        let pushed = probe.fingerprint().await.unwrap();
+        assert_ne!(empty, pushed);
+        assert_eq!(probe.fingerprint().await.unwrap(), pushed);
+    }
+
+    #[test]
+    fn test_next_backoff() {
+        let interval = Duration::from_secs(30);
+        assert_eq!(next_backoff(interval, interval), Duration::from_secs(60));
+        assert_eq!(next_backoff(Duration::ZERO, interval), interval);
+        assert_eq!(
+            next_backoff(Duration::from_secs(200), interval),
+            MAX_BACKOFF
+        );
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_interval("45").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_interval("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_interval("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse_interval("250ms").unwrap(), Duration::from_millis(250));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("10d").is_err());
+        assert!(parse_interval("s").is_err());
+    }
+
+    #[test]
+    fn test_exec_receives_event_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let event = ChangeEvent {
+            event: "changed",
+            remote: "walrus::0x1".to_string(),
+            previous: "5:a".to_string(),
+            current: "6:b".to_string(),
+            detected_at: "now".to_string(),
+        };
+
+        run_exec(
+            &format!(
+                "echo \"$WALRUS_WATCH_REMOTE $WALRUS_WATCH_FINGERPRINT\" > {}",
+                out.display()
+            ),
+            &event,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap().trim(),
+            "walrus::0x1 6:b"
+        );
+        assert!(run_exec("exit 1", &event).is_err());
+    }
+}
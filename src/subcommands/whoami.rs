@@ -0,0 +1,211 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{
+    config::WalrusRemoteConfig,
+    remote::{parse_remote_url, RemoteType},
+    remote_resolution::Remote,
+    sui::{AccessRole, SuiClient},
+    walrus::{format_amount, FROST_PER_WAL, MIST_PER_SUI},
+};
+
+/// The identity pushes would use, as `whoami` reports it
+#[derive(Debug, Serialize)]
+struct Identity {
+    wallet: PathBuf,
+    env: Option<String>,
+    rpc_url: Option<String>,
+    address: String,
+    sui_mist: u128,
+    /// None when the WAL coin type is unknown for this network
+    wal_frost: Option<u128>,
+    wal_coin_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote: Option<RemoteRole>,
+}
+
+/// How the active address relates to the remote asked about
+#[derive(Debug, Serialize)]
+struct RemoteRole {
+    object_id: String,
+    role: AccessRole,
+}
+
+/// Handle the `whoami` subcommand
+/// Shows the wallet, environment, address and balances pushes would use, and with `remote`,
+/// whether that address may push to it
+pub fn handle(remote: Option<&Remote>, json: bool) -> Result<()> {
+    let mut config = WalrusRemoteConfig::load_for_remote(remote.map(|remote| remote.name.as_str()))
+        .context("Failed to load configuration")?;
+    let object_id = match remote {
+        Some(remote) => {
+            let remote_url = parse_remote_url(&remote.url)?;
+            remote_url.options.apply(&mut config);
+            match remote_url.remote_type {
+                RemoteType::Sui(object_id) => Some(object_id),
+                RemoteType::Filesystem(path) => anyhow::bail!(
+                    "whoami --remote only applies to Walrus remotes, not filesystem remote {:?}",
+                    path
+                ),
+            }
+        }
+        None => None,
+    };
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let identity = runtime.block_on(identity(&config, object_id))?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&identity)?);
+    } else {
+        print!("{}", render(&identity));
+    }
+    Ok(())
+}
+
+/// The identity `config`'s wallet pushes as, with its role on RemoteState `object_id` if given
+async fn identity(config: &WalrusRemoteConfig, object_id: Option<String>) -> Result<Identity> {
+    let wallet = config.sui_wallet_path.clone();
+    let sui_client = match &object_id {
+        Some(object_id) => {
+            SuiClient::new(object_id.clone(), wallet, config.network_timeouts()).await?
+        }
+        None => SuiClient::for_wallet(wallet, config.network_timeouts()).await?,
+    };
+    let address = sui_client.sender().to_string();
+
+    let wal_coin_type = config.wal_coin_type_on(sui_client.network());
+    let sui_mist = sui_client.balance(None).await?;
+    let wal_frost = match &wal_coin_type {
+        Some(coin_type) => Some(sui_client.balance(Some(coin_type.clone())).await?),
+        None => None,
+    };
+    let remote = match object_id {
+        Some(object_id) => Some(RemoteRole {
+            role: sui_client.access().await?.role(&address),
+            object_id,
+        }),
+        None => None,
+    };
+
+    Ok(Identity {
+        wallet: config.sui_wallet_path.clone(),
+        env: sui_client.env_alias().map(String::from),
+        rpc_url: sui_client.rpc_url().map(String::from),
+        address,
+        sui_mist,
+        wal_frost,
+        wal_coin_type,
+        remote,
+    })
+}
+
+fn render(identity: &Identity) -> String {
+    let unknown = "unknown";
+    let mut text = format!(
+        "Wallet:  {}\nEnv:     {} ({})\nAddress: {}\nSUI:     {}\n",
+        identity.wallet.display(),
+        identity.env.as_deref().unwrap_or(unknown),
+        identity.rpc_url.as_deref().unwrap_or(unknown),
+        identity.address,
+        format_amount(identity.sui_mist, MIST_PER_SUI)
+    );
+    match identity.wal_frost {
+        Some(frost) => text.push_str(&format!(
+            "WAL:     {}\n",
+            format_amount(frost, FROST_PER_WAL)
+        )),
+        None => text.push_str("WAL:     unknown (set wal_coin_type for this network)\n"),
+    }
+    if let Some(remote) = &identity.remote {
+        text.push_str(&format!(
+            "Remote:  {} ({})\n",
+            remote.object_id, remote.role
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sui::mock_rpc::MockSui;
+
+    #[test]
+    fn test_identity_classifies_the_remote_role() {
+        let dir = tempfile::tempdir().unwrap();
+        let walrus_dir = dir.path().join("walrus");
+        std::fs::create_dir_all(&walrus_dir).unwrap();
+        let sui = MockSui::start(&walrus_dir);
+        let (owner_wallet, owner) = sui.wallet(&dir.path().join("owner"));
+        let (allowed_wallet, allowed) = sui.wallet(&dir.path().join("allowed"));
+        let (stranger_wallet, stranger) = sui.wallet(&dir.path().join("stranger"));
+        let state = sui.create_remote(owner, false);
+        sui.share(state, owner, &[allowed]);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let whoami = |wallet: &PathBuf, object_id: Option<String>| {
+            let config: WalrusRemoteConfig = serde_yaml::from_str(&format!(
+                "sui_wallet_path: \"{}\"\ncache_dir: \"{}\"\n",
+                wallet.display(),
+                dir.path().join("cache").display()
+            ))
+            .unwrap();
+            runtime.block_on(identity(&config, object_id)).unwrap()
+        };
+        for (wallet, address, role) in [
+            (&owner_wallet, owner, AccessRole::Owner),
+            (&allowed_wallet, allowed, AccessRole::Allowlisted),
+            (&stranger_wallet, stranger, AccessRole::Unauthorized),
+        ] {
+            let identity = whoami(wallet, Some(state.to_string()));
+            assert_eq!(identity.address, address.to_string());
+            assert!(identity.sui_mist > 0);
+            let remote = identity.remote.unwrap();
+            assert_eq!(remote.object_id, state.to_string());
+            assert_eq!(remote.role, role, "{}", address);
+        }
+
+        // Without a remote, only the wallet is described
+        let identity = whoami(&stranger_wallet, None);
+        assert_eq!(identity.address, stranger.to_string());
+        assert_eq!(identity.rpc_url.as_deref(), Some(sui.url()));
+        assert!(identity.remote.is_none());
+    }
+
+    #[test]
+    fn test_render_identity() {
+        let mut identity = Identity {
+            wallet: PathBuf::from("/home/me/.sui/sui_config/client.yaml"),
+            env: Some("testnet".to_string()),
+            rpc_url: Some("https://fullnode.testnet.sui.io:443".to_string()),
+            address: "0xaaaa".to_string(),
+            sui_mist: 1_500_000_000,
+            wal_frost: Some(2_000_000_000),
+            wal_coin_type: Some("0x8270::wal::WAL".to_string()),
+            remote: Some(RemoteRole {
+                object_id: "0x1234".to_string(),
+                role: AccessRole::Unauthorized,
+            }),
+        };
+        assert_eq!(
+            render(&identity),
+            "Wallet:  /home/me/.sui/sui_config/client.yaml\n\
+             Env:     testnet (https://fullnode.testnet.sui.io:443)\n\
+             Address: 0xaaaa\n\
+             SUI:     1.5\n\
+             WAL:     2\n\
+             Remote:  0x1234 (not authorized to push)\n"
+        );
+
+        identity.wal_frost = None;
+        identity.remote = None;
+        let text = render(&identity);
+        assert!(text.ends_with("WAL:     unknown (set wal_coin_type for this network)\n"));
+        let json = serde_json::to_value(&identity).unwrap();
+        assert_eq!(json["wal_frost"], serde_json::Value::Null);
+        assert!(json.get("remote").is_none());
+    }
+}
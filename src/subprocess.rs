@@ -0,0 +1,547 @@
+//! Uniform runner for the git, walrus and sui subprocesses we spawn
+
+use std::{
+    ffi::{OsStr, OsString},
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use crate::session_env;
+
+/// Environment variables that point git at the user's repository.
+/// These must not leak into git commands that operate on scratch repositories.
+const GIT_REPO_ENV: &[&str] = &[
+    "GIT_DIR",
+    "GIT_WORK_TREE",
+    "GIT_COMMON_DIR",
+    "GIT_INDEX_FILE",
+    "GIT_OBJECT_DIRECTORY",
+    "GIT_ALTERNATE_OBJECT_DIRECTORIES",
+    "GIT_NAMESPACE",
+];
+
+/// Poll interval while waiting for a child with a timeout
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Builder for a subprocess invocation with captured output and uniform errors
+///
+/// Output is always captured, the locale is pinned to `C` so output parses the
+/// same everywhere, and failures carry the full command line and stderr.
+#[derive(Debug)]
+pub struct CommandRunner {
+    program: OsString,
+    args: Vec<OsString>,
+    envs: Vec<(OsString, OsString)>,
+    env_removes: Vec<OsString>,
+    current_dir: Option<PathBuf>,
+    stdin: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    max_stdout: Option<u64>,
+}
+
+/// How a wait for the child ended
+enum Waited {
+    Exited(ExitStatus),
+    TimedOut,
+    Idle,
+}
+
+impl CommandRunner {
+    /// Create a runner for `program`
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            envs: vec![("LC_ALL".into(), "C".into())],
+            env_removes: Vec::new(),
+            current_dir: None,
+            stdin: None,
+            timeout: None,
+            idle_timeout: None,
+            max_stdout: None,
+        }
+    }
+
+    /// git, operating on the user's repository (inherits `GIT_DIR` from git)
+    pub fn git() -> Self {
+        Self::new("git")
+    }
+
+    /// git, operating on a scratch repository at `git_dir`
+    pub fn git_scratch(git_dir: &Path) -> Self {
+        let mut runner = Self::git().arg("--git-dir").arg(git_dir);
+        runner
+            .env_removes
+            .extend(GIT_REPO_ENV.iter().map(OsString::from));
+        runner
+    }
+
+    /// Append an argument
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Append several arguments
+    pub fn args<I, A>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|a| a.as_ref().to_owned()));
+        self
+    }
+
+    /// Set an environment variable
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        self.envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Run in `dir` instead of the current directory
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Feed `data` to the child's stdin (stdin is closed otherwise)
+    pub fn stdin(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.stdin = Some(data.into());
+        self
+    }
+
+    /// Kill the child and fail if it runs longer than `timeout`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Kill the child and fail if it makes no progress for `idle`
+    ///
+    /// Unlike [`Self::timeout`], a child that keeps making progress may run as long as it
+    /// needs; only one that has stalled is killed. Output on stdout or stderr counts as
+    /// progress, and on Linux so does any CPU time or file I/O of the child, so a quiet
+    /// child that is still downloading or decoding is spared.
+    pub fn idle_timeout(mut self, idle: Duration) -> Self {
+        self.idle_timeout = Some(idle);
+        self
+    }
+
+    /// Fail if the child writes more than `bytes` to stdout
+    ///
+    /// Reading stops at the limit, so a runaway child can't exhaust memory; closing the pipe
+    /// makes it exit on its next write.
+    pub fn max_stdout(mut self, bytes: u64) -> Self {
+        self.max_stdout = Some(bytes);
+        self
+    }
+
+    /// Human-readable command line for logs and errors
+    pub fn describe(&self) -> String {
+        std::iter::once(&self.program)
+            .chain(&self.args)
+            .map(|a| a.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Run to completion and return the output, whatever the exit status
+    pub fn output(self) -> Result<Output> {
+        let description = self.describe();
+        tracing::debug!("running `{}`", description);
+
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args);
+        // A daemon's session runs in the helper's directory and environment, not the daemon's
+        if let Some(session) = session_env::active() {
+            cmd.current_dir(&session.cwd);
+            for (key, _) in std::env::vars_os() {
+                if key.to_str().is_some_and(session_env::is_session_var) {
+                    cmd.env_remove(key);
+                }
+            }
+            cmd.envs(&session.vars);
+        }
+        for key in &self.env_removes {
+            cmd.env_remove(key);
+        }
+        for (key, value) in &self.envs {
+            cmd.env(key, value);
+        }
+        if let Some(dir) = &self.current_dir {
+            // Relative to the session's directory, not the daemon's
+            cmd.current_dir(session_env::absolute(dir)?);
+        }
+        cmd.stdin(if self.stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+        let mut child = cmd
+            .spawn()
+            .with_context(|| format!("Failed to spawn `{}`", description))?;
+
+        // Feed stdin and drain stdout/stderr on threads so large payloads can't deadlock
+        let writer = match (child.stdin.take(), self.stdin) {
+            (Some(mut pipe), Some(data)) => Some(thread::spawn(move || pipe.write_all(&data))),
+            _ => None,
+        };
+        let progress = Arc::new(AtomicU64::new(0));
+        let stdout_reader = spawn_reader(child.stdout.take(), self.max_stdout, &progress);
+        let stderr_reader = spawn_reader(child.stderr.take(), None, &progress);
+
+        let waited = if self.timeout.is_some() || self.idle_timeout.is_some() {
+            wait_with_timeout(&mut child, self.timeout, self.idle_timeout, &progress)
+        } else {
+            child.wait().map(Waited::Exited)
+        }
+        .with_context(|| format!("Failed to wait for `{}`", description))?;
+        let status = match waited {
+            Waited::Exited(status) => status,
+            Waited::TimedOut => anyhow::bail!(
+                "`{}` timed out after {:?}",
+                description,
+                self.timeout.unwrap_or_default()
+            ),
+            Waited::Idle => anyhow::bail!(
+                "`{}` stalled: no progress for {:?} (idle timeout)",
+                description,
+                self.idle_timeout.unwrap_or_default()
+            ),
+        };
+
+        if let Some(writer) = writer {
+            match writer.join() {
+                Ok(Ok(())) => {}
+                // The child may legitimately exit without reading all of its input
+                Ok(Err(e)) if e.kind() == ErrorKind::BrokenPipe => {}
+                Ok(Err(e)) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to write stdin of `{}`", description))
+                }
+                Err(_) => anyhow::bail!("stdin writer for `{}` panicked", description),
+            }
+        }
+
+        let stdout = join_reader(stdout_reader);
+        if let Some(limit) = self.max_stdout {
+            if stdout.len() as u64 > limit {
+                anyhow::bail!(
+                    "`{}` wrote more than {} bytes of output",
+                    description,
+                    limit
+                );
+            }
+        }
+
+        Ok(Output {
+            status,
+            stdout,
+            stderr: join_reader(stderr_reader),
+        })
+    }
+
+    /// Run to completion and fail unless the child exits successfully
+    pub fn run(self) -> Result<Output> {
+        let description = self.describe();
+        let output = self.output()?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "`{}` failed ({}): {}",
+                description,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(output)
+    }
+}
+
+/// Read a child pipe to the end (or just past `limit` bytes) on a background thread,
+/// adding the bytes read to `progress` as they arrive
+fn spawn_reader<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    limit: Option<u64>,
+    progress: &Arc<AtomicU64>,
+) -> Option<JoinHandle<Vec<u8>>> {
+    let progress = Arc::clone(progress);
+    pipe.map(|pipe| {
+        thread::spawn(move || {
+            let mut pipe = pipe.take(limit.map_or(u64::MAX, |limit| limit + 1));
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 64 * 1024];
+            loop {
+                match pipe.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        buf.extend_from_slice(&chunk[..n]);
+                        progress.fetch_add(n as u64, Ordering::Relaxed);
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+            buf
+        })
+    })
+}
+
+/// Collect the bytes read by a reader thread
+fn join_reader(reader: Option<JoinHandle<Vec<u8>>>) -> Vec<u8> {
+    reader
+        .and_then(|handle| handle.join().ok())
+        .unwrap_or_default()
+}
+
+/// Work the child has done that its output doesn't show: CPU clock ticks plus bytes read
+/// and written, from `/proc/<pid>/stat` and `/proc/<pid>/io`
+///
+/// Both only ever grow while the child runs, so any change means it is still busy.
+#[cfg(target_os = "linux")]
+fn child_activity(child: &Child) -> u64 {
+    let cpu_ticks = std::fs::read_to_string(format!("/proc/{}/stat", child.id()))
+        .ok()
+        .and_then(|stat| {
+            // The command name in parentheses may contain spaces; utime and stime are
+            // fields 14 and 15, counting the pid as field 1
+            let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+            let utime: u64 = fields.get(11)?.parse().ok()?;
+            let stime: u64 = fields.get(12)?.parse().ok()?;
+            Some(utime + stime)
+        })
+        .unwrap_or(0);
+    let io_bytes: u64 = std::fs::read_to_string(format!("/proc/{}/io", child.id()))
+        .map(|io| {
+            io.lines()
+                .filter_map(|line| line.split_once(": "))
+                .filter(|(key, _)| matches!(*key, "rchar" | "wchar"))
+                .filter_map(|(_, value)| value.trim().parse::<u64>().ok())
+                .sum()
+        })
+        .unwrap_or(0);
+    cpu_ticks.wrapping_add(io_bytes)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn child_activity(_child: &Child) -> u64 {
+    0
+}
+
+/// Wait for the child, killing it if it outlives `timeout` or neither `progress` nor its own
+/// activity moves for `idle`
+fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    idle: Option<Duration>,
+    progress: &AtomicU64,
+) -> std::io::Result<Waited> {
+    let start = Instant::now();
+    let observe = |child: &Child| {
+        idle.map_or(0, |_| {
+            progress
+                .load(Ordering::Relaxed)
+                .wrapping_add(child_activity(child))
+        })
+    };
+    let mut last_progress = (observe(child), start);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Waited::Exited(status));
+        }
+        let now = Instant::now();
+        let seen = observe(child);
+        if seen != last_progress.0 {
+            last_progress = (seen, now);
+        }
+        let waited = if timeout.is_some_and(|timeout| now - start >= timeout) {
+            Waited::TimedOut
+        } else if idle.is_some_and(|idle| now - last_progress.1 >= idle) {
+            Waited::Idle
+        } else {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        };
+        let _ = child.kill();
+        let _ = child.wait();
+        return Ok(waited);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_captures_stdout_with_stdin() {
+        let output = CommandRunner::new("cat").stdin("hello").run().unwrap();
+        assert_eq!(output.stdout, b"hello");
+    }
+
+    #[test]
+    fn test_pins_locale() {
+        let output = CommandRunner::new("sh")
+            .args(["-c", "echo $LC_ALL"])
+            .run()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "C");
+    }
+
+    #[test]
+    fn test_failure_error_has_command_status_and_stderr() {
+        let err = CommandRunner::new("sh")
+            .args(["-c", "echo boom >&2; exit 3"])
+            .run()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("sh -c echo boom >&2; exit 3"), "{}", err);
+        assert!(err.contains("exit status: 3"), "{}", err);
+        assert!(err.contains("boom"), "{}", err);
+    }
+
+    #[test]
+    fn test_output_does_not_fail_on_nonzero_exit() {
+        let output = CommandRunner::new("sh")
+            .args(["-c", "exit 1"])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+    }
+
+    #[test]
+    fn test_spawn_failure_names_program() {
+        let err = CommandRunner::new("definitely-not-a-real-program")
+            .run()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("definitely-not-a-real-program"), "{}", err);
+    }
+
+    #[test]
+    fn test_timeout_kills_child() {
+        let start = Instant::now();
+        let err = CommandRunner::new("sleep")
+            .arg("5")
+            .timeout(Duration::from_millis(100))
+            .run()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`sleep 5` timed out"), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_max_stdout_stops_runaway_output() {
+        let err = CommandRunner::new("yes")
+            .max_stdout(1024 * 1024)
+            .timeout(Duration::from_secs(10))
+            .run()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("wrote more than 1048576 bytes"), "{}", err);
+
+        let output = CommandRunner::new("echo")
+            .arg("hi")
+            .max_stdout(3)
+            .run()
+            .unwrap();
+        assert_eq!(output.stdout, b"hi\n");
+    }
+
+    #[test]
+    fn test_idle_timeout_kills_stalled_child() {
+        let start = Instant::now();
+        let err = CommandRunner::new("sh")
+            .args(["-c", "echo connected; sleep 5"])
+            .idle_timeout(Duration::from_millis(300))
+            .run()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("stalled"), "{}", err);
+        assert!(err.contains("idle timeout"), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_idle_timeout_spares_slow_steady_progress() {
+        // Runs well past the idle timeout in total, but never goes quiet for that long
+        let start = Instant::now();
+        let output = CommandRunner::new("sh")
+            .args([
+                "-c",
+                "for i in 1 2 3 4 5 6 7 8; do echo $i >&2; sleep 0.1; done; echo done",
+            ])
+            .idle_timeout(Duration::from_millis(500))
+            .run()
+            .unwrap();
+        assert!(start.elapsed() > Duration::from_millis(500));
+        assert_eq!(output.stdout, b"done\n");
+        assert_eq!(output.stderr, b"1\n2\n3\n4\n5\n6\n7\n8\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_idle_timeout_spares_quiet_busy_child() {
+        // Prints nothing for well past the idle timeout, but keeps reading its input
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::write(&input, "1\n2\n3\n4\n5\n6\n7\n8\n").unwrap();
+        let start = Instant::now();
+        let output = CommandRunner::new("sh")
+            .args(["-c", "while read i; do sleep 0.1; done < \"$0\""])
+            .arg(&input)
+            .idle_timeout(Duration::from_millis(500))
+            .run()
+            .unwrap();
+        assert!(start.elapsed() > Duration::from_millis(500));
+        assert!(output.stdout.is_empty());
+    }
+
+    #[test]
+    fn test_relative_dir_resolves_against_the_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(dir.join("repo")).unwrap();
+        let session = session_env::SessionEnv {
+            cwd: dir.clone(),
+            vars: Default::default(),
+        };
+
+        let pwd = |relative: &str| {
+            let output = CommandRunner::new("pwd")
+                .current_dir(relative)
+                .run()
+                .unwrap();
+            PathBuf::from(String::from_utf8_lossy(&output.stdout).trim())
+        };
+        session.scope(|| {
+            assert_eq!(pwd("."), dir);
+            assert_eq!(pwd("repo"), dir.join("repo"));
+        });
+    }
+
+    #[test]
+    fn test_timeout_not_hit() {
+        let output = CommandRunner::new("true")
+            .timeout(Duration::from_secs(10))
+            .run()
+            .unwrap();
+        assert!(output.status.success());
+    }
+}
@@ -1,3 +1,22 @@
+mod access;
 mod client;
+mod compaction;
+mod last_writer;
+mod lock;
+#[cfg(test)]
+#[path = "../tests/mock/sui_rpc.rs"]
+pub(crate) mod mock_rpc;
+mod network;
+mod pinned;
+mod policy;
+mod ref_history;
+mod refs_layout;
 
+pub use access::{AccessRole, RemoteAccess};
 pub use client::SuiClient;
+pub use compaction::{dead_refs, DeadRef};
+pub use last_writer::LastWriter;
+pub use network::{ensure_spending_allowed, SuiNetwork};
+pub use policy::{projected_ref_count, RefChange, RemotePolicy};
+pub use ref_history::{RefHistoryCache, RefUpdate};
+pub use refs_layout::RefsLayout;
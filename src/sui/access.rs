@@ -0,0 +1,168 @@
+//! Who may push to a remote: the RemoteState's owner and anyone on its allowlist
+
+use std::fmt;
+
+use anyhow::Result;
+use serde::Serialize;
+use sui_sdk::rpc_types::{SuiMoveStruct, SuiMoveValue};
+
+/// A remote's pushers, from the RemoteState's `owner` and `allowlist` fields
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteAccess {
+    /// Address that created the remote (always authorized)
+    pub owner: String,
+    /// Addresses a shared remote also accepts pushes from (empty for unshared remotes)
+    pub allowlist: Vec<String>,
+}
+
+/// How an address relates to a remote
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessRole {
+    Owner,
+    Allowlisted,
+    Unauthorized,
+}
+
+impl fmt::Display for AccessRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessRole::Owner => write!(f, "owner"),
+            AccessRole::Allowlisted => write!(f, "on the allowlist"),
+            AccessRole::Unauthorized => write!(f, "not authorized to push"),
+        }
+    }
+}
+
+impl RemoteAccess {
+    /// Read the owner and allowlist from RemoteState fields
+    pub fn from_fields(fields: &SuiMoveStruct) -> Result<Self> {
+        let owner = match field(fields, "owner")? {
+            Some(value) => address(value)?,
+            None => anyhow::bail!("RemoteState has no owner field"),
+        };
+        let allowlist = match field(fields, "allowlist")? {
+            None => None,
+            Some(SuiMoveValue::Option(inner)) => inner.as_ref().as_ref(),
+            // JSON-RPC may flatten Some(value) to the value itself
+            Some(value) => Some(value),
+        };
+        let allowlist = match allowlist {
+            None => Vec::new(),
+            // VecSet<address> is a struct holding its elements in `contents`
+            Some(SuiMoveValue::Struct(set)) => match field(set, "contents")? {
+                Some(SuiMoveValue::Vector(addresses)) => {
+                    addresses.iter().map(address).collect::<Result<_>>()?
+                }
+                other => anyhow::bail!("Expected a vector of addresses, got {:?}", other),
+            },
+            Some(other) => anyhow::bail!("Expected VecSet for allowlist, got {:?}", other),
+        };
+        Ok(Self { owner, allowlist })
+    }
+
+    /// How `address` relates to the remote
+    pub fn role(&self, address: &str) -> AccessRole {
+        if self.owner.eq_ignore_ascii_case(address) {
+            AccessRole::Owner
+        } else if self
+            .allowlist
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(address))
+        {
+            AccessRole::Allowlisted
+        } else {
+            AccessRole::Unauthorized
+        }
+    }
+}
+
+fn address(value: &SuiMoveValue) -> Result<String> {
+    match value {
+        SuiMoveValue::Address(addr) => Ok(addr.to_string()),
+        SuiMoveValue::String(s) => Ok(s.clone()),
+        other => anyhow::bail!("Expected address, got {:?}", other),
+    }
+}
+
+fn field<'a>(fields: &'a SuiMoveStruct, name: &str) -> Result<Option<&'a SuiMoveValue>> {
+    match fields {
+        SuiMoveStruct::WithFields(map) | SuiMoveStruct::WithTypes { fields: map, .. } => {
+            Ok(map.get(name))
+        }
+        SuiMoveStruct::Runtime(_) => anyhow::bail!("Cannot access fields in Runtime variant"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use sui_types::base_types::SuiAddress;
+
+    use super::*;
+
+    const OWNER: &str = "0x000000000000000000000000000000000000000000000000000000000000aaaa";
+    const FRIEND: &str = "0x000000000000000000000000000000000000000000000000000000000000bbbb";
+    const STRANGER: &str = "0x000000000000000000000000000000000000000000000000000000000000cccc";
+
+    /// RemoteState fields as the JSON-RPC read API returns them
+    fn remote_state(allowlist: Option<Vec<SuiMoveValue>>) -> SuiMoveStruct {
+        let allowlist = allowlist.map(|addresses| {
+            SuiMoveValue::Struct(SuiMoveStruct::WithFields(BTreeMap::from([(
+                "contents".to_string(),
+                SuiMoveValue::Vector(addresses),
+            )])))
+        });
+        SuiMoveStruct::WithFields(BTreeMap::from([
+            (
+                "owner".to_string(),
+                SuiMoveValue::Address(OWNER.parse().unwrap()),
+            ),
+            (
+                "allowlist".to_string(),
+                SuiMoveValue::Option(Box::new(allowlist)),
+            ),
+        ]))
+    }
+
+    #[test]
+    fn test_access_from_fields() {
+        let unshared = RemoteAccess::from_fields(&remote_state(None)).unwrap();
+        assert_eq!(unshared.owner, OWNER);
+        assert!(unshared.allowlist.is_empty());
+
+        let friend: SuiAddress = FRIEND.parse().unwrap();
+        let shared = RemoteAccess::from_fields(&remote_state(Some(vec![
+            SuiMoveValue::Address(friend),
+            SuiMoveValue::String(STRANGER.to_string()),
+        ])))
+        .unwrap();
+        assert_eq!(shared.allowlist, vec![FRIEND, STRANGER]);
+
+        let malformed = remote_state(Some(vec![SuiMoveValue::Number(1)]));
+        assert!(RemoteAccess::from_fields(&malformed).is_err());
+    }
+
+    #[test]
+    fn test_roles() {
+        let access = RemoteAccess::from_fields(&remote_state(Some(vec![SuiMoveValue::String(
+            FRIEND.to_string(),
+        )])))
+        .unwrap();
+        assert_eq!(access.role(OWNER), AccessRole::Owner);
+        assert_eq!(
+            access.role(&FRIEND.to_uppercase().replace("0X", "0x")),
+            AccessRole::Allowlisted
+        );
+        assert_eq!(access.role(STRANGER), AccessRole::Unauthorized);
+
+        // Only the owner may push to a remote that was never shared
+        let unshared = RemoteAccess::from_fields(&remote_state(None)).unwrap();
+        assert_eq!(unshared.role(FRIEND), AccessRole::Unauthorized);
+        assert_eq!(
+            serde_json::to_value(AccessRole::Allowlisted).unwrap(),
+            "allowlisted"
+        );
+    }
+}
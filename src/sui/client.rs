@@ -1,4 +1,7 @@
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
 
 use anyhow::{Context, Result};
 use base64::{display::Base64Display, engine::general_purpose::URL_SAFE_NO_PAD};
@@ -8,12 +11,27 @@ use sui_config::PersistedConfig;
 use sui_keys::keystore::AccountKeystore;
 use sui_sdk::{
     rpc_types::{
+        ObjectChange,
+        OwnedObjectRef,
+        SuiArgument,
+        SuiCommand,
+        SuiExecutionStatus,
+        SuiGetPastObjectRequest,
         SuiMoveStruct,
         SuiMoveValue,
+        SuiObjectDataFilter,
         SuiObjectDataOptions,
+        SuiObjectResponseQuery,
         SuiParsedData,
+        SuiPastObjectResponse,
+        SuiProgrammableTransactionBlock,
+        SuiTransactionBlockDataAPI,
         SuiTransactionBlockEffectsAPI,
+        SuiTransactionBlockKind,
+        SuiTransactionBlockResponse,
         SuiTransactionBlockResponseOptions,
+        SuiTransactionBlockResponseQuery,
+        TransactionFilter,
     },
     sui_client_config::SuiClientConfig,
     SuiClientBuilder,
@@ -21,7 +39,11 @@ use sui_sdk::{
 use sui_types::{
     base_types::{ObjectID, ObjectRef, SequenceNumber, SuiAddress},
     crypto::Signature,
-    dynamic_field::DynamicFieldName,
+    digests::TransactionDigest,
+    dynamic_field::{self, DynamicFieldName},
+    object::Owner,
+    parse_sui_struct_tag,
+    parse_sui_type_tag,
     programmable_transaction_builder::ProgrammableTransactionBuilder,
     quorum_driver_types::ExecuteTransactionRequestType,
     transaction::{ObjectArg, Transaction, TransactionData},
@@ -29,18 +51,72 @@ use sui_types::{
 };
 use tokio::time::Instant;
 
-/// Sui on-chain clock object ID (shared object at 0x6)
+use super::{
+    access::{AccessRole, RemoteAccess},
+    last_writer::{LastWriter, LAST_WRITER_FIELD},
+    lock::{lock_action, LockAction, LockInfo},
+    pinned::{FieldChange, FieldReplay, StateTransaction},
+    policy::{RemotePolicy, POLICY_FIELD},
+    ref_history::{self, RefTransaction, RefUpdate},
+    refs_layout::{self, RefsLayout, INLINE_REFS_FIELD, SYMREFS_FIELD},
+    SuiNetwork,
+};
+use crate::config::NetworkTimeouts;
+
+/// Sui on-chain clock object ID on standard networks (shared object at 0x6)
 const CLOCK_OBJECT_ID: &str = "0x0000000000000000000000000000000000000000000000000000000000000006";
 
+/// Transactions read per page, and pages at most, when reconstructing ref history
+const HISTORY_PAGE_SIZE: usize = 50;
+const MAX_HISTORY_PAGES: usize = 20;
+
+/// Pages of transactions replayed, at most, to find the refs as of a pinned transaction
+const MAX_PINNED_HISTORY_PAGES: usize = 200;
+
+/// Past refs table fields read per request
+const PAST_FIELDS_PER_REQUEST: usize = 50;
+
+/// Module and name of the RemoteState type, after the package ID
+const REMOTE_STATE_TYPE: &str = "remote_state::RemoteState";
+
+/// Owned objects read per page when looking for RemoteStates
+const OWNED_OBJECTS_PAGE_SIZE: usize = 50;
+
 /// Default gas budget for transactions (1 SUI = 1_000_000_000 MIST)
 const DEFAULT_GAS_BUDGET: u64 = 10_000_000_000; // 0.1 SUI
 
+/// Refs deleted (or repaired) per transaction, well under the PTB command limit
+const MAX_REF_DELETES_PER_PTB: usize = 500;
+
+/// `remote_state::ERR_LOCK_HELD`: `acquire_lock` found the lock held by another push
+const ERR_LOCK_HELD: u64 = 1;
+
+/// Refs, symrefs (None without a `symrefs` field) and objects map object ID of a RemoteState
+/// at a past transaction
+pub type PinnedState = (
+    BTreeMap<String, String>,
+    Option<BTreeMap<String, String>>,
+    Option<String>,
+);
+
 /// Status information for a SharedBlob object
 #[derive(Debug, Clone)]
 pub struct SharedBlobStatus {
     pub object_id: String,
     pub blob_id: String,
     pub end_epoch: u64,
+    /// Unencoded blob size in bytes, when the Blob object reports it
+    pub size: Option<u64>,
+}
+
+/// A lock taken by [`SuiClient::acquire_lock`]
+#[derive(Debug)]
+pub struct AcquiredLock {
+    /// The RemoteState's new object reference from the lock transaction's effects, for the
+    /// follow-up PTB (None if the effects didn't include it)
+    pub state_ref: Option<ObjectRef>,
+    /// The expired lock of an unfinished push that had to be broken, if any
+    pub broke_stale: Option<LockInfo>,
 }
 
 /// Sui client for interacting with RemoteState on-chain
@@ -60,13 +136,26 @@ pub struct SuiClient {
 
     /// Keystore for signing transactions
     sui_client_config: SuiClientConfig,
+
+    /// Network of the active Sui environment
+    network: SuiNetwork,
+
+    /// SUI balance (in MIST) that transactions must leave untouched
+    gas_reserve_mist: u64,
+
+    /// Shared Clock object passed to lock and push transactions
+    clock_object_id: ObjectID,
 }
 
 impl SuiClient {
     /// Create a new Sui client
     ///
     /// Loads the keystore and active address from Sui client config.
-    pub async fn new(state_object_id: String, wallet_path: PathBuf) -> Result<Self> {
+    pub async fn new(
+        state_object_id: String,
+        wallet_path: PathBuf,
+        timeouts: NetworkTimeouts,
+    ) -> Result<Self> {
         // Parse state object ID
         let state_object_id = ObjectID::from_hex_literal(&state_object_id)
             .with_context(|| format!("Invalid state object ID: {}", state_object_id))?;
@@ -76,10 +165,7 @@ impl SuiClient {
             .with_context(|| format!("Failed to load Sui config from {:?}", wallet_path))?;
 
         // Build Sui client
-        let client = SuiClientBuilder::default()
-            .build(sui_client_config.get_active_env()?.rpc.clone())
-            .await
-            .context("Failed to build Sui client")?;
+        let client = Self::connect(&sui_client_config.get_active_env()?.rpc, timeouts).await?;
 
         // Get active address from config
         let active_address = sui_client_config
@@ -105,12 +191,24 @@ impl SuiClient {
             state_object_id: Some(state_object_id),
             package_id,
             sender: active_address,
+            network: SuiNetwork::from_client_config(&sui_client_config)?,
             sui_client_config,
+            gas_reserve_mist: 0,
+            clock_object_id: ObjectID::from_hex_literal(CLOCK_OBJECT_ID)?,
         })
     }
 
+    /// Create a Sui client for the wallet alone, to read its balances (no remote or package)
+    pub async fn for_wallet(wallet_path: PathBuf, timeouts: NetworkTimeouts) -> Result<Self> {
+        Self::new_for_init(ObjectID::ZERO.to_hex_literal(), wallet_path, timeouts).await
+    }
+
     /// Create a new Sui client for init command (without state object ID)
-    pub async fn new_for_init(package_id: String, wallet_path: PathBuf) -> Result<Self> {
+    pub async fn new_for_init(
+        package_id: String,
+        wallet_path: PathBuf,
+        timeouts: NetworkTimeouts,
+    ) -> Result<Self> {
         // Parse package ID
         let package_id = ObjectID::from_hex_literal(&package_id)
             .with_context(|| format!("Invalid package ID: {}", package_id))?;
@@ -120,10 +218,7 @@ impl SuiClient {
             .with_context(|| format!("Failed to load Sui config from {:?}", wallet_path))?;
 
         // Build Sui client
-        let client = SuiClientBuilder::default()
-            .build(sui_client_config.get_active_env()?.rpc.clone())
-            .await
-            .context("Failed to build Sui client")?;
+        let client = Self::connect(&sui_client_config.get_active_env()?.rpc, timeouts).await?;
 
         // Get active address from config
         let active_address = sui_client_config
@@ -144,19 +239,91 @@ impl SuiClient {
             state_object_id: None,
             package_id,
             sender: active_address,
+            network: SuiNetwork::from_client_config(&sui_client_config)?,
             sui_client_config,
+            gas_reserve_mist: 0,
+            clock_object_id: ObjectID::from_hex_literal(CLOCK_OBJECT_ID)?,
         })
     }
 
-    /// Create a new RemoteState object and return its ID
-    pub async fn create_remote(&self) -> Result<String> {
+    /// Build an RPC client for `rpc`, failing fast if its endpoint doesn't accept a connection
+    /// within `timeouts.connect`
+    ///
+    /// `timeouts.idle` is not applied: the SDK's request timeout is a deadline on the whole
+    /// request, which would cut off large responses that are still arriving.
+    async fn connect(rpc: &str, timeouts: NetworkTimeouts) -> Result<sui_sdk::SuiClient> {
+        probe_connect(rpc, timeouts.connect).await?;
+        SuiClientBuilder::default()
+            .build(rpc)
+            .await
+            .context("Failed to build Sui client")
+    }
+
+    /// Refuse transactions whose gas budget could drop the balance below `mist`
+    pub fn with_gas_reserve(mut self, mist: u64) -> Self {
+        self.gas_reserve_mist = mist;
+        self
+    }
+
+    /// Use the Clock object at `clock_object_id` instead of the standard `0x6`
+    pub fn with_clock_object_id(mut self, clock_object_id: &str) -> Result<Self> {
+        self.clock_object_id = ObjectID::from_hex_literal(clock_object_id)
+            .with_context(|| format!("Invalid clock object ID: {}", clock_object_id))?;
+        Ok(self)
+    }
+
+    /// Address that signs and pays for transactions
+    pub fn sender(&self) -> SuiAddress {
+        self.sender
+    }
+
+    /// RPC URL of the active Sui environment
+    pub fn rpc_url(&self) -> Option<&str> {
+        self.sui_client_config
+            .get_active_env()
+            .ok()
+            .map(|env| env.rpc.as_str())
+    }
+
+    /// Alias of the active Sui environment
+    pub fn env_alias(&self) -> Option<&str> {
+        self.sui_client_config
+            .get_active_env()
+            .ok()
+            .map(|env| env.alias.as_str())
+    }
+
+    /// Network of the active Sui environment
+    pub fn network(&self) -> SuiNetwork {
+        self.network
+    }
+
+    /// Total balance of the sender for `coin_type` (SUI if None), in base units
+    pub async fn balance(&self, coin_type: Option<String>) -> Result<u128> {
+        let balance = self
+            .client
+            .coin_read_api()
+            .get_balance(self.sender, coin_type.clone())
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to read {} balance of {}",
+                    coin_type.as_deref().unwrap_or("SUI"),
+                    self.sender
+                )
+            })?;
+        Ok(balance.total_balance)
+    }
+
+    /// Create a new RemoteState object with the given refs layout and return its ID
+    pub async fn create_remote(&self, layout: RefsLayout) -> Result<String> {
         let mut ptb = ProgrammableTransactionBuilder::new();
 
-        // Call create_remote() which transfers the object to sender
+        // Call create_remote() (or create_remote_inline()) which transfers the object to sender
         ptb.programmable_move_call(
             self.package_id,
             Identifier::new("remote_state")?,
-            Identifier::new("create_remote")?,
+            Identifier::new(layout.create_function())?,
             vec![], // no type arguments
             vec![], // no arguments (uses TxContext)
         );
@@ -169,6 +336,185 @@ impl SuiClient {
         Ok(object_id.to_hex_literal())
     }
 
+    /// Object IDs of the RemoteStates the sender owns or may push to
+    ///
+    /// Remotes never shared are among the sender's owned objects. Shared ones have no owner
+    /// to list them by, so they are found through the transaction index instead (see
+    /// `shared_remote_states`).
+    pub async fn get_owned_remote_states(&self) -> Result<Vec<String>> {
+        let mut object_ids = self.owned_remote_states().await?;
+        let shared = self.shared_remote_states(&object_ids).await?;
+        object_ids.extend(shared);
+        Ok(object_ids)
+    }
+
+    /// Object IDs of the RemoteStates the sender owns
+    ///
+    /// With a package ID (`new_for_init`), only that package's RemoteStates are asked for;
+    /// otherwise every owned object is read and matched by its type's module and name.
+    async fn owned_remote_states(&self) -> Result<Vec<String>> {
+        let filter = if self.package_id == ObjectID::ZERO {
+            None
+        } else {
+            let type_str = format!(
+                "{}::{}",
+                self.package_id.to_hex_literal(),
+                REMOTE_STATE_TYPE
+            );
+            Some(SuiObjectDataFilter::StructType(parse_sui_struct_tag(
+                &type_str,
+            )?))
+        };
+        let query =
+            SuiObjectResponseQuery::new(filter, Some(SuiObjectDataOptions::new().with_type()));
+
+        let mut object_ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = self
+                .client
+                .read_api()
+                .get_owned_objects(
+                    self.sender,
+                    Some(query.clone()),
+                    cursor,
+                    Some(OWNED_OBJECTS_PAGE_SIZE),
+                )
+                .await
+                .with_context(|| format!("Failed to list objects owned by {}", self.sender))?;
+
+            object_ids.extend(
+                page.data
+                    .into_iter()
+                    .filter_map(|response| response.data)
+                    .filter(|data| {
+                        data.type_
+                            .as_ref()
+                            .is_some_and(|type_| is_remote_state_type(&type_.to_string()))
+                    })
+                    .map(|data| data.object_id.to_hex_literal()),
+            );
+
+            if !page.has_next_page {
+                break;
+            }
+            cursor = page.next_cursor;
+        }
+        Ok(object_ids)
+    }
+
+    /// Object IDs of shared RemoteStates, other than `known`, whose owner or allowlist names
+    /// the sender
+    ///
+    /// Candidates are the RemoteStates the sender's own transactions changed (those it shared
+    /// or pushed to) and, for the package given or any seen among those, the ones anyone shared
+    /// or added to an allowlist; the newest `MAX_HISTORY_PAGES` pages of each are searched.
+    async fn shared_remote_states(&self, known: &[String]) -> Result<Vec<String>> {
+        let mut candidates = BTreeSet::new();
+        let mut packages = BTreeSet::new();
+        if self.package_id != ObjectID::ZERO {
+            packages.insert(self.package_id);
+        }
+        self.find_remote_states(
+            TransactionFilter::FromAddress(self.sender),
+            &mut candidates,
+            &mut packages,
+        )
+        .await?;
+        for package in packages.clone() {
+            for function in ["share_with_allowlist", "add_to_allowlist"] {
+                let filter = TransactionFilter::MoveFunction {
+                    package,
+                    module: Some("remote_state".to_string()),
+                    function: Some(function.to_string()),
+                };
+                self.find_remote_states(filter, &mut candidates, &mut packages)
+                    .await?;
+            }
+        }
+        let candidates: Vec<ObjectID> = candidates
+            .into_iter()
+            .filter(|id| !known.contains(&id.to_hex_literal()))
+            .collect();
+
+        let sender = self.sender.to_string();
+        let mut accessible = Vec::new();
+        for chunk in candidates.chunks(OWNED_OBJECTS_PAGE_SIZE) {
+            let responses = self
+                .client
+                .read_api()
+                .multi_get_object_with_options(
+                    chunk.to_vec(),
+                    SuiObjectDataOptions::new().with_content(),
+                )
+                .await
+                .context("Failed to read candidate RemoteStates")?;
+            for data in responses.into_iter().filter_map(|response| response.data) {
+                // Deleted or unreadable candidates are skipped
+                let Some(SuiParsedData::MoveObject(object)) = &data.content else {
+                    continue;
+                };
+                let role = RemoteAccess::from_fields(&object.fields)
+                    .map_or(AccessRole::Unauthorized, |access| access.role(&sender));
+                if role != AccessRole::Unauthorized {
+                    accessible.push(data.object_id.to_hex_literal());
+                }
+            }
+        }
+        Ok(accessible)
+    }
+
+    /// Add the RemoteStates that transactions matching `filter` created or changed to
+    /// `candidates`, and their packages to `packages`
+    async fn find_remote_states(
+        &self,
+        filter: TransactionFilter,
+        candidates: &mut BTreeSet<ObjectID>,
+        packages: &mut BTreeSet<ObjectID>,
+    ) -> Result<()> {
+        let query = SuiTransactionBlockResponseQuery::new(
+            Some(filter),
+            Some(SuiTransactionBlockResponseOptions::new().with_object_changes()),
+        );
+        let mut cursor = None;
+        for _ in 0..MAX_HISTORY_PAGES {
+            let page = self
+                .client
+                .read_api()
+                .query_transaction_blocks(query.clone(), cursor, Some(HISTORY_PAGE_SIZE), true)
+                .await
+                .context("Failed to search transactions for RemoteStates")?;
+            for change in page
+                .data
+                .iter()
+                .flat_map(|response| response.object_changes.iter().flatten())
+            {
+                if let ObjectChange::Created {
+                    object_id,
+                    object_type,
+                    ..
+                }
+                | ObjectChange::Mutated {
+                    object_id,
+                    object_type,
+                    ..
+                } = change
+                {
+                    if is_remote_state_type(&object_type.to_string()) {
+                        candidates.insert(*object_id);
+                        packages.insert(ObjectID::from(object_type.address));
+                    }
+                }
+            }
+
+            if !page.has_next_page {
+                break;
+            }
+            cursor = page.next_cursor;
+        }
+        Ok(())
+    }
+
     /// Share a RemoteState object with an allowlist
     pub async fn share_remote(&self, object_id: String, allowlist: Vec<String>) -> Result<()> {
         // Parse object ID
@@ -270,104 +616,321 @@ impl SuiClient {
         Ok(data.object_ref())
     }
 
-    /// Get the Clock object reference (shared object at 0x6)
-    async fn get_clock_object_ref(&self) -> Result<ObjectRef> {
-        let clock_id = ObjectID::from_hex_literal(CLOCK_OBJECT_ID)
-            .context("Failed to parse clock object ID")?;
+    /// Cheap change token for the RemoteState: `<version>:<digest>`
+    ///
+    /// Every ref upsert and objects-map update takes the RemoteState mutably, so the version
+    /// also moves when only the refs table's dynamic fields change.
+    pub async fn state_fingerprint(&self) -> Result<String> {
+        let (_, version, digest) = self.get_state_object_ref().await?;
+        Ok(format!("{}:{}", version, digest))
+    }
 
-        let object = self
-            .client
-            .read_api()
-            .get_object_with_options(clock_id, SuiObjectDataOptions::new().with_owner())
-            .await
-            .context("Failed to fetch Clock object")?;
+    /// Find the transaction that set each of `refs` (name -> SHA-1) to its current value
+    ///
+    /// Pages through the RemoteState's transactions newest first, stopping once every ref is
+    /// found or after `MAX_HISTORY_PAGES` pages; refs updated longer ago are left out.
+    pub async fn ref_updates(
+        &self,
+        refs: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, RefUpdate>> {
+        let mut found = BTreeMap::new();
+        self.ref_transactions(|transactions| {
+            ref_history::match_updates(refs, transactions, &mut found);
+            found.len() == refs.len()
+        })
+        .await?;
+        Ok(found)
+    }
 
-        let data = object
-            .data
-            .ok_or_else(|| anyhow::anyhow!("Clock object not found"))?;
+    /// Every SHA-1 the RemoteState's transactions set a ref to, from the newest
+    /// `MAX_HISTORY_PAGES` pages of its history
+    ///
+    /// These are the tips a URL pinned to any of those transactions reads.
+    pub async fn ref_history_tips(&self) -> Result<BTreeSet<String>> {
+        let mut tips = BTreeSet::new();
+        self.ref_transactions(|transactions| {
+            for tx in transactions {
+                tips.extend(tx.ref_values().map(|(_, git_sha1)| git_sha1.to_string()));
+            }
+            false
+        })
+        .await?;
+        Ok(tips)
+    }
 
-        Ok(data.object_ref())
+    /// Hand the RemoteState's transactions to `visit` a page at a time, newest first, until it
+    /// returns true, the history ends or `MAX_HISTORY_PAGES` pages were read
+    async fn ref_transactions(
+        &self,
+        mut visit: impl FnMut(&[RefTransaction]) -> bool,
+    ) -> Result<()> {
+        let state_object_id = self.state_object_id.ok_or_else(|| {
+            anyhow::anyhow!("State object ID is not set - cannot read its history")
+        })?;
+        let query = SuiTransactionBlockResponseQuery::new(
+            Some(TransactionFilter::ChangedObject(state_object_id)),
+            Some(SuiTransactionBlockResponseOptions::new().with_input()),
+        );
+
+        let mut cursor = None;
+        for _ in 0..MAX_HISTORY_PAGES {
+            let page = self
+                .client
+                .read_api()
+                .query_transaction_blocks(query.clone(), cursor, Some(HISTORY_PAGE_SIZE), true)
+                .await
+                .context("Failed to query RemoteState transactions")?;
+
+            let transactions: Vec<RefTransaction> =
+                page.data.iter().filter_map(ref_transaction).collect();
+            if visit(&transactions) || !page.has_next_page {
+                break;
+            }
+            cursor = page.next_cursor;
+        }
+        Ok(())
     }
 
-    /// Read all refs from on-chain state
-    pub async fn read_refs(&self) -> Result<BTreeMap<String, String>> {
+    /// Refs, symrefs and objects map object ID of the RemoteState as it was after transaction
+    /// `digest`
+    ///
+    /// Reads the RemoteState at the version the transaction left it at; its refs are rebuilt
+    /// by replaying its transactions up to the pinned one. RPC nodes that prune history may no
+    /// longer have old versions. Symrefs are None if the RemoteState had no `symrefs` field
+    /// at the pin.
+    pub async fn state_at(&self, digest: &str) -> Result<PinnedState> {
         let state_object_id = self.state_object_id.ok_or_else(|| {
-            anyhow::anyhow!("State object ID is not set - cannot get state object reference")
+            anyhow::anyhow!("State object ID is not set - cannot read its history")
         })?;
-        // Get the RemoteState object
-        let remote_state = self
+        let tx_digest: TransactionDigest = digest
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid transaction digest {:?}: {}", digest, e))?;
+        let response = self
             .client
             .read_api()
-            .get_object_with_options(
-                state_object_id,
-                SuiObjectDataOptions::new().with_content().with_bcs(),
+            .get_transaction_with_options(
+                tx_digest,
+                SuiTransactionBlockResponseOptions::new().with_object_changes(),
             )
             .await
-            .context("Failed to fetch RemoteState object")?;
-
-        let data = remote_state
-            .data
-            .ok_or_else(|| anyhow::anyhow!("RemoteState object not found"))?;
+            .with_context(|| format!("Failed to fetch transaction {}", digest))?;
+        let version = response
+            .object_changes
+            .iter()
+            .flatten()
+            .find_map(|change| match change {
+                ObjectChange::Created {
+                    object_id, version, ..
+                }
+                | ObjectChange::Mutated {
+                    object_id, version, ..
+                } if *object_id == state_object_id => Some(*version),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "transaction {} did not change RemoteState {}",
+                    digest,
+                    state_object_id
+                )
+            })?;
 
-        // Extract the refs Table UID from the object content
-        // The Table is stored as a dynamic field container
+        let past = self
+            .client
+            .read_api()
+            .try_get_parsed_past_object(
+                state_object_id,
+                version,
+                SuiObjectDataOptions::new().with_content(),
+            )
+            .await
+            .context("Failed to fetch past RemoteState object")?;
+        let SuiPastObjectResponse::VersionFound(data) = past else {
+            anyhow::bail!(
+                "RemoteState version {} is not available from this RPC node (it may prune history)",
+                version
+            );
+        };
         let content = data
             .content
-            .ok_or_else(|| anyhow::anyhow!("RemoteState has no content"))?;
-
-        // Get the refs Table's ObjectID from the struct
+            .ok_or_else(|| anyhow::anyhow!("Past RemoteState has no content"))?;
+        let objects_object_id = self.extract_objects_blob_object_id_from_content(&content)?;
         let table_id = self
             .extract_table_id_from_content(&content)
             .context("Failed to extract refs table ID")?;
+        let (refs, symrefs) = self.refs_at(state_object_id, table_id, digest).await?;
+        Ok((refs, symrefs, objects_object_id))
+    }
 
-        // Query all dynamic fields of the Table
-        let mut refs = BTreeMap::new();
-        let mut cursor = None;
+    /// Refs and symrefs once transaction `pin` ran, replaying the RemoteState's transactions
+    /// oldest first
+    ///
+    /// Refs come from the `inline_refs` dynamic field if the RemoteState had it at the pin,
+    /// and from the fields of table `table_id` otherwise.
+    async fn refs_at(
+        &self,
+        state_object_id: ObjectID,
+        table_id: ObjectID,
+        pin: &str,
+    ) -> Result<(BTreeMap<String, String>, Option<BTreeMap<String, String>>)> {
+        let query = SuiTransactionBlockResponseQuery::new(
+            Some(TransactionFilter::ChangedObject(state_object_id)),
+            Some(SuiTransactionBlockResponseOptions::new().with_object_changes()),
+        );
+        let inline_id = state_field_id(state_object_id, INLINE_REFS_FIELD)?;
+        let symrefs_id = state_field_id(state_object_id, SYMREFS_FIELD)?;
 
-        loop {
+        let mut replay = FieldReplay::default();
+        let mut state_fields = FieldReplay::default();
+        let mut cursor = None;
+        for _ in 0..MAX_PINNED_HISTORY_PAGES {
             let page = self
                 .client
                 .read_api()
-                .get_dynamic_fields(table_id, cursor, Some(100))
+                .query_transaction_blocks(query.clone(), cursor, Some(HISTORY_PAGE_SIZE), false)
                 .await
-                .context("Failed to get dynamic fields")?;
-
-            for field in page.data {
-                // Extract ref name from field.name
-                let ref_name = self.extract_string_from_dynamic_field_name(&field.name)?;
-
-                // Get the field value (git SHA1)
-                let field_value = self
-                    .client
-                    .read_api()
-                    .get_dynamic_field_object(table_id, field.name.clone())
-                    .await
-                    .context("Failed to get dynamic field value")?;
-
-                if let Some(data) = field_value.data {
-                    if let Some(content) = data.content {
-                        let git_sha1 = self.extract_string_value_from_content(&content)?;
-                        refs.insert(ref_name, git_sha1);
-                    }
-                }
+                .context("Failed to query RemoteState transactions")?;
+
+            let transactions: Vec<StateTransaction> = page
+                .data
+                .iter()
+                .map(|response| state_transaction(response, table_id))
+                .collect();
+            let own: Vec<StateTransaction> = page
+                .data
+                .iter()
+                .map(|response| state_transaction(response, state_object_id))
+                .collect();
+            state_fields.apply(&own, pin);
+            if replay.apply(&transactions, pin) {
+                let refs = match state_fields.fields.get(&inline_id.to_string()) {
+                    Some(version) => self.read_past_vec_map(inline_id, *version).await?,
+                    None => self.read_past_fields(&replay.fields).await?,
+                };
+                let symrefs = match state_fields.fields.get(&symrefs_id.to_string()) {
+                    Some(version) => Some(self.read_past_vec_map(symrefs_id, *version).await?),
+                    None => None,
+                };
+                return Ok((refs, symrefs));
             }
 
-            if page.has_next_page {
-                cursor = page.next_cursor;
-            } else {
+            if !page.has_next_page {
                 break;
             }
+            cursor = page.next_cursor;
+        }
+        anyhow::bail!(
+            "transaction {} is not among the first {} transactions of RemoteState {}",
+            pin,
+            MAX_PINNED_HISTORY_PAGES * HISTORY_PAGE_SIZE,
+            state_object_id
+        )
+    }
+
+    /// Entries of the `inline_refs` or `symrefs` dynamic field object at `version`
+    async fn read_past_vec_map(
+        &self,
+        field_id: ObjectID,
+        version: u64,
+    ) -> Result<BTreeMap<String, String>> {
+        let past = self
+            .client
+            .read_api()
+            .try_get_parsed_past_object(
+                field_id,
+                SequenceNumber::from_u64(version),
+                SuiObjectDataOptions::new().with_content(),
+            )
+            .await
+            .context("Failed to fetch past RemoteState field")?;
+        let SuiPastObjectResponse::VersionFound(data) = past else {
+            anyhow::bail!(
+                "RemoteState field {} version {} is not available from this RPC node (it may prune history)",
+                field_id,
+                version
+            );
+        };
+        match data.content {
+            Some(SuiParsedData::MoveObject(move_obj)) => {
+                let vec_map = refs_layout::vec_map_field(&move_obj.fields).ok_or_else(|| {
+                    anyhow::anyhow!("Past RemoteState field {} has no VecMap", field_id)
+                })?;
+                refs_layout::parse_vec_map(vec_map)
+                    .with_context(|| format!("Failed to parse RemoteState field {}", field_id))
+            }
+            _ => anyhow::bail!("Past RemoteState field {} has no content", field_id),
         }
+    }
+
+    /// Ref name and SHA-1 of each refs table field at the given version
+    async fn read_past_fields(
+        &self,
+        fields: &BTreeMap<String, u64>,
+    ) -> Result<BTreeMap<String, String>> {
+        let requests = fields
+            .iter()
+            .map(|(object_id, version)| {
+                Ok(SuiGetPastObjectRequest {
+                    object_id: ObjectID::from_hex_literal(object_id)?,
+                    version: SequenceNumber::from_u64(*version),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
 
+        let mut refs = BTreeMap::new();
+        for chunk in requests.chunks(PAST_FIELDS_PER_REQUEST) {
+            let responses = self
+                .client
+                .read_api()
+                .try_multi_get_parsed_past_object(
+                    chunk.to_vec(),
+                    SuiObjectDataOptions::new().with_content(),
+                )
+                .await
+                .context("Failed to fetch past refs")?;
+            for response in responses {
+                let SuiPastObjectResponse::VersionFound(data) = response else {
+                    anyhow::bail!(
+                        "A past ref is not available from this RPC node (it may prune history)"
+                    );
+                };
+                let content = data
+                    .content
+                    .ok_or_else(|| anyhow::anyhow!("Past ref has no content"))?;
+                let ref_name = self.extract_string_name_from_content(&content)?;
+                let git_sha1 = self.extract_string_value_from_content(&content)?;
+                refs.insert(ref_name, git_sha1);
+            }
+        }
         Ok(refs)
     }
 
-    /// Get objects blob object ID from on-chain state
-    pub async fn get_objects_blob_object_id(&self) -> Result<Option<String>> {
+    /// Get the Clock object reference (`clock_object_id`, 0x6 unless configured)
+    async fn get_clock_object_ref(&self) -> Result<ObjectRef> {
+        let clock_id = self.clock_object_id;
+
+        let object = self
+            .client
+            .read_api()
+            .get_object_with_options(clock_id, SuiObjectDataOptions::new().with_owner())
+            .await
+            .with_context(|| format!("Failed to fetch Clock object {}", clock_id))?;
+
+        let data = object.data.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Clock object {} not found; check `clock_object_id` in the config",
+                clock_id
+            )
+        })?;
+
+        Ok(data.object_ref())
+    }
+
+    /// Fetch the parsed content of the RemoteState object
+    async fn read_state_content(&self) -> Result<SuiParsedData> {
         let state_object_id = self.state_object_id.ok_or_else(|| {
             anyhow::anyhow!("State object ID is not set - cannot get state object reference")
         })?;
-        // Get the RemoteState object with content
         let remote_state = self
             .client
             .read_api()
@@ -382,12 +945,182 @@ impl SuiClient {
             .data
             .ok_or_else(|| anyhow::anyhow!("RemoteState object not found"))?;
 
-        let content = data
-            .content
-            .ok_or_else(|| anyhow::anyhow!("RemoteState has no content"))?;
+        data.content
+            .ok_or_else(|| anyhow::anyhow!("RemoteState has no content"))
+    }
 
-        // Extract objects_blob_object_id from the struct
-        self.extract_objects_blob_object_id_from_content(&content)
+    /// Fields of the RemoteState's `name` dynamic field object, if it has that field
+    async fn state_field(&self, name: &str) -> Result<Option<SuiMoveStruct>> {
+        let state_object_id = self.state_object_id.ok_or_else(|| {
+            anyhow::anyhow!("State object ID is not set - cannot read its fields")
+        })?;
+        let field = self
+            .client
+            .read_api()
+            .get_dynamic_field_object(state_object_id, string_field_name(name)?)
+            .await
+            .with_context(|| format!("Failed to fetch RemoteState field {:?}", name))?;
+
+        match field.data.and_then(|data| data.content) {
+            Some(SuiParsedData::MoveObject(move_obj)) => Ok(Some(move_obj.fields)),
+            Some(_) => anyhow::bail!("Expected MoveObject for RemoteState field {:?}", name),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the RemoteState's ref and objects-map limits
+    pub async fn policy(&self) -> Result<RemotePolicy> {
+        let field = self.state_field(POLICY_FIELD).await?;
+        RemotePolicy::from_field(field.as_ref()).context("Failed to parse RemoteState policy")
+    }
+
+    /// Read the RemoteState's owner and allowlist
+    pub async fn access(&self) -> Result<RemoteAccess> {
+        match self.read_state_content().await? {
+            SuiParsedData::MoveObject(move_obj) => RemoteAccess::from_fields(&move_obj.fields)
+                .context("Failed to parse RemoteState owner and allowlist"),
+            _ => anyhow::bail!("Expected MoveObject for RemoteState"),
+        }
+    }
+
+    /// Set the RemoteState's limits (owner only); None lifts a limit
+    pub async fn set_policy(&self, policy: RemotePolicy) -> Result<()> {
+        let mut ptb = ProgrammableTransactionBuilder::new();
+
+        let state_ref = self.get_state_object_ref().await?;
+        let state_arg = ptb.obj(ObjectArg::ImmOrOwnedObject(state_ref))?;
+        let max_refs_arg = ptb.pure(policy.max_refs)?;
+        let max_objects_arg = ptb.pure(policy.max_objects)?;
+
+        ptb.programmable_move_call(
+            self.package_id,
+            Identifier::new("remote_state")?,
+            Identifier::new("set_policy")?,
+            vec![], // no type arguments
+            vec![state_arg, max_refs_arg, max_objects_arg],
+        );
+
+        self.execute_ptb(ptb, DEFAULT_GAS_BUDGET).await?;
+
+        Ok(())
+    }
+
+    /// Detect how the RemoteState stores its refs
+    pub async fn refs_layout(&self) -> Result<RefsLayout> {
+        let field = self.state_field(INLINE_REFS_FIELD).await?;
+        Ok(refs_layout::detect_layout(field.as_ref()))
+    }
+
+    /// Fail if upserting `refs` would overflow an inline-layout RemoteState
+    pub async fn ensure_refs_fit(&self, refs: &[(String, String)]) -> Result<()> {
+        if self.refs_layout().await? == RefsLayout::Inline {
+            let current = self.read_refs().await?;
+            refs_layout::check_inline_capacity(&current, refs)?;
+        }
+        Ok(())
+    }
+
+    /// Convert an inline-layout RemoteState to the table layout
+    pub async fn migrate_refs_to_table(&self) -> Result<()> {
+        let mut ptb = ProgrammableTransactionBuilder::new();
+
+        let state_ref = self.get_state_object_ref().await?;
+        let state_arg = ptb.obj(ObjectArg::ImmOrOwnedObject(state_ref))?;
+
+        ptb.programmable_move_call(
+            self.package_id,
+            Identifier::new("remote_state")?,
+            Identifier::new("migrate_refs_to_table")?,
+            vec![], // no type arguments
+            vec![state_arg],
+        );
+
+        self.execute_ptb(ptb, DEFAULT_GAS_BUDGET).await?;
+
+        Ok(())
+    }
+
+    /// Read all refs from on-chain state
+    pub async fn read_refs(&self) -> Result<BTreeMap<String, String>> {
+        // Inline layout: all refs live in a VecMap in one dynamic field of the object
+        if let Some(field) = self.state_field(INLINE_REFS_FIELD).await? {
+            if let Some(inline_refs) = refs_layout::vec_map_field(&field) {
+                return refs_layout::parse_vec_map(inline_refs)
+                    .context("Failed to parse inline refs");
+            }
+        }
+
+        let content = self.read_state_content().await?;
+
+        // Table layout: the refs Table is stored as a dynamic field container
+        // Get the refs Table's ObjectID from the struct
+        let table_id = self
+            .extract_table_id_from_content(&content)
+            .context("Failed to extract refs table ID")?;
+
+        // Query all dynamic fields of the Table
+        let mut refs = BTreeMap::new();
+        let mut cursor = None;
+
+        loop {
+            let page = self
+                .client
+                .read_api()
+                .get_dynamic_fields(table_id, cursor, Some(100))
+                .await
+                .context("Failed to get dynamic fields")?;
+
+            for field in page.data {
+                // Extract ref name from field.name
+                let ref_name = self.extract_string_from_dynamic_field_name(&field.name)?;
+
+                // Get the field value (git SHA1)
+                let field_value = self
+                    .client
+                    .read_api()
+                    .get_dynamic_field_object(table_id, field.name.clone())
+                    .await
+                    .context("Failed to get dynamic field value")?;
+
+                if let Some(data) = field_value.data {
+                    if let Some(content) = data.content {
+                        let git_sha1 = self.extract_string_value_from_content(&content)?;
+                        refs.insert(ref_name, git_sha1);
+                    }
+                }
+            }
+
+            if page.has_next_page {
+                cursor = page.next_cursor;
+            } else {
+                break;
+            }
+        }
+
+        Ok(refs)
+    }
+
+    /// Read the symrefs from on-chain state
+    ///
+    /// None if the RemoteState has no `symrefs` field: no push from a helper that records
+    /// symrefs on-chain has landed, and any symrefs are still in the objects map.
+    pub async fn read_symrefs(&self) -> Result<Option<BTreeMap<String, String>>> {
+        let Some(field) = self.state_field(SYMREFS_FIELD).await? else {
+            return Ok(None);
+        };
+        let symrefs = refs_layout::vec_map_field(&field)
+            .ok_or_else(|| anyhow::anyhow!("RemoteState symrefs field has no VecMap"))?;
+        refs_layout::parse_vec_map(symrefs)
+            .map(Some)
+            .context("Failed to parse symrefs")
+    }
+
+    /// Get objects blob object ID from on-chain state
+    pub async fn get_objects_blob_object_id(&self) -> Result<Option<String>> {
+        let content = self.read_state_content().await?;
+
+        // Extract objects_blob_object_id from the struct
+        self.extract_objects_blob_object_id_from_content(&content)
     }
 
     /// Helper: Extract the Table ID from RemoteState content
@@ -426,6 +1159,19 @@ impl SuiClient {
             .ok_or_else(|| anyhow::anyhow!("Dynamic field name is not a string: {:?}", name.value))
     }
 
+    /// Helper: Extract the string key from dynamic field content
+    fn extract_string_name_from_content(&self, content: &SuiParsedData) -> Result<String> {
+        let move_obj = match content {
+            SuiParsedData::MoveObject(obj) => obj,
+            _ => anyhow::bail!("Expected MoveObject for dynamic field"),
+        };
+        let name_field = self
+            .get_struct_field(&move_obj.fields, "name")
+            .context("Failed to get 'name' field from dynamic field")?;
+        self.extract_string(name_field)
+            .context("Failed to extract string from dynamic field name")
+    }
+
     /// Helper: Extract string value from dynamic field content
     fn extract_string_value_from_content(&self, content: &SuiParsedData) -> Result<String> {
         // Dynamic field values are wrapped in a Field struct
@@ -560,6 +1306,22 @@ impl SuiClient {
         }
     }
 
+    /// Get the Blob struct from a SharedBlob (wrapped in `blob`) or an owned Blob (inline)
+    fn blob_struct<'a>(&self, fields: &'a SuiMoveStruct) -> Result<&'a SuiMoveStruct> {
+        match self.get_struct_field(fields, "blob") {
+            Ok(SuiMoveValue::Struct(s)) => Ok(s),
+            Ok(_) => anyhow::bail!("Expected Struct for blob field"),
+            Err(_) => Ok(fields),
+        }
+    }
+
+    /// Helper: The unencoded `size` of a Blob struct, if present
+    fn blob_size(&self, blob_struct: &SuiMoveStruct) -> Option<u64> {
+        self.get_struct_field(blob_struct, "size")
+            .and_then(|value| self.extract_u64(value))
+            .ok()
+    }
+
     /// Batch query SharedBlob statuses from Sui with pagination
     /// Returns results in the same order as input, with errors for individual failures
     /// Chunks requests to avoid RPC limits (default: 50 objects per batch)
@@ -663,15 +1425,8 @@ impl SuiClient {
                     _ => anyhow::bail!("Expected MoveObject for SharedBlob"),
                 };
 
-                // Navigate to: content.fields.blob.fields
-                let blob_field = self
-                    .get_struct_field(&move_obj.fields, "blob")
-                    .context("Failed to get 'blob' field from SharedBlob")?;
-
-                let blob_struct = match blob_field {
-                    SuiMoveValue::Struct(s) => s,
-                    _ => anyhow::bail!("Expected Struct for blob field"),
-                };
+                // Navigate to: content.fields.blob.fields (owned Blobs have the fields inline)
+                let blob_struct = self.blob_struct(&move_obj.fields)?;
 
                 // Extract blob_id (stored as u256 decimal, convert to base64)
                 let blob_id_value = self
@@ -700,6 +1455,7 @@ impl SuiClient {
                     object_id: object_id_str.to_string(),
                     blob_id,
                     end_epoch,
+                    size: self.blob_size(blob_struct),
                 })
             })();
 
@@ -757,15 +1513,8 @@ impl SuiClient {
             _ => anyhow::bail!("Expected MoveObject for SharedBlob"),
         };
 
-        // Navigate to: content.fields.blob.fields
-        let blob_field = self
-            .get_struct_field(&move_obj.fields, "blob")
-            .context("Failed to get 'blob' field from SharedBlob")?;
-
-        let blob_struct = match blob_field {
-            SuiMoveValue::Struct(s) => s,
-            _ => anyhow::bail!("Expected Struct for blob field"),
-        };
+        // Navigate to: content.fields.blob.fields (owned Blobs have the fields inline)
+        let blob_struct = self.blob_struct(&move_obj.fields)?;
 
         // Extract blob_id (stored as u256 decimal, convert to base64)
         let blob_id_value = self
@@ -795,6 +1544,7 @@ impl SuiClient {
             object_id: object_id.to_string(),
             blob_id,
             end_epoch,
+            size: self.blob_size(blob_struct),
         })
     }
 
@@ -835,10 +1585,29 @@ impl SuiClient {
 
     /// Acquire lock with timeout
     /// Retries on 504 timeout errors since transaction may have succeeded
-    pub async fn acquire_lock(&self, timeout_ms: u64) -> Result<()> {
+    ///
+    /// A lock left by a push that never released it is broken once its declared timeout has
+    /// run out (the Move `acquire_lock` replaces expired locks); a live lock held by another
+    /// pusher fails fast instead of sending a transaction that would abort.
+    pub async fn acquire_lock(&self, timeout_ms: u64) -> Result<AcquiredLock> {
         const MAX_RETRIES: u32 = 3;
         const RETRY_DELAY_MS: u64 = 200;
 
+        let now_ms = self.clock_timestamp_ms().await?;
+        let broke_stale =
+            match lock_action(self.lock_info().await?, &self.sender.to_string(), now_ms) {
+                LockAction::Take => None,
+                LockAction::BreakStale(lock) => {
+                    tracing::warn!(
+                        "  Breaking stale lock {} (expired {}s ago; its push never released it)",
+                        lock,
+                        (now_ms - lock.expires_ms) / 1000
+                    );
+                    Some(lock)
+                }
+                LockAction::Held(lock) => return Err(self.lock_held_error(&lock, now_ms)),
+            };
+
         for attempt in 0..MAX_RETRIES {
             if attempt > 0 {
                 tracing::info!("  Retry attempt {} after 504 timeout...", attempt);
@@ -847,44 +1616,38 @@ impl SuiClient {
                 // Check if lock was actually acquired despite the timeout
                 if self.check_lock_acquired().await? {
                     tracing::info!("  Lock was already acquired in previous attempt");
-                    return Ok(());
+                    return Ok(AcquiredLock {
+                        state_ref: None,
+                        broke_stale,
+                    });
                 }
             }
 
-            let mut ptb = ProgrammableTransactionBuilder::new();
-
             // Get object references
             let state_ref = self.get_state_object_ref().await?;
             let clock_ref = self.get_clock_object_ref().await?;
-
-            // Add objects as inputs
-            let state_arg = ptb.obj(ObjectArg::ImmOrOwnedObject(state_ref))?;
-            // ObjectArg::Receiving(state_ref),
-            let clock_arg = ptb.obj(ObjectArg::SharedObject {
-                id: clock_ref.0,
-                initial_shared_version: SequenceNumber::from(1),
-                mutable: false,
-            })?;
-
-            // Call acquire_lock
-            let timeout_arg = ptb.pure(timeout_ms)?;
-
-            ptb.programmable_move_call(
-                self.package_id,
-                Identifier::new("remote_state")?,
-                Identifier::new("acquire_lock")?,
-                vec![], // no type arguments
-                vec![state_arg, clock_arg, timeout_arg],
-            );
+            let ptb = build_acquire_lock_ptb(self.package_id, state_ref, clock_ref.0, timeout_ms)?;
 
             // Build and execute transaction
             match self.execute_ptb(ptb, DEFAULT_GAS_BUDGET).await {
-                Ok(()) => return Ok(()),
+                Ok(response) => {
+                    return Ok(AcquiredLock {
+                        state_ref: self.state_ref_from_response(&response),
+                        broke_stale,
+                    })
+                }
+                // Another push took the lock between the check above and this transaction
+                Err(e) if is_lock_held_abort(&e) => {
+                    let now_ms = self.clock_timestamp_ms().await?;
+                    return Err(match self.lock_info().await? {
+                        Some(lock) => self.lock_held_error(&lock, now_ms),
+                        None => e,
+                    });
+                }
                 Err(e) => {
                     tracing::error!("git-remote-walrus: [acquire_lock(timeout_ms={timeout_ms})] execute_ptb error: {e:?}");
-                    let err_str = e.to_string();
                     // Retry only on 504 timeouts
-                    if err_str.contains("504") && attempt < MAX_RETRIES - 1 {
+                    if is_timeout_error(&e) && attempt < MAX_RETRIES - 1 {
                         tracing::warn!(
                             "  Got 504 timeout on attempt {}, will retry...",
                             attempt + 1
@@ -899,32 +1662,55 @@ impl SuiClient {
         anyhow::bail!("Failed to acquire lock after {} retries", MAX_RETRIES)
     }
 
-    /// Check if a lock is currently held on the RemoteState
+    /// Error for a push that found `lock` held by another pusher, naming the `unlock` command
+    fn lock_held_error(&self, lock: &LockInfo, now_ms: u64) -> anyhow::Error {
+        let remote = self
+            .state_object_id
+            .map_or_else(|| "<remote>".to_string(), |id| format!("walrus::{}", id));
+        anyhow::anyhow!(
+            "remote is locked by another push ({}); retry once that push finishes or its lock \
+             expires, and run `git-remote-walrus unlock {}` to check on it",
+            lock.describe(now_ms),
+            remote
+        )
+    }
+
+    /// Check if this sender holds the lock on the RemoteState
     async fn check_lock_acquired(&self) -> Result<bool> {
-        let state_object_id = self.state_object_id.ok_or_else(|| {
-            anyhow::anyhow!("State object ID is not set - cannot get state object reference")
-        })?;
+        let sender = self.sender.to_string();
+        Ok(self
+            .lock_info()
+            .await?
+            .is_some_and(|lock| lock.held_by(&sender)))
+    }
+
+    /// The lock currently on the RemoteState, if any
+    pub async fn lock_info(&self) -> Result<Option<LockInfo>> {
+        match self.read_state_content().await? {
+            SuiParsedData::MoveObject(move_obj) => LockInfo::from_fields(&move_obj.fields),
+            _ => anyhow::bail!("RemoteState is not a Move object"),
+        }
+    }
+
+    /// Current chain time in milliseconds, from the Clock object
+    pub async fn clock_timestamp_ms(&self) -> Result<u64> {
+        let clock_id = self.clock_object_id;
         let object = self
             .client
             .read_api()
-            .get_object_with_options(state_object_id, SuiObjectDataOptions::new().with_content())
+            .get_object_with_options(clock_id, SuiObjectDataOptions::new().with_content())
             .await
-            .context("Failed to fetch RemoteState object")?;
-
-        let data = object
-            .data
-            .ok_or_else(|| anyhow::anyhow!("RemoteState object not found"))?;
-
-        if let Some(SuiParsedData::MoveObject(move_obj)) = data.content {
-            if let SuiMoveStruct::WithFields(fields) = move_obj.fields {
-                if let Some(lock_value) = fields.get("lock") {
-                    // If lock field is Some (not null), lock is acquired
-                    return Ok(matches!(lock_value, SuiMoveValue::Option(opt) if opt.is_some()));
-                }
+            .with_context(|| format!("Failed to fetch Clock object {}", clock_id))?;
+        match object.data.and_then(|data| data.content) {
+            Some(SuiParsedData::MoveObject(move_obj)) => {
+                let timestamp = self.get_struct_field(&move_obj.fields, "timestamp_ms")?;
+                self.extract_u64(timestamp)
             }
+            _ => anyhow::bail!(
+                "Clock object {} has no timestamp; check `clock_object_id` in the config",
+                clock_id
+            ),
         }
-
-        Ok(false)
     }
 
     /// Update objects blob ID (requires lock)
@@ -938,11 +1724,7 @@ impl SuiClient {
 
         // Add objects as inputs
         let state_arg = ptb.obj(ObjectArg::ImmOrOwnedObject(state_ref))?;
-        let clock_arg = ptb.obj(ObjectArg::SharedObject {
-            id: clock_ref.0,
-            initial_shared_version: SequenceNumber::from(1),
-            mutable: false,
-        })?;
+        let clock_arg = ptb.obj(clock_object_arg(clock_ref.0))?;
 
         // Call update_objects_blob
         let blob_arg = ptb.pure(blob_id.to_string())?;
@@ -961,8 +1743,7 @@ impl SuiClient {
         Ok(())
     }
 
-    /// Release lock
-    #[allow(dead_code)]
+    /// Release the lock (which the sender must hold)
     pub async fn release_lock(&self) -> Result<()> {
         let mut ptb = ProgrammableTransactionBuilder::new();
 
@@ -987,72 +1768,234 @@ impl SuiClient {
         Ok(())
     }
 
+    /// Remove `names` from the refs table, then release the lock (which the caller must hold)
+    ///
+    /// Refs are deleted in batches of at most `MAX_REF_DELETES_PER_PTB`, each its own
+    /// transaction; only the last releases the lock. Returns the storage rebate net of the
+    /// storage the transactions paid for, in MIST.
+    pub async fn delete_refs_and_release_lock(
+        &self,
+        names: &[String],
+        state_ref: Option<ObjectRef>,
+    ) -> Result<u64> {
+        let mut state_ref = match state_ref {
+            Some(state_ref) => state_ref,
+            None => self.get_state_object_ref().await?,
+        };
+        let batches: Vec<&[String]> = if names.is_empty() {
+            vec![&[]]
+        } else {
+            names.chunks(MAX_REF_DELETES_PER_PTB).collect()
+        };
+        let (mut rebate, mut cost) = (0u64, 0u64);
+        for (i, batch) in batches.iter().enumerate() {
+            let last = i + 1 == batches.len();
+            let ptb = build_edit_refs_ptb(self.package_id, &[], batch, state_ref, last)?;
+            let response = self
+                .execute_ptb(ptb, DEFAULT_GAS_BUDGET)
+                .await
+                .with_context(|| format!("Failed to delete refs (batch {})", i + 1))?;
+            if let Some(effects) = &response.effects {
+                let gas = effects.gas_cost_summary();
+                rebate += gas.storage_rebate;
+                cost += gas.storage_cost;
+            }
+            if !last {
+                state_ref = match self.state_ref_from_response(&response) {
+                    Some(state_ref) => state_ref,
+                    None => self.get_state_object_ref().await?,
+                };
+            }
+        }
+        Ok(rebate.saturating_sub(cost))
+    }
+
+    /// Point `corrections` at their SHA-1s and remove `names` from the refs table, then release
+    /// the lock (which the caller must hold), in one transaction
+    pub async fn repair_refs_and_release_lock(
+        &self,
+        corrections: &[(String, String)],
+        names: &[String],
+        state_ref: Option<ObjectRef>,
+    ) -> Result<()> {
+        if corrections.len() + names.len() > MAX_REF_DELETES_PER_PTB {
+            anyhow::bail!(
+                "{} refs to repair, over the {} one transaction can take",
+                corrections.len() + names.len(),
+                MAX_REF_DELETES_PER_PTB
+            );
+        }
+        let state_ref = match state_ref {
+            Some(state_ref) => state_ref,
+            None => self.get_state_object_ref().await?,
+        };
+        let ptb = build_edit_refs_ptb(self.package_id, corrections, names, state_ref, true)?;
+        self.execute_ptb(ptb, DEFAULT_GAS_BUDGET)
+            .await
+            .context("Failed to repair refs")?;
+        Ok(())
+    }
+
     /// Combined operation: upsert refs and update objects blob atomically via PTB
     ///
     /// This is the most important operation - it ensures that ref updates and
     /// objects blob updates happen atomically in a single transaction.
+    ///
+    /// `state_ref` is the RemoteState reference from the preceding lock transaction. Passing
+    /// it avoids re-reading the object from a fullnode that may not have seen the lock yet;
+    /// without it the reference is read fresh, and re-read if the transaction rejects it as
+    /// stale.
+    ///
+    /// `format` is the objects map's format header, recorded in the RemoteState's
+    /// `last_writer` field, and `symrefs` replace its `symrefs` field, when its package can
+    /// (packages published before those fields existed can't; their symrefs are dropped with a
+    /// warning).
     pub async fn upsert_refs_and_update_objects(
         &self,
         refs: Vec<(String, String)>,
         objects_blob_object_id: String,
+        symrefs: BTreeMap<String, String>,
+        format: String,
+        state_ref: Option<ObjectRef>,
     ) -> Result<()> {
+        const MAX_TIMEOUT_RETRIES: u32 = 2;
+
         tracing::debug!(
             "sui: Storing objects_blob_object_id to RemoteState: {}",
             objects_blob_object_id
         );
 
-        let mut ptb = ProgrammableTransactionBuilder::new();
-
-        // Get object references
-        let state_ref = self.get_state_object_ref().await?;
         let clock_ref = self.get_clock_object_ref().await?;
+        let functions = self.package_functions().await?;
+        let format = functions.contains("set_last_writer").then_some(format);
+        let symrefs = if functions.contains("set_symrefs") {
+            Some(symrefs)
+        } else {
+            if !symrefs.is_empty() {
+                tracing::warn!(
+                    "Package {} cannot record symrefs; republish it to keep {} on the remote",
+                    self.package_id,
+                    symrefs.keys().cloned().collect::<Vec<_>>().join(", ")
+                );
+            }
+            None
+        };
 
-        // Add objects as inputs
-        let state_arg = ptb.obj(ObjectArg::ImmOrOwnedObject(state_ref))?;
-        let clock_arg = ptb.obj(ObjectArg::SharedObject {
-            id: clock_ref.0,
-            initial_shared_version: SequenceNumber::from(1),
-            mutable: false,
-        })?;
+        // A retry after a timeout re-reads the RemoteState reference
+        let mut state_ref = state_ref;
+        retry_after_timeout(
+            MAX_TIMEOUT_RETRIES,
+            tokio::time::Duration::from_secs(1),
+            || {
+                self.execute_upsert(
+                    &refs,
+                    &objects_blob_object_id,
+                    symrefs.as_ref(),
+                    format.as_deref(),
+                    state_ref.take(),
+                    clock_ref,
+                )
+            },
+            || self.upsert_landed(&refs, &objects_blob_object_id),
+        )
+        .await
+    }
 
-        // 1. Batch upsert all refs
-        for (ref_name, git_sha1) in refs {
-            let ref_arg = ptb.pure(ref_name)?;
-            let sha_arg = ptb.pure(git_sha1)?;
+    /// Names of the functions the RemoteState's package exposes in `remote_state`
+    async fn package_functions(&self) -> Result<BTreeSet<String>> {
+        let modules = self
+            .client
+            .read_api()
+            .get_normalized_move_modules_by_package(self.package_id)
+            .await
+            .with_context(|| format!("Failed to read package {}", self.package_id))?;
+        Ok(modules
+            .get("remote_state")
+            .map(|module| module.exposed_functions.keys().cloned().collect())
+            .unwrap_or_default())
+    }
 
-            ptb.programmable_move_call(
-                self.package_id,
-                Identifier::new("remote_state")?,
-                Identifier::new("upsert_ref")?,
-                vec![], // no type arguments
-                vec![state_arg, ref_arg, sha_arg],
-            );
-        }
+    /// Who last wrote the objects map, and the format header they recorded, if any
+    pub async fn last_writer(&self) -> Result<Option<LastWriter>> {
+        let field = self.state_field(LAST_WRITER_FIELD).await?;
+        LastWriter::from_field(field.as_ref()).context("Failed to parse RemoteState last writer")
+    }
 
-        // 2. Update objects blob object ID
-        let objects_blob_object_arg = ptb.pure(objects_blob_object_id)?;
+    /// Execute the upsert PTB once, re-reading a stale RemoteState reference if needed
+    async fn execute_upsert(
+        &self,
+        refs: &[(String, String)],
+        objects_blob_object_id: &str,
+        symrefs: Option<&BTreeMap<String, String>>,
+        format: Option<&str>,
+        state_ref: Option<ObjectRef>,
+        clock_ref: ObjectRef,
+    ) -> Result<()> {
+        const MAX_STALE_RETRIES: u32 = 3;
+        const RETRY_DELAY_MS: u64 = 500;
 
-        ptb.programmable_move_call(
-            self.package_id,
-            Identifier::new("remote_state")?,
-            Identifier::new("update_objects_blob")?,
-            vec![], // no type arguments
-            vec![state_arg, objects_blob_object_arg, clock_arg],
-        );
+        let retries = if state_ref.is_some() {
+            0
+        } else {
+            MAX_STALE_RETRIES
+        };
+        let mut state_ref = match state_ref {
+            Some(state_ref) => state_ref,
+            None => self.get_state_object_ref().await?,
+        };
 
-        // 3. Release lock
-        ptb.programmable_move_call(
-            self.package_id,
-            Identifier::new("remote_state")?,
-            Identifier::new("release_lock")?,
-            vec![], // no type arguments
-            vec![state_arg],
-        );
+        for attempt in 0..=retries {
+            let ptb = build_upsert_ptb(
+                self.package_id,
+                refs,
+                objects_blob_object_id,
+                symrefs,
+                format,
+                state_ref,
+                clock_ref.0,
+            )?;
+
+            // Build and execute transaction (all operations atomic)
+            match self.execute_ptb(ptb, DEFAULT_GAS_BUDGET).await {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < retries && is_stale_object_error(&e) => {
+                    tracing::warn!(
+                        "  RemoteState version {} not yet available, re-reading: {:#}",
+                        state_ref.1,
+                        e
+                    );
+                    tokio::time::sleep(tokio::time::Duration::from_millis(RETRY_DELAY_MS)).await;
+                    state_ref = self.get_state_object_ref().await?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
 
-        // Build and execute transaction (all operations atomic)
-        self.execute_ptb(ptb, DEFAULT_GAS_BUDGET).await?;
+        unreachable!("the final attempt always returns")
+    }
 
-        Ok(())
+    /// Whether the RemoteState already holds `refs` and `objects_blob_object_id`
+    async fn upsert_landed(
+        &self,
+        refs: &[(String, String)],
+        objects_blob_object_id: &str,
+    ) -> Result<bool> {
+        let current_blob = self.get_objects_blob_object_id().await?;
+        if current_blob.as_deref() != Some(objects_blob_object_id) {
+            return Ok(false);
+        }
+        let current_refs = self.read_refs().await?;
+        Ok(refs_applied(&current_refs, refs))
+    }
+
+    /// The RemoteState's post-transaction reference, from a response's mutated objects
+    fn state_ref_from_response(&self, response: &SuiTransactionBlockResponse) -> Option<ObjectRef> {
+        let mutated = response.effects.as_ref()?.mutated();
+        let state_ref = mutated_object_ref(&mutated, self.state_object_id?);
+        if state_ref.is_none() {
+            tracing::debug!("sui: RemoteState missing from transaction effects");
+        }
+        state_ref
     }
 
     /// Execute a PTB with proper gas handling
@@ -1060,7 +2003,7 @@ impl SuiClient {
         &self,
         ptb: ProgrammableTransactionBuilder,
         gas_budget: u64,
-    ) -> Result<()> {
+    ) -> Result<SuiTransactionBlockResponse> {
         tracing::debug!("sui: Executing programmable transaction...");
         tracing::debug!("  Selecting gas coins for budget: {} MIST", gas_budget);
         // 1. Select enough gas coins to cover the budget
@@ -1072,29 +2015,10 @@ impl SuiClient {
             .context("Failed to fetch gas coins")?;
 
         // Collect coins until we have enough balance
-        let mut gas_coins = Vec::new();
-        let mut total_balance = 0u64;
-
-        for coin in coins.data {
-            total_balance += coin.balance;
-            gas_coins.push(coin);
-
-            if total_balance >= gas_budget {
-                break;
-            }
-        }
-
-        if total_balance < gas_budget {
-            anyhow::bail!(
-                "Insufficient gas: need {} MIST, but only have {} MIST available",
-                gas_budget,
-                total_balance
-            );
-        }
-
-        if gas_coins.is_empty() {
-            anyhow::bail!("No gas coins available for sender");
-        }
+        let mut gas_coins = coins.data;
+        let balances: Vec<u64> = gas_coins.iter().map(|c| c.balance).collect();
+        let selected = select_gas_coins(&balances, gas_budget, self.gas_reserve_mist)?;
+        gas_coins.truncate(selected);
 
         tracing::debug!("  Fetching current gas price...");
         // 2. Get current gas price
@@ -1154,8 +2078,9 @@ impl SuiClient {
 
         // 7. Check for errors in transaction execution
         if let Some(effects) = &response.effects {
-            if effects.status().is_err() {
-                anyhow::bail!("Transaction execution failed: {:?}", effects.status());
+            // The abort itself, unescaped, so is_lock_held_abort can read where it happened
+            if let SuiExecutionStatus::Failure { error } = effects.status() {
+                anyhow::bail!("Transaction execution failed: {}", error);
             }
         }
 
@@ -1164,7 +2089,7 @@ impl SuiClient {
             response.digest
         );
 
-        Ok(())
+        Ok(response)
     }
 
     /// Execute a PTB and return the first created object ID
@@ -1182,29 +2107,10 @@ impl SuiClient {
             .context("Failed to fetch gas coins")?;
 
         // Collect coins until we have enough balance
-        let mut gas_coins = Vec::new();
-        let mut total_balance = 0u64;
-
-        for coin in coins.data {
-            total_balance += coin.balance;
-            gas_coins.push(coin);
-
-            if total_balance >= gas_budget {
-                break;
-            }
-        }
-
-        if total_balance < gas_budget {
-            anyhow::bail!(
-                "Insufficient gas: need {} MIST, but only have {} MIST available",
-                gas_budget,
-                total_balance
-            );
-        }
-
-        if gas_coins.is_empty() {
-            anyhow::bail!("No gas coins available for sender");
-        }
+        let mut gas_coins = coins.data;
+        let balances: Vec<u64> = gas_coins.iter().map(|c| c.balance).collect();
+        let selected = select_gas_coins(&balances, gas_budget, self.gas_reserve_mist)?;
+        gas_coins.truncate(selected);
 
         // 2. Get current gas price
         let gas_price = self
@@ -1253,8 +2159,8 @@ impl SuiClient {
 
         // 7. Check for errors in transaction execution
         if let Some(effects) = &response.effects {
-            if effects.status().is_err() {
-                anyhow::bail!("Transaction execution failed: {:?}", effects.status());
+            if let SuiExecutionStatus::Failure { error } = effects.status() {
+                anyhow::bail!("Transaction execution failed: {}", error);
             }
         }
 
@@ -1284,6 +2190,506 @@ impl SuiClient {
     }
 }
 
+/// True for `<package>::remote_state::RemoteState`, whichever package published it
+fn is_remote_state_type(type_str: &str) -> bool {
+    type_str
+        .strip_suffix(REMOTE_STATE_TYPE)
+        .and_then(|package| package.strip_suffix("::"))
+        .is_some_and(|package| ObjectID::from_hex_literal(package).is_ok())
+}
+
+/// The Clock as a read-only shared input (it is created at genesis, so shared at version 1)
+fn clock_object_arg(clock_id: ObjectID) -> ObjectArg {
+    ObjectArg::SharedObject {
+        id: clock_id,
+        initial_shared_version: SequenceNumber::from(1),
+        mutable: false,
+    }
+}
+
+/// PTB for `acquire_lock`
+fn build_acquire_lock_ptb(
+    package_id: ObjectID,
+    state_ref: ObjectRef,
+    clock_id: ObjectID,
+    timeout_ms: u64,
+) -> Result<ProgrammableTransactionBuilder> {
+    let mut ptb = ProgrammableTransactionBuilder::new();
+
+    // Add objects as inputs
+    let state_arg = ptb.obj(ObjectArg::ImmOrOwnedObject(state_ref))?;
+    let clock_arg = ptb.obj(clock_object_arg(clock_id))?;
+
+    // Call acquire_lock
+    let timeout_arg = ptb.pure(timeout_ms)?;
+
+    ptb.programmable_move_call(
+        package_id,
+        Identifier::new("remote_state")?,
+        Identifier::new("acquire_lock")?,
+        vec![], // no type arguments
+        vec![state_arg, clock_arg, timeout_arg],
+    );
+
+    Ok(ptb)
+}
+
+/// PTB for `upsert_refs_and_update_objects`: upsert refs, update objects blob, replace the
+/// symrefs (if given), record the map's format header (if given), release lock
+fn build_upsert_ptb(
+    package_id: ObjectID,
+    refs: &[(String, String)],
+    objects_blob_object_id: &str,
+    symrefs: Option<&BTreeMap<String, String>>,
+    format: Option<&str>,
+    state_ref: ObjectRef,
+    clock_id: ObjectID,
+) -> Result<ProgrammableTransactionBuilder> {
+    let mut ptb = ProgrammableTransactionBuilder::new();
+
+    // Add objects as inputs
+    let state_arg = ptb.obj(ObjectArg::ImmOrOwnedObject(state_ref))?;
+    let clock_arg = ptb.obj(clock_object_arg(clock_id))?;
+
+    // 1. Batch upsert all refs
+    for (ref_name, git_sha1) in refs {
+        let ref_arg = ptb.pure(ref_name.clone())?;
+        let sha_arg = ptb.pure(git_sha1.clone())?;
+
+        ptb.programmable_move_call(
+            package_id,
+            Identifier::new("remote_state")?,
+            Identifier::new("upsert_ref")?,
+            vec![], // no type arguments
+            vec![state_arg, ref_arg, sha_arg],
+        );
+    }
+
+    // 2. Update objects blob object ID
+    let objects_blob_object_arg = ptb.pure(objects_blob_object_id.to_string())?;
+
+    ptb.programmable_move_call(
+        package_id,
+        Identifier::new("remote_state")?,
+        Identifier::new("update_objects_blob")?,
+        vec![], // no type arguments
+        vec![state_arg, objects_blob_object_arg, clock_arg],
+    );
+
+    // 3. Replace the symrefs
+    if let Some(symrefs) = symrefs {
+        let names_arg = ptb.pure(symrefs.keys().cloned().collect::<Vec<_>>())?;
+        let targets_arg = ptb.pure(symrefs.values().cloned().collect::<Vec<_>>())?;
+        ptb.programmable_move_call(
+            package_id,
+            Identifier::new("remote_state")?,
+            Identifier::new("set_symrefs")?,
+            vec![], // no type arguments
+            vec![state_arg, names_arg, targets_arg, clock_arg],
+        );
+    }
+
+    // 4. Record who wrote it, in what format
+    if let Some(format) = format {
+        let format_arg = ptb.pure(format.to_string())?;
+        ptb.programmable_move_call(
+            package_id,
+            Identifier::new("remote_state")?,
+            Identifier::new("set_last_writer")?,
+            vec![], // no type arguments
+            vec![state_arg, format_arg, clock_arg],
+        );
+    }
+
+    // 5. Release lock
+    ptb.programmable_move_call(
+        package_id,
+        Identifier::new("remote_state")?,
+        Identifier::new("release_lock")?,
+        vec![], // no type arguments
+        vec![state_arg],
+    );
+
+    Ok(ptb)
+}
+
+/// PTB for `delete_refs_and_release_lock` and `repair_refs_and_release_lock`: upsert
+/// `upserts`, delete `names`, then release the lock if `release`
+fn build_edit_refs_ptb(
+    package_id: ObjectID,
+    upserts: &[(String, String)],
+    names: &[String],
+    state_ref: ObjectRef,
+    release: bool,
+) -> Result<ProgrammableTransactionBuilder> {
+    let mut ptb = ProgrammableTransactionBuilder::new();
+    let state_arg = ptb.obj(ObjectArg::ImmOrOwnedObject(state_ref))?;
+
+    for (name, git_sha1) in upserts {
+        let ref_arg = ptb.pure(name.clone())?;
+        let sha_arg = ptb.pure(git_sha1.clone())?;
+        ptb.programmable_move_call(
+            package_id,
+            Identifier::new("remote_state")?,
+            Identifier::new("upsert_ref")?,
+            vec![], // no type arguments
+            vec![state_arg, ref_arg, sha_arg],
+        );
+    }
+
+    for name in names {
+        let ref_arg = ptb.pure(name.clone())?;
+        ptb.programmable_move_call(
+            package_id,
+            Identifier::new("remote_state")?,
+            Identifier::new("delete_ref")?,
+            vec![], // no type arguments
+            vec![state_arg, ref_arg],
+        );
+    }
+
+    if release {
+        ptb.programmable_move_call(
+            package_id,
+            Identifier::new("remote_state")?,
+            Identifier::new("release_lock")?,
+            vec![], // no type arguments
+            vec![state_arg],
+        );
+    }
+
+    Ok(ptb)
+}
+
+/// Reference of `object_id` among a transaction's mutated objects
+fn mutated_object_ref(mutated: &[OwnedObjectRef], object_id: ObjectID) -> Option<ObjectRef> {
+    mutated
+        .iter()
+        .find(|owned| owned.object_id() == object_id)
+        .map(|owned| owned.reference.to_object_ref())
+}
+
+/// Whether a transaction failed because an input object version isn't known to the fullnode yet
+fn is_stale_object_error(e: &anyhow::Error) -> bool {
+    let message = format!("{:#}", e);
+    message.contains("not available for consumption") || message.contains("version not found")
+}
+
+/// Where and with which code a Move call aborted
+#[derive(Debug, PartialEq, Eq)]
+struct MoveAbort<'a> {
+    module: &'a str,
+    function: Option<&'a str>,
+    code: u64,
+}
+
+impl<'a> MoveAbort<'a> {
+    /// Parse the `MoveAbort(MoveLocation { .. }, code)` a failed transaction's status reports
+    fn parse(message: &'a str) -> Option<Self> {
+        let abort = &message[message.find("MoveAbort(MoveLocation {")?..];
+        let (_, module) = abort.split_once("name: Identifier(\"")?;
+        let (module, _) = module.split_once('"')?;
+        let (_, function) = abort.split_once("function_name: ")?;
+        let (function, rest) = function.split_once(" }, ")?;
+        let function = match function {
+            "None" => None,
+            name => Some(name.strip_prefix("Some(\"")?.strip_suffix("\")")?),
+        };
+        let code = rest.split_once(')')?.0.parse().ok()?;
+        Some(Self {
+            module,
+            function,
+            code,
+        })
+    }
+}
+
+/// Whether `acquire_lock` aborted with `ERR_LOCK_HELD`
+fn is_lock_held_abort(e: &anyhow::Error) -> bool {
+    let message = format!("{:#}", e);
+    MoveAbort::parse(&message)
+        == Some(MoveAbort {
+            module: "remote_state",
+            function: Some("acquire_lock"),
+            code: ERR_LOCK_HELD,
+        })
+}
+
+/// Sender, time and ref upserts of a programmable transaction
+fn ref_transaction(response: &SuiTransactionBlockResponse) -> Option<RefTransaction> {
+    let data = &response.transaction.as_ref()?.data;
+    let SuiTransactionBlockKind::ProgrammableTransaction(ptb) = data.transaction() else {
+        return None;
+    };
+    Some(RefTransaction {
+        update: RefUpdate {
+            updated_by: data.sender().to_string(),
+            timestamp_ms: response.timestamp_ms,
+            digest: response.digest.to_string(),
+        },
+        upserts: upserted_refs(ptb),
+    })
+}
+
+/// The `ref_name` and `git_sha1` arguments of each `remote_state::upsert_ref` call in `ptb`
+///
+/// Each argument is resolved through the input its call names, wherever it sits among the
+/// PTB's inputs.
+fn upserted_refs(ptb: &SuiProgrammableTransactionBlock) -> Vec<(String, String)> {
+    let pure_string = |argument: Option<&SuiArgument>| match argument? {
+        SuiArgument::Input(index) => ptb
+            .inputs
+            .get(usize::from(*index))?
+            .pure()?
+            .to_json_value()
+            .as_str()
+            .map(String::from),
+        _ => None,
+    };
+    ptb.commands
+        .iter()
+        .filter_map(|command| match command {
+            SuiCommand::MoveCall(call)
+                if call.module == "remote_state" && call.function == "upsert_ref" =>
+            {
+                Some((
+                    pure_string(call.arguments.get(1))?,
+                    pure_string(call.arguments.get(2))?,
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Name of the RemoteState's `0x1::string::String`-keyed dynamic field `name`
+fn string_field_name(name: &str) -> Result<DynamicFieldName> {
+    Ok(DynamicFieldName {
+        type_: parse_sui_type_tag("0x1::string::String")?,
+        value: serde_json::Value::String(name.to_string()),
+    })
+}
+
+/// ID of the RemoteState's `name` dynamic field, whether or not it exists
+fn state_field_id(state_object_id: ObjectID, name: &str) -> Result<ObjectID> {
+    let field_name = string_field_name(name)?;
+    Ok(dynamic_field::derive_dynamic_field_id(
+        state_object_id,
+        &field_name.type_,
+        &bcs::to_bytes(name)?,
+    )?)
+}
+
+/// A RemoteState transaction's writes to dynamic fields of `parent_id` (the refs table or the
+/// RemoteState itself), and deletions
+fn state_transaction(
+    response: &SuiTransactionBlockResponse,
+    parent_id: ObjectID,
+) -> StateTransaction {
+    let parent = SuiAddress::from(parent_id);
+    let changes = response
+        .object_changes
+        .iter()
+        .flatten()
+        .filter_map(|change| match change {
+            ObjectChange::Created {
+                object_id,
+                version,
+                owner: Owner::ObjectOwner(owner),
+                ..
+            }
+            | ObjectChange::Mutated {
+                object_id,
+                version,
+                owner: Owner::ObjectOwner(owner),
+                ..
+            } if *owner == parent => Some(FieldChange::Written {
+                object_id: object_id.to_string(),
+                version: version.value(),
+            }),
+            ObjectChange::Deleted { object_id, .. } | ObjectChange::Wrapped { object_id, .. } => {
+                Some(FieldChange::Deleted {
+                    object_id: object_id.to_string(),
+                })
+            }
+            _ => None,
+        })
+        .collect();
+    StateTransaction {
+        digest: response.digest.to_string(),
+        changes,
+    }
+}
+
+/// Whether a transaction failed with a gateway timeout, after which it may still have committed
+fn is_timeout_error(e: &anyhow::Error) -> bool {
+    format!("{:#}", e).contains("504")
+}
+
+/// Host and port an `http(s)://` RPC URL connects to
+fn rpc_address(url: &str) -> Option<(String, u16)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let default_port = match scheme.to_ascii_lowercase().as_str() {
+        "http" => 80,
+        "https" => 443,
+        _ => return None,
+    };
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority.rsplit('@').next()?;
+    let (host, port) = match host_port.strip_prefix('[') {
+        // IPv6 literal
+        Some(bracketed) => {
+            let (host, after) = bracketed.split_once(']')?;
+            (host, after.strip_prefix(':'))
+        }
+        None => match host_port.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_port, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => default_port,
+    };
+    (!host.is_empty()).then(|| (host.to_string(), port))
+}
+
+/// Fail unless the endpoint of `rpc` accepts a TCP connection within `timeout`
+///
+/// The Sui SDK only bounds whole requests, so an unreachable endpoint would otherwise hang for
+/// the full request timeout (and again for every retry).
+async fn probe_connect(rpc: &str, timeout: std::time::Duration) -> Result<()> {
+    let Some((host, port)) = rpc_address(rpc) else {
+        // Leave URLs we don't understand to the SDK
+        return Ok(());
+    };
+    let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .with_context(|| format!("Failed to resolve Sui RPC host {}", host))?
+        .collect();
+    let mut last_error = None;
+    for addr in addrs {
+        match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => return Ok(()),
+            Ok(Err(e)) => last_error = Some(e),
+            Err(_) => {
+                last_error = Some(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("connecting to {} timed out", addr),
+                ))
+            }
+        }
+    }
+    match last_error {
+        Some(e) => Err(e).with_context(|| {
+            format!(
+                "Could not connect to Sui RPC {} within {:?} (connect_timeout_secs)",
+                rpc, timeout
+            )
+        }),
+        None => anyhow::bail!("Sui RPC host {} resolved to no addresses", host),
+    }
+}
+
+/// Whether every `(ref, sha)` in `refs` is already set in `current`
+fn refs_applied(current: &BTreeMap<String, String>, refs: &[(String, String)]) -> bool {
+    refs.iter()
+        .all(|(name, sha)| current.get(name) == Some(sha))
+}
+
+/// Run `attempt`, and `delay` after a 504 ask `landed` whether it committed anyway before
+/// retrying
+///
+/// A timed-out transaction may still have executed; retrying it blindly would spend gas on
+/// a duplicate. Up to `max_retries` retries are made while `landed` reports it didn't commit.
+async fn retry_after_timeout<A, AF, L, LF>(
+    max_retries: u32,
+    delay: std::time::Duration,
+    mut attempt: A,
+    mut landed: L,
+) -> Result<()>
+where
+    A: FnMut() -> AF,
+    AF: std::future::Future<Output = Result<()>>,
+    L: FnMut() -> LF,
+    LF: std::future::Future<Output = Result<bool>>,
+{
+    let mut retries = 0;
+    loop {
+        let e = match attempt().await {
+            Ok(()) => return Ok(()),
+            Err(e) if is_timeout_error(&e) => e,
+            Err(e) => return Err(e),
+        };
+
+        tracing::warn!(
+            "  Transaction timed out, checking whether it committed: {:#}",
+            e
+        );
+        tokio::time::sleep(delay).await;
+        if landed()
+            .await
+            .context("Failed to re-read RemoteState after a timeout")?
+        {
+            tracing::info!("  Transaction committed despite the timeout");
+            return Ok(());
+        }
+        if retries == max_retries {
+            return Err(e.context(format!(
+                "Transaction timed out {} times and did not commit",
+                retries + 1
+            )));
+        }
+        retries += 1;
+        tracing::warn!(
+            "  Transaction did not commit, retrying ({}/{})",
+            retries,
+            max_retries
+        );
+    }
+}
+
+/// Number of leading coins needed to cover `gas_budget`
+///
+/// Fails if the coins can't cover the budget, or if spending the whole budget would drop
+/// their combined balance below `reserve`.
+fn select_gas_coins(balances: &[u64], gas_budget: u64, reserve: u64) -> Result<usize> {
+    if balances.is_empty() {
+        anyhow::bail!("No gas coins available for sender");
+    }
+
+    let mut selected = 0;
+    let mut selected_balance = 0u128;
+    for &balance in balances {
+        if selected_balance >= gas_budget as u128 {
+            break;
+        }
+        selected_balance += balance as u128;
+        selected += 1;
+    }
+
+    if selected_balance < gas_budget as u128 {
+        anyhow::bail!(
+            "Insufficient gas: need {} MIST, but only have {} MIST available",
+            gas_budget,
+            selected_balance
+        );
+    }
+
+    let total_balance: u128 = balances.iter().map(|&b| b as u128).sum();
+    if total_balance - (gas_budget as u128) < reserve as u128 {
+        anyhow::bail!(
+            "push would drop balance below configured reserve: \
+             gas budget {} MIST from {} MIST leaves less than the {} MIST reserve",
+            gas_budget,
+            total_balance,
+            reserve
+        );
+    }
+
+    Ok(selected)
+}
+
 fn parse_num_blob_id(s: &str) -> Result<String> {
     if let Some(number) = BigUint::parse_bytes(s.as_bytes(), 10) {
         let bytes = number.to_bytes_le();
@@ -1299,11 +2705,441 @@ fn parse_num_blob_id(s: &str) -> Result<String> {
 
 #[cfg(test)]
 mod tests {
+    use serde_json::json;
+    use sui_sdk::rpc_types::SuiObjectRef;
+    use sui_types::{
+        base_types::ObjectDigest,
+        object::Owner,
+        transaction::{CallArg, Command},
+    };
+
     use super::*;
 
+    #[test]
+    fn test_rpc_address() {
+        assert_eq!(
+            rpc_address("https://fullnode.testnet.sui.io:443"),
+            Some(("fullnode.testnet.sui.io".to_string(), 443))
+        );
+        assert_eq!(
+            rpc_address("https://rpc.example/v1?x=1"),
+            Some(("rpc.example".to_string(), 443))
+        );
+        assert_eq!(
+            rpc_address("http://127.0.0.1:9000"),
+            Some(("127.0.0.1".to_string(), 9000))
+        );
+        assert_eq!(
+            rpc_address("http://[::1]:9000/"),
+            Some(("::1".to_string(), 9000))
+        );
+        assert_eq!(rpc_address("http://[::1]"), Some(("::1".to_string(), 80)));
+        assert_eq!(rpc_address("ws://localhost:9000"), None);
+        assert_eq!(rpc_address("localhost:9000"), None);
+    }
+
+    #[test]
+    fn test_is_remote_state_type() {
+        assert!(is_remote_state_type("0x2a::remote_state::RemoteState"));
+        assert!(is_remote_state_type(&format!(
+            "{}::remote_state::RemoteState",
+            ObjectID::random().to_hex_literal()
+        )));
+        assert!(!is_remote_state_type("0x2::coin::Coin<0x2::sui::SUI>"));
+        assert!(!is_remote_state_type(
+            "0x2a::other_remote_state::RemoteState"
+        ));
+        assert!(!is_remote_state_type("remote_state::RemoteState"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_connect() {
+        let timeout = std::time::Duration::from_secs(5);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        probe_connect(&url, timeout).await.unwrap();
+
+        // Nothing listens on a port once its listener is dropped
+        drop(listener);
+        let err = format!("{:#}", probe_connect(&url, timeout).await.unwrap_err());
+        assert!(err.contains("Could not connect to Sui RPC"), "{}", err);
+        assert!(err.contains("connect_timeout_secs"), "{}", err);
+    }
+
     #[test]
     fn test_clock_object_id() {
         let clock_id = ObjectID::from_hex_literal(CLOCK_OBJECT_ID).unwrap();
         assert_eq!(clock_id.to_string(), CLOCK_OBJECT_ID);
     }
+
+    /// IDs of the shared objects a PTB takes as inputs
+    fn shared_inputs(ptb: ProgrammableTransactionBuilder) -> Vec<ObjectID> {
+        ptb.finish()
+            .inputs
+            .into_iter()
+            .filter_map(|input| match input {
+                CallArg::Object(ObjectArg::SharedObject { id, .. }) => Some(id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ptbs_use_the_configured_clock() {
+        let package_id = ObjectID::from_hex_literal("0xabc").unwrap();
+        let clock_id = ObjectID::from_hex_literal("0x1234").unwrap();
+        let state_ref = (
+            ObjectID::from_hex_literal("0x99").unwrap(),
+            SequenceNumber::from(7),
+            ObjectDigest::new([0; 32]),
+        );
+
+        let lock = build_acquire_lock_ptb(package_id, state_ref, clock_id, 60_000).unwrap();
+        assert_eq!(shared_inputs(lock), [clock_id]);
+
+        let refs = [("refs/heads/main".to_string(), "a".repeat(40))];
+        let symrefs = BTreeMap::from([("HEAD".to_string(), "refs/heads/main".to_string())]);
+        let upsert = build_upsert_ptb(
+            package_id,
+            &refs,
+            "0x55",
+            Some(&symrefs),
+            Some("f"),
+            state_ref,
+            clock_id,
+        )
+        .unwrap();
+        assert_eq!(shared_inputs(upsert), [clock_id]);
+    }
+
+    #[test]
+    fn test_upsert_ptb_records_symrefs_and_the_format_header_when_given() {
+        let package_id = ObjectID::from_hex_literal("0xabc").unwrap();
+        let clock_id = ObjectID::from_hex_literal(CLOCK_OBJECT_ID).unwrap();
+        let state_ref = (
+            ObjectID::from_hex_literal("0x99").unwrap(),
+            SequenceNumber::from(7),
+            ObjectDigest::new([0; 32]),
+        );
+        let refs = [("refs/heads/main".to_string(), "a".repeat(40))];
+        let symrefs = BTreeMap::from([("HEAD".to_string(), "refs/heads/main".to_string())]);
+        let functions = |symrefs, format| {
+            build_upsert_ptb(
+                package_id, &refs, "0x55", symrefs, format, state_ref, clock_id,
+            )
+            .unwrap()
+            .finish()
+            .commands
+            .into_iter()
+            .filter_map(|command| match command {
+                Command::MoveCall(call) => Some(call.function.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            functions(
+                Some(&symrefs),
+                Some("features=1;writer=0.2.0;requires=0.1.0")
+            ),
+            [
+                "upsert_ref",
+                "update_objects_blob",
+                "set_symrefs",
+                "set_last_writer",
+                "release_lock"
+            ]
+        );
+        // Packages without set_symrefs and set_last_writer get the PTB they always did
+        assert_eq!(
+            functions(None, None),
+            ["upsert_ref", "update_objects_blob", "release_lock"]
+        );
+    }
+
+    #[test]
+    fn test_upserted_refs_follow_each_call_arguments() {
+        let main = "a".repeat(40);
+        let dev = "b".repeat(40);
+        let pure = |value: &str| json!({ "type": "pure", "valueType": "0x1::string::String", "value": value });
+        let call = |function: &str, inputs: &[u16]| {
+            json!({
+                "MoveCall": {
+                    "package": "0xabc",
+                    "module": "remote_state",
+                    "function": function,
+                    "arguments": [{ "Input": 0 }]
+                        .into_iter()
+                        .chain(inputs.iter().map(|i| json!({ "Input": i })))
+                        .collect::<Vec<_>>(),
+                },
+            })
+        };
+        // Inputs in an order no builder of ours produces: dev's SHA-1 sits next to main's
+        // name, and the objects map ID and a deleted ref's name sit between the pairs
+        let kind: SuiTransactionBlockKind = serde_json::from_value(json!({
+            "kind": "ProgrammableTransaction",
+            "inputs": [
+                // Stands in for the RemoteState, which is never decoded
+                { "type": "pure", "valueType": "u64", "value": "7" },
+                pure("refs/heads/main"),
+                pure(&dev),
+                pure("0xobjects"),
+                pure("refs/heads/dev"),
+                pure(&main),
+                pure("refs/heads/gone"),
+            ],
+            "transactions": [
+                call("upsert_ref", &[1, 5]),
+                call("delete_ref", &[6]),
+                call("upsert_ref", &[4, 2]),
+                call("update_objects_blob", &[3]),
+            ],
+        }))
+        .unwrap();
+        let SuiTransactionBlockKind::ProgrammableTransaction(ptb) = kind else {
+            panic!("not a programmable transaction");
+        };
+
+        assert_eq!(
+            upserted_refs(&ptb),
+            [
+                ("refs/heads/main".to_string(), main.clone()),
+                ("refs/heads/dev".to_string(), dev.clone()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_edit_refs_ptb_takes_each_name() {
+        let package_id = ObjectID::from_hex_literal("0xabc").unwrap();
+        let state_ref = (
+            ObjectID::from_hex_literal("0x99").unwrap(),
+            SequenceNumber::from(7),
+            ObjectDigest::new([0; 32]),
+        );
+        let names = ["refs/heads/a".to_string(), "refs/heads/b".to_string()];
+        let inputs = build_edit_refs_ptb(package_id, &[], &names, state_ref, true)
+            .unwrap()
+            .finish()
+            .inputs;
+        // The RemoteState, then one name per delete; no clock is needed
+        assert_eq!(inputs.len(), 3);
+        assert_eq!(
+            inputs[0],
+            CallArg::Object(ObjectArg::ImmOrOwnedObject(state_ref))
+        );
+
+        // An upsert takes a name and a SHA-1
+        let upserts = [("refs/heads/c".to_string(), "c".repeat(40))];
+        let inputs = build_edit_refs_ptb(package_id, &upserts, &names, state_ref, true)
+            .unwrap()
+            .finish()
+            .inputs;
+        assert_eq!(inputs.len(), 5);
+    }
+
+    fn owned(object_id: ObjectID, version: u64, digest: u8) -> OwnedObjectRef {
+        OwnedObjectRef {
+            owner: Owner::AddressOwner(object_id.into()),
+            reference: SuiObjectRef {
+                object_id,
+                version: SequenceNumber::from_u64(version),
+                digest: ObjectDigest::new([digest; 32]),
+            },
+        }
+    }
+
+    #[test]
+    fn test_mutated_object_ref() {
+        let state_id = ObjectID::from_hex_literal("0x5").unwrap();
+        let gas_id = ObjectID::from_hex_literal("0x7").unwrap();
+
+        // Lock transaction effects: the gas coin and the RemoteState were both mutated
+        let mutated = vec![owned(gas_id, 11, 1), owned(state_id, 42, 2)];
+        assert_eq!(
+            mutated_object_ref(&mutated, state_id),
+            Some((
+                state_id,
+                SequenceNumber::from_u64(42),
+                ObjectDigest::new([2; 32])
+            ))
+        );
+
+        // Missing from the effects: caller falls back to a fresh read
+        assert_eq!(mutated_object_ref(&mutated[..1], state_id), None);
+        assert_eq!(mutated_object_ref(&[], state_id), None);
+    }
+
+    #[test]
+    fn test_is_stale_object_error() {
+        let stale = anyhow::anyhow!(
+            "Object (0x5, SequenceNumber(41)) is not available for consumption, its current version: SequenceNumber(42)"
+        )
+        .context("Failed to execute transaction");
+        assert!(is_stale_object_error(&stale));
+        assert!(is_stale_object_error(&anyhow::anyhow!(
+            "Object version not found"
+        )));
+        assert!(!is_stale_object_error(&anyhow::anyhow!("Insufficient gas")));
+    }
+
+    #[test]
+    fn test_is_lock_held_abort() {
+        let held = anyhow::anyhow!(
+            "Transaction failed: MoveAbort(MoveLocation { module: ModuleId { address: 0x2a, \
+             name: Identifier(\"remote_state\") }, function: 3, instruction: 24, \
+             function_name: Some(\"acquire_lock\") }, 1) in command 0"
+        );
+        assert!(is_lock_held_abort(&held));
+        let not_authorized = anyhow::anyhow!(
+            "MoveAbort(MoveLocation { module: ModuleId { address: 0x2a, \
+             name: Identifier(\"remote_state\") }, function: 3, instruction: 5, \
+             function_name: Some(\"acquire_lock\") }, 5) in command 0"
+        );
+        assert!(!is_lock_held_abort(&not_authorized));
+        assert!(!is_lock_held_abort(&anyhow::anyhow!("Insufficient gas")));
+        // The same code from another function or module is some other error
+        let other_module = anyhow::anyhow!(
+            "MoveAbort(MoveLocation { module: ModuleId { address: 0x2, \
+             name: Identifier(\"dynamic_field\") }, function: 11, instruction: 0, \
+             function_name: Some(\"acquire_lock\") }, 1) in command 0"
+        );
+        assert!(!is_lock_held_abort(&other_module));
+    }
+
+    #[test]
+    fn test_parse_move_abort() {
+        let message = "MoveAbort(MoveLocation { module: ModuleId { address: 0x2a, \
+                       name: Identifier(\"remote_state\") }, function: 5, instruction: 9, \
+                       function_name: Some(\"release_lock\") }, 3) in command 2";
+        assert_eq!(
+            MoveAbort::parse(message),
+            Some(MoveAbort {
+                module: "remote_state",
+                function: Some("release_lock"),
+                code: 3,
+            })
+        );
+        let unnamed = "MoveAbort(MoveLocation { module: ModuleId { address: 0x2a, \
+                       name: Identifier(\"remote_state\") }, function: 5, instruction: 9, \
+                       function_name: None }, 12) in command 0";
+        assert_eq!(MoveAbort::parse(unnamed).unwrap().function, None);
+        assert_eq!(MoveAbort::parse("InsufficientGas"), None);
+    }
+
+    #[test]
+    fn test_refs_applied() {
+        let current: BTreeMap<String, String> = [
+            ("refs/heads/main".to_string(), "a".repeat(40)),
+            ("refs/heads/dev".to_string(), "b".repeat(40)),
+        ]
+        .into();
+        assert!(refs_applied(
+            &current,
+            &[("refs/heads/main".to_string(), "a".repeat(40))]
+        ));
+        assert!(!refs_applied(
+            &current,
+            &[("refs/heads/main".to_string(), "c".repeat(40))]
+        ));
+        assert!(!refs_applied(
+            &current,
+            &[("refs/heads/new".to_string(), "a".repeat(40))]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_after_commit_is_not_retried() {
+        let attempts = std::cell::Cell::new(0);
+        let checks = std::cell::Cell::new(0);
+
+        // The PTB committed but the fullnode answered 504
+        retry_after_timeout(
+            2,
+            std::time::Duration::ZERO,
+            || async {
+                attempts.set(attempts.get() + 1);
+                Err(anyhow::anyhow!(
+                    "Failed to execute transaction: 504 Gateway Timeout"
+                ))
+            },
+            || async {
+                checks.set(checks.get() + 1);
+                Ok(true)
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!((attempts.get(), checks.get()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_without_commit_is_retried() {
+        let attempts = std::cell::Cell::new(0);
+
+        retry_after_timeout(
+            2,
+            std::time::Duration::ZERO,
+            || async {
+                attempts.set(attempts.get() + 1);
+                if attempts.get() == 1 {
+                    anyhow::bail!("504 Gateway Timeout");
+                }
+                Ok(())
+            },
+            || async { Ok(false) },
+        )
+        .await
+        .unwrap();
+        assert_eq!(attempts.get(), 2);
+
+        // Gives up once retries run out; other errors are returned without checking
+        let err = retry_after_timeout(
+            1,
+            std::time::Duration::ZERO,
+            || async { anyhow::bail!("504 Gateway Timeout") },
+            || async { Ok(false) },
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("timed out 2 times"),
+            "{:#}",
+            err
+        );
+
+        let err = retry_after_timeout(
+            1,
+            std::time::Duration::ZERO,
+            || async { anyhow::bail!("Insufficient gas") },
+            || async { panic!("not a timeout") },
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Insufficient gas");
+    }
+
+    #[test]
+    fn test_select_gas_coins_with_reserve() {
+        let coins = [300, 500, 1_000];
+
+        // No reserve: take coins until the budget is covered
+        assert_eq!(select_gas_coins(&coins, 200, 0).unwrap(), 1);
+        assert_eq!(select_gas_coins(&coins, 700, 0).unwrap(), 2);
+        assert_eq!(select_gas_coins(&coins, 1_800, 0).unwrap(), 3);
+
+        // The reserve counts against the whole balance, not just the selected coins
+        assert_eq!(select_gas_coins(&coins, 700, 1_100).unwrap(), 2);
+        let err = select_gas_coins(&coins, 700, 1_101).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("push would drop balance below configured reserve"));
+
+        // Not enough gas at all
+        let err = select_gas_coins(&coins, 2_000, 0).unwrap_err();
+        assert!(err.to_string().contains("Insufficient gas"));
+        assert!(select_gas_coins(&[], 1, 0).is_err());
+    }
 }
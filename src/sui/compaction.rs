@@ -0,0 +1,93 @@
+//! Which entries of the on-chain refs table are dead, and may be removed to reclaim storage
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::commands::hooks::ZERO_SHA1;
+
+/// Why a refs table entry is dead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadReason {
+    /// Points at the zero SHA-1 (or nothing): the ref was deleted, not updated
+    Tombstone,
+    /// Points at an object the remote's objects map doesn't hold, so nothing can fetch it
+    Dangling,
+}
+
+impl fmt::Display for DeadReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeadReason::Tombstone => write!(f, "tombstone"),
+            DeadReason::Dangling => write!(f, "dangling"),
+        }
+    }
+}
+
+/// A refs table entry that can be removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadRef {
+    pub name: String,
+    pub sha: String,
+    pub reason: DeadReason,
+}
+
+/// Entries of `refs` that are tombstoned or point outside `objects` (the objects map)
+///
+/// A ref whose tip is in the objects map is live and never returned.
+pub fn dead_refs<V>(
+    refs: &BTreeMap<String, String>,
+    objects: &BTreeMap<String, V>,
+) -> Vec<DeadRef> {
+    refs.iter()
+        .filter_map(|(name, sha)| {
+            let reason = if sha.is_empty() || sha == ZERO_SHA1 {
+                DeadReason::Tombstone
+            } else if !objects.contains_key(sha) {
+                DeadReason::Dangling
+            } else {
+                return None;
+            };
+            Some(DeadRef {
+                name: name.clone(),
+                sha: sha.clone(),
+                reason,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_refs() {
+        let live = "a".repeat(40);
+        let gone = "b".repeat(40);
+        let refs = BTreeMap::from([
+            ("refs/heads/main".to_string(), live.clone()),
+            ("refs/tags/v1".to_string(), live.clone()),
+            ("refs/heads/old".to_string(), ZERO_SHA1.to_string()),
+            ("refs/heads/empty".to_string(), String::new()),
+            ("refs/heads/lost".to_string(), gone.clone()),
+        ]);
+        let objects = BTreeMap::from([(live, "0xblob".to_string())]);
+
+        let dead = dead_refs(&refs, &objects);
+        let names: Vec<(&str, DeadReason)> = dead
+            .iter()
+            .map(|dead| (dead.name.as_str(), dead.reason))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("refs/heads/empty", DeadReason::Tombstone),
+                ("refs/heads/lost", DeadReason::Dangling),
+                ("refs/heads/old", DeadReason::Tombstone),
+            ]
+        );
+        assert_eq!(dead[1].sha, gone);
+
+        // Nothing is dead when every tip is stored
+        assert!(dead_refs(&BTreeMap::new(), &objects).is_empty());
+    }
+}
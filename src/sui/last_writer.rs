@@ -0,0 +1,140 @@
+//! Who last wrote a remote's objects map, and the format header they recorded for it
+
+use anyhow::Result;
+use sui_sdk::rpc_types::{SuiMoveStruct, SuiMoveValue};
+
+/// Name of the RemoteState dynamic field holding its `LastWriter`
+pub const LAST_WRITER_FIELD: &str = "last_writer";
+
+/// The RemoteState's `last_writer` dynamic field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastWriter {
+    /// Address that made the write
+    pub writer: String,
+    /// Objects map the header describes (the remote's map when the header was recorded)
+    pub objects_blob_object_id: Option<String>,
+    /// Format header of that map, as `FormatHeader::encode` writes it
+    pub format: String,
+}
+
+impl LastWriter {
+    /// Read the last writer from the RemoteState's `last_writer` dynamic field object
+    ///
+    /// Remotes only ever written by helpers that record no header have no such field.
+    pub fn from_field(field: Option<&SuiMoveStruct>) -> Result<Option<Self>> {
+        let Some(field) = field else {
+            return Ok(None);
+        };
+        let value = match struct_field(field, "value")? {
+            Some(SuiMoveValue::Struct(value)) => value,
+            other => anyhow::bail!(
+                "Expected LastWriter struct in last_writer field, got {:?}",
+                other
+            ),
+        };
+
+        let writer = match struct_field(value, "writer")? {
+            Some(SuiMoveValue::Address(addr)) => addr.to_string(),
+            Some(SuiMoveValue::String(s)) => s.clone(),
+            other => anyhow::bail!("Expected address for last writer, got {:?}", other),
+        };
+        let objects_blob_object_id = match struct_field(value, "objects_blob_object_id")? {
+            None => None,
+            Some(SuiMoveValue::Option(inner)) => match inner.as_ref() {
+                None => None,
+                Some(SuiMoveValue::String(s)) => Some(s.clone()),
+                Some(other) => anyhow::bail!("Expected String objects map ID, got {:?}", other),
+            },
+            // JSON-RPC may flatten Some(value) to the value itself
+            Some(SuiMoveValue::String(s)) => Some(s.clone()),
+            Some(other) => anyhow::bail!("Expected String objects map ID, got {:?}", other),
+        };
+        let format = match struct_field(value, "format")? {
+            Some(SuiMoveValue::String(s)) => s.clone(),
+            other => anyhow::bail!("Expected String format header, got {:?}", other),
+        };
+
+        Ok(Some(Self {
+            writer,
+            objects_blob_object_id,
+            format,
+        }))
+    }
+
+    /// The format header, if it describes the objects map in SharedBlob `objects_object_id`
+    ///
+    /// A helper that records no header can write the map after one that does, leaving a
+    /// header behind that describes an earlier map.
+    pub fn format_of(&self, objects_object_id: &str) -> Option<&str> {
+        (self.objects_blob_object_id.as_deref() == Some(objects_object_id))
+            .then_some(self.format.as_str())
+    }
+}
+
+fn struct_field<'a>(fields: &'a SuiMoveStruct, name: &str) -> Result<Option<&'a SuiMoveValue>> {
+    match fields {
+        SuiMoveStruct::WithFields(map) | SuiMoveStruct::WithTypes { fields: map, .. } => {
+            Ok(map.get(name))
+        }
+        SuiMoveStruct::Runtime(_) => anyhow::bail!("Cannot access fields in Runtime variant"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    const WRITER: &str = "0x000000000000000000000000000000000000000000000000000000000000aaaa";
+
+    /// A `dynamic_field::Field<String, LastWriter>` object's fields
+    fn last_writer_field(objects: SuiMoveValue) -> SuiMoveStruct {
+        let value = SuiMoveStruct::WithFields(BTreeMap::from([
+            (
+                "writer".to_string(),
+                SuiMoveValue::Address(WRITER.parse().unwrap()),
+            ),
+            ("objects_blob_object_id".to_string(), objects),
+            (
+                "format".to_string(),
+                SuiMoveValue::String("features=1;writer=0.2.0;requires=0.1.0".to_string()),
+            ),
+        ]));
+        SuiMoveStruct::WithFields(BTreeMap::from([
+            (
+                "name".to_string(),
+                SuiMoveValue::String(LAST_WRITER_FIELD.to_string()),
+            ),
+            ("value".to_string(), SuiMoveValue::Struct(value)),
+        ]))
+    }
+
+    #[test]
+    fn test_last_writer_from_field() {
+        assert_eq!(LastWriter::from_field(None).unwrap(), None);
+
+        let field = last_writer_field(SuiMoveValue::Option(Box::new(Some(SuiMoveValue::String(
+            "0x55".to_string(),
+        )))));
+        let last_writer = LastWriter::from_field(Some(&field)).unwrap().unwrap();
+        assert_eq!(last_writer.writer, WRITER);
+        assert_eq!(
+            last_writer.format_of("0x55"),
+            Some("features=1;writer=0.2.0;requires=0.1.0")
+        );
+        // A header recorded for an earlier map says nothing about the current one
+        assert_eq!(last_writer.format_of("0x66"), None);
+
+        // Flattened, and recorded before the remote had any map
+        let field = last_writer_field(SuiMoveValue::String("0x55".to_string()));
+        let last_writer = LastWriter::from_field(Some(&field)).unwrap().unwrap();
+        assert_eq!(last_writer.objects_blob_object_id.as_deref(), Some("0x55"));
+        let field = last_writer_field(SuiMoveValue::Option(Box::new(None)));
+        let last_writer = LastWriter::from_field(Some(&field)).unwrap().unwrap();
+        assert_eq!(last_writer.format_of("0x55"), None);
+
+        let malformed = SuiMoveStruct::WithFields(BTreeMap::new());
+        assert!(LastWriter::from_field(Some(&malformed)).is_err());
+    }
+}
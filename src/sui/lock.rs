@@ -0,0 +1,191 @@
+//! The RemoteState's push lock, and when a lock left behind by a crashed push may be broken
+
+use std::fmt;
+
+use anyhow::Result;
+use sui_sdk::rpc_types::{SuiMoveStruct, SuiMoveValue};
+
+/// A held lock, from the RemoteState's `lock` field (the Move `LockInfo`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockInfo {
+    /// Address of the pusher that took the lock
+    pub holder: String,
+    /// Chain time (Clock milliseconds) at which the lock's declared timeout runs out
+    pub expires_ms: u64,
+}
+
+impl fmt::Display for LockInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "held by {} until {} ms", self.holder, self.expires_ms)
+    }
+}
+
+impl LockInfo {
+    /// Read the lock from RemoteState fields (None when the remote is unlocked)
+    pub fn from_fields(fields: &SuiMoveStruct) -> Result<Option<Self>> {
+        let Some(lock) = field(fields, "lock")? else {
+            return Ok(None);
+        };
+        let lock = match lock {
+            SuiMoveValue::Option(inner) => match inner.as_ref() {
+                Some(lock) => lock,
+                None => return Ok(None),
+            },
+            // JSON-RPC may flatten Some(value) to the value itself
+            lock => lock,
+        };
+        let SuiMoveValue::Struct(lock) = lock else {
+            anyhow::bail!("Expected LockInfo struct for lock, got {:?}", lock);
+        };
+
+        let holder = match field(lock, "holder")? {
+            Some(SuiMoveValue::Address(addr)) => addr.to_string(),
+            Some(SuiMoveValue::String(s)) => s.clone(),
+            other => anyhow::bail!("Expected address for lock holder, got {:?}", other),
+        };
+        let expires_ms = match field(lock, "expires_ms")? {
+            Some(SuiMoveValue::Number(n)) => *n as u64,
+            Some(SuiMoveValue::String(s)) => s
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid lock expires_ms {:?}", s))?,
+            other => anyhow::bail!("Expected u64 for lock expires_ms, got {:?}", other),
+        };
+        Ok(Some(Self { holder, expires_ms }))
+    }
+
+    /// Whether the lock's declared timeout has run out at chain time `now_ms`
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        now_ms >= self.expires_ms
+    }
+
+    /// Whether `address` holds the lock
+    pub fn held_by(&self, address: &str) -> bool {
+        self.holder.eq_ignore_ascii_case(address)
+    }
+
+    /// `held by <holder>, expires in 42s` (or `expired 42s ago`) at chain time `now_ms`
+    pub fn describe(&self, now_ms: u64) -> String {
+        if self.is_expired(now_ms) {
+            format!(
+                "held by {}, expired {}s ago",
+                self.holder,
+                (now_ms - self.expires_ms) / 1000
+            )
+        } else {
+            format!(
+                "held by {}, expires in {}s",
+                self.holder,
+                (self.expires_ms - now_ms).div_ceil(1000)
+            )
+        }
+    }
+}
+
+/// What taking the lock has to do about the one currently on the remote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockAction {
+    /// Unlocked, or already ours (e.g. from an attempt that timed out): just take it
+    Take,
+    /// Left by a push that never released it and past its timeout: break it by taking it
+    BreakStale(LockInfo),
+    /// Another pusher holds a live lock
+    Held(LockInfo),
+}
+
+/// Decide how `caller` can lock a remote whose lock is `current`, at chain time `now_ms`
+pub fn lock_action(current: Option<LockInfo>, caller: &str, now_ms: u64) -> LockAction {
+    match current {
+        None => LockAction::Take,
+        Some(lock) if lock.held_by(caller) => LockAction::Take,
+        Some(lock) if lock.is_expired(now_ms) => LockAction::BreakStale(lock),
+        Some(lock) => LockAction::Held(lock),
+    }
+}
+
+fn field<'a>(fields: &'a SuiMoveStruct, name: &str) -> Result<Option<&'a SuiMoveValue>> {
+    match fields {
+        SuiMoveStruct::WithFields(map) | SuiMoveStruct::WithTypes { fields: map, .. } => {
+            Ok(map.get(name))
+        }
+        SuiMoveStruct::Runtime(_) => anyhow::bail!("Cannot access fields in Runtime variant"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    const ME: &str = "0xaaaa";
+    const OTHER: &str = "0xbbbb";
+
+    fn remote_state(lock: Option<(&str, &str)>) -> SuiMoveStruct {
+        let lock = lock.map(|(holder, expires_ms)| {
+            SuiMoveValue::Struct(SuiMoveStruct::WithFields(BTreeMap::from([
+                ("holder".to_string(), SuiMoveValue::String(holder.into())),
+                (
+                    "expires_ms".to_string(),
+                    SuiMoveValue::String(expires_ms.into()),
+                ),
+            ])))
+        });
+        SuiMoveStruct::WithFields(BTreeMap::from([(
+            "lock".to_string(),
+            SuiMoveValue::Option(Box::new(lock)),
+        )]))
+    }
+
+    #[test]
+    fn test_lock_from_fields() {
+        assert_eq!(LockInfo::from_fields(&remote_state(None)).unwrap(), None);
+        assert_eq!(
+            LockInfo::from_fields(&remote_state(Some((OTHER, "1700000300000")))).unwrap(),
+            Some(LockInfo {
+                holder: OTHER.to_string(),
+                expires_ms: 1_700_000_300_000,
+            })
+        );
+        assert!(LockInfo::from_fields(&remote_state(Some((OTHER, "soon")))).is_err());
+    }
+
+    #[test]
+    fn test_stale_lock_is_broken() {
+        // A push crashed holding a 5 minute lock taken at t=1000s
+        let lock = LockInfo::from_fields(&remote_state(Some((OTHER, "1300000"))))
+            .unwrap()
+            .unwrap();
+
+        // Still within its timeout: someone else's push may be running
+        assert_eq!(
+            lock_action(Some(lock.clone()), ME, 1_299_999),
+            LockAction::Held(lock.clone())
+        );
+        // Past it: acquisition proceeds by breaking the lock
+        assert_eq!(
+            lock_action(Some(lock.clone()), ME, 1_300_000),
+            LockAction::BreakStale(lock.clone())
+        );
+    }
+
+    #[test]
+    fn test_describe() {
+        let lock = LockInfo {
+            holder: OTHER.to_string(),
+            expires_ms: 1_300_000,
+        };
+        assert_eq!(lock.describe(1_000_001), "held by 0xbbbb, expires in 300s");
+        assert_eq!(lock.describe(1_360_500), "held by 0xbbbb, expired 60s ago");
+    }
+
+    #[test]
+    fn test_own_or_missing_lock_is_taken() {
+        assert_eq!(lock_action(None, ME, 0), LockAction::Take);
+        let mine = LockInfo {
+            holder: ME.to_uppercase().replace("0X", "0x"),
+            expires_ms: 10,
+        };
+        assert_eq!(lock_action(Some(mine.clone()), ME, 5), LockAction::Take);
+        assert_eq!(lock_action(Some(mine), ME, 50), LockAction::Take);
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use sui_config::PersistedConfig;
+use sui_sdk::sui_client_config::SuiClientConfig;
+
+/// Sui network an environment points at, used to gate spending on mainnet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuiNetwork {
+    Mainnet,
+    Testnet,
+    Devnet,
+    Localnet,
+    Unknown,
+}
+
+impl SuiNetwork {
+    /// Classify a Sui environment from its alias and RPC URL
+    ///
+    /// Mainnet wins if either the alias or the URL mentions it, so a
+    /// misleadingly named environment errs on the side of caution.
+    pub fn detect(alias: &str, rpc_url: &str) -> Self {
+        let alias = alias.to_ascii_lowercase();
+        let rpc_url = rpc_url.to_ascii_lowercase();
+        let mentions = |name: &str| alias.contains(name) || rpc_url.contains(name);
+
+        if mentions("mainnet") {
+            SuiNetwork::Mainnet
+        } else if mentions("testnet") {
+            SuiNetwork::Testnet
+        } else if mentions("devnet") {
+            SuiNetwork::Devnet
+        } else if alias.starts_with("local")
+            || rpc_url.contains("localhost")
+            || rpc_url.contains("127.0.0.1")
+            || rpc_url.contains("0.0.0.0")
+        {
+            SuiNetwork::Localnet
+        } else {
+            SuiNetwork::Unknown
+        }
+    }
+
+    /// Coin type of WAL on this network, where it is well known
+    pub fn wal_coin_type(&self) -> Option<&'static str> {
+        match self {
+            SuiNetwork::Mainnet => {
+                Some("0x356a26eb9e012a68958082340d4c4116e7f55615cf27affcff209cf0ae544f59::wal::WAL")
+            }
+            SuiNetwork::Testnet => {
+                Some("0x8270feb7375eee355e64fdb69c50abb6b5f9393a722883c1cf45f8e26048810a::wal::WAL")
+            }
+            SuiNetwork::Devnet | SuiNetwork::Localnet | SuiNetwork::Unknown => None,
+        }
+    }
+
+    /// Classify the active environment of a Sui client config
+    pub fn from_client_config(config: &SuiClientConfig) -> Result<Self> {
+        let env = config.get_active_env()?;
+        Ok(Self::detect(&env.alias, &env.rpc))
+    }
+
+    /// Classify the active environment of the wallet at `wallet_path`
+    pub fn from_wallet(wallet_path: &Path) -> Result<Self> {
+        let config: SuiClientConfig = PersistedConfig::read(wallet_path)
+            .with_context(|| format!("Failed to load Sui config from {:?}", wallet_path))?;
+        Self::from_client_config(&config)
+    }
+}
+
+/// Refuse state-mutating operations on mainnet unless explicitly allowed
+pub fn ensure_spending_allowed(network: SuiNetwork, allow_mainnet: bool) -> Result<()> {
+    if network == SuiNetwork::Mainnet && !allow_mainnet {
+        anyhow::bail!(
+            "refusing to spend on mainnet without explicit opt-in \
+             (set `allow_mainnet: true` in config or WALRUS_REMOTE_ALLOW_MAINNET=1)"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_by_alias() {
+        assert_eq!(SuiNetwork::detect("mainnet", ""), SuiNetwork::Mainnet);
+        assert_eq!(SuiNetwork::detect("testnet", ""), SuiNetwork::Testnet);
+        assert_eq!(SuiNetwork::detect("devnet", ""), SuiNetwork::Devnet);
+        assert_eq!(SuiNetwork::detect("localnet", ""), SuiNetwork::Localnet);
+        assert_eq!(SuiNetwork::detect("custom", ""), SuiNetwork::Unknown);
+    }
+
+    #[test]
+    fn test_detect_by_rpc_url() {
+        assert_eq!(
+            SuiNetwork::detect("prod", "https://fullnode.mainnet.sui.io:443"),
+            SuiNetwork::Mainnet
+        );
+        assert_eq!(
+            SuiNetwork::detect("work", "https://fullnode.testnet.sui.io:443"),
+            SuiNetwork::Testnet
+        );
+        assert_eq!(
+            SuiNetwork::detect("dev", "http://127.0.0.1:9000"),
+            SuiNetwork::Localnet
+        );
+    }
+
+    #[test]
+    fn test_mainnet_wins_over_other_hints() {
+        // An alias claiming testnet must not hide a mainnet RPC URL
+        assert_eq!(
+            SuiNetwork::detect("testnet", "https://fullnode.mainnet.sui.io:443"),
+            SuiNetwork::Mainnet
+        );
+        assert_eq!(
+            SuiNetwork::detect("MainNet", "http://localhost:9000"),
+            SuiNetwork::Mainnet
+        );
+    }
+
+    #[test]
+    fn test_spending_gate() {
+        assert!(ensure_spending_allowed(SuiNetwork::Testnet, false).is_ok());
+        assert!(ensure_spending_allowed(SuiNetwork::Localnet, false).is_ok());
+        assert!(ensure_spending_allowed(SuiNetwork::Unknown, false).is_ok());
+        assert!(ensure_spending_allowed(SuiNetwork::Mainnet, true).is_ok());
+
+        let err = ensure_spending_allowed(SuiNetwork::Mainnet, false).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("refusing to spend on mainnet without explicit opt-in"));
+    }
+}
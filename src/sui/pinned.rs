@@ -0,0 +1,113 @@
+//! A RemoteState's refs as they were after one of its transactions, for remote URLs pinned
+//! with `@<digest>`
+//!
+//! Each ref is a dynamic field object of the refs table (or, for the inline layout, the
+//! RemoteState's `inline_refs` field holds them all), so its value at the pin is the version
+//! the last transaction up to the pin wrote, unless a later one up to it deleted it.
+
+use std::collections::BTreeMap;
+
+/// What a transaction did to one object, from its object changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldChange {
+    /// Created or mutated a refs table field, leaving it at `version`
+    Written { object_id: String, version: u64 },
+    /// Deleted (or wrapped) an object, which may be a refs table field
+    Deleted { object_id: String },
+}
+
+/// A transaction that changed the RemoteState, with its object changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateTransaction {
+    pub digest: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Refs table fields replayed from the RemoteState's transactions, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct FieldReplay {
+    /// Version of each live field, by object ID
+    pub fields: BTreeMap<String, u64>,
+}
+
+impl FieldReplay {
+    /// Apply the next `transactions` up to and including `pin`; true once `pin` was applied
+    pub fn apply(&mut self, transactions: &[StateTransaction], pin: &str) -> bool {
+        for tx in transactions {
+            for change in &tx.changes {
+                match change {
+                    FieldChange::Written { object_id, version } => {
+                        self.fields.insert(object_id.clone(), *version);
+                    }
+                    FieldChange::Deleted { object_id } => {
+                        self.fields.remove(object_id);
+                    }
+                }
+            }
+            if tx.digest == pin {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(object_id: &str, version: u64) -> FieldChange {
+        FieldChange::Written {
+            object_id: object_id.to_string(),
+            version,
+        }
+    }
+
+    fn deleted(object_id: &str) -> FieldChange {
+        FieldChange::Deleted {
+            object_id: object_id.to_string(),
+        }
+    }
+
+    fn tx(digest: &str, changes: Vec<FieldChange>) -> StateTransaction {
+        StateTransaction {
+            digest: digest.to_string(),
+            changes,
+        }
+    }
+
+    #[test]
+    fn test_replay_stops_at_the_pinned_transaction() {
+        // main and topic pushed, then main moved, topic deleted and a tag created
+        let history = [
+            tx("push-1", vec![written("0xmain", 3), written("0xtopic", 3)]),
+            tx("push-2", vec![written("0xmain", 7)]),
+            tx(
+                "push-3",
+                vec![
+                    written("0xmain", 9),
+                    deleted("0xtopic"),
+                    written("0xtag", 9),
+                ],
+            ),
+        ];
+
+        let mut replay = FieldReplay::default();
+        assert!(replay.apply(&history, "push-2"));
+        assert_eq!(
+            replay.fields,
+            BTreeMap::from([("0xmain".to_string(), 7), ("0xtopic".to_string(), 3)])
+        );
+
+        // Pages are applied in turn until the pin turns up
+        let mut replay = FieldReplay::default();
+        assert!(!replay.apply(&history[..1], "push-3"));
+        assert!(replay.apply(&history[1..], "push-3"));
+        assert_eq!(
+            replay.fields,
+            BTreeMap::from([("0xmain".to_string(), 9), ("0xtag".to_string(), 9)])
+        );
+
+        assert!(!FieldReplay::default().apply(&history, "elsewhere"));
+    }
+}
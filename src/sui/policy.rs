@@ -0,0 +1,259 @@
+//! Optional on-chain limits a remote owner sets to keep shared remotes from growing unbounded
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use anyhow::Result;
+use sui_sdk::rpc_types::{SuiMoveStruct, SuiMoveValue};
+
+/// Name of the RemoteState dynamic field holding its `Policy`
+pub const POLICY_FIELD: &str = "policy";
+
+/// Limits from the RemoteState's `policy` dynamic field (None is unlimited)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RemotePolicy {
+    /// Most refs the remote may hold, across every namespace
+    pub max_refs: Option<u64>,
+    /// Most entries the objects map may hold
+    pub max_objects: Option<u64>,
+}
+
+impl fmt::Display for RemotePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = |value: Option<u64>| value.map_or("unlimited".to_string(), |n| n.to_string());
+        write!(
+            f,
+            "max_refs: {}, max_objects: {}",
+            limit(self.max_refs),
+            limit(self.max_objects)
+        )
+    }
+}
+
+/// A ref a push creates or moves, or deletes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefChange<'a> {
+    Upsert(&'a str),
+    #[allow(dead_code)] // pushes don't delete refs yet
+    Delete(&'a str),
+}
+
+impl RemotePolicy {
+    /// Read the policy from the RemoteState's `policy` dynamic field object
+    ///
+    /// Remotes whose owner never set a policy have no such field, and no limits.
+    pub fn from_field(field: Option<&SuiMoveStruct>) -> Result<Self> {
+        let Some(field) = field else {
+            return Ok(Self::default());
+        };
+        let field_map = match fields(field)?.get("value") {
+            Some(SuiMoveValue::Struct(policy)) => fields(policy)?,
+            other => anyhow::bail!("Expected Policy struct in policy field, got {:?}", other),
+        };
+        let limit = |name: &str| -> Result<Option<u64>> {
+            let value = match field_map.get(name) {
+                None => return Ok(None),
+                Some(SuiMoveValue::Option(inner)) => match inner.as_ref() {
+                    Some(value) => value,
+                    None => return Ok(None),
+                },
+                // JSON-RPC may flatten Some(value) to the value itself
+                Some(value) => value,
+            };
+            match value {
+                SuiMoveValue::Number(n) => Ok(Some(*n as u64)),
+                SuiMoveValue::String(s) => s
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| anyhow::anyhow!("Invalid {} {:?}", name, s)),
+                other => anyhow::bail!("Expected u64 for {}, got {:?}", name, other),
+            }
+        };
+
+        Ok(Self {
+            max_refs: limit("max_refs")?,
+            max_objects: limit("max_objects")?,
+        })
+    }
+
+    /// Whether neither limit is set
+    pub fn is_unlimited(&self) -> bool {
+        self.max_refs.is_none() && self.max_objects.is_none()
+    }
+
+    /// Fail if a push leaving `refs_after` refs and `objects_after` objects (when counted)
+    /// breaks a limit
+    pub fn check(&self, refs_after: usize, objects_after: Option<usize>) -> Result<()> {
+        if let Some(max) = self.max_refs.filter(|max| refs_after as u64 > *max) {
+            anyhow::bail!(
+                "push would leave {} refs on this remote, over its on-chain limit of {} \
+                 (max_refs); delete refs or ask the remote owner to raise it with \
+                 `git-remote-walrus policy <remote> --max-refs <n>`",
+                refs_after,
+                max
+            );
+        }
+        if let Some((objects, max)) = objects_after
+            .zip(self.max_objects)
+            .filter(|(objects, max)| *objects as u64 > *max)
+        {
+            anyhow::bail!(
+                "push would grow the objects map to {} objects, over this remote's on-chain \
+                 limit of {} (max_objects); ask the remote owner to raise it with \
+                 `git-remote-walrus policy <remote> --max-objects <n>`",
+                objects,
+                max
+            );
+        }
+        Ok(())
+    }
+}
+
+fn fields(fields: &SuiMoveStruct) -> Result<&BTreeMap<String, SuiMoveValue>> {
+    match fields {
+        SuiMoveStruct::WithFields(map) | SuiMoveStruct::WithTypes { fields: map, .. } => Ok(map),
+        SuiMoveStruct::Runtime(_) => anyhow::bail!("Cannot access fields in Runtime variant"),
+    }
+}
+
+/// Number of refs left after applying `changes`, in order, to `current` ref names
+pub fn projected_ref_count<'a>(
+    current: impl IntoIterator<Item = &'a String>,
+    changes: &[RefChange],
+) -> usize {
+    let mut refs: BTreeSet<&str> = current.into_iter().map(String::as_str).collect();
+    for change in changes {
+        match *change {
+            RefChange::Upsert(name) => refs.insert(name),
+            RefChange::Delete(name) => refs.remove(name),
+        };
+    }
+    refs.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `dynamic_field::Field<String, Policy>` object's fields
+    fn policy_field(fields: Vec<(&str, SuiMoveValue)>) -> SuiMoveStruct {
+        let policy = SuiMoveStruct::WithFields(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        );
+        SuiMoveStruct::WithFields(BTreeMap::from([
+            (
+                "name".to_string(),
+                SuiMoveValue::String(POLICY_FIELD.to_string()),
+            ),
+            ("value".to_string(), SuiMoveValue::Struct(policy)),
+        ]))
+    }
+
+    #[test]
+    fn test_policy_from_field() {
+        // Remotes whose owner never set a policy, or cleared both limits, are unlimited
+        assert!(RemotePolicy::from_field(None).unwrap().is_unlimited());
+        let field = policy_field(vec![
+            ("max_refs", SuiMoveValue::Option(Box::new(None))),
+            ("max_objects", SuiMoveValue::Option(Box::new(None))),
+        ]);
+        assert!(RemotePolicy::from_field(Some(&field))
+            .unwrap()
+            .is_unlimited());
+
+        // u64s arrive as strings, wrapped or flattened
+        let field = policy_field(vec![
+            (
+                "max_refs",
+                SuiMoveValue::Option(Box::new(Some(SuiMoveValue::String("100".into())))),
+            ),
+            ("max_objects", SuiMoveValue::String("5000000".into())),
+        ]);
+        assert_eq!(
+            RemotePolicy::from_field(Some(&field)).unwrap(),
+            RemotePolicy {
+                max_refs: Some(100),
+                max_objects: Some(5_000_000),
+            }
+        );
+
+        let field = policy_field(vec![("max_refs", SuiMoveValue::String("lots".into()))]);
+        assert!(RemotePolicy::from_field(Some(&field)).is_err());
+
+        // A field without a Policy value is malformed, not unlimited
+        let field = SuiMoveStruct::WithFields(BTreeMap::new());
+        assert!(RemotePolicy::from_field(Some(&field)).is_err());
+    }
+
+    #[test]
+    fn test_projection_across_adds_and_deletes() {
+        let current: BTreeMap<String, String> = ["refs/heads/main", "refs/heads/dev"]
+            .into_iter()
+            .map(|name| (name.to_string(), "sha".to_string()))
+            .collect();
+        let count = |changes: &[RefChange]| projected_ref_count(current.keys(), changes);
+
+        assert_eq!(count(&[]), 2);
+        // Moving an existing ref doesn't add one
+        assert_eq!(count(&[RefChange::Upsert("refs/heads/main")]), 2);
+        assert_eq!(
+            count(&[
+                RefChange::Upsert("refs/heads/a"),
+                RefChange::Upsert("refs/heads/b")
+            ]),
+            4
+        );
+        // Deleting makes room, and deleting a missing ref changes nothing
+        assert_eq!(
+            count(&[
+                RefChange::Delete("refs/heads/dev"),
+                RefChange::Upsert("refs/heads/a"),
+                RefChange::Delete("refs/heads/gone"),
+            ]),
+            2
+        );
+        // Re-creating a ref deleted earlier in the same push counts it again
+        assert_eq!(
+            count(&[
+                RefChange::Delete("refs/heads/dev"),
+                RefChange::Upsert("refs/heads/dev")
+            ]),
+            2
+        );
+    }
+
+    #[test]
+    fn test_check_against_mocked_policies() {
+        let unlimited = RemotePolicy::default();
+        assert!(unlimited.check(usize::MAX, Some(usize::MAX)).is_ok());
+
+        let policy = RemotePolicy {
+            max_refs: Some(3),
+            max_objects: Some(1000),
+        };
+        assert!(policy.check(3, Some(1000)).is_ok());
+        // Uncounted objects are only held to the ref limit
+        assert!(policy.check(3, None).is_ok());
+
+        let err = policy.check(4, Some(10)).unwrap_err().to_string();
+        assert!(err.contains("leave 4 refs"), "{}", err);
+        assert!(err.contains("limit of 3 (max_refs)"), "{}", err);
+
+        let err = policy.check(1, Some(1001)).unwrap_err().to_string();
+        assert!(err.contains("1001 objects"), "{}", err);
+        assert!(err.contains("--max-objects"), "{}", err);
+
+        // Only the refs limit set
+        let refs_only = RemotePolicy {
+            max_refs: Some(1),
+            max_objects: None,
+        };
+        assert!(refs_only.check(1, Some(usize::MAX)).is_ok());
+        assert!(refs_only.check(2, None).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! Who last updated each ref, reconstructed from the RemoteState's transaction history
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// The transaction that last set a ref to its current value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RefUpdate {
+    /// Sui address that signed the transaction
+    pub updated_by: String,
+    /// Checkpoint time of the transaction, in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp_ms: Option<u64>,
+    /// Transaction digest
+    pub digest: String,
+}
+
+/// A transaction that touched the RemoteState, with the ref name and SHA-1 arguments of each
+/// of its `upsert_ref` calls, in call order
+#[derive(Debug, Clone)]
+pub struct RefTransaction {
+    pub update: RefUpdate,
+    pub upserts: Vec<(String, String)>,
+}
+
+impl RefTransaction {
+    /// Whether this transaction set `ref_name` to `git_sha1`
+    fn sets(&self, ref_name: &str, git_sha1: &str) -> bool {
+        self.ref_values()
+            .any(|(name, sha)| name == ref_name && sha == git_sha1)
+    }
+
+    /// Each ref name and SHA-1 this transaction set
+    pub fn ref_values(&self) -> impl Iterator<Item = (&str, &str)> + '_ {
+        self.upserts
+            .iter()
+            .map(|(name, sha)| (name.as_str(), sha.as_str()))
+    }
+}
+
+/// Record in `found` the transaction that set each ref of `wanted` (name -> SHA-1) to its
+/// value, from `transactions` ordered newest first; refs already in `found` are skipped
+pub fn match_updates(
+    wanted: &BTreeMap<String, String>,
+    transactions: &[RefTransaction],
+    found: &mut BTreeMap<String, RefUpdate>,
+) {
+    for tx in transactions {
+        for (name, sha) in wanted {
+            if !found.contains_key(name) && tx.sets(name, sha) {
+                found.insert(name.clone(), tx.update.clone());
+            }
+        }
+    }
+}
+
+/// Local cache of reconstructed updates, keyed by RemoteState, ref name and SHA-1
+///
+/// A ref that moved is looked up afresh under its new SHA-1. A ref moved back to a SHA-1 it
+/// had before hits the entry for the earlier transaction, though, so an entry can name an
+/// older update than the one that last set the ref.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RefHistoryCache {
+    #[serde(default)]
+    remotes: BTreeMap<String, BTreeMap<String, RefUpdate>>,
+}
+
+impl RefHistoryCache {
+    /// Load the cache from `path` (empty if it doesn't exist)
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read ref history from {:?}", path))?;
+        serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse ref history from {:?}", path))
+    }
+
+    /// Save the cache to `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_yaml::to_string(self).context("Failed to serialize ref history")?;
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write ref history to {:?}", path))
+    }
+
+    pub fn get(&self, remote: &str, ref_name: &str, git_sha1: &str) -> Option<&RefUpdate> {
+        self.remotes.get(remote)?.get(&key(ref_name, git_sha1))
+    }
+
+    pub fn insert(&mut self, remote: &str, ref_name: &str, git_sha1: &str, update: RefUpdate) {
+        self.remotes
+            .entry(remote.to_string())
+            .or_default()
+            .insert(key(ref_name, git_sha1), update);
+    }
+}
+
+fn key(ref_name: &str, git_sha1: &str) -> String {
+    format!("{} {}", ref_name, git_sha1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(sender: &str, timestamp_ms: u64, upserts: &[(&str, &str)]) -> RefTransaction {
+        RefTransaction {
+            update: RefUpdate {
+                updated_by: sender.to_string(),
+                timestamp_ms: Some(timestamp_ms),
+                digest: format!("digest-{}", timestamp_ms),
+            },
+            upserts: upserts
+                .iter()
+                .map(|(name, sha)| (name.to_string(), sha.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_newest_matching_transaction_wins() {
+        let wanted: BTreeMap<String, String> = [
+            ("refs/heads/main", "bbb"),
+            ("refs/heads/dev", "ccc"),
+            ("refs/tags/v1", "ddd"),
+        ]
+        .into_iter()
+        .map(|(name, sha)| (name.to_string(), sha.to_string()))
+        .collect();
+
+        // Newest first; main was set to bbb twice, and v1's update is beyond the history read
+        let transactions = vec![
+            tx("0xcarol", 400, &[("refs/heads/main", "aaa")]),
+            tx(
+                "0xbob",
+                300,
+                &[("refs/heads/main", "bbb"), ("refs/heads/dev", "ccc")],
+            ),
+            tx("0xalice", 200, &[("refs/heads/main", "bbb")]),
+            // The SHA-1 of one ref is not a match for another
+            tx("0xmallory", 100, &[("refs/heads/main", "ccc")]),
+        ];
+
+        let mut found = BTreeMap::new();
+        match_updates(&wanted, &transactions, &mut found);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found["refs/heads/main"].updated_by, "0xbob");
+        assert_eq!(found["refs/heads/dev"].timestamp_ms, Some(300));
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ref_history.yaml");
+        assert!(RefHistoryCache::load(&path).unwrap().remotes.is_empty());
+
+        let update = tx("0xbob", 300, &[]).update;
+        let mut cache = RefHistoryCache::default();
+        cache.insert("0xremote", "refs/heads/main", "bbb", update.clone());
+        cache.save(&path).unwrap();
+
+        let cache = RefHistoryCache::load(&path).unwrap();
+        assert_eq!(
+            cache.get("0xremote", "refs/heads/main", "bbb"),
+            Some(&update)
+        );
+        assert_eq!(cache.get("0xremote", "refs/heads/main", "aaa"), None);
+        assert_eq!(cache.get("0xother", "refs/heads/main", "bbb"), None);
+    }
+}
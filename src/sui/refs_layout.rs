@@ -0,0 +1,224 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use anyhow::Result;
+use sui_sdk::rpc_types::{SuiMoveStruct, SuiMoveValue};
+
+/// Maximum number of refs an inline-layout remote can hold (mirrors `MAX_INLINE_REFS` in Move)
+pub const MAX_INLINE_REFS: usize = 64;
+
+/// How a RemoteState stores its refs on-chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RefsLayout {
+    /// Dynamic-field Table: scales to many refs, one RPC read per ref
+    #[default]
+    Table,
+    /// VecMap in a RemoteState dynamic field: one object read, limited to `MAX_INLINE_REFS`
+    Inline,
+}
+
+impl FromStr for RefsLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(RefsLayout::Table),
+            "inline" => Ok(RefsLayout::Inline),
+            other => anyhow::bail!(
+                "invalid refs layout {:?} (expected 'table' or 'inline')",
+                other
+            ),
+        }
+    }
+}
+
+impl fmt::Display for RefsLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefsLayout::Table => write!(f, "table"),
+            RefsLayout::Inline => write!(f, "inline"),
+        }
+    }
+}
+
+impl RefsLayout {
+    /// Move function that creates a RemoteState with this layout
+    pub fn create_function(&self) -> &'static str {
+        match self {
+            RefsLayout::Table => "create_remote",
+            RefsLayout::Inline => "create_remote_inline",
+        }
+    }
+}
+
+/// Name of the RemoteState dynamic field holding an inline-layout remote's refs
+pub const INLINE_REFS_FIELD: &str = "inline_refs";
+
+/// Name of the RemoteState dynamic field holding its symrefs (symref name -> target ref)
+///
+/// Remotes last pushed by helpers that kept symrefs in the objects map have no such field.
+pub const SYMREFS_FIELD: &str = "symrefs";
+
+/// Get the `VecMap` from a RemoteState dynamic field object holding one (`inline_refs` or
+/// `symrefs`)
+///
+/// Table-layout remotes, and remotes migrated to the table layout, have no `inline_refs` field.
+pub fn vec_map_field(field: &SuiMoveStruct) -> Option<&SuiMoveStruct> {
+    match struct_field(field, "value").ok()? {
+        SuiMoveValue::Struct(map) => Some(map),
+        _ => None,
+    }
+}
+
+/// Detect the refs layout from the RemoteState's `inline_refs` dynamic field, if it has one
+pub fn detect_layout(field: Option<&SuiMoveStruct>) -> RefsLayout {
+    if field.and_then(vec_map_field).is_some() {
+        RefsLayout::Inline
+    } else {
+        RefsLayout::Table
+    }
+}
+
+/// Parse a Move `VecMap<String, String>` into ref name -> git SHA-1 (or symref -> target)
+pub fn parse_vec_map(vec_map: &SuiMoveStruct) -> Result<BTreeMap<String, String>> {
+    let contents = match struct_field(vec_map, "contents")? {
+        SuiMoveValue::Vector(entries) => entries,
+        other => anyhow::bail!("Expected vector for VecMap contents, got {:?}", other),
+    };
+
+    let mut refs = BTreeMap::new();
+    for entry in contents {
+        let entry = match entry {
+            SuiMoveValue::Struct(entry) => entry,
+            other => anyhow::bail!("Expected struct for VecMap entry, got {:?}", other),
+        };
+        let key = string_value(struct_field(entry, "key")?)?;
+        let value = string_value(struct_field(entry, "value")?)?;
+        refs.insert(key, value);
+    }
+
+    Ok(refs)
+}
+
+/// Check that applying `updates` to an inline remote with `current` refs stays within the limit
+pub fn check_inline_capacity(
+    current: &BTreeMap<String, String>,
+    updates: &[(String, String)],
+) -> Result<()> {
+    let new_refs = updates
+        .iter()
+        .filter(|(name, _)| !current.contains_key(name))
+        .map(|(name, _)| name)
+        .collect::<std::collections::BTreeSet<_>>()
+        .len();
+
+    let total = current.len() + new_refs;
+    if total > MAX_INLINE_REFS {
+        anyhow::bail!(
+            "push would grow this inline-layout remote to {} refs (limit {}); \
+             run `git-remote-walrus migrate-layout <remote>` to switch it to the table layout",
+            total,
+            MAX_INLINE_REFS
+        );
+    }
+
+    Ok(())
+}
+
+fn struct_field<'a>(fields: &'a SuiMoveStruct, name: &str) -> Result<&'a SuiMoveValue> {
+    let field_map = match fields {
+        SuiMoveStruct::WithFields(map) | SuiMoveStruct::WithTypes { fields: map, .. } => map,
+        SuiMoveStruct::Runtime(_) => anyhow::bail!("Cannot access fields in Runtime variant"),
+    };
+    field_map
+        .get(name)
+        .ok_or_else(|| anyhow::anyhow!("Field '{}' not found", name))
+}
+
+fn string_value(value: &SuiMoveValue) -> Result<String> {
+    match value {
+        SuiMoveValue::String(s) => Ok(s.clone()),
+        other => anyhow::bail!("Expected String, got {:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &str, value: &str) -> SuiMoveValue {
+        SuiMoveValue::Struct(SuiMoveStruct::WithFields(BTreeMap::from([
+            ("key".to_string(), SuiMoveValue::String(key.to_string())),
+            ("value".to_string(), SuiMoveValue::String(value.to_string())),
+        ])))
+    }
+
+    fn vec_map(entries: Vec<SuiMoveValue>) -> SuiMoveStruct {
+        SuiMoveStruct::WithFields(BTreeMap::from([(
+            "contents".to_string(),
+            SuiMoveValue::Vector(entries),
+        )]))
+    }
+
+    /// A `dynamic_field::Field<String, VecMap<String, String>>` object's fields
+    fn field(value: SuiMoveStruct) -> SuiMoveStruct {
+        SuiMoveStruct::WithFields(BTreeMap::from([
+            (
+                "name".to_string(),
+                SuiMoveValue::String(INLINE_REFS_FIELD.to_string()),
+            ),
+            ("value".to_string(), SuiMoveValue::Struct(value)),
+        ]))
+    }
+
+    #[test]
+    fn test_detect_layout() {
+        // Table layout, or an inline remote migrated away from it: no field
+        assert_eq!(detect_layout(None), RefsLayout::Table);
+        assert_eq!(
+            detect_layout(Some(&field(vec_map(vec![])))),
+            RefsLayout::Inline
+        );
+    }
+
+    #[test]
+    fn test_parse_inline_refs() {
+        let field = field(vec_map(vec![
+            entry("refs/heads/main", "aaaa"),
+            entry("refs/tags/v1", "bbbb"),
+        ]));
+        let refs = parse_vec_map(vec_map_field(&field).unwrap()).unwrap();
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs["refs/heads/main"], "aaaa");
+        assert_eq!(refs["refs/tags/v1"], "bbbb");
+    }
+
+    #[test]
+    fn test_check_inline_capacity() {
+        let current: BTreeMap<String, String> = (0..MAX_INLINE_REFS - 1)
+            .map(|i| (format!("refs/heads/b{}", i), "sha".to_string()))
+            .collect();
+
+        // Updating existing refs never grows the map
+        let updates = vec![("refs/heads/b0".to_string(), "new".to_string())];
+        assert!(check_inline_capacity(&current, &updates).is_ok());
+
+        // One new ref fits exactly
+        let updates = vec![("refs/heads/new1".to_string(), "sha".to_string())];
+        assert!(check_inline_capacity(&current, &updates).is_ok());
+
+        // Two new refs exceed the limit
+        let updates = vec![
+            ("refs/heads/new1".to_string(), "sha".to_string()),
+            ("refs/heads/new2".to_string(), "sha".to_string()),
+        ];
+        let err = check_inline_capacity(&current, &updates).unwrap_err();
+        assert!(err.to_string().contains("migrate-layout"));
+    }
+
+    #[test]
+    fn test_refs_layout_from_str() {
+        assert_eq!("table".parse::<RefsLayout>().unwrap(), RefsLayout::Table);
+        assert_eq!("inline".parse::<RefsLayout>().unwrap(), RefsLayout::Inline);
+        assert!("json".parse::<RefsLayout>().is_err());
+    }
+}
@@ -1,7 +1,30 @@
+mod banner;
 mod client;
+mod epoch;
+mod expiry;
 mod network_info;
+mod preflight;
+mod ref_impact;
+mod renewal;
 mod tracker;
 
-pub use client::WalrusClient;
+pub use banner::{Banner, BannerLog};
+#[cfg(test)]
+pub(crate) use client::tests::mock_client;
+pub use client::{BlobInfo as StoredBlob, BlobPersistence, EpochInfo, WalrusClient};
+pub use epoch::WalrusEpochProvider;
+pub use expiry::{describe_expiry, estimate_expiry, BlobExpiry, ExpiryReport, EXIT_OK};
 pub use network_info::WalrusNetworkInfo;
-pub use tracker::BlobTracker;
+pub use preflight::{
+    format_amount,
+    CostEstimate,
+    TopUp,
+    UploadPlan,
+    WalletBalances,
+    FAUCET_MAX_ATTEMPTS,
+    FROST_PER_WAL,
+    MIST_PER_SUI,
+};
+pub use ref_impact::{RefImpact, RefImpactCache};
+pub use renewal::{renew_expiring, RenewalPass, Repacked};
+pub use tracker::{BlobInfo as TrackedBlob, BlobTracker};
@@ -0,0 +1,163 @@
+//! Health banner logged while listing refs, so everyday git commands surface expiring blobs
+
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::expiry::BlobExpiry;
+
+/// Minimum time between two banners for the same remote
+pub const BANNER_INTERVAL_SECS: i64 = 60 * 60;
+
+/// What `list` reports about the store behind a remote
+#[derive(Debug, Clone)]
+pub struct Banner {
+    pub state_object_id: String,
+    /// Entries in the objects map
+    pub objects: usize,
+    /// The referenced blob that expires first, if any is tracked
+    pub earliest: Option<BlobExpiry>,
+    /// Referenced blobs whose expiry is unknown
+    pub untracked: usize,
+}
+
+impl fmt::Display for Banner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Walrus remote {}: {} object(s)",
+            abbreviate(&self.state_object_id),
+            self.objects
+        )?;
+        match &self.earliest {
+            Some(blob) => {
+                write!(
+                    f,
+                    ", earliest blob expires at epoch {} ({} epoch(s) left",
+                    blob.end_epoch, blob.epochs_remaining
+                )?;
+                if let Some(at) = &blob.expires_at {
+                    write!(f, ", ≈{}", at)?;
+                }
+                write!(f, ")")?;
+            }
+            None => write!(f, ", no tracked blobs")?,
+        }
+        if self.untracked > 0 {
+            write!(f, ", {} blob(s) with unknown expiry", self.untracked)?;
+        }
+        Ok(())
+    }
+}
+
+/// `0x1234…cdef` for long object IDs
+fn abbreviate(object_id: &str) -> String {
+    if object_id.len() <= 14 {
+        return object_id.to_string();
+    }
+    format!("{}…{}", &object_id[..6], &object_id[object_id.len() - 4..])
+}
+
+/// When a banner was last shown for each remote, kept in `banner.yaml` under the cache directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BannerLog {
+    #[serde(default)]
+    shown: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl BannerLog {
+    /// Load the log from `path` (empty if it doesn't exist)
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read banner log from {:?}", path))?;
+        serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse banner log from {:?}", path))
+    }
+
+    /// Save the log to `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_yaml::to_string(self).context("Failed to serialize banner log")?;
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write banner log to {:?}", path))
+    }
+
+    /// Whether a banner for `remote` is due at `now`; if so, record it as shown
+    ///
+    /// A last-shown time in the future (a clock that went backwards) counts as due.
+    pub fn take_due(&mut self, remote: &str, now: DateTime<Utc>) -> bool {
+        let due = self.shown.get(remote).is_none_or(|last| {
+            *last > now || now - *last >= Duration::seconds(BANNER_INTERVAL_SECS)
+        });
+        if due {
+            self.shown.insert(remote.to_string(), now);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_banner_at_most_once_per_interval_per_remote() {
+        let start: DateTime<Utc> = "2026-01-01T00:00:00Z".parse().unwrap();
+        let mut log = BannerLog::default();
+
+        assert!(log.take_due("0xaaa", start));
+        assert!(!log.take_due("0xaaa", start + Duration::minutes(59)));
+        // Other remotes are limited separately
+        assert!(log.take_due("0xbbb", start + Duration::minutes(30)));
+        assert!(log.take_due("0xaaa", start + Duration::minutes(60)));
+        assert!(!log.take_due("0xaaa", start + Duration::minutes(61)));
+        assert!(log.take_due("0xaaa", start - Duration::days(1)));
+    }
+
+    #[test]
+    fn test_banner_log_survives_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("banner.yaml");
+        let now = Utc::now();
+
+        let mut log = BannerLog::load(&path).unwrap();
+        assert!(log.take_due("0xaaa", now));
+        log.save(&path).unwrap();
+
+        let mut log = BannerLog::load(&path).unwrap();
+        assert!(!log.take_due("0xaaa", now + Duration::minutes(5)));
+    }
+
+    #[test]
+    fn test_banner_display() {
+        let mut banner = Banner {
+            state_object_id: format!("0x{}", "ab".repeat(32)),
+            objects: 42,
+            earliest: None,
+            untracked: 0,
+        };
+        assert_eq!(
+            banner.to_string(),
+            "Walrus remote 0xabab…abab: 42 object(s), no tracked blobs"
+        );
+
+        banner.earliest = Some(BlobExpiry {
+            object_id: "0x1".into(),
+            blob_id: "blob".into(),
+            end_epoch: 120,
+            epochs_remaining: 3,
+            expires_at: None,
+        });
+        banner.untracked = 2;
+        assert_eq!(
+            banner.to_string(),
+            "Walrus remote 0xabab…abab: 42 object(s), earliest blob expires at epoch 120 \
+             (3 epoch(s) left), 2 blob(s) with unknown expiry"
+        );
+    }
+}
@@ -1,13 +1,61 @@
-use std::{io::Write, path::PathBuf, process::Command};
+use std::{
+    ffi::OsString,
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use tempfile::NamedTempFile;
 
+use crate::{proxy::ProxySettings, subprocess::CommandRunner};
+
+/// Timeout for read-only walrus queries (status, epoch info)
+const QUERY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Whether stored blobs are permanent or can be deleted later to reclaim storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlobPersistence {
+    /// Shared permanent blobs (cannot be deleted before expiry)
+    #[default]
+    Permanent,
+    /// Owned deletable blobs (can be reclaimed with `git-remote-walrus reclaim`)
+    Deletable,
+}
+
+impl FromStr for BlobPersistence {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "permanent" => Ok(BlobPersistence::Permanent),
+            "deletable" => Ok(BlobPersistence::Deletable),
+            other => anyhow::bail!(
+                "invalid blob persistence {:?} (expected 'permanent' or 'deletable')",
+                other
+            ),
+        }
+    }
+}
+
+impl fmt::Display for BlobPersistence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobPersistence::Permanent => write!(f, "permanent"),
+            BlobPersistence::Deletable => write!(f, "deletable"),
+        }
+    }
+}
+
 /// Information about a stored blob (from walrus store command)
 #[derive(Debug, Clone)]
 pub struct BlobInfo {
-    /// Sui SharedBlob object ID (for querying status)
+    /// Sui object ID of the SharedBlob, or of the owned Blob for deletable blobs
     pub shared_object_id: String,
     /// Walrus blob ID (for reading content)
     pub blob_id: String,
@@ -24,32 +72,160 @@ pub struct BlobStatus {
 }
 
 /// Walrus epoch information
+///
+/// The CLI reports the epoch start and duration in several shapes; ones we don't understand
+/// deserialize to None rather than failing.
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 #[allow(dead_code)]
 pub struct EpochInfo {
     pub current_epoch: u64,
-    #[serde(default)]
-    pub start_of_current_epoch: Option<serde_json::Value>,
-    #[serde(default)]
-    pub epoch_duration: Option<serde_json::Value>,
+    #[serde(default, deserialize_with = "deserialize_epoch_start")]
+    pub start_of_current_epoch: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "deserialize_epoch_duration")]
+    pub epoch_duration: Option<chrono::Duration>,
     #[serde(default)]
     pub max_epochs_ahead: Option<u64>,
 }
 
+/// Accepts an RFC 3339 string or `{"DateTime": "<RFC 3339>"}`
+fn deserialize_epoch_start<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(value) = Option::<serde_json::Value>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let text = value
+        .as_str()
+        .or_else(|| value.get("DateTime").and_then(|v| v.as_str()));
+    Ok(text
+        .and_then(|text| DateTime::parse_from_rfc3339(text).ok())
+        .map(|t| t.with_timezone(&Utc)))
+}
+
+/// Accepts `{"secs": .., "nanos": ..}`, integer milliseconds, or strings like `14days`
+fn deserialize_epoch_duration<'de, D>(deserializer: D) -> Result<Option<chrono::Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(value) = Option::<serde_json::Value>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let duration = match value {
+        serde_json::Value::Object(map) => map.get("secs").and_then(|s| s.as_u64()).map(|secs| {
+            let nanos = map.get("nanos").and_then(|n| n.as_u64()).unwrap_or(0);
+            Duration::new(secs, nanos as u32)
+        }),
+        serde_json::Value::Number(millis) => millis.as_u64().map(Duration::from_millis),
+        serde_json::Value::String(text) => parse_human_duration(&text),
+        _ => None,
+    };
+    Ok(duration.and_then(|d| chrono::Duration::from_std(d).ok()))
+}
+
+/// Parse a humantime-style duration such as `14days` or `1day 12h`
+fn parse_human_duration(text: &str) -> Option<Duration> {
+    let mut total = Duration::ZERO;
+    let mut parsed_any = false;
+
+    for part in text.split_whitespace() {
+        let split = part.find(|c: char| !c.is_ascii_digit())?;
+        let (number, unit) = part.split_at(split);
+        let value: u64 = number.parse().ok()?;
+        let secs = match unit {
+            "ms" => {
+                total += Duration::from_millis(value);
+                parsed_any = true;
+                continue;
+            }
+            "s" | "sec" | "secs" => value,
+            "m" | "min" | "mins" => value * 60,
+            "h" | "hr" | "hrs" | "hour" | "hours" => value * 3600,
+            "d" | "day" | "days" => value * 86_400,
+            "w" | "week" | "weeks" => value * 604_800,
+            _ => return None,
+        };
+        total += Duration::from_secs(secs);
+        parsed_any = true;
+    }
+
+    parsed_any.then_some(total)
+}
+
 /// Client for interacting with Walrus CLI
 pub struct WalrusClient {
+    binary: PathBuf,
     config_path: Option<PathBuf>,
     default_epochs: u32,
+    persistence: BlobPersistence,
+    encoding: Option<String>,
+    proxy: ProxySettings,
+    idle_timeout: Option<Duration>,
 }
 
 impl WalrusClient {
     /// Create a new Walrus client
     pub fn new(config_path: Option<PathBuf>, default_epochs: u32) -> Self {
         Self {
+            binary: PathBuf::from("walrus"),
             config_path,
             default_epochs,
+            persistence: BlobPersistence::default(),
+            encoding: None,
+            proxy: ProxySettings::default(),
+            idle_timeout: None,
+        }
+    }
+
+    /// Run this walrus CLI instead of `walrus` from PATH
+    pub fn with_binary(mut self, binary: Option<PathBuf>) -> Self {
+        if let Some(binary) = binary {
+            self.binary = binary;
         }
+        self
+    }
+
+    /// CLI this client runs
+    pub fn binary(&self) -> &Path {
+        &self.binary
+    }
+
+    /// Set whether newly stored blobs are permanent or deletable
+    pub fn with_persistence(mut self, persistence: BlobPersistence) -> Self {
+        self.persistence = persistence;
+        self
+    }
+
+    /// Persistence used for newly stored blobs
+    pub fn persistence(&self) -> BlobPersistence {
+        self.persistence
+    }
+
+    /// Set the encoding type passed to `walrus store` (None uses the CLI default)
+    pub fn with_encoding(mut self, encoding: Option<String>) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Encoding type used for newly stored blobs, if overridden
+    pub fn encoding(&self) -> Option<&str> {
+        self.encoding.as_deref()
+    }
+
+    /// Pass proxy settings to the walrus CLI, which makes its own HTTP connections
+    pub fn with_proxy(mut self, proxy: ProxySettings) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Kill walrus commands that make no progress for `idle` (None waits as long as they run)
+    ///
+    /// The CLI makes its own connections, so progress is its output and, on Linux, its CPU
+    /// time and file I/O: a large `walrus read` that prints nothing while it decodes is spared.
+    pub fn with_idle_timeout(mut self, idle: Option<Duration>) -> Self {
+        self.idle_timeout = idle;
+        self
     }
 
     /// Store content on Walrus and return blob info (object_id and blob_id)
@@ -59,6 +235,11 @@ impl WalrusClient {
 
     /// Store content on Walrus with specific epoch duration
     pub fn store_with_epochs(&self, content: &[u8], epochs: u32) -> Result<BlobInfo> {
+        // `walrus store` may reject an empty file; empty objects get constant ContentIds instead
+        if content.is_empty() {
+            anyhow::bail!("Refusing to store an empty blob on Walrus");
+        }
+
         // Create a temporary file for the content
         let mut temp_file =
             NamedTempFile::new().context("Failed to create temporary file for Walrus upload")?;
@@ -71,29 +252,12 @@ impl WalrusClient {
             .flush()
             .context("Failed to flush temporary file")?;
 
-        // Build walrus store command
-        let mut cmd = Command::new("walrus");
-        if let Some(config) = &self.config_path {
-            cmd.arg("--config").arg(config);
-        }
-        cmd.arg("store")
-            .arg("--json")
-            .arg("--share")
-            .arg("--permanent")
-            .arg("--force") // Always create new blob object to get sharedBlobObject ID
-            .arg("--epochs")
-            .arg(epochs.to_string())
-            .arg(temp_file.path());
-
-        // Execute command
-        let output = cmd
-            .output()
-            .context("Failed to execute walrus store command")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("walrus store failed: {}", stderr);
-        }
+        // Build and execute walrus store command
+        let output = self
+            .runner()
+            .args(self.store_args(&[temp_file.path()], epochs))
+            .run()
+            .context("walrus store failed")?;
 
         // Parse JSON output to extract blob info (object_id and blob_id)
         let stdout = String::from_utf8_lossy(&output.stdout);
@@ -114,24 +278,203 @@ impl WalrusClient {
         Ok(blob_info)
     }
 
-    /// Read blob content from Walrus
-    pub fn read(&self, blob_id: &str) -> Result<Vec<u8>> {
-        // Build walrus read command
-        let mut cmd = Command::new("walrus");
+    /// Store several blobs with a single `walrus store` invocation, amortizing the CLI's
+    /// startup cost
+    ///
+    /// Results are in input order; a file the CLI fails to store gets its own error and
+    /// doesn't affect the others.
+    #[cfg(test)]
+    pub fn store_many(&self, contents: &[&[u8]]) -> Vec<Result<BlobInfo>> {
+        self.store_many_with_epochs(contents, self.default_epochs)
+    }
+
+    /// Store several blobs with a single `walrus store` invocation, for `epochs` epochs
+    pub fn store_many_with_epochs(&self, contents: &[&[u8]], epochs: u32) -> Vec<Result<BlobInfo>> {
+        match self.try_store_many(contents, epochs) {
+            Ok(results) => results,
+            Err(e) => {
+                // The invocation as a whole failed, so every file did
+                let message = format!("{:#}", e);
+                contents
+                    .iter()
+                    .map(|_| Err(anyhow::anyhow!("{}", message)))
+                    .collect()
+            }
+        }
+    }
+
+    fn try_store_many(&self, contents: &[&[u8]], epochs: u32) -> Result<Vec<Result<BlobInfo>>> {
+        // `walrus store` may reject an empty file; empty objects get constant ContentIds instead
+        let mut temp_files = Vec::with_capacity(contents.len());
+        for content in contents {
+            if content.is_empty() {
+                temp_files.push(None);
+                continue;
+            }
+            let mut temp_file = NamedTempFile::new()
+                .context("Failed to create temporary file for Walrus upload")?;
+            temp_file
+                .write_all(content)
+                .and_then(|()| temp_file.flush())
+                .context("Failed to write content to temporary file")?;
+            temp_files.push(Some(temp_file));
+        }
+
+        let paths: Vec<&Path> = temp_files.iter().flatten().map(|f| f.path()).collect();
+        let mut stored = if paths.is_empty() {
+            Vec::new()
+        } else {
+            let output = self
+                .runner()
+                .args(self.store_args(&paths, epochs))
+                .output()
+                .context("walrus store failed")?;
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            match Self::parse_store_results(&stdout, &paths) {
+                Ok(results) => results,
+                Err(_) if !output.status.success() => anyhow::bail!(
+                    "walrus store failed ({}): {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                Err(e) => return Err(e),
+            }
+        }
+        .into_iter();
+
+        Ok(temp_files
+            .iter()
+            .map(|temp_file| match temp_file {
+                Some(_) => {
+                    let result = stored.next().expect("one result per stored file");
+                    if let Ok(blob_info) = &result {
+                        tracing::info!(
+                            "Stored blob {} at shared object {} (expires in {} epochs)",
+                            &blob_info.blob_id,
+                            &blob_info.shared_object_id,
+                            epochs
+                        );
+                    }
+                    result
+                }
+                None => Err(anyhow::anyhow!("Refusing to store an empty blob on Walrus")),
+            })
+            .collect())
+    }
+
+    /// Start a walrus command with the configured `--config`
+    pub fn command(&self) -> CommandRunner {
+        let runner = self.runner();
+        match &self.config_path {
+            Some(config) => runner.arg("--config").arg(config),
+            None => runner,
+        }
+    }
+
+    /// Start the walrus CLI with the proxy settings in its environment and the idle timeout
+    fn runner(&self) -> CommandRunner {
+        let runner = self
+            .proxy
+            .env_vars()
+            .into_iter()
+            .fold(CommandRunner::new(&self.binary), |runner, (key, value)| {
+                runner.env(key, value)
+            });
+        match self.idle_timeout {
+            Some(idle) => runner.idle_timeout(idle),
+            None => runner,
+        }
+    }
+
+    /// Build the arguments for `walrus store` of one or more files
+    fn store_args(&self, paths: &[&Path], epochs: u32) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
         if let Some(config) = &self.config_path {
-            cmd.arg("--config").arg(config);
+            args.push("--config".into());
+            args.push(config.into());
+        }
+        args.push("store".into());
+        args.push("--json".into());
+        match self.persistence {
+            BlobPersistence::Permanent => {
+                // Shared blobs can be funded by anyone but can never be deleted
+                args.push("--share".into());
+                args.push("--permanent".into());
+            }
+            BlobPersistence::Deletable => {
+                // Deletable blobs stay owned by the wallet so they can be deleted later
+                args.push("--deletable".into());
+            }
         }
-        cmd.arg("read").arg(blob_id);
+        if let Some(encoding) = &self.encoding {
+            args.push("--encoding-type".into());
+            args.push(encoding.into());
+        }
+        args.push("--force".into()); // Always create new blob object to get its object ID
+        args.push("--epochs".into());
+        args.push(epochs.to_string().into());
+        args.extend(paths.iter().map(|path| path.into()));
+        args
+    }
+
+    /// Delete a deletable blob object owned by the wallet
+    pub fn delete(&self, object_id: &str) -> Result<()> {
+        self.command()
+            .arg("delete")
+            .arg("--object-ids")
+            .arg(object_id)
+            .arg("--yes")
+            .run()
+            .context("walrus delete failed")?;
 
-        // Execute command
-        let output = cmd
-            .output()
-            .context("Failed to execute walrus read command")?;
+        tracing::info!("Deleted blob object {}", object_id);
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("walrus read failed: {}", stderr);
+        Ok(())
+    }
+
+    /// Extend a blob object's lifetime by `epochs` epochs, paid from the wallet
+    ///
+    /// Shared (permanent) blobs are funded through the shared object; deletable blobs must be
+    /// owned by the wallet.
+    pub fn extend(&self, object_id: &str, epochs: u32, persistence: BlobPersistence) -> Result<()> {
+        let mut runner = self
+            .command()
+            .arg("extend")
+            .arg("--blob-obj-id")
+            .arg(object_id)
+            .arg("--epochs-extended")
+            .arg(epochs.to_string());
+        if persistence == BlobPersistence::Permanent {
+            runner = runner.arg("--shared");
         }
+        runner.run().context("walrus extend failed")?;
+
+        tracing::info!("Extended blob object {} by {} epochs", object_id, epochs);
+
+        Ok(())
+    }
+
+    /// Read blob content from Walrus
+    pub fn read(&self, blob_id: &str) -> Result<Vec<u8>> {
+        let output = self
+            .command()
+            .arg("read")
+            .arg(blob_id)
+            .run()
+            .context("walrus read failed")?;
+
+        Ok(output.stdout)
+    }
+
+    /// Read a blob, failing without buffering the rest once it exceeds `max_bytes`
+    pub fn read_limited(&self, blob_id: &str, max_bytes: u64) -> Result<Vec<u8>> {
+        let output = self
+            .command()
+            .arg("read")
+            .arg(blob_id)
+            .max_stdout(max_bytes)
+            .run()
+            .context("walrus read failed")?;
 
         Ok(output.stdout)
     }
@@ -141,23 +484,15 @@ impl WalrusClient {
     pub fn blob_status(&self, blob_id: &str) -> Result<BlobStatus> {
         // Build walrus blob-status command
         // Use --blob-id flag to avoid blob IDs starting with '-' being interpreted as flags
-        let mut cmd = Command::new("walrus");
-        if let Some(config) = &self.config_path {
-            cmd.arg("--config").arg(config);
-        }
-        cmd.arg("blob-status").arg("--json");
-
-        // Execute command
-        let output = cmd
+        let output = self
+            .command()
+            .arg("blob-status")
+            .arg("--json")
             .arg("--blob-id")
             .arg(blob_id)
-            .output()
-            .context("Failed to execute walrus blob-status command")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("walrus blob-status failed: {}", stderr);
-        }
+            .timeout(QUERY_TIMEOUT)
+            .run()
+            .context("walrus blob-status failed")?;
 
         // Parse JSON output
         let stdout = String::from_utf8_lossy(&output.stdout);
@@ -169,22 +504,14 @@ impl WalrusClient {
 
     /// Get current Walrus epoch information
     pub fn current_epoch(&self) -> Result<EpochInfo> {
-        // Build walrus info epoch command
-        let mut cmd = Command::new("walrus");
-        if let Some(config) = &self.config_path {
-            cmd.arg("--config").arg(config);
-        }
-        cmd.arg("info").arg("epoch").arg("--json");
-
-        // Execute command
-        let output = cmd
-            .output()
-            .context("Failed to execute walrus info epoch command")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("walrus info epoch failed: {}", stderr);
-        }
+        let output = self
+            .command()
+            .arg("info")
+            .arg("epoch")
+            .arg("--json")
+            .timeout(QUERY_TIMEOUT)
+            .run()
+            .context("walrus info epoch failed")?;
 
         // Parse JSON output
         let stdout = String::from_utf8_lossy(&output.stdout);
@@ -194,81 +521,136 @@ impl WalrusClient {
         Ok(epoch_info)
     }
 
+    /// Exchange `mist` of the wallet's SUI for the same value in WAL (testnet faucet flow)
+    pub fn get_wal(&self, mist: u128) -> Result<()> {
+        self.command()
+            .arg("get-wal")
+            .arg("--amount")
+            .arg(mist.to_string())
+            .timeout(QUERY_TIMEOUT)
+            .run()
+            .context("walrus get-wal failed")?;
+        Ok(())
+    }
+
     /// Parse blob info (shared_object_id and blob_id) from walrus store output
     fn parse_blob_info(&self, output: &str) -> Result<BlobInfo> {
         // The walrus store command outputs JSON with the blob_id and shared object
         // Format: [{"blobStoreResult": {...}, "path": "..."}]
-        // blobStoreResult contains either:
-        //   - alreadyCertified: Blob already exists (deduplicated)
-        //     { "blobId": "...", "sharedBlobObject": "0x..." }
-        //   - newlyCreated: Blob was just uploaded
-        //     { "blobObject": { "blobId": "..." }, "sharedBlobObject": "0x..." }
+        // (older CLIs print a bare blobStoreResult)
 
         // Try to parse as JSON first
         if let Ok(json) = serde_json::from_str::<serde_json::Value>(output) {
             // Array format with blobStoreResult wrapper
-            if let Some(array) = json.as_array() {
-                if let Some(first) = array.first() {
-                    if let Some(result) = first.get("blobStoreResult") {
-                        // Try newlyCreated (blob was uploaded)
-                        if let Some(nc) = result.get("newlyCreated") {
-                            if let (Some(blob_id), Some(shared_object_id)) = (
-                                nc.get("blobObject")
-                                    .and_then(|bo| bo.get("blobId"))
-                                    .and_then(|id| id.as_str()),
-                                nc.get("sharedBlobObject").and_then(|id| id.as_str()),
-                            ) {
-                                return Ok(BlobInfo {
-                                    shared_object_id: shared_object_id.to_string(),
-                                    blob_id: blob_id.to_string(),
-                                });
-                            }
-                        }
-                        // Try alreadyCertified (blob was deduplicated)
-                        if let Some(ac) = result.get("alreadyCertified") {
-                            if let (Some(blob_id), Some(shared_object_id)) = (
-                                ac.get("blobId").and_then(|id| id.as_str()),
-                                ac.get("sharedBlobObject").and_then(|id| id.as_str()),
-                            ) {
-                                return Ok(BlobInfo {
-                                    shared_object_id: shared_object_id.to_string(),
-                                    blob_id: blob_id.to_string(),
-                                });
-                            }
-                        }
-                    }
-                }
+            let result = match json.as_array() {
+                Some(array) => array.first().and_then(|first| first.get("blobStoreResult")),
+                // Fallback: direct object access (for compatibility)
+                None => Some(&json),
+            };
+            if let Some(Ok(blob_info)) = result.map(Self::parse_store_result) {
+                return Ok(blob_info);
             }
+        }
 
-            // Fallback: try direct object access (for compatibility)
-            if let Some(nc) = json.get("newlyCreated") {
-                if let (Some(blob_id), Some(shared_object_id)) = (
-                    nc.get("blobObject")
-                        .and_then(|bo| bo.get("blobId"))
-                        .and_then(|id| id.as_str()),
-                    nc.get("sharedBlobObject").and_then(|id| id.as_str()),
-                ) {
-                    return Ok(BlobInfo {
-                        shared_object_id: shared_object_id.to_string(),
-                        blob_id: blob_id.to_string(),
-                    });
-                }
-            }
+        anyhow::bail!("Failed to parse blob info from walrus output: {}", output)
+    }
 
-            if let Some(ac) = json.get("alreadyCertified") {
-                if let (Some(blob_id), Some(shared_object_id)) = (
-                    ac.get("blobId").and_then(|id| id.as_str()),
-                    ac.get("sharedBlobObject").and_then(|id| id.as_str()),
-                ) {
-                    return Ok(BlobInfo {
-                        shared_object_id: shared_object_id.to_string(),
-                        blob_id: blob_id.to_string(),
-                    });
-                }
+    /// Parse the result array of a multi-file `walrus store`, matching entries to `paths`
+    ///
+    /// Each input gets its entry's blob info, or an error if the CLI reported a failure for
+    /// it or left it out.
+    fn parse_store_results(output: &str, paths: &[&Path]) -> Result<Vec<Result<BlobInfo>>> {
+        let json: serde_json::Value = serde_json::from_str(output)
+            .with_context(|| format!("Failed to parse walrus store output: {}", output))?;
+        let entries = json
+            .as_array()
+            .with_context(|| format!("walrus store output is not an array: {}", output))?;
+
+        Ok(paths
+            .iter()
+            .map(|path| {
+                let entry = entries
+                    .iter()
+                    .find(|entry| entry.get("path").and_then(|p| p.as_str()) == path.to_str())
+                    .with_context(|| format!("walrus store reported nothing for {:?}", path))?;
+                let result = entry
+                    .get("blobStoreResult")
+                    .with_context(|| format!("walrus store entry has no result: {}", entry))?;
+                Self::parse_store_result(result)
+            })
+            .collect())
+    }
+
+    /// Blob info from one `blobStoreResult`
+    ///
+    /// It holds one of:
+    ///   - newlyCreated: Blob was just uploaded
+    ///     { "blobObject": { "id": "0x...", "blobId": "..." }, "sharedBlobObject": "0x..." }
+    ///   - alreadyCertified: Blob already exists (deduplicated)
+    ///     { "blobId": "...", "sharedBlobObject": "0x..." }
+    ///   - markedInvalid or error: the blob could not be stored
+    fn parse_store_result(result: &serde_json::Value) -> Result<BlobInfo> {
+        // Try newlyCreated (blob was uploaded)
+        if let Some(nc) = result.get("newlyCreated") {
+            if let (Some(blob_id), Some(shared_object_id)) = (
+                nc.get("blobObject")
+                    .and_then(|bo| bo.get("blobId"))
+                    .and_then(|id| id.as_str()),
+                Self::newly_created_object_id(nc),
+            ) {
+                return Ok(BlobInfo {
+                    shared_object_id: shared_object_id.to_string(),
+                    blob_id: blob_id.to_string(),
+                });
+            }
+        }
+        // Try alreadyCertified (blob was deduplicated)
+        if let Some(ac) = result.get("alreadyCertified") {
+            if let (Some(blob_id), Some(shared_object_id)) = (
+                ac.get("blobId").and_then(|id| id.as_str()),
+                ac.get("sharedBlobObject").and_then(|id| id.as_str()),
+            ) {
+                return Ok(BlobInfo {
+                    shared_object_id: shared_object_id.to_string(),
+                    blob_id: blob_id.to_string(),
+                });
             }
         }
+        if let Some(error) = result.get("error") {
+            let field = |name: &str| error.get(name).and_then(|v| v.as_str()).unwrap_or("?");
+            anyhow::bail!(
+                "walrus store failed during {}: {}",
+                field("failurePhase"),
+                field("errorMsg")
+            );
+        }
+        if let Some(invalid) = result.get("markedInvalid") {
+            anyhow::bail!(
+                "walrus store marked blob {} invalid",
+                invalid
+                    .get("blobId")
+                    .and_then(|id| id.as_str())
+                    .unwrap_or("?")
+            );
+        }
 
-        anyhow::bail!("Failed to parse blob info from walrus output: {}", output)
+        anyhow::bail!(
+            "Failed to parse blob info from walrus store result: {}",
+            result
+        )
+    }
+
+    /// Object ID of a newly created blob: the SharedBlob if shared, else the owned Blob
+    fn newly_created_object_id(newly_created: &serde_json::Value) -> Option<&str> {
+        newly_created
+            .get("sharedBlobObject")
+            .and_then(|id| id.as_str())
+            .or_else(|| {
+                newly_created
+                    .get("blobObject")
+                    .and_then(|bo| bo.get("id"))
+                    .and_then(|id| id.as_str())
+            })
     }
 }
 
@@ -279,7 +661,7 @@ impl Default for WalrusClient {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     #[test]
@@ -318,4 +700,310 @@ mod tests {
         assert_eq!(blob_info.blob_id, "newly-created-id");
         assert_eq!(blob_info.shared_object_id, "0xabc");
     }
+
+    #[test]
+    fn test_parse_blob_info_deletable_newly_created() {
+        let client = WalrusClient::default();
+        let output = r#"[{"blobStoreResult": {"newlyCreated": {"blobObject": {"id": "0xdef", "blobId": "deletable-id", "deletable": true}}}, "path": "/tmp/file"}]"#;
+        let blob_info = client.parse_blob_info(output).unwrap();
+        assert_eq!(blob_info.blob_id, "deletable-id");
+        assert_eq!(blob_info.shared_object_id, "0xdef");
+    }
+
+    #[test]
+    fn test_parse_store_results_matches_paths() {
+        let output = r#"[
+            {"blobStoreResult": {"alreadyCertified": {"blobId": "b2", "sharedBlobObject": "0x2"}}, "path": "/tmp/two"},
+            {"blobStoreResult": {"error": {"blobId": null, "failurePhase": "store", "errorMsg": "insufficient WAL"}}, "path": "/tmp/three"},
+            {"blobStoreResult": {"newlyCreated": {"blobObject": {"id": "0xowned", "blobId": "b1"}, "sharedBlobObject": "0x1"}}, "path": "/tmp/one"},
+            {"blobStoreResult": {"markedInvalid": {"blobId": "b4", "event": {}}}, "path": "/tmp/four"}
+        ]"#;
+        let paths = [
+            "/tmp/one",
+            "/tmp/two",
+            "/tmp/three",
+            "/tmp/four",
+            "/tmp/five",
+        ]
+        .map(Path::new);
+        let results = WalrusClient::parse_store_results(output, &paths).unwrap();
+        assert_eq!(results.len(), 5);
+
+        // Entries are matched by path, not position
+        let one = results[0].as_ref().unwrap();
+        assert_eq!(
+            (one.blob_id.as_str(), one.shared_object_id.as_str()),
+            ("b1", "0x1")
+        );
+        assert_eq!(results[1].as_ref().unwrap().shared_object_id, "0x2");
+
+        let errors: Vec<String> = results[2..]
+            .iter()
+            .map(|r| format!("{:#}", r.as_ref().unwrap_err()))
+            .collect();
+        assert!(errors[0].contains("insufficient WAL"), "{}", errors[0]);
+        assert!(errors[1].contains("b4 invalid"), "{}", errors[1]);
+        assert!(errors[2].contains("/tmp/five"), "{}", errors[2]);
+
+        assert!(WalrusClient::parse_store_results("not json", &paths).is_err());
+        assert!(WalrusClient::parse_store_results(r#"{"a": 1}"#, &paths).is_err());
+    }
+
+    #[test]
+    fn test_store_args_permanent() {
+        let client = WalrusClient::new(Some(PathBuf::from("/walrus.yaml")), 3);
+        let args = client.store_args(&[Path::new("/tmp/blob")], 3);
+        let args: Vec<&str> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "--config",
+                "/walrus.yaml",
+                "store",
+                "--json",
+                "--share",
+                "--permanent",
+                "--force",
+                "--epochs",
+                "3",
+                "/tmp/blob"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_store_args_deletable() {
+        let client = WalrusClient::default().with_persistence(BlobPersistence::Deletable);
+        let args = client.store_args(&[Path::new("/tmp/blob")], 5);
+        let args: Vec<&str> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "store",
+                "--json",
+                "--deletable",
+                "--force",
+                "--epochs",
+                "5",
+                "/tmp/blob"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_store_args_encoding() {
+        let client = WalrusClient::default().with_encoding(Some("RS2".to_string()));
+        let args = client.store_args(&[Path::new("/tmp/blob")], 5);
+        let args: Vec<&str> = args.iter().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "store",
+                "--json",
+                "--share",
+                "--permanent",
+                "--encoding-type",
+                "RS2",
+                "--force",
+                "--epochs",
+                "5",
+                "/tmp/blob"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blob_persistence_from_str() {
+        assert_eq!(
+            "permanent".parse::<BlobPersistence>().unwrap(),
+            BlobPersistence::Permanent
+        );
+        assert_eq!(
+            "deletable".parse::<BlobPersistence>().unwrap(),
+            BlobPersistence::Deletable
+        );
+        assert!("forever".parse::<BlobPersistence>().is_err());
+    }
+
+    /// Client backed by the fake CLI in tests/mock/walrus, keeping its blobs in `dir`
+    pub(crate) fn mock_client(dir: &Path) -> WalrusClient {
+        let mock = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/mock/walrus");
+        WalrusClient::new(Some(dir.join("client.yaml")), 5).with_binary(Some(mock))
+    }
+
+    #[test]
+    fn test_mock_store_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+
+        let shared = client.store(b"hello walrus").unwrap();
+        assert!(shared.shared_object_id.starts_with("0x"));
+        assert_eq!(client.read(&shared.blob_id).unwrap(), b"hello walrus");
+
+        // --force creates a new object for the same content
+        let deletable = mock_client(dir.path())
+            .with_persistence(BlobPersistence::Deletable)
+            .store(b"hello walrus")
+            .unwrap();
+        assert_eq!(deletable.blob_id, shared.blob_id);
+        assert_ne!(deletable.shared_object_id, shared.shared_object_id);
+
+        client.delete(&deletable.shared_object_id).unwrap();
+        assert!(client.read("missing").is_err());
+
+        // Empty content never reaches the CLI
+        assert!(client.store(b"").is_err());
+        let calls = std::fs::read_to_string(dir.path().join("calls.log")).unwrap();
+        assert_eq!(calls.lines().filter(|l| l.starts_with("store")).count(), 2);
+    }
+
+    #[test]
+    fn test_mock_store_many_maps_failures_per_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+
+        let contents: [&[u8]; 4] = [b"first", b"mock-fail: no funds", b"", b"fourth"];
+        let results = client.store_many_with_epochs(&contents, 7);
+        assert_eq!(results.len(), 4);
+        assert_eq!(
+            client.read(&results[0].as_ref().unwrap().blob_id).unwrap(),
+            b"first"
+        );
+        assert!(format!("{:#}", results[1].as_ref().unwrap_err()).contains("mock failure"));
+        assert!(results[2].is_err());
+        assert_eq!(
+            client.read(&results[3].as_ref().unwrap().blob_id).unwrap(),
+            b"fourth"
+        );
+
+        // One CLI invocation for all three files
+        let calls = std::fs::read_to_string(dir.path().join("calls.log")).unwrap();
+        let stores: Vec<&str> = calls.lines().filter(|l| l.starts_with("store")).collect();
+        assert_eq!(stores.len(), 1);
+        assert!(stores[0].contains("--epochs 7"), "{}", stores[0]);
+    }
+
+    #[test]
+    fn test_idle_timeout_stops_a_stalled_cli() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path()).with_idle_timeout(Some(Duration::from_millis(300)));
+        let stored = client.store(b"before the stall").unwrap();
+
+        std::fs::write(dir.path().join("stall"), "5").unwrap();
+        let start = std::time::Instant::now();
+        let err = format!("{:#}", client.read(&stored.blob_id).unwrap_err());
+        assert!(err.contains("walrus read failed"), "{}", err);
+        assert!(err.contains("idle timeout"), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_proxy_is_passed_to_the_cli() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy = ProxySettings {
+            https: Some("http://proxy.example:3128".to_string()),
+            no_proxy: vec!["localhost".to_string()],
+            ..ProxySettings::default()
+        };
+        let client = mock_client(dir.path()).with_proxy(proxy);
+
+        client.store(b"through the proxy").unwrap();
+        client.current_epoch().unwrap();
+
+        let seen = std::fs::read_to_string(dir.path().join("proxy.log")).unwrap();
+        assert_eq!(
+            seen,
+            "store https=http://proxy.example:3128 no=localhost\n\
+             info https=http://proxy.example:3128 no=localhost\n"
+        );
+    }
+
+    #[test]
+    fn test_mock_read_limited() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+
+        let stored = client.store(&[b'x'; 4096]).unwrap();
+        assert_eq!(
+            client.read_limited(&stored.blob_id, 4096).unwrap().len(),
+            4096
+        );
+        let err = client.read_limited(&stored.blob_id, 4095).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("more than 4095 bytes"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_mock_extend() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+
+        let stored = client.store(b"extend me").unwrap();
+        client
+            .extend(&stored.shared_object_id, 3, BlobPersistence::Permanent)
+            .unwrap();
+
+        let object =
+            std::fs::read_to_string(dir.path().join("objects").join(&stored.shared_object_id))
+                .unwrap();
+        // Stored at epoch 1 for 5 epochs, then extended by 3
+        assert_eq!(object.split_whitespace().nth(1), Some("9"));
+        assert!(client
+            .extend("0xmissing", 3, BlobPersistence::Permanent)
+            .is_err());
+    }
+
+    #[test]
+    fn test_mock_current_epoch() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+        assert_eq!(client.current_epoch().unwrap().current_epoch, 1);
+
+        std::fs::write(dir.path().join("epoch"), "42").unwrap();
+        assert_eq!(client.current_epoch().unwrap().current_epoch, 42);
+    }
+
+    fn epoch_info(json: &str) -> EpochInfo {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_epoch_duration_formats() {
+        let info = epoch_info(r#"{"currentEpoch":3,"epochDuration":{"secs":86400,"nanos":0}}"#);
+        assert_eq!(info.epoch_duration, Some(chrono::Duration::days(1)));
+
+        let info = epoch_info(r#"{"currentEpoch":3,"epochDuration":1209600000}"#);
+        assert_eq!(info.epoch_duration, Some(chrono::Duration::weeks(2)));
+
+        let info = epoch_info(r#"{"currentEpoch":3,"epochDuration":"1day 12h"}"#);
+        assert_eq!(info.epoch_duration, Some(chrono::Duration::hours(36)));
+
+        let info = epoch_info(r#"{"currentEpoch":3,"epochDuration":"soon"}"#);
+        assert_eq!(info.epoch_duration, None);
+        assert_eq!(epoch_info(r#"{"currentEpoch":3}"#).epoch_duration, None);
+        let info = epoch_info(r#"{"currentEpoch":3,"epochDuration":null}"#);
+        assert_eq!(info.epoch_duration, None);
+    }
+
+    #[test]
+    fn test_start_of_current_epoch() {
+        let info = epoch_info(
+            r#"{"currentEpoch":3,"startOfCurrentEpoch":{"DateTime":"2025-05-01T00:00:00Z"}}"#,
+        );
+        assert_eq!(
+            info.start_of_current_epoch.unwrap().to_rfc3339(),
+            "2025-05-01T00:00:00+00:00"
+        );
+
+        let info = epoch_info(r#"{"currentEpoch":3,"startOfCurrentEpoch":{"Message":"n/a"}}"#);
+        assert!(info.start_of_current_epoch.is_none());
+        let info = epoch_info(r#"{"currentEpoch":3,"startOfCurrentEpoch":"2025-05-01T12:00:00Z"}"#);
+        assert_eq!(
+            info.start_of_current_epoch.unwrap().to_rfc3339(),
+            "2025-05-01T12:00:00+00:00"
+        );
+    }
 }
@@ -0,0 +1,248 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+
+use super::{client::EpochInfo, WalrusClient};
+
+/// Wait before the one retry of a failed epoch query
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Long-running commands (auto-renew) re-read the epoch once it's older than this
+const MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// The current epoch and when it was read
+#[derive(Debug, Clone)]
+pub struct FetchedEpoch {
+    pub info: EpochInfo,
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// Why the current epoch couldn't be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EpochError {
+    /// The walrus CLI isn't installed where we looked
+    CliMissing { binary: String },
+    /// The CLI ran but failed, usually because it couldn't reach the network
+    Unreachable { detail: String },
+    /// The CLI printed something that isn't the epoch JSON we understand
+    UnexpectedJson { detail: String },
+}
+
+impl fmt::Display for EpochError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EpochError::CliMissing { binary } => write!(
+                f,
+                "the walrus CLI `{}` was not found; install it or point `walrus_binary` \
+                 (or WALRUS_BINARY) at it",
+                binary
+            ),
+            EpochError::Unreachable { detail } => write!(
+                f,
+                "could not reach the Walrus network ({}); check your connection and the \
+                 Walrus client config, then try `walrus info epoch`",
+                detail
+            ),
+            EpochError::UnexpectedJson { detail } => write!(
+                f,
+                "`walrus info epoch --json` printed output this version doesn't understand \
+                 ({}); check that the walrus CLI is a supported version",
+                detail
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EpochError {}
+
+impl EpochError {
+    /// Classify a failed `walrus info epoch`
+    fn classify(client: &WalrusClient, err: &anyhow::Error) -> Self {
+        let missing = err.chain().any(|cause| {
+            cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+        });
+        if missing {
+            return EpochError::CliMissing {
+                binary: client.binary().display().to_string(),
+            };
+        }
+        let detail = format!("{:#}", err);
+        if err.chain().any(|cause| cause.is::<serde_json::Error>()) {
+            EpochError::UnexpectedJson { detail }
+        } else {
+            EpochError::Unreachable { detail }
+        }
+    }
+}
+
+/// Reads the current Walrus epoch at most once per command, for every feature that needs it
+///
+/// Callers say whether they need the epoch: [`required`](Self::required) fails with guidance,
+/// [`advisory`](Self::advisory) logs a single notice and lets the caller carry on without it.
+/// Failures are cached like successes, so a command doesn't retry a dead network per call.
+#[derive(Debug, Default)]
+pub struct WalrusEpochProvider {
+    cached: RefCell<Option<(Instant, Result<FetchedEpoch, EpochError>)>>,
+    noticed: Cell<bool>,
+}
+
+impl WalrusEpochProvider {
+    /// The current epoch, or an error explaining why `purpose` can't go ahead without it
+    pub fn required(&self, client: &WalrusClient, purpose: &str) -> anyhow::Result<FetchedEpoch> {
+        self.fetch(client).map_err(|e| {
+            anyhow::Error::new(e).context(format!("{} needs the current Walrus epoch", purpose))
+        })
+    }
+
+    /// The current epoch, or None after noting (once per command) that `purpose` is skipped
+    pub fn advisory(&self, client: &WalrusClient, purpose: &str) -> Option<FetchedEpoch> {
+        match self.fetch(client) {
+            Ok(fetched) => Some(fetched),
+            Err(e) => {
+                if !self.noticed.replace(true) {
+                    tracing::warn!("Skipping {}: {}", purpose, e);
+                }
+                None
+            }
+        }
+    }
+
+    /// Read the epoch afresh next time, as a new command would
+    pub fn reset(&self) {
+        self.cached.take();
+        self.noticed.set(false);
+    }
+
+    fn fetch(&self, client: &WalrusClient) -> Result<FetchedEpoch, EpochError> {
+        if let Some((at, result)) = self.cached.borrow().as_ref() {
+            if at.elapsed() < MAX_AGE {
+                return result.clone();
+            }
+        }
+
+        let mut result = Self::query(client);
+        if let Err(e) = &result {
+            // Installing the CLI won't happen between two attempts
+            if !matches!(e, EpochError::CliMissing { .. }) {
+                tracing::debug!("Retrying `walrus info epoch` after: {}", e);
+                std::thread::sleep(RETRY_DELAY);
+                result = Self::query(client);
+            }
+        }
+        *self.cached.borrow_mut() = Some((Instant::now(), result.clone()));
+        result
+    }
+
+    fn query(client: &WalrusClient) -> Result<FetchedEpoch, EpochError> {
+        let info = client
+            .current_epoch()
+            .map_err(|e| EpochError::classify(client, &e))?;
+        Ok(FetchedEpoch {
+            info,
+            fetched_at: Utc::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::walrus::mock_client;
+
+    fn epoch_queries(dir: &Path) -> usize {
+        std::fs::read_to_string(dir.join("calls.log"))
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| line.starts_with("info epoch"))
+            .count()
+    }
+
+    #[test]
+    fn test_epoch_is_read_once_per_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+        std::fs::write(dir.path().join("epoch"), "7").unwrap();
+
+        let provider = WalrusEpochProvider::default();
+        assert_eq!(
+            provider
+                .required(&client, "Renewing blobs")
+                .unwrap()
+                .info
+                .current_epoch,
+            7
+        );
+        std::fs::write(dir.path().join("epoch"), "8").unwrap();
+        assert_eq!(
+            provider
+                .advisory(&client, "expiry warnings")
+                .unwrap()
+                .info
+                .current_epoch,
+            7
+        );
+        assert_eq!(epoch_queries(dir.path()), 1);
+    }
+
+    #[test]
+    fn test_missing_cli() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path()).with_binary(Some(dir.path().join("no-walrus")));
+
+        let provider = WalrusEpochProvider::default();
+        let err = provider.required(&client, "Renewing blobs").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EpochError>(),
+            Some(EpochError::CliMissing { .. })
+        ));
+        let message = format!("{:#}", err);
+        assert!(message.starts_with("Renewing blobs needs the current Walrus epoch"));
+        assert!(message.contains("WALRUS_BINARY"), "{}", message);
+        assert!(provider.advisory(&client, "expiry warnings").is_none());
+    }
+
+    #[test]
+    fn test_unreachable_network_is_retried_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+        std::fs::write(dir.path().join("offline"), "connection refused").unwrap();
+
+        let provider = WalrusEpochProvider::default();
+        assert!(provider.advisory(&client, "expiry warnings").is_none());
+        let err = provider
+            .required(&client, "Reporting blob expiry")
+            .unwrap_err();
+        match err.downcast_ref::<EpochError>() {
+            Some(EpochError::Unreachable { detail }) => {
+                assert!(detail.contains("connection refused"), "{}", detail)
+            }
+            other => panic!("expected Unreachable, got {:?}", other),
+        }
+        // One query and one retry; the failure is cached for the rest of the command
+        assert_eq!(epoch_queries(dir.path()), 2);
+    }
+
+    #[test]
+    fn test_unexpected_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+        std::fs::write(dir.path().join("epoch"), "unknown").unwrap();
+
+        let err = WalrusEpochProvider::default()
+            .required(&client, "Renewing blobs")
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EpochError>(),
+            Some(EpochError::UnexpectedJson { .. })
+        ));
+        assert!(format!("{:#}", err).contains("supported version"));
+    }
+}
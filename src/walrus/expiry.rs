@@ -0,0 +1,268 @@
+//! Expiration report for the blobs a remote's current state references
+
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+use super::{client::EpochInfo, BlobTracker};
+
+/// Exit code when the report was produced and nothing needs attention
+pub const EXIT_OK: i32 = 0;
+
+/// Exit code when the expiry of some referenced blob could not be determined
+pub const EXIT_ERROR: i32 = 1;
+
+/// Exit code when a referenced blob expires within the requested window
+pub const EXIT_EXPIRING: i32 = 2;
+
+/// Expiry of one referenced blob
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlobExpiry {
+    pub object_id: String,
+    pub blob_id: String,
+    pub end_epoch: u64,
+    pub epochs_remaining: u64,
+    /// Estimated wall-clock expiry (None if the epoch duration is unknown)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+}
+
+/// Expiry of every blob referenced by a remote, soonest first
+#[derive(Debug, Clone, Serialize)]
+pub struct ExpiryReport {
+    pub current_epoch: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epoch_duration_secs: Option<u64>,
+    pub blobs: Vec<BlobExpiry>,
+    /// Referenced blob objects whose expiry is unknown (not tracked and not found on Sui)
+    pub untracked: Vec<String>,
+}
+
+impl ExpiryReport {
+    /// Build the report for `referenced` blob object IDs from the tracker and epoch info
+    ///
+    /// Wall-clock estimates count from the start of the current epoch when known, else `now`.
+    pub fn build(
+        tracker: &BlobTracker,
+        referenced: &BTreeSet<String>,
+        epoch: &EpochInfo,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let mut blobs = Vec::new();
+        let mut untracked = Vec::new();
+        for object_id in referenced {
+            let Some(info) = tracker.get_blob(object_id) else {
+                untracked.push(object_id.clone());
+                continue;
+            };
+
+            let epochs_remaining = info.end_epoch.saturating_sub(epoch.current_epoch);
+            let expires_at = estimate_expiry(epoch, epochs_remaining, now).map(|t| t.to_rfc3339());
+
+            blobs.push(BlobExpiry {
+                object_id: object_id.clone(),
+                blob_id: info.blob_id.clone(),
+                end_epoch: info.end_epoch,
+                epochs_remaining,
+                expires_at,
+            });
+        }
+        blobs.sort_by_key(|blob| blob.end_epoch);
+
+        Self {
+            current_epoch: epoch.current_epoch,
+            epoch_duration_secs: epoch
+                .epoch_duration
+                .and_then(|d| u64::try_from(d.num_seconds()).ok()),
+            blobs,
+            untracked,
+        }
+    }
+
+    /// Blobs whose end epoch is at most `epochs` after the current epoch
+    pub fn expiring_within(&self, epochs: u64) -> Vec<&BlobExpiry> {
+        self.blobs
+            .iter()
+            .filter(|blob| blob.epochs_remaining <= epochs)
+            .collect()
+    }
+
+    /// Exit code for `--fail-if-expiring-within`: 2 if anything expires in the window,
+    /// 1 if some referenced blob's expiry is unknown, 0 otherwise
+    pub fn exit_code(&self, fail_if_expiring_within: Option<u64>) -> i32 {
+        let Some(epochs) = fail_if_expiring_within else {
+            return EXIT_OK;
+        };
+
+        if !self.expiring_within(epochs).is_empty() {
+            EXIT_EXPIRING
+        } else if !self.untracked.is_empty() {
+            EXIT_ERROR
+        } else {
+            EXIT_OK
+        }
+    }
+}
+
+/// Estimated wall-clock start of the epoch `epochs_remaining` epochs after the current one,
+/// which is when a blob with that many epochs left expires
+///
+/// Counts from the start of the current epoch when known, else `now`; None if the epoch
+/// duration is unknown.
+pub fn estimate_expiry(
+    epoch: &EpochInfo,
+    epochs_remaining: u64,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let remaining = epoch
+        .epoch_duration?
+        .checked_mul(i32::try_from(epochs_remaining).ok()?)?;
+    epoch
+        .start_of_current_epoch
+        .unwrap_or(now)
+        .checked_add_signed(remaining)
+}
+
+/// `≈ 2024-08-03, in 13 days` (or `in 5 hours`, or `already past`) for an estimated expiry
+pub fn describe_expiry(expires_at: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let left = expires_at - now;
+    let relative = if left <= Duration::zero() {
+        "already past".to_string()
+    } else if left < Duration::days(1) {
+        format!("in {} hour(s)", left.num_hours().max(1))
+    } else {
+        format!("in {} day(s)", left.num_days())
+    };
+    format!("≈ {}, {}", expires_at.format("%Y-%m-%d"), relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> BlobTracker {
+        let mut tracker = BlobTracker::default();
+        tracker.track_blob("0x1".to_string(), "blob1".to_string(), 105, None);
+        tracker.track_blob("0x2".to_string(), "blob2".to_string(), 150, None);
+        // Tracked but not referenced by this remote
+        tracker.track_blob("0x9".to_string(), "blob9".to_string(), 100, None);
+        tracker
+    }
+
+    fn epoch(json: &str) -> EpochInfo {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn referenced(ids: &[&str]) -> BTreeSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn test_report_remaining_epochs_and_wall_clock() {
+        let epoch = epoch(
+            r#"{"currentEpoch":100,"startOfCurrentEpoch":{"DateTime":"2025-01-01T00:00:00Z"},
+                "epochDuration":{"secs":86400,"nanos":0}}"#,
+        );
+        let report =
+            ExpiryReport::build(&tracker(), &referenced(&["0x2", "0x1"]), &epoch, Utc::now());
+
+        assert_eq!(report.current_epoch, 100);
+        assert_eq!(report.epoch_duration_secs, Some(86_400));
+        assert_eq!(report.blobs.len(), 2);
+        assert_eq!(report.blobs[0].object_id, "0x1");
+        assert_eq!(report.blobs[0].epochs_remaining, 5);
+        assert_eq!(
+            report.blobs[0].expires_at.as_deref(),
+            Some("2025-01-06T00:00:00+00:00")
+        );
+        assert_eq!(report.blobs[1].epochs_remaining, 50);
+        assert!(report.untracked.is_empty());
+
+        // Without a duration there is no wall-clock estimate
+        let report = ExpiryReport::build(
+            &tracker(),
+            &referenced(&["0x1"]),
+            &self::epoch(r#"{"currentEpoch":100}"#),
+            Utc::now(),
+        );
+        assert_eq!(report.blobs[0].expires_at, None);
+    }
+
+    #[test]
+    fn test_exit_code_matrix() {
+        let epoch = epoch(r#"{"currentEpoch":100}"#);
+        let now = Utc::now();
+        let healthy = ExpiryReport::build(&tracker(), &referenced(&["0x1", "0x2"]), &epoch, now);
+        let partial =
+            ExpiryReport::build(&tracker(), &referenced(&["0x2", "0xunknown"]), &epoch, now);
+        let mixed =
+            ExpiryReport::build(&tracker(), &referenced(&["0x1", "0xunknown"]), &epoch, now);
+
+        // No threshold: always 0
+        assert_eq!(healthy.exit_code(None), EXIT_OK);
+        assert_eq!(partial.exit_code(None), EXIT_OK);
+
+        // 0x1 has 5 epochs left
+        assert_eq!(healthy.exit_code(Some(4)), EXIT_OK);
+        assert_eq!(healthy.exit_code(Some(5)), EXIT_EXPIRING);
+        assert_eq!(healthy.exit_code(Some(60)), EXIT_EXPIRING);
+
+        // Unknown expiry is an operational error unless something is known to be expiring
+        assert_eq!(partial.exit_code(Some(10)), EXIT_ERROR);
+        assert_eq!(partial.exit_code(Some(50)), EXIT_EXPIRING);
+        assert_eq!(mixed.exit_code(Some(10)), EXIT_EXPIRING);
+
+        // Blobs other remotes uploaded don't count
+        let unreferenced = ExpiryReport::build(&tracker(), &referenced(&[]), &epoch, now);
+        assert_eq!(unreferenced.exit_code(Some(1000)), EXIT_OK);
+    }
+
+    #[test]
+    fn test_epochs_to_wall_clock() {
+        let now: DateTime<Utc> = "2024-07-21T06:00:00Z".parse().unwrap();
+        let fortnightly = epoch(
+            r#"{"currentEpoch":100,"startOfCurrentEpoch":"2024-07-20T00:00:00Z",
+                "epochDuration":"14days"}"#,
+        );
+
+        // Counted from the epoch start, not from now
+        let expires_at = estimate_expiry(&fortnightly, 1, now).unwrap();
+        assert_eq!(expires_at.to_rfc3339(), "2024-08-03T00:00:00+00:00");
+        assert_eq!(
+            describe_expiry(expires_at, now),
+            "≈ 2024-08-03, in 12 day(s)"
+        );
+
+        // A blob ending at the current epoch is already gone
+        let expires_at = estimate_expiry(&fortnightly, 0, now).unwrap();
+        assert_eq!(expires_at.to_rfc3339(), "2024-07-20T00:00:00+00:00");
+        assert_eq!(
+            describe_expiry(expires_at, now),
+            "≈ 2024-07-20, already past"
+        );
+
+        // Without a start, count from now
+        let daily = epoch(r#"{"currentEpoch":100,"epochDuration":{"secs":86400,"nanos":0}}"#);
+        let expires_at = estimate_expiry(&daily, 13, now).unwrap();
+        assert_eq!(expires_at.to_rfc3339(), "2024-08-03T06:00:00+00:00");
+        assert_eq!(
+            describe_expiry(expires_at, now),
+            "≈ 2024-08-03, in 13 day(s)"
+        );
+
+        let hourly = epoch(r#"{"currentEpoch":100,"epochDuration":3600000}"#);
+        let expires_at = estimate_expiry(&hourly, 5, now).unwrap();
+        assert_eq!(
+            describe_expiry(expires_at, now),
+            "≈ 2024-07-21, in 5 hour(s)"
+        );
+
+        // Unknown duration, or too many epochs to represent
+        assert_eq!(
+            estimate_expiry(&epoch(r#"{"currentEpoch":100}"#), 5, now),
+            None
+        );
+        assert_eq!(estimate_expiry(&daily, u64::MAX, now), None);
+    }
+}
@@ -1,12 +1,10 @@
-use std::{
-    fs,
-    path::{Path, PathBuf},
-    process::Command,
-};
+use std::{fs, path::Path, time::Duration};
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
+use super::WalrusClient;
+
 /// Walrus network size limits
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -17,12 +15,28 @@ pub struct SizeInfo {
     pub max_blob_size: u64,
 }
 
+/// Walrus storage prices (in FROST; 1 WAL = 10^9 FROST)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PriceInfo {
+    /// Price per storage unit per epoch
+    pub storage_price_per_unit_size: u64,
+    /// One-off price per storage unit written
+    pub write_price_per_unit_size: u64,
+}
+
 /// Walrus network information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct WalrusNetworkInfo {
     /// Size constraints
     pub size_info: SizeInfo,
+    /// Encoding types the network accepts for `walrus store --encoding-type`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encoding_types: Vec<String>,
+    /// Storage prices, used to estimate upload costs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_info: Option<PriceInfo>,
     /// Timestamp when this was last queried (for potential cache invalidation)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub queried_at: Option<String>,
@@ -61,25 +75,16 @@ impl WalrusNetworkInfo {
     }
 
     /// Query network info from Walrus CLI
-    pub fn query(walrus_config_path: Option<&PathBuf>) -> Result<Self> {
-        let mut cmd = Command::new("walrus");
-
-        if let Some(config_path) = walrus_config_path {
-            cmd.arg("--config").arg(config_path);
-        }
+    pub fn query(walrus: &WalrusClient) -> Result<Self> {
+        let cmd = walrus
+            .command()
+            .arg("info")
+            .arg("--json")
+            .timeout(Duration::from_secs(120));
 
-        cmd.arg("info").arg("--json");
+        tracing::debug!("Querying Walrus network info: {}", cmd.describe());
 
-        tracing::debug!("Querying Walrus network info: {:?}", cmd);
-
-        let output = cmd
-            .output()
-            .context("Failed to execute walrus info command")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("walrus info command failed: {}", stderr);
-        }
+        let output = cmd.run().context("walrus info command failed")?;
 
         let stdout = String::from_utf8_lossy(&output.stdout);
 
@@ -107,6 +112,8 @@ impl WalrusNetworkInfo {
                 storage_unit_size,
                 max_blob_size,
             },
+            encoding_types: parse_encoding_types(&json),
+            price_info: parse_price_info(&json),
             queried_at: Some(chrono::Utc::now().to_rfc3339()),
         })
     }
@@ -115,6 +122,58 @@ impl WalrusNetworkInfo {
     pub fn max_blob_size(&self) -> u64 {
         self.size_info.max_blob_size
     }
+
+    /// Check that `encoding` is one of the encoding types this network supports
+    pub fn check_encoding(&self, encoding: &str) -> Result<()> {
+        if self.encoding_types.is_empty() {
+            anyhow::bail!(
+                "cannot use walrus_encoding {:?}: `walrus info` did not report any supported encoding types",
+                encoding
+            );
+        }
+
+        if !self.encoding_types.iter().any(|t| t == encoding) {
+            anyhow::bail!(
+                "walrus_encoding {:?} is not supported by this Walrus network (supported: {})",
+                encoding,
+                self.encoding_types.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Extract storage prices from `walrus info --json` output
+fn parse_price_info(json: &serde_json::Value) -> Option<PriceInfo> {
+    let price_info = json.get("priceInfo")?;
+    Some(PriceInfo {
+        storage_price_per_unit_size: price_info.get("storagePricePerUnitSize")?.as_u64()?,
+        write_price_per_unit_size: price_info.get("writePricePerUnitSize")?.as_u64()?,
+    })
+}
+
+/// Extract supported encoding types from `walrus info --json` output
+///
+/// Entries may be plain strings or objects with an `encodingType` field.
+fn parse_encoding_types(json: &serde_json::Value) -> Vec<String> {
+    let Some(types) = json
+        .get("encodingInfo")
+        .and_then(|info| info.get("encodingTypes"))
+        .and_then(|types| types.as_array())
+    else {
+        return Vec::new();
+    };
+
+    types
+        .iter()
+        .filter_map(|entry| {
+            entry
+                .as_str()
+                .or_else(|| entry.get("encodingType").and_then(|t| t.as_str()))
+                .map(str::to_string)
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -122,6 +181,7 @@ mod tests {
     use tempfile::tempdir;
 
     use super::*;
+    use crate::walrus::client::tests::mock_client;
 
     #[test]
     fn test_save_and_load() {
@@ -133,6 +193,8 @@ mod tests {
                 storage_unit_size: 1048576,
                 max_blob_size: 1834952,
             },
+            encoding_types: vec!["RS2".to_string()],
+            price_info: None,
             queried_at: Some("2025-10-15T03:46:32Z".to_string()),
         };
 
@@ -141,6 +203,7 @@ mod tests {
         let loaded = WalrusNetworkInfo::load(&path).unwrap().unwrap();
         assert_eq!(loaded.size_info.max_blob_size, 1834952);
         assert_eq!(loaded.size_info.storage_unit_size, 1048576);
+        assert_eq!(loaded.encoding_types, vec!["RS2".to_string()]);
     }
 
     #[test]
@@ -159,9 +222,75 @@ mod tests {
                 storage_unit_size: 1048576,
                 max_blob_size: 1834952,
             },
+            encoding_types: Vec::new(),
+            price_info: None,
             queried_at: None,
         };
 
         assert_eq!(info.max_blob_size(), 1834952);
     }
+
+    #[test]
+    fn test_parse_encoding_types() {
+        let json = serde_json::json!({
+            "encodingInfo": {
+                "encodingTypes": ["RS2", {"encodingType": "RedStuffRaptorQ"}]
+            }
+        });
+        assert_eq!(parse_encoding_types(&json), vec!["RS2", "RedStuffRaptorQ"]);
+        assert!(parse_encoding_types(&serde_json::json!({})).is_empty());
+    }
+
+    #[test]
+    fn test_parse_price_info() {
+        let json = serde_json::json!({
+            "priceInfo": {
+                "storagePricePerUnitSize": 11000,
+                "writePricePerUnitSize": 20000
+            }
+        });
+        assert_eq!(
+            parse_price_info(&json),
+            Some(PriceInfo {
+                storage_price_per_unit_size: 11000,
+                write_price_per_unit_size: 20000,
+            })
+        );
+        assert_eq!(parse_price_info(&serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn test_query_mock_walrus() {
+        let dir = tempdir().unwrap();
+        let info = WalrusNetworkInfo::query(&mock_client(dir.path())).unwrap();
+
+        assert_eq!(info.size_info.storage_unit_size, 1024 * 1024);
+        assert_eq!(info.max_blob_size(), 1024 * 1024);
+        assert_eq!(info.encoding_types, vec!["RS2".to_string()]);
+        assert!(info.price_info.is_some());
+        assert!(info.queried_at.is_some());
+    }
+
+    #[test]
+    fn test_check_encoding() {
+        let mut info = WalrusNetworkInfo {
+            size_info: SizeInfo {
+                storage_unit_size: 1,
+                max_blob_size: 1,
+            },
+            encoding_types: vec!["RS2".to_string()],
+            price_info: None,
+            queried_at: None,
+        };
+
+        assert!(info.check_encoding("RS2").is_ok());
+
+        let err = info.check_encoding("RS3").unwrap_err().to_string();
+        assert!(err.contains("\"RS3\" is not supported"), "{}", err);
+        assert!(err.contains("supported: RS2"), "{}", err);
+
+        // Older walrus CLIs don't report encodings, so nothing can be validated
+        info.encoding_types.clear();
+        assert!(info.check_encoding("RS2").is_err());
+    }
 }
@@ -0,0 +1,333 @@
+//! Pre-flight cost estimate for a push, checked against the wallet before any upload
+
+use anyhow::Result;
+
+use super::network_info::PriceInfo;
+use crate::sui::SuiNetwork;
+
+/// FROST per WAL
+pub const FROST_PER_WAL: u128 = 1_000_000_000;
+
+/// MIST per SUI
+pub const MIST_PER_SUI: u128 = 1_000_000_000;
+
+/// FROST `walrus get-wal` gives for each MIST exchanged (1 WAL per SUI)
+const FROST_PER_MIST_EXCHANGED: u128 = FROST_PER_WAL / MIST_PER_SUI;
+
+/// Encoded size is roughly this multiple of the blob size (erasure coding)
+const ENCODING_EXPANSION: u64 = 5;
+
+/// Per-blob metadata stored on every shard, charged regardless of blob size
+const BLOB_METADATA_OVERHEAD: u64 = 64 * 1024 * 1024;
+
+/// Gas for registering and certifying one blob (walrus store)
+const GAS_PER_BLOB_MIST: u128 = 10_000_000;
+
+/// Base gas for the state-update PTB
+const GAS_PTB_BASE_MIST: u128 = 10_000_000;
+
+/// Additional gas per ref upserted by the state-update PTB
+const GAS_PER_REF_MIST: u128 = 1_000_000;
+
+/// What a push is about to spend resources on
+#[derive(Debug, Clone, Default)]
+pub struct UploadPlan {
+    /// Size in bytes of each blob to be stored
+    pub blob_sizes: Vec<u64>,
+    /// Storage duration for the blobs
+    pub epochs: u32,
+    /// Refs updated by the state-update PTB (0 if no PTB is planned)
+    pub refs: usize,
+}
+
+/// Faucet rounds `auto_faucet` attempts before a push gives up
+pub const FAUCET_MAX_ATTEMPTS: u32 = 3;
+
+/// A faucet request covering part of a shortfall
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopUp {
+    /// Request SUI from the network's faucet
+    Sui,
+    /// Exchange this much SUI (in MIST) for WAL
+    Wal { mist: u128 },
+}
+
+/// Estimated resources needed for an upload plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostEstimate {
+    /// WAL needed for storage, in FROST
+    pub wal_frost: u128,
+    /// SUI needed for gas, in MIST
+    pub sui_mist: u128,
+}
+
+/// Wallet balances to check an estimate against
+#[derive(Debug, Clone)]
+pub struct WalletBalances {
+    /// Address paying for the push
+    pub address: String,
+    /// SUI balance in MIST
+    pub sui_mist: u128,
+    /// WAL balance in FROST (None if the WAL coin type is unknown for this network)
+    pub wal_frost: Option<u128>,
+}
+
+impl CostEstimate {
+    /// Estimate the cost of `plan` at `prices`, with storage billed per `storage_unit_size` bytes
+    pub fn estimate(plan: &UploadPlan, prices: &PriceInfo, storage_unit_size: u64) -> Self {
+        let unit = storage_unit_size.max(1) as u128;
+
+        let wal_frost = plan
+            .blob_sizes
+            .iter()
+            .map(|&size| {
+                let encoded =
+                    size as u128 * ENCODING_EXPANSION as u128 + BLOB_METADATA_OVERHEAD as u128;
+                let units = encoded.div_ceil(unit);
+                units * prices.storage_price_per_unit_size as u128 * plan.epochs as u128
+                    + units * prices.write_price_per_unit_size as u128
+            })
+            .sum();
+
+        let mut sui_mist = plan.blob_sizes.len() as u128 * GAS_PER_BLOB_MIST;
+        if plan.refs > 0 {
+            sui_mist += GAS_PTB_BASE_MIST + plan.refs as u128 * GAS_PER_REF_MIST;
+        }
+
+        Self {
+            wal_frost,
+            sui_mist,
+        }
+    }
+
+    /// Fail fast if the wallet can't cover this estimate
+    pub fn check(&self, balances: &WalletBalances) -> Result<()> {
+        if let Some(wal_frost) = balances.wal_frost {
+            if wal_frost < self.wal_frost {
+                anyhow::bail!(
+                    "need ≈{} WAL, wallet has {} — top up address {}",
+                    format_amount(self.wal_frost, FROST_PER_WAL),
+                    format_amount(wal_frost, FROST_PER_WAL),
+                    balances.address
+                );
+            }
+        }
+
+        if balances.sui_mist < self.sui_mist {
+            anyhow::bail!(
+                "need ≈{} SUI for gas, wallet has {} — top up address {}",
+                format_amount(self.sui_mist, MIST_PER_SUI),
+                format_amount(balances.sui_mist, MIST_PER_SUI),
+                balances.address
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Faucet requests that would cover what `balances` lack for this estimate on `network`
+    ///
+    /// SUI comes first, since exchanging for WAL spends SUI: the wallet needs the gas plus
+    /// the SUI the exchange takes. Faucets only exist on testnet, devnet and localnet; a
+    /// shortfall anywhere else (mainnet in particular) is an error.
+    pub fn top_ups(&self, balances: &WalletBalances, network: SuiNetwork) -> Result<Vec<TopUp>> {
+        let exchange_mist = balances
+            .wal_frost
+            .filter(|wal| *wal < self.wal_frost)
+            .map(|wal| (self.wal_frost - wal).div_ceil(FROST_PER_MIST_EXCHANGED));
+
+        let mut top_ups = Vec::new();
+        if balances.sui_mist < self.sui_mist + exchange_mist.unwrap_or(0) {
+            top_ups.push(TopUp::Sui);
+        }
+        if let Some(mist) = exchange_mist {
+            top_ups.push(TopUp::Wal { mist });
+        }
+
+        if !top_ups.is_empty() {
+            match network {
+                SuiNetwork::Testnet | SuiNetwork::Devnet | SuiNetwork::Localnet => {}
+                SuiNetwork::Mainnet => anyhow::bail!(
+                    "auto_faucet never tops up on mainnet — fund address {} yourself",
+                    balances.address
+                ),
+                SuiNetwork::Unknown => anyhow::bail!(
+                    "auto_faucet only tops up on testnet, devnet and localnet, and this \
+                     network is unrecognized — fund address {} yourself",
+                    balances.address
+                ),
+            }
+        }
+        Ok(top_ups)
+    }
+}
+
+/// Format a base-unit amount as a decimal with up to 4 significant fractional digits
+pub fn format_amount(amount: u128, per_unit: u128) -> String {
+    let value = amount as f64 / per_unit as f64;
+    let formatted = format!("{:.4}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRICES: PriceInfo = PriceInfo {
+        storage_price_per_unit_size: 100,
+        write_price_per_unit_size: 20_000,
+    };
+    const UNIT: u64 = 1024 * 1024;
+
+    fn balances(sui: u128, wal: Option<u128>) -> WalletBalances {
+        WalletBalances {
+            address: "0xabc".to_string(),
+            sui_mist: sui,
+            wal_frost: wal,
+        }
+    }
+
+    #[test]
+    fn test_estimate_fixture() {
+        // One 1 MiB blob: 5 MiB encoded + 64 MiB metadata = 69 units
+        let plan = UploadPlan {
+            blob_sizes: vec![UNIT],
+            epochs: 10,
+            refs: 2,
+        };
+        let estimate = CostEstimate::estimate(&plan, &PRICES, UNIT);
+        assert_eq!(estimate.wal_frost, 69 * 100 * 10 + 69 * 20_000);
+        assert_eq!(
+            estimate.sui_mist,
+            GAS_PER_BLOB_MIST + GAS_PTB_BASE_MIST + 2 * GAS_PER_REF_MIST
+        );
+
+        // No blobs and no PTB costs nothing
+        let estimate = CostEstimate::estimate(&UploadPlan::default(), &PRICES, UNIT);
+        assert_eq!(
+            estimate,
+            CostEstimate {
+                wal_frost: 0,
+                sui_mist: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_check_short_on_wal() {
+        let estimate = CostEstimate {
+            wal_frost: 800_000_000,
+            sui_mist: 1,
+        };
+        let err = estimate
+            .check(&balances(MIST_PER_SUI, Some(200_000_000)))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "need ≈0.8 WAL, wallet has 0.2 — top up address 0xabc");
+    }
+
+    #[test]
+    fn test_check_short_on_sui() {
+        let estimate = CostEstimate {
+            wal_frost: 0,
+            sui_mist: 20_000_000,
+        };
+        let err = estimate
+            .check(&balances(5_000_000, Some(0)))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("need ≈0.02 SUI"), "{}", err);
+        assert!(err.contains("0xabc"), "{}", err);
+    }
+
+    #[test]
+    fn test_check_enough_funds() {
+        let estimate = CostEstimate {
+            wal_frost: 800_000_000,
+            sui_mist: 20_000_000,
+        };
+        assert!(estimate
+            .check(&balances(MIST_PER_SUI, Some(FROST_PER_WAL)))
+            .is_ok());
+        // Unknown WAL balance only checks gas
+        assert!(estimate.check(&balances(MIST_PER_SUI, None)).is_ok());
+    }
+
+    #[test]
+    fn test_top_ups_below_and_above_thresholds() {
+        let estimate = CostEstimate {
+            wal_frost: 800_000_000,
+            sui_mist: 20_000_000,
+        };
+
+        // Short on both: SUI first, then the SUI that exchanges for exactly the missing WAL
+        assert_eq!(
+            estimate
+                .top_ups(&balances(5_000_000, Some(300_000_000)), SuiNetwork::Testnet)
+                .unwrap(),
+            vec![TopUp::Sui, TopUp::Wal { mist: 500_000_000 }]
+        );
+        // Enough SUI for gas but not for the exchange as well
+        assert_eq!(
+            estimate
+                .top_ups(
+                    &balances(100_000_000, Some(300_000_000)),
+                    SuiNetwork::Testnet
+                )
+                .unwrap(),
+            vec![TopUp::Sui, TopUp::Wal { mist: 500_000_000 }]
+        );
+        // Enough SUI for both only needs the exchange
+        assert_eq!(
+            estimate
+                .top_ups(
+                    &balances(MIST_PER_SUI, Some(300_000_000)),
+                    SuiNetwork::Testnet
+                )
+                .unwrap(),
+            vec![TopUp::Wal { mist: 500_000_000 }]
+        );
+        // Enough of both needs nothing
+        assert!(estimate
+            .top_ups(
+                &balances(MIST_PER_SUI, Some(FROST_PER_WAL)),
+                SuiNetwork::Testnet
+            )
+            .unwrap()
+            .is_empty());
+        // Without a known WAL coin type only gas is topped up
+        assert_eq!(
+            estimate
+                .top_ups(&balances(0, None), SuiNetwork::Localnet)
+                .unwrap(),
+            vec![TopUp::Sui]
+        );
+    }
+
+    #[test]
+    fn test_top_ups_refused_on_mainnet() {
+        let estimate = CostEstimate {
+            wal_frost: 800_000_000,
+            sui_mist: 20_000_000,
+        };
+        let short = balances(5_000_000, Some(0));
+
+        let err = estimate
+            .top_ups(&short, SuiNetwork::Mainnet)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("never tops up on mainnet"), "{}", err);
+        assert!(estimate.top_ups(&short, SuiNetwork::Unknown).is_err());
+        // A funded mainnet wallet has nothing to refuse
+        assert!(estimate
+            .top_ups(
+                &balances(MIST_PER_SUI, Some(FROST_PER_WAL)),
+                SuiNetwork::Mainnet
+            )
+            .unwrap()
+            .is_empty());
+    }
+}
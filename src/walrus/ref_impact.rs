@@ -0,0 +1,246 @@
+//! Refs whose objects live in each blob, so expiry warnings can say what a lost blob breaks
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{ParsedContentId, State};
+
+/// Ref names with objects reachable from their tip stored in each of some blob objects
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefImpact {
+    blobs: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl RefImpact {
+    /// Build the map of `state`'s refs for each of `blob_object_ids`; `reachable` lists the Git
+    /// SHA-1s reachable from a tip
+    ///
+    /// Each distinct tip is walked once, however many refs point at it.
+    pub fn build(
+        state: &State,
+        blob_object_ids: &BTreeSet<&str>,
+        mut reachable: impl FnMut(&str) -> Result<Vec<String>>,
+    ) -> Result<Self> {
+        let mut blobs_by_tip: HashMap<&str, BTreeSet<String>> = HashMap::new();
+        let mut blobs: BTreeMap<String, BTreeSet<String>> = blob_object_ids
+            .iter()
+            .map(|blob_object_id| (blob_object_id.to_string(), BTreeSet::new()))
+            .collect();
+        for (ref_name, tip) in &state.refs {
+            if !blobs_by_tip.contains_key(tip.as_str()) {
+                let tip_blobs = reachable(tip)?
+                    .iter()
+                    .filter_map(|git_sha1| state.objects.get(git_sha1))
+                    .filter_map(|content_id| ParsedContentId::parse(content_id).ok())
+                    .filter_map(|parsed| parsed.blob_object_id().map(str::to_string))
+                    .filter(|blob_object_id| blob_object_ids.contains(blob_object_id.as_str()))
+                    .collect();
+                blobs_by_tip.insert(tip, tip_blobs);
+            }
+            for blob_object_id in &blobs_by_tip[tip.as_str()] {
+                blobs
+                    .entry(blob_object_id.clone())
+                    .or_default()
+                    .insert(ref_name.clone());
+            }
+        }
+
+        Ok(Self { blobs })
+    }
+
+    /// Refs that would have unreadable objects if `blob_object_id` expired
+    pub fn refs_in(&self, blob_object_id: &str) -> Vec<&str> {
+        self.blobs
+            .get(blob_object_id)
+            .map(|refs| refs.iter().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Local cache of the refs each expiring blob holds objects for, kept in `ref_impact.yaml`
+///
+/// Entries are per remote, blob object and the Walrus epoch they were built in, so warnings
+/// repeated by every push in an epoch walk the history once. A ref moved onto or off a blob's
+/// objects shows from the next epoch.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RefImpactCache {
+    /// Remote -> blob object ID -> epoch and refs
+    #[serde(default)]
+    blobs: BTreeMap<String, BTreeMap<String, CachedRefs>>,
+    /// Whole-state maps written by earlier versions, dropped on the next save
+    #[serde(default, skip_serializing)]
+    remotes: serde_yaml::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CachedRefs {
+    epoch: u64,
+    refs: BTreeSet<String>,
+}
+
+impl RefImpactCache {
+    /// Load the cache from `path` (empty if it doesn't exist)
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read ref impact from {:?}", path))?;
+        serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse ref impact from {:?}", path))
+    }
+
+    /// Save the cache to `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_yaml::to_string(self).context("Failed to serialize ref impact")?;
+        fs::write(path, content)
+            .with_context(|| format!("Failed to write ref impact to {:?}", path))
+    }
+
+    /// The map of `remote`'s refs for `blob_object_ids`, if each was built in `epoch`
+    pub fn get(
+        &self,
+        remote: &str,
+        blob_object_ids: &BTreeSet<&str>,
+        epoch: u64,
+    ) -> Option<RefImpact> {
+        let cached = self.blobs.get(remote)?;
+        let blobs = blob_object_ids
+            .iter()
+            .map(|blob_object_id| {
+                let entry = cached
+                    .get(*blob_object_id)
+                    .filter(|entry| entry.epoch == epoch)?;
+                Some((blob_object_id.to_string(), entry.refs.clone()))
+            })
+            .collect::<Option<_>>()?;
+        Some(RefImpact { blobs })
+    }
+
+    /// Record `impact`, built in `epoch`, for `remote`; entries from other epochs are dropped
+    pub fn insert(&mut self, remote: &str, impact: &RefImpact, epoch: u64) {
+        let cached = self.blobs.entry(remote.to_string()).or_default();
+        cached.retain(|_, entry| entry.epoch == epoch);
+        for (blob_object_id, refs) in &impact.blobs {
+            cached.insert(
+                blob_object_id.clone(),
+                CachedRefs {
+                    epoch,
+                    refs: refs.clone(),
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> State {
+        let mut state = State::default();
+        for (git_sha1, content_id) in [
+            ("c1", "0xold:0:10"),
+            ("c2", "0xnew:0:10"),
+            ("c3", "0xnew:10:10"),
+            ("t1", "0xlegacy"),
+        ] {
+            state.objects.insert(git_sha1.into(), content_id.into());
+        }
+        state.refs.insert("refs/heads/main".into(), "c2".into());
+        state.refs.insert("refs/heads/old".into(), "c1".into());
+        state.refs.insert("refs/heads/copy".into(), "c2".into());
+        state.refs.insert("refs/tags/v1".into(), "t1".into());
+        state
+    }
+
+    /// Linear history: c3 -> c2 -> c1, and t1 tags c1
+    fn reachable(tip: &str) -> Result<Vec<String>> {
+        let ids: &[&str] = match tip {
+            "c3" => &["c3", "c2", "c1"],
+            "c2" => &["c2", "c1"],
+            "c1" => &["c1"],
+            "t1" => &["t1", "c1"],
+            other => anyhow::bail!("unknown tip {}", other),
+        };
+        Ok(ids.iter().map(|id| id.to_string()).collect())
+    }
+
+    #[test]
+    fn test_blob_to_refs() {
+        let mut walked = Vec::new();
+        let wanted = BTreeSet::from(["0xold", "0xnew", "0xlegacy", "0xother"]);
+        let impact = RefImpact::build(&state(), &wanted, |tip| {
+            walked.push(tip.to_string());
+            reachable(tip)
+        })
+        .unwrap();
+
+        // main and copy share a tip, walked once
+        walked.sort();
+        assert_eq!(walked, ["c1", "c2", "t1"]);
+        assert_eq!(
+            impact.refs_in("0xold"),
+            [
+                "refs/heads/copy",
+                "refs/heads/main",
+                "refs/heads/old",
+                "refs/tags/v1"
+            ]
+        );
+        assert_eq!(
+            impact.refs_in("0xnew"),
+            ["refs/heads/copy", "refs/heads/main"]
+        );
+        assert_eq!(impact.refs_in("0xlegacy"), ["refs/tags/v1"]);
+        assert!(impact.refs_in("0xother").is_empty());
+
+        // Only the blobs asked about are mapped
+        let impact = RefImpact::build(&state(), &BTreeSet::from(["0xnew"]), reachable).unwrap();
+        assert!(impact.refs_in("0xold").is_empty());
+        assert_eq!(
+            impact.refs_in("0xnew"),
+            ["refs/heads/copy", "refs/heads/main"]
+        );
+    }
+
+    #[test]
+    fn test_cache_is_per_blob_and_epoch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ref_impact.yaml");
+        let mut state = state();
+        let old = BTreeSet::from(["0xold"]);
+
+        let mut cache = RefImpactCache::load(&path).unwrap();
+        let impact = RefImpact::build(&state, &old, reachable).unwrap();
+        cache.insert("0xremote", &impact, 7);
+        cache.save(&path).unwrap();
+
+        // Pushes moving refs within the epoch reuse it
+        state.refs.insert("refs/heads/old".into(), "c2".into());
+        let cache = RefImpactCache::load(&path).unwrap();
+        assert_eq!(cache.get("0xremote", &old, 7), Some(impact));
+        assert!(cache.get("0xother", &old, 7).is_none());
+        assert!(cache
+            .get("0xremote", &BTreeSet::from(["0xold", "0xnew"]), 7)
+            .is_none());
+        assert!(cache.get("0xremote", &old, 8).is_none());
+
+        // Maps of whole states from earlier versions are ignored
+        fs::write(
+            &path,
+            "remotes:\n  0xremote:\n    version: abc\n    blobs:\n      0xold: [refs/heads/main]\n",
+        )
+        .unwrap();
+        let cache = RefImpactCache::load(&path).unwrap();
+        assert!(cache.get("0xremote", &old, 7).is_none());
+    }
+}
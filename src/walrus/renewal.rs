@@ -0,0 +1,201 @@
+//! Extend referenced blobs before they expire
+
+use std::collections::BTreeSet;
+
+use super::{client::EpochInfo, BlobTracker, ExpiryReport, WalrusClient};
+
+/// One blob extended during a renewal pass
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Renewal {
+    pub object_id: String,
+    pub old_end_epoch: u64,
+    pub new_end_epoch: u64,
+}
+
+/// Outcome of one renewal pass
+#[derive(Debug, Default)]
+pub struct RenewalPass {
+    pub current_epoch: u64,
+    /// Blobs extended in this pass
+    pub renewed: Vec<Renewal>,
+    /// Blobs that could not be extended (object ID and error), to retry next pass
+    pub failed: Vec<(String, String)>,
+    /// Blobs that already expired and can no longer be extended
+    pub expired: Vec<String>,
+    /// Mostly-dead blobs whose live objects were copied to a new blob instead of renewing
+    pub repacked: Vec<Repacked>,
+}
+
+/// A sparse blob replaced by a blob of just its live objects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repacked {
+    pub old_object_id: String,
+    /// None when nothing in the old blob was live
+    pub new_object_id: Option<String>,
+    /// Objects now read from the new blob
+    pub moved: usize,
+    /// Unreachable objects dropped from the objects map
+    pub dropped: usize,
+    pub bytes_saved: u64,
+}
+
+/// Extend every `referenced` blob expiring within `threshold` epochs by `epochs` epochs
+///
+/// Extensions are capped at the network's `max_epochs_ahead`. Each successful extension is
+/// recorded in `tracker`; failures are collected rather than aborting the pass.
+pub fn renew_expiring(
+    client: &WalrusClient,
+    tracker: &mut BlobTracker,
+    referenced: &BTreeSet<String>,
+    epoch: &EpochInfo,
+    threshold: u64,
+    epochs: u32,
+) -> RenewalPass {
+    let report = ExpiryReport::build(tracker, referenced, epoch, chrono::Utc::now());
+    let max_end_epoch = epoch
+        .max_epochs_ahead
+        .map_or(u64::MAX, |ahead| epoch.current_epoch + ahead);
+
+    let mut pass = RenewalPass {
+        current_epoch: epoch.current_epoch,
+        ..Default::default()
+    };
+    for blob in report.expiring_within(threshold) {
+        if blob.end_epoch <= epoch.current_epoch {
+            tracing::warn!(
+                "Blob object {} expired at epoch {}; it can no longer be extended",
+                blob.object_id,
+                blob.end_epoch
+            );
+            pass.expired.push(blob.object_id.clone());
+            continue;
+        }
+
+        let new_end_epoch = (blob.end_epoch + u64::from(epochs)).min(max_end_epoch);
+        if new_end_epoch <= blob.end_epoch {
+            tracing::debug!(
+                "Blob object {} already ends at the furthest allowed epoch",
+                blob.object_id
+            );
+            continue;
+        }
+        let Some(mut info) = tracker.get_blob(&blob.object_id).cloned() else {
+            continue;
+        };
+
+        match client.extend(
+            &blob.object_id,
+            (new_end_epoch - blob.end_epoch) as u32,
+            info.persistence,
+        ) {
+            Ok(()) => {
+                tracing::info!(
+                    "Renewed blob object {}: end epoch {} -> {}",
+                    blob.object_id,
+                    blob.end_epoch,
+                    new_end_epoch
+                );
+                info.end_epoch = new_end_epoch;
+                tracker.insert(info);
+                pass.renewed.push(Renewal {
+                    object_id: blob.object_id.clone(),
+                    old_end_epoch: blob.end_epoch,
+                    new_end_epoch,
+                });
+            }
+            Err(e) => {
+                tracing::warn!("Failed to renew blob object {}: {:#}", blob.object_id, e);
+                pass.failed
+                    .push((blob.object_id.clone(), format!("{:#}", e)));
+            }
+        }
+    }
+
+    pass
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::walrus::{client::tests::mock_client, BlobPersistence};
+
+    #[test]
+    fn test_renews_only_blobs_within_threshold() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+
+        // Stored at epoch 1: ends at epoch 6 (default 5 epochs) and 41
+        let soon = client.store(b"expiring soon").unwrap();
+        let later = client.store_with_epochs(b"expiring later", 40).unwrap();
+        let mut tracker = BlobTracker::default();
+        tracker.track_blob(soon.shared_object_id.clone(), soon.blob_id, 6, None);
+        tracker.track_blob(later.shared_object_id.clone(), later.blob_id, 41, None);
+        tracker.track_blob("0xexpired".to_string(), "gone".to_string(), 3, None);
+        let referenced: BTreeSet<String> = [
+            soon.shared_object_id.clone(),
+            later.shared_object_id.clone(),
+            "0xexpired".to_string(),
+        ]
+        .into();
+
+        std::fs::write(dir.path().join("epoch"), "4").unwrap();
+        let epoch = client.current_epoch().unwrap();
+        let pass = renew_expiring(&client, &mut tracker, &referenced, &epoch, 5, 10);
+
+        assert_eq!(
+            pass.renewed,
+            vec![Renewal {
+                object_id: soon.shared_object_id.clone(),
+                old_end_epoch: 6,
+                new_end_epoch: 16,
+            }]
+        );
+        assert!(pass.failed.is_empty(), "{:?}", pass.failed);
+        assert_eq!(pass.expired, vec!["0xexpired".to_string()]);
+        assert_eq!(
+            tracker.get_blob(&soon.shared_object_id).unwrap().end_epoch,
+            16
+        );
+        assert_eq!(
+            tracker.get_blob(&later.shared_object_id).unwrap().end_epoch,
+            41
+        );
+
+        let calls = std::fs::read_to_string(dir.path().join("calls.log")).unwrap();
+        let extends: Vec<&str> = calls.lines().filter(|l| l.starts_with("extend")).collect();
+        assert_eq!(
+            extends,
+            vec![format!(
+                "extend --blob-obj-id {} --epochs-extended 10 --shared",
+                soon.shared_object_id
+            )]
+        );
+    }
+
+    #[test]
+    fn test_renewal_failures_are_collected_and_capped() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = mock_client(dir.path());
+
+        let stored = client.store(b"near the cap").unwrap();
+        let mut tracker = BlobTracker::default();
+        tracker.track_blob(stored.shared_object_id.clone(), stored.blob_id, 6, None);
+        // Unknown to Walrus, so extending it fails
+        let mut missing = tracker.get_blob(&stored.shared_object_id).unwrap().clone();
+        missing.object_id = "0xmissing".to_string();
+        missing.persistence = BlobPersistence::Deletable;
+        tracker.insert(missing);
+        let referenced: BTreeSet<String> =
+            [stored.shared_object_id.clone(), "0xmissing".to_string()].into();
+
+        // The mock allows 53 epochs ahead of epoch 1
+        let epoch = client.current_epoch().unwrap();
+        let pass = renew_expiring(&client, &mut tracker, &referenced, &epoch, 10, 100);
+
+        assert_eq!(pass.renewed.len(), 1);
+        assert_eq!(pass.renewed[0].new_end_epoch, 54);
+        assert_eq!(pass.failed.len(), 1);
+        assert_eq!(pass.failed[0].0, "0xmissing");
+        assert_eq!(tracker.get_blob("0xmissing").unwrap().end_epoch, 6);
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashSet},
     fs,
     path::{Path, PathBuf},
 };
@@ -7,11 +7,13 @@ use std::{
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
+use super::BlobPersistence;
+
 /// Information about a tracked blob
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct BlobInfo {
-    /// Sui object ID of the SharedBlob
+    /// Sui object ID of the SharedBlob (or owned Blob for deletable blobs)
     pub object_id: String,
     /// Walrus blob ID (for reading content)
     pub blob_id: String,
@@ -20,6 +22,15 @@ pub struct BlobInfo {
     /// Optional: size in bytes
     #[serde(skip_serializing_if = "Option::is_none")]
     pub size: Option<u64>,
+    /// Whether the blob is permanent or deletable
+    #[serde(default)]
+    pub persistence: BlobPersistence,
+    /// RemoteState object ID of the remote that uploaded this blob
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// Epochs the uploading push chose from `retention_rules` (or `default_epochs`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_epochs: Option<u32>,
 }
 
 /// Tracks blob expiration epochs
@@ -85,12 +96,19 @@ impl BlobTracker {
                 blob_id,
                 end_epoch,
                 size,
+                persistence: BlobPersistence::default(),
+                remote: None,
+                retention_epochs: None,
             },
         );
     }
 
+    /// Track a blob with full info (persistence and owning remote)
+    pub fn insert(&mut self, info: BlobInfo) {
+        self.blobs.insert(info.object_id.clone(), info);
+    }
+
     /// Get blob info by object_id
-    #[allow(dead_code)]
     pub fn get_blob(&self, object_id: &str) -> Option<&BlobInfo> {
         self.blobs.get(object_id)
     }
@@ -121,6 +139,16 @@ impl BlobTracker {
         self.blobs.values()
     }
 
+    /// Get deletable blobs uploaded by `remote` that are not in `referenced`
+    pub fn reclaimable(&self, remote: &str, referenced: &HashSet<String>) -> Vec<&BlobInfo> {
+        self.blobs
+            .values()
+            .filter(|info| info.persistence == BlobPersistence::Deletable)
+            .filter(|info| info.remote.as_deref() == Some(remote))
+            .filter(|info| !referenced.contains(&info.object_id))
+            .collect()
+    }
+
     /// Get count of tracked blobs
     pub fn count(&self) -> usize {
         self.blobs.len()
@@ -226,6 +254,44 @@ mod tests {
         assert_eq!(expiring.len(), 0);
     }
 
+    #[test]
+    fn test_reclaimable() {
+        let deletable = |object_id: &str, remote: &str| BlobInfo {
+            object_id: object_id.to_string(),
+            blob_id: format!("blob-{}", object_id),
+            end_epoch: 100,
+            size: None,
+            persistence: BlobPersistence::Deletable,
+            remote: Some(remote.to_string()),
+            retention_epochs: None,
+        };
+
+        let mut tracker = BlobTracker::new();
+        // Permanent blobs are never reclaimable
+        tracker.track_blob("0x1".to_string(), "blob1".to_string(), 100, None);
+        // Deletable and referenced by the current objects map
+        tracker.insert(deletable("0x2", "0xremote"));
+        // Deletable and unreferenced
+        tracker.insert(deletable("0x3", "0xremote"));
+        // Deletable and unreferenced, but uploaded by another remote
+        tracker.insert(deletable("0x4", "0xother"));
+
+        let referenced: HashSet<String> = ["0x1", "0x2"].iter().map(|s| s.to_string()).collect();
+        let reclaimable = tracker.reclaimable("0xremote", &referenced);
+        assert_eq!(reclaimable.len(), 1);
+        assert_eq!(reclaimable[0].object_id, "0x3");
+    }
+
+    #[test]
+    fn test_load_without_persistence_defaults_to_permanent() {
+        let yaml =
+            "blobs:\n  '0x1':\n    object_id: '0x1'\n    blob_id: blob1\n    end_epoch: 100\n";
+        let tracker: BlobTracker = serde_yaml::from_str(yaml).unwrap();
+        let blob = tracker.get_blob("0x1").unwrap();
+        assert_eq!(blob.persistence, BlobPersistence::Permanent);
+        assert_eq!(blob.remote, None);
+    }
+
     #[test]
     fn test_serialization() {
         let mut tracker = BlobTracker::new();
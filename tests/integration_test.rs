@@ -9,8 +9,12 @@ use std::{
     sync::Once,
 };
 
+use sui_types::base_types::ObjectID;
 use tempfile::TempDir;
 
+#[path = "mock/sui_rpc.rs"]
+mod sui_rpc;
+
 static INIT: Once = Once::new();
 
 /// Setup git-remote-walrus in PATH for tests
@@ -98,6 +102,59 @@ fn test_basic_push_clone() {
     assert_eq!(content2, "Second file");
 }
 
+#[test]
+fn test_explicit_fs_scheme_for_hex_looking_path() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "content").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "First"]);
+    let head = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    // git runs walrus+fs:: remotes through a git-remote-walrus+fs helper
+    let bin = temp.path().join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    let helper = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/release/git-remote-walrus");
+    std::os::unix::fs::symlink(helper, bin.join("git-remote-walrus+fs")).unwrap();
+    let path = format!(
+        "{}:{}",
+        bin.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    let run = |args: &[&str]| {
+        Command::new("git")
+            .current_dir(&test_repo)
+            .env("PATH", &path)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // A directory named like a Sui object ID, relative to the repository
+    let output = run(&["push", "walrus+fs::0xdeadbeef", "main"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(test_repo.join("0xdeadbeef").join("state.yaml").exists());
+
+    run(&["remote", "add", "storage", "walrus+fs::0xdeadbeef"]);
+    let output = run(&["ls-remote", "storage"]);
+    let listed = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        listed.contains(&format!("{}\trefs/heads/main", head)),
+        "{}",
+        listed
+    );
+}
+
 #[test]
 fn test_multiple_branches() {
     setup_git_remote();
@@ -143,6 +200,490 @@ fn test_multiple_branches() {
     assert_eq!(feature_sha, cloned_feature_sha);
 }
 
+#[test]
+fn test_atomic_push() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let cloned_repo = temp.path().join("cloned");
+    let storage_url = format!("walrus::{}", storage.display());
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("main.txt"), "main").unwrap();
+    git(&test_repo, &["add", "main.txt"]);
+    git(&test_repo, &["commit", "-m", "Main commit"]);
+    git(&test_repo, &["branch", "Feature"]);
+    git(&test_repo, &["push", &storage_url, "main", "Feature"]);
+    let main_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    git(
+        temp.path(),
+        &["clone", &storage_url, cloned_repo.to_str().unwrap()],
+    );
+    git(&cloned_repo, &["config", "user.name", "Test"]);
+    git(&cloned_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(cloned_repo.join("main.txt"), "updated").unwrap();
+    git(&cloned_repo, &["commit", "-am", "Update main"]);
+    git(&cloned_repo, &["branch", "feature"]);
+    let updated_sha = git(&cloned_repo, &["rev-parse", "HEAD"]);
+    let remote_main = |dir: &Path| git(dir, &["ls-remote", &storage_url, "refs/heads/main"]);
+
+    // `feature` differs only in case from the stored `Feature`, so the remote refuses it, and
+    // with --atomic main isn't updated either
+    let output = Command::new("git")
+        .current_dir(&cloned_repo)
+        .args(["push", "--atomic", &storage_url, "main", "feature"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("atomic push failed"), "{}", stderr);
+    assert!(remote_main(&cloned_repo).starts_with(&main_sha));
+
+    git(&cloned_repo, &["push", "--atomic", &storage_url, "main"]);
+    assert!(remote_main(&cloned_repo).starts_with(&updated_sha));
+}
+
+#[test]
+fn test_push_options_recorded() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let storage_url = format!("walrus::{}", storage.display());
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "v1").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "First"]);
+
+    let push = |options: &[&str]| {
+        let mut cmd = Command::new("git");
+        cmd.current_dir(&test_repo).arg("push");
+        for option in options {
+            cmd.arg(format!("--push-option={}", option));
+        }
+        cmd.args([&storage_url, "main"]).output().unwrap()
+    };
+    let describe = || {
+        let output = Command::new("git-remote-walrus")
+            .args(["describe", &storage_url])
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let output = push(&["ci.skip", "merge-request.create", "notify=team a"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let described = describe();
+    assert!(
+        described.contains("Last push options: ci.skip, merge-request.create, notify=team a"),
+        "{}",
+        described
+    );
+
+    // Over-long options are refused, and the push with them
+    std::fs::write(test_repo.join("file.txt"), "v2").unwrap();
+    git(&test_repo, &["commit", "-am", "Second"]);
+    let too_long = "x".repeat(2048);
+    let output = push(&[too_long.as_str()]);
+    assert!(!output.status.success());
+
+    // A push without options clears the last push's
+    let output = push(&[]);
+    assert!(output.status.success());
+    let described = describe();
+    assert!(!described.contains("Last push options"), "{}", described);
+
+    // but each push's options stay in the push history
+    std::fs::write(test_repo.join("file.txt"), "v3").unwrap();
+    git(&test_repo, &["commit", "-am", "Third"]);
+    let output = push(&["ci.deploy=staging"]);
+    assert!(output.status.success());
+    let described = describe();
+    assert!(
+        described.contains("[options: ci.skip, merge-request.create, notify=team a]"),
+        "{}",
+        described
+    );
+    assert!(
+        described.contains("[options: ci.deploy=staging]"),
+        "{}",
+        described
+    );
+}
+
+#[test]
+fn test_non_ascii_ref_names() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let cloned_repo = temp.path().join("cloned");
+    let storage_url = format!("walrus::{}", storage.display());
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "content").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "First"]);
+    git(&test_repo, &["branch", "café"]);
+    git(&test_repo, &["push", &storage_url, "main", "café"]);
+    let head = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    let listed = git(&test_repo, &["ls-remote", &storage_url]);
+    assert!(
+        listed.contains(&format!("{}\trefs/heads/café", head)),
+        "{}",
+        listed
+    );
+    git(
+        temp.path(),
+        &[
+            "clone",
+            "--branch",
+            "café",
+            &storage_url,
+            cloned_repo.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(
+        git(&cloned_repo, &["symbolic-ref", "HEAD"]),
+        "refs/heads/café"
+    );
+    assert_eq!(git(&cloned_repo, &["rev-parse", "HEAD"]), head);
+
+    // Git allows a Latin-1 name too, but the remote only stores UTF-8 names: the ref is
+    // refused by name rather than stored under a lossily converted one
+    let latin1 = OsStr::from_bytes(b"caf\xe9");
+    let status = Command::new("git")
+        .current_dir(&test_repo)
+        .arg("branch")
+        .arg(latin1)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = Command::new("git")
+        .current_dir(&test_repo)
+        .args(["push", &storage_url])
+        .arg(latin1)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("only UTF-8 ref names"), "{}", stderr);
+    assert_eq!(git(&test_repo, &["ls-remote", &storage_url]), listed);
+}
+
+#[test]
+fn test_namespaced_repos_share_one_remote() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let storage = temp.path().join("storage");
+    let base_url = format!("walrus::{}", storage.display());
+
+    // Two unrelated repositories pushed to the same storage under different namespaces
+    let mut heads = Vec::new();
+    for name in ["alpha", "beta"] {
+        let repo = temp.path().join(name);
+        std::fs::create_dir(&repo).unwrap();
+        git(&repo, &["init", "-b", "main"]);
+        git(&repo, &["config", "user.name", "Test"]);
+        git(&repo, &["config", "user.email", "test@test.com"]);
+        std::fs::write(repo.join("name.txt"), name).unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-m", name]);
+        git(&repo, &["branch", &format!("only-{}", name)]);
+
+        let url = format!("{}?namespace={}", base_url, name);
+        git(&repo, &["push", &url, "--all"]);
+        heads.push((name, url, git(&repo, &["rev-parse", "HEAD"])));
+    }
+
+    for (name, url, head) in &heads {
+        let cloned = temp.path().join(format!("{}-clone", name));
+        git(temp.path(), &["clone", url, cloned.to_str().unwrap()]);
+
+        assert_eq!(&git(&cloned, &["rev-parse", "HEAD"]), head);
+        assert_eq!(
+            std::fs::read_to_string(cloned.join("name.txt")).unwrap(),
+            *name
+        );
+
+        // Only this repo's branches are visible
+        let remote_refs = git(&cloned, &["ls-remote", url]);
+        assert!(remote_refs.contains(&format!("refs/heads/only-{}", name)));
+        assert!(!remote_refs.contains("namespaces"), "{}", remote_refs);
+        let other = if *name == "alpha" { "beta" } else { "alpha" };
+        assert!(!remote_refs.contains(other), "{}", remote_refs);
+    }
+
+    // A push without a namespace is refused rather than mixing with the namespaced repos
+    let alpha = temp.path().join("alpha");
+    let output = Command::new("git")
+        .current_dir(&alpha)
+        .args(["push", &base_url, "main"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(git(&alpha, &["ls-remote", &base_url]), "");
+}
+
+#[test]
+fn test_git_namespace_maps_refs() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let repo = temp.path().join("repo");
+    let storage = temp.path().join("storage");
+    let url = format!("walrus::{}", storage.display());
+
+    std::fs::create_dir(&repo).unwrap();
+    git(&repo, &["init", "-b", "main"]);
+    git(&repo, &["config", "user.name", "Test"]);
+    git(&repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(repo.join("file.txt"), "namespaced").unwrap();
+    git(&repo, &["add", "."]);
+    git(&repo, &["commit", "-m", "In a namespace"]);
+    git(&repo, &["branch", "dev"]);
+    let head = git(&repo, &["rev-parse", "HEAD"]);
+
+    let in_namespace = |dir: &Path, args: &[&str]| {
+        let output = Command::new("git")
+            .current_dir(dir)
+            .env("GIT_NAMESPACE", "team")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    };
+    in_namespace(&repo, &["push", &url, "main", "dev"]);
+    let output = Command::new("git-remote-walrus")
+        .env("GIT_NAMESPACE", "team")
+        .args(["symref", &url, "HEAD", "dev"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // Stored under the namespace
+    let state = std::fs::read_to_string(storage.join("state.yaml")).unwrap();
+    assert!(
+        state.contains("refs/namespaces/team/refs/heads/main"),
+        "{}",
+        state
+    );
+    assert!(state.contains("refs/namespaces/team/HEAD"), "{}", state);
+
+    // Served without the prefix inside the namespace, and not at all outside it
+    let listed = in_namespace(&repo, &["ls-remote", "--symref", &url]);
+    assert!(listed.contains("ref: refs/heads/dev\tHEAD"), "{}", listed);
+    assert!(
+        listed.contains(&format!("{}\trefs/heads/main", head)),
+        "{}",
+        listed
+    );
+    assert!(!listed.contains("namespaces"), "{}", listed);
+    assert_eq!(git(&repo, &["ls-remote", &url]), "");
+
+    let cloned = temp.path().join("cloned");
+    in_namespace(temp.path(), &["clone", &url, cloned.to_str().unwrap()]);
+    assert_eq!(git(&cloned, &["symbolic-ref", "--short", "HEAD"]), "dev");
+    assert_eq!(git(&cloned, &["rev-parse", "HEAD"]), head);
+
+    // Outside the namespace, pushes are refused rather than mixed in beside it
+    let output = Command::new("git")
+        .current_dir(&repo)
+        .args(["push", &url, "main"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("hosts namespaced repositories (team)")
+    );
+}
+
+#[test]
+fn test_cat_and_log_path_without_cloning() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let repo = temp.path().join("repo");
+    let storage = temp.path().join("storage");
+    let url = format!("walrus::{}", storage.display());
+
+    std::fs::create_dir(&repo).unwrap();
+    git(&repo, &["init", "-b", "main"]);
+    git(&repo, &["config", "user.name", "Test"]);
+    git(&repo, &["config", "user.email", "test@test.com"]);
+    std::fs::create_dir(repo.join("docs")).unwrap();
+
+    // Distinct commit times, so the newest-first order is well defined
+    let mut time = 1_700_000_000;
+    let mut commit = |files: &[(&str, &str)], message: &str| {
+        for (path, content) in files {
+            std::fs::write(repo.join(path), content).unwrap();
+        }
+        time += 60;
+        let date = format!("{} +0000", time);
+        let output = Command::new("git")
+            .current_dir(&repo)
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_DATE", &date)
+            .args(["commit", "-qam", message])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+    };
+    std::fs::write(repo.join("docs/guide.txt"), "v1\n").unwrap();
+    std::fs::write(repo.join("other.txt"), "a\n").unwrap();
+    git(&repo, &["add", "."]);
+    commit(&[], "Add files");
+    commit(&[("other.txt", "b\n")], "Unrelated change");
+    git(&repo, &["checkout", "-q", "-b", "side"]);
+    commit(&[("other.txt", "side\n")], "Side change");
+    git(&repo, &["checkout", "-q", "main"]);
+    commit(&[("docs/guide.txt", "v2\n")], "Edit guide");
+    let output = Command::new("git")
+        .current_dir(&repo)
+        .env("GIT_AUTHOR_DATE", "1700001000 +0000")
+        .env("GIT_COMMITTER_DATE", "1700001000 +0000")
+        .args(["merge", "-q", "--no-edit", "side"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    commit(&[("docs/guide.txt", "v3\n")], "Edit guide again");
+    git(&repo, &["push", &url, "main", "side"]);
+
+    let helper = |args: &[&str]| {
+        let output = Command::new("git-remote-walrus")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(helper(&["cat", &url, "main:docs/guide.txt"]), "v3\n");
+    assert_eq!(helper(&["cat", &url, ":other.txt"]), "side\n");
+    assert_eq!(helper(&["cat", &url, "side:other.txt"]), "side\n");
+    let output = Command::new("git-remote-walrus")
+        .args(["cat", &url, "main:docs"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    // Same commits as `git log -- <path>`, newest first
+    for (path, reference) in [
+        ("docs/guide.txt", "main"),
+        ("other.txt", "main"),
+        ("docs", "main"),
+        ("other.txt", "side"),
+    ] {
+        let listed = helper(&["log-path", &url, path, "--ref", reference]);
+        let shas: Vec<&str> = listed
+            .lines()
+            .map(|line| line.split(' ').next().unwrap())
+            .collect();
+        let expected = git(&repo, &["log", "--format=%H", reference, "--", path]);
+        assert_eq!(shas, expected.lines().collect::<Vec<_>>(), "{}", path);
+        assert!(listed
+            .lines()
+            .all(|line| line.contains("Test <test@test.com>")));
+    }
+}
+
+#[test]
+fn test_push_many_branches_unpacks_once() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+
+    for i in 0..5 {
+        std::fs::write(test_repo.join(format!("shared{}.txt", i)), i.to_string()).unwrap();
+        git(&test_repo, &["add", "."]);
+        git(&test_repo, &["commit", "-m", &format!("Shared {}", i)]);
+    }
+    let shared_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    // Half the branches share main's tip, the rest add one commit each on top of it
+    let mut expected = vec![("main".to_string(), shared_sha.clone())];
+    for i in 0..9 {
+        let branch = format!("branch{}", i);
+        git(&test_repo, &["checkout", "-q", "-b", &branch, "main"]);
+        if i % 2 == 1 {
+            std::fs::write(test_repo.join(format!("{}.txt", branch)), &branch).unwrap();
+            git(&test_repo, &["add", "."]);
+            git(&test_repo, &["commit", "-m", &branch]);
+        }
+        expected.push((branch, git(&test_repo, &["rev-parse", "HEAD"])));
+    }
+
+    let storage_url = format!("walrus::{}", storage.display());
+    let output = Command::new("git")
+        .current_dir(&test_repo)
+        .args(["push", &storage_url, "--all"])
+        .env("RUST_LOG", "git_remote_walrus=info")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "push failed: {}", stderr);
+
+    // All ten refs are stored from a single pack
+    assert_eq!(
+        stderr.matches("Unpacked ").count(),
+        1,
+        "expected one unpack: {}",
+        stderr
+    );
+
+    let remote_refs = git(&test_repo, &["ls-remote", &storage_url]);
+    for (branch, sha) in &expected {
+        assert!(
+            remote_refs.contains(&format!("{}\trefs/heads/{}", sha, branch)),
+            "{} missing from {}",
+            branch,
+            remote_refs
+        );
+    }
+}
+
 #[test]
 fn test_binary_files() {
     setup_git_remote();
@@ -177,6 +718,55 @@ fn test_binary_files() {
     assert_eq!(binary_data, cloned_data);
 }
 
+#[test]
+fn test_empty_files_and_trees() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let cloned_repo = temp.path().join("cloned");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+
+    // The first commit has the empty tree; the second only empty files
+    git(&test_repo, &["commit", "--allow-empty", "-m", "Empty"]);
+    std::fs::create_dir_all(test_repo.join("a/b")).unwrap();
+    for path in ["empty", "a/empty", "a/b/also-empty"] {
+        std::fs::write(test_repo.join(path), "").unwrap();
+    }
+    git(&test_repo, &["add", "."]);
+    git(&test_repo, &["commit", "-m", "Add empty files"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "main"]);
+    git(
+        temp.path(),
+        &["clone", &storage_url, cloned_repo.to_str().unwrap()],
+    );
+
+    for path in ["empty", "a/empty", "a/b/also-empty"] {
+        assert_eq!(std::fs::read(cloned_repo.join(path)).unwrap(), b"");
+    }
+    assert_eq!(
+        git(&cloned_repo, &["rev-parse", "HEAD~1^{tree}"]),
+        "4b825dc642cb6eb9a060e54bf8d69288fbee4904"
+    );
+    assert_eq!(
+        git(&cloned_repo, &["rev-parse", "HEAD:empty"]),
+        "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+    );
+    let fsck = Command::new("git")
+        .current_dir(&cloned_repo)
+        .args(["fsck", "--strict"])
+        .status()
+        .unwrap();
+    assert!(fsck.success());
+}
+
 #[test]
 fn test_lightweight_tags() {
     setup_git_remote();
@@ -219,65 +809,533 @@ fn test_lightweight_tags() {
 }
 
 #[test]
-fn test_incremental_push() {
+fn test_clone_of_tag_only_remote_checks_out_latest_tag() {
     setup_git_remote();
 
     let temp = TempDir::new().unwrap();
     let test_repo = temp.path().join("test-repo");
     let storage = temp.path().join("storage");
 
-    // Create test repository
     std::fs::create_dir(&test_repo).unwrap();
     git(&test_repo, &["init"]);
     git(&test_repo, &["config", "user.name", "Test"]);
     git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("VERSION"), "1\n").unwrap();
+    git(&test_repo, &["add", "VERSION"]);
+    git(&test_repo, &["commit", "-m", "Release 1"]);
+    git(&test_repo, &["tag", "-a", "-m", "Release 1", "v1"]);
+    // A later committer time, so v2 is newest regardless of how fast the test runs
+    std::fs::write(test_repo.join("VERSION"), "2\n").unwrap();
+    git(&test_repo, &["add", "VERSION"]);
+    let commit = Command::new("git")
+        .current_dir(&test_repo)
+        .args(["commit", "-m", "Release 2"])
+        .env("GIT_COMMITTER_DATE", "@4102444800 +0000")
+        .status()
+        .unwrap();
+    assert!(commit.success());
+    git(&test_repo, &["tag", "-a", "-m", "Release 2", "v2"]);
+    let v2_commit = git(&test_repo, &["rev-parse", "v2^{commit}"]);
 
-    // First push
-    std::fs::write(test_repo.join("file1.txt"), "First").unwrap();
-    git(&test_repo, &["add", "file1.txt"]);
-    git(&test_repo, &["commit", "-m", "First"]);
-
+    // Only tags on the remote
     let storage_url = format!("walrus::{}", storage.display());
-    git(&test_repo, &["push", &storage_url, "main"]);
-
-    // Second push
-    std::fs::write(test_repo.join("file2.txt"), "Second").unwrap();
-    git(&test_repo, &["add", "file2.txt"]);
-    git(&test_repo, &["commit", "-m", "Second"]);
-    let second_sha = git(&test_repo, &["rev-parse", "HEAD"]);
-
-    git(&test_repo, &["push", &storage_url, "main"]);
-
-    // Clone and verify second commit is present
-    let cloned_repo = temp.path().join("cloned");
     git(
-        temp.path(),
-        &["clone", &storage_url, cloned_repo.to_str().unwrap()],
+        &test_repo,
+        &["push", &storage_url, "refs/tags/v1", "refs/tags/v2"],
     );
 
-    let cloned_sha = git(&cloned_repo, &["rev-parse", "HEAD"]);
-    assert_eq!(second_sha, cloned_sha);
+    // Without tags, HEAD's objects are fetched by object ID alone
+    for (name, extra) in [("cloned", None), ("cloned-no-tags", Some("--no-tags"))] {
+        let cloned_repo = temp.path().join(name);
+        let mut args = vec!["clone"];
+        args.extend(extra);
+        args.extend([storage_url.as_str(), cloned_repo.to_str().unwrap()]);
+        git(temp.path(), &args);
 
-    // Verify both files exist
-    assert!(cloned_repo.join("file1.txt").exists());
-    assert!(cloned_repo.join("file2.txt").exists());
+        assert_eq!(
+            git(&cloned_repo, &["rev-parse", "HEAD"]),
+            v2_commit,
+            "{}",
+            name
+        );
+        assert_eq!(
+            std::fs::read_to_string(cloned_repo.join("VERSION")).unwrap(),
+            "2\n",
+            "{}",
+            name
+        );
+        assert_eq!(git(&cloned_repo, &["status", "--porcelain"]), "");
+    }
 }
 
 #[test]
-fn test_object_deduplication_across_pushes() {
+fn test_signed_tag_round_trip() {
     setup_git_remote();
 
     let temp = TempDir::new().unwrap();
+    let gnupg_home = temp.path().join("gnupg");
     let test_repo = temp.path().join("test-repo");
     let storage = temp.path().join("storage");
+    let cloned_repo = temp.path().join("cloned");
+
+    // A throwaway signing key, kept out of the user's keyring
+    std::fs::create_dir(&gnupg_home).unwrap();
+    let gpg = |dir: &Path, program: &str, args: &[&str]| {
+        Command::new(program)
+            .current_dir(dir)
+            .env("GNUPGHOME", &gnupg_home)
+            .args(args)
+            .output()
+    };
+    let keygen = [
+        "--batch",
+        "--passphrase",
+        "",
+        "--quick-gen-key",
+        "Test <test@test.com>",
+        "ed25519",
+        "sign",
+        "never",
+    ];
+    match gpg(temp.path(), "gpg", &keygen) {
+        Ok(output) if output.status.success() => {}
+        _ => {
+            eprintln!("gpg unavailable, skipping signed tag test");
+            return;
+        }
+    }
 
-    // Create test repository
     std::fs::create_dir(&test_repo).unwrap();
     git(&test_repo, &["init"]);
     git(&test_repo, &["config", "user.name", "Test"]);
     git(&test_repo, &["config", "user.email", "test@test.com"]);
-
-    // Step 1: Create first commit with single file
+    std::fs::write(test_repo.join("file.txt"), "content").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "Commit"]);
+    let tag = gpg(
+        &test_repo,
+        "git",
+        &[
+            "tag",
+            "-s",
+            "-u",
+            "test@test.com",
+            "-m",
+            "Signed release",
+            "v1",
+        ],
+    )
+    .unwrap();
+    assert!(
+        tag.status.success(),
+        "{}",
+        String::from_utf8_lossy(&tag.stderr)
+    );
+    let tag_sha = git(&test_repo, &["rev-parse", "v1"]);
+
+    // Push the branch first, so the tag's commit is already on the remote
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "main"]);
+    git(&test_repo, &["push", &storage_url, "v1:refs/tags/v1"]);
+
+    git(
+        temp.path(),
+        &["clone", &storage_url, cloned_repo.to_str().unwrap()],
+    );
+
+    // The tag object arrives byte-for-byte, signature included
+    assert_eq!(git(&cloned_repo, &["rev-parse", "v1"]), tag_sha);
+    let verify = gpg(&cloned_repo, "git", &["verify-tag", "v1"]).unwrap();
+    assert!(
+        verify.status.success(),
+        "verify-tag failed: {}",
+        String::from_utf8_lossy(&verify.stderr)
+    );
+
+    let _ = gpg(temp.path(), "gpgconf", &["--kill", "gpg-agent"]);
+}
+
+#[test]
+fn test_ssh_signed_commit_and_tag_round_trip() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let key = temp.path().join("signing_key");
+    let allowed_signers = temp.path().join("allowed_signers");
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let cloned_repo = temp.path().join("cloned");
+
+    // A throwaway ssh key; ssh signing needs no gpg binary or agent
+    match Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", "test", "-f"])
+        .arg(&key)
+        .output()
+    {
+        Ok(output) if output.status.success() => {}
+        _ => {
+            eprintln!("ssh-keygen unavailable, skipping ssh signing test");
+            return;
+        }
+    }
+    let public_key = std::fs::read_to_string(key.with_extension("pub")).unwrap();
+    std::fs::write(
+        &allowed_signers,
+        format!("test@test.com {}", public_key.trim()),
+    )
+    .unwrap();
+    let signing = |dir: &Path| {
+        for (name, value) in [
+            ("gpg.format", "ssh"),
+            ("user.signingkey", key.to_str().unwrap()),
+            (
+                "gpg.ssh.allowedSignersFile",
+                allowed_signers.to_str().unwrap(),
+            ),
+        ] {
+            git(dir, &["config", name, value]);
+        }
+    };
+    let succeeds = |dir: &Path, args: &[&str]| {
+        let output = Command::new("git")
+            .current_dir(dir)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    signing(&test_repo);
+    std::fs::write(test_repo.join("file.txt"), "v1").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    succeeds(&test_repo, &["commit", "-S", "-m", "Signed commit"]);
+    std::fs::write(test_repo.join("file.txt"), "v2").unwrap();
+    succeeds(&test_repo, &["commit", "-S", "-am", "Second signed commit"]);
+    succeeds(&test_repo, &["tag", "-s", "-m", "Signed release", "v1"]);
+    succeeds(&test_repo, &["verify-commit", "HEAD"]);
+    let commit_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+    let parent_sha = git(&test_repo, &["rev-parse", "HEAD~1"]);
+    let tag_sha = git(&test_repo, &["rev-parse", "v1"]);
+
+    // Branch and tag in one push, then a clone, through the export and fetch paths
+    let storage_url = format!("walrus::{}", storage.display());
+    succeeds(&test_repo, &["push", &storage_url, "main", "v1"]);
+    git(
+        temp.path(),
+        &["clone", &storage_url, cloned_repo.to_str().unwrap()],
+    );
+    signing(&cloned_repo);
+
+    // Signed objects arrive byte-for-byte, so their IDs and signatures are unchanged
+    assert_eq!(git(&cloned_repo, &["rev-parse", "HEAD"]), commit_sha);
+    assert_eq!(git(&cloned_repo, &["rev-parse", "HEAD~1"]), parent_sha);
+    assert_eq!(git(&cloned_repo, &["rev-parse", "v1"]), tag_sha);
+    succeeds(&cloned_repo, &["verify-commit", "HEAD"]);
+    succeeds(&cloned_repo, &["verify-commit", "HEAD~1"]);
+    succeeds(&cloned_repo, &["tag", "-v", "v1"]);
+}
+
+#[test]
+fn test_signed_push_certificates() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let gnupg_home = temp.path().join("gnupg");
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+
+    // A throwaway signing key, kept out of the user's keyring
+    std::fs::create_dir(&gnupg_home).unwrap();
+    let keygen = Command::new("gpg")
+        .env("GNUPGHOME", &gnupg_home)
+        .args([
+            "--batch",
+            "--passphrase",
+            "",
+            "--quick-gen-key",
+            "Test <test@test.com>",
+            "ed25519",
+            "sign",
+            "never",
+        ])
+        .output();
+    match keygen {
+        Ok(output) if output.status.success() => {}
+        _ => {
+            eprintln!("gpg unavailable, skipping signed push test");
+            return;
+        }
+    }
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    git(&test_repo, &["config", "user.signingkey", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "one").unwrap();
+    git(&test_repo, &["add", "."]);
+    git(&test_repo, &["commit", "-m", "One"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    let push = |args: &[&str], signers: &str| {
+        Command::new("git")
+            .current_dir(&test_repo)
+            .arg("push")
+            .args(args)
+            .args([&storage_url, "main"])
+            .env("GNUPGHOME", &gnupg_home)
+            .env("WALRUS_REMOTE_REQUIRE_SIGNED_PUSH", "1")
+            .env("WALRUS_REMOTE_PUSH_CERT_KEYRING", &gnupg_home)
+            .env("WALRUS_REMOTE_PUSH_CERT_SIGNERS", signers)
+            .output()
+            .unwrap()
+    };
+
+    // Unsigned pushes are refused when certificates are required
+    let output = push(&[], "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("only accepts signed pushes"), "{}", stderr);
+    assert!(!storage.join("state.yaml").exists());
+
+    // ...as are pushes signed by a key outside the allowed signers
+    let output = push(&["--signed"], "0123456789ABCDEF");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("may not push"), "{}", stderr);
+    assert!(!storage.join("state.yaml").exists());
+
+    // A signed push records who signed it
+    let output = push(&["--signed"], "");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let describe = Command::new("git-remote-walrus")
+        .args(["describe", &storage_url])
+        .output()
+        .unwrap();
+    let describe = String::from_utf8_lossy(&describe.stdout);
+    assert!(
+        describe.contains("Last push signed by: Test <test@test.com> ("),
+        "{}",
+        describe
+    );
+
+    // --signed=if-asked signs because the remote asks
+    std::fs::write(test_repo.join("file.txt"), "two").unwrap();
+    git(&test_repo, &["commit", "-am", "Two"]);
+    let output = push(&["--signed=if-asked"], "");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let _ = Command::new("gpgconf")
+        .env("GNUPGHOME", &gnupg_home)
+        .args(["--kill", "gpg-agent"])
+        .output();
+}
+
+#[test]
+fn test_incremental_push() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+
+    // Create test repository
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+
+    // First push
+    std::fs::write(test_repo.join("file1.txt"), "First").unwrap();
+    git(&test_repo, &["add", "file1.txt"]);
+    git(&test_repo, &["commit", "-m", "First"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "main"]);
+
+    // Second push
+    std::fs::write(test_repo.join("file2.txt"), "Second").unwrap();
+    git(&test_repo, &["add", "file2.txt"]);
+    git(&test_repo, &["commit", "-m", "Second"]);
+    let second_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    git(&test_repo, &["push", &storage_url, "main"]);
+
+    // Clone and verify second commit is present
+    let cloned_repo = temp.path().join("cloned");
+    git(
+        temp.path(),
+        &["clone", &storage_url, cloned_repo.to_str().unwrap()],
+    );
+
+    let cloned_sha = git(&cloned_repo, &["rev-parse", "HEAD"]);
+    assert_eq!(second_sha, cloned_sha);
+
+    // Verify both files exist
+    assert!(cloned_repo.join("file1.txt").exists());
+    assert!(cloned_repo.join("file2.txt").exists());
+}
+
+#[test]
+fn test_two_remotes_keep_their_own_marks_and_private_refs() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "history").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "History"]);
+
+    for name in ["first", "second"] {
+        let url = format!("walrus::{}", temp.path().join(name).display());
+        git(&test_repo, &["remote", "add", name, &url]);
+    }
+
+    // What git recorded for the first push must not hide history from the second remote
+    git(&test_repo, &["push", "first", "main"]);
+    std::fs::write(test_repo.join("file.txt"), "tip").unwrap();
+    git(&test_repo, &["commit", "-am", "Tip"]);
+    git(&test_repo, &["push", "first", "main"]);
+    git(&test_repo, &["push", "second", "main"]);
+    let tip = git(&test_repo, &["rev-parse", "main"]);
+
+    for name in ["first", "second"] {
+        assert!(test_repo
+            .join(".git/walrus")
+            .join(name)
+            .join("marks")
+            .exists());
+        assert_eq!(
+            git(
+                &test_repo,
+                &["rev-parse", &format!("refs/walrus/{}/heads/main", name)]
+            ),
+            tip
+        );
+
+        let clone = temp.path().join(format!("{}-clone", name));
+        let url = format!("walrus::{}", temp.path().join(name).display());
+        git(temp.path(), &["clone", &url, clone.to_str().unwrap()]);
+        assert_eq!(git(&clone, &["rev-parse", "HEAD"]), tip);
+        let fsck = Command::new("git")
+            .current_dir(&clone)
+            .args(["fsck", "--full"])
+            .output()
+            .unwrap();
+        assert!(
+            fsck.status.success(),
+            "{}: {}",
+            name,
+            String::from_utf8_lossy(&fsck.stderr)
+        );
+        assert_eq!(git(&clone, &["log", "--format=%s"]), "Tip\nHistory");
+    }
+    assert!(!test_repo.join(".git/walrus/marks").exists());
+    assert_eq!(git(&test_repo, &["for-each-ref", "refs/walrus/heads/"]), "");
+}
+
+#[test]
+fn test_push_summarizes_ref_changes() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let storage_url = format!("walrus::{}", storage.display());
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    let push_stderr = |args: &[&str]| {
+        let output = Command::new("git")
+            .current_dir(&test_repo)
+            .arg("push")
+            .args(args)
+            .args([storage_url.as_str(), "main"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stderr).to_string()
+    };
+    let commit = |name: &str, subject: &str| {
+        std::fs::write(test_repo.join(name), subject).unwrap();
+        git(&test_repo, &["add", name]);
+        git(&test_repo, &["commit", "-m", subject, "-m", "Body"]);
+        git(&test_repo, &["rev-parse", "HEAD"])
+    };
+
+    let first = commit("file1.txt", "First subject");
+    let stderr = push_stderr(&[]);
+    assert!(
+        stderr.contains(&format!(
+            " * [new] {}  refs/heads/main  First subject",
+            &first[..7]
+        )),
+        "{}",
+        stderr
+    );
+
+    let second = commit("file2.txt", "Second subject");
+    let stderr = push_stderr(&[]);
+    assert!(
+        stderr.contains(&format!(
+            "   {}..{}  refs/heads/main  Second subject",
+            &first[..7],
+            &second[..7]
+        )),
+        "{}",
+        stderr
+    );
+
+    // --quiet leaves the summary out, --verbose shows full SHA-1s
+    let third = commit("file3.txt", "Third subject");
+    let stderr = push_stderr(&["--quiet"]);
+    assert!(!stderr.contains("Remote refs after push"), "{}", stderr);
+    let stderr = push_stderr(&["--verbose"]);
+    assert!(
+        stderr.contains(&format!(" = [up to date] {}  refs/heads/main", third)),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_object_deduplication_across_pushes() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+
+    // Create test repository
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+
+    // Step 1: Create first commit with single file
     std::fs::write(test_repo.join("file1.txt"), "line 1\n").unwrap();
     git(&test_repo, &["add", "file1.txt"]);
     git(&test_repo, &["commit", "-m", "First commit"]);
@@ -578,3 +1636,1525 @@ fn test_clone_modify_push_cycle() {
         new_objects
     );
 }
+
+#[test]
+fn test_push_after_cache_loss() {
+    let remote = MockRemote::new();
+    let original = remote.repo("original", 10);
+    remote.git(&original, &["push", &remote.url, "main"]);
+
+    // A copy of the history that never talked to the remote, so it cannot tell git what the
+    // remote already has once main moves on
+    let copy = remote.temp.path().join("copy");
+    remote.git(
+        remote.temp.path(),
+        &["clone", original.to_str().unwrap(), copy.to_str().unwrap()],
+    );
+    remote.git(&copy, &["config", "user.name", "Test"]);
+    remote.git(&copy, &["config", "user.email", "test@test.com"]);
+    std::fs::write(original.join("file0.txt"), "content 0\nupstream\n").unwrap();
+    remote.git(&original, &["commit", "-am", "Upstream change"]);
+    remote.git(&original, &["push", &remote.url, "main"]);
+
+    std::fs::write(copy.join("file0.txt"), "content 0\nmodified\n").unwrap();
+    remote.git(&copy, &["commit", "-am", "Small change"]);
+
+    // Lose the cache (a new machine), then push the copy's whole history as a new branch
+    let cache_dir = remote.temp.path().join("cache");
+    assert!(cache_dir.exists());
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+    let (_, stderr) = remote.git_with_env(
+        &copy,
+        &["push", &remote.url, "main:feature"],
+        &[("RUST_LOG", "git_remote_walrus=info")],
+    );
+
+    let counts = |prefix: &str| -> usize {
+        stderr
+            .lines()
+            .filter_map(|line| line.split(prefix).nth(1))
+            .filter_map(|rest| rest.split(' ').next()?.parse::<usize>().ok())
+            .sum()
+    };
+    // History is found in the remote's objects map, not the lost cache
+    let skipped = counts("Skipping ");
+    assert!(
+        skipped >= 30,
+        "Push without a cache skipped {} stored objects, expected the 30 of history: {}",
+        skipped,
+        stderr
+    );
+    let uploaded = counts("Uploading ");
+    assert!(
+        uploaded <= 4,
+        "Push without a cache uploaded {} objects, expected ~3: {}",
+        uploaded,
+        stderr
+    );
+
+    let head = remote.git(&copy, &["rev-parse", "HEAD"]).0;
+    let cloned = remote.temp.path().join("cloned");
+    remote.git(
+        remote.temp.path(),
+        &[
+            "clone",
+            "-b",
+            "feature",
+            &remote.url,
+            cloned.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(remote.git(&cloned, &["rev-parse", "HEAD"]).0, head);
+    remote.git(&cloned, &["fsck", "--full"]);
+}
+
+#[test]
+fn test_named_and_url_only_remotes() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let fetch_repo = temp.path().join("fetch-repo");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+
+    std::fs::write(test_repo.join("file.txt"), "named remote").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "Commit"]);
+    let orig_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    // Named remote: git passes the remote name and the URL
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["remote", "add", "storage", &storage_url]);
+    git(&test_repo, &["push", "storage", "main"]);
+
+    // URL-only remote: git passes the URL in both positions
+    std::fs::create_dir(&fetch_repo).unwrap();
+    git(&fetch_repo, &["init"]);
+    git(&fetch_repo, &["fetch", &storage_url, "main"]);
+
+    let fetched_sha = git(&fetch_repo, &["rev-parse", "FETCH_HEAD"]);
+    assert_eq!(orig_sha, fetched_sha);
+}
+
+#[test]
+fn test_metadata_updated_on_push() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+
+    std::fs::write(test_repo.join("file.txt"), "v1").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "First"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "main"]);
+
+    let describe = || {
+        let output = Command::new("git-remote-walrus")
+            .args(["describe", &storage_url])
+            .output()
+            .expect("failed to run git-remote-walrus describe");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let first = describe();
+    assert!(
+        first.contains("Default branch: refs/heads/main"),
+        "{}",
+        first
+    );
+    assert!(first.contains("Description: (not set)"), "{}", first);
+    assert!(!first.contains("Last push at: (not set)"), "{}", first);
+
+    let status = Command::new("git-remote-walrus")
+        .args(["set-description", &storage_url, "Integration test repo"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // Another push keeps the description and refreshes the push time
+    std::fs::write(test_repo.join("file.txt"), "v2").unwrap();
+    git(&test_repo, &["commit", "-am", "Second"]);
+    git(&test_repo, &["push", &storage_url, "main"]);
+
+    let second = describe();
+    assert!(
+        second.contains("Description: Integration test repo"),
+        "{}",
+        second
+    );
+    let created = |out: &str| {
+        out.lines()
+            .find(|l| l.contains("Created at:"))
+            .map(str::to_string)
+    };
+    assert_eq!(created(&first), created(&second));
+}
+
+#[test]
+fn test_serve_over_git_daemon_protocol() {
+    use std::io::BufRead;
+
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let mirror = temp.path().join("mirrors").join("remote.git");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "v1").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "First"]);
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "main"]);
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut serve = Command::new("git-remote-walrus")
+        .args(["serve", &storage_url, "--interval", "200ms"])
+        .arg(format!("--listen=127.0.0.1:{}", port))
+        .arg("--mirror")
+        .arg(&mirror)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .expect("failed to run git-remote-walrus serve");
+    let mut banner = String::new();
+    std::io::BufReader::new(serve.stdout.take().unwrap())
+        .read_line(&mut banner)
+        .unwrap();
+    let url = format!("git://127.0.0.1:{}/remote.git", port);
+    assert!(banner.contains(&url), "{}", banner);
+
+    let cloned = temp.path().join("cloned");
+    let output = Command::new("git")
+        .args(["clone", &url])
+        .arg(&cloned)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(cloned.join("file.txt")).unwrap(),
+        "v1"
+    );
+
+    // The mirror follows new pushes
+    std::fs::write(test_repo.join("file.txt"), "v2").unwrap();
+    git(&test_repo, &["commit", "-am", "Second"]);
+    git(&test_repo, &["push", &storage_url, "main"]);
+    let pushed = git(&test_repo, &["rev-parse", "HEAD"]);
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(20);
+    loop {
+        let served = git(&cloned, &["ls-remote", &url, "refs/heads/main"]);
+        if served.starts_with(&pushed) {
+            break;
+        }
+        assert!(
+            std::time::Instant::now() < deadline,
+            "mirror never caught up: {}",
+            served
+        );
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+
+    // Read-only: git daemon doesn't accept pushes
+    std::fs::write(cloned.join("file.txt"), "v3").unwrap();
+    git(
+        &cloned,
+        &[
+            "-c",
+            "user.name=T",
+            "-c",
+            "user.email=t@t",
+            "commit",
+            "-am",
+            "Third",
+        ],
+    );
+    let output = Command::new("git")
+        .current_dir(&cloned)
+        .args(["push", "origin", "HEAD:main"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    // Ctrl-C stops the daemon along with serve
+    let status = Command::new("kill")
+        .args(["-INT", &serve.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(serve.wait().unwrap().success());
+    assert!(Command::new("git")
+        .args(["ls-remote", &url])
+        .output()
+        .map(|output| !output.status.success())
+        .unwrap());
+}
+
+#[test]
+fn test_fetch_tags_brings_unreachable_tag() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let fetch_repo = temp.path().join("fetch-repo");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+
+    std::fs::write(test_repo.join("file.txt"), "main").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "Main commit"]);
+
+    // Tag on a commit that no pushed branch contains
+    git(&test_repo, &["checkout", "-b", "side"]);
+    std::fs::write(test_repo.join("side.txt"), "side").unwrap();
+    git(&test_repo, &["add", "side.txt"]);
+    git(&test_repo, &["commit", "-m", "Side commit"]);
+    git(&test_repo, &["tag", "v-side"]);
+    let side_sha = git(&test_repo, &["rev-parse", "side"]);
+    git(&test_repo, &["checkout", "main"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "main"]);
+    git(&test_repo, &["push", &storage_url, "refs/tags/v-side"]);
+
+    std::fs::create_dir(&fetch_repo).unwrap();
+    git(&fetch_repo, &["init"]);
+    git(&fetch_repo, &["remote", "add", "origin", &storage_url]);
+
+    // Fetching the branch alone doesn't bring the side commit
+    git(&fetch_repo, &["fetch", "origin", "main"]);
+    assert_eq!(git(&fetch_repo, &["cat-file", "-t", &side_sha]), "");
+
+    // --tags brings the tag and everything it reaches
+    git(&fetch_repo, &["fetch", "--tags", "origin"]);
+    assert_eq!(
+        git(&fetch_repo, &["rev-parse", "refs/tags/v-side"]),
+        side_sha
+    );
+    assert_eq!(git(&fetch_repo, &["cat-file", "-t", &side_sha]), "commit");
+    assert_eq!(git(&fetch_repo, &["show", "v-side:side.txt"]), "side");
+}
+
+#[test]
+fn test_single_branch_clone_of_tag_reads_only_its_objects() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let cloned_repo = temp.path().join("cloned");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "release").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "Release"]);
+    git(&test_repo, &["tag", "-a", "-m", "Release 1.2.3", "v1.2.3"]);
+    let release_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    // Another branch, and a tag on it, carry a large binary
+    git(&test_repo, &["checkout", "-b", "assets"]);
+    let binary: Vec<u8> = (0..2_000_000u64).map(|i| (i * 7919 % 251) as u8).collect();
+    std::fs::write(test_repo.join("big.bin"), &binary).unwrap();
+    git(&test_repo, &["add", "big.bin"]);
+    git(&test_repo, &["commit", "-m", "Add assets"]);
+    git(&test_repo, &["tag", "assets-1"]);
+    let binary_sha = git(&test_repo, &["rev-parse", "HEAD:big.bin"]);
+    git(&test_repo, &["checkout", "main"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "--all"]);
+    git(&test_repo, &["push", &storage_url, "--tags"]);
+
+    // Take the binary's stored object away: any read of it fails the clone
+    let state = std::fs::read_to_string(storage.join("state.yaml")).unwrap();
+    let state: serde_yaml::Value = serde_yaml::from_str(&state).unwrap();
+    let content_id = state["objects"][binary_sha.as_str()].as_str().unwrap();
+    std::fs::remove_file(storage.join("objects").join(content_id)).unwrap();
+
+    let output = Command::new("git")
+        .current_dir(temp.path())
+        .args([
+            "clone",
+            "--single-branch",
+            "--branch",
+            "v1.2.3",
+            &storage_url,
+        ])
+        .arg(&cloned_repo)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(git(&cloned_repo, &["rev-parse", "HEAD"]), release_sha);
+    assert_eq!(
+        std::fs::read_to_string(cloned_repo.join("file.txt")).unwrap(),
+        "release"
+    );
+    assert_eq!(git(&cloned_repo, &["cat-file", "-t", &binary_sha]), "");
+    assert_eq!(git(&cloned_repo, &["tag", "--list"]), "v1.2.3");
+}
+
+/// Run a scripted helper session in `dir` and return the helper's stdout
+fn helper_session(dir: &Path, url: &str, git_dir: Option<&Path>, input: &[u8]) -> Vec<u8> {
+    let mut cmd = Command::new("git-remote-walrus");
+    cmd.current_dir(dir)
+        .args(["origin", url])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    if let Some(git_dir) = git_dir {
+        cmd.env("GIT_DIR", git_dir);
+    }
+    run_helper(cmd, input)
+}
+
+/// Feed `input` to a prepared helper command and return its stdout
+fn run_helper(mut cmd: Command, input: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut child = cmd.spawn().expect("failed to spawn helper");
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "helper failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+#[test]
+fn test_scripted_push_and_fetch_sessions() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let fetch_repo = temp.path().join("fetch-repo");
+    let url = format!("walrus::{}", temp.path().join("storage").display());
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "scripted").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "Scripted commit"]);
+    let sha = git(&test_repo, &["rev-parse", "main"]);
+
+    // Push: the export command followed by git's fast-export stream
+    let stream = Command::new("git")
+        .current_dir(&test_repo)
+        .args(["fast-export", "--use-done-feature", "refs/heads/main"])
+        .output()
+        .unwrap()
+        .stdout;
+    let mut input = b"capabilities\n\nexport\n".to_vec();
+    input.extend_from_slice(&stream);
+    input.extend_from_slice(b"\n");
+    assert_eq!(
+        String::from_utf8(helper_session(&test_repo, &url, None, &input)).unwrap(),
+        "fetch\nexport\nsigned-tags\noption\nrefspec refs/heads/*:refs/walrus/origin/heads/*\n\
+         refspec refs/tags/*:refs/walrus/origin/tags/*\n\nok refs/heads/main\n\n"
+    );
+
+    // Fetch: list the refs, then fetch main into a fresh repository
+    std::fs::create_dir(&fetch_repo).unwrap();
+    git(&fetch_repo, &["init"]);
+    let git_dir = fetch_repo.join(".git");
+    let input = format!("list\n\nfetch {} refs/heads/main\n\n", sha);
+    assert_eq!(
+        String::from_utf8(helper_session(
+            &fetch_repo,
+            &url,
+            Some(&git_dir),
+            input.as_bytes()
+        ))
+        .unwrap(),
+        format!("{} refs/heads/main\n@refs/heads/main HEAD\n\n\n", sha)
+    );
+    assert_eq!(git(&fetch_repo, &["cat-file", "-t", &sha]), "commit");
+}
+
+#[test]
+fn test_read_only_remote_rejects_push() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let cloned_repo = temp.path().join("cloned");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "one").unwrap();
+    git(&test_repo, &["add", "."]);
+    git(&test_repo, &["commit", "-m", "One"]);
+    let pushed_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "main"]);
+
+    std::fs::write(test_repo.join("file.txt"), "two").unwrap();
+    git(&test_repo, &["commit", "-am", "Two"]);
+    let state_before = std::fs::read(storage.join("state.yaml")).unwrap();
+
+    let output = Command::new("git")
+        .current_dir(&test_repo)
+        .args(["push", &storage_url, "main"])
+        .env("WALRUS_REMOTE_READ_ONLY", "1")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("this remote is configured read-only"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        std::fs::read(storage.join("state.yaml")).unwrap(),
+        state_before
+    );
+
+    // Clone and fetch still work
+    let output = Command::new("git")
+        .current_dir(temp.path())
+        .args(["clone", &storage_url, cloned_repo.to_str().unwrap()])
+        .env("WALRUS_REMOTE_READ_ONLY", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(git(&cloned_repo, &["rev-parse", "HEAD"]), pushed_sha);
+}
+
+#[test]
+fn test_pre_push_and_post_fetch_hooks() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let cloned_repo = temp.path().join("cloned");
+    let pushed = temp.path().join("pushed.txt");
+    let fetched = temp.path().join("fetched.txt");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "one").unwrap();
+    git(&test_repo, &["add", "."]);
+    git(&test_repo, &["commit", "-m", "One"]);
+    let first_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    let push = |hook: &str, envs: &[(&str, &str)]| {
+        Command::new("git")
+            .current_dir(&test_repo)
+            .args(["push", &storage_url, "main"])
+            .env("WALRUS_REMOTE_PRE_PUSH_HOOK", hook)
+            .envs(envs.iter().copied())
+            .output()
+            .unwrap()
+    };
+
+    // A rejecting hook stops the push before anything is stored
+    let reject = "echo 'pushes to main need review' >&2; exit 1";
+    let output = push(reject, &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("pre-push hook"), "{}", stderr);
+    assert!(stderr.contains("pushes to main need review"), "{}", stderr);
+    assert!(!storage.join("state.yaml").exists());
+
+    // An accepting hook sees git's pre-push input
+    let accept = format!("cat > {}", pushed.display());
+    assert!(push(&accept, &[]).status.success());
+    assert_eq!(
+        std::fs::read_to_string(&pushed).unwrap(),
+        format!(
+            "refs/heads/main {} refs/heads/main {}\n",
+            first_sha,
+            "0".repeat(40)
+        )
+    );
+
+    std::fs::write(test_repo.join("file.txt"), "two").unwrap();
+    git(&test_repo, &["commit", "-am", "Two"]);
+    let second_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+    assert!(push(&accept, &[]).status.success());
+    assert_eq!(
+        std::fs::read_to_string(&pushed).unwrap(),
+        format!(
+            "refs/heads/main {} refs/heads/main {}\n",
+            second_sha, first_sha
+        )
+    );
+
+    // Hooks can be switched off in an emergency
+    std::fs::write(test_repo.join("file.txt"), "three").unwrap();
+    git(&test_repo, &["commit", "-am", "Three"]);
+    let third_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+    assert!(push(reject, &[("WALRUS_REMOTE_NO_HOOKS", "1")])
+        .status
+        .success());
+
+    // The post-fetch hook gets the fetched refs
+    let output = Command::new("git")
+        .current_dir(temp.path())
+        .args(["clone", &storage_url, cloned_repo.to_str().unwrap()])
+        .env(
+            "WALRUS_REMOTE_POST_FETCH_HOOK",
+            format!("cat > {}", fetched.display()),
+        )
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(git(&cloned_repo, &["rev-parse", "HEAD"]), third_sha);
+    assert_eq!(
+        std::fs::read_to_string(&fetched).unwrap(),
+        format!("{} refs/heads/main\n", third_sha)
+    );
+
+    // ...and a failing one fails the fetch, leaving the ref where it was
+    std::fs::write(test_repo.join("file.txt"), "four").unwrap();
+    git(&test_repo, &["commit", "-am", "Four"]);
+    git(&test_repo, &["push", &storage_url, "main"]);
+    let output = Command::new("git")
+        .current_dir(&cloned_repo)
+        .args(["fetch", "origin"])
+        .env(
+            "WALRUS_REMOTE_POST_FETCH_HOOK",
+            "echo 'untrusted' >&2; exit 1",
+        )
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("post-fetch hook"), "{}", stderr);
+    assert!(stderr.contains("untrusted"), "{}", stderr);
+    assert_eq!(git(&cloned_repo, &["rev-parse", "origin/main"]), third_sha);
+}
+
+#[test]
+fn test_shallow_clone_deepen_and_push() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let shallow_repo = temp.path().join("shallow-repo");
+    let storage_url = format!("walrus::{}", temp.path().join("storage").display());
+    let run = |dir: &Path, args: &[&str]| {
+        let output = Command::new("git")
+            .current_dir(dir)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    };
+
+    std::fs::create_dir(&test_repo).unwrap();
+    run(&test_repo, &["init", "-b", "main"]);
+    run(&test_repo, &["config", "user.name", "Test"]);
+    run(&test_repo, &["config", "user.email", "test@test.com"]);
+    for n in 1..=3 {
+        std::fs::write(test_repo.join("file.txt"), format!("v{}", n)).unwrap();
+        run(&test_repo, &["add", "file.txt"]);
+        run(&test_repo, &["commit", "-m", &format!("Commit {}", n)]);
+    }
+    let commits: Vec<String> = run(&test_repo, &["rev-list", "--reverse", "main"])
+        .lines()
+        .map(String::from)
+        .collect();
+    run(&test_repo, &["push", &storage_url, "main"]);
+
+    // A --depth 1 clone has the tip alone, and git accepts the repository as shallow
+    run(
+        temp.path(),
+        &[
+            "clone",
+            "--depth",
+            "1",
+            &storage_url,
+            shallow_repo.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(
+        run(&shallow_repo, &["rev-parse", "--is-shallow-repository"]),
+        "true"
+    );
+    assert_eq!(run(&shallow_repo, &["log", "--format=%H"]), commits[2]);
+    run(&shallow_repo, &["fsck", "--strict"]);
+    assert_eq!(
+        std::fs::read_to_string(shallow_repo.join(".git/shallow")).unwrap(),
+        format!("{}\n", commits[2])
+    );
+
+    // --deepen 1 fetches one more generation, below the current boundary
+    run(&shallow_repo, &["fetch", "--deepen", "1", "origin"]);
+    assert_eq!(
+        run(&shallow_repo, &["log", "--format=%H"]),
+        format!("{}\n{}", commits[2], commits[1])
+    );
+    run(&shallow_repo, &["fsck", "--strict"]);
+
+    // A push from the shallow clone sends only the new commit, on top of what the remote has
+    run(&shallow_repo, &["config", "user.name", "Test"]);
+    run(&shallow_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(shallow_repo.join("file.txt"), "v4").unwrap();
+    run(&shallow_repo, &["commit", "-am", "Commit 4"]);
+    let pushed = run(&shallow_repo, &["rev-parse", "HEAD"]);
+    run(&shallow_repo, &["push", "origin", "main"]);
+    run(&test_repo, &["pull", "--ff-only", &storage_url, "main"]);
+    assert_eq!(run(&test_repo, &["rev-parse", "HEAD"]), pushed);
+    run(&test_repo, &["fsck", "--strict"]);
+
+    // The same history can't go to a remote that lacks what the clone is cut off from
+    let other_url = format!("walrus::{}", temp.path().join("other").display());
+    let output = Command::new("git")
+        .current_dir(&shallow_repo)
+        .args(["push", &other_url, "main"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("shallow update not allowed"), "{}", stderr);
+
+    // --unshallow fetches the rest, and the clone is whole again
+    run(&shallow_repo, &["fetch", "--unshallow", "origin"]);
+    assert_eq!(
+        run(&shallow_repo, &["rev-parse", "--is-shallow-repository"]),
+        "false"
+    );
+    assert_eq!(run(&shallow_repo, &["rev-list", "--count", "main"]), "4");
+    run(&shallow_repo, &["fsck", "--strict"]);
+}
+
+#[test]
+fn test_hidden_refs_need_all_refs() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let fetch_repo = temp.path().join("fetch-repo");
+    let storage_url = format!("walrus::{}", temp.path().join("storage").display());
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "main").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "Main commit"]);
+    git(&test_repo, &["tag", "v1"]);
+    git(&test_repo, &["checkout", "-b", "archive"]);
+    std::fs::write(test_repo.join("file.txt"), "old").unwrap();
+    git(&test_repo, &["commit", "-am", "Old commit"]);
+    git(&test_repo, &["tag", "old"]);
+    let old_sha = git(&test_repo, &["rev-parse", "old"]);
+    git(&test_repo, &["push", &storage_url, "main", "v1", "old"]);
+
+    let patterns = "refs/heads/*,refs/tags/v*";
+    let ls_remote = |url: &str| {
+        let output = Command::new("git")
+            .current_dir(&test_repo)
+            .args(["ls-remote", url])
+            .env("WALRUS_REMOTE_ADVERTISE_REF_PATTERNS", patterns)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        (
+            String::from_utf8_lossy(&output.stdout).to_string(),
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        )
+    };
+
+    let (listed, stderr) = ls_remote(&storage_url);
+    assert!(listed.contains("refs/heads/main"), "{}", listed);
+    assert!(listed.contains("refs/tags/v1"), "{}", listed);
+    assert!(!listed.contains("refs/tags/old"), "{}", listed);
+    assert!(stderr.contains("1 ref(s)"), "{}", stderr);
+
+    // all_refs=true advertises everything
+    let (listed, _) = ls_remote(&format!("{}?all_refs=true", storage_url));
+    assert!(listed.contains("refs/tags/old"), "{}", listed);
+
+    // git fetches only advertised refs, so naming the hidden tag is not enough
+    std::fs::create_dir(&fetch_repo).unwrap();
+    git(&fetch_repo, &["init"]);
+    let fetch = |url: &str| {
+        Command::new("git")
+            .current_dir(&fetch_repo)
+            .args(["fetch", url, "refs/tags/old:refs/tags/old"])
+            .env("WALRUS_REMOTE_ADVERTISE_REF_PATTERNS", patterns)
+            .output()
+            .unwrap()
+    };
+    let output = fetch(&storage_url);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("couldn't find remote ref"), "{}", stderr);
+
+    // With all_refs=true it is advertised, and fetched
+    let output = fetch(&format!("{}?all_refs=true", storage_url));
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(git(&fetch_repo, &["rev-parse", "refs/tags/old"]), old_sha);
+    assert_eq!(
+        git(&fetch_repo, &["show", &format!("{}:file.txt", old_sha)]),
+        "old"
+    );
+}
+
+/// Every path below `dir` with its kind, executable bit and content (or symlink target)
+fn snapshot_dir(dir: &Path) -> std::collections::BTreeMap<String, String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut entries = std::collections::BTreeMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.strip_prefix(dir).unwrap().display().to_string();
+            let meta = std::fs::symlink_metadata(&path).unwrap();
+            let description = if meta.file_type().is_symlink() {
+                format!("link -> {}", std::fs::read_link(&path).unwrap().display())
+            } else if meta.is_dir() {
+                pending.push(path);
+                "dir".to_string()
+            } else {
+                let executable = meta.permissions().mode() & 0o111 != 0;
+                format!(
+                    "file exec={} {}",
+                    executable,
+                    String::from_utf8_lossy(&std::fs::read(&path).unwrap())
+                )
+            };
+            entries.insert(name, description);
+        }
+    }
+    entries
+}
+
+#[test]
+fn test_publish_site_matches_git_archive() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::create_dir_all(test_repo.join("docs/img")).unwrap();
+    std::fs::write(test_repo.join("index.html"), "<h1>Home</h1>\n").unwrap();
+    std::fs::write(test_repo.join("docs/guide.md"), "# Guide\n").unwrap();
+    std::fs::write(test_repo.join("docs/img/logo.svg"), "<svg/>\n").unwrap();
+    std::fs::write(test_repo.join("build.sh"), "#!/bin/sh\necho hi\n").unwrap();
+    std::os::unix::fs::symlink("docs/guide.md", test_repo.join("README.md")).unwrap();
+    git(&test_repo, &["add", "."]);
+    git(&test_repo, &["update-index", "--chmod=+x", "build.sh"]);
+    git(&test_repo, &["commit", "-m", "Site"]);
+    git(&test_repo, &["tag", "v1"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "main"]);
+    git(&test_repo, &["push", &storage_url, "v1:refs/tags/v1"]);
+
+    // Reference output from git archive for the same commit
+    let expected = temp.path().join("expected");
+    std::fs::create_dir(&expected).unwrap();
+    let archive = Command::new("git")
+        .current_dir(&test_repo)
+        .args(["archive", "--format=tar", "main"])
+        .output()
+        .unwrap();
+    assert!(archive.status.success());
+    let mut tar = Command::new("tar")
+        .current_dir(&expected)
+        .args(["-xf", "-"])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(&mut tar.stdin.take().unwrap(), &archive.stdout).unwrap();
+    assert!(tar.wait().unwrap().success());
+
+    for ref_name in ["refs/heads/main", "v1"] {
+        let site = temp
+            .path()
+            .join(format!("site-{}", ref_name.replace('/', "-")));
+        let output = Command::new("git-remote-walrus")
+            .args(["publish-site", &storage_url, "--ref", ref_name, "--out"])
+            .arg(&site)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(snapshot_dir(&site), snapshot_dir(&expected), "{}", ref_name);
+    }
+
+    // --index keeps the tree's own index.html, and a site is never written over
+    let site = temp.path().join("site-index");
+    let publish = || {
+        Command::new("git-remote-walrus")
+            .args(["publish-site", &storage_url, "--index", "--out"])
+            .arg(&site)
+            .output()
+            .unwrap()
+    };
+    assert!(publish().status.success());
+    assert_eq!(
+        std::fs::read_to_string(site.join("index.html")).unwrap(),
+        "<h1>Home</h1>\n"
+    );
+    let output = publish();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not empty"));
+}
+
+#[test]
+fn test_archive_matches_git_archive() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    let long_dir = format!("{0}/{0}", "d".repeat(60));
+    std::fs::create_dir_all(test_repo.join("a/b")).unwrap();
+    std::fs::create_dir_all(test_repo.join(&long_dir)).unwrap();
+    std::fs::write(test_repo.join("a.txt"), "sorts after a/\n").unwrap();
+    std::fs::write(test_repo.join("a/b/c.txt"), "nested\n").unwrap();
+    std::fs::write(test_repo.join("empty"), "").unwrap();
+    std::fs::write(test_repo.join("run.sh"), "#!/bin/sh\necho hi\n").unwrap();
+    std::fs::write(test_repo.join(long_dir.clone() + "/file.txt"), "deep\n").unwrap();
+    std::fs::write(test_repo.join("n".repeat(120)), "long name\n").unwrap();
+    std::fs::write(test_repo.join("big.txt"), "compressible line\n".repeat(500)).unwrap();
+    std::os::unix::fs::symlink("a/b/c.txt", test_repo.join("link")).unwrap();
+    std::os::unix::fs::symlink("t".repeat(110), test_repo.join("long-link")).unwrap();
+    git(&test_repo, &["add", "."]);
+    git(&test_repo, &["update-index", "--chmod=+x", "run.sh"]);
+    git(&test_repo, &["commit", "-m", "Project"]);
+    git(&test_repo, &["tag", "v1.2"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "main"]);
+    git(&test_repo, &["push", &storage_url, "v1.2:refs/tags/v1.2"]);
+
+    let git_archive = |args: &[&str]| {
+        let output = Command::new("git")
+            .current_dir(&test_repo)
+            .arg("archive")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+    let archive = |args: &[&str]| {
+        let output = Command::new("git-remote-walrus")
+            .arg("archive")
+            .arg(&storage_url)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output.stdout
+    };
+
+    // Tar output is byte-for-byte what git archive writes, with and without a prefix
+    assert!(archive(&["main"]) == git_archive(&["--format=tar", "main"]));
+    let expected = git_archive(&["--format=tar", "--prefix=project-1.2/", "v1.2"]);
+    assert!(archive(&["v1.2", "--prefix", "project-1.2/"]) == expected);
+    assert!(
+        archive(&[
+            "refs/heads/main",
+            "--format",
+            "tar",
+            "--prefix",
+            "project-1.2/"
+        ]) == expected
+    );
+
+    // tar.gz and zip extract to the same files as git archive's tar
+    let extract = |archive: &[u8], dir: &str, program: &str, args: &[&str]| {
+        let dir = temp.path().join(dir);
+        std::fs::create_dir(&dir).unwrap();
+        let file = dir.join("archive");
+        std::fs::write(&file, archive).unwrap();
+        let status = Command::new(program)
+            .current_dir(&dir)
+            .args(args)
+            .arg(&file)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::remove_file(file).unwrap();
+        snapshot_dir(&dir)
+    };
+    let expected = extract(&expected, "expected", "tar", &["-xf"]);
+
+    let tar_gz = temp.path().join("project.tar.gz");
+    let output = Command::new("git-remote-walrus")
+        .args([
+            "archive",
+            &storage_url,
+            "v1.2",
+            "--prefix",
+            "project-1.2/",
+            "--out",
+        ])
+        .arg(&tar_gz)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("tar.gz archive of refs/tags/v1.2"));
+    let tar_gz = std::fs::read(tar_gz).unwrap();
+    assert_eq!(&tar_gz[..2], &[0x1f, 0x8b]);
+    assert_eq!(extract(&tar_gz, "from-tar-gz", "tar", &["-xzf"]), expected);
+
+    let zip = archive(&["main", "--format", "zip", "--prefix", "project-1.2/"]);
+    assert_eq!(extract(&zip, "from-zip", "unzip", &["-q"]), expected);
+
+    // A failed archive leaves no partial file behind
+    let missing = temp.path().join("missing.zip");
+    let output = Command::new("git-remote-walrus")
+        .args(["archive", &storage_url, "nope", "--out"])
+        .arg(&missing)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(!missing.exists());
+}
+
+#[test]
+fn test_fetch_into_bare_repo_and_worktree() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "fetched").unwrap();
+    git(&test_repo, &["add", "file.txt"]);
+    git(&test_repo, &["commit", "-m", "Commit"]);
+    let sha = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "main"]);
+
+    // Bare repository: objects live directly under the repo, there is no .git
+    let bare = temp.path().join("bare.git");
+    git(temp.path(), &["init", "--bare", bare.to_str().unwrap()]);
+    git(&bare, &["fetch", &storage_url, "main:refs/heads/main"]);
+    assert_eq!(git(&bare, &["cat-file", "-t", &sha]), "commit");
+    assert!(!bare.join(".git").exists());
+
+    // Linked worktree: .git is a file and objects live in the main repository
+    let main_repo = temp.path().join("main-repo");
+    let worktree = temp.path().join("worktree");
+    git(
+        temp.path(),
+        &["init", "-b", "main", main_repo.to_str().unwrap()],
+    );
+    git(&main_repo, &["config", "user.name", "Test"]);
+    git(&main_repo, &["config", "user.email", "test@test.com"]);
+    git(&main_repo, &["commit", "--allow-empty", "-m", "Base"]);
+    git(
+        &main_repo,
+        &["worktree", "add", "-b", "wt", worktree.to_str().unwrap()],
+    );
+    git(
+        &worktree,
+        &["fetch", &storage_url, "main:refs/heads/fetched"],
+    );
+    assert!(worktree.join(".git").is_file());
+    assert_eq!(git(&main_repo, &["cat-file", "-t", &sha]), "commit");
+    assert_eq!(git(&main_repo, &["rev-parse", "refs/heads/fetched"]), sha);
+
+    // Separate git dir, with the helper run from a subdirectory of the work tree and no GIT_DIR
+    let separate = temp.path().join("separate.git");
+    let work = temp.path().join("work");
+    git(
+        temp.path(),
+        &[
+            "init",
+            "--separate-git-dir",
+            separate.to_str().unwrap(),
+            work.to_str().unwrap(),
+        ],
+    );
+    std::fs::create_dir(work.join("sub")).unwrap();
+    let input = format!("fetch {} refs/heads/main\n\n", sha);
+    helper_session(&work.join("sub"), &storage_url, None, input.as_bytes());
+    assert_eq!(git(&work, &["cat-file", "-t", &sha]), "commit");
+    assert!(!work.join("sub/.git").exists());
+}
+
+#[test]
+fn test_push_rejects_conflicting_ref_names() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let cloned_repo = temp.path().join("cloned");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("file.txt"), "one").unwrap();
+    git(&test_repo, &["add", "."]);
+    git(&test_repo, &["commit", "-m", "One"]);
+    let pushed_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["branch", "feature"]);
+    git(&test_repo, &["push", &storage_url, "main", "feature"]);
+    let state_before = std::fs::read(storage.join("state.yaml")).unwrap();
+
+    // A case variant, and a ref below an existing one (a directory/file conflict)
+    git(&test_repo, &["branch", "Main"]);
+    git(&test_repo, &["branch", "-D", "feature"]);
+    git(&test_repo, &["branch", "feature/x"]);
+    for (branch, message) in [
+        (
+            "Main",
+            "differs only in case from existing ref refs/heads/main",
+        ),
+        (
+            "feature/x",
+            "conflicts with existing ref refs/heads/feature",
+        ),
+    ] {
+        let output = Command::new("git")
+            .current_dir(&test_repo)
+            .args(["push", &storage_url, branch])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{}", branch);
+        assert!(stderr.contains(message), "{}", stderr);
+    }
+    assert_eq!(
+        std::fs::read(storage.join("state.yaml")).unwrap(),
+        state_before
+    );
+
+    git(
+        temp.path(),
+        &["clone", &storage_url, cloned_repo.to_str().unwrap()],
+    );
+    assert_eq!(git(&cloned_repo, &["rev-parse", "HEAD"]), pushed_sha);
+}
+
+#[test]
+fn test_migrate_to_filesystem() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let migrated = temp.path().join("migrated");
+    let cloned_repo = temp.path().join("cloned");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    for i in 0..3 {
+        std::fs::write(test_repo.join("file.txt"), format!("version {}", i)).unwrap();
+        git(&test_repo, &["add", "."]);
+        git(&test_repo, &["commit", "-m", &format!("Commit {}", i)]);
+    }
+    git(&test_repo, &["tag", "v1"]);
+    let pushed_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["push", &storage_url, "main", "v1"]);
+
+    let migrate = || {
+        Command::new("git-remote-walrus")
+            .arg("migrate")
+            .arg(&storage)
+            .arg("--to-filesystem")
+            .arg(&migrated)
+            .output()
+            .unwrap()
+    };
+    let output = migrate();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains("✓ Migrated 2 refs and 9 objects"),
+        "{}",
+        stdout
+    );
+    assert!(String::from_utf8_lossy(&migrate().stdout).contains("Already migrated"));
+
+    let migrated_url = format!("walrus::{}", migrated.display());
+    git(
+        temp.path(),
+        &["clone", &migrated_url, cloned_repo.to_str().unwrap()],
+    );
+    assert_eq!(git(&cloned_repo, &["rev-parse", "HEAD"]), pushed_sha);
+    assert_eq!(git(&cloned_repo, &["rev-parse", "v1"]), pushed_sha);
+    assert_eq!(
+        git(&cloned_repo, &["log", "--format=%s"]),
+        "Commit 2\nCommit 1\nCommit 0"
+    );
+}
+
+#[test]
+fn test_symrefs_resolve_after_clone() {
+    setup_git_remote();
+
+    let temp = TempDir::new().unwrap();
+    let test_repo = temp.path().join("test-repo");
+    let storage = temp.path().join("storage");
+    let cloned_repo = temp.path().join("cloned");
+
+    std::fs::create_dir(&test_repo).unwrap();
+    git(&test_repo, &["init", "-b", "main"]);
+    git(&test_repo, &["config", "user.name", "Test"]);
+    git(&test_repo, &["config", "user.email", "test@test.com"]);
+    std::fs::write(test_repo.join("main.txt"), "main").unwrap();
+    git(&test_repo, &["add", "main.txt"]);
+    git(&test_repo, &["commit", "-m", "Main commit"]);
+    git(&test_repo, &["checkout", "-b", "release"]);
+    std::fs::write(test_repo.join("release.txt"), "release").unwrap();
+    git(&test_repo, &["add", "release.txt"]);
+    git(&test_repo, &["commit", "-m", "Release commit"]);
+    let release_sha = git(&test_repo, &["rev-parse", "HEAD"]);
+
+    let storage_url = format!("walrus::{}", storage.display());
+    git(&test_repo, &["remote", "add", "origin", &storage_url]);
+    git(&test_repo, &["push", "origin", "main", "release"]);
+
+    let symref = |args: &[&str]| {
+        let output = Command::new("git-remote-walrus")
+            .arg("symref")
+            .arg(&storage_url)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    };
+    symref(&["HEAD", "release"]);
+    symref(&["refs/heads/production", "refs/heads/release"]);
+    assert_eq!(symref(&["HEAD"]), "refs/heads/release");
+
+    // Pointing a symref at a missing ref is refused
+    let output = Command::new("git-remote-walrus")
+        .args(["symref", &storage_url, "HEAD", "missing"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let ls_remote = git(&test_repo, &["ls-remote", "--symref", "origin"]);
+    assert!(
+        ls_remote.contains("ref: refs/heads/release\tHEAD"),
+        "{}",
+        ls_remote
+    );
+    assert!(
+        ls_remote.contains("ref: refs/heads/release\trefs/heads/production"),
+        "{}",
+        ls_remote
+    );
+
+    // The clone checks out the stored HEAD and resolves the custom symref
+    git(
+        temp.path(),
+        &["clone", &storage_url, cloned_repo.to_str().unwrap()],
+    );
+    assert_eq!(
+        git(&cloned_repo, &["symbolic-ref", "--short", "HEAD"]),
+        "release"
+    );
+    assert_eq!(git(&cloned_repo, &["rev-parse", "HEAD"]), release_sha);
+    assert_eq!(
+        git(&cloned_repo, &["rev-parse", "origin/production"]),
+        release_sha
+    );
+
+    // `git remote set-head -a` follows the stored HEAD
+    git(&test_repo, &["remote", "set-head", "origin", "-a"]);
+    assert_eq!(
+        git(&test_repo, &["symbolic-ref", "refs/remotes/origin/HEAD"]),
+        "refs/remotes/origin/release"
+    );
+
+    // Deleting HEAD falls back to main
+    symref(&["HEAD", "--delete"]);
+    git(&test_repo, &["remote", "set-head", "origin", "-a"]);
+    assert_eq!(
+        git(&test_repo, &["symbolic-ref", "refs/remotes/origin/HEAD"]),
+        "refs/remotes/origin/main"
+    );
+}
+
+/// A remote on the mock Sui RPC (tests/mock/sui_rpc.rs) with its blobs in the mock walrus CLI
+///
+/// Its `home` holds a git-remote-walrus config pointing at both mocks; git run through
+/// `MockRemote::git` uses it, and so does the helper git starts.
+struct MockRemote {
+    temp: TempDir,
+    home: PathBuf,
+    walrus_dir: PathBuf,
+    sui: sui_rpc::MockSui,
+    state: ObjectID,
+    url: String,
+}
+
+impl MockRemote {
+    fn new() -> Self {
+        setup_git_remote();
+
+        let temp = TempDir::new().unwrap();
+        let home = temp.path().join("home");
+        let walrus_dir = temp.path().join("walrus");
+        std::fs::create_dir_all(&walrus_dir).unwrap();
+        let walrus_config = walrus_dir.join("client.yaml");
+        std::fs::write(&walrus_config, "").unwrap();
+
+        let sui = sui_rpc::MockSui::start(&walrus_dir);
+        let (wallet, owner) = sui.wallet(&temp.path().join("sui"));
+        let state = sui.create_remote(owner, false);
+
+        let config_dir = home.join(".config/git-remote-walrus");
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::write(
+            config_dir.join("config.yaml"),
+            format!(
+                "sui_wallet_path: \"{}\"\nwalrus_config_path: \"{}\"\nwalrus_binary: \"{}\"\n\
+                 cache_dir: \"{}\"\nskip_preflight: true\n",
+                wallet.display(),
+                walrus_config.display(),
+                Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("tests/mock/walrus")
+                    .display(),
+                temp.path().join("cache").display()
+            ),
+        )
+        .unwrap();
+
+        let url = format!("walrus::{}", state);
+        Self {
+            temp,
+            home,
+            walrus_dir,
+            sui,
+            state,
+            url,
+        }
+    }
+
+    /// Run git in `dir` with the mock's HOME, failing the test if it fails; returns stdout
+    /// and stderr
+    fn git(&self, dir: &Path, args: &[&str]) -> (String, String) {
+        self.git_with_env(dir, args, &[])
+    }
+
+    /// `git`, with extra environment variables
+    fn git_with_env(&self, dir: &Path, args: &[&str], env: &[(&str, &str)]) -> (String, String) {
+        let output = Command::new("git")
+            .current_dir(dir)
+            .args(args)
+            .envs(env.iter().copied())
+            .env("HOME", &self.home)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env_remove("SUI_WALLET")
+            .env_remove("WALRUS_CONFIG")
+            .env_remove("WALRUS_BINARY")
+            .env_remove("WALRUS_REMOTE_CACHE_DIR")
+            .output()
+            .expect("failed to execute git");
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        assert!(output.status.success(), "git {:?} failed: {}", args, stderr);
+        (
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            stderr,
+        )
+    }
+
+    /// A new repository `name` with `commits` commits on main
+    fn repo(&self, name: &str, commits: usize) -> PathBuf {
+        let repo = self.temp.path().join(name);
+        std::fs::create_dir(&repo).unwrap();
+        self.git(&repo, &["init", "-b", "main"]);
+        self.git(&repo, &["config", "user.name", "Test"]);
+        self.git(&repo, &["config", "user.email", "test@test.com"]);
+        for i in 0..commits {
+            std::fs::write(
+                repo.join(format!("file{}.txt", i)),
+                format!("content {}\n", i),
+            )
+            .unwrap();
+            self.git(&repo, &["add", "."]);
+            self.git(&repo, &["commit", "-m", &format!("Commit {}", i)]);
+        }
+        repo
+    }
+}
+
+#[test]
+fn test_push_clone_against_mock_sui_and_walrus() {
+    let remote = MockRemote::new();
+    let repo = remote.repo("repo", 2);
+    let head = remote.git(&repo, &["rev-parse", "HEAD"]).0;
+    remote.git(&repo, &["push", &remote.url, "main"]);
+
+    // The ref and the objects map are on chain, the map's blob in the mock walrus
+    let refs = remote.sui.refs(remote.state);
+    assert_eq!(refs.get("refs/heads/main"), Some(&head));
+    let objects = remote
+        .sui
+        .objects_blob_object_id(remote.state)
+        .expect("push recorded no objects map");
+    assert!(remote.walrus_dir.join("objects").join(&objects).exists());
+    assert_eq!(remote.sui.lock_holder(remote.state), None);
+
+    let cloned = remote.temp.path().join("cloned");
+    remote.git(
+        remote.temp.path(),
+        &["clone", &remote.url, cloned.to_str().unwrap()],
+    );
+    assert_eq!(remote.git(&cloned, &["rev-parse", "HEAD"]).0, head);
+    assert_eq!(
+        std::fs::read_to_string(cloned.join("file1.txt")).unwrap(),
+        "content 1\n"
+    );
+
+    // A push from the clone moves the ref on chain, and the original fetches it back
+    remote.git(&cloned, &["config", "user.name", "Test"]);
+    remote.git(&cloned, &["config", "user.email", "test@test.com"]);
+    std::fs::write(cloned.join("file2.txt"), "content 2\n").unwrap();
+    remote.git(&cloned, &["add", "file2.txt"]);
+    remote.git(&cloned, &["commit", "-m", "Commit 2"]);
+    let new_head = remote.git(&cloned, &["rev-parse", "HEAD"]).0;
+    remote.git(&cloned, &["push", "origin", "main"]);
+    assert_eq!(
+        remote.sui.refs(remote.state).get("refs/heads/main"),
+        Some(&new_head)
+    );
+    assert_ne!(
+        remote.sui.objects_blob_object_id(remote.state),
+        Some(objects)
+    );
+
+    remote.git(&repo, &["fetch", &remote.url, "main"]);
+    assert_eq!(remote.git(&repo, &["rev-parse", "FETCH_HEAD"]).0, new_head);
+    assert_eq!(
+        remote.git(&repo, &["show", "FETCH_HEAD:file2.txt"]).0,
+        "content 2"
+    );
+}
+
+#[test]
+fn test_clone_pinned_to_an_older_transaction() {
+    let remote = MockRemote::new();
+    let repo = remote.repo("repo", 1);
+    let old_head = remote.git(&repo, &["rev-parse", "HEAD"]).0;
+    remote.git(&repo, &["push", &remote.url, "main"]);
+    let pin = remote.sui.transactions(remote.state).pop().unwrap();
+
+    // Later pushes move main and add a branch
+    std::fs::write(repo.join("file1.txt"), "content 1\n").unwrap();
+    remote.git(&repo, &["add", "file1.txt"]);
+    remote.git(&repo, &["commit", "-m", "Commit 1"]);
+    remote.git(&repo, &["push", &remote.url, "main", "main:feature"]);
+    let refs = remote.sui.refs(remote.state);
+    assert_ne!(refs.get("refs/heads/main"), Some(&old_head));
+    assert!(refs.contains_key("refs/heads/feature"));
+
+    // A clone pinned to the first push sees the remote as it was then
+    let pinned = remote.temp.path().join("pinned");
+    let url = format!("{}@{}", remote.url, pin);
+    remote.git(
+        remote.temp.path(),
+        &["clone", &url, pinned.to_str().unwrap()],
+    );
+    assert_eq!(remote.git(&pinned, &["rev-parse", "HEAD"]).0, old_head);
+    assert_eq!(remote.git(&pinned, &["rev-list", "--count", "HEAD"]).0, "1");
+    assert!(!pinned.join("file1.txt").exists());
+    let (remote_refs, _) = remote.git(&pinned, &["ls-remote", &url]);
+    assert!(
+        !remote_refs.contains("refs/heads/feature"),
+        "{}",
+        remote_refs
+    );
+}
@@ -0,0 +1,1870 @@
+//! Mock Sui JSON-RPC fullnode for tests
+//!
+//! Serves the RPCs git-remote-walrus makes over plain HTTP/1.1 and executes its `remote_state`
+//! PTBs against an in-memory ledger, with the Move module's checks and abort codes. Every
+//! object version and transaction is kept, so ref history and pinned reads work. SharedBlob
+//! objects are read from the `objects/` directory of the mock walrus CLI (tests/mock/walrus).
+//! Signatures are not checked.
+//!
+//! The unit tests include this file as `crate::sui::mock_rpc`, the integration tests by path.
+
+#![allow(dead_code)]
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use num_bigint::BigUint;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sui_sdk::rpc_types::{Coin, ObjectChange, OwnedObjectRef, SuiObjectRef};
+use sui_types::{
+    base_types::{ObjectDigest, ObjectID, ObjectRef, SequenceNumber, SuiAddress},
+    crypto::{get_key_pair, Ed25519KeyPair, EncodeDecodeBase64, SuiKeyPair},
+    digests::TransactionDigest,
+    dynamic_field,
+    object::Owner,
+    parse_sui_struct_tag,
+    parse_sui_type_tag,
+    programmable_transaction_builder::ProgrammableTransactionBuilder,
+    transaction::{
+        Argument,
+        CallArg,
+        Command,
+        ObjectArg,
+        TransactionData,
+        TransactionDataAPI,
+        TransactionKind,
+    },
+    Identifier,
+};
+
+/// Package the mock's RemoteStates are published in
+pub const PACKAGE_ID: &str = "0x000000000000000000000000000000000000000000000000000000000000c0de";
+
+/// Package of the SharedBlob and Blob types served for the mock walrus CLI's objects
+const WALRUS_PACKAGE_ID: &str =
+    "0x000000000000000000000000000000000000000000000000000000000000beef";
+
+/// The shared Clock
+pub const CLOCK_ID: &str = "0x0000000000000000000000000000000000000000000000000000000000000006";
+
+/// MIST a new wallet's gas coin holds, and each transaction costs
+const FUNDING_MIST: u64 = 100_000_000_000;
+const GAS_PER_TRANSACTION: u64 = 1_000_000;
+
+/// `remote_state::MAX_INLINE_REFS`
+const MAX_INLINE_REFS: usize = 64;
+
+/// `remote_state` abort codes
+const ERR_LOCK_HELD: u64 = 1;
+const ERR_NO_LOCK: u64 = 2;
+const ERR_NOT_LOCK_HOLDER: u64 = 3;
+const ERR_LOCK_EXPIRED: u64 = 4;
+const ERR_NOT_AUTHORIZED: u64 = 5;
+const ERR_NOT_OWNER: u64 = 6;
+const ERR_INLINE_REFS_FULL: u64 = 7;
+const ERR_NOT_INLINE: u64 = 8;
+const ERR_REF_LIMIT: u64 = 9;
+
+const STRING: &str = "0x1::string::String";
+const VEC_MAP: &str = "0x2::vec_map::VecMap<0x1::string::String, 0x1::string::String>";
+const VEC_MAP_ENTRY: &str = "0x2::vec_map::Entry<0x1::string::String, 0x1::string::String>";
+const TABLE: &str = "0x2::table::Table<0x1::string::String, 0x1::string::String>";
+const SUI: &str = "0x2::sui::SUI";
+const SUI_COIN: &str = "0x2::coin::Coin<0x2::sui::SUI>";
+
+/// RPC methods the mock serves, as `rpc.discover` lists them
+const METHODS: &[&str] = &[
+    "rpc.discover",
+    "sui_getObject",
+    "sui_multiGetObjects",
+    "sui_tryGetPastObject",
+    "sui_tryMultiGetPastObjects",
+    "sui_getTransactionBlock",
+    "sui_executeTransactionBlock",
+    "sui_getReferenceGasPrice",
+    "sui_getNormalizedMoveModulesByPackage",
+    "suix_getDynamicFieldObject",
+    "suix_getDynamicFields",
+    "suix_getCoins",
+    "suix_getBalance",
+    "suix_getOwnedObjects",
+    "suix_queryTransactionBlocks",
+];
+
+/// A running mock node; it serves until the test process exits
+#[derive(Clone)]
+pub struct MockSui {
+    url: String,
+    ledger: Arc<Mutex<Ledger>>,
+}
+
+impl MockSui {
+    /// Serve on a free local port, with the SharedBlobs of the mock walrus CLI in `walrus_dir`
+    pub fn start(walrus_dir: &Path) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock Sui RPC");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let ledger = Arc::new(Mutex::new(Ledger::new(walrus_dir)));
+        let serving = ledger.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let ledger = serving.clone();
+                thread::spawn(move || {
+                    // A dropped connection (e.g. the helper's connect probe) just ends it
+                    let _ = serve_connection(stream, &ledger);
+                });
+            }
+        });
+        Self { url, ledger }
+    }
+
+    /// RPC URL of the node
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn ledger(&self) -> MutexGuard<'_, Ledger> {
+        self.ledger.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Write a Sui client config and keystore for a new, funded address into `dir`
+    ///
+    /// Returns the config path (for `sui_wallet_path` or `SUI_WALLET`) and the address. The
+    /// config's only environment is `localnet`, pointing at this node.
+    pub fn wallet(&self, dir: &Path) -> (PathBuf, SuiAddress) {
+        let (address, keypair): (SuiAddress, Ed25519KeyPair) = get_key_pair();
+        fs::create_dir_all(dir).unwrap();
+        let keystore = dir.join("sui.keystore");
+        let keys = [SuiKeyPair::Ed25519(keypair).encode_base64()];
+        fs::write(&keystore, serde_json::to_string(&keys).unwrap()).unwrap();
+
+        let config = dir.join("client.yaml");
+        fs::write(
+            &config,
+            format!(
+                "keystore:\n  File: \"{}\"\nenvs:\n  - alias: localnet\n    rpc: \"{}\"\n    \
+                 ws: ~\n    basic_auth: ~\nactive_env: localnet\nactive_address: \"{}\"\n",
+                keystore.display(),
+                self.url,
+                address
+            ),
+        )
+        .unwrap();
+        self.fund(address);
+        (config, address)
+    }
+
+    /// Give `address` a new gas coin
+    pub fn fund(&self, address: SuiAddress) {
+        self.ledger().fund(address, FUNDING_MIST);
+    }
+
+    /// Create a RemoteState owned by `owner`, with the inline refs layout if `inline`
+    pub fn create_remote(&self, owner: SuiAddress, inline: bool) -> ObjectID {
+        let function = if inline {
+            "create_remote_inline"
+        } else {
+            "create_remote"
+        };
+        let digest = self.execute_as(owner, |ptb, package| {
+            ptb.programmable_move_call(
+                package,
+                Identifier::new("remote_state")?,
+                Identifier::new(function)?,
+                vec![],
+                vec![],
+            );
+            Ok(())
+        });
+        self.ledger()
+            .objects
+            .iter()
+            .find(|(_, object)| {
+                object.previous_transaction == digest && matches!(object.body, Body::State(_))
+            })
+            .map(|(id, _)| *id)
+            .expect("create_remote created no RemoteState")
+    }
+
+    /// Share `state` (owned by `owner`) with `allowlist`, as `init --shared` does
+    pub fn share(&self, state: ObjectID, owner: SuiAddress, allowlist: &[SuiAddress]) {
+        let state_ref = self.ledger().object_ref(state);
+        self.execute_as(owner, |ptb, package| {
+            let state_arg = ptb.obj(ObjectArg::ImmOrOwnedObject(state_ref))?;
+            let allowlist_arg = ptb.pure(allowlist.to_vec())?;
+            ptb.programmable_move_call(
+                package,
+                Identifier::new("remote_state")?,
+                Identifier::new("share_with_allowlist")?,
+                vec![],
+                vec![state_arg, allowlist_arg],
+            );
+            Ok(())
+        });
+    }
+
+    /// Run a PTB built by `build` as `sender`, panicking unless it succeeds
+    fn execute_as(
+        &self,
+        sender: SuiAddress,
+        build: impl FnOnce(&mut ProgrammableTransactionBuilder, ObjectID) -> anyhow::Result<()>,
+    ) -> TransactionDigest {
+        let mut ptb = ProgrammableTransactionBuilder::new();
+        build(&mut ptb, ObjectID::from_hex_literal(PACKAGE_ID).unwrap()).unwrap();
+        let mut ledger = self.ledger();
+        let gas = ledger.gas_coins(sender);
+        let data =
+            TransactionData::new_programmable(sender, gas, ptb.finish(), 10_000_000_000, 1000);
+        let bytes = STANDARD.encode(bcs::to_bytes(&data).unwrap());
+        let (digest, response) = ledger.execute(&bytes, Vec::new()).unwrap();
+        let status = &response["effects"]["status"];
+        assert_eq!(
+            status["status"], "success",
+            "mock transaction failed: {}",
+            status
+        );
+        digest
+    }
+
+    /// Fix the Clock at `timestamp_ms` (it follows the system clock until set)
+    pub fn set_clock_ms(&self, timestamp_ms: u64) {
+        self.ledger().clock_ms = Some(timestamp_ms);
+    }
+
+    /// Leave `function` out of the package, as in one published before it existed
+    pub fn remove_function(&self, function: &str) {
+        self.ledger().missing_functions.insert(function.to_string());
+    }
+
+    /// Times `method` was called
+    pub fn calls(&self, method: &str) -> usize {
+        self.ledger().calls.get(method).copied().unwrap_or(0)
+    }
+
+    /// Refs of `state`, from whichever layout it uses
+    pub fn refs(&self, state: ObjectID) -> BTreeMap<String, String> {
+        let ledger = self.ledger();
+        let exec = Exec::new(&ledger, SuiAddress::ZERO, [0; 32]);
+        exec.refs(state)
+    }
+
+    /// Symrefs of `state` (None without a `symrefs` field)
+    pub fn symrefs(&self, state: ObjectID) -> Option<BTreeMap<String, String>> {
+        match self.ledger().field(state, "symrefs") {
+            Some(FieldValue::Map(entries)) => Some(entries.iter().cloned().collect()),
+            _ => None,
+        }
+    }
+
+    /// Objects map object ID of `state`
+    pub fn objects_blob_object_id(&self, state: ObjectID) -> Option<String> {
+        self.ledger().state(state).objects_blob_object_id
+    }
+
+    /// Lock holder of `state`, if it is locked
+    pub fn lock_holder(&self, state: ObjectID) -> Option<SuiAddress> {
+        self.ledger().state(state).lock.map(|(holder, _)| holder)
+    }
+
+    /// Digests of the transactions that changed `object`, oldest first
+    pub fn transactions(&self, object: ObjectID) -> Vec<String> {
+        self.ledger()
+            .transactions
+            .iter()
+            .filter(|tx| tx.changed.contains(&object))
+            .map(|tx| tx.digest.to_string())
+            .collect()
+    }
+}
+
+/// Who holds an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Holder {
+    Address(SuiAddress),
+    /// A dynamic field of the object (or table) with this ID
+    Object(ObjectID),
+    /// Shared at this version (0 until the sharing transaction commits)
+    Shared(u64),
+}
+
+impl Holder {
+    fn owner(self) -> Owner {
+        match self {
+            Holder::Address(address) => Owner::AddressOwner(address),
+            Holder::Object(parent) => Owner::ObjectOwner(parent.into()),
+            Holder::Shared(version) => Owner::Shared {
+                initial_shared_version: SequenceNumber::from_u64(version),
+            },
+        }
+    }
+}
+
+/// A RemoteState's own fields
+#[derive(Debug, Clone, PartialEq)]
+struct RemoteState {
+    owner: SuiAddress,
+    table_id: ObjectID,
+    objects_blob_object_id: Option<String>,
+    /// Holder and expiry (Clock ms)
+    lock: Option<(SuiAddress, u64)>,
+    allowlist: Option<Vec<SuiAddress>>,
+}
+
+/// Value of a `String`-keyed dynamic field
+#[derive(Debug, Clone, PartialEq)]
+enum FieldValue {
+    /// A refs table entry's SHA-1
+    Sha1(String),
+    /// `inline_refs` or `symrefs`
+    Map(Vec<(String, String)>),
+    Policy {
+        max_refs: Option<u64>,
+        max_objects: Option<u64>,
+    },
+    LastWriter {
+        writer: SuiAddress,
+        objects_blob_object_id: Option<String>,
+        format: String,
+    },
+}
+
+impl FieldValue {
+    fn type_(&self) -> String {
+        match self {
+            FieldValue::Sha1(_) => STRING.to_string(),
+            FieldValue::Map(_) => VEC_MAP.to_string(),
+            FieldValue::Policy { .. } => format!("{}::remote_state::Policy", PACKAGE_ID),
+            FieldValue::LastWriter { .. } => format!("{}::remote_state::LastWriter", PACKAGE_ID),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            FieldValue::Sha1(sha1) => json!(sha1),
+            FieldValue::Map(entries) => json!({
+                "type": VEC_MAP,
+                "fields": {
+                    "contents": entries
+                        .iter()
+                        .map(|(key, value)| json!({
+                            "type": VEC_MAP_ENTRY,
+                            "fields": { "key": key, "value": value },
+                        }))
+                        .collect::<Vec<_>>(),
+                },
+            }),
+            FieldValue::Policy {
+                max_refs,
+                max_objects,
+            } => json!({
+                "type": self.type_(),
+                "fields": {
+                    "max_refs": max_refs.map(|n| n.to_string()),
+                    "max_objects": max_objects.map(|n| n.to_string()),
+                },
+            }),
+            FieldValue::LastWriter {
+                writer,
+                objects_blob_object_id,
+                format,
+            } => json!({
+                "type": self.type_(),
+                "fields": {
+                    "writer": writer,
+                    "objects_blob_object_id": objects_blob_object_id,
+                    "format": format,
+                },
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Body {
+    State(RemoteState),
+    Field {
+        parent: ObjectID,
+        name: String,
+        value: FieldValue,
+    },
+    Coin(u64),
+}
+
+impl Body {
+    fn type_(&self) -> String {
+        match self {
+            Body::State(_) => format!("{}::remote_state::RemoteState", PACKAGE_ID),
+            Body::Field { value, .. } => {
+                format!("0x2::dynamic_field::Field<{}, {}>", STRING, value.type_())
+            }
+            Body::Coin(_) => SUI_COIN.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Object {
+    body: Body,
+    holder: Holder,
+    version: u64,
+    digest: ObjectDigest,
+    previous_transaction: TransactionDigest,
+}
+
+/// An executed transaction
+struct Executed {
+    digest: TransactionDigest,
+    sender: SuiAddress,
+    /// `remote_state` functions it called
+    functions: Vec<String>,
+    /// Objects it created, mutated or deleted
+    changed: BTreeSet<ObjectID>,
+    /// Its full `SuiTransactionBlockResponse`
+    response: Value,
+}
+
+struct Ledger {
+    walrus_dir: PathBuf,
+    objects: BTreeMap<ObjectID, Object>,
+    /// Every version each object has had, as `SuiObjectData`
+    past: BTreeMap<(ObjectID, u64), Value>,
+    transactions: Vec<Executed>,
+    /// Version the next transaction gives the objects it writes (the Clock is at 1)
+    next_version: u64,
+    clock_ms: Option<u64>,
+    missing_functions: BTreeSet<String>,
+    calls: BTreeMap<String, usize>,
+}
+
+impl Ledger {
+    fn new(walrus_dir: &Path) -> Self {
+        Self {
+            walrus_dir: walrus_dir.to_path_buf(),
+            objects: BTreeMap::new(),
+            past: BTreeMap::new(),
+            transactions: Vec::new(),
+            next_version: 2,
+            clock_ms: None,
+            missing_functions: BTreeSet::new(),
+            calls: BTreeMap::new(),
+        }
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.clock_ms.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64
+        })
+    }
+
+    fn state(&self, id: ObjectID) -> RemoteState {
+        match self.objects.get(&id).map(|object| &object.body) {
+            Some(Body::State(state)) => state.clone(),
+            _ => panic!("{} is not a RemoteState", id),
+        }
+    }
+
+    fn field(&self, parent: ObjectID, name: &str) -> Option<&FieldValue> {
+        match &self.objects.get(&field_id(parent, name))?.body {
+            Body::Field { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    fn object_ref(&self, id: ObjectID) -> ObjectRef {
+        let object = &self.objects[&id];
+        (id, SequenceNumber::from_u64(object.version), object.digest)
+    }
+
+    fn gas_coins(&self, owner: SuiAddress) -> Vec<ObjectRef> {
+        self.objects
+            .iter()
+            .filter(|(_, object)| {
+                object.holder == Holder::Address(owner) && matches!(object.body, Body::Coin(_))
+            })
+            .map(|(id, _)| self.object_ref(*id))
+            .collect()
+    }
+
+    fn fund(&mut self, owner: SuiAddress, mist: u64) {
+        let version = self.next_version;
+        self.next_version += 1;
+        let id = ObjectID::new(hash(&[
+            owner.to_string().as_bytes(),
+            version.to_le_bytes().as_slice(),
+        ]));
+        let object = Object {
+            body: Body::Coin(mist),
+            holder: Holder::Address(owner),
+            version,
+            digest: object_digest(id, version),
+            previous_transaction: TransactionDigest::new([0; 32]),
+        };
+        self.past
+            .insert((id, version), render(&self.objects, id, &object));
+        self.objects.insert(id, object);
+    }
+
+    /// Answer a JSON-RPC call
+    fn handle(&mut self, method: &str, params: &[Value]) -> Result<Value, String> {
+        *self.calls.entry(method.to_string()).or_default() += 1;
+        let param = |i: usize| params.get(i).cloned().unwrap_or(Value::Null);
+        match method {
+            "rpc.discover" => Ok(json!({
+                "openrpc": "1.2.6",
+                "info": { "title": "Mock Sui JSON-RPC", "version": "1.50.0" },
+                "methods": METHODS.iter().map(|name| json!({ "name": name })).collect::<Vec<_>>(),
+            })),
+            "sui_getObject" => Ok(self.object_response(object_id(&param(0))?)),
+            "sui_multiGetObjects" => param(0)
+                .as_array()
+                .ok_or("Expected a list of object IDs")?
+                .iter()
+                .map(|id| Ok(self.object_response(object_id(id)?)))
+                .collect::<Result<Vec<_>, String>>()
+                .map(Value::from),
+            "suix_getDynamicFieldObject" => {
+                let parent = object_id(&param(0))?;
+                let name = param(1)["value"]
+                    .as_str()
+                    .ok_or("Mock Sui RPC only has String-keyed dynamic fields")?
+                    .to_string();
+                let id = field_id(parent, &name);
+                Ok(match self.objects.get(&id) {
+                    Some(object) => json!({ "data": render(&self.objects, id, object) }),
+                    None => json!({
+                        "error": { "code": "dynamicFieldNotFound", "parent_object_id": parent },
+                    }),
+                })
+            }
+            "suix_getDynamicFields" => {
+                let parent = object_id(&param(0))?;
+                let cursor = param(1).as_str().map(str::to_string);
+                let limit = param(2).as_u64().unwrap_or(50) as usize;
+                let fields: Vec<(&ObjectID, &Object)> = self
+                    .objects
+                    .iter()
+                    .filter(|(id, object)| {
+                        matches!(&object.body, Body::Field { parent: p, .. } if *p == parent)
+                            && cursor
+                                .as_ref()
+                                .is_none_or(|cursor| id.to_string() > *cursor)
+                    })
+                    .collect();
+                let page: Vec<Value> = fields
+                    .iter()
+                    .take(limit)
+                    .map(|(id, object)| dynamic_field_info(**id, object))
+                    .collect();
+                let next = fields
+                    .iter()
+                    .take(limit)
+                    .last()
+                    .map(|(id, _)| id.to_string());
+                Ok(json!({
+                    "data": page,
+                    "nextCursor": next,
+                    "hasNextPage": fields.len() > limit,
+                }))
+            }
+            "suix_getCoins" => {
+                let owner = address(&param(0))?;
+                let coins: Vec<Value> = match param(1).as_str() {
+                    Some(coin_type) if !coin_type.ends_with("::sui::SUI") => Vec::new(),
+                    _ => self.coins(owner),
+                };
+                Ok(json!({ "data": coins, "nextCursor": null, "hasNextPage": false }))
+            }
+            "suix_getBalance" => {
+                let owner = address(&param(0))?;
+                let coin_type = param(1).as_str().unwrap_or(SUI).to_string();
+                let balances: Vec<u64> = if coin_type == SUI {
+                    self.objects
+                        .values()
+                        .filter(|object| object.holder == Holder::Address(owner))
+                        .filter_map(|object| match object.body {
+                            Body::Coin(balance) => Some(balance),
+                            _ => None,
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                Ok(json!({
+                    "coinType": coin_type,
+                    "coinObjectCount": balances.len(),
+                    "totalBalance": balances.iter().sum::<u64>().to_string(),
+                    "lockedBalance": {},
+                }))
+            }
+            "sui_getReferenceGasPrice" => Ok(json!("1000")),
+            "sui_executeTransactionBlock" => {
+                let tx_bytes = param(0);
+                let tx_bytes = tx_bytes
+                    .as_str()
+                    .ok_or("Expected base64 transaction bytes")?;
+                let signatures = param(1).as_array().cloned().unwrap_or_default();
+                let (_, mut response) = self.execute(tx_bytes, signatures)?;
+                response["confirmedLocalExecution"] = json!(true);
+                Ok(response)
+            }
+            "suix_queryTransactionBlocks" => self.query_transactions(
+                &param(0),
+                param(1).as_str(),
+                param(2).as_u64().unwrap_or(50) as usize,
+                param(3).as_bool().unwrap_or(false),
+            ),
+            "sui_getTransactionBlock" => {
+                let digest = param(0);
+                self.transactions
+                    .iter()
+                    .find(|tx| Some(tx.digest.to_string().as_str()) == digest.as_str())
+                    .map(|tx| tx.response.clone())
+                    .ok_or_else(|| format!("Could not find the referenced transaction {}", digest))
+            }
+            "sui_tryGetPastObject" => {
+                let version = number(&param(1)).ok_or("Expected a version")?;
+                Ok(self.past_object(object_id(&param(0))?, version))
+            }
+            "sui_tryMultiGetPastObjects" => param(0)
+                .as_array()
+                .ok_or("Expected a list of past object requests")?
+                .iter()
+                .map(|request| {
+                    let version = number(&request["version"]).ok_or("Expected a version")?;
+                    Ok(self.past_object(object_id(&request["objectId"])?, version))
+                })
+                .collect::<Result<Vec<_>, String>>()
+                .map(Value::from),
+            "sui_getNormalizedMoveModulesByPackage" => {
+                let functions: BTreeMap<&str, Value> = FUNCTIONS
+                    .iter()
+                    .filter(|(name, _)| !self.missing_functions.contains(*name))
+                    .map(|(name, _)| {
+                        (
+                            *name,
+                            json!({
+                                "visibility": "Public",
+                                "isEntry": false,
+                                "typeParameters": [],
+                                "parameters": [],
+                                "return": [],
+                            }),
+                        )
+                    })
+                    .collect();
+                Ok(json!({
+                    "remote_state": {
+                        "fileFormatVersion": 6,
+                        "address": PACKAGE_ID,
+                        "name": "remote_state",
+                        "friends": [],
+                        "structs": {},
+                        "enums": {},
+                        "exposedFunctions": functions,
+                    },
+                }))
+            }
+            "suix_getOwnedObjects" => {
+                let owner = address(&param(0))?;
+                let wanted = match param(1)["filter"]["StructType"].as_str() {
+                    Some(type_) => Some(parse_sui_struct_tag(type_).map_err(|e| e.to_string())?),
+                    None => None,
+                };
+                let data: Vec<Value> = self
+                    .objects
+                    .iter()
+                    .filter(|(_, object)| object.holder == Holder::Address(owner))
+                    .filter(|(_, object)| match &wanted {
+                        Some(tag) => {
+                            parse_sui_struct_tag(&object.body.type_()).ok().as_ref() == Some(tag)
+                        }
+                        None => true,
+                    })
+                    .map(|(id, object)| json!({ "data": render(&self.objects, *id, object) }))
+                    .collect();
+                Ok(json!({ "data": data, "nextCursor": null, "hasNextPage": false }))
+            }
+            _ => Err(format!("Method not found: {}", method)),
+        }
+    }
+
+    /// `SuiObjectResponse` for `id`: a ledger object, the Clock, or a mock walrus SharedBlob
+    fn object_response(&self, id: ObjectID) -> Value {
+        let data = if id == ObjectID::from_hex_literal(CLOCK_ID).unwrap() {
+            Some(self.clock())
+        } else if let Some(object) = self.objects.get(&id) {
+            Some(render(&self.objects, id, object))
+        } else {
+            self.blob_object(id)
+        };
+        match data {
+            Some(data) => json!({ "data": data }),
+            None => json!({ "error": { "code": "notExists", "object_id": id } }),
+        }
+    }
+
+    fn clock(&self) -> Value {
+        let id = ObjectID::from_hex_literal(CLOCK_ID).unwrap();
+        object_data(
+            id,
+            1,
+            object_digest(id, 1),
+            "0x2::clock::Clock",
+            Holder::Shared(1),
+            TransactionDigest::new([0; 32]),
+            json!({ "id": { "id": id }, "timestamp_ms": self.now_ms().to_string() }),
+        )
+    }
+
+    /// The blob object the mock walrus CLI recorded as `objects/<id>`
+    /// (`<blob_id> <end_epoch> <deletable> <size>`): a SharedBlob, or for deletable blobs the
+    /// owned Blob itself
+    fn blob_object(&self, id: ObjectID) -> Option<Value> {
+        let record =
+            fs::read_to_string(self.walrus_dir.join("objects").join(id.to_string())).ok()?;
+        let mut fields = record.split_whitespace();
+        let blob_id = URL_SAFE_NO_PAD.decode(fields.next()?).ok()?;
+        let end_epoch: u64 = fields.next()?.parse().ok()?;
+        let deletable = fields.next()? == "true";
+        let size = fields.next().unwrap_or("0");
+        let blob_type = format!("{}::blob::Blob", WALRUS_PACKAGE_ID);
+        let blob_fields = json!({
+                "id": { "id": id },
+                "registered_epoch": 1,
+                // Blob IDs are u256s, little-endian
+                "blob_id": BigUint::from_bytes_le(&blob_id).to_str_radix(10),
+                "size": size,
+                "encoding_type": 1,
+                "certified_epoch": 1,
+                "storage": {
+                    "type": format!("{}::storage_resource::Storage", WALRUS_PACKAGE_ID),
+                    "fields": {
+                        "id": { "id": id },
+                        "start_epoch": 1,
+                        "end_epoch": end_epoch,
+                        "storage_size": size,
+                    },
+                },
+                "deletable": deletable,
+        });
+        let digest = ObjectDigest::new(hash(&[record.as_bytes()]));
+        if deletable {
+            return Some(object_data(
+                id,
+                1,
+                digest,
+                &blob_type,
+                Holder::Address(SuiAddress::ZERO),
+                TransactionDigest::new([0; 32]),
+                blob_fields,
+            ));
+        }
+        let blob = json!({ "type": blob_type, "fields": blob_fields });
+        Some(object_data(
+            id,
+            1,
+            digest,
+            &format!("{}::shared_blob::SharedBlob", WALRUS_PACKAGE_ID),
+            Holder::Shared(1),
+            TransactionDigest::new([0; 32]),
+            json!({ "id": { "id": id }, "blob": blob, "funds": "0" }),
+        ))
+    }
+
+    fn coins(&self, owner: SuiAddress) -> Vec<Value> {
+        self.objects
+            .iter()
+            .filter(|(_, object)| object.holder == Holder::Address(owner))
+            .filter_map(|(id, object)| match object.body {
+                Body::Coin(balance) => Some(Coin {
+                    coin_type: SUI.to_string(),
+                    coin_object_id: *id,
+                    version: SequenceNumber::from_u64(object.version),
+                    digest: object.digest,
+                    balance,
+                    previous_transaction: object.previous_transaction,
+                }),
+                _ => None,
+            })
+            .map(|coin| serde_json::to_value(coin).unwrap())
+            .collect()
+    }
+
+    fn past_object(&self, id: ObjectID, version: u64) -> Value {
+        match self.past.get(&(id, version)) {
+            Some(data) => json!({ "status": "VersionFound", "details": data }),
+            None => json!({ "status": "ObjectNotExists", "details": id }),
+        }
+    }
+
+    fn query_transactions(
+        &self,
+        query: &Value,
+        cursor: Option<&str>,
+        limit: usize,
+        descending: bool,
+    ) -> Result<Value, String> {
+        let filter = &query["filter"];
+        let changed = match filter["ChangedObject"].as_str() {
+            Some(id) => Some(object_id(&json!(id))?),
+            None => None,
+        };
+        let from = match filter["FromAddress"].as_str() {
+            Some(from) => Some(address(&json!(from))?),
+            None => None,
+        };
+        let function = filter["MoveFunction"]["function"].as_str();
+        let mut matching: Vec<&Executed> = self
+            .transactions
+            .iter()
+            .filter(|tx| changed.is_none_or(|id| tx.changed.contains(&id)))
+            .filter(|tx| from.is_none_or(|from| tx.sender == from))
+            .filter(|tx| function.is_none_or(|function| tx.functions.iter().any(|f| f == function)))
+            .collect();
+        if descending {
+            matching.reverse();
+        }
+        let start = match cursor {
+            Some(cursor) => matching
+                .iter()
+                .position(|tx| tx.digest.to_string() == cursor)
+                .map_or(matching.len(), |i| i + 1),
+            None => 0,
+        };
+        let page: Vec<&Executed> = matching[start..].iter().take(limit).copied().collect();
+        Ok(json!({
+            "data": page.iter().map(|tx| tx.response.clone()).collect::<Vec<_>>(),
+            "nextCursor": page.last().map(|tx| tx.digest.to_string()),
+            "hasNextPage": start + page.len() < matching.len(),
+        }))
+    }
+
+    /// Execute a base64 BCS `TransactionData`, returning its digest and response
+    ///
+    /// Input objects at a stale version, owned by someone else, or calls to functions the
+    /// package lacks are refused as a fullnode would, without executing; a Move abort fails
+    /// the transaction, leaving only its gas charge and version bumps.
+    fn execute(
+        &mut self,
+        tx_bytes: &str,
+        signatures: Vec<Value>,
+    ) -> Result<(TransactionDigest, Value), String> {
+        let bytes = STANDARD.decode(tx_bytes).map_err(|e| e.to_string())?;
+        let data: TransactionData =
+            bcs::from_bytes(&bytes).map_err(|e| format!("Invalid transaction bytes: {}", e))?;
+        let sender = data.sender();
+        let TransactionKind::ProgrammableTransaction(pt) = data.kind() else {
+            return Err("Mock Sui RPC only executes programmable transactions".to_string());
+        };
+        let gas: Vec<ObjectRef> = data.gas().to_vec();
+        let Some(gas_coin) = gas.first().map(|gas| gas.0) else {
+            return Err("Transaction has no gas coins".to_string());
+        };
+
+        // Inputs, and the owned objects among them, which must be current
+        let mut inputs = Vec::new();
+        let mut owned: Vec<ObjectRef> = gas.clone();
+        for input in &pt.inputs {
+            if let CallArg::Pure(bytes) = input {
+                inputs.push(Input::Pure(bytes.clone()));
+            } else if let CallArg::Object(ObjectArg::ImmOrOwnedObject(object_ref)) = input {
+                owned.push(*object_ref);
+                inputs.push(Input::Object(object_ref.0));
+            } else if let CallArg::Object(ObjectArg::SharedObject { id, .. }) = input {
+                inputs.push(Input::Object(*id));
+            } else {
+                return Err(format!("Mock Sui RPC cannot take input {:?}", input));
+            }
+        }
+        for (id, version, _) in &owned {
+            let Some(object) = self.objects.get(id) else {
+                return Err(format!("Object {} version {:?} not found", id, version));
+            };
+            if object.version != version.value() {
+                return Err(format!(
+                    "Transaction validator signing failed due to issues with transaction \
+                     inputs, please review the errors and try again:\n- Object ({}, {:?}) is \
+                     not available for consumption, its current version: {:?}",
+                    id,
+                    version,
+                    SequenceNumber::from_u64(object.version)
+                ));
+            }
+            if let Holder::Address(owner) = object.holder {
+                if owner != sender {
+                    return Err(format!(
+                        "Object {} is owned by account address {}, but given owner/signer \
+                         address is {}",
+                        id, owner, sender
+                    ));
+                }
+            }
+        }
+
+        // Calls, with the type each input is used as
+        let package = ObjectID::from_hex_literal(PACKAGE_ID).unwrap();
+        let mut calls = Vec::new();
+        let mut input_params = BTreeMap::new();
+        for command in &pt.commands {
+            let Command::MoveCall(call) = command else {
+                return Err("Mock Sui RPC only executes Move calls".to_string());
+            };
+            let function = call.function.to_string();
+            let params = params(&function)
+                .filter(|_| {
+                    call.package == package
+                        && call.module.to_string() == "remote_state"
+                        && !self.missing_functions.contains(&function)
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "Error checking transaction input objects: FunctionNotFound {}::{}::{}",
+                        call.package, call.module, function
+                    )
+                })?;
+            if call.arguments.len() != params.len() {
+                return Err(format!(
+                    "{} takes {} arguments, given {}",
+                    function,
+                    params.len(),
+                    call.arguments.len()
+                ));
+            }
+            let mut args = Vec::new();
+            for (argument, param) in call.arguments.iter().zip(params) {
+                let Argument::Input(i) = argument else {
+                    return Err("Mock Sui RPC only passes transaction inputs to calls".to_string());
+                };
+                let input = inputs
+                    .get(*i as usize)
+                    .ok_or_else(|| format!("No input {}", i))?;
+                input_params.entry(*i as usize).or_insert(*param);
+                args.push(input.clone());
+            }
+            calls.push((function, args));
+        }
+
+        let seed = hash(&[
+            bytes.as_slice(),
+            self.transactions.len().to_le_bytes().as_slice(),
+        ]);
+        let digest = TransactionDigest::new(seed);
+        let mut exec = Exec::new(self, sender, seed);
+        let mut error = None;
+        for (index, (function, args)) in calls.iter().enumerate() {
+            if let Err(failure) = exec.call(function, args) {
+                error = Some(match failure {
+                    Failure::Abort(code) => format!(
+                        "MoveAbort(MoveLocation {{ module: ModuleId {{ address: {}, name: \
+                         Identifier(\"remote_state\") }}, function: 0, instruction: 0, \
+                         function_name: Some(\"{}\") }}, {}) in command {}",
+                        PACKAGE_ID.trim_start_matches("0x"),
+                        function,
+                        code,
+                        index
+                    ),
+                    Failure::Invalid(message) => format!("{} in command {}", message, index),
+                });
+                break;
+            }
+        }
+        let mut after = match error {
+            None => exec.objects,
+            Some(_) => self.objects.clone(),
+        };
+        if let Some(Body::Coin(balance)) = after.get_mut(&gas_coin).map(|coin| &mut coin.body) {
+            *balance = balance.saturating_sub(GAS_PER_TRANSACTION);
+        }
+
+        // Every written object moves to the transaction's version; mutable inputs always do
+        let version = self.next_version;
+        self.next_version += 1;
+        let inputs_written: BTreeSet<ObjectID> = owned.iter().map(|object| object.0).collect();
+        let ids: BTreeSet<ObjectID> = self.objects.keys().chain(after.keys()).copied().collect();
+        let (mut created, mut mutated, mut deleted) = (Vec::new(), Vec::new(), Vec::new());
+        for id in ids {
+            match (self.objects.get(&id), after.get_mut(&id)) {
+                (None, Some(object)) => {
+                    stamp(object, id, version, digest);
+                    created.push(id);
+                }
+                (Some(old), Some(object))
+                    if old.body != object.body
+                        || old.holder != object.holder
+                        || inputs_written.contains(&id) =>
+                {
+                    stamp(object, id, version, digest);
+                    mutated.push((id, old.version));
+                }
+                (Some(old), None) => deleted.push((id, old.body.type_())),
+                _ => {}
+            }
+        }
+        for id in created.iter().chain(mutated.iter().map(|(id, _)| id)) {
+            self.past
+                .insert((*id, version), render(&after, *id, &after[id]));
+        }
+
+        let owned_ref = |id: &ObjectID| {
+            let object = &after[id];
+            serde_json::to_value(OwnedObjectRef {
+                owner: object.holder.owner(),
+                reference: SuiObjectRef {
+                    object_id: *id,
+                    version: SequenceNumber::from_u64(object.version),
+                    digest: object.digest,
+                },
+            })
+            .unwrap()
+        };
+        let object_type = |type_: String| parse_sui_struct_tag(&type_).unwrap();
+        let mut changes = Vec::new();
+        for id in &created {
+            let object = &after[id];
+            changes.push(ObjectChange::Created {
+                sender,
+                owner: object.holder.owner(),
+                object_type: object_type(object.body.type_()),
+                object_id: *id,
+                version: SequenceNumber::from_u64(version),
+                digest: object.digest,
+            });
+        }
+        for (id, previous_version) in &mutated {
+            let object = &after[id];
+            changes.push(ObjectChange::Mutated {
+                sender,
+                owner: object.holder.owner(),
+                object_type: object_type(object.body.type_()),
+                object_id: *id,
+                version: SequenceNumber::from_u64(version),
+                previous_version: SequenceNumber::from_u64(*previous_version),
+                digest: object.digest,
+            });
+        }
+        for (id, type_) in &deleted {
+            changes.push(ObjectChange::Deleted {
+                sender,
+                object_type: object_type(type_.clone()),
+                object_id: *id,
+                version: SequenceNumber::from_u64(version),
+            });
+        }
+
+        let effects = json!({
+            "messageVersion": "v1",
+            "status": match &error {
+                None => json!({ "status": "success" }),
+                Some(error) => json!({ "status": "failure", "error": error }),
+            },
+            "executedEpoch": "0",
+            "gasUsed": {
+                "computationCost": GAS_PER_TRANSACTION.to_string(),
+                "storageCost": "0",
+                "storageRebate": "0",
+                "nonRefundableStorageFee": "0",
+            },
+            "modifiedAtVersions": [],
+            "sharedObjects": [],
+            "transactionDigest": digest,
+            "created": created.iter().map(owned_ref).collect::<Vec<_>>(),
+            "mutated": mutated.iter().map(|(id, _)| owned_ref(id)).collect::<Vec<_>>(),
+            "unwrapped": [],
+            "deleted": deleted
+                .iter()
+                .map(|(id, _)| {
+                    serde_json::to_value(SuiObjectRef {
+                        object_id: *id,
+                        version: SequenceNumber::from_u64(version),
+                        digest: ObjectDigest::OBJECT_DIGEST_DELETED,
+                    })
+                    .unwrap()
+                })
+                .collect::<Vec<_>>(),
+            "unwrappedThenDeleted": [],
+            "wrapped": [],
+            "gasObject": owned_ref(&gas_coin),
+            "dependencies": [],
+        });
+        let transaction = json!({
+            "data": {
+                "messageVersion": "v1",
+                "transaction": {
+                    "kind": "ProgrammableTransaction",
+                    "inputs": pt
+                        .inputs
+                        .iter()
+                        .enumerate()
+                        .map(|(i, input)| input_json(input, input_params.get(&i).copied()))
+                        .collect::<Vec<_>>(),
+                    "transactions": calls
+                        .iter()
+                        .zip(&pt.commands)
+                        .map(|((function, _), command)| command_json(function, command))
+                        .collect::<Vec<_>>(),
+                },
+                "sender": sender,
+                "gasData": {
+                    "payment": gas
+                        .iter()
+                        .map(|gas| serde_json::to_value(SuiObjectRef::from(*gas)).unwrap())
+                        .collect::<Vec<_>>(),
+                    "owner": sender,
+                    "price": data.gas_price().to_string(),
+                    "budget": data.gas_budget().to_string(),
+                },
+            },
+            "txSignatures": signatures,
+        });
+        let response = json!({
+            "digest": digest,
+            "transaction": transaction,
+            "effects": effects,
+            "objectChanges": changes
+                .iter()
+                .map(|change| serde_json::to_value(change).unwrap())
+                .collect::<Vec<_>>(),
+            "timestampMs": self.now_ms().to_string(),
+            "checkpoint": (self.transactions.len() + 1).to_string(),
+        });
+
+        let changed = created
+            .iter()
+            .copied()
+            .chain(mutated.iter().map(|(id, _)| *id))
+            .chain(deleted.iter().map(|(id, _)| *id))
+            .collect();
+        self.objects = after;
+        self.transactions.push(Executed {
+            digest,
+            sender,
+            functions: calls.iter().map(|(function, _)| function.clone()).collect(),
+            changed,
+            response: response.clone(),
+        });
+        Ok((digest, response))
+    }
+}
+
+/// Move the object to `version`, written by transaction `digest`
+fn stamp(object: &mut Object, id: ObjectID, version: u64, digest: TransactionDigest) {
+    object.version = version;
+    object.digest = object_digest(id, version);
+    object.previous_transaction = digest;
+    if object.holder == Holder::Shared(0) {
+        object.holder = Holder::Shared(version);
+    }
+}
+
+/// A transaction input, resolved
+#[derive(Debug, Clone)]
+enum Input {
+    Pure(Vec<u8>),
+    Object(ObjectID),
+}
+
+/// How a `remote_state` function takes each argument
+#[derive(Debug, Clone, Copy)]
+enum Param {
+    State,
+    Clock,
+    U64,
+    OptionU64,
+    Str,
+    Strings,
+    Address,
+    Addresses,
+}
+
+/// `remote_state` functions and their parameters (less the TxContext)
+const FUNCTIONS: &[(&str, &[Param])] = &[
+    ("create_remote", &[]),
+    ("create_remote_inline", &[]),
+    ("share_with_allowlist", &[Param::State, Param::Addresses]),
+    ("acquire_lock", &[Param::State, Param::Clock, Param::U64]),
+    ("release_lock", &[Param::State]),
+    ("upsert_ref", &[Param::State, Param::Str, Param::Str]),
+    ("delete_ref", &[Param::State, Param::Str]),
+    ("migrate_refs_to_table", &[Param::State]),
+    (
+        "update_objects_blob",
+        &[Param::State, Param::Str, Param::Clock],
+    ),
+    ("set_last_writer", &[Param::State, Param::Str, Param::Clock]),
+    (
+        "set_symrefs",
+        &[Param::State, Param::Strings, Param::Strings, Param::Clock],
+    ),
+    (
+        "set_policy",
+        &[Param::State, Param::OptionU64, Param::OptionU64],
+    ),
+    ("add_to_allowlist", &[Param::State, Param::Address]),
+    ("remove_from_allowlist", &[Param::State, Param::Address]),
+];
+
+fn params(function: &str) -> Option<&'static [Param]> {
+    FUNCTIONS
+        .iter()
+        .find(|(name, _)| *name == function)
+        .map(|(_, params)| *params)
+}
+
+/// Why a call failed
+enum Failure {
+    Abort(u64),
+    Invalid(String),
+}
+
+fn abort_unless(condition: bool, code: u64) -> Result<(), Failure> {
+    if condition {
+        Ok(())
+    } else {
+        Err(Failure::Abort(code))
+    }
+}
+
+fn object_arg(arg: &Input) -> Result<ObjectID, Failure> {
+    match arg {
+        Input::Object(id) => Ok(*id),
+        Input::Pure(_) => Err(Failure::Invalid(
+            "CommandArgumentError { kind: TypeMismatch }".to_string(),
+        )),
+    }
+}
+
+fn pure<T: DeserializeOwned>(arg: &Input) -> Result<T, Failure> {
+    match arg {
+        Input::Pure(bytes) => bcs::from_bytes(bytes).map_err(|_| {
+            Failure::Invalid("CommandArgumentError { kind: InvalidBCSBytes }".to_string())
+        }),
+        Input::Object(_) => Err(Failure::Invalid(
+            "CommandArgumentError { kind: TypeMismatch }".to_string(),
+        )),
+    }
+}
+
+/// A transaction's working copy of the ledger's objects
+struct Exec {
+    objects: BTreeMap<ObjectID, Object>,
+    sender: SuiAddress,
+    now_ms: u64,
+    /// Seed of the IDs of the objects the transaction creates
+    seed: [u8; 32],
+    created: u64,
+}
+
+impl Exec {
+    fn new(ledger: &Ledger, sender: SuiAddress, seed: [u8; 32]) -> Self {
+        Self {
+            objects: ledger.objects.clone(),
+            sender,
+            now_ms: ledger.now_ms(),
+            seed,
+            created: 0,
+        }
+    }
+
+    fn new_id(&mut self) -> ObjectID {
+        self.created += 1;
+        ObjectID::new(hash(&[
+            self.seed.as_slice(),
+            self.created.to_le_bytes().as_slice(),
+        ]))
+    }
+
+    fn add(&mut self, id: ObjectID, body: Body, holder: Holder) {
+        self.objects.insert(
+            id,
+            Object {
+                body,
+                holder,
+                version: 0,
+                digest: ObjectDigest::new([0; 32]),
+                previous_transaction: TransactionDigest::new([0; 32]),
+            },
+        );
+    }
+
+    fn state(&self, id: ObjectID) -> Result<RemoteState, Failure> {
+        match self.objects.get(&id).map(|object| &object.body) {
+            Some(Body::State(state)) => Ok(state.clone()),
+            _ => Err(Failure::Invalid(format!(
+                "CommandArgumentError {{ kind: TypeMismatch }}: {} is not a RemoteState",
+                id
+            ))),
+        }
+    }
+
+    fn put_state(&mut self, id: ObjectID, state: RemoteState) {
+        if let Some(object) = self.objects.get_mut(&id) {
+            object.body = Body::State(state);
+        }
+    }
+
+    fn field(&self, parent: ObjectID, name: &str) -> Option<&FieldValue> {
+        match &self.objects.get(&field_id(parent, name))?.body {
+            Body::Field { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Add or replace dynamic field `name` of `parent` (a remove and add in one transaction
+    /// leave the same object, mutated)
+    fn set_field(&mut self, parent: ObjectID, name: &str, value: FieldValue) {
+        let id = field_id(parent, name);
+        let body = Body::Field {
+            parent,
+            name: name.to_string(),
+            value,
+        };
+        match self.objects.get_mut(&id) {
+            Some(object) => object.body = body,
+            None => self.add(id, body, Holder::Object(parent)),
+        }
+    }
+
+    fn remove_field(&mut self, parent: ObjectID, name: &str) -> Option<FieldValue> {
+        match self.objects.remove(&field_id(parent, name))?.body {
+            Body::Field { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    fn table(&self, table_id: ObjectID) -> BTreeMap<String, String> {
+        self.objects
+            .values()
+            .filter_map(|object| match &object.body {
+                Body::Field {
+                    parent,
+                    name,
+                    value: FieldValue::Sha1(sha1),
+                } if *parent == table_id => Some((name.clone(), sha1.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn inline_refs(&self, state: ObjectID) -> Option<Vec<(String, String)>> {
+        match self.field(state, "inline_refs")? {
+            FieldValue::Map(entries) => Some(entries.clone()),
+            _ => None,
+        }
+    }
+
+    fn refs(&self, id: ObjectID) -> BTreeMap<String, String> {
+        match self.inline_refs(id) {
+            Some(entries) => entries.into_iter().collect(),
+            None => match self.state(id) {
+                Ok(state) => self.table(state.table_id),
+                Err(_) => BTreeMap::new(),
+            },
+        }
+    }
+
+    fn max_refs(&self, state: ObjectID) -> Option<u64> {
+        match self.field(state, "policy") {
+            Some(FieldValue::Policy { max_refs, .. }) => *max_refs,
+            _ => None,
+        }
+    }
+
+    fn check_authorized(&self, state: &RemoteState) -> Result<(), Failure> {
+        abort_unless(
+            state.owner == self.sender
+                || state
+                    .allowlist
+                    .as_ref()
+                    .is_some_and(|allowlist| allowlist.contains(&self.sender)),
+            ERR_NOT_AUTHORIZED,
+        )
+    }
+
+    fn check_lock_held(&self, state: &RemoteState) -> Result<(), Failure> {
+        let Some((holder, expires_ms)) = state.lock else {
+            return Err(Failure::Abort(ERR_NO_LOCK));
+        };
+        abort_unless(holder == self.sender, ERR_NOT_LOCK_HOLDER)?;
+        abort_unless(self.now_ms < expires_ms, ERR_LOCK_EXPIRED)
+    }
+
+    /// Run `remote_state::<function>` on `args`
+    fn call(&mut self, function: &str, args: &[Input]) -> Result<(), Failure> {
+        if function == "create_remote" || function == "create_remote_inline" {
+            let id = self.new_id();
+            let table_id = self.new_id();
+            let state = RemoteState {
+                owner: self.sender,
+                table_id,
+                objects_blob_object_id: None,
+                lock: None,
+                allowlist: None,
+            };
+            self.add(id, Body::State(state), Holder::Address(self.sender));
+            if function == "create_remote_inline" {
+                self.set_field(id, "inline_refs", FieldValue::Map(Vec::new()));
+            }
+            return Ok(());
+        }
+
+        let id = object_arg(&args[0])?;
+        let mut state = self.state(id)?;
+        match function {
+            "share_with_allowlist" => {
+                abort_unless(state.owner == self.sender, ERR_NOT_OWNER)?;
+                let mut allowlist = Vec::new();
+                for address in pure::<Vec<SuiAddress>>(&args[1])? {
+                    if !allowlist.contains(&address) {
+                        allowlist.push(address);
+                    }
+                }
+                state.allowlist = Some(allowlist);
+                if let Some(object) = self.objects.get_mut(&id) {
+                    object.holder = Holder::Shared(0);
+                }
+            }
+            "acquire_lock" => {
+                self.check_authorized(&state)?;
+                let timeout_ms: u64 = pure(&args[2])?;
+                if let Some((holder, expires_ms)) = state.lock {
+                    abort_unless(
+                        holder == self.sender || self.now_ms >= expires_ms,
+                        ERR_LOCK_HELD,
+                    )?;
+                }
+                state.lock = Some((self.sender, self.now_ms + timeout_ms));
+            }
+            "release_lock" => {
+                let Some((holder, _)) = state.lock else {
+                    return Err(Failure::Abort(ERR_NO_LOCK));
+                };
+                abort_unless(holder == self.sender, ERR_NOT_LOCK_HOLDER)?;
+                state.lock = None;
+            }
+            "upsert_ref" => {
+                self.check_authorized(&state)?;
+                let name: String = pure(&args[1])?;
+                let sha1: String = pure(&args[2])?;
+                let below_limit = |count: usize| {
+                    self.max_refs(id)
+                        .is_none_or(|max_refs| (count as u64) < max_refs)
+                };
+                if let Some(mut entries) = self.inline_refs(id) {
+                    match entries.iter_mut().find(|(key, _)| *key == name) {
+                        Some(entry) => entry.1 = sha1,
+                        None => {
+                            abort_unless(entries.len() < MAX_INLINE_REFS, ERR_INLINE_REFS_FULL)?;
+                            abort_unless(below_limit(entries.len()), ERR_REF_LIMIT)?;
+                            entries.push((name, sha1));
+                        }
+                    }
+                    self.set_field(id, "inline_refs", FieldValue::Map(entries));
+                } else {
+                    if self.field(state.table_id, &name).is_none() {
+                        abort_unless(below_limit(self.table(state.table_id).len()), ERR_REF_LIMIT)?;
+                    }
+                    self.set_field(state.table_id, &name, FieldValue::Sha1(sha1));
+                }
+            }
+            "delete_ref" => {
+                self.check_authorized(&state)?;
+                let name: String = pure(&args[1])?;
+                if let Some(mut entries) = self.inline_refs(id) {
+                    if entries.iter().any(|(key, _)| *key == name) {
+                        entries.retain(|(key, _)| *key != name);
+                        self.set_field(id, "inline_refs", FieldValue::Map(entries));
+                    }
+                } else {
+                    self.remove_field(state.table_id, &name);
+                }
+            }
+            "migrate_refs_to_table" => {
+                self.check_authorized(&state)?;
+                let Some(FieldValue::Map(entries)) = self.remove_field(id, "inline_refs") else {
+                    return Err(Failure::Abort(ERR_NOT_INLINE));
+                };
+                for (name, sha1) in entries {
+                    self.set_field(state.table_id, &name, FieldValue::Sha1(sha1));
+                }
+            }
+            "update_objects_blob" => {
+                self.check_lock_held(&state)?;
+                state.objects_blob_object_id = Some(pure(&args[1])?);
+            }
+            "set_last_writer" => {
+                self.check_lock_held(&state)?;
+                let value = FieldValue::LastWriter {
+                    writer: self.sender,
+                    objects_blob_object_id: state.objects_blob_object_id.clone(),
+                    format: pure(&args[1])?,
+                };
+                self.set_field(id, "last_writer", value);
+            }
+            "set_symrefs" => {
+                self.check_lock_held(&state)?;
+                let names: Vec<String> = pure(&args[1])?;
+                let targets: Vec<String> = pure(&args[2])?;
+                if names.len() != targets.len() {
+                    // vec_map::from_keys_values aborts on mismatched lengths
+                    return Err(Failure::Invalid(
+                        "MoveAbort in 0x2::vec_map::from_keys_values".to_string(),
+                    ));
+                }
+                let entries = names.into_iter().zip(targets).collect();
+                self.set_field(id, "symrefs", FieldValue::Map(entries));
+            }
+            "set_policy" => {
+                abort_unless(state.owner == self.sender, ERR_NOT_OWNER)?;
+                let value = FieldValue::Policy {
+                    max_refs: pure(&args[1])?,
+                    max_objects: pure(&args[2])?,
+                };
+                self.set_field(id, "policy", value);
+            }
+            "add_to_allowlist" => {
+                abort_unless(state.owner == self.sender, ERR_NOT_OWNER)?;
+                let address: SuiAddress = pure(&args[1])?;
+                let allowlist = state.allowlist.get_or_insert_with(Vec::new);
+                if !allowlist.contains(&address) {
+                    allowlist.push(address);
+                }
+            }
+            "remove_from_allowlist" => {
+                abort_unless(state.owner == self.sender, ERR_NOT_OWNER)?;
+                let address: SuiAddress = pure(&args[1])?;
+                if let Some(allowlist) = &mut state.allowlist {
+                    allowlist.retain(|allowed| *allowed != address);
+                }
+            }
+            _ => return Err(Failure::Invalid(format!("Unknown function {}", function))),
+        }
+        self.put_state(id, state);
+        Ok(())
+    }
+}
+
+/// `SuiObjectData` for a ledger object, as `objects` hold it
+fn render(objects: &BTreeMap<ObjectID, Object>, id: ObjectID, object: &Object) -> Value {
+    let fields = match &object.body {
+        Body::State(state) => {
+            let size = objects
+                .values()
+                .filter(|field| {
+                    matches!(&field.body, Body::Field { parent, .. } if *parent == state.table_id)
+                })
+                .count();
+            json!({
+                "id": { "id": id },
+                "owner": state.owner,
+                "refs": {
+                    "type": TABLE,
+                    "fields": { "id": { "id": state.table_id }, "size": size.to_string() },
+                },
+                "objects_blob_object_id": state.objects_blob_object_id,
+                "lock": state.lock.map(|(holder, expires_ms)| json!({
+                    "type": format!("{}::remote_state::LockInfo", PACKAGE_ID),
+                    "fields": { "holder": holder, "expires_ms": expires_ms.to_string() },
+                })),
+                "allowlist": state.allowlist.as_ref().map(|allowlist| json!({
+                    "type": "0x2::vec_set::VecSet<address>",
+                    "fields": { "contents": allowlist },
+                })),
+            })
+        }
+        Body::Field { name, value, .. } => json!({
+            "id": { "id": id },
+            "name": name,
+            "value": value.to_json(),
+        }),
+        Body::Coin(balance) => json!({ "id": { "id": id }, "balance": balance.to_string() }),
+    };
+    object_data(
+        id,
+        object.version,
+        object.digest,
+        &object.body.type_(),
+        object.holder,
+        object.previous_transaction,
+        fields,
+    )
+}
+
+fn object_data(
+    id: ObjectID,
+    version: u64,
+    digest: ObjectDigest,
+    type_: &str,
+    holder: Holder,
+    previous_transaction: TransactionDigest,
+    fields: Value,
+) -> Value {
+    json!({
+        "objectId": id,
+        "version": version.to_string(),
+        "digest": digest,
+        "type": type_,
+        "owner": holder.owner(),
+        "previousTransaction": previous_transaction,
+        "storageRebate": "0",
+        "content": {
+            "dataType": "moveObject",
+            "type": type_,
+            "hasPublicTransfer": false,
+            "fields": fields,
+        },
+    })
+}
+
+/// `DynamicFieldInfo` of a dynamic field object
+fn dynamic_field_info(id: ObjectID, object: &Object) -> Value {
+    let Body::Field { name, value, .. } = &object.body else {
+        unreachable!("only dynamic fields are listed");
+    };
+    json!({
+        "name": { "type": STRING, "value": name },
+        "bcsName": base58(&bcs::to_bytes(name).unwrap()),
+        "bcsEncoding": "base58",
+        "type": "DynamicField",
+        "objectType": value.type_(),
+        "objectId": id,
+        "version": object.version,
+        "digest": object.digest,
+    })
+}
+
+/// `SuiCallArg` of a transaction input, decoded as the call that takes it does
+fn input_json(input: &CallArg, param: Option<Param>) -> Value {
+    if let CallArg::Object(ObjectArg::ImmOrOwnedObject((id, version, digest))) = input {
+        return json!({
+            "type": "object",
+            "objectType": "immOrOwnedObject",
+            "objectId": id,
+            "version": version.value().to_string(),
+            "digest": digest,
+        });
+    }
+    if let CallArg::Object(ObjectArg::SharedObject {
+        id,
+        initial_shared_version,
+        ..
+    }) = input
+    {
+        return json!({
+            "type": "object",
+            "objectType": "sharedObject",
+            "objectId": id,
+            "initialSharedVersion": initial_shared_version.value().to_string(),
+            "mutable": false,
+        });
+    }
+    let CallArg::Pure(bytes) = input else {
+        return Value::Null;
+    };
+    let decoded = |param: Param| -> Option<(&'static str, Value)> {
+        Some(match param {
+            Param::Str => (STRING, json!(bcs::from_bytes::<String>(bytes).ok()?)),
+            Param::Strings => (
+                "vector<0x1::string::String>",
+                json!(bcs::from_bytes::<Vec<String>>(bytes).ok()?),
+            ),
+            Param::U64 => (
+                "u64",
+                json!(bcs::from_bytes::<u64>(bytes).ok()?.to_string()),
+            ),
+            Param::OptionU64 => (
+                "0x1::option::Option<u64>",
+                json!(bcs::from_bytes::<Option<u64>>(bytes)
+                    .ok()?
+                    .map(|n| n.to_string())
+                    .into_iter()
+                    .collect::<Vec<_>>()),
+            ),
+            Param::Address => ("address", json!(bcs::from_bytes::<SuiAddress>(bytes).ok()?)),
+            Param::Addresses => (
+                "vector<address>",
+                json!(bcs::from_bytes::<Vec<SuiAddress>>(bytes).ok()?),
+            ),
+            Param::State | Param::Clock => return None,
+        })
+    };
+    match param.and_then(decoded) {
+        Some((value_type, value)) => {
+            json!({ "type": "pure", "valueType": value_type, "value": value })
+        }
+        None => json!({ "type": "pure", "valueType": null, "value": bytes }),
+    }
+}
+
+/// `SuiCommand` of a Move call
+fn command_json(function: &str, command: &Command) -> Value {
+    let Command::MoveCall(call) = command else {
+        return Value::Null;
+    };
+    let arguments: Vec<Value> = call
+        .arguments
+        .iter()
+        .map(|argument| match argument {
+            Argument::GasCoin => json!("GasCoin"),
+            Argument::Input(i) => json!({ "Input": i }),
+            Argument::Result(i) => json!({ "Result": i }),
+            Argument::NestedResult(i, j) => json!({ "NestedResult": [i, j] }),
+        })
+        .collect();
+    json!({
+        "MoveCall": {
+            "package": call.package,
+            "module": call.module.to_string(),
+            "function": function,
+            "arguments": arguments,
+        },
+    })
+}
+
+/// ID of `String`-keyed dynamic field `name` of `parent`
+fn field_id(parent: ObjectID, name: &str) -> ObjectID {
+    dynamic_field::derive_dynamic_field_id(
+        parent,
+        &parse_sui_type_tag(STRING).unwrap(),
+        &bcs::to_bytes(name).unwrap(),
+    )
+    .unwrap()
+}
+
+fn object_digest(id: ObjectID, version: u64) -> ObjectDigest {
+    ObjectDigest::new(hash(&[
+        id.to_string().as_bytes(),
+        version.to_le_bytes().as_slice(),
+    ]))
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn base58(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    let digits = if zeros == bytes.len() {
+        Vec::new()
+    } else {
+        BigUint::from_bytes_be(bytes).to_radix_be(58)
+    };
+    "1".repeat(zeros)
+        + &digits
+            .iter()
+            .map(|digit| ALPHABET[*digit as usize] as char)
+            .collect::<String>()
+}
+
+fn object_id(value: &Value) -> Result<ObjectID, String> {
+    value
+        .as_str()
+        .and_then(|id| ObjectID::from_hex_literal(id).ok())
+        .ok_or_else(|| format!("Invalid object ID {}", value))
+}
+
+fn address(value: &Value) -> Result<SuiAddress, String> {
+    value
+        .as_str()
+        .and_then(|address| address.parse().ok())
+        .ok_or_else(|| format!("Invalid address {}", value))
+}
+
+/// A u64 sent as a number or a string
+fn number(value: &Value) -> Option<u64> {
+    value.as_u64().or_else(|| value.as_str()?.parse().ok())
+}
+
+/// Answer HTTP/1.1 JSON-RPC requests on `stream` until the client hangs up
+fn serve_connection(stream: TcpStream, ledger: &Mutex<Ledger>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut content_length = 0;
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let response = match serde_json::from_slice::<Value>(&body) {
+            Ok(Value::Array(requests)) => {
+                Value::Array(requests.iter().map(|r| respond(ledger, r)).collect())
+            }
+            Ok(request) => respond(ledger, &request),
+            Err(e) => json!({
+                "jsonrpc": "2.0",
+                "error": { "code": -32700, "message": e.to_string() },
+                "id": null,
+            }),
+        };
+        let body = response.to_string();
+        write!(
+            writer,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        writer.flush()?;
+    }
+}
+
+fn respond(ledger: &Mutex<Ledger>, request: &Value) -> Value {
+    let method = request["method"].as_str().unwrap_or_default();
+    let params = match &request["params"] {
+        Value::Array(params) => params.clone(),
+        Value::Null => Vec::new(),
+        other => vec![other.clone()],
+    };
+    let result = ledger
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .handle(method, &params);
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": request["id"] }),
+        Err(message) => json!({
+            "jsonrpc": "2.0",
+            "error": { "code": -32000, "message": message },
+            "id": request["id"],
+        }),
+    }
+}